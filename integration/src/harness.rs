@@ -99,7 +99,43 @@ pub fn client_invalid_user_token() -> IdentityToken {
     IdentityToken::UserName(CLIENT_USERPASS_ID.into(), "xxxx".into())
 }
 
+/// The tests also cover the deprecated security policies, which must be allowed explicitly
+fn security_profile() -> SecurityProfile {
+    SecurityProfile {
+        allow_deprecated_policies: true,
+        ..Default::default()
+    }
+}
+
 pub fn new_server(port: u16) -> Server {
+    new_server_with_listeners(port, &[])
+}
+
+/// Creates a server that listens on the listeners instead of the hostname and port, if any are
+/// supplied
+pub fn new_server_with_listeners(port: u16, listeners: &[ServerListener]) -> Server {
+    new_server_with_builder(port, listeners, |builder| builder)
+}
+
+/// Creates a server that is part of a redundant server set. Its application uri is made unique
+/// by the port.
+pub fn new_redundant_server(port: u16, redundancy: ServerRedundancy) -> Server {
+    new_server_with_builder(port, &[], |builder| {
+        builder
+            .application_uri(redundant_server_uri(port))
+            .redundancy(redundancy)
+    })
+}
+
+/// The application uri of a server made by `new_redundant_server`
+pub fn redundant_server_uri(port: u16) -> String {
+    format!("urn:integration_server:{}", port)
+}
+
+fn new_server_with_builder<F>(port: u16, listeners: &[ServerListener], configure: F) -> Server
+where
+    F: FnOnce(ServerBuilder) -> ServerBuilder,
+{
     let endpoint_path = "/";
 
     // Both client and server define this
@@ -114,7 +150,7 @@ pub fn new_server(port: u16) -> Server {
     ];
 
     // Create an OPC UA server with sample configuration and default node set
-    let server = ServerBuilder::new()
+    let builder = ServerBuilder::new()
         .application_name("integration_server")
         .application_uri("urn:integration_server")
         .discovery_urls(vec![endpoint_url(port, endpoint_path).to_string()])
@@ -122,6 +158,7 @@ pub fn new_server(port: u16) -> Server {
         .pki_dir(format!("./pki-server/{}", port))
         .discovery_server_url(None)
         .host_and_port(hostname(), port)
+        .security_profile(security_profile())
         .user_token(sample_user_id, server_user_token())
         .user_token(x509_user_id, server_x509_token())
         .endpoints(
@@ -212,7 +249,12 @@ pub fn new_server(port: u16) -> Server {
                 )
             })
             .collect(),
-        )
+        );
+    let server = listeners
+        .iter()
+        .fold(configure(builder), |builder, listener| {
+            builder.listener(listener.clone())
+        })
         .server()
         .unwrap();
 
@@ -334,13 +376,14 @@ impl callbacks::Method for HelloX {
     }
 }
 
-fn new_client(port: u16, quick_timeout: bool) -> Client {
+pub fn new_client(port: u16, quick_timeout: bool) -> Client {
     let builder = ClientBuilder::new()
         .application_name("integration_client")
         .application_uri("x")
         .pki_dir(format!("./pki-client/{port}"))
         .create_sample_keypair(true)
         .trust_server_certs(true)
+        .security_profile(security_profile())
         .session_retry_initial(Duration::from_millis(200));
 
     let builder = if quick_timeout {
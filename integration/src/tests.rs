@@ -1,9 +1,15 @@
 use std::{sync::Arc, thread};
 
 use chrono::Utc;
+use futures::StreamExt;
 use log::*;
 
-use opcua::client::{Client, DataChangeCallback, IdentityToken};
+use opcua::client::{
+    AddressSpaceCache, BlockingClient, Client, ConflictPolicy, ConnectionManager,
+    ConnectionManagerOptions, ConnectionState, CrawlOptions, DataChangeCallback, FailoverMode,
+    FailoverOptions, IdentityToken, QueueResult, Session, SessionRetryPolicy,
+    SubscriptionCallbacks, WriteQueue, WriteQueueOptions,
+};
 use opcua::server::prelude::*;
 use opcua::sync::*;
 
@@ -160,6 +166,43 @@ fn server_abort() {
     }
 }
 
+/// Starts the server on a thread and shuts it down gracefully, expecting it to count down the
+/// delay and then exit before the test times out.
+#[test]
+fn server_shutdown() {
+    opcua::console_logging::init();
+
+    let server = Arc::new(RwLock::new(new_server(0)));
+    let server2 = server.clone();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let _t = thread::spawn(move || {
+        // This should run & block until the shutdown delay has expired
+        Server::run_server(server);
+        tx.send(()).unwrap();
+    });
+
+    let start_time = Utc::now();
+    {
+        server2
+            .write()
+            .shutdown(std::time::Duration::from_secs(2), "Shutdown test");
+    }
+    {
+        let server_state = server2.read().server_state();
+        assert!(server_state.read().is_shutting_down());
+    }
+
+    rx.recv_timeout(std::time::Duration::from_secs(10))
+        .expect("Shutdown test timed out");
+    let elapsed = Utc::now().signed_duration_since(start_time);
+    assert!(elapsed.num_milliseconds() >= 2000);
+    info!(
+        "Shutdown test succeeded after {} ms",
+        elapsed.num_milliseconds()
+    );
+}
+
 /// Start a server, send a HELLO message but then wait for the server
 /// to timeout and drop the connection.
 #[tokio::test]
@@ -253,6 +296,79 @@ async fn connect_none() {
     connect_with(port, endpoint_none(port), IdentityToken::Anonymous).await;
 }
 
+/// Returns a Unix domain socket listener for the port's server, which only accepts client
+/// processes of the user id
+#[cfg(unix)]
+fn unix_listener(port: u16, peer_uid: u32) -> ServerListener {
+    let socket_path = std::env::temp_dir().join(format!("opcua-integration-{}.sock", port));
+    let mut listener = ServerListener::new_unix(socket_path, &[]);
+    listener.peer_uids.insert(peer_uid);
+    listener
+}
+
+/// Returns the user id that this process runs as
+#[cfg(unix)]
+fn current_uid(port: u16) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    let path = std::env::temp_dir().join(format!("opcua-integration-{}.uid", port));
+    std::fs::write(&path, []).unwrap();
+    let uid = std::fs::metadata(&path).unwrap().uid();
+    let _ = std::fs::remove_file(&path);
+    uid
+}
+
+/// Connect to the server through a Unix domain socket, using no encryption, anonymous
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_uds() {
+    let port = next_port();
+    let listener = unix_listener(port, current_uid(port));
+    let endpoint_url = format!("{}/", listener.base_endpoint_url());
+    let server = new_server_with_listeners(port, &[listener]);
+    let client = new_client(port, false);
+    let client_test = move |rx_client_command, client| async move {
+        let endpoint = (
+            endpoint_url.as_ref(),
+            SecurityPolicy::None.to_str(),
+            MessageSecurityMode::None,
+        );
+        regular_client_test(
+            endpoint,
+            IdentityToken::Anonymous,
+            rx_client_command,
+            client,
+        )
+        .await;
+    };
+    perform_test(client, server, Some(client_test), regular_server_test).await;
+}
+
+/// Connect to the server through a Unix domain socket that doesn't accept the client's user id
+#[cfg(unix)]
+#[tokio::test]
+async fn connect_uds_with_invalid_peer() {
+    let port = next_port();
+    let listener = unix_listener(port, current_uid(port).wrapping_add(1));
+    let endpoint_url = format!("{}/", listener.base_endpoint_url());
+    let server = new_server_with_listeners(port, &[listener]);
+    let client = new_client(port, true);
+    let client_test = move |rx_client_command, client| async move {
+        let endpoint = (
+            endpoint_url.as_ref(),
+            SecurityPolicy::None.to_str(),
+            MessageSecurityMode::None,
+        );
+        invalid_token_test(
+            endpoint,
+            IdentityToken::Anonymous,
+            rx_client_command,
+            client,
+        )
+        .await;
+    };
+    perform_test(client, server, Some(client_test), regular_server_test).await;
+}
+
 /// Connect to the server using Basic128Rsa15 + Sign
 #[tokio::test]
 async fn connect_basic128rsa15_sign() {
@@ -468,6 +584,824 @@ async fn read_write_read() {
     ).await;
 }
 
+/// Read more nodes than the server accepts in one request, so the client splits the read
+#[tokio::test]
+async fn read_batched_by_operation_limits() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(client_endpoint, IdentityToken::Anonymous)
+                .await
+                .unwrap();
+
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let max_nodes_per_read = session.operation_limits().max_nodes_per_read;
+            assert!(max_nodes_per_read > 0);
+
+            let node_ids = (1..=max_nodes_per_read * 4 + 1)
+                .map(stress_node_id)
+                .collect::<Vec<_>>();
+            let nodes_to_read = node_ids
+                .iter()
+                .map(|node_id| ReadValueId {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::NodeId as u32,
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            let results = session
+                .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+                .await
+                .unwrap();
+            assert_eq!(results.len(), node_ids.len());
+            for (node_id, value) in node_ids.into_iter().zip(results) {
+                assert_eq!(value.value, Some(Variant::from(node_id)));
+            }
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
+/// Crawl the address space into a client side cache and look nodes up by browse path
+#[tokio::test]
+async fn address_space_cache() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(client_endpoint, IdentityToken::Anonymous)
+                .await
+                .unwrap();
+
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let cache = AddressSpaceCache::new(
+                session.clone(),
+                CrawlOptions {
+                    max_depth: Some(3),
+                    ..Default::default()
+                },
+            );
+            let crawled = cache.crawl(&ObjectId::RootFolder.into()).await.unwrap();
+            assert_eq!(crawled, cache.len());
+
+            let stress = cache.find("/Objects/Stress").unwrap();
+            assert_eq!(stress.len(), 1);
+            assert_eq!(cache.children(&stress[0]).len(), 1000);
+
+            let node = cache.find("/Objects/Stress/stress node v0999").unwrap();
+            assert_eq!(node, vec![stress_node_id(999)]);
+            let node = cache.get(&node[0]).unwrap();
+            assert_eq!(node.node_class(), NodeClass::Variable);
+            assert_eq!(node.browse_name(), QualifiedName::from("stress node v0999"));
+
+            // Beyond the crawl depth, so only the server can resolve the path
+            let path = "/Objects/Server.ServerStatus.BuildInfo.ProductName";
+            assert_eq!(cache.find(path), Err(StatusCode::BadNoMatch));
+            assert_eq!(
+                cache.resolve(path).await.unwrap(),
+                vec![NodeId::from(
+                    &VariableId::Server_ServerStatus_BuildInfo_ProductName
+                )]
+            );
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
+/// Use the blocking client from a thread outside of any runtime, with callbacks on the callback thread
+#[tokio::test]
+async fn blocking_client() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, client: Client| async move {
+            let test = move || {
+                let mut client = BlockingClient::new(client).unwrap();
+                let session = client
+                    .connect_to_endpoint(client_endpoint, IdentityToken::Anonymous)
+                    .unwrap();
+
+                let node_id = stress_node_id(2);
+                let (tx, rx) = std::sync::mpsc::channel();
+                let subscription_id = session
+                    .create_subscription(
+                        std::time::Duration::from_millis(100),
+                        100,
+                        10,
+                        0,
+                        0,
+                        true,
+                        DataChangeCallback::new(move |value, item| {
+                            let thread_name = thread::current().name().map(|n| n.to_owned());
+                            let _ = tx.send((value, item.client_handle(), thread_name));
+                        }),
+                    )
+                    .unwrap();
+                let mut item_to_create: MonitoredItemCreateRequest = node_id.clone().into();
+                item_to_create.requested_parameters.client_handle = 7;
+                item_to_create.requested_parameters.sampling_interval = 50.0;
+                let results = session
+                    .create_monitored_items(
+                        subscription_id,
+                        TimestampsToReturn::Both,
+                        vec![item_to_create],
+                    )
+                    .unwrap();
+                assert!(results[0].status_code.is_good());
+
+                let results = session
+                    .write(&[WriteValue {
+                        node_id: node_id.clone(),
+                        attribute_id: AttributeId::Value as u32,
+                        index_range: UAString::null(),
+                        value: Variant::Int32(5).into(),
+                    }])
+                    .unwrap();
+                assert_eq!(results[0], StatusCode::Good);
+
+                let results = session
+                    .read(&[node_id.into()], TimestampsToReturn::Both, 0.0)
+                    .unwrap();
+                assert_eq!(results[0].value, Some(Variant::Int32(5)));
+
+                // Wait for the written value, skipping the initial one
+                loop {
+                    let (value, client_handle, thread_name) = rx
+                        .recv_timeout(std::time::Duration::from_secs(5))
+                        .unwrap();
+                    assert_eq!(client_handle, 7);
+                    assert!(thread_name.unwrap().starts_with("opcua-callbacks"));
+                    if value.value == Some(Variant::Int32(5)) {
+                        break;
+                    }
+                }
+
+                session.disconnect().unwrap();
+            };
+            // The blocking client must be used outside of the runtime of the test
+            tokio::task::spawn_blocking(move || thread::spawn(test).join().unwrap())
+                .await
+                .unwrap();
+        },
+        false
+    ).await;
+}
+
+/// Get the complete `None` endpoint description of each server, as a redundant server set
+async fn redundant_server_set(
+    client: &Client,
+    ports: &[u16],
+) -> Vec<(EndpointDescription, IdentityToken)> {
+    let mut servers = Vec::new();
+    for port in ports {
+        let url = endpoint_none(*port).endpoint_url;
+        // The server may still be starting
+        let endpoints = loop {
+            match client.get_server_endpoints_from_url(url.as_ref()).await {
+                Ok(endpoints) => break endpoints,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+            }
+        };
+        let endpoint = Client::find_matching_endpoint(
+            &endpoints,
+            url.as_ref(),
+            SecurityPolicy::None,
+            MessageSecurityMode::None,
+        )
+        .unwrap();
+        servers.push((endpoint, IdentityToken::Anonymous));
+    }
+    servers
+}
+
+/// Wait until the session is connected to the server on the port
+async fn wait_for_active_server(session: &Session, port: u16) {
+    let url = endpoint_none(port).endpoint_url;
+    tokio::time::timeout(std::time::Duration::from_secs(20), async {
+        while session.active_endpoint().endpoint_url != url {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        session.wait_for_connection().await;
+    })
+    .await
+    .unwrap();
+}
+
+/// Fail over to the second server of a redundant server set in warm mode, when the service level
+/// of the first one drops
+#[tokio::test]
+async fn failover_on_service_level() {
+    let port = next_port();
+    let standby_port = next_port();
+    let (client, server) = new_client_server(port, false);
+    let address_space = server.address_space();
+
+    let client_test = move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>,
+                            mut client: Client| async move {
+        let standby_server = Arc::new(RwLock::new(new_server(standby_port)));
+        let standby_handle = tokio::task::spawn(Server::new_server_task(standby_server));
+
+        let servers = redundant_server_set(&client, &[port, standby_port]).await;
+        let (session, event_loop) = client
+            .new_session_with_failover(
+                servers,
+                FailoverOptions {
+                    check_interval: std::time::Duration::from_millis(200),
+                    ..FailoverOptions::new(FailoverMode::Warm)
+                },
+            )
+            .unwrap();
+        let handle = event_loop.spawn();
+        session.wait_for_connection().await;
+
+        // Both servers are healthy, so the session stays on the first one
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        assert_eq!(
+            session.server_service_levels().unwrap(),
+            vec![Some(255), Some(255)]
+        );
+        assert_eq!(
+            session.active_endpoint().endpoint_url,
+            endpoint_none(port).endpoint_url
+        );
+
+        {
+            let mut address_space = address_space.write();
+            address_space.set_service_level(10, &DateTime::now());
+        }
+        wait_for_active_server(&session, standby_port).await;
+        assert_eq!(session.server_service_levels().unwrap()[1], Some(255));
+
+        let results = session
+            .read(
+                &[NodeId::from(&VariableId::Server_ServiceLevel).into()],
+                TimestampsToReturn::Neither,
+                0.0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(results[0].value, Some(Variant::Byte(255)));
+
+        session.disconnect().await.unwrap();
+        handle.await.unwrap();
+        standby_handle.abort();
+    };
+
+    perform_test(client, server, Some(client_test), regular_server_test).await;
+}
+
+/// Fail over to the second server of a redundant server set in cold mode when the first one goes
+/// away, recreating the subscription with the same monitored item client handle
+#[tokio::test]
+async fn failover_on_connect_error() {
+    let port = next_port();
+    let primary_port = next_port();
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let primary_server = Arc::new(RwLock::new(new_server(primary_port)));
+            let primary_handle =
+                tokio::task::spawn(Server::new_server_task(primary_server.clone()));
+
+            let servers = redundant_server_set(&client, &[primary_port, port]).await;
+            let (session, event_loop) = client
+                .new_session_with_failover(servers, FailoverOptions::new(FailoverMode::Cold))
+                .unwrap();
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let (tx, mut rx) = unbounded_channel();
+            let status_tx = tx.clone();
+            let subscription_id = session
+                .create_subscription(
+                    std::time::Duration::from_millis(100),
+                    100,
+                    10,
+                    0,
+                    0,
+                    true,
+                    SubscriptionCallbacks::new(
+                        move |notification| {
+                            let _ = status_tx.send(Err(notification.status));
+                        },
+                        move |value, item| {
+                            let _ = tx.send(Ok((value, item.client_handle())));
+                        },
+                        |_, _| {},
+                    ),
+                )
+                .await
+                .unwrap();
+            let node_id = stress_node_id(3);
+            let mut item_to_create: MonitoredItemCreateRequest = node_id.clone().into();
+            item_to_create.requested_parameters.client_handle = 9;
+            item_to_create.requested_parameters.sampling_interval = 50.0;
+            session
+                .create_monitored_items(
+                    subscription_id,
+                    TimestampsToReturn::Both,
+                    vec![item_to_create],
+                )
+                .await
+                .unwrap();
+            let (_, client_handle) = rx.recv().await.unwrap().unwrap();
+            assert_eq!(client_handle, 9);
+
+            primary_server
+                .write()
+                .shutdown(std::time::Duration::from_millis(0), "Failover test");
+            wait_for_active_server(&session, port).await;
+
+            let results = session
+                .write(&[WriteValue {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    value: Variant::Int32(42).into(),
+                }])
+                .await
+                .unwrap();
+            assert_eq!(results[0], StatusCode::Good);
+
+            // The subscription is told it moved, then the item reports with the same client handle
+            let mut transferred = false;
+            tokio::time::timeout(std::time::Duration::from_secs(10), async {
+                loop {
+                    match rx.recv().await.unwrap() {
+                        Err(status) => {
+                            if status == StatusCode::GoodSubscriptionTransferred {
+                                transferred = true;
+                            }
+                        }
+                        Ok((value, client_handle)) => {
+                            assert_eq!(client_handle, 9);
+                            if value.value == Some(Variant::Int32(42)) {
+                                break;
+                            }
+                        }
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            assert!(transferred);
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+            primary_handle.abort();
+        },
+        false
+    ).await;
+}
+
+/// Delivers the redundancy messages of a server straight to another server
+struct RedundantServerChannel(Arc<RwLock<Server>>);
+
+impl RedundancyChannel for RedundantServerChannel {
+    fn send(&self, message: RedundancyMessage) {
+        self.0.read().receive_redundancy_message(message);
+    }
+}
+
+/// Fail over in hot mode to a server that the subscriptions are replicated to, and transfer them
+#[tokio::test]
+async fn failover_transfers_replicated_subscriptions() {
+    let port = next_port();
+    let primary_port = next_port();
+    let standby_port = next_port();
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let redundancy = ServerRedundancy {
+                support: "Hot".to_string(),
+                server_uris: vec![
+                    redundant_server_uri(primary_port),
+                    redundant_server_uri(standby_port),
+                ],
+                update_interval_ms: 100,
+                replicate_subscriptions: true,
+            };
+            let standby_server = Arc::new(RwLock::new(new_redundant_server(
+                standby_port,
+                redundancy.clone(),
+            )));
+            let mut primary_server = new_redundant_server(primary_port, redundancy);
+            primary_server.set_redundancy_channel(RedundantServerChannel(standby_server.clone()));
+            let primary_server = Arc::new(RwLock::new(primary_server));
+            let standby_handle =
+                tokio::task::spawn(Server::new_server_task(standby_server.clone()));
+            let primary_handle =
+                tokio::task::spawn(Server::new_server_task(primary_server.clone()));
+
+            let servers = redundant_server_set(&client, &[primary_port, standby_port]).await;
+            let (session, event_loop) = client
+                .new_session_with_failover(servers, FailoverOptions::new(FailoverMode::Hot))
+                .unwrap();
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let (tx, mut rx) = unbounded_channel();
+            let subscription_id = session
+                .create_subscription(
+                    std::time::Duration::from_millis(100),
+                    100,
+                    10,
+                    0,
+                    0,
+                    true,
+                    DataChangeCallback::new(move |value, item| {
+                        let _ = tx.send((value, item.client_handle()));
+                    }),
+                )
+                .await
+                .unwrap();
+            let node_id = stress_node_id(4);
+            let mut item_to_create: MonitoredItemCreateRequest = node_id.clone().into();
+            item_to_create.requested_parameters.client_handle = 9;
+            item_to_create.requested_parameters.sampling_interval = 50.0;
+            session
+                .create_monitored_items(
+                    subscription_id,
+                    TimestampsToReturn::Both,
+                    vec![item_to_create],
+                )
+                .await
+                .unwrap();
+            let (_, client_handle) = rx.recv().await.unwrap();
+            assert_eq!(client_handle, 9);
+
+            // Give the primary server time to replicate the subscription
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+            primary_server
+                .write()
+                .shutdown(std::time::Duration::from_millis(0), "Failover test");
+            wait_for_active_server(&session, standby_port).await;
+
+            let results = session
+                .write(&[WriteValue {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    value: Variant::Int32(42).into(),
+                }])
+                .await
+                .unwrap();
+            assert_eq!(results[0], StatusCode::Good);
+
+            tokio::time::timeout(std::time::Duration::from_secs(10), async {
+                loop {
+                    let (value, client_handle) = rx.recv().await.unwrap();
+                    assert_eq!(client_handle, 9);
+                    if value.value == Some(Variant::Int32(42)) {
+                        break;
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+            // The subscription was transferred rather than created again on the standby server
+            let subscription_ids = session.subscription_state.lock().subscription_ids();
+            assert_eq!(subscription_ids, Some(vec![subscription_id]));
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+            primary_handle.abort();
+            standby_server.write().abort();
+            standby_handle.abort();
+        },
+        false
+    ).await;
+}
+
+/// Connect to several servers through a connection manager, one at a time, while one of the
+/// servers cannot be reached
+#[tokio::test]
+async fn connection_manager_many_servers() {
+    let port = next_port();
+    let second_port = next_port();
+    let missing_port = next_port();
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, client: Client| async move {
+            let second_server = Arc::new(RwLock::new(new_server(second_port)));
+            let second_handle = tokio::task::spawn(Server::new_server_task(second_server.clone()));
+
+            let servers = redundant_server_set(&client, &[port, second_port]).await;
+            let manager = ConnectionManager::new(
+                client,
+                ConnectionManagerOptions {
+                    max_concurrent_connects: 1,
+                    retry_policy: SessionRetryPolicy::infinity(
+                        std::time::Duration::from_millis(1000),
+                        std::time::Duration::from_millis(100),
+                    ),
+                    ..Default::default()
+                },
+            );
+            let events = manager.events();
+            tokio::pin!(events);
+
+            for server in servers {
+                manager.add_server(server).unwrap();
+            }
+            let missing_url = endpoint_none(missing_port).endpoint_url.as_ref().to_string();
+            manager
+                .add_server((endpoint_none(missing_port), IdentityToken::Anonymous))
+                .unwrap();
+            assert!(manager
+                .add_server((endpoint_none(missing_port), IdentityToken::Anonymous))
+                .is_err());
+            assert_eq!(manager.endpoint_urls().len(), 3);
+
+            // Both servers connect, and the missing one keeps being retried
+            let mut connected = Vec::new();
+            let mut retries = 0;
+            tokio::time::timeout(std::time::Duration::from_secs(20), async {
+                while connected.len() < 2 || retries < 2 {
+                    let event = events.next().await.unwrap();
+                    match event.health.state {
+                        ConnectionState::Connected => {
+                            assert_ne!(event.endpoint_url, missing_url);
+                            connected.push(event.endpoint_url);
+                        }
+                        ConnectionState::Reconnecting { .. } => {
+                            assert_eq!(event.endpoint_url, missing_url);
+                            retries += 1;
+                        }
+                        ConnectionState::Connecting => {}
+                        state => panic!("Unexpected connection state {:?}", state),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+            let health = manager.health_all();
+            for endpoint_url in &connected {
+                assert!(health[endpoint_url].is_healthy());
+            }
+            let missing_health = &health[&missing_url];
+            assert!(!missing_health.is_healthy());
+            assert!(missing_health.failed_attempts >= 2);
+            assert!(missing_health.last_error.is_some());
+
+            let session = manager.session(&connected[0]).unwrap();
+            let results = session
+                .read(
+                    &[NodeId::from(&VariableId::Server_ServerStatus_State).into()],
+                    TimestampsToReturn::Neither,
+                    0.0,
+                )
+                .await
+                .unwrap();
+            assert_eq!(results[0].value, Some(Variant::Int32(0)));
+
+            assert!(manager.remove_server(&missing_url).await);
+            assert!(!manager.remove_server(&missing_url).await);
+            assert!(manager.health(&missing_url).is_none());
+
+            manager.shutdown().await;
+            assert!(manager.endpoint_urls().is_empty());
+            second_server.write().abort();
+            second_handle.abort();
+        },
+        false
+    ).await;
+}
+
+/// Queue writes and method calls while the session is not connected, and forward them in order
+/// once it is, after reloading the queue from its file
+#[tokio::test]
+async fn write_queue_store_and_forward() {
+    let port = next_port();
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(endpoint_none(port), IdentityToken::Anonymous)
+                .await
+                .unwrap();
+
+            let path = std::env::temp_dir().join(format!("opcua-write-queue-{}.json", port));
+            let _ = std::fs::remove_file(&path);
+            let options = WriteQueueOptions {
+                path: Some(path.clone()),
+                retry_interval: std::time::Duration::from_millis(100),
+                ..Default::default()
+            };
+            let write_value = |idx: usize, value: i32| WriteValue {
+                node_id: stress_node_id(idx),
+                attribute_id: AttributeId::Value as u32,
+                index_range: UAString::null(),
+                value: Variant::Int32(value).into(),
+            };
+
+            // The session is not connected yet, so everything is queued
+            let queue = WriteQueue::new(session.clone(), options.clone()).unwrap();
+            queue
+                .write(write_value(11, 10), ConflictPolicy::LastWriteWins, None)
+                .unwrap();
+            queue
+                .write(
+                    write_value(11, 99),
+                    ConflictPolicy::LastWriteWins,
+                    Some(std::time::Duration::from_millis(0)),
+                )
+                .unwrap();
+            queue
+                .write(
+                    write_value(12, 20),
+                    ConflictPolicy::FailIfChanged(Variant::Int32(12345)),
+                    None,
+                )
+                .unwrap();
+            queue
+                .call(
+                    CallMethodRequest {
+                        object_id: functions_object_id(),
+                        method_id: hellox_method_id(),
+                        input_arguments: Some(vec![Variant::from("Foo")]),
+                    },
+                    None,
+                )
+                .unwrap();
+            queue
+                .write(
+                    write_value(11, 11),
+                    ConflictPolicy::FailIfChanged(Variant::Int32(10)),
+                    Some(std::time::Duration::from_secs(60)),
+                )
+                .unwrap();
+            drop(queue);
+
+            // The pending items survive a restart
+            let queue = WriteQueue::new(session.clone(), options).unwrap();
+            assert_eq!(queue.len(), 5);
+            let outcomes = queue.outcomes();
+            tokio::pin!(outcomes);
+            let queue_handle = queue.spawn();
+            let handle = event_loop.spawn();
+
+            let outcomes = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+                let mut results = Vec::new();
+                while results.len() < 5 {
+                    let outcome = outcomes.next().await.unwrap();
+                    results.push((outcome.item.id, outcome.result));
+                }
+                results
+            })
+            .await
+            .unwrap();
+
+            assert!(matches!(outcomes[0], (1, QueueResult::Expired)));
+            assert!(matches!(outcomes[1], (0, QueueResult::Written(StatusCode::Good))));
+            assert!(matches!(
+                outcomes[2],
+                (2, QueueResult::Conflict(Some(Variant::Int32(0))))
+            ));
+            match &outcomes[3] {
+                (3, QueueResult::Called(result)) => {
+                    assert!(result.status_code.is_good());
+                    let output_args = result.output_arguments.as_ref().unwrap();
+                    assert_eq!(output_args[0].to_string(), "Hello Foo!");
+                }
+                outcome => panic!("Unexpected outcome {:?}", outcome),
+            }
+            assert!(matches!(outcomes[4], (4, QueueResult::Written(StatusCode::Good))));
+            assert!(queue.is_empty());
+
+            let results = session
+                .read(
+                    &[stress_node_id(11).into(), stress_node_id(12).into()],
+                    TimestampsToReturn::Neither,
+                    0.0,
+                )
+                .await
+                .unwrap();
+            assert_eq!(results[0].value, Some(Variant::Int32(11)));
+            assert_eq!(results[1].value, Some(Variant::Int32(0)));
+
+            // Nothing is left to send after a restart
+            queue_handle.abort();
+            let queue = WriteQueue::new(session.clone(), WriteQueueOptions {
+                path: Some(path.clone()),
+                ..Default::default()
+            }).unwrap();
+            assert!(queue.is_empty());
+            let _ = std::fs::remove_file(&path);
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
+/// Subscribe to the same variable through two connections. The notifications of each
+/// subscription must arrive through its own connection.
+#[tokio::test]
+async fn subscriptions_of_two_connections() {
+    let port = next_port();
+    let client_endpoint = endpoint_basic128rsa15_sign_encrypt(port);
+    let identity_token = client_x509_token();
+
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let node_id = stress_node_id(6);
+            let (tx, mut rx) = unbounded_channel();
+            let mut sessions = Vec::new();
+            for client_handle in [1u32, 2u32] {
+                let (session, event_loop) = client
+                    .new_session_from_endpoint(client_endpoint.clone(), identity_token.clone())
+                    .await
+                    .unwrap();
+                let handle = event_loop.spawn();
+                session.wait_for_connection().await;
+
+                let tx = tx.clone();
+                let subscription_id = session
+                    .create_subscription(
+                        std::time::Duration::from_millis(100),
+                        100,
+                        10,
+                        0,
+                        0,
+                        true,
+                        DataChangeCallback::new(move |value, item| {
+                            let _ = tx.send((client_handle, item.client_handle(), value));
+                        }),
+                    )
+                    .await
+                    .unwrap();
+                let mut item_to_create: MonitoredItemCreateRequest = node_id.clone().into();
+                item_to_create.requested_parameters.client_handle = client_handle;
+                item_to_create.requested_parameters.sampling_interval = 50.0;
+                session
+                    .create_monitored_items(
+                        subscription_id,
+                        TimestampsToReturn::Both,
+                        vec![item_to_create],
+                    )
+                    .await
+                    .unwrap();
+                sessions.push((session, handle));
+            }
+
+            let results = sessions[0]
+                .0
+                .write(&[WriteValue {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    value: Variant::Int32(42).into(),
+                }])
+                .await
+                .unwrap();
+            assert_eq!(results[0], StatusCode::Good);
+
+            // Both subscriptions report the new value, each to the session that created it
+            let mut received = [false, false];
+            tokio::time::timeout(std::time::Duration::from_secs(10), async {
+                while received != [true, true] {
+                    let (session_handle, client_handle, value) = rx.recv().await.unwrap();
+                    assert_eq!(session_handle, client_handle);
+                    if value.value == Some(Variant::Int32(42)) {
+                        received[client_handle as usize - 1] = true;
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+            // Closing one connection leaves the session of the other open
+            for (session, handle) in sessions {
+                session.disconnect().await.unwrap();
+                handle.await.unwrap();
+            }
+        },
+        false
+    ).await;
+}
+
 /// Connect with the server and attempt to subscribe and monitor 1000 variables
 #[tokio::test]
 async fn subscribe_1000() {
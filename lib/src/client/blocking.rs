@@ -0,0 +1,549 @@
+//! A blocking (synchronous) facade over the asynchronous client.
+//!
+//! [`BlockingClient`] owns a tokio runtime and drives the asynchronous [`Client`] and [`Session`]
+//! on it, so that tools and FFI callers can use the client without writing any async code.
+//! Subscription callbacks are called on a dedicated thread of each session, in the order the
+//! notifications were received. The notifications wait for the thread in a bounded queue, and
+//! notifications that arrive while it is full are dropped.
+//!
+//! The blocking functions must not be called from within an asynchronous context, since they
+//! block the calling thread until the request completes.
+
+use std::{
+    future::Future,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use tokio::{runtime::Runtime, select, task::JoinHandle};
+
+use crate::{
+    sync::Mutex,
+    types::{
+        BrowseDescription, BrowsePath, BrowsePathResult, BrowseResult, ByteString,
+        CallMethodRequest, CallMethodResult, DataValue, EndpointDescription, HistoryReadResult,
+        HistoryReadValueId, HistoryUpdateResult, MonitoredItemCreateRequest,
+        MonitoredItemCreateResult, MonitoredItemModifyRequest, MonitoredItemModifyResult,
+        MonitoringMode, ReadValueId, StatusChangeNotification, StatusCode, TimestampsToReturn,
+        Variant, WriteValue,
+    },
+};
+
+use super::{
+    Client, HistoryReadAction, HistoryUpdateAction, IdentityToken, MonitoredItem,
+    OnSubscriptionNotification, Session, SessionEventLoop,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Number of notifications that wait for the callback thread of a session by default.
+const DEFAULT_CALLBACK_QUEUE_SIZE: usize = 100;
+
+/// A client that blocks the calling thread on each request, running the asynchronous [`Client`]
+/// on a runtime that it owns.
+pub struct BlockingClient {
+    runtime: Arc<Runtime>,
+    client: Client,
+    callback_queue_size: usize,
+}
+
+impl BlockingClient {
+    /// Create a blocking client around `client`, with a new multi-threaded runtime.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BlockingClient)` - The blocking client.
+    /// * `Err(StatusCode)` - The runtime could not be created.
+    pub fn new(client: Client) -> Result<Self, StatusCode> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("opcua-client")
+            .build()
+            .map_err(|e| {
+                error!("Cannot create the client runtime, error = {}", e);
+                StatusCode::BadResourceUnavailable
+            })?;
+        Ok(Self {
+            runtime: Arc::new(runtime),
+            client,
+            callback_queue_size: DEFAULT_CALLBACK_QUEUE_SIZE,
+        })
+    }
+
+    /// Set the number of notifications that wait for the callback thread of each session that is
+    /// connected from now on, at least 1. Notifications that arrive while the queue is full are
+    /// dropped, and the next data value passed to the callback has the overflow bit set in its
+    /// status.
+    pub fn set_callback_queue_size(&mut self, callback_queue_size: usize) {
+        self.callback_queue_size = callback_queue_size.max(1);
+    }
+
+    /// The asynchronous client.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The asynchronous client, for changes to its configuration.
+    pub fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Gets the endpoints of the server at the default endpoint of the client configuration.
+    pub fn get_server_endpoints(&self) -> Result<Vec<EndpointDescription>, StatusCode> {
+        self.runtime.block_on(self.client.get_server_endpoints())
+    }
+
+    /// Connects to a server endpoint and waits until the session is activated. See
+    /// [`Client::new_session_from_endpoint`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BlockingSession)` - The connected session.
+    /// * `Err(StatusCode)` - The session could not be created or connected.
+    pub fn connect_to_endpoint(
+        &mut self,
+        endpoint: impl Into<EndpointDescription>,
+        user_identity_token: IdentityToken,
+    ) -> Result<BlockingSession, StatusCode> {
+        let (session, event_loop) = self.runtime.block_on(
+            self.client
+                .new_session_from_endpoint(endpoint, user_identity_token),
+        )?;
+        BlockingSession::connect(
+            self.runtime.clone(),
+            session,
+            event_loop,
+            self.callback_queue_size,
+        )
+    }
+
+    /// Connects to a named endpoint of the client configuration, or the default endpoint if
+    /// `endpoint_id` is `None`, and waits until the session is activated. See
+    /// [`Client::connect_to_endpoint_id`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BlockingSession)` - The connected session.
+    /// * `Err(StatusCode)` - The session could not be created or connected.
+    pub fn connect_to_endpoint_id(
+        &mut self,
+        endpoint_id: Option<&str>,
+    ) -> Result<BlockingSession, StatusCode> {
+        let (session, event_loop) = self
+            .runtime
+            .block_on(self.client.connect_to_endpoint_id(endpoint_id))?;
+        BlockingSession::connect(
+            self.runtime.clone(),
+            session,
+            event_loop,
+            self.callback_queue_size,
+        )
+    }
+}
+
+/// A session whose service calls block the calling thread until the server responds. The session
+/// event loop runs on the runtime of the [`BlockingClient`] that created it, and keeps the session
+/// connected as the asynchronous session would.
+///
+/// Dropping the session without calling [`BlockingSession::disconnect`] stops the event loop
+/// without closing the session on the server.
+pub struct BlockingSession {
+    runtime: Arc<Runtime>,
+    session: Arc<Session>,
+    event_loop: Mutex<Option<JoinHandle<StatusCode>>>,
+    callbacks: mpsc::SyncSender<Job>,
+}
+
+impl BlockingSession {
+    fn connect(
+        runtime: Arc<Runtime>,
+        session: Arc<Session>,
+        event_loop: SessionEventLoop,
+        callback_queue_size: usize,
+    ) -> Result<Self, StatusCode> {
+        let mut event_loop = runtime.spawn(event_loop.run());
+        let connected = runtime.block_on(async {
+            select! {
+                _ = session.wait_for_connection() => Ok(()),
+                status = &mut event_loop => match status {
+                    Ok(status) if status.is_bad() => Err(status),
+                    _ => Err(StatusCode::BadNotConnected),
+                },
+            }
+        });
+        if let Err(status) = connected {
+            error!("Cannot connect the session, error = {}", status);
+            return Err(status);
+        }
+
+        // The event loop must never wait for the callback thread, since a callback may be
+        // waiting for the event loop to complete a blocking call on the session
+        let (callbacks, jobs) = mpsc::sync_channel::<Job>(callback_queue_size);
+        thread::Builder::new()
+            .name(format!("opcua-callbacks-{}", session.session_id()))
+            .spawn(move || {
+                // Ends when every subscription callback of the session has been dropped
+                for job in jobs {
+                    job();
+                }
+            })
+            .map_err(|e| {
+                error!("Cannot start the callback thread, error = {}", e);
+                event_loop.abort();
+                StatusCode::BadResourceUnavailable
+            })?;
+
+        Ok(Self {
+            runtime,
+            session,
+            event_loop: Mutex::new(Some(event_loop)),
+            callbacks,
+        })
+    }
+
+    /// The asynchronous session, for calls that have no blocking version.
+    pub fn session(&self) -> &Arc<Session> {
+        &self.session
+    }
+
+    /// Run a future on the runtime of the session and block until it completes, e.g. to make an
+    /// asynchronous call on [`BlockingSession::session`].
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Close the session and the connection to the server, and wait for the event loop to end.
+    pub fn disconnect(&self) -> Result<(), StatusCode> {
+        self.block_on(self.session.disconnect())?;
+        let event_loop = trace_lock!(self.event_loop).take();
+        if let Some(event_loop) = event_loop {
+            let _ = self.block_on(event_loop);
+        }
+        Ok(())
+    }
+
+    /// Read attributes of nodes. See [`Session::read`].
+    pub fn read(
+        &self,
+        nodes_to_read: &[ReadValueId],
+        timestamps_to_return: TimestampsToReturn,
+        max_age: f64,
+    ) -> Result<Vec<DataValue>, StatusCode> {
+        self.block_on(
+            self.session
+                .read(nodes_to_read, timestamps_to_return, max_age),
+        )
+    }
+
+    /// Write attributes of nodes. See [`Session::write`].
+    pub fn write(&self, nodes_to_write: &[WriteValue]) -> Result<Vec<StatusCode>, StatusCode> {
+        self.block_on(self.session.write(nodes_to_write))
+    }
+
+    /// Read the history of nodes. See [`Session::history_read`].
+    pub fn history_read(
+        &self,
+        history_read_details: HistoryReadAction,
+        timestamps_to_return: TimestampsToReturn,
+        release_continuation_points: bool,
+        nodes_to_read: &[HistoryReadValueId],
+    ) -> Result<Vec<HistoryReadResult>, StatusCode> {
+        self.block_on(self.session.history_read(
+            history_read_details,
+            timestamps_to_return,
+            release_continuation_points,
+            nodes_to_read,
+        ))
+    }
+
+    /// Update the history of nodes. See [`Session::history_update`].
+    pub fn history_update(
+        &self,
+        history_update_details: &[HistoryUpdateAction],
+    ) -> Result<Vec<HistoryUpdateResult>, StatusCode> {
+        self.block_on(self.session.history_update(history_update_details))
+    }
+
+    /// Browse the references of nodes. See [`Session::browse`].
+    pub fn browse(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+    ) -> Result<Option<Vec<BrowseResult>>, StatusCode> {
+        self.block_on(self.session.browse(nodes_to_browse))
+    }
+
+    /// Continue or release browse continuation points. See [`Session::browse_next`].
+    pub fn browse_next(
+        &self,
+        release_continuation_points: bool,
+        continuation_points: &[ByteString],
+    ) -> Result<Option<Vec<BrowseResult>>, StatusCode> {
+        self.block_on(
+            self.session
+                .browse_next(release_continuation_points, continuation_points),
+        )
+    }
+
+    /// Translate browse paths to node ids. See [`Session::translate_browse_paths_to_node_ids`].
+    pub fn translate_browse_paths_to_node_ids(
+        &self,
+        browse_paths: &[BrowsePath],
+    ) -> Result<Vec<BrowsePathResult>, StatusCode> {
+        self.block_on(
+            self.session
+                .translate_browse_paths_to_node_ids(browse_paths),
+        )
+    }
+
+    /// Call a method. See [`Session::call`].
+    pub fn call(
+        &self,
+        method: impl Into<CallMethodRequest>,
+    ) -> Result<CallMethodResult, StatusCode> {
+        self.block_on(self.session.call(method))
+    }
+
+    /// Call several methods. See [`Session::call_methods`].
+    pub fn call_methods(
+        &self,
+        methods_to_call: &[CallMethodRequest],
+    ) -> Result<Vec<CallMethodResult>, StatusCode> {
+        self.block_on(self.session.call_methods(methods_to_call))
+    }
+
+    /// Create a subscription. See [`Session::create_subscription`]. The callback is called on the
+    /// callback thread of the session rather than in the session event loop, so it may block
+    /// and may make blocking calls on the session.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_subscription(
+        &self,
+        publishing_interval: Duration,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        publishing_enabled: bool,
+        callback: impl OnSubscriptionNotification + 'static,
+    ) -> Result<u32, StatusCode> {
+        let callback = ThreadedCallback {
+            callback: Arc::new(Mutex::new(Box::new(callback))),
+            jobs: self.callbacks.clone(),
+            overflowed: false,
+        };
+        self.block_on(self.session.create_subscription(
+            publishing_interval,
+            lifetime_count,
+            max_keep_alive_count,
+            max_notifications_per_publish,
+            priority,
+            publishing_enabled,
+            callback,
+        ))
+    }
+
+    /// Modify a subscription. See [`Session::modify_subscription`].
+    pub fn modify_subscription(
+        &self,
+        subscription_id: u32,
+        publishing_interval: f64,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+    ) -> Result<(), StatusCode> {
+        self.block_on(self.session.modify_subscription(
+            subscription_id,
+            publishing_interval,
+            lifetime_count,
+            max_keep_alive_count,
+            max_notifications_per_publish,
+            priority,
+        ))
+    }
+
+    /// Enable or disable publishing of subscriptions. See [`Session::set_publishing_mode`].
+    pub fn set_publishing_mode(
+        &self,
+        subscription_ids: &[u32],
+        publishing_enabled: bool,
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        self.block_on(
+            self.session
+                .set_publishing_mode(subscription_ids, publishing_enabled),
+        )
+    }
+
+    /// Delete a subscription. See [`Session::delete_subscription`].
+    pub fn delete_subscription(&self, subscription_id: u32) -> Result<StatusCode, StatusCode> {
+        self.block_on(self.session.delete_subscription(subscription_id))
+    }
+
+    /// Create monitored items in a subscription. See [`Session::create_monitored_items`].
+    pub fn create_monitored_items(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: Vec<MonitoredItemCreateRequest>,
+    ) -> Result<Vec<MonitoredItemCreateResult>, StatusCode> {
+        self.block_on(self.session.create_monitored_items(
+            subscription_id,
+            timestamps_to_return,
+            items_to_create,
+        ))
+    }
+
+    /// Modify monitored items of a subscription. See [`Session::modify_monitored_items`].
+    pub fn modify_monitored_items(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_modify: &[MonitoredItemModifyRequest],
+    ) -> Result<Vec<MonitoredItemModifyResult>, StatusCode> {
+        self.block_on(self.session.modify_monitored_items(
+            subscription_id,
+            timestamps_to_return,
+            items_to_modify,
+        ))
+    }
+
+    /// Set the monitoring mode of monitored items. See [`Session::set_monitoring_mode`].
+    pub fn set_monitoring_mode(
+        &self,
+        subscription_id: u32,
+        monitoring_mode: MonitoringMode,
+        monitored_item_ids: &[u32],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        self.block_on(self.session.set_monitoring_mode(
+            subscription_id,
+            monitoring_mode,
+            monitored_item_ids,
+        ))
+    }
+
+    /// Delete monitored items of a subscription. See [`Session::delete_monitored_items`].
+    pub fn delete_monitored_items(
+        &self,
+        subscription_id: u32,
+        items_to_delete: &[u32],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        self.block_on(
+            self.session
+                .delete_monitored_items(subscription_id, items_to_delete),
+        )
+    }
+}
+
+impl Drop for BlockingSession {
+    fn drop(&mut self) {
+        if let Some(event_loop) = trace_lock!(self.event_loop).take() {
+            event_loop.abort();
+        }
+    }
+}
+
+/// Passes notifications from the session event loop to a callback on the callback thread.
+struct ThreadedCallback {
+    callback: Arc<Mutex<Box<dyn OnSubscriptionNotification>>>,
+    jobs: mpsc::SyncSender<Job>,
+    /// Set when a notification was dropped because the queue of the callback thread was full
+    overflowed: bool,
+}
+
+impl ThreadedCallback {
+    /// Queue a call of the callback without waiting, returning false if it was dropped.
+    fn dispatch(
+        &mut self,
+        f: impl FnOnce(&mut dyn OnSubscriptionNotification) + Send + 'static,
+    ) -> bool {
+        let callback = self.callback.clone();
+        let job = Box::new(move || {
+            let mut callback = trace_lock!(callback);
+            f(callback.as_mut());
+        });
+        match self.jobs.try_send(job) {
+            Ok(_) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                if !self.overflowed {
+                    warn!("Callback queue is full, dropping subscription notifications");
+                }
+                self.overflowed = true;
+                false
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                warn!("Callback thread has ended, dropping a subscription notification");
+                false
+            }
+        }
+    }
+}
+
+impl OnSubscriptionNotification for ThreadedCallback {
+    fn on_subscription_status_change(&mut self, notification: StatusChangeNotification) {
+        self.dispatch(move |c| c.on_subscription_status_change(notification));
+    }
+
+    fn on_data_value(&mut self, mut notification: DataValue, item: &MonitoredItem) {
+        let overflowed = self.overflowed;
+        if overflowed {
+            notification.status = Some(notification.status() | StatusCode::OVERFLOW);
+        }
+        let item = item.clone();
+        if self.dispatch(move |c| c.on_data_value(notification, &item)) && overflowed {
+            self.overflowed = false;
+        }
+    }
+
+    fn on_event(&mut self, event_fields: Option<Vec<Variant>>, item: &MonitoredItem) {
+        let item = item.clone();
+        self.dispatch(move |c| c.on_event(event_fields, &item));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+
+    use super::{Job, ThreadedCallback};
+    use crate::{
+        client::{DataChangeCallback, MonitoredItem, OnSubscriptionNotification},
+        sync::Mutex,
+        types::{DataValue, StatusCode},
+    };
+
+    #[test]
+    fn full_callback_queue_drops_notifications() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (jobs, queue) = mpsc::sync_channel::<Job>(1);
+        let mut callback = ThreadedCallback {
+            callback: Arc::new(Mutex::new(Box::new(DataChangeCallback::new({
+                let received = received.clone();
+                move |value: DataValue, _: &MonitoredItem| {
+                    let status = value.status();
+                    trace_lock!(received).push((value.value.unwrap(), status))
+                }
+            })))),
+            jobs,
+            overflowed: false,
+        };
+        let item = MonitoredItem::new(1);
+
+        // The second value does not fit in the queue and is dropped without blocking
+        callback.on_data_value(DataValue::new_now(1), &item);
+        callback.on_data_value(DataValue::new_now(2), &item);
+        queue.recv().unwrap()();
+
+        // The next value says that values were dropped
+        callback.on_data_value(DataValue::new_now(3), &item);
+        queue.recv().unwrap()();
+        callback.on_data_value(DataValue::new_now(4), &item);
+        queue.recv().unwrap()();
+
+        let received = trace_lock!(received);
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], (1.into(), StatusCode::Good));
+        assert!(received[1].1.contains(StatusCode::OVERFLOW));
+        assert_eq!(received[1].0, 3.into());
+        assert_eq!(received[2], (4.into(), StatusCode::Good));
+    }
+}
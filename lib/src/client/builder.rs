@@ -1,6 +1,9 @@
 use std::{path::PathBuf, time::Duration};
 
-use crate::server::prelude::Config;
+use crate::{
+    crypto::{CertificateRenewal, PrivateKeyPassphrase, SecurityProfile},
+    server::prelude::Config,
+};
 
 use super::{Client, ClientConfig, ClientEndpoint, ClientUserToken, ANONYMOUS_USER_TOKEN_ID};
 
@@ -87,6 +90,14 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets where the passphrase of an encrypted private key comes from, e.g. an environment
+    /// variable or a callback. If the client creates a sample keypair, its private key is
+    /// encrypted with this passphrase.
+    pub fn private_key_passphrase(mut self, private_key_passphrase: PrivateKeyPassphrase) -> Self {
+        self.config.private_key_passphrase = Some(private_key_passphrase);
+        self
+    }
+
     /// Sets whether the client should automatically trust servers. If this is not set then
     /// the client will reject the server upon first connect and the server's certificate
     /// must be manually moved from pki's `/rejected` folder to the `/trusted` folder. If it is
@@ -105,6 +116,24 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets the client to renew its own self-signed certificate when it expires within the
+    /// specified number of days. The client checks when it is created and before each new session.
+    pub fn renew_certificate_before_days(mut self, renew_before_days: u32) -> Self {
+        self.config.certificate_renewal = CertificateRenewal {
+            enabled: true,
+            renew_before_days,
+        };
+        self
+    }
+
+    /// Sets the security profile, which restricts the security policies of the endpoints the
+    /// client connects to and the server certificates it accepts. By default the deprecated
+    /// policies Basic128Rsa15 and Basic256 are not allowed.
+    pub fn security_profile(mut self, security_profile: SecurityProfile) -> Self {
+        self.config.security_profile = security_profile;
+        self
+    }
+
     /// Sets the pki directory where client's own key pair is stored and where `/trusted` and
     /// `/rejected` server certificates are stored.
     pub fn pki_dir(mut self, pki_dir: impl Into<PathBuf>) -> Self {
@@ -279,6 +308,13 @@ impl ClientBuilder {
         self
     }
 
+    /// Maximum number of concurrent requests when a call with more operations than the server
+    /// operation limits allow is split into several requests.
+    pub fn max_concurrent_batches(mut self, max_concurrent_batches: usize) -> Self {
+        self.config.performance.max_concurrent_batches = max_concurrent_batches;
+        self
+    }
+
     /// Session name - the default name to use for a new session
     pub fn session_name(mut self, session_name: impl Into<String>) -> Self {
         self.config.session_name = session_name.into();
@@ -0,0 +1,746 @@
+//! A client side cache of the address space of a server.
+//!
+//! [`AddressSpaceCache`] crawls the server from one or more root nodes with `Browse` and `Read`,
+//! and keeps a graph of the nodes, their attributes and references in memory. Nodes can be
+//! looked up by node id or by a browse path such as `/Objects/2:Plant/2:Line1`. The cache can
+//! subscribe to `GeneralModelChangeEventType` events so that it stays fresh when the address space
+//! of the server changes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::StreamExt;
+
+use crate::{
+    sync::RwLock,
+    types::{
+        AttributeId, BrowseDescription, BrowseDirection, BrowsePath, BrowseResultMask,
+        ContentFilter, DataValue, EventFilter, ExtensionObject, FilterOperator, LocalizedText,
+        ModelChangeStructureDataType, ModelChangeStructureVerbMask, MonitoredItemCreateRequest,
+        NodeClass, NodeId, ObjectId, ObjectTypeId, Operand, QualifiedName, ReadValueId,
+        ReferenceDescription, ReferenceTypeId, RelativePath, RelativePathElement,
+        SimpleAttributeOperand, StatusCode, TimestampsToReturn, UAString, Variant,
+    },
+};
+
+use super::{OverflowPolicy, Session, StreamOptions, SubscriptionNotification};
+
+/// Options that control which nodes and references [`AddressSpaceCache`] crawls.
+#[derive(Debug, Clone)]
+pub struct CrawlOptions {
+    /// Maximum number of references to follow from a root node, or `None` for no limit.
+    pub max_depth: Option<usize>,
+    /// The type of the references to follow.
+    pub reference_type_id: NodeId,
+    /// Whether subtypes of `reference_type_id` are followed too.
+    pub include_subtypes: bool,
+    /// Mask of the [`NodeClass`] values of the nodes to follow, or 0 for every node class.
+    pub node_class_mask: u32,
+    /// Attributes read for each node in addition to the node class, browse name and display name.
+    pub attributes: Vec<AttributeId>,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            attributes: vec![AttributeId::Description],
+        }
+    }
+}
+
+/// A node in an [`AddressSpaceCache`].
+#[derive(Debug, Clone)]
+pub struct CachedNode {
+    node_id: NodeId,
+    /// Number of references followed from a root node to reach this node
+    depth: usize,
+    attributes: HashMap<AttributeId, DataValue>,
+    references: Vec<ReferenceDescription>,
+}
+
+impl CachedNode {
+    /// The id of the node.
+    pub fn node_id(&self) -> &NodeId {
+        &self.node_id
+    }
+
+    /// The class of the node.
+    pub fn node_class(&self) -> NodeClass {
+        match self.attribute(AttributeId::NodeClass) {
+            Some(Variant::Int32(v)) => node_class_from_i32(*v),
+            _ => NodeClass::Unspecified,
+        }
+    }
+
+    /// The browse name of the node.
+    pub fn browse_name(&self) -> QualifiedName {
+        match self.attribute(AttributeId::BrowseName) {
+            Some(Variant::QualifiedName(v)) => (**v).clone(),
+            _ => QualifiedName::null(),
+        }
+    }
+
+    /// The display name of the node.
+    pub fn display_name(&self) -> LocalizedText {
+        match self.attribute(AttributeId::DisplayName) {
+            Some(Variant::LocalizedText(v)) => (**v).clone(),
+            _ => LocalizedText::null(),
+        }
+    }
+
+    /// The value of an attribute read by the crawl, if the node has it.
+    pub fn attribute(&self, attribute_id: AttributeId) -> Option<&Variant> {
+        self.attributes
+            .get(&attribute_id)
+            .and_then(|v| v.value.as_ref())
+    }
+
+    /// The references of the node that the crawl followed.
+    pub fn references(&self) -> &[ReferenceDescription] {
+        &self.references
+    }
+}
+
+/// A client side cache of the address space of a server, filled by crawling it from root nodes.
+pub struct AddressSpaceCache {
+    session: Arc<Session>,
+    options: CrawlOptions,
+    roots: RwLock<Vec<NodeId>>,
+    nodes: RwLock<HashMap<NodeId, CachedNode>>,
+}
+
+impl AddressSpaceCache {
+    /// Create an empty cache that crawls the server of `session` with `options`.
+    pub fn new(session: Arc<Session>, options: CrawlOptions) -> Self {
+        Self {
+            session,
+            options,
+            roots: RwLock::new(Vec::new()),
+            nodes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The crawl options of the cache.
+    pub fn options(&self) -> &CrawlOptions {
+        &self.options
+    }
+
+    /// Crawl the server from `root`, adding every node that is reached to the cache. Nodes that
+    /// are already in the cache are read again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of nodes that were crawled.
+    /// * `Err(StatusCode)` - A browse or read request failed.
+    pub async fn crawl(&self, root: &NodeId) -> Result<usize, StatusCode> {
+        {
+            let mut roots = trace_write_lock!(self.roots);
+            if !roots.contains(root) {
+                roots.push(root.clone());
+            }
+        }
+        self.crawl_from(vec![(root.clone(), 0)], &mut HashSet::new(), &self.nodes)
+            .await
+    }
+
+    /// Browse and read the nodes in `frontier`, and then the nodes they reference, level by level
+    /// until `max_depth` is reached. The nodes are written to `nodes` as each level is read.
+    async fn crawl_from(
+        &self,
+        mut frontier: Vec<(NodeId, usize)>,
+        visited: &mut HashSet<NodeId>,
+        nodes: &RwLock<HashMap<NodeId, CachedNode>>,
+    ) -> Result<usize, StatusCode> {
+        let mut crawled = 0;
+        frontier.retain(|(node_id, _)| visited.insert(node_id.clone()));
+        while !frontier.is_empty() {
+            let fetched = self.fetch(&frontier).await?;
+            crawled += fetched.len();
+
+            let mut next = Vec::new();
+            for node in &fetched {
+                if self.options.max_depth.is_some_and(|max| node.depth >= max) {
+                    continue;
+                }
+                for reference in &node.references {
+                    if reference.node_id.server_index != 0 {
+                        continue;
+                    }
+                    let node_id = &reference.node_id.node_id;
+                    if visited.insert(node_id.clone()) {
+                        next.push((node_id.clone(), node.depth + 1));
+                    }
+                }
+            }
+
+            {
+                let mut cache = trace_write_lock!(nodes);
+                for (node_id, _) in &frontier {
+                    cache.remove(node_id);
+                }
+                for node in fetched {
+                    cache.insert(node.node_id.clone(), node);
+                }
+            }
+            frontier = next;
+        }
+        Ok(crawled)
+    }
+
+    /// Browse and read a set of nodes. Nodes that the server does not know are left out.
+    async fn fetch(&self, node_ids: &[(NodeId, usize)]) -> Result<Vec<CachedNode>, StatusCode> {
+        let nodes_to_browse = node_ids
+            .iter()
+            .map(|(node_id, _)| BrowseDescription {
+                node_id: node_id.clone(),
+                browse_direction: BrowseDirection::Forward,
+                reference_type_id: self.options.reference_type_id.clone(),
+                include_subtypes: self.options.include_subtypes,
+                node_class_mask: self.options.node_class_mask,
+                result_mask: BrowseResultMask::All as u32,
+            })
+            .collect::<Vec<_>>();
+        let browse_results = self
+            .session
+            .browse(&nodes_to_browse)
+            .await?
+            .unwrap_or_default();
+
+        let mut attribute_ids = vec![
+            AttributeId::NodeClass,
+            AttributeId::BrowseName,
+            AttributeId::DisplayName,
+        ];
+        for attribute_id in &self.options.attributes {
+            if !attribute_ids.contains(attribute_id) {
+                attribute_ids.push(*attribute_id);
+            }
+        }
+        let nodes_to_read = node_ids
+            .iter()
+            .flat_map(|(node_id, _)| {
+                attribute_ids.iter().map(|attribute_id| ReadValueId {
+                    node_id: node_id.clone(),
+                    attribute_id: *attribute_id as u32,
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let values = self
+            .session
+            .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+            .await?;
+
+        let nodes = node_ids
+            .iter()
+            .zip(browse_results)
+            .zip(values.chunks(attribute_ids.len()))
+            .filter(|((_, browse_result), _)| browse_result.status_code.is_good())
+            .map(|(((node_id, depth), browse_result), values)| CachedNode {
+                node_id: node_id.clone(),
+                depth: *depth,
+                attributes: attribute_ids
+                    .iter()
+                    .copied()
+                    .zip(values.iter().cloned())
+                    .filter(|(_, v)| v.status().is_good())
+                    .collect(),
+                references: browse_result.references.unwrap_or_default(),
+            })
+            .collect();
+        Ok(nodes)
+    }
+
+    /// Browse and read a node again, crawling any nodes it now references that are not cached.
+    /// The node is removed from the cache if the server no longer knows it.
+    pub async fn refresh(&self, node_id: &NodeId) -> Result<(), StatusCode> {
+        let depth = {
+            let cache = trace_read_lock!(self.nodes);
+            cache.get(node_id).map(|n| n.depth)
+        };
+        let Some(depth) = depth else {
+            return Ok(());
+        };
+        let mut visited = {
+            let cache = trace_read_lock!(self.nodes);
+            cache
+                .keys()
+                .filter(|k| *k != node_id)
+                .cloned()
+                .collect::<HashSet<_>>()
+        };
+        self.crawl_from(vec![(node_id.clone(), depth)], &mut visited, &self.nodes)
+            .await?;
+        Ok(())
+    }
+
+    /// Crawl every root node again. The cache keeps its nodes until the crawl has succeeded, so
+    /// it is left as it was if a browse or read request fails.
+    pub async fn refresh_all(&self) -> Result<(), StatusCode> {
+        let roots = trace_read_lock!(self.roots).clone();
+        let nodes = RwLock::new(HashMap::new());
+        let mut visited = HashSet::new();
+        for root in roots {
+            self.crawl_from(vec![(root, 0)], &mut visited, &nodes)
+                .await?;
+        }
+        *trace_write_lock!(self.nodes) = nodes.into_inner();
+        Ok(())
+    }
+
+    /// Apply the changes of a model change event to the cache. Nodes that the changes affect are
+    /// refreshed, and every root is crawled again if the event does not say what changed. A
+    /// deleted node is removed, and the cached nodes that referenced it are refreshed so that
+    /// their references no longer lead to it.
+    pub async fn apply_model_changes(
+        &self,
+        changes: &[ModelChangeStructureDataType],
+    ) -> Result<(), StatusCode> {
+        if changes.is_empty() {
+            return self.refresh_all().await;
+        }
+        for change in changes {
+            if change.verb & ModelChangeStructureVerbMask::NodeDeleted as u8 != 0 {
+                let parents = {
+                    let mut cache = trace_write_lock!(self.nodes);
+                    cache.remove(&change.affected);
+                    referencing_nodes(&cache, &change.affected)
+                };
+                for parent in parents {
+                    self.refresh(&parent).await?;
+                }
+            } else {
+                self.refresh(&change.affected).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe to `GeneralModelChangeEventType` events of the server and apply them to the
+    /// cache until the subscription is deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `publishing_interval` - The publishing interval of the subscription.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(u32)` - The id of the subscription. Deleting it stops the updates.
+    /// * `Err(StatusCode)` - The subscription or its monitored item could not be created.
+    pub async fn watch_model_changes(
+        self: &Arc<Self>,
+        publishing_interval: Duration,
+    ) -> Result<u32, StatusCode> {
+        let (subscription_id, _) = self
+            .session
+            .create_subscription_stream(
+                publishing_interval,
+                100,
+                10,
+                0,
+                0,
+                true,
+                StreamOptions::new(1, OverflowPolicy::DropOldest),
+            )
+            .await?;
+
+        let event_filter = EventFilter {
+            select_clauses: Some(vec![SimpleAttributeOperand::new(
+                ObjectTypeId::GeneralModelChangeEventType,
+                "Changes",
+                AttributeId::Value,
+                UAString::null(),
+            )]),
+            where_clause: ContentFilter {
+                elements: Some(vec![(
+                    FilterOperator::OfType,
+                    vec![Operand::literal(Variant::from(NodeId::from(
+                        &ObjectTypeId::GeneralModelChangeEventType,
+                    )))],
+                )
+                    .into()]),
+            },
+        };
+        let mut item_to_create: MonitoredItemCreateRequest = NodeId::from(&ObjectId::Server).into();
+        item_to_create.item_to_monitor.attribute_id = AttributeId::EventNotifier as u32;
+        item_to_create.requested_parameters.queue_size = 100;
+        item_to_create.requested_parameters.filter = ExtensionObject::from_encodable(
+            ObjectId::EventFilter_Encoding_DefaultBinary,
+            &event_filter,
+        );
+
+        let mut results = self
+            .session
+            .create_monitored_items_stream(
+                subscription_id,
+                TimestampsToReturn::Neither,
+                vec![item_to_create],
+                StreamOptions::new(100, OverflowPolicy::DropOldest),
+            )
+            .await?;
+        let (result, mut events) = results.remove(0);
+        if result.status_code.is_bad() {
+            let _ = self.session.delete_subscription(subscription_id).await;
+            return Err(result.status_code);
+        }
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            while let Some(notification) = events.next().await {
+                let SubscriptionNotification::Event {
+                    event_fields,
+                    status,
+                    ..
+                } = notification
+                else {
+                    continue;
+                };
+                // Events were dropped, so the changes are not known
+                let changes = if status.contains(StatusCode::OVERFLOW) {
+                    Vec::new()
+                } else {
+                    cache.model_changes(event_fields)
+                };
+                if let Err(e) = cache.apply_model_changes(&changes).await {
+                    warn!(
+                        "Failed to apply model changes to the address space cache: {}",
+                        e
+                    );
+                }
+            }
+        });
+
+        Ok(subscription_id)
+    }
+
+    /// Decode the `Changes` field of a model change event.
+    fn model_changes(
+        &self,
+        event_fields: Option<Vec<Variant>>,
+    ) -> Vec<ModelChangeStructureDataType> {
+        let Some(Variant::Array(changes)) = event_fields.and_then(|f| f.into_iter().next()) else {
+            return Vec::new();
+        };
+        let decoding_options = self.session.decoding_options();
+        changes
+            .values
+            .iter()
+            .filter_map(|v| match v {
+                Variant::ExtensionObject(obj) => obj.decode_inner(&decoding_options).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get a copy of a node from the cache.
+    pub fn get(&self, node_id: &NodeId) -> Option<CachedNode> {
+        trace_read_lock!(self.nodes).get(node_id).cloned()
+    }
+
+    /// The number of nodes in the cache.
+    pub fn len(&self) -> usize {
+        trace_read_lock!(self.nodes).len()
+    }
+
+    /// Tests if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        trace_read_lock!(self.nodes).is_empty()
+    }
+
+    /// The ids of the nodes that a cached node references.
+    pub fn children(&self, node_id: &NodeId) -> Vec<NodeId> {
+        trace_read_lock!(self.nodes)
+            .get(node_id)
+            .map(|n| {
+                n.references
+                    .iter()
+                    .filter(|r| r.node_id.server_index == 0)
+                    .map(|r| r.node_id.node_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Find the nodes that a browse path relative to the root folder leads to in the cache, e.g.
+    /// `/Objects/2:Plant/2:Line1`. The path uses the syntax of OPC UA Part 4 Appendix A.
+    ///
+    /// References are matched by their type and the standard reference type hierarchy, so paths
+    /// with references of types that are defined by the server are only found by
+    /// [`AddressSpaceCache::resolve`].
+    pub fn find(&self, path: &str) -> Result<Vec<NodeId>, StatusCode> {
+        self.find_from(&ObjectId::RootFolder.into(), path)
+    }
+
+    /// Find the nodes that a browse path relative to `node_id` leads to in the cache.
+    pub fn find_from(&self, node_id: &NodeId, path: &str) -> Result<Vec<NodeId>, StatusCode> {
+        let relative_path = parse_path(path)?;
+        let cache = trace_read_lock!(self.nodes);
+        find_nodes(&cache, node_id, &relative_path)
+    }
+
+    /// Find the nodes that a browse path relative to the root folder leads to, looking in the
+    /// cache first and then asking the server with `TranslateBrowsePathsToNodeIds`.
+    pub async fn resolve(&self, path: &str) -> Result<Vec<NodeId>, StatusCode> {
+        if let Ok(node_ids) = self.find(path) {
+            return Ok(node_ids);
+        }
+        let browse_path = BrowsePath {
+            starting_node: ObjectId::RootFolder.into(),
+            relative_path: parse_path(path)?,
+        };
+        let mut results = self
+            .session
+            .translate_browse_paths_to_node_ids(&[browse_path])
+            .await?;
+        let result = results.pop().ok_or(StatusCode::BadUnexpectedError)?;
+        if result.status_code.is_bad() {
+            return Err(result.status_code);
+        }
+        Ok(result
+            .targets
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| t.target_id.server_index == 0)
+            .map(|t| t.target_id.node_id)
+            .collect())
+    }
+}
+
+fn parse_path(path: &str) -> Result<RelativePath, StatusCode> {
+    RelativePath::from_str(path, &RelativePathElement::default_node_resolver)
+        .map_err(|_| StatusCode::BadBrowseNameInvalid)
+}
+
+fn node_class_from_i32(v: i32) -> NodeClass {
+    match v {
+        1 => NodeClass::Object,
+        2 => NodeClass::Variable,
+        4 => NodeClass::Method,
+        8 => NodeClass::ObjectType,
+        16 => NodeClass::VariableType,
+        32 => NodeClass::ReferenceType,
+        64 => NodeClass::DataType,
+        128 => NodeClass::View,
+        _ => NodeClass::Unspecified,
+    }
+}
+
+/// The supertype of a standard reference type.
+fn reference_supertype(reference_type_id: &NodeId) -> Option<ReferenceTypeId> {
+    use ReferenceTypeId::*;
+    let supertype = match reference_type_id.as_reference_type_id().ok()? {
+        HierarchicalReferences | NonHierarchicalReferences => References,
+        HasChild | Organizes | HasEventSource => HierarchicalReferences,
+        Aggregates | HasSubtype => HasChild,
+        HasComponent | HasProperty => Aggregates,
+        HasOrderedComponent => HasComponent,
+        HasNotifier => HasEventSource,
+        HasTypeDefinition | HasModellingRule | HasEncoding | HasDescription | GeneratesEvent => {
+            NonHierarchicalReferences
+        }
+        _ => return None,
+    };
+    Some(supertype)
+}
+
+/// Tests if a reference of type `reference_type_id` matches a path element.
+fn reference_matches(reference_type_id: &NodeId, element: &RelativePathElement) -> bool {
+    if *reference_type_id == element.reference_type_id {
+        return true;
+    }
+    if !element.include_subtypes {
+        return false;
+    }
+    let mut reference_type_id = reference_type_id.clone();
+    while let Some(supertype) = reference_supertype(&reference_type_id) {
+        reference_type_id = supertype.into();
+        if reference_type_id == element.reference_type_id {
+            return true;
+        }
+    }
+    false
+}
+
+/// The cached nodes with a reference to `node_id`.
+fn referencing_nodes(cache: &HashMap<NodeId, CachedNode>, node_id: &NodeId) -> Vec<NodeId> {
+    cache
+        .values()
+        .filter(|node| {
+            node.references
+                .iter()
+                .any(|r| r.node_id.server_index == 0 && r.node_id.node_id == *node_id)
+        })
+        .map(|node| node.node_id.clone())
+        .collect()
+}
+
+/// Follow a relative path through the cached references from `node_id`.
+fn find_nodes(
+    cache: &HashMap<NodeId, CachedNode>,
+    node_id: &NodeId,
+    relative_path: &RelativePath,
+) -> Result<Vec<NodeId>, StatusCode> {
+    let elements = relative_path.elements.as_deref().unwrap_or_default();
+    if elements.is_empty() {
+        return Err(StatusCode::BadNothingToDo);
+    }
+    let mut matching_nodes = vec![node_id.clone()];
+    for element in elements {
+        if element.target_name.is_null() {
+            return Err(StatusCode::BadBrowseNameInvalid);
+        }
+        matching_nodes = matching_nodes
+            .iter()
+            .filter_map(|node_id| cache.get(node_id))
+            .flat_map(|node| node.references.iter())
+            .filter(|r| {
+                r.is_forward != element.is_inverse
+                    && r.node_id.server_index == 0
+                    && r.browse_name == element.target_name
+                    && reference_matches(&r.reference_type_id, element)
+            })
+            .map(|r| r.node_id.node_id.clone())
+            .collect();
+        if matching_nodes.is_empty() {
+            return Err(StatusCode::BadNoMatch);
+        }
+    }
+    Ok(matching_nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{find_nodes, parse_path, referencing_nodes, CachedNode};
+    use crate::types::{
+        ExpandedNodeId, LocalizedText, NodeClass, NodeId, ObjectId, QualifiedName,
+        ReferenceDescription, ReferenceTypeId, StatusCode,
+    };
+
+    fn node(
+        node_id: NodeId,
+        references: Vec<(ReferenceTypeId, NodeId, QualifiedName)>,
+    ) -> CachedNode {
+        CachedNode {
+            node_id,
+            depth: 0,
+            attributes: HashMap::new(),
+            references: references
+                .into_iter()
+                .map(
+                    |(reference_type_id, node_id, browse_name)| ReferenceDescription {
+                        reference_type_id: reference_type_id.into(),
+                        is_forward: true,
+                        node_id: ExpandedNodeId::from(node_id),
+                        display_name: LocalizedText::from(browse_name.name.as_ref()),
+                        browse_name,
+                        node_class: NodeClass::Object,
+                        type_definition: ExpandedNodeId::null(),
+                    },
+                )
+                .collect(),
+        }
+    }
+
+    fn cache() -> HashMap<NodeId, CachedNode> {
+        let root: NodeId = ObjectId::RootFolder.into();
+        let objects: NodeId = ObjectId::ObjectsFolder.into();
+        let plant = NodeId::new(2, "Plant");
+        let line = NodeId::new(2, "Line1");
+        let speed = NodeId::new(2, "Speed");
+        [
+            node(
+                root.clone(),
+                vec![(
+                    ReferenceTypeId::Organizes,
+                    objects.clone(),
+                    QualifiedName::new(0, "Objects"),
+                )],
+            ),
+            node(
+                objects.clone(),
+                vec![(
+                    ReferenceTypeId::Organizes,
+                    plant.clone(),
+                    QualifiedName::new(2, "Plant"),
+                )],
+            ),
+            node(
+                plant.clone(),
+                vec![(
+                    ReferenceTypeId::HasComponent,
+                    line.clone(),
+                    QualifiedName::new(2, "Line1"),
+                )],
+            ),
+            node(
+                line.clone(),
+                vec![(
+                    ReferenceTypeId::HasProperty,
+                    speed.clone(),
+                    QualifiedName::new(2, "Speed"),
+                )],
+            ),
+        ]
+        .into_iter()
+        .map(|n| (n.node_id.clone(), n))
+        .collect()
+    }
+
+    fn find(path: &str) -> Result<Vec<NodeId>, StatusCode> {
+        find_nodes(
+            &cache(),
+            &ObjectId::RootFolder.into(),
+            &parse_path(path).unwrap(),
+        )
+    }
+
+    #[test]
+    fn find_by_browse_path() {
+        assert_eq!(
+            find("/Objects/2:Plant/2:Line1").unwrap(),
+            vec![NodeId::new(2, "Line1")]
+        );
+        assert_eq!(
+            find("/Objects/2:Plant.2:Line1.2:Speed").unwrap(),
+            vec![NodeId::new(2, "Speed")]
+        );
+    }
+
+    #[test]
+    fn find_checks_reference_types() {
+        // Organizes is not an Aggregates reference
+        assert_eq!(find(".Objects"), Err(StatusCode::BadNoMatch));
+        assert_eq!(
+            find("<Organizes>Objects/2:Plant").unwrap(),
+            vec![NodeId::new(2, "Plant")]
+        );
+        assert_eq!(find("<HasComponent>Objects"), Err(StatusCode::BadNoMatch));
+    }
+
+    #[test]
+    fn find_missing_node() {
+        assert_eq!(find("/Objects/2:Line1"), Err(StatusCode::BadNoMatch));
+        assert_eq!(find("/Objects/Plant"), Err(StatusCode::BadNoMatch));
+    }
+
+    #[test]
+    fn referencing_nodes_of_deleted_node() {
+        let mut cache = cache();
+        let line = NodeId::new(2, "Line1");
+        cache.remove(&line);
+        assert_eq!(
+            referencing_nodes(&cache, &line),
+            vec![NodeId::new(2, "Plant")]
+        );
+        assert!(referencing_nodes(&cache, &ObjectId::RootFolder.into()).is_empty());
+    }
+}
@@ -14,11 +14,13 @@ use std::{
 
 use crate::{
     core::config::Config,
-    crypto::SecurityPolicy,
+    crypto::{
+        passphrase, CertificateRenewal, PrivateKeyPassphrase, SecurityPolicy, SecurityProfile,
+    },
     types::{ApplicationType, MessageSecurityMode, UAString},
 };
 
-use super::retry::SessionRetryPolicy;
+use super::{retry::SessionRetryPolicy, IssuedTokenProvider};
 
 pub const ANONYMOUS_USER_TOKEN_ID: &str = "ANONYMOUS";
 
@@ -33,6 +35,12 @@ pub struct ClientUserToken {
     pub cert_path: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub private_key_path: Option<String>,
+    /// Passphrase of the private key if it is an encrypted PKCS#8 key
+    #[serde(default, skip_serializing_if = "passphrase::skip_serializing")]
+    pub private_key_passphrase: Option<PrivateKeyPassphrase>,
+    /// Supplies an issued token (JWT). It cannot be held in a config file and must be set in code.
+    #[serde(skip)]
+    pub issued_token_provider: Option<IssuedTokenProvider>,
 }
 
 impl ClientUserToken {
@@ -47,6 +55,8 @@ impl ClientUserToken {
             password: Some(password.into()),
             cert_path: None,
             private_key_path: None,
+            private_key_passphrase: None,
+            issued_token_provider: None,
         }
     }
 
@@ -62,6 +72,24 @@ impl ClientUserToken {
             password: None,
             cert_path: Some(cert_path.to_string_lossy().to_string()),
             private_key_path: Some(private_key_path.to_string_lossy().to_string()),
+            private_key_passphrase: None,
+            issued_token_provider: None,
+        }
+    }
+
+    /// Constructs a client token which obtains an issued token (JWT) from the provider every time
+    /// the session is activated.
+    pub fn issued_token<S>(user: S, issued_token_provider: IssuedTokenProvider) -> Self
+    where
+        S: Into<String>,
+    {
+        ClientUserToken {
+            user: user.into(),
+            password: None,
+            cert_path: None,
+            private_key_path: None,
+            private_key_passphrase: None,
+            issued_token_provider: Some(issued_token_provider),
         }
     }
 
@@ -74,7 +102,18 @@ impl ClientUserToken {
             valid = false;
         }
         // A token must properly represent one kind of token or it is not valid
-        if self.password.is_some() {
+        if self.issued_token_provider.is_some() {
+            if self.password.is_some()
+                || self.cert_path.is_some()
+                || self.private_key_path.is_some()
+            {
+                error!(
+                    "User token {} holds an issued token provider and a password or certificate info - it cannot be both.",
+                    self.user
+                );
+                valid = false;
+            }
+        } else if self.password.is_some() {
             if self.cert_path.is_some() || self.private_key_path.is_some() {
                 error!(
                     "User token {} holds a password and certificate info - it cannot be both.",
@@ -163,6 +202,16 @@ pub struct Performance {
     pub(crate) recreate_monitored_items_chunk: usize,
     /// Maximum number of inflight messages.
     pub(crate) max_inflight_messages: usize,
+    /// Maximum number of concurrent requests when a call is split to respect the operation
+    /// limits of the server.
+    #[serde(default = "Performance::default_max_concurrent_batches")]
+    pub(crate) max_concurrent_batches: usize,
+}
+
+impl Performance {
+    fn default_max_concurrent_batches() -> usize {
+        5
+    }
 }
 
 /// Client OPC UA configuration
@@ -181,12 +230,23 @@ pub struct ClientConfig {
     pub(crate) certificate_path: Option<PathBuf>,
     /// Custom private key path, to be used instead of the default private key path
     pub(crate) private_key_path: Option<PathBuf>,
+    /// Passphrase of the private key if it is an encrypted PKCS#8 key. A sample keypair is
+    /// created with an encrypted private key when this is set.
+    #[serde(default, skip_serializing_if = "passphrase::skip_serializing")]
+    pub(crate) private_key_passphrase: Option<PrivateKeyPassphrase>,
     /// Auto trusts server certificates. For testing/samples only unless you're sure what you're
     /// doing.
     pub(crate) trust_server_certs: bool,
     /// Verify server certificates. For testing/samples only unless you're sure what you're
     /// doing.
     pub(crate) verify_server_certs: bool,
+    /// Automatic renewal of the client's own certificate before it expires
+    #[serde(default)]
+    pub(crate) certificate_renewal: CertificateRenewal,
+    /// Restricts the security policies of the endpoints the client connects to and the server
+    /// certificates it accepts. Deprecated policies are disabled by default.
+    #[serde(default)]
+    pub(crate) security_profile: SecurityProfile,
     /// PKI folder, either absolute or relative to executable
     pub(crate) pki_dir: PathBuf,
     /// Preferred locales
@@ -254,6 +314,9 @@ impl Config for ClientConfig {
             error!("User tokens contains an endpoint with an empty id");
             valid = false;
         }
+        if !self.security_profile.is_valid() {
+            valid = false;
+        }
         self.user_tokens.iter().for_each(|(_, token)| {
             if !token.is_valid() {
                 valid = false;
@@ -343,8 +406,11 @@ impl ClientConfig {
             create_sample_keypair: false,
             certificate_path: None,
             private_key_path: None,
+            private_key_passphrase: None,
             trust_server_certs: false,
             verify_server_certs: true,
+            certificate_renewal: CertificateRenewal::default(),
+            security_profile: SecurityProfile::default(),
             product_uri: String::new(),
             pki_dir,
             preferred_locales: Vec::new(),
@@ -373,6 +439,7 @@ impl ClientConfig {
                 ignore_clock_skew: false,
                 recreate_monitored_items_chunk: 1000,
                 max_inflight_messages: 20,
+                max_concurrent_batches: Performance::default_max_concurrent_batches(),
             },
             session_name: "Rust OPC UA Client".into(),
         }
@@ -536,6 +603,8 @@ mod tests {
                 password: Some(String::new()),
                 cert_path: None,
                 private_key_path: None,
+                private_key_passphrase: None,
+                issued_token_provider: None,
             },
         );
         assert!(!config.is_valid());
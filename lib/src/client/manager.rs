@@ -0,0 +1,437 @@
+//! A connection manager that keeps sessions to many servers.
+//!
+//! [`ConnectionManager`] owns a session for each server it is given, keyed by endpoint url, and
+//! runs the event loop of each session on a shared runtime. The sessions share the certificate
+//! store of the [`Client`] they are made from. The manager limits how many servers it connects to
+//! at the same time, and retries failed connections with a jittered exponential backoff so that a
+//! mass outage is not followed by a reconnect storm. The health of each server can be read at any
+//! time, and its changes are published as a stream of [`ConnectionEvent`]s.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, TryStreamExt};
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::{
+    core::comms::url::is_opc_ua_binary_url,
+    sync::{Mutex, RwLock},
+    types::StatusCode,
+};
+
+use super::{
+    retry::SessionRetryPolicy, session::SessionInfo, Client, Session, SessionActivity,
+    SessionEventLoop, SessionPollResult,
+};
+
+/// Options of a [`ConnectionManager`].
+#[derive(Debug, Clone)]
+pub struct ConnectionManagerOptions {
+    /// Maximum number of servers that are connected to at the same time. Other servers wait for
+    /// a connection attempt to finish before they connect.
+    pub max_concurrent_connects: usize,
+    /// Delays between attempts to connect to a server. Each delay is randomized between half and
+    /// all of the delay of the policy. The manager gives up on a server after the retry limit.
+    pub retry_policy: SessionRetryPolicy,
+    /// Number of events that a stream of [`ConnectionManager::events`] holds. A stream that falls
+    /// further behind skips the oldest events.
+    pub event_capacity: usize,
+}
+
+impl Default for ConnectionManagerOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrent_connects: 8,
+            retry_policy: SessionRetryPolicy::infinity(
+                Duration::from_millis(SessionRetryPolicy::DEFAULT_MAX_SLEEP_MS),
+                Duration::from_millis(SessionRetryPolicy::DEFAULT_INITIAL_SLEEP_MS),
+            ),
+            event_capacity: 1024,
+        }
+    }
+}
+
+/// The state of the connection to a server of a [`ConnectionManager`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    /// Waiting to connect, or connecting to the server.
+    Connecting,
+    /// The session is connected and activated.
+    Connected,
+    /// The connection failed or was lost with the status code, and is retried after the delay.
+    Reconnecting { status: StatusCode, delay: Duration },
+    /// The manager gave up connecting to the server after the retry limit of its options.
+    Failed(StatusCode),
+    /// The session was closed, or the server was removed from the manager.
+    Closed,
+}
+
+/// The health of the connection to a server of a [`ConnectionManager`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionHealth {
+    /// State of the connection.
+    pub state: ConnectionState,
+    /// When the session last connected, if it is connected.
+    pub connected_since: Option<Instant>,
+    /// Last error of the connection, if any.
+    pub last_error: Option<StatusCode>,
+    /// Number of failed connection attempts since the session was last connected.
+    pub failed_attempts: u32,
+    /// Number of keep alive requests that failed in a row while connected.
+    pub keep_alive_failures: u32,
+}
+
+impl Default for ConnectionHealth {
+    fn default() -> Self {
+        Self {
+            state: ConnectionState::Connecting,
+            connected_since: None,
+            last_error: None,
+            failed_attempts: 0,
+            keep_alive_failures: 0,
+        }
+    }
+}
+
+impl ConnectionHealth {
+    /// Tests if the session is connected and its last keep alive succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.state == ConnectionState::Connected && self.keep_alive_failures == 0
+    }
+}
+
+/// A change of the health of the connection to a server.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    /// Endpoint url of the server.
+    pub endpoint_url: String,
+    /// Health of the connection after the change.
+    pub health: ConnectionHealth,
+}
+
+/// The health of the connection to a server, which publishes its changes.
+struct ServerConnection {
+    endpoint_url: String,
+    health: RwLock<ConnectionHealth>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl ServerConnection {
+    /// Updates the health, publishing it if `update` returns `true`.
+    fn update(&self, update: impl FnOnce(&mut ConnectionHealth) -> bool) {
+        let event = {
+            let mut health = trace_write_lock!(self.health);
+            if !update(&mut health) {
+                return;
+            }
+            ConnectionEvent {
+                endpoint_url: self.endpoint_url.clone(),
+                health: health.clone(),
+            }
+        };
+        // There may be no one listening
+        let _ = self.events.send(event);
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        self.update(|health| {
+            if health.state == state {
+                return false;
+            }
+            health.state = state;
+            if state != ConnectionState::Connected {
+                health.connected_since = None;
+            }
+            true
+        });
+    }
+}
+
+struct ManagedServer {
+    session: Arc<Session>,
+    connection: Arc<ServerConnection>,
+    task: JoinHandle<()>,
+}
+
+/// Keeps sessions to many servers, keyed by endpoint url. See the [module](self) documentation.
+pub struct ConnectionManager {
+    client: Mutex<Client>,
+    runtime: Handle,
+    options: ConnectionManagerOptions,
+    connect_limit: Arc<Semaphore>,
+    events: broadcast::Sender<ConnectionEvent>,
+    servers: RwLock<BTreeMap<String, ManagedServer>>,
+}
+
+impl ConnectionManager {
+    /// Create a connection manager that makes its sessions from `client`, and runs them on the
+    /// current tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn new(client: Client, options: ConnectionManagerOptions) -> Self {
+        Self::new_with_runtime(client, options, Handle::current())
+    }
+
+    /// Create a connection manager that makes its sessions from `client`, and runs them on
+    /// `runtime`.
+    pub fn new_with_runtime(
+        client: Client,
+        options: ConnectionManagerOptions,
+        runtime: Handle,
+    ) -> Self {
+        let (events, _) = broadcast::channel(options.event_capacity.max(1));
+        Self {
+            client: Mutex::new(client),
+            runtime,
+            connect_limit: Arc::new(Semaphore::new(options.max_concurrent_connects.max(1))),
+            options,
+            events,
+            servers: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Adds a server and starts connecting to it. The endpoint must be a complete description as
+    /// returned by `GetEndpoints`, including the server certificate when it is secured.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Arc<Session>)` - The session of the server.
+    /// * `Err(String)` - The endpoint is invalid, or the manager already has a server at its url.
+    pub fn add_server(&self, session_info: impl Into<SessionInfo>) -> Result<Arc<Session>, String> {
+        let session_info = session_info.into();
+        let endpoint_url = session_info.endpoint.endpoint_url.as_ref().to_string();
+        if !is_opc_ua_binary_url(&endpoint_url) {
+            return Err(format!(
+                "Endpoint url {}, is not a valid / supported url",
+                endpoint_url
+            ));
+        }
+
+        let mut servers = trace_write_lock!(self.servers);
+        if servers.contains_key(&endpoint_url) {
+            return Err(format!(
+                "The connection manager already has a server at {}",
+                endpoint_url
+            ));
+        }
+
+        // The event loop makes a single attempt to connect, the manager retries
+        let (session, event_loop) = trace_lock!(self.client)
+            .new_session_with_retry_policy(session_info, SessionRetryPolicy::never());
+        let connection = Arc::new(ServerConnection {
+            endpoint_url: endpoint_url.clone(),
+            health: RwLock::new(ConnectionHealth::default()),
+            events: self.events.clone(),
+        });
+        let _ = self.events.send(ConnectionEvent {
+            endpoint_url: endpoint_url.clone(),
+            health: ConnectionHealth::default(),
+        });
+        let task = self.runtime.spawn(Self::run_server(
+            event_loop,
+            self.options.retry_policy.clone(),
+            self.connect_limit.clone(),
+            connection.clone(),
+        ));
+        servers.insert(
+            endpoint_url,
+            ManagedServer {
+                session: session.clone(),
+                connection,
+                task,
+            },
+        );
+        Ok(session)
+    }
+
+    /// Removes a server, closing its session.
+    ///
+    /// # Returns
+    ///
+    /// * `true` - The server was removed.
+    /// * `false` - The manager has no server at the url.
+    pub async fn remove_server(&self, endpoint_url: &str) -> bool {
+        let server = trace_write_lock!(self.servers).remove(endpoint_url);
+        match server {
+            Some(server) => {
+                Self::close_server(server).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all the servers, closing their sessions.
+    pub async fn shutdown(&self) {
+        let servers = std::mem::take(&mut *trace_write_lock!(self.servers));
+        futures::future::join_all(servers.into_values().map(Self::close_server)).await;
+    }
+
+    /// The session of the server at the url.
+    pub fn session(&self, endpoint_url: &str) -> Option<Arc<Session>> {
+        trace_read_lock!(self.servers)
+            .get(endpoint_url)
+            .map(|server| server.session.clone())
+    }
+
+    /// The endpoint urls of the servers.
+    pub fn endpoint_urls(&self) -> Vec<String> {
+        trace_read_lock!(self.servers).keys().cloned().collect()
+    }
+
+    /// The health of the connection to the server at the url.
+    pub fn health(&self, endpoint_url: &str) -> Option<ConnectionHealth> {
+        trace_read_lock!(self.servers)
+            .get(endpoint_url)
+            .map(|server| trace_read_lock!(server.connection.health).clone())
+    }
+
+    /// The health of the connections to all the servers, keyed by endpoint url.
+    pub fn health_all(&self) -> BTreeMap<String, ConnectionHealth> {
+        trace_read_lock!(self.servers)
+            .iter()
+            .map(|(endpoint_url, server)| {
+                (
+                    endpoint_url.clone(),
+                    trace_read_lock!(server.connection.health).clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// A stream of the changes of health of the connections, from now on. The stream skips the
+    /// oldest changes if it falls more than the event capacity of the options behind.
+    pub fn events(&self) -> impl Stream<Item = ConnectionEvent> + Send + 'static {
+        futures::stream::unfold(self.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Connection event stream skipped {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    async fn close_server(server: ManagedServer) {
+        // Fails if the session is not connected, in which case there is nothing to close
+        let _ = server.session.disconnect().await;
+        server.task.abort();
+        server.connection.set_state(ConnectionState::Closed);
+    }
+
+    /// Runs the event loop of a server until its session is closed, or the retry limit is reached.
+    async fn run_server(
+        event_loop: SessionEventLoop,
+        retry_policy: SessionRetryPolicy,
+        connect_limit: Arc<Semaphore>,
+        connection: Arc<ServerConnection>,
+    ) {
+        let mut backoff = retry_policy.new_jittered_backoff();
+        loop {
+            let stream = event_loop.renew().enter();
+            tokio::pin!(stream);
+            let mut permit = None;
+            let result = loop {
+                match stream.try_next().await {
+                    Ok(Some(SessionPollResult::BeginConnect)) => {
+                        connection.set_state(ConnectionState::Connecting);
+                        // The next poll of the event loop makes the connection attempt
+                        permit = connect_limit.clone().acquire_owned().await.ok();
+                    }
+                    Ok(Some(SessionPollResult::Reconnected(_))) => {
+                        permit = None;
+                        backoff = retry_policy.new_jittered_backoff();
+                        connection.update(|health| {
+                            health.state = ConnectionState::Connected;
+                            health.connected_since = Some(Instant::now());
+                            health.failed_attempts = 0;
+                            health.keep_alive_failures = 0;
+                            true
+                        });
+                    }
+                    Ok(Some(SessionPollResult::ConnectionLost(status))) => {
+                        connection.update(|health| {
+                            health.last_error = Some(status);
+                            false
+                        });
+                    }
+                    Ok(Some(SessionPollResult::SessionActivity(activity))) => {
+                        connection.update(|health| match activity {
+                            SessionActivity::KeepAliveSucceeded => {
+                                let changed = health.keep_alive_failures > 0;
+                                health.keep_alive_failures = 0;
+                                changed
+                            }
+                            SessionActivity::KeepAliveFailed(status) => {
+                                health.keep_alive_failures += 1;
+                                health.last_error = Some(status);
+                                health.keep_alive_failures == 1
+                            }
+                        });
+                    }
+                    Ok(Some(_)) => {}
+                    Ok(None) => break Ok(()),
+                    Err(status) => break Err(status),
+                }
+            };
+            drop(permit);
+
+            let status = match result {
+                Ok(()) => {
+                    connection.set_state(ConnectionState::Closed);
+                    return;
+                }
+                Err(status) => status,
+            };
+            match backoff.next() {
+                Some(delay) => {
+                    debug!(
+                        "Connection to {} failed, error = {}, retrying in {:?}",
+                        connection.endpoint_url, status, delay
+                    );
+                    connection.update(|health| {
+                        health.state = ConnectionState::Reconnecting { status, delay };
+                        health.connected_since = None;
+                        health.last_error = Some(status);
+                        health.failed_attempts += 1;
+                        true
+                    });
+                    tokio::time::sleep(delay).await;
+                }
+                None => {
+                    error!(
+                        "Giving up connecting to {}, error = {}",
+                        connection.endpoint_url, status
+                    );
+                    connection.update(|health| {
+                        health.state = ConnectionState::Failed(status);
+                        health.last_error = Some(status);
+                        health.failed_attempts += 1;
+                        true
+                    });
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionManager {
+    fn drop(&mut self) {
+        for server in trace_read_lock!(self.servers).values() {
+            server.task.abort();
+        }
+    }
+}
@@ -110,20 +110,38 @@
 //! [`ClientBuilder`]: ./client_builder/struct.ClientBuilder.html
 //! [`Session`]: ./session/struct.Session.html
 
+mod blocking;
 mod builder;
+mod cache;
 mod config;
+mod manager;
+mod queue;
 mod retry;
 mod session;
 mod transport;
 
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, sync::Arc};
 
+use crate::{crypto::PrivateKeyPassphrase, types::StatusCode};
+
+pub use blocking::{BlockingClient, BlockingSession};
 pub use builder::ClientBuilder;
+pub use cache::{AddressSpaceCache, CachedNode, CrawlOptions};
 pub use config::{ClientConfig, ClientEndpoint, ClientUserToken, ANONYMOUS_USER_TOKEN_ID};
+pub use manager::{
+    ConnectionEvent, ConnectionHealth, ConnectionManager, ConnectionManagerOptions, ConnectionState,
+};
+pub use queue::{
+    ConflictPolicy, QueueOutcome, QueueResult, QueuedItem, QueuedOperation, WriteQueue,
+    WriteQueueOptions,
+};
+pub use retry::SessionRetryPolicy;
 pub use session::{
-    Client, DataChangeCallback, EventCallback, MonitoredItem, OnSubscriptionNotification, Session,
-    SessionActivity, SessionConnectMode, SessionEventLoop, SessionPollResult, Subscription,
-    SubscriptionCallbacks,
+    Client, DataChangeCallback, EventCallback, FailoverActivity, FailoverMode, FailoverOptions,
+    FailoverReason, HistoryReadAction, HistoryUpdateAction, MonitoredItem, NotificationStream,
+    OnSubscriptionNotification, OperationLimits, OverflowPolicy, Session, SessionActivity,
+    SessionConnectMode, SessionEventLoop, SessionPollResult, StreamOptions, Subscription,
+    SubscriptionCallbacks, SubscriptionNotification,
 };
 pub use transport::AsyncSecureChannel;
 
@@ -135,4 +153,47 @@ pub enum IdentityToken {
     UserName(String, String),
     /// X5090 cert - a path to the cert.der, and private.pem
     X509(PathBuf, PathBuf),
+    /// X509 cert - a path to the cert.der, an encrypted private.pem and its passphrase
+    X509Encrypted(PathBuf, PathBuf, PrivateKeyPassphrase),
+    /// Issued token, i.e. a JSON Web Token obtained from an authorization service
+    IssuedToken(IssuedTokenProvider),
+}
+
+/// Supplies the JSON Web Token for an issued identity token. The provider is called every time
+/// the session is activated, so a token that has expired can be replaced by a fresh one when the
+/// session reconnects.
+#[derive(Clone)]
+pub struct IssuedTokenProvider(Arc<dyn Fn() -> Result<String, StatusCode> + Send + Sync>);
+
+impl fmt::Debug for IssuedTokenProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "IssuedTokenProvider")
+    }
+}
+
+impl PartialEq for IssuedTokenProvider {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl IssuedTokenProvider {
+    /// Creates a provider from a function that returns the current token
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> Result<String, StatusCode> + Send + Sync + 'static,
+    {
+        IssuedTokenProvider(Arc::new(f))
+    }
+
+    /// Creates a provider that always returns the same token
+    pub fn fixed(token: impl Into<String>) -> Self {
+        let token = token.into();
+        Self::new(move || Ok(token.clone()))
+    }
+
+    /// Obtains the current token
+    pub fn token(&self) -> Result<String, StatusCode> {
+        (self.0)()
+    }
 }
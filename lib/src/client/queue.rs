@@ -0,0 +1,509 @@
+//! A store-and-forward queue of writes and method calls.
+//!
+//! [`WriteQueue`] holds the writes and method calls of an application while its session cannot
+//! reach the server, and forwards them in order once the session is connected. Items can expire,
+//! and a write can be made to fail rather than overwrite a value that changed on the server in the
+//! meantime. The pending items are saved to a file, if the queue has one, so they survive a restart
+//! of the application. The outcome of each item is published as a stream of [`QueueOutcome`]s.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::Stream;
+use tokio::sync::{mpsc, Notify};
+
+use crate::{
+    sync::Mutex,
+    types::{
+        CallMethodRequest, CallMethodResult, DateTime, ReadValueId, StatusCode, TimestampsToReturn,
+        Variant, WriteValue,
+    },
+};
+
+use super::Session;
+
+/// Options of a [`WriteQueue`].
+#[derive(Debug, Clone)]
+pub struct WriteQueueOptions {
+    /// File that the pending items are saved to, so they survive a restart. Without a file the
+    /// items are only kept in memory.
+    pub path: Option<PathBuf>,
+    /// Maximum number of pending items. Items beyond it are rejected.
+    pub max_items: usize,
+    /// Delay before an item that failed because the server could not be reached is sent again.
+    pub retry_interval: Duration,
+}
+
+impl Default for WriteQueueOptions {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_items: 10000,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What to do when the value that a queued write replaces is not the one the application
+/// expected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Write the value whatever the value on the server is.
+    LastWriteWins,
+    /// Read the value first, and fail the write if it is not the inner value, i.e. the value that
+    /// the application last saw.
+    FailIfChanged(Variant),
+}
+
+/// A write or method call of a [`WriteQueue`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum QueuedOperation {
+    Write {
+        value: WriteValue,
+        conflict_policy: ConflictPolicy,
+    },
+    Call(CallMethodRequest),
+}
+
+/// A pending item of a [`WriteQueue`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedItem {
+    /// Id of the item, in the order the items were queued.
+    pub id: u64,
+    pub operation: QueuedOperation,
+    /// When the item was queued.
+    pub queued_at: DateTime,
+    /// When the item expires, if it does.
+    pub expires_at: Option<DateTime>,
+}
+
+impl QueuedItem {
+    fn is_expired(&self, now: &DateTime) -> bool {
+        self.expires_at
+            .as_ref()
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// The result of an item of a [`WriteQueue`].
+#[derive(Debug, Clone)]
+pub enum QueueResult {
+    /// The value was written, with the status code of the write from the server.
+    Written(StatusCode),
+    /// The method was called, with its result from the server.
+    Called(CallMethodResult),
+    /// The item expired before it could be sent.
+    Expired,
+    /// The write was not sent because the value on the server is not the value of its
+    /// [`ConflictPolicy::FailIfChanged`]. The inner value is the one on the server.
+    Conflict(Option<Variant>),
+    /// The server rejected the request with the status code.
+    Failed(StatusCode),
+    /// The request was sent but failed with the status code before a response came back, so it
+    /// may or may not have been carried out. Method calls and writes with
+    /// [`ConflictPolicy::FailIfChanged`] are not sent again in that case, since doing so twice
+    /// could do harm.
+    Uncertain(StatusCode),
+}
+
+/// The outcome of an item of a [`WriteQueue`].
+#[derive(Debug, Clone)]
+pub struct QueueOutcome {
+    pub item: QueuedItem,
+    pub result: QueueResult,
+}
+
+/// The content of the queue file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WriteQueueFile {
+    next_id: u64,
+    items: VecDeque<QueuedItem>,
+}
+
+/// Queues writes and method calls for a session and forwards them in order when the session is
+/// connected. See the [module](self) documentation.
+///
+/// The queue does nothing unless it is run with [`WriteQueue::run`] or [`WriteQueue::spawn`],
+/// alongside the event loop of the session.
+pub struct WriteQueue {
+    session: Arc<Session>,
+    options: WriteQueueOptions,
+    state: Mutex<WriteQueueFile>,
+    item_queued: Notify,
+    outcome_senders: Mutex<Vec<mpsc::UnboundedSender<QueueOutcome>>>,
+}
+
+impl WriteQueue {
+    /// Create a queue for the session. The items that were pending in the file of the options,
+    /// if any, are queued again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Arc<WriteQueue>)` - The queue.
+    /// * `Err(StatusCode)` - The file of the options cannot be read.
+    pub fn new(session: Arc<Session>, options: WriteQueueOptions) -> Result<Arc<Self>, StatusCode> {
+        let state = match options.path {
+            Some(ref path) if path.exists() => Self::load(path).map_err(|err| {
+                error!("Cannot load the write queue {}, {}", path.display(), err);
+                StatusCode::BadDecodingError
+            })?,
+            _ => WriteQueueFile::default(),
+        };
+        if !state.items.is_empty() {
+            info!("Write queue has {} pending items", state.items.len());
+        }
+        Ok(Arc::new(Self {
+            session,
+            options,
+            state: Mutex::new(state),
+            item_queued: Notify::new(),
+            outcome_senders: Mutex::new(Vec::new()),
+        }))
+    }
+
+    fn load(path: &PathBuf) -> Result<WriteQueueFile, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())
+    }
+
+    /// Queues a write.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to write.
+    /// * `conflict_policy` - What to do if the value on the server changed.
+    /// * `expiry` - Time after which the write is dropped if it was not sent, or `None` to keep
+    ///   it until it is sent.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(u64)` - The id of the item.
+    /// * `Err(StatusCode)` - The queue is full, or the item cannot be saved.
+    pub fn write(
+        &self,
+        value: WriteValue,
+        conflict_policy: ConflictPolicy,
+        expiry: Option<Duration>,
+    ) -> Result<u64, StatusCode> {
+        self.push(
+            QueuedOperation::Write {
+                value,
+                conflict_policy,
+            },
+            expiry,
+        )
+    }
+
+    /// Queues a method call.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method to call.
+    /// * `expiry` - Time after which the call is dropped if it was not sent, or `None` to keep
+    ///   it until it is sent.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(u64)` - The id of the item.
+    /// * `Err(StatusCode)` - The queue is full, or the item cannot be saved.
+    pub fn call(
+        &self,
+        method: impl Into<CallMethodRequest>,
+        expiry: Option<Duration>,
+    ) -> Result<u64, StatusCode> {
+        self.push(QueuedOperation::Call(method.into()), expiry)
+    }
+
+    fn push(
+        &self,
+        operation: QueuedOperation,
+        expiry: Option<Duration>,
+    ) -> Result<u64, StatusCode> {
+        let queued_at = DateTime::now();
+        // An expiry too far in the future to represent never expires
+        let expires_at = expiry
+            .and_then(|expiry| chrono::Duration::from_std(expiry).ok())
+            .and_then(|expiry| queued_at.as_chrono().checked_add_signed(expiry))
+            .map(DateTime::from);
+        let id = {
+            let mut state = trace_lock!(self.state);
+            if state.items.len() >= self.options.max_items {
+                error!("Write queue is full, the item is rejected");
+                return Err(StatusCode::BadResourceUnavailable);
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.items.push_back(QueuedItem {
+                id,
+                operation,
+                queued_at,
+                expires_at,
+            });
+            if let Err(err) = self.save(&state) {
+                state.items.pop_back();
+                error!("Cannot save the write queue, {}", err);
+                return Err(StatusCode::BadResourceUnavailable);
+            }
+            id
+        };
+        self.item_queued.notify_one();
+        Ok(id)
+    }
+
+    /// The pending items, in the order they will be sent.
+    pub fn pending(&self) -> Vec<QueuedItem> {
+        trace_lock!(self.state).items.iter().cloned().collect()
+    }
+
+    /// The number of pending items.
+    pub fn len(&self) -> usize {
+        trace_lock!(self.state).items.len()
+    }
+
+    /// Tests if there are no pending items.
+    pub fn is_empty(&self) -> bool {
+        trace_lock!(self.state).items.is_empty()
+    }
+
+    /// A stream of the outcomes of the items, from now on, in the order of the items.
+    pub fn outcomes(&self) -> impl Stream<Item = QueueOutcome> + Send + 'static {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        trace_lock!(self.outcome_senders).push(sender);
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|outcome| (outcome, receiver))
+        })
+    }
+
+    /// Convenience method for running the queue on a tokio task. The task runs until it is
+    /// aborted.
+    pub fn spawn(self: &Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn(self.clone().run())
+    }
+
+    /// Forwards the items to the server whenever the session is connected. This never returns,
+    /// drop the future to stop the queue. Items that have not been sent stay in the queue.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            self.expire_items();
+
+            let item = trace_lock!(self.state).items.front().cloned();
+            let Some(item) = item else {
+                self.item_queued.notified().await;
+                continue;
+            };
+
+            // Expire items while waiting for the connection
+            if tokio::time::timeout(
+                self.options.retry_interval,
+                self.session.wait_for_connection(),
+            )
+            .await
+            .is_err()
+            {
+                continue;
+            }
+
+            match self.forward(&item).await {
+                Ok(result) => self.complete(item, result),
+                Err(status) => {
+                    debug!(
+                        "Write queue item {} could not be sent, error = {}, retrying",
+                        item.id, status
+                    );
+                    tokio::time::sleep(self.options.retry_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Sends an item to the server. Fails if the server cannot be reached, in which case the
+    /// item must be sent again.
+    async fn forward(&self, item: &QueuedItem) -> Result<QueueResult, StatusCode> {
+        match &item.operation {
+            QueuedOperation::Write {
+                value,
+                conflict_policy,
+            } => {
+                if let ConflictPolicy::FailIfChanged(expected) = conflict_policy {
+                    let read_value = ReadValueId {
+                        node_id: value.node_id.clone(),
+                        attribute_id: value.attribute_id,
+                        index_range: value.index_range.clone(),
+                        data_encoding: Default::default(),
+                    };
+                    let current = match self
+                        .session
+                        .read(&[read_value], TimestampsToReturn::Neither, 0.0)
+                        .await
+                    {
+                        Ok(mut results) if !results.is_empty() => results.remove(0),
+                        Ok(_) => return Ok(QueueResult::Failed(StatusCode::BadUnexpectedError)),
+                        Err(status) => return Self::request_failed(status, true),
+                    };
+                    let status = current.status();
+                    if status.is_bad() {
+                        return Ok(QueueResult::Failed(status));
+                    }
+                    if current.value.as_ref() != Some(expected) {
+                        return Ok(QueueResult::Conflict(current.value));
+                    }
+                }
+                match self.session.write(std::slice::from_ref(value)).await {
+                    Ok(results) => Ok(QueueResult::Written(
+                        results
+                            .into_iter()
+                            .next()
+                            .unwrap_or(StatusCode::BadUnexpectedError),
+                    )),
+                    Err(status) => Self::request_failed(
+                        status,
+                        *conflict_policy == ConflictPolicy::LastWriteWins,
+                    ),
+                }
+            }
+            QueuedOperation::Call(method) => match self.session.call(method.clone()).await {
+                Ok(result) => Ok(QueueResult::Called(result)),
+                Err(status) => Self::request_failed(status, false),
+            },
+        }
+    }
+
+    /// Fails with the status code if the request was never sent because there is no connection,
+    /// and gives the result of the item otherwise. A request that failed in transit may have been
+    /// carried out, so it only fails if it is safe to send it again.
+    fn request_failed(status: StatusCode, is_repeatable: bool) -> Result<QueueResult, StatusCode> {
+        match status.status() {
+            StatusCode::BadNotConnected | StatusCode::BadServerNotConnected => Err(status),
+            StatusCode::BadConnectionClosed
+            | StatusCode::BadSecureChannelClosed
+            | StatusCode::BadSessionClosed
+            | StatusCode::BadSessionIdInvalid
+            | StatusCode::BadCommunicationError
+            | StatusCode::BadNoCommunication
+            | StatusCode::BadRequestInterrupted
+            | StatusCode::BadTimeout => {
+                if is_repeatable {
+                    Err(status)
+                } else {
+                    Ok(QueueResult::Uncertain(status))
+                }
+            }
+            _ => Ok(QueueResult::Failed(status)),
+        }
+    }
+
+    /// Removes the expired items.
+    fn expire_items(&self) {
+        let now = DateTime::now();
+        let expired = {
+            let mut state = trace_lock!(self.state);
+            if !state.items.iter().any(|item| item.is_expired(&now)) {
+                return;
+            }
+            let (expired, pending) = std::mem::take(&mut state.items)
+                .into_iter()
+                .partition::<Vec<_>, _>(|item| item.is_expired(&now));
+            state.items = pending.into_iter().collect();
+            if let Err(err) = self.save(&state) {
+                error!("Cannot save the write queue, {}", err);
+            }
+            expired
+        };
+        for item in expired {
+            debug!("Write queue item {} expired", item.id);
+            self.send_outcome(QueueOutcome {
+                item,
+                result: QueueResult::Expired,
+            });
+        }
+    }
+
+    /// Removes the item that was sent from the front of the queue.
+    fn complete(&self, item: QueuedItem, result: QueueResult) {
+        {
+            let mut state = trace_lock!(self.state);
+            if state.items.front().is_some_and(|front| front.id == item.id) {
+                state.items.pop_front();
+            }
+            if let Err(err) = self.save(&state) {
+                error!("Cannot save the write queue, {}", err);
+            }
+        }
+        self.send_outcome(QueueOutcome { item, result });
+    }
+
+    fn send_outcome(&self, outcome: QueueOutcome) {
+        trace_lock!(self.outcome_senders).retain(|sender| sender.send(outcome.clone()).is_ok());
+    }
+
+    /// Saves the items to the file, if there is one
+    fn save(&self, state: &WriteQueueFile) -> Result<(), String> {
+        let Some(ref path) = self.options.path else {
+            return Ok(());
+        };
+        // Write to a temporary file first so a failed save does not lose the previous one
+        let tmp_path = path.with_extension("tmp");
+        File::create(&tmp_path)
+            .map_err(|err| err.to_string())
+            .and_then(|f| {
+                serde_json::to_writer(BufWriter::new(f), state).map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&tmp_path, path).map_err(|err| err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_failed_retries_only_when_repeatable() {
+        // Requests that were never sent are always sent again
+        for status in [
+            StatusCode::BadNotConnected,
+            StatusCode::BadServerNotConnected,
+        ] {
+            assert_eq!(
+                WriteQueue::request_failed(status, true).unwrap_err(),
+                status
+            );
+            assert_eq!(
+                WriteQueue::request_failed(status, false).unwrap_err(),
+                status
+            );
+        }
+
+        // Requests that may have been carried out are only sent again if that is harmless
+        for status in [
+            StatusCode::BadConnectionClosed,
+            StatusCode::BadCommunicationError,
+            StatusCode::BadSecureChannelClosed,
+            StatusCode::BadSessionClosed,
+            StatusCode::BadSessionIdInvalid,
+            StatusCode::BadNoCommunication,
+            StatusCode::BadTimeout,
+            StatusCode::BadRequestInterrupted,
+        ] {
+            assert_eq!(
+                WriteQueue::request_failed(status, true).unwrap_err(),
+                status
+            );
+            assert!(matches!(
+                WriteQueue::request_failed(status, false),
+                Ok(QueueResult::Uncertain(s)) if s == status
+            ));
+        }
+
+        // Anything else is the result of the item
+        assert!(matches!(
+            WriteQueue::request_failed(StatusCode::BadNodeIdUnknown, false),
+            Ok(QueueResult::Failed(StatusCode::BadNodeIdUnknown))
+        ));
+    }
+}
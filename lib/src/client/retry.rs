@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use crate::crypto::random;
+
 pub(crate) struct ExponentialBackoff {
     max_sleep: Duration,
     max_retries: Option<u32>,
@@ -34,6 +36,31 @@ impl Iterator for ExponentialBackoff {
     }
 }
 
+/// Exponential backoff that waits a random time between half and all of each delay, so that
+/// many connections lost at the same time do not all retry at once.
+pub(crate) struct JitteredBackoff {
+    inner: ExponentialBackoff,
+}
+
+impl JitteredBackoff {
+    pub fn new(inner: ExponentialBackoff) -> Self {
+        Self { inner }
+    }
+}
+
+impl Iterator for JitteredBackoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0u8; 4];
+        random::bytes(&mut bytes);
+        let jitter = u32::from_le_bytes(bytes) as f64 / u32::MAX as f64;
+        self.inner
+            .next()
+            .map(|sleep| sleep / 2 + (sleep / 2).mul_f64(jitter))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionRetryPolicy {
     reconnect_max_sleep: Duration,
@@ -72,6 +99,10 @@ impl SessionRetryPolicy {
         )
     }
 
+    pub(crate) fn new_jittered_backoff(&self) -> JitteredBackoff {
+        JitteredBackoff::new(self.new_backoff())
+    }
+
     pub fn infinity(max_sleep: Duration, initial_sleep: Duration) -> Self {
         Self {
             reconnect_initial_sleep: initial_sleep,
@@ -134,4 +165,18 @@ mod tests {
         let mut backoff = policy.new_backoff();
         assert!(backoff.next().is_none());
     }
+
+    #[test]
+    fn session_retry_jittered() {
+        let policy = SessionRetryPolicy::default();
+        let backoff = policy.new_backoff();
+        let mut jittered = policy.new_jittered_backoff();
+
+        for sleep in backoff {
+            let jittered_sleep = jittered.next().unwrap();
+            assert!(jittered_sleep >= sleep / 2);
+            assert!(jittered_sleep <= sleep);
+        }
+        assert_eq!(None, jittered.next());
+    }
 }
@@ -17,7 +17,7 @@ use crate::{
         config::Config,
         supported_message::SupportedMessage,
     },
-    crypto::{CertificateStore, SecurityPolicy},
+    crypto::{CertificateStore, SecurityPolicy, X509},
     sync::RwLock,
     types::{
         ApplicationDescription, DecodingOptions, EndpointDescription, FindServersRequest,
@@ -27,6 +27,7 @@ use crate::{
 };
 
 use super::{
+    failover::{Failover, FailoverMode, FailoverOptions},
     process_service_result, process_unexpected_response, Session, SessionEventLoop, SessionInfo,
 };
 
@@ -60,6 +61,7 @@ impl Client {
                 false,
                 config.certificate_path.as_deref(),
                 config.private_key_path.as_deref(),
+                config.private_key_passphrase.clone(),
                 application_description,
             );
         if client_certificate.is_none() || client_pkey.is_none() {
@@ -72,6 +74,9 @@ impl Client {
         // Clients may choose to auto trust servers to save some messing around with rejected certs
         certificate_store.set_trust_unknown_certs(config.trust_server_certs);
 
+        // The security profile restricts the policies and server certificates that are acceptable
+        certificate_store.set_security_profile(config.security_profile.clone());
+
         // The session retry policy dictates how many times to retry if connection to the server goes down
         // and on what interval
 
@@ -85,10 +90,29 @@ impl Client {
             config.session_retry_initial,
         );
 
-        Self {
+        let client = Self {
             config,
             session_retry_policy,
             certificate_store: Arc::new(RwLock::new(certificate_store)),
+        };
+        client.renew_certificate();
+        client
+    }
+
+    /// Renews the client's own certificate if renewal is enabled and the certificate expires
+    /// within the configured number of days. Sessions that are already connected carry on with
+    /// the old certificate until they reconnect.
+    fn renew_certificate(&self) {
+        if !self.config.certificate_renewal.enabled {
+            return;
+        }
+        let certificate_store = trace_write_lock!(self.certificate_store);
+        match certificate_store
+            .renew_application_instance_cert(self.config.certificate_renewal.renew_before_days)
+        {
+            Ok(Some(_)) => info!("Client certificate has been renewed"),
+            Ok(None) => {}
+            Err(err) => error!("Client certificate could not be renewed, error = {}", err),
         }
     }
 
@@ -187,6 +211,13 @@ impl Client {
             );
             status_code
         })?;
+        if !self.is_supported_endpoint(&server_endpoint) {
+            error!(
+                "Endpoint {} does not comply with the security profile",
+                endpoint.endpoint_url.as_ref()
+            );
+            return Err(StatusCode::BadSecurityPolicyRejected);
+        }
 
         Ok(self
             .new_session_from_info(SessionInfo {
@@ -225,16 +256,94 @@ impl Client {
                 session_info.endpoint.endpoint_url
             ))
         } else {
-            Ok(Session::new(
-                self.certificate_store.clone(),
-                session_info,
-                self.config.session_name.clone().into(),
-                self.config.application_description(),
-                self.session_retry_policy.clone(),
-                self.decoding_options(),
-                &self.config,
-            ))
+            let session_retry_policy = self.session_retry_policy.clone();
+            Ok(self.new_session_with_retry_policy(session_info, session_retry_policy))
+        }
+    }
+
+    /// Creates a session with its own retry policy, instead of that of the client.
+    pub(crate) fn new_session_with_retry_policy(
+        &mut self,
+        session_info: SessionInfo,
+        session_retry_policy: SessionRetryPolicy,
+    ) -> (Arc<Session>, SessionEventLoop) {
+        self.renew_certificate();
+        Session::new(
+            self.certificate_store.clone(),
+            session_info,
+            self.config.session_name.clone().into(),
+            self.config.application_description(),
+            session_retry_policy,
+            self.decoding_options(),
+            &self.config,
+        )
+    }
+
+    /// Creates a session on a non-transparent redundant server set, that fails over between the
+    /// servers when the active server fails or its `ServiceLevel` drops. See [`FailoverOptions`].
+    ///
+    /// The session starts on the first server. Subscriptions are moved to the new server when the
+    /// session fails over, keeping their monitored item client handles and callbacks, and each
+    /// subscription is told with a status change of `GoodSubscriptionTransferred`. The retry
+    /// policy of the client applies across the servers of the set.
+    ///
+    /// # Arguments
+    ///
+    /// * `servers` - Session info for each server of the set. The endpoints must be complete
+    ///   descriptions as returned by `GetEndpoints`, including the server certificate and the
+    ///   application uri of the server.
+    /// * `options` - How to fail over between the servers.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((Arc<AsyncSession>, SessionEventLoop))` - Session and event loop.
+    /// * `Err(String)` - The server set is empty or an endpoint is invalid.
+    ///
+    pub fn new_session_with_failover(
+        &mut self,
+        servers: impl IntoIterator<Item = impl Into<SessionInfo>>,
+        options: FailoverOptions,
+    ) -> Result<(Arc<Session>, SessionEventLoop), String> {
+        let servers = servers
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<SessionInfo>>();
+        let Some(first) = servers.first().cloned() else {
+            return Err("A redundant server set needs at least one server".to_string());
+        };
+
+        // Warm and hot failover keep a standby session on every server, that never gives up
+        let standby_retry_policy = SessionRetryPolicy::infinity(
+            std::time::Duration::from_millis(SessionRetryPolicy::DEFAULT_MAX_SLEEP_MS),
+            std::time::Duration::from_millis(SessionRetryPolicy::DEFAULT_INITIAL_SLEEP_MS),
+        );
+        let mut failover_servers = Vec::with_capacity(servers.len());
+        for session_info in servers {
+            if !is_opc_ua_binary_url(session_info.endpoint.endpoint_url.as_ref()) {
+                return Err(format!(
+                    "Endpoint url {}, is not a valid / supported url",
+                    session_info.endpoint.endpoint_url
+                ));
+            }
+            let standby = if options.mode == FailoverMode::Cold {
+                None
+            } else {
+                Some(Session::new(
+                    self.certificate_store.clone(),
+                    session_info.clone(),
+                    self.config.session_name.clone().into(),
+                    self.config.application_description(),
+                    standby_retry_policy.clone(),
+                    self.decoding_options(),
+                    &self.config,
+                ))
+            };
+            failover_servers.push((session_info, standby));
         }
+
+        let (session, event_loop) = self.new_session_from_info(first)?;
+        session.set_failover(Failover::new(options, failover_servers));
+        Ok((session, event_loop))
     }
 
     /// Creates a new [`AsyncSession`] using the default endpoint specified in the config. If
@@ -317,6 +426,14 @@ impl Client {
                 );
                 if endpoint.is_none() {
                     Err(format!("Endpoint {}, {:?} / {:?} does not match against any supplied by the server", endpoint_url, security_policy, security_mode))
+                } else if endpoint
+                    .as_ref()
+                    .is_some_and(|e| !self.is_supported_endpoint(e))
+                {
+                    Err(format!(
+                        "Endpoint {}, {:?} / {:?} does not comply with the security profile",
+                        endpoint_url, security_policy, security_mode
+                    ))
                 } else if let Some(user_identity_token) =
                     self.client_identity_token(client_endpoint.user_token_id.clone())
                 {
@@ -382,14 +499,26 @@ impl Client {
         } else {
             let token = self.config.user_tokens.get(&user_token_id)?;
 
-            if let Some(ref password) = token.password {
+            if let Some(ref issued_token_provider) = token.issued_token_provider {
+                Some(IdentityToken::IssuedToken(issued_token_provider.clone()))
+            } else if let Some(ref password) = token.password {
                 Some(IdentityToken::UserName(
                     token.user.clone(),
                     password.clone(),
                 ))
             } else if let Some(ref cert_path) = token.cert_path {
                 token.private_key_path.as_ref().map(|private_key_path| {
-                    IdentityToken::X509(PathBuf::from(cert_path), PathBuf::from(private_key_path))
+                    let cert_path = PathBuf::from(cert_path);
+                    let private_key_path = PathBuf::from(private_key_path);
+                    if let Some(ref passphrase) = token.private_key_passphrase {
+                        IdentityToken::X509Encrypted(
+                            cert_path,
+                            private_key_path,
+                            passphrase.clone(),
+                        )
+                    } else {
+                        IdentityToken::X509(cert_path, private_key_path)
+                    }
                 })
             } else {
                 None
@@ -397,6 +526,12 @@ impl Client {
         }
     }
 
+    /// Returns the `CertificateStore` for the client, e.g. to set a certificate approver that
+    /// decides whether to trust an unknown server certificate.
+    pub fn certificate_store(&self) -> Arc<RwLock<CertificateStore>> {
+        self.certificate_store.clone()
+    }
+
     /// Gets the [`ClientEndpoint`] information for the default endpoint, as defined
     /// by the configuration. If there is no default endpoint, this function will return an error.
     ///
@@ -655,7 +790,9 @@ impl Client {
         Some(matching_endpoint)
     }
 
-    /// Determine if we recognize the security of this endpoint.
+    /// Determine if we recognize the security of this endpoint and if it complies with the
+    /// security profile, i.e. the profile allows its security policy and the server certificate
+    /// has a key length and signature algorithm that are valid for the policy.
     ///
     /// # Arguments
     ///
@@ -665,10 +802,40 @@ impl Client {
     ///
     /// * `bool` - `true` if the endpoint is supported.
     pub fn is_supported_endpoint(&self, endpoint: &EndpointDescription) -> bool {
-        if let Ok(security_policy) = SecurityPolicy::from_str(endpoint.security_policy_uri.as_ref())
+        let security_policy = match SecurityPolicy::from_str(endpoint.security_policy_uri.as_ref())
         {
-            !matches!(security_policy, SecurityPolicy::Unknown)
+            Ok(SecurityPolicy::Unknown) | Err(_) => return false,
+            Ok(security_policy) => security_policy,
+        };
+        let security_profile = &self.config.security_profile;
+        if !security_profile.is_policy_allowed(security_policy) {
+            debug!(
+                "Endpoint {} uses the security policy {} which the security profile does not allow",
+                endpoint.endpoint_url, security_policy
+            );
+            false
+        } else if security_policy == SecurityPolicy::None {
+            true
+        } else if let Ok(server_certificate) = X509::from_byte_string(&endpoint.server_certificate)
+        {
+            let key_length_valid = server_certificate
+                .key_length()
+                .map(|key_length| security_profile.is_valid_key_length(security_policy, key_length))
+                .unwrap_or(false);
+            let signature_valid = security_policy
+                .is_valid_certificate_signature_hash(server_certificate.signature_hash());
+            if !key_length_valid || !signature_valid {
+                debug!(
+                    "Endpoint {} has a server certificate whose key length or signature is invalid for the security policy {}",
+                    endpoint.endpoint_url, security_policy
+                );
+            }
+            key_length_valid && signature_valid
         } else {
+            debug!(
+                "Endpoint {} has no valid server certificate",
+                endpoint.endpoint_url
+            );
             false
         }
     }
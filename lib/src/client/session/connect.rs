@@ -102,6 +102,7 @@ impl SessionConnector {
             }
         };
 
+        self.inner.read_operation_limits().await;
         self.inner.transfer_subscriptions_from_old_session().await;
 
         Ok(reconnect)
@@ -16,6 +16,7 @@ use crate::{
 
 use super::{
     connect::{SessionConnectMode, SessionConnector},
+    failover::{FailoverActivity, FailoverCheck, FailoverMonitor, FailoverReason},
     services::subscriptions::event_loop::{SubscriptionActivity, SubscriptionEventLoop},
     session::SessionState,
     Session,
@@ -41,6 +42,8 @@ pub enum SessionPollResult {
     Subscription(SubscriptionActivity),
     /// The session begins (re)connecting to the server.
     BeginConnect,
+    /// The session checked or changed the active server of its redundant server set.
+    Failover(FailoverActivity),
 }
 
 enum SessionEventLoopState {
@@ -48,6 +51,7 @@ enum SessionEventLoopState {
         SecureChannelEventLoop,
         BoxStream<'static, SessionActivity>,
         BoxStream<'static, SubscriptionActivity>,
+        BoxStream<'static, FailoverCheck>,
     ),
     Connecting(SessionConnector, ExponentialBackoff, Instant),
     Disconnected,
@@ -77,6 +81,16 @@ impl SessionEventLoop {
         }
     }
 
+    /// Another event loop for the same session, to run once this one has ended.
+    pub(crate) fn renew(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            retry: self.retry.clone(),
+            trigger_publish_recv: self.trigger_publish_recv.clone(),
+            keep_alive_interval: self.keep_alive_interval,
+        }
+    }
+
     /// Convenience method for running the session event loop until completion,
     /// this method will return once the session is closed manually, or
     /// after it fails to reconnect.
@@ -120,7 +134,12 @@ impl SessionEventLoop {
             (self, SessionEventLoopState::Disconnected),
             |(slf, state)| async move {
                 let (res, state) = match state {
-                    SessionEventLoopState::Connected(mut c, mut activity, mut subscriptions) => {
+                    SessionEventLoopState::Connected(
+                        mut c,
+                        mut activity,
+                        mut subscriptions,
+                        mut failover,
+                    ) => {
                         tokio::select! {
                             r = c.poll() => {
                                 if let TransportPollResult::Closed(code) = r {
@@ -138,7 +157,7 @@ impl SessionEventLoop {
                                 } else {
                                     Ok((
                                         SessionPollResult::Transport(r),
-                                        SessionEventLoopState::Connected(c, activity, subscriptions),
+                                        SessionEventLoopState::Connected(c, activity, subscriptions, failover),
                                     ))
                                 }
                             }
@@ -151,7 +170,7 @@ impl SessionEventLoop {
 
                                 Ok((
                                     SessionPollResult::SessionActivity(r),
-                                    SessionEventLoopState::Connected(c, activity, subscriptions),
+                                    SessionEventLoopState::Connected(c, activity, subscriptions, failover),
                                 ))
                             }
                             r = subscriptions.next() => {
@@ -163,13 +182,42 @@ impl SessionEventLoop {
 
                                 Ok((
                                     SessionPollResult::Subscription(r),
-                                    SessionEventLoopState::Connected(c, activity, subscriptions),
+                                    SessionEventLoopState::Connected(c, activity, subscriptions, failover),
                                 ))
                             }
+                            r = failover.next() => {
+                                // Should never be null, fail out
+                                let Some(r) = r else {
+                                    session_error!(slf.inner, "Failover monitor ended unexpectedly");
+                                    return Err(StatusCode::BadUnexpectedError);
+                                };
+
+                                match r {
+                                    FailoverCheck::ServiceLevels(levels) => Ok((
+                                        SessionPollResult::Failover(FailoverActivity::ServiceLevels(levels)),
+                                        SessionEventLoopState::Connected(c, activity, subscriptions, failover),
+                                    )),
+                                    FailoverCheck::FailOver(server, level) => {
+                                        drop(activity);
+                                        drop(subscriptions);
+                                        drop(failover);
+                                        slf.leave_server(c).await;
+                                        slf.inner.fail_over_to(server);
+                                        Ok((
+                                            SessionPollResult::Failover(FailoverActivity::FailedOver {
+                                                server,
+                                                reason: FailoverReason::ServiceLevel(level),
+                                            }),
+                                            SessionEventLoopState::Disconnected,
+                                        ))
+                                    }
+                                }
+                            }
                         }
                     }
                     SessionEventLoopState::Disconnected => {
                         let connector = SessionConnector::new(slf.inner.clone());
+                        slf.inner.start_standby_sessions();
 
                         let _ = slf.inner.state_watch_tx.send(SessionState::Connecting);
 
@@ -188,6 +236,21 @@ impl SessionEventLoop {
                         match connector.try_connect().await {
                             Ok((channel, result)) => {
                                 let _ = slf.inner.state_watch_tx.send(SessionState::Connected);
+                                let subscriptions = SubscriptionEventLoop::new(
+                                    slf.inner.clone(),
+                                    slf.trigger_publish_recv.clone(),
+                                )
+                                .run()
+                                .boxed();
+                                // Subscriptions recreated or transferred while connecting need
+                                // a publish right away, the server may discard their first
+                                // notifications otherwise.
+                                if trace_lock!(slf.inner.subscription_state)
+                                    .subscription_ids()
+                                    .is_some()
+                                {
+                                    let _ = slf.inner.trigger_publish_tx.send(Instant::now());
+                                }
                                 Ok((
                                     SessionPollResult::Reconnected(result),
                                     SessionEventLoopState::Connected(
@@ -198,12 +261,15 @@ impl SessionEventLoop {
                                         )
                                         .run()
                                         .boxed(),
-                                        SubscriptionEventLoop::new(
-                                            slf.inner.clone(),
-                                            slf.trigger_publish_recv.clone(),
-                                        )
-                                        .run()
-                                        .boxed(),
+                                        subscriptions,
+                                        match slf.inner.failover_check_interval() {
+                                            Some(interval) => {
+                                                FailoverMonitor::new(slf.inner.clone(), interval)
+                                                    .run()
+                                                    .boxed()
+                                            }
+                                            None => futures::stream::pending().boxed(),
+                                        },
                                     ),
                                 ))
                             }
@@ -211,7 +277,11 @@ impl SessionEventLoop {
                                 warn!("Failed to connect to server, status code: {e}");
                                 match backoff.next() {
                                     Some(x) => Ok((
-                                        SessionPollResult::ReconnectFailed(e),
+                                        // With a redundant server set, try another server next
+                                        match slf.inner.fail_over_on_connect_error(e) {
+                                            Some(activity) => SessionPollResult::Failover(activity),
+                                            None => SessionPollResult::ReconnectFailed(e),
+                                        },
                                         SessionEventLoopState::Connecting(
                                             connector,
                                             backoff,
@@ -231,6 +301,30 @@ impl SessionEventLoop {
     }
 }
 
+impl SessionEventLoop {
+    /// Leave the active server before failing over to another server of the set, closing the
+    /// session if needed and then the secure channel.
+    async fn leave_server(&self, mut channel: SecureChannelEventLoop) {
+        let leave = self.inner.leave_server();
+        tokio::pin!(leave);
+        loop {
+            tokio::select! {
+                r = channel.poll() => {
+                    if let TransportPollResult::Closed(_) = r {
+                        break;
+                    }
+                }
+                _ = &mut leave => {
+                    self.inner.channel.close_channel().await;
+                    while !matches!(channel.poll().await, TransportPollResult::Closed(_)) {}
+                    break;
+                }
+            }
+        }
+        let _ = self.inner.state_watch_tx.send(SessionState::Disconnected);
+    }
+}
+
 /// Periodic activity performed by the session.
 #[derive(Debug, Clone)]
 pub enum SessionActivity {
@@ -0,0 +1,461 @@
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{future::join_all, Stream};
+use tokio::task::JoinHandle;
+
+use crate::{
+    client::session::{session_debug, session_warn},
+    sync::Mutex,
+    types::{
+        AttributeId, EndpointDescription, ReadValueId, RedundantServerDataType, StatusCode,
+        TimestampsToReturn, VariableId, Variant,
+    },
+};
+
+use super::{Session, SessionEventLoop, SessionInfo};
+
+/// How the client prepares to fail over to another server of a non-transparent redundant server
+/// set. See OPC UA Part 4 6.6.2.4.5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailoverMode {
+    /// Other servers are only contacted when failing over. The service levels of the other
+    /// servers are taken from the `RedundantServerArray` of the active server, and subscriptions
+    /// are recreated on the new server.
+    #[default]
+    Cold,
+    /// The client keeps a session open on every server of the set to read its `ServiceLevel`, so
+    /// it fails over to the healthiest server that is known to be reachable. Subscriptions are
+    /// recreated on the new server.
+    Warm,
+    /// As `Warm`, but subscriptions are first moved to the new server with `TransferSubscriptions`,
+    /// for server sets that replicate subscriptions. Subscriptions that cannot be transferred are
+    /// recreated.
+    Hot,
+}
+
+/// Options for failing over between the servers of a redundant server set.
+#[derive(Debug, Clone)]
+pub struct FailoverOptions {
+    /// How the client prepares to fail over.
+    pub mode: FailoverMode,
+    /// Interval between reads of the `ServiceLevel` of the servers.
+    pub check_interval: Duration,
+    /// The client fails over when the `ServiceLevel` of the active server is below this level and
+    /// another server has a higher level. Servers at 200 and above are healthy.
+    pub min_service_level: u8,
+}
+
+impl Default for FailoverOptions {
+    fn default() -> Self {
+        Self {
+            mode: FailoverMode::Cold,
+            check_interval: Duration::from_secs(5),
+            min_service_level: 200,
+        }
+    }
+}
+
+impl FailoverOptions {
+    pub fn new(mode: FailoverMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+}
+
+/// The reason the session moved to another server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailoverReason {
+    /// The `ServiceLevel` of the previous server dropped to the inner level.
+    ServiceLevel(u8),
+    /// Connecting to the previous server failed with the inner [`StatusCode`].
+    ConnectFailed(StatusCode),
+}
+
+/// Failover activity of a session with a redundant server set.
+#[derive(Debug, Clone)]
+pub enum FailoverActivity {
+    /// The service levels of the servers were read, in the order of the server set. A level is
+    /// `None` if it is not known.
+    ServiceLevels(Vec<Option<u8>>),
+    /// The session is moving to the server at index `server` of the server set.
+    FailedOver {
+        server: usize,
+        reason: FailoverReason,
+    },
+}
+
+struct FailoverServer {
+    info: SessionInfo,
+    service_level: Mutex<Option<u8>>,
+    /// Session used to read the service level of the server in warm and hot mode
+    standby: Option<Arc<Session>>,
+}
+
+/// A server of a redundant server set, with its standby session in warm and hot mode.
+pub(crate) type FailoverServerInfo = (SessionInfo, Option<(Arc<Session>, SessionEventLoop)>);
+
+/// The redundant server set of a session, and the state of failing over between its servers.
+pub(crate) struct Failover {
+    options: FailoverOptions,
+    servers: Vec<FailoverServer>,
+    active: AtomicUsize,
+    /// Set when the session moves to another server, until its subscriptions have moved too
+    failed_over: AtomicBool,
+    standby_loops: Mutex<Vec<SessionEventLoop>>,
+    standby_tasks: Mutex<Vec<JoinHandle<StatusCode>>>,
+}
+
+impl Failover {
+    /// Create the failover state for a server set. The session starts on the first server.
+    pub(crate) fn new(options: FailoverOptions, servers: Vec<FailoverServerInfo>) -> Self {
+        let mut standby_loops = Vec::new();
+        let servers = servers
+            .into_iter()
+            .map(|(info, standby)| {
+                let standby = standby.map(|(session, event_loop)| {
+                    standby_loops.push(event_loop);
+                    session
+                });
+                FailoverServer {
+                    info,
+                    service_level: Mutex::new(None),
+                    standby,
+                }
+            })
+            .collect();
+        Self {
+            options,
+            servers,
+            active: AtomicUsize::new(0),
+            failed_over: AtomicBool::new(false),
+            standby_loops: Mutex::new(standby_loops),
+            standby_tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn service_levels(&self) -> Vec<Option<u8>> {
+        self.servers
+            .iter()
+            .map(|s| *trace_lock!(s.service_level))
+            .collect()
+    }
+}
+
+impl Drop for Failover {
+    fn drop(&mut self) {
+        for task in trace_lock!(self.standby_tasks).drain(..) {
+            task.abort();
+        }
+    }
+}
+
+/// The server to fail over to from `current`. It is the server with the highest known service
+/// level, with unknown levels after known ones, and ties going to the next server in the set.
+fn next_server(levels: &[Option<u8>], current: usize) -> Option<usize> {
+    let count = levels.len();
+    (1..count)
+        .map(|offset| (current + offset) % count)
+        .max_by_key(|i| {
+            let level = levels[*i].map(i16::from).unwrap_or(-1);
+            (level, Reverse((*i + count - current) % count))
+        })
+}
+
+/// The server to fail over to if the service level of the active server is below `min_level`
+/// and another server has a higher level.
+fn server_to_fail_over_to(
+    levels: &[Option<u8>],
+    current: usize,
+    min_level: u8,
+) -> Option<(usize, u8)> {
+    let level = levels[current]?;
+    if level >= min_level {
+        return None;
+    }
+    let server = next_server(levels, current)?;
+    if levels[server]? > level {
+        Some((server, level))
+    } else {
+        None
+    }
+}
+
+/// Result of a periodic check of the service levels of the server set.
+pub(super) enum FailoverCheck {
+    /// The service levels were read and the session stays on the active server
+    ServiceLevels(Vec<Option<u8>>),
+    /// The session should move to the server, since the active server is at the service level
+    FailOver(usize, u8),
+}
+
+/// Periodically reads the service levels of the server set while the session is connected.
+pub(super) struct FailoverMonitor {
+    inner: Arc<Session>,
+    interval: tokio::time::Interval,
+}
+
+impl FailoverMonitor {
+    pub fn new(inner: Arc<Session>, check_interval: Duration) -> Self {
+        let mut interval = tokio::time::interval(check_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        Self { inner, interval }
+    }
+
+    pub fn run(self) -> impl Stream<Item = FailoverCheck> {
+        futures::stream::unfold(self, |mut slf| async move {
+            slf.interval.tick().await;
+            let failover = slf.inner.failover.get()?;
+            let levels = slf.inner.read_service_levels(failover).await;
+            let active = failover.active.load(Ordering::Relaxed);
+            let check =
+                match server_to_fail_over_to(&levels, active, failover.options.min_service_level) {
+                    Some((server, level)) => FailoverCheck::FailOver(server, level),
+                    None => FailoverCheck::ServiceLevels(levels),
+                };
+            Some((check, slf))
+        })
+    }
+}
+
+impl Session {
+    pub(crate) fn set_failover(&self, failover: Failover) {
+        if self.failover.set(failover).is_err() {
+            session_warn!(self, "Session already has a redundant server set");
+        }
+    }
+
+    /// The endpoint of the server the session connects to. With a redundant server set this is
+    /// the endpoint of the active server.
+    pub fn active_endpoint(&self) -> EndpointDescription {
+        self.session_info.load().endpoint.clone()
+    }
+
+    /// The last known service levels of the servers of the redundant server set of the session,
+    /// in the order of the set, or `None` if the session has no redundant server set.
+    pub fn server_service_levels(&self) -> Option<Vec<Option<u8>>> {
+        self.failover.get().map(|f| f.service_levels())
+    }
+
+    /// The check interval of the redundant server set, if the session has one.
+    pub(super) fn failover_check_interval(&self) -> Option<Duration> {
+        self.failover.get().map(|f| f.options.check_interval)
+    }
+
+    /// Start the event loops of the standby sessions, the first time the session event loop runs.
+    pub(super) fn start_standby_sessions(&self) {
+        let Some(failover) = self.failover.get() else {
+            return;
+        };
+        let standby_loops = std::mem::take(&mut *trace_lock!(failover.standby_loops));
+        if standby_loops.is_empty() {
+            return;
+        }
+        let mut standby_tasks = trace_lock!(failover.standby_tasks);
+        for event_loop in standby_loops {
+            standby_tasks.push(event_loop.spawn());
+        }
+    }
+
+    /// Close the standby sessions and stop their event loops.
+    pub(super) async fn disconnect_standby_sessions(&self) {
+        let Some(failover) = self.failover.get() else {
+            return;
+        };
+        let standby_sessions = failover
+            .servers
+            .iter()
+            .filter_map(|s| s.standby.as_ref())
+            .map(|s| s.disconnect());
+        let _ = join_all(standby_sessions).await;
+        for task in trace_lock!(failover.standby_tasks).drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Read the service level of the active server, and of the other servers through their
+    /// standby sessions or the `RedundantServerArray` of the active server.
+    async fn read_service_levels(&self, failover: &Failover) -> Vec<Option<u8>> {
+        let active = failover.active.load(Ordering::Relaxed);
+        let nodes_to_read = [
+            VariableId::Server_ServiceLevel,
+            VariableId::Server_ServerRedundancy_RedundantServerArray,
+        ]
+        .iter()
+        .map(|v| ReadValueId {
+            node_id: v.into(),
+            attribute_id: AttributeId::Value as u32,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+        let values = self
+            .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+            .await
+            .unwrap_or_default();
+
+        let mut levels = vec![None; failover.servers.len()];
+        if let Some(Variant::Byte(level)) = values.first().and_then(|v| v.value.as_ref()) {
+            levels[active] = Some(*level);
+        }
+
+        if failover.options.mode == FailoverMode::Cold {
+            if let Some(Variant::Array(array)) = values.get(1).and_then(|v| v.value.as_ref()) {
+                let decoding_options = self.decoding_options();
+                for value in &array.values {
+                    let Variant::ExtensionObject(obj) = value else {
+                        continue;
+                    };
+                    let Ok(redundant_server) =
+                        obj.decode_inner::<RedundantServerDataType>(&decoding_options)
+                    else {
+                        continue;
+                    };
+                    let server = failover.servers.iter().position(|s| {
+                        s.info.endpoint.server.application_uri == redundant_server.server_id
+                    });
+                    if let Some(server) = server.filter(|s| *s != active) {
+                        levels[server] = Some(redundant_server.service_level);
+                    }
+                }
+            }
+        } else {
+            let nodes_to_read = &nodes_to_read[..1];
+            let standby_levels = failover
+                .servers
+                .iter()
+                .enumerate()
+                .map(|(i, s)| async move {
+                    let standby = s.standby.as_ref().filter(|_| i != active)?;
+                    let values = standby
+                        .read(nodes_to_read, TimestampsToReturn::Neither, 0.0)
+                        .await
+                        .ok()?;
+                    match values.first()?.value {
+                        Some(Variant::Byte(level)) => Some(level),
+                        _ => None,
+                    }
+                });
+            for (i, level) in join_all(standby_levels).await.into_iter().enumerate() {
+                if level.is_some() {
+                    levels[i] = level;
+                }
+            }
+        }
+
+        for (server, level) in failover.servers.iter().zip(&levels) {
+            *trace_lock!(server.service_level) = *level;
+        }
+        session_debug!(self, "Service levels of the server set {:?}", levels);
+        levels
+    }
+
+    /// Point the session at another server of the set. The next connection creates a new
+    /// session on that server and moves the subscriptions to it.
+    pub(super) fn fail_over_to(&self, server: usize) {
+        let Some(failover) = self.failover.get() else {
+            return;
+        };
+        let info = failover.servers[server].info.clone();
+        session_warn!(
+            self,
+            "Failing over to server {} at {}",
+            server,
+            info.endpoint.endpoint_url
+        );
+        failover.active.store(server, Ordering::Relaxed);
+        failover.failed_over.store(true, Ordering::Relaxed);
+        self.channel.set_session_info(info.clone());
+        self.session_info.store(Arc::new(info));
+        self.reset();
+    }
+
+    /// Pick another server of the set after connecting to the active server failed.
+    pub(super) fn fail_over_on_connect_error(
+        &self,
+        status: StatusCode,
+    ) -> Option<FailoverActivity> {
+        let failover = self.failover.get()?;
+        let active = failover.active.load(Ordering::Relaxed);
+        let server = next_server(&failover.service_levels(), active)?;
+        self.fail_over_to(server);
+        Some(FailoverActivity::FailedOver {
+            server,
+            reason: FailoverReason::ConnectFailed(status),
+        })
+    }
+
+    /// Leave the active server before failing over. The session is closed unless its
+    /// subscriptions are to be transferred to the new server.
+    pub(super) async fn leave_server(&self) {
+        let hot = self
+            .failover
+            .get()
+            .is_some_and(|f| f.options.mode == FailoverMode::Hot);
+        if !hot {
+            if let Err(e) = self.close_session().await {
+                session_debug!(
+                    self,
+                    "Failed to close the session before failing over: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Returns the failover mode if the session moved to another server since the last call.
+    pub(super) fn take_failed_over(&self) -> Option<FailoverMode> {
+        let failover = self.failover.get()?;
+        if failover.failed_over.swap(false, Ordering::Relaxed) {
+            Some(failover.options.mode)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_server, server_to_fail_over_to};
+
+    #[test]
+    fn next_server_prefers_highest_level() {
+        assert_eq!(next_server(&[Some(100), Some(200), Some(250)], 0), Some(2));
+        assert_eq!(next_server(&[Some(100), None, Some(10)], 0), Some(2));
+        assert_eq!(next_server(&[Some(255)], 0), None);
+    }
+
+    #[test]
+    fn next_server_rotates_without_levels() {
+        assert_eq!(next_server(&[None, None, None], 0), Some(1));
+        assert_eq!(next_server(&[None, None, None], 1), Some(2));
+        assert_eq!(next_server(&[None, None, None], 2), Some(0));
+        // Ties go to the next server in the set
+        assert_eq!(next_server(&[Some(200), Some(200), Some(200)], 1), Some(2));
+    }
+
+    #[test]
+    fn fail_over_when_degraded() {
+        // Healthy
+        assert_eq!(
+            server_to_fail_over_to(&[Some(255), Some(255)], 0, 200),
+            None
+        );
+        // Degraded, but no better server
+        assert_eq!(server_to_fail_over_to(&[Some(100), Some(50)], 0, 200), None);
+        assert_eq!(server_to_fail_over_to(&[Some(100), None], 0, 200), None);
+        assert_eq!(
+            server_to_fail_over_to(&[Some(100), Some(50), Some(150)], 0, 200),
+            Some((2, 100))
+        );
+        // Level of the active server unknown
+        assert_eq!(server_to_fail_over_to(&[None, Some(255)], 0, 200), None);
+    }
+}
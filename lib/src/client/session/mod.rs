@@ -1,6 +1,8 @@
 mod client;
 mod connect;
 mod event_loop;
+mod failover;
+mod operation_limits;
 mod services;
 mod session;
 
@@ -39,9 +41,13 @@ impl From<(EndpointDescription, IdentityToken)> for SessionInfo {
 pub use client::Client;
 pub use connect::SessionConnectMode;
 pub use event_loop::{SessionActivity, SessionEventLoop, SessionPollResult};
+pub use failover::{FailoverActivity, FailoverMode, FailoverOptions, FailoverReason};
+pub use operation_limits::OperationLimits;
+pub use services::attributes::{HistoryReadAction, HistoryUpdateAction};
 pub use services::subscriptions::{
-    DataChangeCallback, EventCallback, MonitoredItem, OnSubscriptionNotification, Subscription,
-    SubscriptionCallbacks,
+    DataChangeCallback, EventCallback, MonitoredItem, NotificationStream,
+    OnSubscriptionNotification, OverflowPolicy, StreamOptions, Subscription, SubscriptionCallbacks,
+    SubscriptionNotification,
 };
 pub use session::Session;
 
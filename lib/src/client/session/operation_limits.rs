@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use futures::{Future, StreamExt, TryStreamExt};
+
+use crate::{
+    client::session::{session_debug, session_warn},
+    types::{
+        AttributeId, NodeId, ReadValueId, StatusCode, TimestampsToReturn, VariableId, Variant,
+    },
+};
+
+use super::Session;
+
+/// The operation limits of the server, read from `Server.ServerCapabilities.OperationLimits`
+/// after the session is activated. Calls with more operations than a limit are split into
+/// several requests. A limit of 0 means there is no limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationLimits {
+    /// Maximum nodes in a `Read` request
+    pub max_nodes_per_read: usize,
+    /// Maximum nodes in a `HistoryRead` request for data values
+    pub max_nodes_per_history_read_data: usize,
+    /// Maximum nodes in a `HistoryRead` request for events
+    pub max_nodes_per_history_read_events: usize,
+    /// Maximum nodes in a `Write` request
+    pub max_nodes_per_write: usize,
+    /// Maximum details in a `HistoryUpdate` request for data values
+    pub max_nodes_per_history_update_data: usize,
+    /// Maximum details in a `HistoryUpdate` request for events
+    pub max_nodes_per_history_update_events: usize,
+    /// Maximum methods in a `Call` request
+    pub max_nodes_per_method_call: usize,
+    /// Maximum nodes in a `Browse` or `BrowseNext` request
+    pub max_nodes_per_browse: usize,
+    /// Maximum nodes in a `RegisterNodes` or `UnregisterNodes` request
+    pub max_nodes_per_register_nodes: usize,
+    /// Maximum browse paths in a `TranslateBrowsePathsToNodeIds` request
+    pub max_nodes_per_translate_browse_paths_to_node_ids: usize,
+    /// Maximum items in an `AddNodes`, `AddReferences`, `DeleteNodes` or `DeleteReferences` request
+    pub max_nodes_per_node_management: usize,
+    /// Maximum monitored items in a request of the monitored item services
+    pub max_monitored_items_per_call: usize,
+}
+
+impl OperationLimits {
+    /// The variables holding each limit, in the order of [`OperationLimits::set`].
+    const VARIABLES: [VariableId; 12] = [
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerRead,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerHistoryReadData,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerHistoryReadEvents,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerWrite,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerHistoryUpdateData,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerHistoryUpdateEvents,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerMethodCall,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerBrowse,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerRegisterNodes,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerTranslateBrowsePathsToNodeIds,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerNodeManagement,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxMonitoredItemsPerCall,
+    ];
+
+    fn set(&mut self, index: usize, value: usize) {
+        let limit = match index {
+            0 => &mut self.max_nodes_per_read,
+            1 => &mut self.max_nodes_per_history_read_data,
+            2 => &mut self.max_nodes_per_history_read_events,
+            3 => &mut self.max_nodes_per_write,
+            4 => &mut self.max_nodes_per_history_update_data,
+            5 => &mut self.max_nodes_per_history_update_events,
+            6 => &mut self.max_nodes_per_method_call,
+            7 => &mut self.max_nodes_per_browse,
+            8 => &mut self.max_nodes_per_register_nodes,
+            9 => &mut self.max_nodes_per_translate_browse_paths_to_node_ids,
+            10 => &mut self.max_nodes_per_node_management,
+            11 => &mut self.max_monitored_items_per_call,
+            _ => return,
+        };
+        *limit = value;
+    }
+}
+
+/// The stricter of two limits, where 0 means no limit.
+pub(crate) fn min_limit(a: usize, b: usize) -> usize {
+    match (a, b) {
+        (0, b) => b,
+        (a, 0) => a,
+        (a, b) => a.min(b),
+    }
+}
+
+/// Splits `items` into chunks of at most `limit` items and calls `f` on each, with up to
+/// `concurrency` calls in flight at once. The results are concatenated in the order of the items.
+pub(crate) async fn run_batched<'a, T, R, F, Fut>(
+    items: &'a [T],
+    limit: usize,
+    concurrency: usize,
+    f: F,
+) -> Result<Vec<R>, StatusCode>
+where
+    F: Fn(&'a [T]) -> Fut,
+    Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+{
+    if limit == 0 || items.len() <= limit {
+        return f(items).await;
+    }
+    let batches = items.chunks(limit).map(f).collect::<Vec<_>>();
+    let results: Vec<Vec<R>> = futures::stream::iter(batches)
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Like [`run_batched`], but a batch that fails does not fail the call while another batch
+/// succeeded, since the operations of that batch have taken effect on the server. Each operation
+/// of a failed batch gets the result `failed` makes from the status of the batch. The call only
+/// fails when every batch fails.
+pub(crate) async fn run_batched_per_operation<'a, T, R, F, Fut, E>(
+    items: &'a [T],
+    limit: usize,
+    concurrency: usize,
+    f: F,
+    failed: E,
+) -> Result<Vec<R>, StatusCode>
+where
+    F: Fn(&'a [T]) -> Fut,
+    Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+    E: Fn(StatusCode) -> R,
+{
+    if limit == 0 || items.len() <= limit {
+        return f(items).await;
+    }
+    let batches = items.chunks(limit).map(f).collect::<Vec<_>>();
+    let results: Vec<Result<Vec<R>, StatusCode>> = futures::stream::iter(batches)
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+    if let Some(Err(status)) = results.first() {
+        if results.iter().all(|r| r.is_err()) {
+            return Err(*status);
+        }
+    }
+    let mut operations = Vec::with_capacity(items.len());
+    for (chunk, result) in items.chunks(limit).zip(results) {
+        match result {
+            Ok(result) => operations.extend(result),
+            Err(status) => operations.extend(chunk.iter().map(|_| failed(status))),
+        }
+    }
+    Ok(operations)
+}
+
+impl Session {
+    /// The operation limits of the server, read when the session was activated.
+    pub fn operation_limits(&self) -> OperationLimits {
+        (**self.operation_limits.load()).clone()
+    }
+
+    /// Calls `f` on chunks of `items` of at most `limit` items, concurrently, and concatenates
+    /// the results in order.
+    pub(super) async fn batched<'a, T, R, F, Fut>(
+        &self,
+        items: &'a [T],
+        limit: usize,
+        f: F,
+    ) -> Result<Vec<R>, StatusCode>
+    where
+        F: Fn(&'a [T]) -> Fut,
+        Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+    {
+        if limit != 0 && items.len() > limit {
+            session_debug!(
+                self,
+                "Splitting {} operations into batches of {}",
+                items.len(),
+                limit
+            );
+        }
+        run_batched(items, limit, self.max_concurrent_batches, f).await
+    }
+
+    /// Calls `f` on chunks of `items` like [`Session::batched`], for services that change the
+    /// server. The operations of a failed batch get the result `failed` makes from its status.
+    pub(super) async fn batched_per_operation<'a, T, R, F, Fut, E>(
+        &self,
+        items: &'a [T],
+        limit: usize,
+        f: F,
+        failed: E,
+    ) -> Result<Vec<R>, StatusCode>
+    where
+        F: Fn(&'a [T]) -> Fut,
+        Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+        E: Fn(StatusCode) -> R,
+    {
+        if limit != 0 && items.len() > limit {
+            session_debug!(
+                self,
+                "Splitting {} operations into batches of {}",
+                items.len(),
+                limit
+            );
+        }
+        run_batched_per_operation(items, limit, self.max_concurrent_batches, f, failed).await
+    }
+
+    /// Read the operation limits of the server. If they cannot be read, the previous limits are
+    /// kept.
+    pub(crate) async fn read_operation_limits(&self) {
+        let nodes_to_read = OperationLimits::VARIABLES
+            .iter()
+            .map(|v| ReadValueId {
+                node_id: NodeId::from(v),
+                attribute_id: AttributeId::Value as u32,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let values = match self
+            .read_inner(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+            .await
+        {
+            Ok(values) => values,
+            Err(e) => {
+                session_warn!(self, "Failed to read the server operation limits: {}", e);
+                return;
+            }
+        };
+
+        let mut limits = OperationLimits::default();
+        for (index, value) in values.iter().enumerate() {
+            let limit = match value.value {
+                Some(Variant::UInt32(v)) => v as usize,
+                Some(Variant::UInt16(v)) => v as usize,
+                _ => 0,
+            };
+            limits.set(index, limit);
+        }
+        session_debug!(self, "Server operation limits {:?}", limits);
+        self.operation_limits.store(Arc::new(limits));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{min_limit, run_batched, run_batched_per_operation};
+    use crate::types::StatusCode;
+
+    #[tokio::test]
+    async fn batches_in_order() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let results = run_batched(&items, 3, 2, |chunk| async move {
+            assert!(chunk.len() <= 3);
+            Ok(chunk.iter().map(|i| i * 2).collect())
+        })
+        .await
+        .unwrap();
+        assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<u32>>());
+    }
+
+    #[tokio::test]
+    async fn unlimited_is_one_batch() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let results = run_batched(&items, 0, 2, |chunk| async move {
+            assert_eq!(chunk.len(), 10);
+            Ok(chunk.to_vec())
+        })
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn failed_batch_fails_call() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let result = run_batched(&items, 4, 2, |chunk| async move {
+            if chunk.contains(&5) {
+                Err(StatusCode::BadTooManyOperations)
+            } else {
+                Ok(chunk.to_vec())
+            }
+        })
+        .await;
+        assert_eq!(result, Err(StatusCode::BadTooManyOperations));
+    }
+
+    #[tokio::test]
+    async fn failed_middle_batch_fails_its_operations() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let results = run_batched_per_operation(
+            &items,
+            4,
+            2,
+            |chunk| async move {
+                if chunk.contains(&5) {
+                    Err(StatusCode::BadTooManyOperations)
+                } else {
+                    Ok(chunk.iter().map(|_| StatusCode::Good).collect())
+                }
+            },
+            |status| status,
+        )
+        .await
+        .unwrap();
+        let mut expected = vec![StatusCode::Good; 10];
+        expected[4..8].fill(StatusCode::BadTooManyOperations);
+        assert_eq!(results, expected);
+    }
+
+    #[tokio::test]
+    async fn every_batch_failed_fails_call() {
+        let items = (0..10).collect::<Vec<u32>>();
+        let result = run_batched_per_operation(
+            &items,
+            4,
+            2,
+            |_| async move { Err::<Vec<StatusCode>, _>(StatusCode::BadNotConnected) },
+            |status| status,
+        )
+        .await;
+        assert_eq!(result, Err(StatusCode::BadNotConnected));
+    }
+
+    #[test]
+    fn stricter_limit() {
+        assert_eq!(min_limit(0, 0), 0);
+        assert_eq!(min_limit(0, 5), 5);
+        assert_eq!(min_limit(7, 0), 7);
+        assert_eq!(min_limit(7, 5), 5);
+    }
+}
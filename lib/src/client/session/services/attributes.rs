@@ -1,7 +1,8 @@
 use crate::{
     client::{
         session::{
-            process_service_result, process_unexpected_response, session_debug, session_error,
+            operation_limits::min_limit, process_service_result, process_unexpected_response,
+            session_debug, session_error,
         },
         Session,
     },
@@ -109,27 +110,41 @@ impl Session {
             session_error!(self, "read(), was not supplied with any nodes to read");
             Err(StatusCode::BadNothingToDo)
         } else {
-            session_debug!(self, "read() requested to read nodes {:?}", nodes_to_read);
-            let request = ReadRequest {
-                request_header: self.make_request_header(),
-                max_age,
-                timestamps_to_return,
-                nodes_to_read: Some(nodes_to_read.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::ReadResponse(response) = response {
-                session_debug!(self, "read(), success");
-                process_service_result(&response.response_header)?;
-                let results = if let Some(results) = response.results {
-                    results
-                } else {
-                    Vec::new()
-                };
-                Ok(results)
+            let limit = self.operation_limits.load().max_nodes_per_read;
+            self.batched(nodes_to_read, limit, |nodes_to_read| {
+                self.read_inner(nodes_to_read, timestamps_to_return, max_age)
+            })
+            .await
+        }
+    }
+
+    /// Send a single [`ReadRequest`] for all of `nodes_to_read`.
+    pub(crate) async fn read_inner(
+        &self,
+        nodes_to_read: &[ReadValueId],
+        timestamps_to_return: TimestampsToReturn,
+        max_age: f64,
+    ) -> Result<Vec<DataValue>, StatusCode> {
+        session_debug!(self, "read() requested to read nodes {:?}", nodes_to_read);
+        let request = ReadRequest {
+            request_header: self.make_request_header(),
+            max_age,
+            timestamps_to_return,
+            nodes_to_read: Some(nodes_to_read.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::ReadResponse(response) = response {
+            session_debug!(self, "read(), success");
+            process_service_result(&response.response_header)?;
+            let results = if let Some(results) = response.results {
+                results
             } else {
-                session_error!(self, "read() value failed");
-                Err(process_unexpected_response(response))
-            }
+                Vec::new()
+            };
+            Ok(results)
+        } else {
+            session_error!(self, "read() value failed");
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -162,11 +177,35 @@ impl Session {
         release_continuation_points: bool,
         nodes_to_read: &[HistoryReadValueId],
     ) -> Result<Vec<HistoryReadResult>, StatusCode> {
+        let limits = self.operation_limits.load();
+        let limit = if matches!(history_read_details, HistoryReadAction::ReadEventDetails(_)) {
+            limits.max_nodes_per_history_read_events
+        } else {
+            limits.max_nodes_per_history_read_data
+        };
         // Turn the enum into an extension object
         let history_read_details = ExtensionObject::from(history_read_details);
+        self.batched(nodes_to_read, limit, |nodes_to_read| {
+            self.history_read_inner(
+                &history_read_details,
+                timestamps_to_return,
+                release_continuation_points,
+                nodes_to_read,
+            )
+        })
+        .await
+    }
+
+    async fn history_read_inner(
+        &self,
+        history_read_details: &ExtensionObject,
+        timestamps_to_return: TimestampsToReturn,
+        release_continuation_points: bool,
+        nodes_to_read: &[HistoryReadValueId],
+    ) -> Result<Vec<HistoryReadResult>, StatusCode> {
         let request = HistoryReadRequest {
             request_header: self.make_request_header(),
-            history_read_details,
+            history_read_details: history_read_details.clone(),
             timestamps_to_return,
             release_continuation_points,
             nodes_to_read: if nodes_to_read.is_empty() {
@@ -219,19 +258,33 @@ impl Session {
             session_error!(self, "write() was not supplied with any nodes to write");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = WriteRequest {
-                request_header: self.make_request_header(),
-                nodes_to_write: Some(nodes_to_write.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::WriteResponse(response) = response {
-                session_debug!(self, "write(), success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results.unwrap_or_default())
-            } else {
-                session_error!(self, "write() failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_write;
+            self.batched_per_operation(
+                nodes_to_write,
+                limit,
+                |nodes_to_write| self.write_inner(nodes_to_write),
+                |status| status,
+            )
+            .await
+        }
+    }
+
+    async fn write_inner(
+        &self,
+        nodes_to_write: &[WriteValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = WriteRequest {
+            request_header: self.make_request_header(),
+            nodes_to_write: Some(nodes_to_write.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::WriteResponse(response) = response {
+            session_debug!(self, "write(), success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "write() failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -268,30 +321,58 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
+            let limits = self.operation_limits.load();
+            let limit = history_update_details
+                .iter()
+                .map(|action| match action {
+                    HistoryUpdateAction::UpdateEventDetails(_)
+                    | HistoryUpdateAction::DeleteEventDetails(_) => {
+                        limits.max_nodes_per_history_update_events
+                    }
+                    _ => limits.max_nodes_per_history_update_data,
+                })
+                .fold(0, min_limit);
             // Turn the enums into ExtensionObjects
             let history_update_details = history_update_details
                 .iter()
                 .map(ExtensionObject::from)
                 .collect::<Vec<ExtensionObject>>();
 
-            let request = HistoryUpdateRequest {
-                request_header: self.make_request_header(),
-                history_update_details: Some(history_update_details.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::HistoryUpdateResponse(response) = response {
-                session_debug!(self, "history_update(), success");
-                process_service_result(&response.response_header)?;
-                let results = if let Some(results) = response.results {
-                    results
-                } else {
-                    Vec::new()
-                };
-                Ok(results)
+            self.batched_per_operation(
+                &history_update_details,
+                limit,
+                |history_update_details| self.history_update_inner(history_update_details),
+                |status_code| HistoryUpdateResult {
+                    status_code,
+                    operation_results: None,
+                    diagnostic_infos: None,
+                },
+            )
+            .await
+        }
+    }
+
+    async fn history_update_inner(
+        &self,
+        history_update_details: &[ExtensionObject],
+    ) -> Result<Vec<HistoryUpdateResult>, StatusCode> {
+        let request = HistoryUpdateRequest {
+            request_header: self.make_request_header(),
+            history_update_details: Some(history_update_details.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::HistoryUpdateResponse(response) = response {
+            session_debug!(self, "history_update(), success");
+            process_service_result(&response.response_header)?;
+            let results = if let Some(results) = response.results {
+                results
             } else {
-                session_error!(self, "history_update() failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+                Vec::new()
+            };
+            Ok(results)
+        } else {
+            session_error!(self, "history_update() failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 }
@@ -1,6 +1,8 @@
 use crate::{
     client::{
-        session::{process_unexpected_response, session_debug, session_error},
+        session::{
+            process_service_result, process_unexpected_response, session_debug, session_error,
+        },
         Session,
     },
     core::supported_message::SupportedMessage,
@@ -61,6 +63,74 @@ impl Session {
         }
     }
 
+    /// Calls several methods on the server by sending a [`CallRequest`] to the server. Calls with
+    /// more methods than the server accepts in one request are split into several requests.
+    ///
+    /// See OPC UA Part 4 - Services 5.11.2 for complete description of the service and error responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `methods_to_call` - A list of [`CallMethodRequest`] for the methods to call.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<CallMethodResult>)` - A [`CallMethodResult`] for each method call. The size and order of the
+    ///   list matches the size and order of the `methods_to_call` parameter.
+    /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
+    ///
+    pub async fn call_methods(
+        &self,
+        methods_to_call: &[CallMethodRequest],
+    ) -> Result<Vec<CallMethodResult>, StatusCode> {
+        if methods_to_call.is_empty() {
+            session_error!(self, "call_methods(), was not supplied with any methods");
+            Err(StatusCode::BadNothingToDo)
+        } else {
+            let limit = self.operation_limits.load().max_nodes_per_method_call;
+            self.batched_per_operation(
+                methods_to_call,
+                limit,
+                |methods_to_call| self.call_methods_inner(methods_to_call),
+                |status_code| CallMethodResult {
+                    status_code,
+                    input_argument_results: None,
+                    input_argument_diagnostic_infos: None,
+                    output_arguments: None,
+                },
+            )
+            .await
+        }
+    }
+
+    async fn call_methods_inner(
+        &self,
+        methods_to_call: &[CallMethodRequest],
+    ) -> Result<Vec<CallMethodResult>, StatusCode> {
+        session_debug!(self, "call_methods(), {} methods", methods_to_call.len());
+        let request = CallRequest {
+            request_header: self.make_request_header(),
+            methods_to_call: Some(methods_to_call.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::CallResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            let results = response.results.unwrap_or_default();
+            if results.len() != methods_to_call.len() {
+                session_error!(
+                    self,
+                    "call_methods(), expecting {} results from the server, got {}",
+                    methods_to_call.len(),
+                    results.len()
+                );
+                Err(StatusCode::BadUnexpectedError)
+            } else {
+                Ok(results)
+            }
+        } else {
+            Err(process_unexpected_response(response))
+        }
+    }
+
     /// Calls GetMonitoredItems via call_method(), putting a sane interface on the input / output.
     ///
     /// # Arguments
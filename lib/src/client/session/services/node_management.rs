@@ -6,7 +6,7 @@ use crate::{
     core::supported_message::SupportedMessage,
     types::{
         AddNodesItem, AddNodesRequest, AddNodesResult, AddReferencesItem, AddReferencesRequest,
-        DeleteNodesItem, DeleteNodesRequest, DeleteReferencesItem, DeleteReferencesRequest,
+        DeleteNodesItem, DeleteNodesRequest, DeleteReferencesItem, DeleteReferencesRequest, NodeId,
         StatusCode,
     },
 };
@@ -33,16 +33,17 @@ impl Session {
             session_error!(self, "add_nodes, called with no nodes to add");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = AddNodesRequest {
-                request_header: self.make_request_header(),
-                nodes_to_add: Some(nodes_to_add.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::AddNodesResponse(response) = response {
-                Ok(response.results.unwrap())
-            } else {
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_node_management;
+            self.batched_per_operation(
+                nodes_to_add,
+                limit,
+                |nodes_to_add| self.add_nodes_inner(nodes_to_add),
+                |status_code| AddNodesResult {
+                    status_code,
+                    added_node_id: NodeId::null(),
+                },
+            )
+            .await
         }
     }
 
@@ -67,17 +68,14 @@ impl Session {
             session_error!(self, "add_references, called with no references to add");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = AddReferencesRequest {
-                request_header: self.make_request_header(),
-                references_to_add: Some(references_to_add.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::AddReferencesResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                Ok(response.results.unwrap())
-            } else {
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_node_management;
+            self.batched_per_operation(
+                references_to_add,
+                limit,
+                |references_to_add| self.add_references_inner(references_to_add),
+                |status| status,
+            )
+            .await
         }
     }
 
@@ -102,16 +100,14 @@ impl Session {
             session_error!(self, "delete_nodes, called with no nodes to delete");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = DeleteNodesRequest {
-                request_header: self.make_request_header(),
-                nodes_to_delete: Some(nodes_to_delete.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::DeleteNodesResponse(response) = response {
-                Ok(response.results.unwrap())
-            } else {
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_node_management;
+            self.batched_per_operation(
+                nodes_to_delete,
+                limit,
+                |nodes_to_delete| self.delete_nodes_inner(nodes_to_delete),
+                |status| status,
+            )
+            .await
         }
     }
 
@@ -139,16 +135,79 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = DeleteReferencesRequest {
-                request_header: self.make_request_header(),
-                references_to_delete: Some(references_to_delete.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::DeleteReferencesResponse(response) = response {
-                Ok(response.results.unwrap())
-            } else {
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_node_management;
+            self.batched_per_operation(
+                references_to_delete,
+                limit,
+                |references_to_delete| self.delete_references_inner(references_to_delete),
+                |status| status,
+            )
+            .await
+        }
+    }
+
+    async fn add_nodes_inner(
+        &self,
+        nodes_to_add: &[AddNodesItem],
+    ) -> Result<Vec<AddNodesResult>, StatusCode> {
+        let request = AddNodesRequest {
+            request_header: self.make_request_header(),
+            nodes_to_add: Some(nodes_to_add.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::AddNodesResponse(response) = response {
+            Ok(response.results.unwrap())
+        } else {
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn add_references_inner(
+        &self,
+        references_to_add: &[AddReferencesItem],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = AddReferencesRequest {
+            request_header: self.make_request_header(),
+            references_to_add: Some(references_to_add.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::AddReferencesResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap())
+        } else {
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn delete_nodes_inner(
+        &self,
+        nodes_to_delete: &[DeleteNodesItem],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = DeleteNodesRequest {
+            request_header: self.make_request_header(),
+            nodes_to_delete: Some(nodes_to_delete.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::DeleteNodesResponse(response) = response {
+            Ok(response.results.unwrap())
+        } else {
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn delete_references_inner(
+        &self,
+        references_to_delete: &[DeleteReferencesItem],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = DeleteReferencesRequest {
+            request_header: self.make_request_header(),
+            references_to_delete: Some(references_to_delete.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::DeleteReferencesResponse(response) = response {
+            Ok(response.results.unwrap())
+        } else {
+            Err(process_unexpected_response(response))
         }
     }
 }
@@ -1,6 +1,9 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use crypto::{certificate_store::CertificateStore, user_identity::make_user_name_identity_token};
+use crypto::{
+    certificate_store::CertificateStore,
+    user_identity::{make_issued_identity_token, make_user_name_identity_token},
+};
 
 use crate::{
     client::{
@@ -11,12 +14,12 @@ use crate::{
         comms::{secure_channel::SecureChannel, url::hostname_from_url},
         supported_message::SupportedMessage,
     },
-    crypto::{self, SecurityPolicy},
+    crypto::{self, PrivateKeyPassphrase, SecurityPolicy},
     types::{
-        ActivateSessionRequest, AnonymousIdentityToken, ByteString, CancelRequest,
-        CloseSessionRequest, CreateSessionRequest, ExtensionObject, IntegerId, NodeId, ObjectId,
-        SignatureData, StatusCode, UAString, UserNameIdentityToken, UserTokenPolicy, UserTokenType,
-        X509IdentityToken,
+        profiles, ActivateSessionRequest, AnonymousIdentityToken, ByteString, CancelRequest,
+        CloseSessionRequest, CreateSessionRequest, ExtensionObject, IntegerId, IssuedIdentityToken,
+        NodeId, ObjectId, SignatureData, StatusCode, UAString, UserNameIdentityToken,
+        UserTokenPolicy, UserTokenType, X509IdentityToken,
     },
 };
 
@@ -33,7 +36,8 @@ impl Session {
     /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
     ///
     pub(crate) async fn create_session(&self) -> Result<NodeId, StatusCode> {
-        let endpoint_url = self.session_info.endpoint.endpoint_url.clone();
+        let session_info = self.session_info.load_full();
+        let endpoint_url = session_info.endpoint.endpoint_url.clone();
 
         let client_nonce = self.channel.client_nonce();
         let server_uri = UAString::null();
@@ -85,20 +89,20 @@ impl Session {
                 if let Ok(server_certificate) =
                     crypto::X509::from_byte_string(&response.server_certificate)
                 {
-                    // Validate server certificate against hostname and application_uri
-                    let hostname =
-                        hostname_from_url(self.session_info.endpoint.endpoint_url.as_ref())
-                            .map_err(|_| StatusCode::BadUnexpectedError)?;
-                    let application_uri =
-                        self.session_info.endpoint.server.application_uri.as_ref();
-
-                    let certificate_store = trace_write_lock!(self.certificate_store);
-                    let result = certificate_store.validate_or_reject_application_instance_cert(
+                    // Validate server certificate against hostname and application_uri. An
+                    // opc.uds url has no hostname to validate.
+                    let hostname = hostname_from_url(session_info.endpoint.endpoint_url.as_ref())
+                        .map_err(|_| StatusCode::BadUnexpectedError)?;
+                    let application_uri = session_info.endpoint.server.application_uri.as_ref();
+
+                    let result = CertificateStore::validate_or_approve_application_instance_cert(
+                        &self.certificate_store,
                         &server_certificate,
                         security_policy,
-                        Some(&hostname),
+                        Some(hostname.as_str()).filter(|h| !h.is_empty()),
                         Some(application_uri),
-                    );
+                    )
+                    .await;
                     if result.is_bad() {
                         return Err(result);
                     }
@@ -133,11 +137,11 @@ impl Session {
 
         drop(secure_channel);
 
-        let locale_ids = if self.session_info.preferred_locales.is_empty() {
+        let session_info = self.session_info.load_full();
+        let locale_ids = if session_info.preferred_locales.is_empty() {
             None
         } else {
-            let locale_ids = self
-                .session_info
+            let locale_ids = session_info
                 .preferred_locales
                 .iter()
                 .map(UAString::from)
@@ -202,18 +206,29 @@ impl Session {
         &self,
         channel: &SecureChannel,
     ) -> Result<(ExtensionObject, SignatureData), StatusCode> {
-        let server_cert = &channel.remote_cert();
-        let server_nonce = &channel.remote_nonce();
-
-        let user_identity_token = &self.session_info.user_identity_token;
+        let session_info = self.session_info.load();
+        let user_identity_token = &session_info.user_identity_token;
         let user_token_type = match user_identity_token {
             IdentityToken::Anonymous => UserTokenType::Anonymous,
             IdentityToken::UserName(_, _) => UserTokenType::UserName,
-            IdentityToken::X509(_, _) => UserTokenType::Certificate,
+            IdentityToken::X509(_, _) | IdentityToken::X509Encrypted(_, _, _) => {
+                UserTokenType::Certificate
+            }
+            IdentityToken::IssuedToken(_) => UserTokenType::IssuedToken,
         };
 
-        let endpoint = &self.session_info.endpoint;
-        let policy = endpoint.find_policy(user_token_type);
+        let endpoint = &session_info.endpoint;
+        let policy = if user_token_type == UserTokenType::IssuedToken {
+            // Only JSON Web Tokens are supported
+            endpoint.user_identity_tokens.as_ref().and_then(|policies| {
+                policies.iter().find(|t| {
+                    t.token_type == user_token_type
+                        && t.issued_token_type.as_ref() == profiles::ISSUED_TOKEN_TYPE_JWT
+                })
+            })
+        } else {
+            endpoint.find_policy(user_token_type)
+        };
 
         match policy {
             None => {
@@ -256,47 +271,34 @@ impl Session {
                         );
                         Ok((identity_token, SignatureData::null()))
                     }
-                    IdentityToken::X509(cert_path, private_key_path) => {
-                        let Some(server_cert) = &server_cert else {
-                            error!("Cannot create an X509IdentityToken because the remote server has no cert with which to create a signature");
-                            return Err(StatusCode::BadCertificateInvalid);
-                        };
-                        let certificate_data =
-                            CertificateStore::read_cert(cert_path).map_err(|e| {
-                                error!(
-                                    "Certificate cannot be loaded from path {}, error = {}",
-                                    cert_path.to_str().unwrap(),
-                                    e
-                                );
-                                StatusCode::BadSecurityPolicyRejected
-                            })?;
-                        let private_key =
-                            CertificateStore::read_pkey(private_key_path).map_err(|e| {
-                                error!(
-                                    "Private key cannot be loaded from path {}, error = {}",
-                                    private_key_path.to_str().unwrap(),
-                                    e
-                                );
-                                StatusCode::BadSecurityPolicyRejected
-                            })?;
-                        let user_token_signature = crypto::create_signature_data(
-                            &private_key,
+                    IdentityToken::X509(cert_path, private_key_path) => self
+                        .make_x509_identity_token(
+                            channel,
+                            policy,
                             security_policy,
-                            &server_cert.as_byte_string(),
-                            &ByteString::from(server_nonce),
-                        )?;
-
-                        // Create identity token
-                        let identity_token = X509IdentityToken {
-                            policy_id: policy.policy_id.clone(),
-                            certificate_data: certificate_data.as_byte_string(),
-                        };
+                            cert_path,
+                            private_key_path,
+                            None,
+                        ),
+                    IdentityToken::X509Encrypted(cert_path, private_key_path, passphrase) => self
+                        .make_x509_identity_token(
+                            channel,
+                            policy,
+                            security_policy,
+                            cert_path,
+                            private_key_path,
+                            Some(passphrase),
+                        ),
+                    IdentityToken::IssuedToken(issued_token_provider) => {
+                        // Obtain the token each time so a fresh one is used when reactivating
+                        let token = issued_token_provider.token()?;
+                        let identity_token =
+                            self.make_issued_identity_token(channel, policy, token.as_bytes())?;
                         let identity_token = ExtensionObject::from_encodable(
-                            ObjectId::X509IdentityToken_Encoding_DefaultBinary,
+                            ObjectId::IssuedIdentityToken_Encoding_DefaultBinary,
                             &identity_token,
                         );
-
-                        Ok((identity_token, user_token_signature))
+                        Ok((identity_token, SignatureData::null()))
                     }
                 }
             }
@@ -324,6 +326,76 @@ impl Session {
         )
     }
 
+    /// Create an X509 identity token and the signature that proves possession of its private key.
+    fn make_x509_identity_token(
+        &self,
+        secure_channel: &SecureChannel,
+        user_token_policy: &UserTokenPolicy,
+        security_policy: SecurityPolicy,
+        cert_path: &Path,
+        private_key_path: &Path,
+        passphrase: Option<&PrivateKeyPassphrase>,
+    ) -> Result<(ExtensionObject, SignatureData), StatusCode> {
+        let Some(server_cert) = secure_channel.remote_cert() else {
+            error!("Cannot create an X509IdentityToken because the remote server has no cert with which to create a signature");
+            return Err(StatusCode::BadCertificateInvalid);
+        };
+        let certificate_data = CertificateStore::read_cert(cert_path).map_err(|e| {
+            error!(
+                "Certificate cannot be loaded from path {}, error = {}",
+                cert_path.display(),
+                e
+            );
+            StatusCode::BadSecurityPolicyRejected
+        })?;
+        let private_key = CertificateStore::read_pkey_with_passphrase(private_key_path, passphrase)
+            .map_err(|e| {
+                error!(
+                    "Private key cannot be loaded from path {}, error = {}",
+                    private_key_path.display(),
+                    e
+                );
+                StatusCode::BadSecurityPolicyRejected
+            })?;
+        let user_token_signature = crypto::create_signature_data(
+            &private_key,
+            security_policy,
+            &server_cert.as_byte_string(),
+            &ByteString::from(secure_channel.remote_nonce()),
+        )?;
+
+        // Create identity token
+        let identity_token = X509IdentityToken {
+            policy_id: user_token_policy.policy_id.clone(),
+            certificate_data: certificate_data.as_byte_string(),
+        };
+        let identity_token = ExtensionObject::from_encodable(
+            ObjectId::X509IdentityToken_Encoding_DefaultBinary,
+            &identity_token,
+        );
+
+        Ok((identity_token, user_token_signature))
+    }
+
+    /// Create an issued identity token.
+    fn make_issued_identity_token(
+        &self,
+        secure_channel: &SecureChannel,
+        user_token_policy: &UserTokenPolicy,
+        token_data: &[u8],
+    ) -> Result<IssuedIdentityToken, StatusCode> {
+        let channel_security_policy = secure_channel.security_policy();
+        let nonce = secure_channel.remote_nonce();
+        let cert = secure_channel.remote_cert();
+        make_issued_identity_token(
+            channel_security_policy,
+            user_token_policy,
+            nonce,
+            &cert,
+            token_data,
+        )
+    }
+
     /// Close the session by sending a [`CloseSessionRequest`] to the server.
     ///
     /// This is not accessible by users, they must instead call `disconnect` to properly close the session.
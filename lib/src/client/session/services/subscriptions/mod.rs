@@ -1,6 +1,7 @@
 pub mod event_loop;
 mod service;
 pub mod state;
+mod stream;
 
 use std::{
     collections::{BTreeSet, HashMap},
@@ -13,6 +14,10 @@ use crate::types::{
     StatusChangeNotification, Variant,
 };
 
+pub use stream::{NotificationStream, OverflowPolicy, StreamOptions, SubscriptionNotification};
+
+use stream::NotificationStreams;
+
 pub(crate) struct CreateMonitoredItem {
     pub id: u32,
     pub client_handle: u32,
@@ -141,6 +146,7 @@ impl OnSubscriptionNotification for EventCallback {
     }
 }
 
+#[derive(Clone)]
 pub struct MonitoredItem {
     /// This is the monitored item's id within the subscription
     id: u32,
@@ -255,6 +261,8 @@ pub struct Subscription {
     client_handles: HashMap<u32, u32>,
 
     callback: Box<dyn OnSubscriptionNotification>,
+    /// Streams that receive the notifications in addition to the callback
+    streams: NotificationStreams,
 }
 
 impl Subscription {
@@ -280,6 +288,7 @@ impl Subscription {
             monitored_items: HashMap::new(),
             client_handles: HashMap::new(),
             callback: status_change_callback,
+            streams: NotificationStreams::default(),
         }
     }
 
@@ -375,6 +384,8 @@ impl Subscription {
             // Remove the monitored item and the client handle / id entry
             if let Some(monitored_item) = self.monitored_items.remove(id) {
                 let _ = self.client_handles.remove(&monitored_item.client_handle());
+                self.streams
+                    .remove_item_stream(monitored_item.client_handle());
             }
         })
     }
@@ -390,6 +401,11 @@ impl Subscription {
         }
     }
 
+    pub(crate) fn on_status_change(&mut self, notification: StatusChangeNotification) {
+        self.streams.on_subscription_status_change(&notification);
+        self.callback.on_subscription_status_change(notification);
+    }
+
     pub(crate) fn on_notification(
         &mut self,
         notification: NotificationMessage,
@@ -418,6 +434,7 @@ impl Subscription {
                                 .and_then(|handle| self.monitored_items.get(handle));
 
                             if let Some(item) = item {
+                                self.streams.on_data_value(&notif.value, item);
                                 self.callback.on_data_value(notif.value, item);
                             }
                         }
@@ -434,6 +451,7 @@ impl Subscription {
                                 .and_then(|handle| self.monitored_items.get(handle));
 
                             if let Some(item) = item {
+                                self.streams.on_event(&notif.event_fields, item);
                                 self.callback.on_event(notif.event_fields, item);
                             }
                         }
@@ -442,7 +460,10 @@ impl Subscription {
                 }
             } else if id == ObjectId::StatusChangeNotification_Encoding_DefaultBinary as u32 {
                 match obj.decode_inner::<StatusChangeNotification>(decoding_options) {
-                    Ok(it) => self.callback.on_subscription_status_change(it),
+                    Ok(it) => {
+                        self.streams.on_subscription_status_change(&it);
+                        self.callback.on_subscription_status_change(it)
+                    }
                     Err(e) => warn!("Failed to decode status change notification: {e}"),
                 }
             }
@@ -8,22 +8,31 @@ use crate::{
         session::{
             process_service_result, process_unexpected_response,
             services::subscriptions::{CreateMonitoredItem, ModifyMonitoredItem, Subscription},
-            session_debug, session_error, session_trace, session_warn,
+            session_debug, session_error, session_trace, session_warn, FailoverMode,
         },
         Session,
     },
     core::supported_message::SupportedMessage,
     types::{
         CreateMonitoredItemsRequest, CreateSubscriptionRequest, DeleteMonitoredItemsRequest,
-        DeleteSubscriptionsRequest, ModifyMonitoredItemsRequest, ModifySubscriptionRequest,
-        MonitoredItemCreateRequest, MonitoredItemCreateResult, MonitoredItemModifyRequest,
-        MonitoredItemModifyResult, MonitoringMode, MonitoringParameters, PublishRequest,
-        SetMonitoringModeRequest, SetPublishingModeRequest, SetTriggeringRequest, StatusCode,
-        TimestampsToReturn, TransferResult, TransferSubscriptionsRequest,
+        DeleteSubscriptionsRequest, ExtensionObject, ModifyMonitoredItemsRequest,
+        ModifySubscriptionRequest, MonitoredItemCreateRequest, MonitoredItemCreateResult,
+        MonitoredItemModifyRequest, MonitoredItemModifyResult, MonitoringMode,
+        MonitoringParameters, PublishRequest, SetMonitoringModeRequest, SetPublishingModeRequest,
+        SetTriggeringRequest, StatusCode, TimestampsToReturn, TransferResult,
+        TransferSubscriptionsRequest,
     },
 };
 
-use super::OnSubscriptionNotification;
+use super::{
+    stream::{NotificationQueue, NotificationStreams},
+    NotificationStream, OnSubscriptionNotification, StreamOptions,
+};
+
+/// Callback of subscriptions that only deliver notifications to a stream.
+struct NoCallback;
+
+impl OnSubscriptionNotification for NoCallback {}
 
 impl Session {
     async fn create_subscription_inner(
@@ -35,6 +44,7 @@ impl Session {
         publishing_enabled: bool,
         priority: u8,
         callback: Box<dyn OnSubscriptionNotification>,
+        streams: NotificationStreams,
     ) -> Result<u32, StatusCode> {
         let request = CreateSubscriptionRequest {
             request_header: self.make_request_header(),
@@ -48,7 +58,7 @@ impl Session {
         let response = self.send(request).await?;
         if let SupportedMessage::CreateSubscriptionResponse(response) = response {
             process_service_result(&response.response_header)?;
-            let subscription = Subscription::new(
+            let mut subscription = Subscription::new(
                 response.subscription_id,
                 Duration::from_millis(response.revised_publishing_interval.max(0.0).floor() as u64),
                 response.revised_lifetime_count,
@@ -58,6 +68,7 @@ impl Session {
                 publishing_enabled,
                 callback,
             );
+            subscription.streams = streams;
 
             // Add the new subscription to the subscription state
             {
@@ -139,10 +150,57 @@ impl Session {
             publishing_enabled,
             priority,
             Box::new(callback),
+            NotificationStreams::default(),
         )
         .await
     }
 
+    /// Create a subscription by sending a [`CreateSubscriptionRequest`] to the server, returning
+    /// a stream of its notifications instead of calling a callback.
+    ///
+    /// The stream yields every status change, data value and event of the subscription, and
+    /// ends when the subscription is deleted. Notifications that arrive while the stream is
+    /// full are handled according to the [`OverflowPolicy`](super::OverflowPolicy) in `options`.
+    ///
+    /// See [`Session::create_subscription`] for a description of the other arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Capacity and overflow policy of the returned stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((u32, NotificationStream))` - identifier for new subscription and its notification stream
+    /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
+    ///
+    pub async fn create_subscription_stream(
+        &self,
+        publishing_interval: Duration,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        publishing_enabled: bool,
+        options: StreamOptions,
+    ) -> Result<(u32, NotificationStream), StatusCode> {
+        let (sender, stream) = NotificationStream::channel(options);
+        let mut streams = NotificationStreams::default();
+        streams.set_subscription_stream(sender);
+        let subscription_id = self
+            .create_subscription_inner(
+                publishing_interval,
+                lifetime_count,
+                max_keep_alive_count,
+                max_notifications_per_publish,
+                publishing_enabled,
+                priority,
+                Box::new(NoCallback),
+                streams,
+            )
+            .await?;
+        Ok((subscription_id, stream))
+    }
+
     fn subscription_exists(&self, subscription_id: u32) -> bool {
         let subscription_state = trace_lock!(self.subscription_state);
         subscription_state.subscription_exists(subscription_id)
@@ -464,63 +522,167 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let mut final_items_to_create = Vec::new();
-            let mut created_items = Vec::new();
+            let limit = self.operation_limits.load().max_monitored_items_per_call;
+            self.batched_per_operation(
+                &items_to_create,
+                limit,
+                |items_to_create| {
+                    self.create_monitored_items_inner(
+                        subscription_id,
+                        timestamps_to_return,
+                        items_to_create,
+                    )
+                },
+                |status_code| MonitoredItemCreateResult {
+                    status_code,
+                    monitored_item_id: 0,
+                    revised_sampling_interval: 0.0,
+                    revised_queue_size: 0,
+                    filter_result: ExtensionObject::null(),
+                },
+            )
+            .await
+        }
+    }
 
-            for mut req in items_to_create {
-                if req.requested_parameters.client_handle == 0 {
-                    req.requested_parameters.client_handle = self.monitored_item_handle.next();
-                }
+    async fn create_monitored_items_inner(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: &[MonitoredItemCreateRequest],
+    ) -> Result<Vec<MonitoredItemCreateResult>, StatusCode> {
+        let mut final_items_to_create = Vec::new();
+        let mut created_items = Vec::new();
 
-                final_items_to_create.push(req.clone());
-                created_items.push(req);
+        for mut req in items_to_create.iter().cloned() {
+            if req.requested_parameters.client_handle == 0 {
+                req.requested_parameters.client_handle = self.monitored_item_handle.next();
             }
 
-            let request = CreateMonitoredItemsRequest {
-                request_header: self.make_request_header(),
-                subscription_id,
-                timestamps_to_return,
-                items_to_create: Some(final_items_to_create.clone()),
-            };
-            let response = self.send(request).await?;
+            final_items_to_create.push(req.clone());
+            created_items.push(req);
+        }
 
-            if let SupportedMessage::CreateMonitoredItemsResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                if let Some(ref results) = response.results {
-                    session_debug!(
-                        self,
-                        "create_monitored_items, {} items created",
-                        created_items.len()
-                    );
-                    // Set the items in our internal state
-                    let items_to_create = created_items
-                        .into_iter()
-                        .zip(results)
-                        .map(|(i, r)| CreateMonitoredItem {
-                            id: r.monitored_item_id,
-                            client_handle: i.requested_parameters.client_handle,
-                            discard_oldest: i.requested_parameters.discard_oldest,
-                            item_to_monitor: i.item_to_monitor.clone(),
-                            monitoring_mode: i.monitoring_mode,
-                            queue_size: r.revised_queue_size,
-                            sampling_interval: r.revised_sampling_interval,
-                            filter: i.requested_parameters.filter,
-                        })
-                        .collect::<Vec<CreateMonitoredItem>>();
-                    {
-                        let mut subscription_state = trace_lock!(self.subscription_state);
-                        subscription_state.insert_monitored_items(subscription_id, items_to_create);
+        let request = CreateMonitoredItemsRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            timestamps_to_return,
+            items_to_create: Some(final_items_to_create.clone()),
+        };
+        let response = self.send(request).await?;
+
+        if let SupportedMessage::CreateMonitoredItemsResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            if let Some(ref results) = response.results {
+                session_debug!(
+                    self,
+                    "create_monitored_items, {} items created",
+                    created_items.len()
+                );
+                // Set the items in our internal state
+                let items_to_create = created_items
+                    .into_iter()
+                    .zip(results)
+                    .map(|(i, r)| CreateMonitoredItem {
+                        id: r.monitored_item_id,
+                        client_handle: i.requested_parameters.client_handle,
+                        discard_oldest: i.requested_parameters.discard_oldest,
+                        item_to_monitor: i.item_to_monitor.clone(),
+                        monitoring_mode: i.monitoring_mode,
+                        queue_size: r.revised_queue_size,
+                        sampling_interval: r.revised_sampling_interval,
+                        filter: i.requested_parameters.filter,
+                    })
+                    .collect::<Vec<CreateMonitoredItem>>();
+                {
+                    let mut subscription_state = trace_lock!(self.subscription_state);
+                    subscription_state.insert_monitored_items(subscription_id, items_to_create);
+                }
+            } else {
+                session_debug!(
+                    self,
+                    "create_monitored_items, success but no monitored items were created"
+                );
+            }
+            Ok(response.results.unwrap())
+        } else {
+            session_error!(self, "create_monitored_items failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    /// Creates monitored items on a subscription by sending a [`CreateMonitoredItemsRequest`] to
+    /// the server, returning a stream of notifications for each item.
+    ///
+    /// Each stream yields the data values or events of its monitored item, in addition to any
+    /// callback or stream of the subscription, and ends when the monitored item is deleted. The
+    /// stream of an item the server failed to create ends immediately.
+    ///
+    /// See OPC UA Part 4 - Services 5.12.2 for complete description of the service and error responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `subscription_id` - The Server-assigned identifier for the Subscription that will report Notifications for this MonitoredItem
+    /// * `timestamps_to_return` - An enumeration that specifies the timestamp Attributes to be transmitted for each MonitoredItem.
+    /// * `items_to_create` - A list of [`MonitoredItemCreateRequest`] to be created and assigned to the specified Subscription.
+    /// * `options` - Capacity and overflow policy of each returned stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<(MonitoredItemCreateResult, NotificationStream)>)` - A [`MonitoredItemCreateResult`] and notification stream
+    ///   for each item to create. The size and order of the list matches the size and order of the `items_to_create` request parameter.
+    /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
+    ///
+    pub async fn create_monitored_items_stream(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: Vec<MonitoredItemCreateRequest>,
+        options: StreamOptions,
+    ) -> Result<Vec<(MonitoredItemCreateResult, NotificationStream)>, StatusCode> {
+        let mut streams = Vec::with_capacity(items_to_create.len());
+        let items_to_create = {
+            // Register the streams before the items exist, so no notification is missed
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            items_to_create
+                .into_iter()
+                .map(|mut req| {
+                    if req.requested_parameters.client_handle == 0 {
+                        req.requested_parameters.client_handle = self.monitored_item_handle.next();
                     }
-                } else {
-                    session_debug!(
-                        self,
-                        "create_monitored_items, success but no monitored items were created"
+                    let (sender, stream) = NotificationStream::channel(options);
+                    subscription_state.insert_item_stream(
+                        subscription_id,
+                        req.requested_parameters.client_handle,
+                        sender,
                     );
+                    streams.push((req.requested_parameters.client_handle, stream));
+                    req
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let results = self
+            .create_monitored_items(subscription_id, timestamps_to_return, items_to_create)
+            .await;
+
+        let mut subscription_state = trace_lock!(self.subscription_state);
+        match results {
+            Ok(results) => Ok(results
+                .into_iter()
+                .zip(streams)
+                .map(|(result, (client_handle, stream))| {
+                    if result.status_code.is_bad() {
+                        subscription_state.remove_item_stream(subscription_id, client_handle);
+                    }
+                    (result, stream)
+                })
+                .collect()),
+            Err(e) => {
+                for (client_handle, _) in streams {
+                    subscription_state.remove_item_stream(subscription_id, client_handle);
                 }
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "create_monitored_items failed {:?}", response);
-                Err(process_unexpected_response(response))
+                Err(e)
             }
         }
     }
@@ -570,42 +732,68 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let monitored_item_ids = items_to_modify
-                .iter()
-                .map(|i| i.monitored_item_id)
-                .collect::<Vec<u32>>();
-            let request = ModifyMonitoredItemsRequest {
-                request_header: self.make_request_header(),
-                subscription_id,
-                timestamps_to_return,
-                items_to_modify: Some(items_to_modify.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::ModifyMonitoredItemsResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                if let Some(ref results) = response.results {
-                    // Set the items in our internal state
-                    let items_to_modify = monitored_item_ids
-                        .iter()
-                        .zip(results.iter())
-                        .map(|(id, r)| ModifyMonitoredItem {
-                            id: *id,
-                            queue_size: r.revised_queue_size,
-                            sampling_interval: r.revised_sampling_interval,
-                        })
-                        .collect::<Vec<ModifyMonitoredItem>>();
-                    {
-                        let mut subscription_state = trace_lock!(self.subscription_state);
-                        subscription_state
-                            .modify_monitored_items(subscription_id, &items_to_modify);
-                    }
+            let limit = self.operation_limits.load().max_monitored_items_per_call;
+            self.batched_per_operation(
+                items_to_modify,
+                limit,
+                |items_to_modify| {
+                    self.modify_monitored_items_inner(
+                        subscription_id,
+                        timestamps_to_return,
+                        items_to_modify,
+                    )
+                },
+                |status_code| MonitoredItemModifyResult {
+                    status_code,
+                    revised_sampling_interval: 0.0,
+                    revised_queue_size: 0,
+                    filter_result: ExtensionObject::null(),
+                },
+            )
+            .await
+        }
+    }
+
+    async fn modify_monitored_items_inner(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_modify: &[MonitoredItemModifyRequest],
+    ) -> Result<Vec<MonitoredItemModifyResult>, StatusCode> {
+        let monitored_item_ids = items_to_modify
+            .iter()
+            .map(|i| i.monitored_item_id)
+            .collect::<Vec<u32>>();
+        let request = ModifyMonitoredItemsRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            timestamps_to_return,
+            items_to_modify: Some(items_to_modify.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::ModifyMonitoredItemsResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            if let Some(ref results) = response.results {
+                // Set the items in our internal state
+                let items_to_modify = monitored_item_ids
+                    .iter()
+                    .zip(results.iter())
+                    .map(|(id, r)| ModifyMonitoredItem {
+                        id: *id,
+                        queue_size: r.revised_queue_size,
+                        sampling_interval: r.revised_sampling_interval,
+                    })
+                    .collect::<Vec<ModifyMonitoredItem>>();
+                {
+                    let mut subscription_state = trace_lock!(self.subscription_state);
+                    subscription_state.modify_monitored_items(subscription_id, &items_to_modify);
                 }
-                session_debug!(self, "modify_monitored_items, success");
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "modify_monitored_items failed {:?}", response);
-                Err(process_unexpected_response(response))
             }
+            session_debug!(self, "modify_monitored_items, success");
+            Ok(response.results.unwrap())
+        } else {
+            session_error!(self, "modify_monitored_items failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -635,31 +823,53 @@ impl Session {
             session_error!(self, "set_monitoring_mode, called with nothing to do");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = {
-                let monitored_item_ids = Some(monitored_item_ids.to_vec());
-                SetMonitoringModeRequest {
-                    request_header: self.make_request_header(),
-                    subscription_id,
-                    monitoring_mode,
-                    monitored_item_ids,
-                }
-            };
-            let response = self.send(request).await?;
+            let limit = self.operation_limits.load().max_monitored_items_per_call;
+            self.batched_per_operation(
+                monitored_item_ids,
+                limit,
+                |monitored_item_ids| {
+                    self.set_monitoring_mode_inner(
+                        subscription_id,
+                        monitoring_mode,
+                        monitored_item_ids,
+                    )
+                },
+                |status| status,
+            )
+            .await
+        }
+    }
 
-            {
-                let mut subscription_state = trace_lock!(self.subscription_state);
-                subscription_state.set_monitoring_mode(
-                    subscription_id,
-                    monitored_item_ids,
-                    monitoring_mode,
-                );
-            }
-            if let SupportedMessage::SetMonitoringModeResponse(response) = response {
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "set_monitoring_mode failed {:?}", response);
-                Err(process_unexpected_response(response))
+    async fn set_monitoring_mode_inner(
+        &self,
+        subscription_id: u32,
+        monitoring_mode: MonitoringMode,
+        monitored_item_ids: &[u32],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = {
+            let monitored_item_ids = Some(monitored_item_ids.to_vec());
+            SetMonitoringModeRequest {
+                request_header: self.make_request_header(),
+                subscription_id,
+                monitoring_mode,
+                monitored_item_ids,
             }
+        };
+        let response = self.send(request).await?;
+
+        {
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.set_monitoring_mode(
+                subscription_id,
+                monitored_item_ids,
+                monitoring_mode,
+            );
+        }
+        if let SupportedMessage::SetMonitoringModeResponse(response) = response {
+            Ok(response.results.unwrap())
+        } else {
+            session_error!(self, "set_monitoring_mode failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -772,24 +982,41 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = DeleteMonitoredItemsRequest {
-                request_header: self.make_request_header(),
-                subscription_id,
-                monitored_item_ids: Some(items_to_delete.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::DeleteMonitoredItemsResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                if response.results.is_some() {
-                    let mut subscription_state = trace_lock!(self.subscription_state);
-                    subscription_state.delete_monitored_items(subscription_id, items_to_delete);
-                }
-                session_debug!(self, "delete_monitored_items, success");
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "delete_monitored_items failed {:?}", response);
-                Err(process_unexpected_response(response))
+            let limit = self.operation_limits.load().max_monitored_items_per_call;
+            self.batched_per_operation(
+                items_to_delete,
+                limit,
+                |items_to_delete| {
+                    self.delete_monitored_items_inner(subscription_id, items_to_delete)
+                },
+                |status| status,
+            )
+            .await
+        }
+    }
+
+    async fn delete_monitored_items_inner(
+        &self,
+        subscription_id: u32,
+        items_to_delete: &[u32],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = DeleteMonitoredItemsRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            monitored_item_ids: Some(items_to_delete.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::DeleteMonitoredItemsResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            if response.results.is_some() {
+                let mut subscription_state = trace_lock!(self.subscription_state);
+                subscription_state.delete_monitored_items(subscription_id, items_to_delete);
             }
+            session_debug!(self, "delete_monitored_items, success");
+            Ok(response.results.unwrap())
+        } else {
+            session_error!(self, "delete_monitored_items failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -804,6 +1031,25 @@ impl Session {
     /// Send a publish request, returning `true` if the session should send a new request
     /// immediately.
     pub(crate) async fn publish(&self) -> Result<bool, StatusCode> {
+        // Hold back the request while a blocking notification stream is full, but not long
+        // enough for the server to run out of publish requests
+        let (blocking_queues, max_hold_back) = {
+            let subscription_state = trace_lock!(self.subscription_state);
+            (
+                subscription_state.blocking_queues(),
+                subscription_state.max_publish_hold_back(),
+            )
+        };
+        if !blocking_queues.is_empty()
+            && !NotificationQueue::wait_for_all(&blocking_queues, max_hold_back).await
+        {
+            session_warn!(
+                self,
+                "A blocking notification stream is still full after {:?}, publishing anyway",
+                max_hold_back
+            );
+        }
+
         let acks = {
             let mut subscription_state = trace_lock!(self.subscription_state);
             let acks = subscription_state.take_acknowledgements();
@@ -873,6 +1119,7 @@ impl Session {
     pub(crate) async fn transfer_subscriptions_from_old_session(&self) {
         // TODO: This whole thing should probably be optional, so that users can
         // customize the process.
+        let failed_over = self.take_failed_over();
         let subscription_ids = {
             let subscription_state = trace_lock!(self.subscription_state);
             subscription_state.subscription_ids()
@@ -884,10 +1131,17 @@ impl Session {
 
         // Start by getting the subscription ids
         // Try to use TransferSubscriptions to move subscriptions_ids over. If this
-        // works then there is nothing else to do.
+        // works then there is nothing else to do. After a cold or warm failover the new
+        // server cannot know the subscriptions, so they are recreated straight away.
         let mut subscription_ids_to_recreate =
             subscription_ids.iter().copied().collect::<HashSet<u32>>();
-        if let Ok(transfer_results) = self.transfer_subscriptions(&subscription_ids, true).await {
+        let transfer = !matches!(failed_over, Some(FailoverMode::Cold | FailoverMode::Warm));
+        let transfer_results = if transfer {
+            self.transfer_subscriptions(&subscription_ids, true).await
+        } else {
+            Err(StatusCode::BadNothingToDo)
+        };
+        if let Ok(transfer_results) = transfer_results {
             session_debug!(self, "transfer_results = {:?}", transfer_results);
             transfer_results.iter().enumerate().for_each(|(i, r)| {
                 if r.status_code.is_good() {
@@ -927,6 +1181,7 @@ impl Session {
                     subscription.publishing_enabled,
                     subscription.priority,
                     subscription.callback,
+                    subscription.streams,
                 )
                 .await
             else {
@@ -980,5 +1235,11 @@ impl Session {
                 }
             }
         }
+
+        if failed_over.is_some() {
+            // Tell the subscriptions that they carry on from another server
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.on_status_change(StatusCode::GoodSubscriptionTransferred);
+        }
     }
 }
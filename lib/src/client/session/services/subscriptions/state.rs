@@ -1,13 +1,18 @@
 use std::{
     collections::HashMap,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use crate::types::{
-    DecodingOptions, MonitoringMode, NotificationMessage, SubscriptionAcknowledgement,
+    DecodingOptions, DiagnosticInfo, MonitoringMode, NotificationMessage, StatusChangeNotification,
+    StatusCode, SubscriptionAcknowledgement,
 };
 
-use super::{CreateMonitoredItem, ModifyMonitoredItem, Subscription};
+use super::{
+    stream::{NotificationQueue, NotificationSender},
+    CreateMonitoredItem, ModifyMonitoredItem, Subscription,
+};
 
 /// State containing all known subscriptions in the session.
 pub struct SubscriptionState {
@@ -159,6 +164,43 @@ impl SubscriptionState {
         }
     }
 
+    pub(crate) fn insert_item_stream(
+        &mut self,
+        subscription_id: u32,
+        client_handle: u32,
+        sender: NotificationSender,
+    ) {
+        if let Some(ref mut subscription) = self.subscriptions.get_mut(&subscription_id) {
+            subscription
+                .streams
+                .insert_item_stream(client_handle, sender);
+        }
+    }
+
+    pub(crate) fn remove_item_stream(&mut self, subscription_id: u32, client_handle: u32) {
+        if let Some(ref mut subscription) = self.subscriptions.get_mut(&subscription_id) {
+            subscription.streams.remove_item_stream(client_handle);
+        }
+    }
+
+    /// Notification stream queues that are full and must be read before the next publish.
+    pub(crate) fn blocking_queues(&self) -> Vec<Arc<NotificationQueue>> {
+        self.subscriptions
+            .values()
+            .flat_map(|s| s.streams.blocking_queues())
+            .collect()
+    }
+
+    /// The longest a publish request may be held back for a full blocking notification stream,
+    /// half the shortest keep-alive period of the subscriptions.
+    pub(crate) fn max_publish_hold_back(&self) -> Duration {
+        self.subscriptions
+            .values()
+            .map(|s| s.publishing_interval() * s.max_keep_alive_count().max(1) / 2)
+            .min()
+            .unwrap_or_default()
+    }
+
     pub(crate) fn set_triggering(
         &mut self,
         subscription_id: u32,
@@ -186,6 +228,16 @@ impl SubscriptionState {
         }
     }
 
+    /// Tell every subscription that its status changed, e.g. because it moved to another server.
+    pub(crate) fn on_status_change(&mut self, status: StatusCode) {
+        for subscription in self.subscriptions.values_mut() {
+            subscription.on_status_change(StatusChangeNotification {
+                status,
+                diagnostic_info: DiagnosticInfo::null(),
+            });
+        }
+    }
+
     pub(crate) fn handle_notification(
         &mut self,
         subscription_id: u32,
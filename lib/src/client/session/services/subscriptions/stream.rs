@@ -0,0 +1,469 @@
+//! Streams of subscription notifications, an alternative to [OnSubscriptionNotification]
+//! callbacks for async code.
+//!
+//! [OnSubscriptionNotification]: super::OnSubscriptionNotification
+
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+use tokio::sync::Notify;
+
+use crate::sync::*;
+use crate::types::{DataValue, StatusChangeNotification, StatusCode, Variant};
+
+use super::MonitoredItem;
+
+/// What a notification stream does when a notification arrives and its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Discard the oldest notification in the queue to make room. The next data value or event
+    /// has the overflow bit set in its status.
+    #[default]
+    DropOldest,
+    /// Keep every notification and hold back further publish requests until the stream has
+    /// been read below its capacity. The notifications of one publish response are always
+    /// queued together, so the queue can briefly hold more than its capacity.
+    ///
+    /// Publish requests are shared by every subscription of the session, so a full stream
+    /// delays the notifications of all of them. To keep the server from closing subscriptions
+    /// that run out of publish requests, a request is held back for at most half the shortest
+    /// keep-alive period of the session's subscriptions. After that publishing resumes and the
+    /// queue grows past its capacity, so a stream that is never read keeps using memory.
+    Block,
+}
+
+/// Capacity and overflow policy of a notification stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOptions {
+    /// Number of notifications the stream queues before it overflows.
+    pub capacity: usize,
+    /// What happens when the queue is full.
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            overflow_policy: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl StreamOptions {
+    /// Create stream options.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Number of notifications the stream queues before it overflows, at least 1.
+    /// * `overflow_policy` - What happens when the queue is full.
+    pub fn new(capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            overflow_policy,
+        }
+    }
+}
+
+/// A notification received on a subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionNotification {
+    /// The subscription changed state on the server.
+    StatusChange(StatusChangeNotification),
+    /// A data value changed.
+    DataValue {
+        /// Server assigned ID of the monitored item.
+        monitored_item_id: u32,
+        /// Client assigned handle of the monitored item.
+        client_handle: u32,
+        /// The new value.
+        value: DataValue,
+    },
+    /// An event was received.
+    Event {
+        /// Server assigned ID of the monitored item.
+        monitored_item_id: u32,
+        /// Client assigned handle of the monitored item.
+        client_handle: u32,
+        /// Fields selected by the event filter of the monitored item.
+        event_fields: Option<Vec<Variant>>,
+        /// Good, with the overflow bit set if older notifications were dropped.
+        status: StatusCode,
+    },
+}
+
+impl SubscriptionNotification {
+    /// The status of the notification. The overflow bit is set if the stream dropped older
+    /// notifications before this one.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::StatusChange(notification) => notification.status,
+            Self::DataValue { value, .. } => value.status(),
+            Self::Event { status, .. } => *status,
+        }
+    }
+
+    fn set_overflow(&mut self) -> bool {
+        match self {
+            Self::StatusChange(_) => false,
+            Self::DataValue { value, .. } => {
+                value.status = Some(value.status() | StatusCode::OVERFLOW);
+                true
+            }
+            Self::Event { status, .. } => {
+                *status |= StatusCode::OVERFLOW;
+                true
+            }
+        }
+    }
+}
+
+struct QueueState {
+    notifications: VecDeque<SubscriptionNotification>,
+    /// Set when notifications were dropped and no notification has carried the overflow bit yet
+    overflow: bool,
+    /// Total number of notifications dropped
+    dropped: u64,
+    /// The subscription or monitored item is gone, so nothing more will be queued
+    sender_closed: bool,
+    /// The stream was dropped, so nothing more will be read
+    receiver_closed: bool,
+}
+
+/// The queue between the session, which pushes notifications, and a [NotificationStream].
+pub(crate) struct NotificationQueue {
+    options: StreamOptions,
+    state: Mutex<QueueState>,
+    notification_queued: Notify,
+    notification_read: Notify,
+}
+
+impl NotificationQueue {
+    fn new(options: StreamOptions) -> Self {
+        Self {
+            options,
+            state: Mutex::new(QueueState {
+                notifications: VecDeque::with_capacity(options.capacity),
+                overflow: false,
+                dropped: 0,
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            notification_queued: Notify::new(),
+            notification_read: Notify::new(),
+        }
+    }
+
+    fn push(&self, mut notification: SubscriptionNotification) {
+        {
+            let mut state = trace_lock!(self.state);
+            if state.receiver_closed {
+                return;
+            }
+            if state.notifications.len() >= self.options.capacity
+                && self.options.overflow_policy == OverflowPolicy::DropOldest
+            {
+                state.notifications.pop_front();
+                state.overflow = true;
+                state.dropped += 1;
+            }
+            if state.overflow && notification.set_overflow() {
+                state.overflow = false;
+            }
+            state.notifications.push_back(notification);
+        }
+        self.notification_queued.notify_waiters();
+    }
+
+    /// Tests if the queue is full and blocks publishing until it is read.
+    pub(crate) fn is_blocking(&self) -> bool {
+        let state = trace_lock!(self.state);
+        self.options.overflow_policy == OverflowPolicy::Block
+            && !state.receiver_closed
+            && state.notifications.len() >= self.options.capacity
+    }
+
+    /// Waits until the queue has room, or the stream is dropped.
+    pub(crate) async fn wait_for_capacity(&self) {
+        loop {
+            let read = self.notification_read.notified();
+            tokio::pin!(read);
+            read.as_mut().enable();
+            if !self.is_blocking() {
+                return;
+            }
+            read.await;
+        }
+    }
+
+    /// Waits until every queue in `queues` has room, for at most `max_wait`. Returns `false`
+    /// if a queue is still full when the time runs out.
+    pub(crate) async fn wait_for_all(
+        queues: &[Arc<NotificationQueue>],
+        max_wait: Duration,
+    ) -> bool {
+        let wait = futures::future::join_all(queues.iter().map(|q| q.wait_for_capacity()));
+        tokio::time::timeout(max_wait, wait).await.is_ok()
+    }
+
+    async fn recv(&self) -> Option<SubscriptionNotification> {
+        loop {
+            let queued = self.notification_queued.notified();
+            tokio::pin!(queued);
+            queued.as_mut().enable();
+            {
+                let mut state = trace_lock!(self.state);
+                if let Some(notification) = state.notifications.pop_front() {
+                    drop(state);
+                    self.notification_read.notify_waiters();
+                    return Some(notification);
+                } else if state.sender_closed {
+                    return None;
+                }
+            }
+            queued.await;
+        }
+    }
+
+    fn close_sender(&self) {
+        trace_lock!(self.state).sender_closed = true;
+        self.notification_queued.notify_waiters();
+    }
+
+    fn close_receiver(&self) {
+        {
+            let mut state = trace_lock!(self.state);
+            state.receiver_closed = true;
+            state.notifications.clear();
+        }
+        self.notification_read.notify_waiters();
+    }
+}
+
+/// The sending half of a [NotificationStream], held by the subscription. The stream ends when
+/// it is dropped.
+pub(crate) struct NotificationSender {
+    queue: Arc<NotificationQueue>,
+}
+
+impl Drop for NotificationSender {
+    fn drop(&mut self) {
+        self.queue.close_sender();
+    }
+}
+
+/// A stream of notifications for a subscription or for a single monitored item. It ends when
+/// the subscription or monitored item is deleted.
+pub struct NotificationStream {
+    queue: Arc<NotificationQueue>,
+    inner: BoxStream<'static, SubscriptionNotification>,
+}
+
+impl NotificationStream {
+    pub(crate) fn channel(options: StreamOptions) -> (NotificationSender, NotificationStream) {
+        let queue = Arc::new(NotificationQueue::new(options));
+        let inner = futures::stream::unfold(queue.clone(), |queue| async move {
+            let notification = queue.recv().await?;
+            Some((notification, queue))
+        })
+        .boxed();
+        (
+            NotificationSender {
+                queue: queue.clone(),
+            },
+            NotificationStream { queue, inner },
+        )
+    }
+
+    /// The capacity and overflow policy of the stream.
+    pub fn options(&self) -> StreamOptions {
+        self.queue.options
+    }
+
+    /// The number of notifications the stream has dropped because its queue was full.
+    pub fn dropped(&self) -> u64 {
+        trace_lock!(self.queue.state).dropped
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = SubscriptionNotification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        self.queue.close_receiver();
+    }
+}
+
+/// The streams that the notifications of a subscription are delivered to, in addition to its
+/// callback.
+#[derive(Default)]
+pub(crate) struct NotificationStreams {
+    /// Stream of every notification of the subscription
+    subscription: Option<NotificationSender>,
+    /// Streams of single monitored items, keyed by client handle
+    items: HashMap<u32, NotificationSender>,
+}
+
+impl NotificationStreams {
+    pub(crate) fn set_subscription_stream(&mut self, sender: NotificationSender) {
+        self.subscription = Some(sender);
+    }
+
+    pub(crate) fn insert_item_stream(&mut self, client_handle: u32, sender: NotificationSender) {
+        self.items.insert(client_handle, sender);
+    }
+
+    pub(crate) fn remove_item_stream(&mut self, client_handle: u32) {
+        self.items.remove(&client_handle);
+    }
+
+    fn senders(&self, client_handle: u32) -> impl Iterator<Item = &NotificationSender> {
+        self.subscription
+            .iter()
+            .chain(self.items.get(&client_handle))
+    }
+
+    /// Queues that are full and block publishing.
+    pub(crate) fn blocking_queues(&self) -> impl Iterator<Item = Arc<NotificationQueue>> + '_ {
+        self.subscription
+            .iter()
+            .chain(self.items.values())
+            .filter(|s| s.queue.is_blocking())
+            .map(|s| s.queue.clone())
+    }
+
+    pub(crate) fn on_subscription_status_change(&self, notification: &StatusChangeNotification) {
+        if let Some(ref sender) = self.subscription {
+            sender
+                .queue
+                .push(SubscriptionNotification::StatusChange(notification.clone()));
+        }
+    }
+
+    pub(crate) fn on_data_value(&self, value: &DataValue, item: &MonitoredItem) {
+        for sender in self.senders(item.client_handle()) {
+            sender.queue.push(SubscriptionNotification::DataValue {
+                monitored_item_id: item.id(),
+                client_handle: item.client_handle(),
+                value: value.clone(),
+            });
+        }
+    }
+
+    pub(crate) fn on_event(&self, event_fields: &Option<Vec<Variant>>, item: &MonitoredItem) {
+        for sender in self.senders(item.client_handle()) {
+            sender.queue.push(SubscriptionNotification::Event {
+                monitored_item_id: item.id(),
+                client_handle: item.client_handle(),
+                event_fields: event_fields.clone(),
+                status: StatusCode::Good,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+
+    use super::{
+        MonitoredItem, NotificationQueue, NotificationStream, NotificationStreams, OverflowPolicy,
+        StreamOptions, SubscriptionNotification,
+    };
+    use crate::types::{DataValue, StatusCode, Variant};
+
+    fn stream(
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> (NotificationStreams, NotificationStream) {
+        let (sender, stream) = NotificationStream::channel(StreamOptions::new(capacity, policy));
+        let mut streams = NotificationStreams::default();
+        streams.set_subscription_stream(sender);
+        (streams, stream)
+    }
+
+    fn value(notification: Option<SubscriptionNotification>) -> (i32, StatusCode) {
+        match notification {
+            Some(SubscriptionNotification::DataValue { value, .. }) => {
+                let Variant::Int32(v) = value.value.clone().unwrap() else {
+                    panic!("unexpected value {:?}", value);
+                };
+                (v, value.status())
+            }
+            n => panic!("unexpected notification {:?}", n),
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_sets_overflow() {
+        let (streams, mut stream) = stream(2, OverflowPolicy::DropOldest);
+        let item = MonitoredItem::new(1);
+        for i in 0..3 {
+            streams.on_data_value(&DataValue::new_now(i), &item);
+        }
+        assert_eq!(stream.dropped(), 1);
+        assert!(streams.blocking_queues().next().is_none());
+
+        assert_eq!(value(stream.next().await), (1, StatusCode::Good));
+        let (v, status) = value(stream.next().await);
+        assert_eq!(v, 2);
+        assert!(status.contains(StatusCode::OVERFLOW));
+    }
+
+    #[tokio::test]
+    async fn block_keeps_notifications() {
+        let (streams, mut stream) = stream(2, OverflowPolicy::Block);
+        let item = MonitoredItem::new(1);
+        for i in 0..3 {
+            streams.on_data_value(&DataValue::new_now(i), &item);
+        }
+        assert_eq!(stream.dropped(), 0);
+        let queue = streams.blocking_queues().next().unwrap();
+
+        assert_eq!(value(stream.next().await), (0, StatusCode::Good));
+        assert!(queue.is_blocking());
+        assert_eq!(value(stream.next().await), (1, StatusCode::Good));
+        queue.wait_for_capacity().await;
+        assert!(streams.blocking_queues().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn block_wait_is_bounded() {
+        let (streams, mut stream) = stream(1, OverflowPolicy::Block);
+        let item = MonitoredItem::new(1);
+        streams.on_data_value(&DataValue::new_now(0), &item);
+        let queues: Vec<_> = streams.blocking_queues().collect();
+
+        let max_wait = Duration::from_millis(50);
+        assert!(!NotificationQueue::wait_for_all(&queues, max_wait).await);
+        streams.on_data_value(&DataValue::new_now(1), &item);
+        assert_eq!(stream.dropped(), 0);
+
+        assert_eq!(value(stream.next().await), (0, StatusCode::Good));
+        assert_eq!(value(stream.next().await), (1, StatusCode::Good));
+        assert!(NotificationQueue::wait_for_all(&queues, max_wait).await);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_when_sender_dropped() {
+        let (streams, mut stream) = stream(2, OverflowPolicy::DropOldest);
+        streams.on_data_value(&DataValue::new_now(5), &MonitoredItem::new(1));
+        drop(streams);
+        assert_eq!(value(stream.next().await), (5, StatusCode::Good));
+        assert!(stream.next().await.is_none());
+    }
+}
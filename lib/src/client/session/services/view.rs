@@ -24,8 +24,8 @@ impl Session {
     ///
     /// # Returns
     ///
-    /// * `Ok(Option<Vec<BrowseResult>)` - A list [`BrowseResult`] corresponding to each node to browse. A browse result
-    ///                                    may contain a continuation point, for use with `browse_next()`.
+    /// * `Ok(Option<Vec<BrowseResult>)` - A list [`BrowseResult`] corresponding to each node to browse. Continuation
+    ///                                    points are followed with `browse_next()`, so each result holds every reference.
     /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
     ///
     pub async fn browse(
@@ -36,24 +36,85 @@ impl Session {
             session_error!(self, "browse, was not supplied with any nodes to browse");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = BrowseRequest {
-                request_header: self.make_request_header(),
-                view: ViewDescription {
-                    view_id: NodeId::null(),
-                    timestamp: DateTime::null(),
-                    view_version: 0,
-                },
-                requested_max_references_per_node: 1000,
-                nodes_to_browse: Some(nodes_to_browse.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::BrowseResponse(response) = response {
-                session_debug!(self, "browse, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results)
-            } else {
-                session_error!(self, "browse failed {:?}", response);
-                Err(process_unexpected_response(response))
+            let limit = self.operation_limits.load().max_nodes_per_browse;
+            let mut results = self
+                .batched(nodes_to_browse, limit, |nodes_to_browse| {
+                    self.browse_inner(nodes_to_browse)
+                })
+                .await?;
+            self.follow_continuation_points(&mut results).await?;
+            Ok(Some(results))
+        }
+    }
+
+    async fn browse_inner(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+    ) -> Result<Vec<BrowseResult>, StatusCode> {
+        let request = BrowseRequest {
+            request_header: self.make_request_header(),
+            view: ViewDescription {
+                view_id: NodeId::null(),
+                timestamp: DateTime::null(),
+                view_version: 0,
+            },
+            requested_max_references_per_node: 1000,
+            nodes_to_browse: Some(nodes_to_browse.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::BrowseResponse(response) = response {
+            session_debug!(self, "browse, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "browse failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    /// Call `browse_next()` until none of the results has a continuation point, appending the
+    /// references to each result.
+    async fn follow_continuation_points(
+        &self,
+        results: &mut [BrowseResult],
+    ) -> Result<(), StatusCode> {
+        loop {
+            let (indices, continuation_points): (Vec<usize>, Vec<ByteString>) = results
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| !r.continuation_point.is_null_or_empty())
+                .map(|(i, r)| (i, r.continuation_point.clone()))
+                .unzip();
+            if continuation_points.is_empty() {
+                return Ok(());
+            }
+
+            let next_results = self
+                .browse_next(false, &continuation_points)
+                .await?
+                .unwrap_or_default();
+            if next_results.len() != indices.len() {
+                session_error!(
+                    self,
+                    "browse_next returned {} results for {} continuation points",
+                    next_results.len(),
+                    indices.len()
+                );
+                return Err(StatusCode::BadUnexpectedError);
+            }
+
+            for (i, next) in indices.into_iter().zip(next_results) {
+                let result = &mut results[i];
+                result.continuation_point = next.continuation_point;
+                if next.status_code.is_bad() {
+                    result.status_code = next.status_code;
+                }
+                if let Some(references) = next.references {
+                    result
+                        .references
+                        .get_or_insert_with(Vec::new)
+                        .extend(references);
+                }
             }
         }
     }
@@ -82,20 +143,34 @@ impl Session {
         if continuation_points.is_empty() {
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = BrowseNextRequest {
-                request_header: self.make_request_header(),
-                continuation_points: Some(continuation_points.to_vec()),
-                release_continuation_points,
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::BrowseNextResponse(response) = response {
-                session_debug!(self, "browse_next, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results)
-            } else {
-                session_error!(self, "browse_next failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_browse;
+            let results = self
+                .batched(continuation_points, limit, |continuation_points| {
+                    self.browse_next_inner(release_continuation_points, continuation_points)
+                })
+                .await?;
+            Ok(Some(results))
+        }
+    }
+
+    async fn browse_next_inner(
+        &self,
+        release_continuation_points: bool,
+        continuation_points: &[ByteString],
+    ) -> Result<Vec<BrowseResult>, StatusCode> {
+        let request = BrowseNextRequest {
+            request_header: self.make_request_header(),
+            continuation_points: Some(continuation_points.to_vec()),
+            release_continuation_points,
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::BrowseNextResponse(response) = response {
+            session_debug!(self, "browse_next, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "browse_next failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -128,23 +203,37 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = TranslateBrowsePathsToNodeIdsRequest {
-                request_header: self.make_request_header(),
-                browse_paths: Some(browse_paths.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::TranslateBrowsePathsToNodeIdsResponse(response) = response {
-                session_debug!(self, "translate_browse_paths_to_node_ids, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results.unwrap_or_default())
-            } else {
-                session_error!(
-                    self,
-                    "translate_browse_paths_to_node_ids failed {:?}",
-                    response
-                );
-                Err(process_unexpected_response(response))
-            }
+            let limit = self
+                .operation_limits
+                .load()
+                .max_nodes_per_translate_browse_paths_to_node_ids;
+            self.batched(browse_paths, limit, |browse_paths| {
+                self.translate_browse_paths_to_node_ids_inner(browse_paths)
+            })
+            .await
+        }
+    }
+
+    async fn translate_browse_paths_to_node_ids_inner(
+        &self,
+        browse_paths: &[BrowsePath],
+    ) -> Result<Vec<BrowsePathResult>, StatusCode> {
+        let request = TranslateBrowsePathsToNodeIdsRequest {
+            request_header: self.make_request_header(),
+            browse_paths: Some(browse_paths.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::TranslateBrowsePathsToNodeIdsResponse(response) = response {
+            session_debug!(self, "translate_browse_paths_to_node_ids, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(
+                self,
+                "translate_browse_paths_to_node_ids failed {:?}",
+                response
+            );
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -175,19 +264,30 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = RegisterNodesRequest {
-                request_header: self.make_request_header(),
-                nodes_to_register: Some(nodes_to_register.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::RegisterNodesResponse(response) = response {
-                session_debug!(self, "register_nodes, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.registered_node_ids.unwrap())
-            } else {
-                session_error!(self, "register_nodes failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_register_nodes;
+            self.batched(nodes_to_register, limit, |nodes_to_register| {
+                self.register_nodes_inner(nodes_to_register)
+            })
+            .await
+        }
+    }
+
+    async fn register_nodes_inner(
+        &self,
+        nodes_to_register: &[NodeId],
+    ) -> Result<Vec<NodeId>, StatusCode> {
+        let request = RegisterNodesRequest {
+            request_header: self.make_request_header(),
+            nodes_to_register: Some(nodes_to_register.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::RegisterNodesResponse(response) = response {
+            session_debug!(self, "register_nodes, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.registered_node_ids.unwrap())
+        } else {
+            session_error!(self, "register_nodes failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 
@@ -214,19 +314,31 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = UnregisterNodesRequest {
-                request_header: self.make_request_header(),
-                nodes_to_unregister: Some(nodes_to_unregister.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::UnregisterNodesResponse(response) = response {
-                session_debug!(self, "unregister_nodes, success");
-                process_service_result(&response.response_header)?;
-                Ok(())
-            } else {
-                session_error!(self, "unregister_nodes failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            let limit = self.operation_limits.load().max_nodes_per_register_nodes;
+            self.batched(nodes_to_unregister, limit, |nodes_to_unregister| {
+                self.unregister_nodes_inner(nodes_to_unregister)
+            })
+            .await?;
+            Ok(())
+        }
+    }
+
+    async fn unregister_nodes_inner(
+        &self,
+        nodes_to_unregister: &[NodeId],
+    ) -> Result<Vec<()>, StatusCode> {
+        let request = UnregisterNodesRequest {
+            request_header: self.make_request_header(),
+            nodes_to_unregister: Some(nodes_to_unregister.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::UnregisterNodesResponse(response) = response {
+            session_debug!(self, "unregister_nodes, success");
+            process_service_result(&response.response_header)?;
+            Ok(Vec::new())
+        } else {
+            session_error!(self, "unregister_nodes failed {:?}", response);
+            Err(process_unexpected_response(response))
         }
     }
 }
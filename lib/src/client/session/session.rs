@@ -1,7 +1,7 @@
 use std::{
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
+        Arc, OnceLock,
     },
     time::{Duration, Instant},
 };
@@ -19,7 +19,10 @@ use crate::{
     types::{ApplicationDescription, DecodingOptions, NodeId, RequestHeader, StatusCode, UAString},
 };
 
-use super::{services::subscriptions::state::SubscriptionState, SessionEventLoop, SessionInfo};
+use super::{
+    failover::Failover, services::subscriptions::state::SubscriptionState, OperationLimits,
+    SessionEventLoop, SessionInfo,
+};
 
 #[derive(Clone, Copy)]
 pub enum SessionState {
@@ -46,7 +49,7 @@ pub struct Session {
     pub(super) session_id: Arc<ArcSwap<NodeId>>,
     pub(super) auth_token: Arc<ArcSwap<NodeId>>,
     pub(super) internal_session_id: AtomicU32,
-    pub(super) session_info: SessionInfo,
+    pub(super) session_info: ArcSwap<SessionInfo>,
     pub(super) session_name: UAString,
     pub(super) application_description: ApplicationDescription,
     pub(super) request_timeout: Duration,
@@ -57,6 +60,9 @@ pub struct Session {
     pub subscription_state: Mutex<SubscriptionState>,
     pub(super) monitored_item_handle: AtomicHandle,
     pub(super) trigger_publish_tx: tokio::sync::watch::Sender<Instant>,
+    pub(super) operation_limits: ArcSwap<OperationLimits>,
+    pub(super) max_concurrent_batches: usize,
+    pub(super) failover: OnceLock<Failover>,
 }
 
 impl Session {
@@ -95,7 +101,7 @@ impl Session {
             state_watch_rx,
             state_watch_tx,
             session_id: Default::default(),
-            session_info,
+            session_info: ArcSwap::new(Arc::new(session_info)),
             auth_token,
             session_name,
             application_description,
@@ -108,6 +114,9 @@ impl Session {
             subscription_state: Mutex::new(SubscriptionState::new(config.min_publish_interval)),
             monitored_item_handle: AtomicHandle::new(1000),
             trigger_publish_tx,
+            operation_limits: Default::default(),
+            max_concurrent_batches: config.performance.max_concurrent_batches,
+            failover: OnceLock::new(),
         });
 
         (
@@ -136,6 +145,12 @@ impl Session {
         self.channel.make_request_header(self.request_timeout)
     }
 
+    /// The decoding options of the secure channel, for decoding extension objects received
+    /// from the server.
+    pub(crate) fn decoding_options(&self) -> DecodingOptions {
+        trace_read_lock!(self.channel.secure_channel).decoding_options()
+    }
+
     /// Reset the session after a hard disconnect, clearing the session ID and incrementing the internal
     /// session counter.
     pub(crate) fn reset(&self) {
@@ -182,6 +197,7 @@ impl Session {
         self.channel.close_channel().await;
 
         self.wait_for_state(false).await;
+        self.disconnect_standby_sessions().await;
 
         Ok(())
     }
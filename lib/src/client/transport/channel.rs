@@ -27,7 +27,7 @@ use crate::client::{
 
 /// Wrapper around an open secure channel
 pub struct AsyncSecureChannel {
-    session_info: SessionInfo,
+    session_info: ArcSwap<SessionInfo>,
     session_retry_policy: SessionRetryPolicy,
     pub(crate) secure_channel: Arc<RwLock<SecureChannel>>,
     certificate_store: Arc<RwLock<CertificateStore>>,
@@ -68,7 +68,7 @@ impl AsyncSecureChannel {
             transport_config,
             issue_channel_lock: tokio::sync::Mutex::new(()),
             state: SecureChannelState::new(ignore_clock_skew, secure_channel.clone(), auth_token),
-            session_info,
+            session_info: ArcSwap::new(Arc::new(session_info)),
             secure_channel,
             certificate_store,
             session_retry_policy,
@@ -159,6 +159,11 @@ impl AsyncSecureChannel {
         Ok(())
     }
 
+    /// Set the endpoint and identity used the next time the channel connects.
+    pub(crate) fn set_session_info(&self, session_info: SessionInfo) {
+        self.session_info.store(Arc::new(session_info));
+    }
+
     pub(crate) fn security_policy(&self) -> SecurityPolicy {
         let secure_channel = trace_read_lock!(self.secure_channel);
         secure_channel.security_policy()
@@ -202,16 +207,16 @@ impl AsyncSecureChannel {
     async fn create_transport(
         &self,
     ) -> Result<(TcpTransport, tokio::sync::mpsc::Sender<OutgoingMessage>), StatusCode> {
-        let endpoint_url = self.session_info.endpoint.endpoint_url.clone();
+        let session_info = self.session_info.load();
+        let endpoint_url = session_info.endpoint.endpoint_url.clone();
         info!("Connect");
         let security_policy =
-            SecurityPolicy::from_str(self.session_info.endpoint.security_policy_uri.as_ref())
-                .unwrap();
+            SecurityPolicy::from_str(session_info.endpoint.security_policy_uri.as_ref()).unwrap();
 
         if security_policy == SecurityPolicy::Unknown {
             error!(
                 "connect, security policy \"{}\" is unknown",
-                self.session_info.endpoint.security_policy_uri.as_ref()
+                session_info.endpoint.security_policy_uri.as_ref()
             );
             return Err(StatusCode::BadSecurityPolicyRejected);
         } else {
@@ -225,15 +230,11 @@ impl AsyncSecureChannel {
                 secure_channel.set_private_key(key);
                 secure_channel.set_cert(cert);
                 secure_channel.set_security_policy(security_policy);
-                secure_channel.set_security_mode(self.session_info.endpoint.security_mode);
-                let _ = secure_channel.set_remote_cert_from_byte_string(
-                    &self.session_info.endpoint.server_certificate,
-                );
+                secure_channel.set_security_mode(session_info.endpoint.security_mode);
+                let _ = secure_channel
+                    .set_remote_cert_from_byte_string(&session_info.endpoint.server_certificate);
                 info!("Security policy = {:?}", security_policy);
-                info!(
-                    "Security mode = {:?}",
-                    self.session_info.endpoint.security_mode
-                );
+                info!("Security mode = {:?}", session_info.endpoint.security_mode);
             }
 
             let (send, recv) = tokio::sync::mpsc::channel(self.transport_config.max_inflight);
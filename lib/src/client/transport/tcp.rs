@@ -4,9 +4,10 @@ use super::buffer::SendBuffer;
 use super::core::{OutgoingMessage, TransportPollResult, TransportState};
 use crate::core::comms::{
     secure_channel::SecureChannel,
+    stream::BoxedStream,
     tcp_codec::{Message, TcpCodec},
     tcp_types::HelloMessage,
-    url::hostname_port_from_url,
+    url::{hostname_port_from_url, uds_path_from_url, OPC_UDS_SCHEME},
 };
 use crate::core::supported_message::SupportedMessage;
 use crate::types::{encoding::BinaryEncoder, StatusCode};
@@ -25,8 +26,8 @@ enum TransportCloseState {
 
 pub(crate) struct TcpTransport {
     state: TransportState,
-    read: FramedRead<ReadHalf<TcpStream>, TcpCodec>,
-    write: WriteHalf<TcpStream>,
+    read: FramedRead<ReadHalf<BoxedStream>, TcpCodec>,
+    write: WriteHalf<BoxedStream>,
     send_buffer: SendBuffer,
     should_close: bool,
     closed: TransportCloseState,
@@ -83,11 +84,56 @@ impl TcpTransport {
         endpoint_url: &str,
     ) -> Result<
         (
-            FramedRead<ReadHalf<TcpStream>, TcpCodec>,
-            WriteHalf<TcpStream>,
+            FramedRead<ReadHalf<BoxedStream>, TcpCodec>,
+            WriteHalf<BoxedStream>,
         ),
         StatusCode,
     > {
+        let socket = if endpoint_url.starts_with(OPC_UDS_SCHEME) {
+            Self::connect_uds(endpoint_url).await?
+        } else {
+            Self::connect_tcp(endpoint_url).await?
+        };
+
+        let (reader, mut writer) = tokio::io::split(socket);
+
+        let hello = HelloMessage::new(
+            endpoint_url,
+            config.send_buffer_size,
+            config.recv_buffer_size,
+            config.max_message_size,
+            config.max_chunk_count,
+        );
+        let mut framed_read = {
+            let secure_channel = trace_read_lock!(secure_channel);
+            FramedRead::new(reader, TcpCodec::new(secure_channel.decoding_options()))
+        };
+
+        writer
+            .write_all(&hello.encode_to_vec())
+            .await
+            .map_err(|err| {
+                error!("Cannot send hello to server, err = {:?}", err);
+                StatusCode::BadCommunicationError
+            })?;
+        match framed_read.next().await {
+            Some(Ok(Message::Acknowledge(ack))) => {
+                // TODO revise our sizes and other things according to the ACK
+                log::trace!("Received acknowledgement: {:?}", ack);
+            }
+            other => {
+                error!(
+                    "Unexpected error while waiting for server ACK. Expected ACK, got {:?}",
+                    other
+                );
+                return Err(StatusCode::BadConnectionClosed);
+            }
+        }
+
+        Ok((framed_read, writer))
+    }
+
+    async fn connect_tcp(endpoint_url: &str) -> Result<BoxedStream, StatusCode> {
         let (host, port) = hostname_port_from_url(
             endpoint_url,
             crate::core::constants::DEFAULT_OPC_UA_SERVER_PORT,
@@ -120,43 +166,53 @@ impl TcpTransport {
             error!("Could not connect to host {}, {:?}", addr, err);
             StatusCode::BadCommunicationError
         })?;
+        Ok(Box::new(socket))
+    }
 
-        let (reader, mut writer) = tokio::io::split(socket);
+    /// Connects to the Unix domain socket of an `opc.uds` url. The url path is the path of the
+    /// socket file followed by the endpoint path, so the socket is the nearest ancestor of the
+    /// path that is a socket.
+    #[cfg(unix)]
+    async fn connect_uds(endpoint_url: &str) -> Result<BoxedStream, StatusCode> {
+        use std::os::unix::fs::FileTypeExt;
 
-        let hello = HelloMessage::new(
-            endpoint_url,
-            config.send_buffer_size,
-            config.recv_buffer_size,
-            config.max_message_size,
-            config.max_chunk_count,
-        );
-        let mut framed_read = {
-            let secure_channel = trace_read_lock!(secure_channel);
-            FramedRead::new(reader, TcpCodec::new(secure_channel.decoding_options()))
+        let path = uds_path_from_url(endpoint_url)?;
+        let Some(socket_path) = path.ancestors().find(|p| {
+            std::fs::metadata(p)
+                .map(|m| m.file_type().is_socket())
+                .unwrap_or(false)
+        }) else {
+            error!("Invalid address {}, no such socket", endpoint_url);
+            return Err(StatusCode::BadTcpEndpointUrlInvalid);
         };
 
-        writer
-            .write_all(&hello.encode_to_vec())
+        debug!(
+            "Connecting to {} with url {}",
+            socket_path.display(),
+            endpoint_url
+        );
+
+        let socket = tokio::net::UnixStream::connect(socket_path)
             .await
             .map_err(|err| {
-                error!("Cannot send hello to server, err = {:?}", err);
-                StatusCode::BadCommunicationError
-            })?;
-        match framed_read.next().await {
-            Some(Ok(Message::Acknowledge(ack))) => {
-                // TODO revise our sizes and other things according to the ACK
-                log::trace!("Received acknowledgement: {:?}", ack);
-            }
-            other => {
                 error!(
-                    "Unexpected error while waiting for server ACK. Expected ACK, got {:?}",
-                    other
+                    "Could not connect to socket {}, {:?}",
+                    socket_path.display(),
+                    err
                 );
-                return Err(StatusCode::BadConnectionClosed);
-            }
-        }
+                StatusCode::BadCommunicationError
+            })?;
+        Ok(Box::new(socket))
+    }
 
-        Ok((framed_read, writer))
+    #[cfg(not(unix))]
+    async fn connect_uds(endpoint_url: &str) -> Result<BoxedStream, StatusCode> {
+        uds_path_from_url(endpoint_url)?;
+        error!(
+            "Cannot connect to {}, Unix domain sockets are not supported on this platform",
+            endpoint_url
+        );
+        Err(StatusCode::BadTcpEndpointUrlInvalid)
     }
 
     fn handle_incoming_message(
@@ -6,7 +6,11 @@ use std::io::{Cursor, Write};
 
 use crate::types::{status_code::StatusCode, BinaryEncoder, EncodingResult};
 
-use super::{chunker::Chunker, secure_channel::SecureChannel, tcp_types::AcknowledgeMessage};
+use super::{
+    chunker::Chunker,
+    secure_channel::SecureChannel,
+    tcp_types::{AcknowledgeMessage, ErrorMessage},
+};
 
 use crate::core::supported_message::SupportedMessage;
 
@@ -47,6 +51,10 @@ impl MessageWriter {
         ack.encode(&mut self.buffer)
     }
 
+    pub fn write_error(&mut self, error: &ErrorMessage) -> EncodingResult<usize> {
+        error.encode(&mut self.buffer)
+    }
+
     /// Encodes the message into a series of chunks, encrypts those chunks and writes the
     /// result into the buffer ready to be sent.
     pub fn write(
@@ -11,6 +11,7 @@ pub mod message_chunk_info;
 pub mod message_writer;
 pub mod secure_channel;
 pub mod security_header;
+pub mod stream;
 pub mod tcp_codec;
 pub mod tcp_types;
 pub mod url;
@@ -150,6 +150,10 @@ impl SecureChannel {
         self.private_key = private_key;
     }
 
+    pub fn private_key(&self) -> Option<&PrivateKey> {
+        self.private_key.as_ref()
+    }
+
     pub fn security_mode(&self) -> MessageSecurityMode {
         self.security_mode
     }
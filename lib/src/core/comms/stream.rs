@@ -0,0 +1,16 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Provides a stream type that UA-TCP messages can be framed over regardless of the underlying
+//! socket, e.g. a TCP or a Unix domain socket connection.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A bidirectional byte stream that a connection runs over
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> AsyncStream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+/// A boxed [`AsyncStream`], so TCP and Unix domain socket connections can be treated alike
+pub type BoxedStream = Box<dyn AsyncStream>;
@@ -4,11 +4,16 @@
 
 //! Provides functions for parsing Urls from strings.
 
+use std::path::PathBuf;
+
 use url::Url;
 
 use crate::types::status_code::StatusCode;
 
 pub const OPC_TCP_SCHEME: &str = "opc.tcp";
+/// Scheme of the UA-TCP protocol carried over a Unix domain socket, e.g.
+/// `opc.uds:///run/opcua/server.sock/path`
+pub const OPC_UDS_SCHEME: &str = "opc.uds";
 
 /// Creates a `Url` from the input string, supplying a default port if necessary.
 fn opc_url_from_str(s: &str) -> Result<Url, ()> {
@@ -71,7 +76,7 @@ pub fn is_valid_opc_ua_url(url: &str) -> bool {
 
 pub fn is_opc_ua_binary_url(url: &str) -> bool {
     if let Ok(url) = opc_url_from_str(url) {
-        url.scheme() == OPC_TCP_SCHEME
+        url.scheme() == OPC_TCP_SCHEME || url.scheme() == OPC_UDS_SCHEME
     } else {
         false
     }
@@ -103,6 +108,27 @@ pub fn hostname_port_from_url(url: &str, default_port: u16) -> Result<(String, u
     }
 }
 
+/// Returns the path of an `opc.uds` url. The path holds the socket file, optionally followed by
+/// the path of the endpoint, so it is up to the caller to find the socket within it.
+pub fn uds_path_from_url(url: &str) -> Result<PathBuf, StatusCode> {
+    let url = Url::parse(url).map_err(|_| StatusCode::BadTcpEndpointUrlInvalid)?;
+    if url.scheme() != OPC_UDS_SCHEME
+        || url.host_str().is_some_and(|host| !host.is_empty())
+        || url.port().is_some()
+        || url.path().len() <= 1
+    {
+        Err(StatusCode::BadTcpEndpointUrlInvalid)
+    } else {
+        // A file url decodes the path the same way
+        Url::parse(&format!("file://{}", url.path()))
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            // Drops a trailing slash
+            .map(|path| path.components().collect())
+            .ok_or(StatusCode::BadTcpEndpointUrlInvalid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,6 +140,28 @@ mod tests {
             "opc.tcp://[FEDC:BA98:7654:3210:FEDC:BA98:7654:3210]:80/xyz"
         ));
         assert!(!is_opc_ua_binary_url("http://foo/xyz"));
+        assert!(is_opc_ua_binary_url("opc.uds:///tmp/opcua.sock"));
+    }
+
+    #[test]
+    fn uds_path() {
+        assert_eq!(
+            uds_path_from_url("opc.uds:///tmp/opcua.sock").unwrap(),
+            PathBuf::from("/tmp/opcua.sock")
+        );
+        assert_eq!(
+            uds_path_from_url("opc.uds:///tmp/opcua.sock/")
+                .unwrap()
+                .as_os_str(),
+            "/tmp/opcua.sock"
+        );
+        assert_eq!(
+            uds_path_from_url("opc.uds:///tmp/opc%20ua.sock/xyz").unwrap(),
+            PathBuf::from("/tmp/opc ua.sock/xyz")
+        );
+        assert!(uds_path_from_url("opc.uds://foo/tmp/opcua.sock").is_err());
+        assert!(uds_path_from_url("opc.uds://").is_err());
+        assert!(uds_path_from_url("opc.tcp://foo/tmp/opcua.sock").is_err());
     }
 
     #[test]
@@ -126,6 +174,14 @@ mod tests {
             "opc.tcp://localhost/xyz",
             "opc.tcp://127.0.0.1/abc"
         ));
+        assert!(url_matches_except_host(
+            "opc.uds:///tmp/opcua.sock/xyz",
+            "opc.uds:///tmp/opcua.sock/xyz"
+        ));
+        assert!(!url_matches_except_host(
+            "opc.uds:///tmp/opcua.sock/xyz",
+            "opc.uds:///tmp/other.sock/xyz"
+        ));
     }
 
     #[test]
@@ -166,5 +222,13 @@ mod tests {
             url_with_replaced_hostname("opc.tcp://localhost:123/x", "127.0.0.1").unwrap(),
             "opc.tcp://127.0.0.1:123/x"
         );
+        assert_eq!(
+            url_with_replaced_hostname("opc.uds:///tmp/opcua.sock/x", "").unwrap(),
+            "opc.uds:///tmp/opcua.sock/x"
+        );
+        assert_eq!(
+            hostname_from_url("opc.uds:///tmp/opcua.sock/x").unwrap(),
+            ""
+        );
     }
 }
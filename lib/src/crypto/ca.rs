@@ -0,0 +1,163 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! A certificate authority for a private PKI. It issues application instance certificates,
+//! signs certificate signing requests and revokes certificates through a CRL.
+
+use openssl::x509::X509Req;
+
+use super::{
+    crl::Crl,
+    pkey::PrivateKey,
+    x509::{CertificateUsage, X509Data, X509},
+};
+
+/// A certificate authority, i.e. a CA certificate and its private key
+#[derive(Debug)]
+pub struct CertificateAuthority {
+    cert: X509,
+    pkey: PrivateKey,
+}
+
+impl CertificateAuthority {
+    /// Creates a certificate authority from an existing CA certificate and its private key
+    pub fn new(cert: X509, pkey: PrivateKey) -> Result<Self, String> {
+        let matches = cert
+            .value
+            .public_key()
+            .map(|public_key| public_key.public_eq(&pkey.value))
+            .unwrap_or(false);
+        if matches {
+            Ok(CertificateAuthority { cert, pkey })
+        } else {
+            Err("CA private key does not belong to the CA certificate".to_string())
+        }
+    }
+
+    /// Creates a new certificate authority with a self-signed CA certificate. The alt host names
+    /// of the data are not used.
+    pub fn create(x509_data: &X509Data) -> Result<Self, String> {
+        let pkey = PrivateKey::new(x509_data.key_size);
+        let subject_name = x509_data.x509_name()?;
+        let cert = X509::build(
+            &pkey.value,
+            &subject_name,
+            &[],
+            x509_data.certificate_duration_days,
+            CertificateUsage::CertificateAuthority,
+            None,
+            &pkey,
+        )?;
+        Ok(CertificateAuthority { cert, pkey })
+    }
+
+    /// The CA certificate
+    pub fn cert(&self) -> &X509 {
+        &self.cert
+    }
+
+    /// The CA private key
+    pub fn pkey(&self) -> &PrivateKey {
+        &self.pkey
+    }
+
+    /// Issues an application instance certificate and a new private key from the data.
+    pub fn issue(&self, x509_data: &X509Data) -> Result<(X509, PrivateKey), String> {
+        let pkey = PrivateKey::new(x509_data.key_size);
+        let subject_name = x509_data.x509_name()?;
+        let cert = X509::build(
+            &pkey.value,
+            &subject_name,
+            &x509_data.alt_host_names,
+            x509_data.certificate_duration_days,
+            CertificateUsage::Issued,
+            Some(&self.cert),
+            &self.pkey,
+        )?;
+        Ok((cert, pkey))
+    }
+
+    /// Issues the certificate and a new private key of an intermediate CA from the data. The
+    /// alt host names of the data are not used.
+    pub fn issue_ca(&self, x509_data: &X509Data) -> Result<(X509, PrivateKey), String> {
+        let pkey = PrivateKey::new(x509_data.key_size);
+        let subject_name = x509_data.x509_name()?;
+        let cert = X509::build(
+            &pkey.value,
+            &subject_name,
+            &[],
+            x509_data.certificate_duration_days,
+            CertificateUsage::CertificateAuthority,
+            Some(&self.cert),
+            &self.pkey,
+        )?;
+        Ok((cert, pkey))
+    }
+
+    /// Signs a certificate signing request in PEM or DER format and returns an application
+    /// instance certificate. The subject and public key come from the request, the alt host
+    /// names, of which the first is the application uri, are supplied by the CA.
+    pub fn sign_request(
+        &self,
+        request: &[u8],
+        alt_host_names: &[String],
+        certificate_duration_days: u32,
+    ) -> Result<X509, String> {
+        let request = X509Req::from_pem(request)
+            .or_else(|_| X509Req::from_der(request))
+            .map_err(|_| "Cannot read the certificate signing request".to_string())?;
+        let public_key = request
+            .public_key()
+            .map_err(|_| "Certificate signing request has no public key".to_string())?;
+        if !request.verify(&public_key).unwrap_or(false) {
+            return Err("Certificate signing request has an invalid signature".to_string());
+        }
+        X509::build(
+            &public_key,
+            request.subject_name(),
+            alt_host_names,
+            certificate_duration_days,
+            CertificateUsage::Issued,
+            Some(&self.cert),
+            &self.pkey,
+        )
+    }
+
+    /// Creates a CRL holding the revocations of the previous CRL, if any. The CRL must be
+    /// reissued before it expires after the supplied number of days.
+    pub fn crl(&self, previous: Option<&Crl>, next_update_days: u32) -> Result<Crl, String> {
+        self.check_crl(previous)?;
+        Crl::new_signed(&self.cert, &self.pkey, previous, &[], next_update_days)
+    }
+
+    /// Revokes a certificate issued by this CA. The returned CRL holds the revocations of the
+    /// previous CRL, if any, plus the certificate.
+    pub fn revoke(
+        &self,
+        previous: Option<&Crl>,
+        cert: &X509,
+        next_update_days: u32,
+    ) -> Result<Crl, String> {
+        self.check_crl(previous)?;
+        if !self.is_issuer_of(cert) {
+            return Err("Certificate was not issued by this CA".to_string());
+        }
+        Crl::new_signed(&self.cert, &self.pkey, previous, &[cert], next_update_days)
+    }
+
+    /// Tests if the certificate was issued and signed by this CA
+    pub fn is_issuer_of(&self, cert: &X509) -> bool {
+        self.cert.value.issued(&cert.value) == openssl::x509::X509VerifyResult::OK
+            && cert.value.verify(&self.pkey.value).unwrap_or(false)
+    }
+
+    fn check_crl(&self, crl: Option<&Crl>) -> Result<(), String> {
+        match crl {
+            Some(crl) if !crl.is_issued_by(&self.cert) => {
+                Err("CRL was not issued by this CA".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
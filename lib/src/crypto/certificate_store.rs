@@ -5,17 +5,29 @@
 //! The certificate store holds and retrieves private keys and certificates from disk. It is responsible
 //! for checking certificates supplied by the remote end to see if they are valid and trusted or not.
 
+use std::collections::HashSet;
 use std::fs::{metadata, File};
+use std::future::Future;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use openssl::{pkey, x509};
+use futures::future::BoxFuture;
+use openssl::{
+    stack::Stack,
+    x509::{self, store::X509StoreBuilder, verify::X509VerifyFlags, X509StoreContext},
+};
 
-use crate::types::status_code::StatusCode;
+use crate::{
+    sync::{Mutex, RwLock},
+    types::status_code::StatusCode,
+};
 
 use super::{
+    crl::Crl,
+    passphrase::PrivateKeyPassphrase,
     pkey::PrivateKey,
-    security_policy::SecurityPolicy,
+    security_policy::{SecurityPolicy, SecurityProfile},
     x509::{X509Data, X509},
 };
 
@@ -27,6 +39,69 @@ const OWN_PRIVATE_KEY_PATH: &str = "private/private.pem";
 const TRUSTED_CERTS_DIR: &str = "trusted";
 /// The directory holding rejected certificates
 const REJECTED_CERTS_DIR: &str = "rejected";
+/// The directory holding the certificates of intermediate certificate authorities. They are used
+/// to build a chain to a trusted CA but are not trusted themselves.
+const ISSUER_CERTS_DIR: &str = "issuers";
+/// The directory holding certificate revocation lists
+const CRL_DIR: &str = "crl";
+
+/// Settings for renewing the application instance certificate automatically before it expires.
+/// Only a self-signed certificate is renewed since one issued by a CA must be renewed by the CA.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct CertificateRenewal {
+    /// Renew the certificate automatically
+    pub enabled: bool,
+    /// Renew the certificate when it expires within this number of days
+    pub renew_before_days: u32,
+}
+
+impl Default for CertificateRenewal {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            renew_before_days: 30,
+        }
+    }
+}
+
+/// The decision of a [`CertificateApprover`] on a certificate that is not trusted
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CertificateApproval {
+    /// Trust the certificate for this connection only
+    ApproveOnce,
+    /// Trust the certificate and store it in the trusted directory so it is trusted from now on
+    ApprovePermanently,
+    /// Do not trust the certificate and store it in the rejected directory
+    Reject,
+}
+
+/// A function that decides whether to trust an unknown certificate, e.g. by asking a user to
+/// confirm its thumbprint. It is called with the certificate and its validation result, i.e.
+/// `BadCertificateUntrusted` if being unknown is all that is wrong with it, or the reason it is
+/// invalid regardless of trust.
+///
+/// The decision is awaited without the certificate store being locked, so other validations go
+/// on while the approver waits for a user.
+pub type CertificateApprover =
+    Arc<dyn Fn(X509, StatusCode) -> BoxFuture<'static, CertificateApproval> + Send + Sync>;
+
+/// The outcome of validating a cert before the certificate approver is asked
+pub(crate) enum CertificateValidation {
+    /// The cert is valid or not without asking the approver
+    Done(StatusCode),
+    /// The cert is unknown and the approver must decide on it. Holds the status it is asked with.
+    Unknown(CertificateApprover, StatusCode),
+}
+
+/// The certs the certificate approver is deciding on or has approved once
+#[derive(Default)]
+struct Approvals {
+    /// File names of the certs the approver has been asked about and not yet decided on
+    pending: HashSet<String>,
+    /// File names of the certs approved once after their validation had already failed, so the
+    /// next validation of each succeeds
+    approved_once: HashSet<String>,
+}
 
 /// The certificate store manages the storage of a server/client's own certificate & private key
 /// and the trust / rejection of certificates from the other end.
@@ -48,6 +123,17 @@ pub struct CertificateStore {
     /// into the trusted folder if this flag is set. Certs in the trusted folder must still pass
     /// validity checks.
     trust_unknown_certs: bool,
+    /// Passphrase of the applications own private key if it is encrypted. When set, a newly
+    /// created private key is also written encrypted.
+    private_key_passphrase: Option<PrivateKeyPassphrase>,
+    /// Decides whether to trust an unknown certificate instead of rejecting it. It is not called
+    /// if unknown certs are trusted anyway.
+    certificate_approver: Option<CertificateApprover>,
+    /// The certs the certificate approver is deciding on or has approved once
+    approvals: Mutex<Approvals>,
+    /// Restricts the security policies that certificates may be validated for, and the key
+    /// lengths and signature algorithms of those certificates
+    security_profile: SecurityProfile,
 }
 
 impl CertificateStore {
@@ -62,6 +148,10 @@ impl CertificateStore {
             check_time: true,
             skip_verify_certs: false,
             trust_unknown_certs: false,
+            private_key_passphrase: None,
+            certificate_approver: None,
+            approvals: Mutex::new(Approvals::default()),
+            security_profile: SecurityProfile::default(),
         }
     }
 
@@ -70,12 +160,14 @@ impl CertificateStore {
         overwrite: bool,
         cert_path: Option<&Path>,
         pkey_path: Option<&Path>,
+        pkey_passphrase: Option<PrivateKeyPassphrase>,
         x509_data: Option<X>,
     ) -> (CertificateStore, Option<X509>, Option<PrivateKey>)
     where
         X: Into<X509Data>,
     {
         let mut certificate_store = CertificateStore::new(pki_path);
+        certificate_store.private_key_passphrase = pkey_passphrase;
         if let (Some(cert_path), Some(pkey_path)) = (cert_path, pkey_path) {
             certificate_store.own_certificate_path = cert_path.to_path_buf();
             certificate_store.own_private_key_path = pkey_path.to_path_buf();
@@ -118,19 +210,89 @@ impl CertificateStore {
         self.trust_unknown_certs = trust_unknown_certs;
     }
 
+    pub fn set_security_profile(&mut self, security_profile: SecurityProfile) {
+        self.security_profile = security_profile;
+    }
+
+    /// Sets a function that decides whether to trust an unknown certificate instead of it being
+    /// rejected. The function is not called for a certificate in the rejected directory.
+    pub fn set_certificate_approver<F, Fut>(&mut self, approver: F)
+    where
+        F: Fn(X509, StatusCode) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CertificateApproval> + Send + 'static,
+    {
+        self.certificate_approver = Some(Arc::new(move |cert, status_code| {
+            Box::pin(approver(cert, status_code))
+        }));
+    }
+
+    /// Sets a function that decides whether to trust an unknown certificate like
+    /// [`CertificateStore::set_certificate_approver`], for a function that blocks, e.g. on a
+    /// prompt in the terminal. It runs on the blocking thread pool of the runtime.
+    pub fn set_blocking_certificate_approver<F>(&mut self, approver: F)
+    where
+        F: Fn(&X509, StatusCode) -> CertificateApproval + Send + Sync + 'static,
+    {
+        let approver = Arc::new(approver);
+        self.set_certificate_approver(move |cert, status_code| {
+            let approver = approver.clone();
+            async move {
+                tokio::task::spawn_blocking(move || approver(&cert, status_code))
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Certificate approver failed, {}", e);
+                        CertificateApproval::Reject
+                    })
+            }
+        });
+    }
+
+    /// Removes the certificate approver so unknown certificates are rejected
+    pub fn clear_certificate_approver(&mut self) {
+        self.certificate_approver = None;
+    }
+
     pub fn set_check_time(&mut self, check_time: bool) {
         self.check_time = check_time;
     }
 
+    pub fn set_private_key_passphrase(&mut self, passphrase: Option<PrivateKeyPassphrase>) {
+        self.private_key_passphrase = passphrase;
+    }
+
     /// Reads a private key from a path on disk.
     pub fn read_pkey(path: &Path) -> Result<PrivateKey, String> {
+        Self::read_pkey_with_passphrase(path, None)
+    }
+
+    /// Reads a private key from a path on disk. An encrypted PKCS#8 key is decrypted with the
+    /// passphrase, which must be supplied for such a key.
+    pub fn read_pkey_with_passphrase(
+        path: &Path,
+        passphrase: Option<&PrivateKeyPassphrase>,
+    ) -> Result<PrivateKey, String> {
         if let Ok(pkey_info) = metadata(path) {
-            if let Ok(mut f) = File::open(&path) {
+            if let Ok(mut f) = File::open(path) {
                 let mut buffer = Vec::with_capacity(pkey_info.len() as usize);
                 let _ = f.read_to_end(&mut buffer);
                 drop(f);
-                if let Ok(pkey) = pkey::PKey::private_key_from_pem(&buffer) {
-                    return Ok(PrivateKey::wrap_private_key(pkey));
+                if PrivateKey::is_encrypted_pem(&buffer) {
+                    let Some(passphrase) = passphrase else {
+                        return Err(format!(
+                            "Private key at path {:?} is encrypted but no passphrase was supplied",
+                            path
+                        ));
+                    };
+                    let passphrase = passphrase.passphrase()?;
+                    return PrivateKey::from_pem_with_passphrase(&buffer, passphrase.as_bytes())
+                        .map_err(|_| {
+                            format!(
+                                "Cannot decrypt pkey from path {:?}, the passphrase may be wrong",
+                                path
+                            )
+                        });
+                } else if let Ok(pkey) = PrivateKey::from_pem(&buffer) {
+                    return Ok(pkey);
                 }
             }
         }
@@ -140,14 +302,11 @@ impl CertificateStore {
     /// Reads the store's own certificate and private key
     pub fn read_own_cert_and_pkey(&self) -> Result<(X509, PrivateKey), String> {
         if let Ok(cert) = CertificateStore::read_cert(&self.own_certificate_path()) {
-            CertificateStore::read_pkey(&self.own_private_key_path())
-                .map(|pkey| (cert, pkey))
-                .map_err(|_| {
-                    format!(
-                        "Cannot read pkey from path {:?}",
-                        self.own_private_key_path()
-                    )
-                })
+            CertificateStore::read_pkey_with_passphrase(
+                &self.own_private_key_path(),
+                self.private_key_passphrase.as_ref(),
+            )
+            .map(|pkey| (cert, pkey))
         } else {
             Err(format!(
                 "Cannot read cert from path {:?}",
@@ -172,16 +331,60 @@ impl CertificateStore {
         cert_path: &Path,
         pkey_path: &Path,
     ) -> Result<(X509, PrivateKey), String> {
+        Self::create_certificate_and_key_with_passphrase(
+            args, overwrite, cert_path, pkey_path, None,
+        )
+    }
+
+    /// Create a certificate and key pair to the specified locations. If a passphrase is supplied
+    /// the private key is written as an encrypted PKCS#8 PEM.
+    pub fn create_certificate_and_key_with_passphrase(
+        args: &X509Data,
+        overwrite: bool,
+        cert_path: &Path,
+        pkey_path: &Path,
+        passphrase: Option<&PrivateKeyPassphrase>,
+    ) -> Result<(X509, PrivateKey), String> {
+        // Resolve the passphrase first so nothing is written if it is unavailable
+        if let Some(passphrase) = passphrase {
+            let _ = passphrase.passphrase()?;
+        }
         let (cert, pkey) = X509::cert_and_pkey(args)?;
+        CertificateStore::write_certificate_and_key(
+            &cert, &pkey, overwrite, cert_path, pkey_path, passphrase,
+        )?;
+        Ok((cert, pkey))
+    }
+
+    /// Writes an existing certificate and key pair to the specified locations, e.g. ones issued
+    /// by a [`CertificateAuthority`]. If a passphrase is supplied the private key is written as an
+    /// encrypted PKCS#8 PEM.
+    ///
+    /// [`CertificateAuthority`]: super::ca::CertificateAuthority
+    pub fn write_certificate_and_key(
+        cert: &X509,
+        pkey: &PrivateKey,
+        overwrite: bool,
+        cert_path: &Path,
+        pkey_path: &Path,
+        passphrase: Option<&PrivateKeyPassphrase>,
+    ) -> Result<(), String> {
+        // Resolve the passphrase first so nothing is written if it is unavailable
+        let passphrase = passphrase.map(|p| p.passphrase()).transpose()?;
 
         // Write the public cert
-        let _ = CertificateStore::store_cert(&cert, cert_path, overwrite)?;
+        let _ = CertificateStore::store_cert(cert, cert_path, overwrite)?;
 
         // Write the private key
-        let pem = pkey.private_key_to_pem().unwrap();
+        let pem = if let Some(passphrase) = passphrase {
+            pkey.private_key_to_encrypted_pem(passphrase.as_bytes())
+        } else {
+            pkey.private_key_to_pem()
+        }
+        .map_err(|_| "Cannot encode private key".to_string())?;
         info!("Writing private key to {}", &pkey_path.display());
         let _ = CertificateStore::write_to_file(&pem, pkey_path, overwrite)?;
-        Ok((cert, pkey))
+        Ok(())
     }
 
     /// This function will use the supplied arguments to create an Application Instance Certificate
@@ -192,16 +395,84 @@ impl CertificateStore {
         args: &X509Data,
         overwrite: bool,
     ) -> Result<(X509, PrivateKey), String> {
-        CertificateStore::create_certificate_and_key(
+        CertificateStore::create_certificate_and_key_with_passphrase(
             args,
             overwrite,
             &self.own_certificate_path(),
             &self.own_private_key_path(),
+            self.private_key_passphrase.as_ref(),
         )
     }
 
+    /// Renews the application instance certificate and private key if the certificate expires
+    /// within the supplied number of days. The new certificate has the same subject, application
+    /// uri, alt host names, key size and duration as the old one. The old certificate and key are
+    /// kept beside the new ones with the old thumbprint in their file names.
+    ///
+    /// Returns the new certificate and key, or `None` if the certificate does not need renewing.
+    ///
+    /// # Errors
+    ///
+    /// A string description of any failure
+    ///
+    pub fn renew_application_instance_cert(
+        &self,
+        renew_before_days: u32,
+    ) -> Result<Option<(X509, PrivateKey)>, String> {
+        let cert_path = self.own_certificate_path();
+        let pkey_path = self.own_private_key_path();
+        let cert = CertificateStore::read_cert(&cert_path)?;
+        if !cert.expires_within(&chrono::Utc::now(), renew_before_days) {
+            return Ok(None);
+        }
+        if !cert.is_self_signed() {
+            warn!("Application instance certificate expires soon but it is not self-signed so it must be renewed by its issuer");
+            return Ok(None);
+        }
+        let args = cert.to_x509_data().map_err(|_| {
+            format!(
+                "Cannot read the subject and alt host names of cert {}",
+                cert_path.display()
+            )
+        })?;
+
+        // Keep the old cert and key
+        let thumbprint = cert.thumbprint().as_hex_string();
+        for path in [&cert_path, &pkey_path] {
+            let retired_path = Self::retired_path(path, &thumbprint);
+            std::fs::copy(path, &retired_path).map_err(|_| {
+                format!(
+                    "Cannot copy {} to {}",
+                    path.display(),
+                    retired_path.display()
+                )
+            })?;
+        }
+
+        info!(
+            "Renewing application instance certificate {} which expires {:?}",
+            thumbprint,
+            cert.not_after()
+        );
+        self.create_and_store_application_instance_cert(&args, true)
+            .map(Some)
+    }
+
+    /// Returns the path that a renewed cert or key is kept at, e.g. "own/cert [thumbprint].der"
+    fn retired_path(path: &Path, thumbprint: &str) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = if let Some(extension) = path.extension() {
+            format!("{} [{}].{}", stem, thumbprint, extension.to_string_lossy())
+        } else {
+            format!("{} [{}]", stem, thumbprint)
+        };
+        path.with_file_name(file_name)
+    }
+
     /// Validates the cert as trusted and valid. If the cert is unknown, it will be written to
     /// the rejected folder so that the administrator can manually move it to the trusted folder.
+    /// The certificate approver is not asked, use
+    /// [`CertificateStore::validate_or_approve_application_instance_cert`] for that.
     ///
     /// # Errors
     ///
@@ -215,17 +486,93 @@ impl CertificateStore {
         hostname: Option<&str>,
         application_uri: Option<&str>,
     ) -> StatusCode {
-        let result = self.validate_application_instance_cert(
+        match self.check_or_reject_application_instance_cert(
             cert,
             security_policy,
             hostname,
             application_uri,
-        );
+        ) {
+            CertificateValidation::Done(status_code) => status_code,
+            // The cert is left for the approver to decide on later
+            CertificateValidation::Unknown(..) => StatusCode::BadCertificateUntrusted,
+        }
+    }
+
+    /// Validates the cert like [`CertificateStore::validate_or_reject_application_instance_cert`],
+    /// but if the cert is unknown the certificate approver decides whether to trust it. The store
+    /// is not locked while the approver decides.
+    pub async fn validate_or_approve_application_instance_cert(
+        certificate_store: &RwLock<CertificateStore>,
+        cert: &X509,
+        security_policy: SecurityPolicy,
+        hostname: Option<&str>,
+        application_uri: Option<&str>,
+    ) -> StatusCode {
+        let (approver, status_code) = {
+            let certificate_store = trace_read_lock!(certificate_store);
+            match certificate_store.check_or_reject_application_instance_cert(
+                cert,
+                security_policy,
+                hostname,
+                application_uri,
+            ) {
+                CertificateValidation::Done(status_code) => return status_code,
+                CertificateValidation::Unknown(approver, status_code) => (approver, status_code),
+            }
+        };
+        let cert_file_name = CertificateStore::cert_file_name(cert);
+        if !trace_read_lock!(certificate_store).start_approval(&cert_file_name) {
+            return StatusCode::BadCertificateUntrusted;
+        }
+        let approval = approver(cert.clone(), status_code).await;
+        let certificate_store = trace_read_lock!(certificate_store);
+        certificate_store.finish_approval(cert, &cert_file_name, approval, status_code, false)
+    }
+
+    /// Asks the certificate approver about an unknown cert whose validation has already failed.
+    /// The decision applies to the next validation of the cert, e.g. when the peer retries.
+    pub(crate) async fn approve_later(
+        certificate_store: Arc<RwLock<CertificateStore>>,
+        approver: CertificateApprover,
+        cert: X509,
+        status_code: StatusCode,
+    ) {
+        let cert_file_name = CertificateStore::cert_file_name(&cert);
+        if !trace_read_lock!(certificate_store).start_approval(&cert_file_name) {
+            return;
+        }
+        let approval = approver(cert.clone(), status_code).await;
+        let certificate_store = trace_read_lock!(certificate_store);
+        let _ =
+            certificate_store.finish_approval(&cert, &cert_file_name, approval, status_code, true);
+    }
+
+    /// Validates the cert and writes it to the rejected folder if it fails, unless it is unknown
+    /// and the certificate approver must decide on it.
+    pub(crate) fn check_or_reject_application_instance_cert(
+        &self,
+        cert: &X509,
+        security_policy: SecurityPolicy,
+        hostname: Option<&str>,
+        application_uri: Option<&str>,
+    ) -> CertificateValidation {
+        let validation =
+            self.check_application_instance_cert(cert, security_policy, hostname, application_uri);
+        if let CertificateValidation::Done(result) = validation {
+            self.reject_if_bad(cert, result);
+        }
+        validation
+    }
+
+    /// Writes a cert that failed validation to the rejected folder, unless it failed for reasons
+    /// that are not down to the cert
+    fn reject_if_bad(&self, cert: &X509, result: StatusCode) {
         if result.is_bad() {
             match result {
-                StatusCode::BadUnexpectedError | StatusCode::BadSecurityChecksFailed => {
-                    /* DO NOTHING */
-                }
+                StatusCode::BadUnexpectedError
+                | StatusCode::BadSecurityChecksFailed
+                | StatusCode::BadSecurityPolicyRejected
+                | StatusCode::BadCertificatePolicyCheckFailed => { /* DO NOTHING */ }
                 _ => {
                     // Store result in rejected folder
                     // TODO this appears to be redundant if cert is already in rejected dir
@@ -233,7 +580,6 @@ impl CertificateStore {
                 }
             }
         }
-        result
     }
 
     /// Ensures that the cert provided is the same as the one specified by a path. This is a
@@ -279,9 +625,34 @@ impl CertificateStore {
         hostname: Option<&str>,
         application_uri: Option<&str>,
     ) -> StatusCode {
+        match self.check_application_instance_cert(cert, security_policy, hostname, application_uri)
+        {
+            CertificateValidation::Done(status_code) => status_code,
+            CertificateValidation::Unknown(..) => StatusCode::BadCertificateUntrusted,
+        }
+    }
+
+    /// Validates the certificate, or if it is unknown and there is a certificate approver, leaves
+    /// it for the approver to decide on
+    fn check_application_instance_cert(
+        &self,
+        cert: &X509,
+        security_policy: SecurityPolicy,
+        hostname: Option<&str>,
+        application_uri: Option<&str>,
+    ) -> CertificateValidation {
         let cert_file_name = CertificateStore::cert_file_name(cert);
         debug!("Validating cert with name on disk {}", cert_file_name);
 
+        // The security profile may forbid the policy, e.g. because it is deprecated
+        if !self.security_profile.is_policy_allowed(security_policy) {
+            warn!(
+                "Certificate {} cannot be validated because the security profile does not allow the policy {}",
+                cert_file_name, security_policy
+            );
+            return CertificateValidation::Done(StatusCode::BadSecurityPolicyRejected);
+        }
+
         // Look for the cert in the rejected folder. If it's rejected there is no purpose going
         // any further
         {
@@ -291,7 +662,7 @@ impl CertificateStore {
                     "Path for rejected certificates {} does not exist",
                     cert_path.display()
                 );
-                return StatusCode::BadUnexpectedError;
+                return CertificateValidation::Done(StatusCode::BadUnexpectedError);
             }
             cert_path.push(&cert_file_name);
             if cert_path.exists() {
@@ -299,7 +670,7 @@ impl CertificateStore {
                     "Certificate {} is untrusted because it resides in the rejected directory",
                     cert_file_name
                 );
-                return StatusCode::BadSecurityChecksFailed;
+                return CertificateValidation::Done(StatusCode::BadSecurityChecksFailed);
             }
         }
 
@@ -313,100 +684,336 @@ impl CertificateStore {
                     "Path for rejected certificates {} does not exist",
                     cert_path.display()
                 );
-                return StatusCode::BadUnexpectedError;
+                return CertificateValidation::Done(StatusCode::BadUnexpectedError);
             }
             cert_path.push(&cert_file_name);
 
-            // Check if cert is in the trusted folder
-            if !cert_path.exists() {
-                // ... trust checks based on ca could be added here to add cert straight to trust folder
+            // Check if cert is in the trusted folder, or was issued by a CA that is
+            let issued_by_trusted_ca = !cert_path.exists() && self.is_issued_by_trusted_ca(cert);
+            if issued_by_trusted_ca {
+                info!(
+                    "Certificate {} is trusted because it was issued by a trusted certificate authority",
+                    cert_file_name
+                );
+            } else if !cert_path.exists() {
                 if self.trust_unknown_certs {
                     // Put the unknown cert into the trusted folder
                     warn!("Certificate {} is unknown but policy will store it into the trusted directory", cert_file_name);
                     let _ = self.store_trusted_cert(cert);
                 // Note that we drop through and still check the cert for validity
+                } else if let Some(ref approver) = self.certificate_approver {
+                    let approved_once = trace_lock!(self.approvals)
+                        .approved_once
+                        .remove(&cert_file_name);
+                    let status_code = self.verify_trusted_cert(
+                        cert,
+                        &cert_file_name,
+                        security_policy,
+                        hostname,
+                        application_uri,
+                    );
+                    if approved_once {
+                        info!(
+                            "Certificate {} was approved for one more connection",
+                            cert_file_name
+                        );
+                        return CertificateValidation::Done(status_code);
+                    }
+                    // Being unknown is all that is wrong with the cert if it would otherwise
+                    // be valid
+                    let status_code = if status_code.is_good() {
+                        StatusCode::BadCertificateUntrusted
+                    } else {
+                        status_code
+                    };
+                    return CertificateValidation::Unknown(approver.clone(), status_code);
                 } else {
                     warn!("Certificate {} is unknown and untrusted so it will be stored in rejected directory", cert_file_name);
                     let _ = self.store_rejected_cert(cert);
-                    return StatusCode::BadCertificateUntrusted;
+                    return CertificateValidation::Done(StatusCode::BadCertificateUntrusted);
                 }
             }
 
             // Read the cert from the trusted folder to make sure it matches the one supplied
-            if !CertificateStore::ensure_cert_and_file_are_the_same(cert, &cert_path) {
+            if !issued_by_trusted_ca
+                && !CertificateStore::ensure_cert_and_file_are_the_same(cert, &cert_path)
+            {
                 error!("Certificate in memory does not match the one on disk {} so cert will automatically be treated as untrusted", cert_path.display());
-                return StatusCode::BadUnexpectedError;
+                return CertificateValidation::Done(StatusCode::BadUnexpectedError);
             }
 
-            // Check that the certificate is the right length for the security policy
-            match cert.key_length() {
-                Err(_) => {
-                    error!("Cannot read key length from certificate {}", cert_file_name);
+            CertificateValidation::Done(self.verify_trusted_cert(
+                cert,
+                &cert_file_name,
+                security_policy,
+                hostname,
+                application_uri,
+            ))
+        }
+    }
+
+    /// Verifies a cert that is trusted, or would be if it were approved. Checks that it has not
+    /// been revoked and has a valid key length, and unless verification is skipped, checks its
+    /// validity period, hostname and application uri.
+    fn verify_trusted_cert(
+        &self,
+        cert: &X509,
+        cert_file_name: &str,
+        security_policy: SecurityPolicy,
+        hostname: Option<&str>,
+        application_uri: Option<&str>,
+    ) -> StatusCode {
+        // A trusted cert can still have been revoked by its issuer
+        let revocation_status = self.revocation_status(cert);
+        if revocation_status == StatusCode::BadCertificateRevoked {
+            warn!(
+                "Certificate {} has been revoked by its issuer",
+                cert_file_name
+            );
+            return revocation_status;
+        } else if revocation_status.is_bad() {
+            warn!(
+                "Certificate {} cannot be checked for revocation because the CRL of its issuer has expired",
+                cert_file_name
+            );
+            return revocation_status;
+        }
+
+        // Check that the certificate is the right length for the security policy
+        match cert.key_length() {
+            Err(_) => {
+                error!("Cannot read key length from certificate {}", cert_file_name);
+                return StatusCode::BadSecurityChecksFailed;
+            }
+            Ok(key_length) => {
+                if !self
+                    .security_profile
+                    .is_valid_key_length(security_policy, key_length)
+                {
+                    warn!(
+                        "Certificate {} has an invalid key length {} for the policy {}",
+                        cert_file_name, key_length, security_policy
+                    );
                     return StatusCode::BadSecurityChecksFailed;
                 }
-                Ok(key_length) => {
-                    if !security_policy.is_valid_keylength(key_length) {
-                        warn!(
-                            "Certificate {} has an invalid key length {} for the policy {}",
-                            cert_file_name, key_length, security_policy
-                        );
-                        return StatusCode::BadSecurityChecksFailed;
-                    }
-                }
             }
+        }
+
+        // Check that the certificate is signed with an algorithm the policy accepts
+        let signature_hash = cert.signature_hash();
+        if !security_policy.is_valid_certificate_signature_hash(signature_hash) {
+            warn!(
+                "Certificate {} is signed with a {:?} hash that is invalid for the policy {}",
+                cert_file_name, signature_hash, security_policy
+            );
+            return StatusCode::BadCertificatePolicyCheckFailed;
+        }
+
+        if self.skip_verify_certs {
+            debug!(
+                "Skipping additional verifications for certificate {}",
+                cert_file_name
+            );
+            return StatusCode::Good;
+        }
 
-            if self.skip_verify_certs {
-                debug!(
-                    "Skipping additional verifications for certificate {}",
+        // Now inspect the cert not before / after values to ensure its validity
+        if self.check_time {
+            use chrono::Utc;
+            let now = Utc::now();
+            let status_code = cert.is_time_valid(&now);
+            if status_code.is_bad() {
+                warn!(
+                    "Certificate {} is not valid for now, check start/end timestamps",
                     cert_file_name
                 );
-                return StatusCode::Good;
+                return status_code;
             }
+        }
 
-            // Now inspect the cert not before / after values to ensure its validity
-            if self.check_time {
-                use chrono::Utc;
-                let now = Utc::now();
-                let status_code = cert.is_time_valid(&now);
-                if status_code.is_bad() {
-                    warn!(
-                        "Certificate {} is not valid for now, check start/end timestamps",
-                        cert_file_name
-                    );
-                    return status_code;
-                }
+        // Compare the hostname of the cert against the cert supplied
+        if let Some(hostname) = hostname {
+            let status_code = cert.is_hostname_valid(hostname);
+            if status_code.is_bad() {
+                warn!(
+                    "Certificate {} does not have a valid hostname",
+                    cert_file_name
+                );
+                return status_code;
             }
+        }
 
-            // Compare the hostname of the cert against the cert supplied
-            if let Some(hostname) = hostname {
-                let status_code = cert.is_hostname_valid(hostname);
-                if status_code.is_bad() {
-                    warn!(
-                        "Certificate {} does not have a valid hostname",
-                        cert_file_name
-                    );
-                    return status_code;
+        // Compare the application / product uri to the supplied application description
+        if let Some(application_uri) = application_uri {
+            let status_code = cert.is_application_uri_valid(application_uri);
+            if status_code.is_bad() {
+                warn!(
+                    "Certificate {} does not have a valid application uri",
+                    cert_file_name
+                );
+                return status_code;
+            }
+        }
+        StatusCode::Good
+    }
+
+    /// Notes that the certificate approver is being asked about a cert. Returns `false` if it is
+    /// already deciding on the cert, so it is not asked twice.
+    fn start_approval(&self, cert_file_name: &str) -> bool {
+        let inserted = trace_lock!(self.approvals)
+            .pending
+            .insert(cert_file_name.to_string());
+        if !inserted {
+            warn!(
+                "Certificate {} is untrusted while the certificate approver decides on it",
+                cert_file_name
+            );
+        }
+        inserted
+    }
+
+    /// Acts on the decision of the certificate approver about a cert and returns its validation
+    /// result. Approval does not override the reason an otherwise invalid cert is invalid, so only
+    /// a cert whose status is `BadCertificateUntrusted` is trusted. When the validation of the
+    /// cert has already failed, a cert approved once is trusted for its next validation.
+    fn finish_approval(
+        &self,
+        cert: &X509,
+        cert_file_name: &str,
+        approval: CertificateApproval,
+        status_code: StatusCode,
+        validation_failed: bool,
+    ) -> StatusCode {
+        let mut approvals = trace_lock!(self.approvals);
+        approvals.pending.remove(cert_file_name);
+        match approval {
+            CertificateApproval::ApproveOnce | CertificateApproval::ApprovePermanently
+                if status_code != StatusCode::BadCertificateUntrusted =>
+            {
+                warn!(
+                    "Certificate {} is approved but is still invalid, {}",
+                    cert_file_name, status_code
+                );
+                status_code
+            }
+            CertificateApproval::ApproveOnce => {
+                info!(
+                    "Certificate {} is approved for this connection",
+                    cert_file_name
+                );
+                if validation_failed {
+                    approvals.approved_once.insert(cert_file_name.to_string());
                 }
+                StatusCode::Good
             }
+            CertificateApproval::ApprovePermanently => {
+                info!(
+                    "Certificate {} is approved and will be stored in the trusted directory",
+                    cert_file_name
+                );
+                let _ = self.store_trusted_cert(cert);
+                StatusCode::Good
+            }
+            CertificateApproval::Reject => {
+                warn!(
+                    "Certificate {} is not approved so it will be stored in rejected directory",
+                    cert_file_name
+                );
+                let _ = self.store_rejected_cert(cert);
+                StatusCode::BadCertificateUntrusted
+            }
+        }
+    }
 
-            // Compare the application / product uri to the supplied application description
-            if let Some(application_uri) = application_uri {
-                let status_code = cert.is_application_uri_valid(application_uri);
-                if status_code.is_bad() {
-                    warn!(
-                        "Certificate {} does not have a valid application uri",
-                        cert_file_name
-                    );
-                    return status_code;
+    /// Tests if the cert was issued by a certificate authority whose certificate is in the trusted
+    /// folder, either directly or through intermediate CAs in the issuers folder. Only certs that
+    /// are CAs can issue, so a trusted application instance cert cannot vouch for other certs.
+    fn is_issued_by_trusted_ca(&self, cert: &X509) -> bool {
+        let trusted_certs = CertificateStore::read_certs_in_dir(&self.trusted_certs_dir())
+            .into_iter()
+            .filter(|trusted_cert| trusted_cert.is_ca())
+            .collect::<Vec<_>>();
+        if trusted_certs.is_empty() {
+            return false;
+        }
+        let verify = || -> Result<bool, openssl::error::ErrorStack> {
+            let mut store = X509StoreBuilder::new()?;
+            for trusted_cert in trusted_certs {
+                store.add_cert(trusted_cert.value)?;
+            }
+            if !self.check_time {
+                store.set_flags(X509VerifyFlags::NO_CHECK_TIME)?;
+            }
+            let store = store.build();
+            let mut chain = Stack::new()?;
+            for issuer_cert in CertificateStore::read_certs_in_dir(&self.issuer_certs_dir()) {
+                if issuer_cert.is_ca() {
+                    chain.push(issuer_cert.value)?;
                 }
             }
+            let mut context = X509StoreContext::new()?;
+            context.init(&store, &cert.value, &chain, |c| {
+                let verified = c.verify_cert()?;
+                if !verified {
+                    debug!(
+                        "Certificate chain does not verify, {}",
+                        c.error().error_string()
+                    );
+                }
+                Ok(verified)
+            })
+        };
+        verify().unwrap_or(false)
+    }
 
-            // Other tests that we might do with trust lists
-            // ... issuer
-            // ... trust (self-signed, ca etc.)
-            // ... revocation
+    /// Checks the cert against the CRLs in the crl folder that were issued by the cert's issuer.
+    /// Returns `BadCertificateRevoked` if it is on one of them, or `BadCertificateRevocationUnknown`
+    /// if one of them is past its next update, since the cert may have been revoked since.
+    fn revocation_status(&self, cert: &X509) -> StatusCode {
+        let crls = self.read_crls();
+        if crls.is_empty() {
+            return StatusCode::Good;
+        }
+        let mut issuer_certs = CertificateStore::read_certs_in_dir(&self.trusted_certs_dir());
+        issuer_certs.extend(CertificateStore::read_certs_in_dir(
+            &self.issuer_certs_dir(),
+        ));
+        let issuer_crls = issuer_certs
+            .iter()
+            .filter(|issuer| issuer.value.issued(&cert.value) == x509::X509VerifyResult::OK)
+            .flat_map(|issuer| crls.iter().filter(|crl| crl.is_issued_by(issuer)))
+            .collect::<Vec<_>>();
+        if issuer_crls.iter().any(|crl| crl.is_revoked(cert)) {
+            StatusCode::BadCertificateRevoked
+        } else if self.check_time && issuer_crls.iter().any(|crl| crl.is_expired()) {
+            StatusCode::BadCertificateRevocationUnknown
+        } else {
+            StatusCode::Good
+        }
+    }
+
+    /// Reads every certificate in a directory, skipping any file that is not a certificate
+    fn read_certs_in_dir(dir: &Path) -> Vec<X509> {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| CertificateStore::read_cert(&entry.path()).ok())
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Reads every CRL in the crl folder, skipping any file that is not a CRL
+    fn read_crls(&self) -> Vec<Crl> {
+        if let Ok(entries) = std::fs::read_dir(self.crl_dir()) {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| CertificateStore::read_crl(&entry.path()).ok())
+                .collect()
+        } else {
+            Vec::new()
         }
-        StatusCode::Good
     }
 
     /// Returns a certificate file name from the cert's issuer and thumbprint fields.
@@ -435,7 +1042,12 @@ impl CertificateStore {
     ///
     pub fn ensure_pki_path(&self) -> Result<(), String> {
         let mut path = self.pki_path.clone();
-        let subdirs = [TRUSTED_CERTS_DIR, REJECTED_CERTS_DIR];
+        let subdirs = [
+            TRUSTED_CERTS_DIR,
+            REJECTED_CERTS_DIR,
+            ISSUER_CERTS_DIR,
+            CRL_DIR,
+        ];
         for subdir in &subdirs {
             path.push(subdir);
             CertificateStore::ensure_dir(&path)?;
@@ -491,6 +1103,20 @@ impl CertificateStore {
         path
     }
 
+    /// Get the path to the issuer certs dir
+    pub fn issuer_certs_dir(&self) -> PathBuf {
+        let mut path = PathBuf::from(&self.pki_path);
+        path.push(ISSUER_CERTS_DIR);
+        path
+    }
+
+    /// Get the path to the certificate revocation lists dir
+    pub fn crl_dir(&self) -> PathBuf {
+        let mut path = PathBuf::from(&self.pki_path);
+        path.push(CRL_DIR);
+        path
+    }
+
     /// Write a cert to the rejected directory. If the write succeeds, the function
     /// returns a path to the written file.
     ///
@@ -507,6 +1133,60 @@ impl CertificateStore {
         Ok(cert_path)
     }
 
+    /// Returns the certs in the rejected directory with their file names, sorted by file name.
+    /// Files that cannot be read as certs are skipped.
+    pub fn rejected_certs(&self) -> Vec<(String, X509)> {
+        let mut certs = if let Ok(entries) = std::fs::read_dir(self.rejected_certs_dir()) {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    CertificateStore::read_cert(&entry.path())
+                        .ok()
+                        .map(|cert| (file_name, cert))
+                })
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        certs.sort_by(|a, b| a.0.cmp(&b.0));
+        certs
+    }
+
+    /// Moves a cert from the rejected directory to the trusted directory, so it is trusted when
+    /// it is next presented. If the move succeeds, the function returns a path to the trusted file.
+    ///
+    /// # Errors
+    ///
+    /// A string description of any failure
+    ///
+    pub fn trust_rejected_cert(&self, file_name: &str) -> Result<PathBuf, String> {
+        // The file name must not escape the rejected directory
+        if file_name.is_empty()
+            || file_name.contains(['/', '\\'])
+            || file_name == "."
+            || file_name == ".."
+        {
+            return Err(format!("Invalid certificate file name {}", file_name));
+        }
+        let mut rejected_path = self.rejected_certs_dir();
+        rejected_path.push(file_name);
+        let cert = CertificateStore::read_cert(&rejected_path)?;
+        let trusted_path = self.store_trusted_cert(&cert)?;
+        if let Err(err) = std::fs::remove_file(&rejected_path) {
+            warn!(
+                "Cannot remove approved certificate {} from the rejected directory, {}",
+                rejected_path.display(),
+                err
+            );
+        }
+        info!(
+            "Rejected certificate {} is now trusted",
+            rejected_path.display()
+        );
+        Ok(trusted_path)
+    }
+
     /// Writes a cert to the trusted directory. If the write succeeds, the function
     /// returns a path to the written file.
     ///
@@ -572,6 +1252,35 @@ impl CertificateStore {
         Ok(X509::from(cert.unwrap()))
     }
 
+    /// Writes a CRL in DER format to disk, replacing any previous version of it
+    ///
+    /// # Errors
+    ///
+    /// A string description of any failure
+    ///
+    pub fn write_crl(crl: &Crl, path: &Path) -> Result<usize, String> {
+        let der = crl.to_der().map_err(|_| "Cannot encode CRL".to_string())?;
+        info!("Writing CRL to {}", path.display());
+        CertificateStore::write_to_file(&der, path, true)
+    }
+
+    /// Reads a CRL in .crl (DER) or .pem format from disk
+    ///
+    /// # Errors
+    ///
+    /// A string description of any failure
+    ///
+    pub fn read_crl(path: &Path) -> Result<Crl, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|_| format!("Could not read bytes from CRL file {}", path.display()))?;
+        match path.extension() {
+            Some(v) if v == "crl" || v == "der" => Crl::from_der(&bytes),
+            Some(v) if v == "pem" => Crl::from_pem(&bytes),
+            _ => return Err("Only .crl, .der and .pem CRLs are supported".to_string()),
+        }
+        .map_err(|_| format!("Could not read CRL from file {}", path.display()))
+    }
+
     /// Writes bytes to file and returns the size written, or an error reason for failure.
     ///
     /// # Errors
@@ -0,0 +1,181 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Certificate revocation lists, i.e. the certificates that a certificate authority has revoked
+//! before their expiry.
+
+use std::fmt::{self, Debug, Formatter};
+
+use foreign_types::{ForeignType, ForeignTypeRef};
+use openssl::{
+    asn1::Asn1Time,
+    x509::{self, CrlStatus},
+};
+
+use super::{
+    pkey::PrivateKey,
+    x509::{X509Error, X509},
+};
+
+/// This is a wrapper around the `OpenSSL` `X509Crl`
+pub struct Crl {
+    pub(crate) value: x509::X509Crl,
+}
+
+impl Debug for Crl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[crl]")
+    }
+}
+
+impl Crl {
+    pub fn from_der(der: &[u8]) -> Result<Self, X509Error> {
+        x509::X509Crl::from_der(der)
+            .map(|value| Crl { value })
+            .map_err(|_| {
+                error!("Cannot produce a CRL from the data supplied");
+                X509Error
+            })
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self, X509Error> {
+        x509::X509Crl::from_pem(pem)
+            .map(|value| Crl { value })
+            .map_err(|_| {
+                error!("Cannot produce a CRL from the data supplied");
+                X509Error
+            })
+    }
+
+    pub fn to_der(&self) -> Result<Vec<u8>, X509Error> {
+        self.value.to_der().map_err(|e| {
+            error!("Cannot turn CRL to DER, err = {:?}", e);
+            X509Error
+        })
+    }
+
+    /// Tests if the CRL was issued and signed by the certificate authority of the supplied cert
+    pub fn is_issued_by(&self, issuer: &X509) -> bool {
+        let same_name = self
+            .value
+            .issuer_name()
+            .try_cmp(issuer.value.subject_name())
+            .map(|o| o.is_eq())
+            .unwrap_or(false);
+        same_name
+            && issuer
+                .value
+                .public_key()
+                .and_then(|pkey| self.value.verify(&pkey))
+                .unwrap_or(false)
+    }
+
+    /// Tests if the cert is on the list. The caller should establish that the CRL was issued by
+    /// the cert's issuer.
+    pub fn is_revoked(&self, cert: &X509) -> bool {
+        matches!(self.value.get_by_cert(&cert.value), CrlStatus::Revoked(_))
+    }
+
+    /// Tests if the CRL is past its next update, i.e. the issuer may have revoked certs since
+    /// that are not on it. A CRL without a next update never expires.
+    pub fn is_expired(&self) -> bool {
+        match (self.value.next_update(), Asn1Time::days_from_now(0)) {
+            (Some(next_update), Ok(now)) => next_update < now,
+            (Some(_), Err(_)) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Returns the serial numbers of the revoked certs as hex strings
+    pub fn revoked_serial_numbers(&self) -> Vec<String> {
+        if let Some(revoked) = self.value.get_revoked() {
+            revoked
+                .iter()
+                .filter_map(|r| r.serial_number().to_bn().ok())
+                .filter_map(|bn| bn.to_hex_str().ok().map(|s| s.to_string()))
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Creates a CRL signed by the issuer holding the revoked certs of an earlier CRL plus any
+    /// newly revoked certs. The CRL is valid for the supplied number of days.
+    pub(crate) fn new_signed(
+        issuer: &X509,
+        signing_key: &PrivateKey,
+        previous: Option<&Crl>,
+        revoke: &[&X509],
+        next_update_days: u32,
+    ) -> Result<Crl, String> {
+        let err = |e: openssl::error::ErrorStack| format!("Cannot build CRL, {}", e);
+        let last_update = Asn1Time::days_from_now(0).map_err(err)?;
+        let next_update = Asn1Time::days_from_now(next_update_days).map_err(err)?;
+
+        // The openssl crate has no builder for a CRL so this calls the ffi functions directly
+        unsafe {
+            use openssl_sys::*;
+
+            let crl = X509_CRL_new();
+            if crl.is_null() {
+                return Err(err(openssl::error::ErrorStack::get()));
+            }
+            // Owning the pointer frees it on any error
+            let crl = x509::X509Crl::from_ptr(crl);
+            let ptr = crl.as_ptr();
+
+            // Version 2
+            let mut ok = X509_CRL_set_version(ptr, 1) > 0
+                && X509_CRL_set_issuer_name(ptr, issuer.value.subject_name().as_ptr()) > 0
+                && X509_CRL_set1_lastUpdate(ptr, last_update.as_ptr()) > 0
+                && X509_CRL_set1_nextUpdate(ptr, next_update.as_ptr()) > 0;
+
+            // Carry over the earlier revocations
+            if let Some(revoked) = previous.and_then(|p| p.value.get_revoked()) {
+                for r in revoked.iter() {
+                    if !ok {
+                        break;
+                    }
+                    let r = r.to_owned().map_err(err)?;
+                    ok = X509_CRL_add0_revoked(ptr, r.as_ptr()) > 0;
+                    if ok {
+                        // The CRL owns the entry now
+                        std::mem::forget(r);
+                    }
+                }
+            }
+
+            // Add the newly revoked certs unless they are already on the list
+            for cert in revoke {
+                if !ok {
+                    break;
+                }
+                if previous.map(|p| p.is_revoked(cert)).unwrap_or(false) {
+                    continue;
+                }
+                let r = X509_REVOKED_new();
+                if r.is_null() {
+                    ok = false;
+                    break;
+                }
+                ok = X509_REVOKED_set_serialNumber(r, cert.value.serial_number().as_ptr()) > 0
+                    && X509_REVOKED_set_revocationDate(r, last_update.as_ptr()) > 0
+                    && X509_CRL_add0_revoked(ptr, r) > 0;
+                if !ok {
+                    X509_REVOKED_free(r);
+                }
+            }
+
+            if ok {
+                ok = X509_CRL_sort(ptr) > 0
+                    && X509_CRL_sign(ptr, signing_key.value.as_ptr(), EVP_sha256()) > 0;
+            }
+            if ok {
+                Ok(Crl { value: crl })
+            } else {
+                Err(err(openssl::error::ErrorStack::get()))
+            }
+        }
+    }
+}
@@ -0,0 +1,334 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Decoding and verification of JSON Web Tokens (RFC 7519) that a client presents inside of an
+//! `IssuedIdentityToken`.
+//!
+//! Tokens must be signed (JWS compact serialization). Supported algorithms are HS256/384/512,
+//! RS256/384/512, PS256/384/512 and ES256/384/512. Unsigned tokens (`"alg": "none"`) are always
+//! rejected.
+
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::{
+    bn::BigNum,
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{self, Id},
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Signer, Verifier},
+    x509,
+};
+use serde_json::{Map, Value};
+
+use crate::types::status_code::StatusCode;
+
+use super::password::constant_time_eq;
+
+/// The key that a token's signature is verified against.
+pub enum JwtVerificationKey {
+    /// A public RSA or EC key for RS*, PS* and ES* signatures
+    Public(pkey::PKey<pkey::Public>),
+    /// A shared secret for HS* signatures
+    Secret(Vec<u8>),
+}
+
+impl fmt::Debug for JwtVerificationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never write out the secret
+        match self {
+            JwtVerificationKey::Public(_) => write!(f, "JwtVerificationKey::Public"),
+            JwtVerificationKey::Secret(_) => write!(f, "JwtVerificationKey::Secret"),
+        }
+    }
+}
+
+impl JwtVerificationKey {
+    /// Creates a verification key from a PEM encoded public key or X509 certificate.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, StatusCode> {
+        if let Ok(key) = pkey::PKey::public_key_from_pem(pem) {
+            Ok(JwtVerificationKey::Public(key))
+        } else if let Ok(cert) = x509::X509::from_pem(pem) {
+            cert.public_key()
+                .map(JwtVerificationKey::Public)
+                .map_err(|_| StatusCode::BadConfigurationError)
+        } else {
+            error!("JWT verification key is not a PEM public key or certificate");
+            Err(StatusCode::BadConfigurationError)
+        }
+    }
+
+    /// Creates a verification key from a shared secret.
+    pub fn from_secret(secret: &[u8]) -> Self {
+        JwtVerificationKey::Secret(secret.to_vec())
+    }
+}
+
+/// A decoded but not yet verified JSON Web Token.
+#[derive(Debug)]
+pub struct Jwt {
+    algorithm: String,
+    claims: Map<String, Value>,
+    /// The "header.payload" part of the token which the signature covers
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+impl Jwt {
+    /// Decodes the token's header and claims. Nothing is verified at this point.
+    pub fn decode(token: &str) -> Result<Jwt, StatusCode> {
+        let invalid = || {
+            error!("Issued token is not a well formed JWT");
+            StatusCode::BadIdentityTokenInvalid
+        };
+        let mut parts = token.trim().split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => return Err(invalid()),
+        };
+        let decode_json = |part: &str| -> Result<Map<String, Value>, StatusCode> {
+            let json = URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid())?;
+            match serde_json::from_slice::<Value>(&json) {
+                Ok(Value::Object(map)) => Ok(map),
+                _ => Err(invalid()),
+            }
+        };
+        let header = decode_json(header)?;
+        let claims = decode_json(payload)?;
+        let algorithm = header
+            .get("alg")
+            .and_then(|alg| alg.as_str())
+            .ok_or_else(invalid)?
+            .to_string();
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        let signing_input = token.trim()[..header_and_payload_len(token.trim())].to_string();
+        Ok(Jwt {
+            algorithm,
+            claims,
+            signing_input,
+            signature,
+        })
+    }
+
+    /// The signing algorithm from the token's header, e.g. "RS256"
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// The token's claims
+    pub fn claims(&self) -> &Map<String, Value> {
+        &self.claims
+    }
+
+    /// Verifies the token's signature with the key. The key type must suit the algorithm.
+    pub fn verify_signature(&self, key: &JwtVerificationKey) -> Result<(), StatusCode> {
+        let data = self.signing_input.as_bytes();
+        let valid = match (self.algorithm.as_str(), key) {
+            ("HS256", JwtVerificationKey::Secret(secret)) => {
+                verify_hmac(MessageDigest::sha256(), secret, data, &self.signature)
+            }
+            ("HS384", JwtVerificationKey::Secret(secret)) => {
+                verify_hmac(MessageDigest::sha384(), secret, data, &self.signature)
+            }
+            ("HS512", JwtVerificationKey::Secret(secret)) => {
+                verify_hmac(MessageDigest::sha512(), secret, data, &self.signature)
+            }
+            ("RS256", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha256(), false, key, data, &self.signature)
+            }
+            ("RS384", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha384(), false, key, data, &self.signature)
+            }
+            ("RS512", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha512(), false, key, data, &self.signature)
+            }
+            ("PS256", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha256(), true, key, data, &self.signature)
+            }
+            ("PS384", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha384(), true, key, data, &self.signature)
+            }
+            ("PS512", JwtVerificationKey::Public(key)) => {
+                verify_rsa(MessageDigest::sha512(), true, key, data, &self.signature)
+            }
+            ("ES256", JwtVerificationKey::Public(key)) => verify_ecdsa(
+                MessageDigest::sha256(),
+                Nid::X9_62_PRIME256V1,
+                32,
+                key,
+                data,
+                &self.signature,
+            ),
+            ("ES384", JwtVerificationKey::Public(key)) => verify_ecdsa(
+                MessageDigest::sha384(),
+                Nid::SECP384R1,
+                48,
+                key,
+                data,
+                &self.signature,
+            ),
+            ("ES512", JwtVerificationKey::Public(key)) => verify_ecdsa(
+                MessageDigest::sha512(),
+                Nid::SECP521R1,
+                66,
+                key,
+                data,
+                &self.signature,
+            ),
+            (algorithm, key) => {
+                error!(
+                    "JWT algorithm \"{}\" is unsupported or cannot be verified with key {:?}",
+                    algorithm, key
+                );
+                false
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            error!("JWT signature is invalid");
+            Err(StatusCode::BadIdentityTokenRejected)
+        }
+    }
+
+    /// Validates the registered claims. The issuer must match `iss`, the audience must be in `aud`
+    /// and the current time (seconds since the epoch) must be within `nbf` and `exp`, give or
+    /// take the leeway. `exp` is mandatory.
+    pub fn validate_claims(
+        &self,
+        issuer: &str,
+        audience: &str,
+        leeway: i64,
+        now: i64,
+    ) -> Result<(), StatusCode> {
+        if self.claims.get("iss").and_then(|iss| iss.as_str()) != Some(issuer) {
+            error!("JWT issuer does not match \"{}\"", issuer);
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+        let audience_valid = match self.claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(aud)) => aud.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !audience_valid {
+            error!("JWT audience does not contain \"{}\"", audience);
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+        match self.numeric_claim("exp") {
+            Some(exp) if now < exp + leeway => {}
+            Some(_) => {
+                error!("JWT has expired");
+                return Err(StatusCode::BadIdentityTokenRejected);
+            }
+            None => {
+                error!("JWT has no expiration time");
+                return Err(StatusCode::BadIdentityTokenInvalid);
+            }
+        }
+        if let Some(nbf) = self.numeric_claim("nbf") {
+            if now + leeway < nbf {
+                error!("JWT is not valid yet");
+                return Err(StatusCode::BadIdentityTokenRejected);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tests if the claim holds the value, or if the claim is an array, that it contains the value.
+    pub fn claim_matches(&self, claim: &str, value: &str) -> bool {
+        match self.claims.get(claim) {
+            Some(Value::String(v)) => v == value,
+            Some(Value::Array(v)) => v.iter().any(|v| v.as_str() == Some(value)),
+            _ => false,
+        }
+    }
+
+    fn numeric_claim(&self, claim: &str) -> Option<i64> {
+        self.claims
+            .get(claim)
+            .and_then(|v| v.as_i64().or_else(|| v.as_f64().map(|v| v as i64)))
+    }
+}
+
+fn header_and_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(0)
+}
+
+fn verify_hmac(digest: MessageDigest, secret: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let Ok(key) = pkey::PKey::hmac(secret) else {
+        return false;
+    };
+    let Ok(mut signer) = Signer::new(digest, &key) else {
+        return false;
+    };
+    match signer.update(data).and_then(|_| signer.sign_to_vec()) {
+        Ok(expected) => constant_time_eq(&expected, signature),
+        Err(_) => false,
+    }
+}
+
+fn verify_rsa(
+    digest: MessageDigest,
+    pss: bool,
+    key: &pkey::PKey<pkey::Public>,
+    data: &[u8],
+    signature: &[u8],
+) -> bool {
+    if key.id() != Id::RSA {
+        return false;
+    }
+    let Ok(mut verifier) = Verifier::new(digest, key) else {
+        return false;
+    };
+    if pss
+        && (verifier.set_rsa_padding(Padding::PKCS1_PSS).is_err()
+            || verifier
+                .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+                .is_err())
+    {
+        return false;
+    }
+    verifier
+        .update(data)
+        .and_then(|_| verifier.verify(signature))
+        .unwrap_or(false)
+}
+
+fn verify_ecdsa(
+    digest: MessageDigest,
+    curve: Nid,
+    component_len: usize,
+    key: &pkey::PKey<pkey::Public>,
+    data: &[u8],
+    signature: &[u8],
+) -> bool {
+    // Each algorithm names its curve (RFC 7518), so a key on another curve is rejected
+    let on_curve = key
+        .ec_key()
+        .is_ok_and(|ec| ec.group().curve_name() == Some(curve));
+    // JWS holds the signature as the concatenated R and S values but OpenSSL wants DER
+    if key.id() != Id::EC || !on_curve || signature.len() != component_len * 2 {
+        return false;
+    }
+    let der = BigNum::from_slice(&signature[..component_len])
+        .and_then(|r| {
+            BigNum::from_slice(&signature[component_len..])
+                .and_then(|s| EcdsaSig::from_private_components(r, s))
+        })
+        .and_then(|sig| sig.to_der());
+    let Ok(der) = der else {
+        return false;
+    };
+    let Ok(mut verifier) = Verifier::new(digest, key) else {
+        return false;
+    };
+    verifier
+        .update(data)
+        .and_then(|_| verifier.verify(&der))
+        .unwrap_or(false)
+}
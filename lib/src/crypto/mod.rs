@@ -10,16 +10,21 @@ use std::fmt;
 
 use crate::types::{service_types::SignatureData, status_code::StatusCode, ByteString, UAString};
 pub use {
-    aeskey::*, certificate_store::*, hash::*, pkey::*, security_policy::*, thumbprint::*,
-    user_identity::*, x509::*,
+    aeskey::*, ca::*, certificate_store::*, crl::*, hash::*, passphrase::*, pkey::*,
+    security_policy::*, thumbprint::*, user_identity::*, x509::*,
 };
 
 #[cfg(test)]
 mod tests;
 
 pub mod aeskey;
+pub mod ca;
 pub mod certificate_store;
+pub mod crl;
 pub mod hash;
+pub mod jwt;
+pub mod passphrase;
+pub mod password;
 pub mod pkey;
 pub mod random;
 pub mod security_policy;
@@ -0,0 +1,93 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Sources of the passphrase that protects an encrypted PKCS#8 private key on disk.
+
+use std::{fmt, sync::Arc};
+
+/// A function that returns a private key passphrase, e.g. by asking a secrets manager.
+#[derive(Clone)]
+pub struct PassphraseCallback(Arc<dyn Fn() -> Result<String, String> + Send + Sync>);
+
+impl fmt::Debug for PassphraseCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PassphraseCallback")
+    }
+}
+
+impl PartialEq for PassphraseCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl PassphraseCallback {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn() -> Result<String, String> + Send + Sync + 'static,
+    {
+        PassphraseCallback(Arc::new(f))
+    }
+}
+
+/// Where the passphrase of an encrypted private key comes from. In a configuration file it is
+/// written as either `!value <passphrase>` or `!env <variable name>`. A callback can only be
+/// set in code.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivateKeyPassphrase {
+    /// The passphrase itself. Only use this if the configuration file is protected.
+    Value(String),
+    /// The name of an environment variable that holds the passphrase
+    Env(String),
+    /// A function that returns the passphrase
+    #[serde(skip)]
+    Callback(PassphraseCallback),
+}
+
+impl fmt::Debug for PrivateKeyPassphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never write out the passphrase
+        match self {
+            PrivateKeyPassphrase::Value(_) => write!(f, "Value(..)"),
+            PrivateKeyPassphrase::Env(name) => write!(f, "Env({})", name),
+            PrivateKeyPassphrase::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+impl PrivateKeyPassphrase {
+    /// Creates a passphrase source from a function
+    pub fn callback<F>(f: F) -> Self
+    where
+        F: Fn() -> Result<String, String> + Send + Sync + 'static,
+    {
+        PrivateKeyPassphrase::Callback(PassphraseCallback::new(f))
+    }
+
+    /// Obtains the passphrase from its source
+    pub fn passphrase(&self) -> Result<String, String> {
+        let passphrase = match self {
+            PrivateKeyPassphrase::Value(value) => value.clone(),
+            PrivateKeyPassphrase::Env(name) => std::env::var(name).map_err(|_| {
+                format!(
+                    "Private key passphrase environment variable {} is not set",
+                    name
+                )
+            })?,
+            PrivateKeyPassphrase::Callback(callback) => (callback.0)()?,
+        };
+        if passphrase.is_empty() {
+            Err("Private key passphrase is empty".to_string())
+        } else {
+            Ok(passphrase)
+        }
+    }
+}
+
+/// Tests if a configured passphrase should be left out when a configuration is saved. A callback
+/// cannot be saved.
+pub(crate) fn skip_serializing(passphrase: &Option<PrivateKeyPassphrase>) -> bool {
+    matches!(passphrase, None | Some(PrivateKeyPassphrase::Callback(_)))
+}
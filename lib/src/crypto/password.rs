@@ -0,0 +1,297 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Salted password hashing and verification for user name / password identities held in
+//! configuration.
+//!
+//! Hashes are stored as [PHC strings](https://github.com/P-H-C/phc-string-format), e.g.
+//! `$pbkdf2-sha256$i=600000$<salt>$<hash>`, so the algorithm and its parameters travel with the
+//! hash and can be strengthened later without invalidating existing hashes.
+
+use std::{fmt, str::FromStr};
+
+use argon2::{password_hash, Argon2, PasswordHasher, PasswordVerifier};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use openssl::{hash::MessageDigest, pkcs5};
+
+use crate::types::status_code::StatusCode;
+
+use super::random;
+
+/// Length of a generated salt in bytes
+const SALT_LENGTH: usize = 16;
+/// Length of a derived key in bytes for PBKDF2 and scrypt hashes created by this module
+const KEY_LENGTH: usize = 32;
+/// Default PBKDF2-HMAC-SHA256 iteration count
+const PBKDF2_SHA256_ITERATIONS: u32 = 600_000;
+/// Default PBKDF2-HMAC-SHA512 iteration count
+const PBKDF2_SHA512_ITERATIONS: u32 = 210_000;
+/// Default scrypt cost parameter as a log2 value, i.e. N = 2^15
+const SCRYPT_LOG_N: u8 = 15;
+/// Default scrypt block size
+const SCRYPT_R: u32 = 8;
+/// Default scrypt parallelization
+const SCRYPT_P: u32 = 1;
+/// Upper bound on the memory that scrypt may use while verifying a hash
+const SCRYPT_MAX_MEM: u64 = 256 * 1024 * 1024;
+
+/// Algorithms that can be used to hash a password
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PasswordHashAlgorithm {
+    Argon2id,
+    Scrypt,
+    Pbkdf2Sha256,
+    Pbkdf2Sha512,
+}
+
+impl fmt::Display for PasswordHashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PasswordHashAlgorithm::Argon2id => "argon2id",
+            PasswordHashAlgorithm::Scrypt => "scrypt",
+            PasswordHashAlgorithm::Pbkdf2Sha256 => "pbkdf2-sha256",
+            PasswordHashAlgorithm::Pbkdf2Sha512 => "pbkdf2-sha512",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PasswordHashAlgorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argon2id" => Ok(PasswordHashAlgorithm::Argon2id),
+            "scrypt" => Ok(PasswordHashAlgorithm::Scrypt),
+            "pbkdf2-sha256" => Ok(PasswordHashAlgorithm::Pbkdf2Sha256),
+            "pbkdf2-sha512" => Ok(PasswordHashAlgorithm::Pbkdf2Sha512),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Hashes the password with a random salt and the default parameters for the algorithm and
+/// returns the result as a PHC string.
+pub fn hash_password(
+    algorithm: PasswordHashAlgorithm,
+    password: &[u8],
+) -> Result<String, StatusCode> {
+    let mut salt = [0u8; SALT_LENGTH];
+    random::bytes(&mut salt);
+    match algorithm {
+        PasswordHashAlgorithm::Argon2id => {
+            let salt = password_hash::SaltString::encode_b64(&salt).map_err(|err| {
+                error!("Cannot encode salt for argon2 hash, {}", err);
+                StatusCode::BadUnexpectedError
+            })?;
+            Argon2::default()
+                .hash_password(password, &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| {
+                    error!("Cannot hash password with argon2, {}", err);
+                    StatusCode::BadUnexpectedError
+                })
+        }
+        PasswordHashAlgorithm::Scrypt => {
+            let key = scrypt(
+                password,
+                &salt,
+                SCRYPT_LOG_N,
+                SCRYPT_R,
+                SCRYPT_P,
+                KEY_LENGTH,
+            )?;
+            Ok(format!(
+                "${}$ln={},r={},p={}${}${}",
+                algorithm,
+                SCRYPT_LOG_N,
+                SCRYPT_R,
+                SCRYPT_P,
+                STANDARD_NO_PAD.encode(salt),
+                STANDARD_NO_PAD.encode(key)
+            ))
+        }
+        PasswordHashAlgorithm::Pbkdf2Sha256 | PasswordHashAlgorithm::Pbkdf2Sha512 => {
+            let (digest, iterations) = if algorithm == PasswordHashAlgorithm::Pbkdf2Sha256 {
+                (MessageDigest::sha256(), PBKDF2_SHA256_ITERATIONS)
+            } else {
+                (MessageDigest::sha512(), PBKDF2_SHA512_ITERATIONS)
+            };
+            let key = pbkdf2(password, &salt, iterations, digest, KEY_LENGTH)?;
+            Ok(format!(
+                "${}$i={}${}${}",
+                algorithm,
+                iterations,
+                STANDARD_NO_PAD.encode(salt),
+                STANDARD_NO_PAD.encode(key)
+            ))
+        }
+    }
+}
+
+/// Tests if the password hash is a well formed PHC string for a supported algorithm.
+pub fn is_valid_password_hash(password_hash: &str) -> bool {
+    PhcHash::parse(password_hash).is_ok()
+}
+
+/// Verifies the password against a PHC string produced by `hash_password()`. The derived key is
+/// compared in constant time. An error is returned if the hash is malformed.
+pub fn verify_password(password_hash: &str, password: &[u8]) -> Result<bool, StatusCode> {
+    match PhcHash::parse(password_hash)? {
+        PhcHash::Argon2(hash) => {
+            // Argon2 verifies in constant time internally
+            Ok(Argon2::default().verify_password(password, &hash).is_ok())
+        }
+        PhcHash::Scrypt {
+            log_n,
+            r,
+            p,
+            salt,
+            key,
+        } => {
+            let derived = scrypt(password, &salt, log_n, r, p, key.len())?;
+            Ok(constant_time_eq(&derived, &key))
+        }
+        PhcHash::Pbkdf2 {
+            digest,
+            iterations,
+            salt,
+            key,
+        } => {
+            let derived = pbkdf2(password, &salt, iterations, digest, key.len())?;
+            Ok(constant_time_eq(&derived, &key))
+        }
+    }
+}
+
+/// Compares two byte slices without short-circuiting on the first difference. Only the length
+/// of the inputs can be inferred from timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a, b)
+}
+
+fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+    key_length: usize,
+) -> Result<Vec<u8>, StatusCode> {
+    if log_n == 0 || log_n >= 64 {
+        error!("Scrypt cost parameter ln={} is out of range", log_n);
+        return Err(StatusCode::BadConfigurationError);
+    }
+    let mut key = vec![0u8; key_length];
+    pkcs5::scrypt(
+        password,
+        salt,
+        1u64 << log_n,
+        r as u64,
+        p as u64,
+        SCRYPT_MAX_MEM,
+        &mut key,
+    )
+    .map_err(|err| {
+        error!("Cannot derive scrypt key, {}", err);
+        StatusCode::BadConfigurationError
+    })?;
+    Ok(key)
+}
+
+fn pbkdf2(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    digest: MessageDigest,
+    key_length: usize,
+) -> Result<Vec<u8>, StatusCode> {
+    let mut key = vec![0u8; key_length];
+    pkcs5::pbkdf2_hmac(password, salt, iterations as usize, digest, &mut key).map_err(|err| {
+        error!("Cannot derive pbkdf2 key, {}", err);
+        StatusCode::BadConfigurationError
+    })?;
+    Ok(key)
+}
+
+/// A parsed PHC string
+enum PhcHash<'a> {
+    Argon2(password_hash::PasswordHash<'a>),
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+        salt: Vec<u8>,
+        key: Vec<u8>,
+    },
+    Pbkdf2 {
+        digest: MessageDigest,
+        iterations: u32,
+        salt: Vec<u8>,
+        key: Vec<u8>,
+    },
+}
+
+impl<'a> PhcHash<'a> {
+    fn parse(s: &'a str) -> Result<Self, StatusCode> {
+        let invalid = || {
+            error!("Password hash is not a valid PHC string for a supported algorithm");
+            StatusCode::BadConfigurationError
+        };
+        // "$id$params$salt$hash" splits into ["", id, params, salt, hash]
+        let parts: Vec<&str> = s.split('$').collect();
+        if parts.len() < 2 || !parts[0].is_empty() {
+            return Err(invalid());
+        }
+        match PasswordHashAlgorithm::from_str(parts[1]).map_err(|_| invalid())? {
+            PasswordHashAlgorithm::Argon2id => password_hash::PasswordHash::new(s)
+                .map(PhcHash::Argon2)
+                .map_err(|_| invalid()),
+            algorithm => {
+                if parts.len() != 5 {
+                    return Err(invalid());
+                }
+                let salt = STANDARD_NO_PAD.decode(parts[3]).map_err(|_| invalid())?;
+                let key = STANDARD_NO_PAD.decode(parts[4]).map_err(|_| invalid())?;
+                if salt.is_empty() || key.is_empty() {
+                    return Err(invalid());
+                }
+                let param = |name: &str| -> Result<u32, StatusCode> {
+                    parts[2]
+                        .split(',')
+                        .filter_map(|p| p.split_once('='))
+                        .find(|(k, _)| *k == name)
+                        .and_then(|(_, v)| v.parse::<u32>().ok())
+                        .ok_or_else(invalid)
+                };
+                match algorithm {
+                    PasswordHashAlgorithm::Scrypt => Ok(PhcHash::Scrypt {
+                        log_n: u8::try_from(param("ln")?).map_err(|_| invalid())?,
+                        r: param("r")?,
+                        p: param("p")?,
+                        salt,
+                        key,
+                    }),
+                    _ => {
+                        let digest = if algorithm == PasswordHashAlgorithm::Pbkdf2Sha256 {
+                            MessageDigest::sha256()
+                        } else {
+                            MessageDigest::sha512()
+                        };
+                        let iterations = param("i")?;
+                        if iterations == 0 {
+                            return Err(invalid());
+                        }
+                        Ok(PhcHash::Pbkdf2 {
+                            digest,
+                            iterations,
+                            salt,
+                            key,
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
@@ -15,7 +15,9 @@ use super::{
     aeskey::AesKey,
     hash,
     pkey::{KeySize, PrivateKey, PublicKey, RsaPadding},
-    random, SHA1_SIZE, SHA256_SIZE,
+    random,
+    x509::CertificateSignatureHash,
+    SHA1_SIZE, SHA256_SIZE,
 };
 
 // These are constants that govern the different encryption / signing modes for OPC UA. In some
@@ -211,6 +213,63 @@ impl From<SecurityPolicy> for String {
     }
 }
 
+/// A security profile restricts the security policies that may be used and the certificates that
+/// are acceptable to them, on top of the requirements of each policy.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SecurityProfile {
+    /// Allow the policies deprecated by OPC UA 1.04, i.e. Basic128Rsa15 and Basic256, which
+    /// depend on SHA-1.
+    pub allow_deprecated_policies: bool,
+    /// The minimum key length in bits of a certificate. The policy's own minimum still applies.
+    pub min_key_length: usize,
+    /// The maximum key length in bits of a certificate. The policy's own maximum still applies.
+    pub max_key_length: usize,
+}
+
+impl Default for SecurityProfile {
+    fn default() -> Self {
+        Self {
+            allow_deprecated_policies: false,
+            min_key_length: 1024,
+            max_key_length: 4096,
+        }
+    }
+}
+
+impl SecurityProfile {
+    /// Tests if the security policy may be used. `None` is always allowed here, it is up to the
+    /// endpoints whether to offer or use it.
+    pub fn is_policy_allowed(&self, security_policy: SecurityPolicy) -> bool {
+        security_policy.is_supported()
+            && (self.allow_deprecated_policies || !security_policy.is_deprecated())
+    }
+
+    /// Returns the min and max (inclusive) key length in bits of a certificate for the policy
+    pub fn min_max_key_length(&self, security_policy: SecurityPolicy) -> (usize, usize) {
+        let (min, max) = security_policy.min_max_asymmetric_keylength();
+        (min.max(self.min_key_length), max.min(self.max_key_length))
+    }
+
+    /// Tests if the key length of a certificate is valid for the policy
+    pub fn is_valid_key_length(&self, security_policy: SecurityPolicy, key_length: usize) -> bool {
+        let (min, max) = self.min_max_key_length(security_policy);
+        key_length >= min && key_length <= max
+    }
+
+    /// Tests if the configured key lengths are sensible
+    pub fn is_valid(&self) -> bool {
+        if self.min_key_length > self.max_key_length {
+            error!(
+                "Security profile is invalid. The min key length {} exceeds the max key length {}",
+                self.min_key_length, self.max_key_length
+            );
+            false
+        } else {
+            true
+        }
+    }
+}
+
 impl SecurityPolicy {
     pub fn to_uri(&self) -> &'static str {
         match self {
@@ -380,6 +439,18 @@ impl SecurityPolicy {
         keylength >= min_max.0 && keylength <= min_max.1
     }
 
+    /// Tests if a certificate signed with the hash is acceptable to this policy. The deprecated
+    /// policies accept SHA-1 or SHA-256 and stronger, the others only SHA-256 and stronger.
+    pub fn is_valid_certificate_signature_hash(&self, hash: CertificateSignatureHash) -> bool {
+        match hash {
+            CertificateSignatureHash::Sha256
+            | CertificateSignatureHash::Sha384
+            | CertificateSignatureHash::Sha512 => true,
+            CertificateSignatureHash::Sha1 => self.is_deprecated(),
+            _ => false,
+        }
+    }
+
     /// Creates a random nonce in a bytestring with a length appropriate for the policy
     pub fn random_nonce(&self) -> ByteString {
         match self {
@@ -0,0 +1,356 @@
+use foreign_types::ForeignTypeRef;
+use openssl::{
+    asn1::{Asn1Object, Asn1OctetString, Asn1Time},
+    hash::MessageDigest,
+    x509::{
+        extension::SubjectAlternativeName, X509Builder, X509Extension, X509NameBuilder,
+        X509ReqBuilder,
+    },
+};
+
+use crate::{
+    crypto::{
+        ca::CertificateAuthority,
+        certificate_store::CertificateStore,
+        crl::Crl,
+        pkey::PrivateKey,
+        tests::{make_certificate_store, APPLICATION_HOSTNAME, APPLICATION_URI},
+        x509::{X509Data, X509},
+        SecurityPolicy,
+    },
+    types::status_code::StatusCode,
+};
+
+fn x509_data(common_name: &str) -> X509Data {
+    X509Data {
+        key_size: 2048,
+        common_name: common_name.to_string(),
+        organization: "x.org".to_string(),
+        organizational_unit: "x.org ops".to_string(),
+        country: "EN".to_string(),
+        state: "London".to_string(),
+        alt_host_names: vec![
+            APPLICATION_URI.to_string(),
+            APPLICATION_HOSTNAME.to_string(),
+        ],
+        certificate_duration_days: 60,
+    }
+}
+
+#[test]
+fn issue_cert() {
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+    assert!(ca.cert().is_self_signed());
+
+    let (cert, pkey) = ca.issue(&x509_data("x")).unwrap();
+    assert!(!cert.is_self_signed());
+    assert!(ca.is_issuer_of(&cert));
+    assert!(cert
+        .public_key()
+        .unwrap()
+        .verify_sha256(b"hello", &{
+            let mut signature = vec![0u8; 256];
+            let _ = pkey.sign_sha256(b"hello", &mut signature).unwrap();
+            signature
+        })
+        .unwrap());
+    assert!(cert.is_application_uri_valid(APPLICATION_URI).is_good());
+    assert!(cert.is_hostname_valid(APPLICATION_HOSTNAME).is_good());
+
+    // The CA does not take the key of another cert
+    let other = CertificateAuthority::create(&x509_data("Other CA")).unwrap();
+    assert!(!other.is_issuer_of(&cert));
+    assert!(CertificateAuthority::new(ca.cert().clone(), PrivateKey::new(2048)).is_err());
+}
+
+#[test]
+fn sign_request() {
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+
+    let pkey = PrivateKey::new(2048);
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "requester").unwrap();
+    let name = name.build();
+    let mut request = X509ReqBuilder::new().unwrap();
+    request.set_subject_name(&name).unwrap();
+    request.set_pubkey(&pkey.value).unwrap();
+    request.sign(&pkey.value, MessageDigest::sha256()).unwrap();
+    let request = request.build().to_pem().unwrap();
+
+    let alt_host_names = [
+        APPLICATION_URI.to_string(),
+        APPLICATION_HOSTNAME.to_string(),
+    ];
+    let cert = ca.sign_request(&request, &alt_host_names, 30).unwrap();
+    assert!(ca.is_issuer_of(&cert));
+    assert_eq!(cert.common_name().unwrap(), "requester");
+    assert!(cert.is_application_uri_valid(APPLICATION_URI).is_good());
+    assert!(cert.is_hostname_valid(APPLICATION_HOSTNAME).is_good());
+
+    assert!(ca.sign_request(b"garbage", &alt_host_names, 30).is_err());
+}
+
+#[test]
+fn revoke_cert() {
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+    let (cert1, _) = ca.issue(&x509_data("x1")).unwrap();
+    let (cert2, _) = ca.issue(&x509_data("x2")).unwrap();
+
+    let crl = ca.crl(None, 30).unwrap();
+    assert!(crl.is_issued_by(ca.cert()));
+    assert!(crl.revoked_serial_numbers().is_empty());
+
+    let crl = ca.revoke(Some(&crl), &cert1, 30).unwrap();
+    assert!(crl.is_revoked(&cert1));
+    assert!(!crl.is_revoked(&cert2));
+
+    // Revocations carry over and are not repeated
+    let crl = ca.revoke(Some(&crl), &cert2, 30).unwrap();
+    let crl = ca.revoke(Some(&crl), &cert2, 30).unwrap();
+    assert!(crl.is_revoked(&cert1));
+    assert!(crl.is_revoked(&cert2));
+    assert_eq!(crl.revoked_serial_numbers().len(), 2);
+    let crl = ca.crl(Some(&crl), 30).unwrap();
+    assert_eq!(crl.revoked_serial_numbers().len(), 2);
+
+    // Another CA cannot revoke the certs or extend the CRL
+    let other = CertificateAuthority::create(&x509_data("Other CA")).unwrap();
+    assert!(!crl.is_issued_by(other.cert()));
+    assert!(other.revoke(None, &cert1, 30).is_err());
+    assert!(other.crl(Some(&crl), 30).is_err());
+}
+
+#[test]
+fn remove_from_crl_is_not_revoked() {
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+    let (cert, _) = ca.issue(&x509_data("x")).unwrap();
+    let crl = ca.revoke(None, &cert, 30).unwrap();
+    assert!(crl.is_revoked(&cert));
+
+    // Give the entry the removeFromCRL reason code (8) that a delta CRL uses to lift a hold
+    let reason = X509Extension::new_from_der(
+        &Asn1Object::from_str("2.5.29.21").unwrap(),
+        false,
+        &Asn1OctetString::new_from_bytes(&[0x0a, 0x01, 0x08]).unwrap(),
+    )
+    .unwrap();
+    let der = unsafe {
+        use openssl_sys::*;
+        let entry = crl.value.get_revoked().unwrap().get(0).unwrap().as_ptr();
+        assert!(X509_REVOKED_add_ext(entry, reason.as_ptr(), -1) > 0);
+        assert!(X509_CRL_sign(crl.value.as_ptr(), ca.pkey().value.as_ptr(), EVP_sha256()) > 0);
+        crl.to_der().unwrap()
+    };
+
+    // The reason code is read when the CRL is parsed
+    let crl = Crl::from_der(&der).unwrap();
+    assert!(crl.is_issued_by(ca.cert()));
+    assert_eq!(crl.revoked_serial_numbers().len(), 1);
+    assert!(!crl.is_revoked(&cert));
+}
+
+#[test]
+fn validate_issued_cert() {
+    let (tmp_dir, cert_store) = make_certificate_store();
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+    let (cert, _) = ca.issue(&x509_data("x")).unwrap();
+
+    // Untrusted until the CA is trusted
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::BadCertificateUntrusted
+    );
+    let _ = std::fs::remove_dir_all(cert_store.rejected_certs_dir());
+    assert!(cert_store.ensure_pki_path().is_ok());
+
+    CertificateStore::write_certificate_and_key(
+        ca.cert(),
+        ca.pkey(),
+        false,
+        &cert_store.trusted_certs_dir().join("ca.der"),
+        &tmp_dir.path().join("private/ca.pem"),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            Some(APPLICATION_HOSTNAME),
+            Some(APPLICATION_URI)
+        ),
+        StatusCode::Good
+    );
+    // The cert is trusted through its issuer, not by being copied
+    assert!(!cert_store
+        .trusted_certs_dir()
+        .join(CertificateStore::cert_file_name(&cert))
+        .exists());
+
+    // A cert from another CA is still untrusted
+    let other = CertificateAuthority::create(&x509_data("Other CA")).unwrap();
+    let (other_cert, _) = other.issue(&x509_data("x")).unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &other_cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::BadCertificateUntrusted
+    );
+
+    // Revoked once the CRL is in the store
+    let crl = ca.revoke(None, &cert, 30).unwrap();
+    CertificateStore::write_crl(&crl, &cert_store.crl_dir().join("ca.crl")).unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::BadCertificateRevoked
+    );
+}
+
+#[test]
+fn validate_cert_from_intermediate_ca() {
+    let (_tmp_dir, cert_store) = make_certificate_store();
+    let root = CertificateAuthority::create(&x509_data("Root CA")).unwrap();
+    let (intermediate_cert, intermediate_pkey) = root.issue_ca(&x509_data("Issuing CA")).unwrap();
+    let intermediate = CertificateAuthority::new(intermediate_cert, intermediate_pkey).unwrap();
+    let (cert, _) = intermediate.issue(&x509_data("x")).unwrap();
+
+    std::fs::write(
+        cert_store.trusted_certs_dir().join("root.der"),
+        root.cert().to_der().unwrap(),
+    )
+    .unwrap();
+    std::fs::write(
+        cert_store.issuer_certs_dir().join("issuing.der"),
+        intermediate.cert().to_der().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::Good
+    );
+
+    // An issuer cert is not trusted by itself
+    let _ = std::fs::remove_file(cert_store.trusted_certs_dir().join("root.der"));
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::BadCertificateUntrusted
+    );
+}
+
+#[test]
+fn reject_cert_issued_by_trusted_application_cert() {
+    let (_tmp_dir, cert_store) = make_certificate_store();
+    let (app_cert, app_pkey) = X509::cert_and_pkey(&x509_data("app")).unwrap();
+    assert!(!app_cert.is_ca());
+    assert!(CertificateAuthority::create(&x509_data("Test CA"))
+        .unwrap()
+        .cert()
+        .is_ca());
+
+    // A leaf claiming the identity of another application, signed with the key of a trusted peer
+    let pkey = PrivateKey::new(2048);
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_subject_name(&x509_data("impostor").x509_name().unwrap())
+        .unwrap();
+    builder
+        .set_issuer_name(app_cert.value.subject_name())
+        .unwrap();
+    builder.set_pubkey(&pkey.value).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+        .unwrap();
+    let subject_alternative_name = SubjectAlternativeName::new()
+        .uri(APPLICATION_URI)
+        .dns(APPLICATION_HOSTNAME)
+        .build(&builder.x509v3_context(Some(&app_cert.value), None))
+        .unwrap();
+    builder.append_extension(subject_alternative_name).unwrap();
+    builder
+        .sign(&app_pkey.value, MessageDigest::sha256())
+        .unwrap();
+    let leaf = X509::from(builder.build());
+
+    std::fs::write(
+        cert_store.trusted_certs_dir().join("app.der"),
+        app_cert.to_der().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &leaf,
+            SecurityPolicy::Basic256Sha256,
+            Some(APPLICATION_HOSTNAME),
+            Some(APPLICATION_URI)
+        ),
+        StatusCode::BadCertificateUntrusted
+    );
+}
+
+#[test]
+fn reject_cert_with_expired_crl() {
+    let (_tmp_dir, cert_store) = make_certificate_store();
+    let ca = CertificateAuthority::create(&x509_data("Test CA")).unwrap();
+    let (cert, _) = ca.issue(&x509_data("x")).unwrap();
+    std::fs::write(
+        cert_store.trusted_certs_dir().join("ca.der"),
+        ca.cert().to_der().unwrap(),
+    )
+    .unwrap();
+
+    // A current CRL that does not list the cert
+    let crl = ca.crl(None, 30).unwrap();
+    assert!(!crl.is_expired());
+    CertificateStore::write_crl(&crl, &cert_store.crl_dir().join("ca.crl")).unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::Good
+    );
+
+    // A CRL whose next update has passed may be missing revocations
+    let crl = ca.crl(None, 0).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert!(crl.is_expired());
+    CertificateStore::write_crl(&crl, &cert_store.crl_dir().join("ca.crl")).unwrap();
+    assert_eq!(
+        cert_store.validate_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        ),
+        StatusCode::BadCertificateRevocationUnknown
+    );
+}
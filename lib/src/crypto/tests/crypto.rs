@@ -6,6 +6,7 @@ use crate::{
         aeskey::AesKey,
         certificate_store::*,
         hash,
+        passphrase::PrivateKeyPassphrase,
         pkey::{KeySize, PrivateKey, RsaPadding},
         random,
         tests::{
@@ -109,6 +110,143 @@ fn create_own_cert_in_pki() {
     drop(tmp_dir)
 }
 
+#[test]
+fn create_own_cert_with_encrypted_pkey() {
+    let args = X509Data {
+        key_size: 2048,
+        common_name: "x".to_string(),
+        organization: "x.org".to_string(),
+        organizational_unit: "x.org ops".to_string(),
+        country: "EN".to_string(),
+        state: "London".to_string(),
+        alt_host_names: vec!["host1".to_string(), "host2".to_string()],
+        certificate_duration_days: 60,
+    };
+
+    let (tmp_dir, mut cert_store) = make_certificate_store();
+    cert_store
+        .set_private_key_passphrase(Some(PrivateKeyPassphrase::Value("passphrase1".to_string())));
+    let (_, pkey) = cert_store
+        .create_and_store_application_instance_cert(&args, false)
+        .unwrap();
+
+    // Key on disk is encrypted and cannot be read without a passphrase
+    let pem = std::fs::read(cert_store.own_private_key_path()).unwrap();
+    assert!(PrivateKey::is_encrypted_pem(&pem));
+    assert!(PrivateKey::from_pem(&pem).is_err());
+
+    // Store reads it back with the passphrase
+    let (_, read_pkey) = cert_store.read_own_cert_and_pkey().unwrap();
+    assert_eq!(
+        read_pkey.private_key_to_pem().unwrap(),
+        pkey.private_key_to_pem().unwrap()
+    );
+
+    // But not without it or with the wrong one
+    let path = cert_store.own_private_key_path();
+    assert!(CertificateStore::read_pkey(&path).is_err());
+    let wrong = PrivateKeyPassphrase::Value("passphrase2".to_string());
+    assert!(CertificateStore::read_pkey_with_passphrase(&path, Some(&wrong)).is_err());
+
+    // Passphrase from the environment or a callback
+    std::env::set_var("OPCUA_TEST_PKEY_PASSPHRASE", "passphrase1");
+    let env = PrivateKeyPassphrase::Env("OPCUA_TEST_PKEY_PASSPHRASE".to_string());
+    assert!(CertificateStore::read_pkey_with_passphrase(&path, Some(&env)).is_ok());
+    let env = PrivateKeyPassphrase::Env("OPCUA_TEST_PKEY_PASSPHRASE_UNSET".to_string());
+    assert!(CertificateStore::read_pkey_with_passphrase(&path, Some(&env)).is_err());
+    let callback = PrivateKeyPassphrase::callback(|| Ok("passphrase1".to_string()));
+    assert!(CertificateStore::read_pkey_with_passphrase(&path, Some(&callback)).is_ok());
+
+    // A passphrase does no harm to an unencrypted key
+    cert_store.set_private_key_passphrase(None);
+    let _ = cert_store
+        .create_and_store_application_instance_cert(&args, true)
+        .unwrap();
+    assert!(CertificateStore::read_pkey_with_passphrase(&path, Some(&callback)).is_ok());
+    drop(tmp_dir)
+}
+
+#[test]
+fn renew_own_cert() {
+    let args = X509Data {
+        key_size: 2048,
+        common_name: "x".to_string(),
+        organization: "x.org".to_string(),
+        organizational_unit: "x.org ops".to_string(),
+        country: "EN".to_string(),
+        state: "London".to_string(),
+        alt_host_names: vec![
+            APPLICATION_URI.to_string(),
+            APPLICATION_HOSTNAME.to_string(),
+            "127.0.0.1".to_string(),
+        ],
+        certificate_duration_days: 20,
+    };
+
+    let (tmp_dir, cert_store) = make_certificate_store();
+    let (cert, _) = cert_store
+        .create_and_store_application_instance_cert(&args, false)
+        .unwrap();
+    assert!(cert.is_self_signed());
+
+    // Not within the renewal window
+    assert!(!cert.expires_within(&chrono::Utc::now(), 10));
+    assert!(cert_store
+        .renew_application_instance_cert(10)
+        .unwrap()
+        .is_none());
+
+    // Within the renewal window
+    let (renewed, renewed_pkey) = cert_store
+        .renew_application_instance_cert(30)
+        .unwrap()
+        .unwrap();
+    assert_ne!(renewed.thumbprint(), cert.thumbprint());
+    assert_eq!(renewed.subject_name(), cert.subject_name());
+    assert!(renewed.is_application_uri_valid(APPLICATION_URI).is_good());
+    assert!(renewed.is_hostname_valid(APPLICATION_HOSTNAME).is_good());
+    assert!(renewed.is_hostname_valid("127.0.0.1").is_good());
+    assert_eq!(renewed.key_length().unwrap(), 2048);
+    assert_eq!(
+        renewed.to_x509_data().unwrap().certificate_duration_days,
+        20
+    );
+
+    // The store now holds the renewed pair and keeps the old one beside it
+    let (own_cert, own_pkey) = cert_store.read_own_cert_and_pkey().unwrap();
+    assert_eq!(own_cert.thumbprint(), renewed.thumbprint());
+    assert_eq!(
+        own_pkey.private_key_to_pem().unwrap(),
+        renewed_pkey.private_key_to_pem().unwrap()
+    );
+    let thumbprint = cert.thumbprint().as_hex_string();
+    let mut old_cert_path = tmp_dir.path().to_path_buf();
+    old_cert_path.push(format!("own/cert [{}].der", thumbprint));
+    let old_cert = CertificateStore::read_cert(&old_cert_path).unwrap();
+    assert_eq!(old_cert.thumbprint(), cert.thumbprint());
+    let mut old_pkey_path = tmp_dir.path().to_path_buf();
+    old_pkey_path.push(format!("private/private [{}].pem", thumbprint));
+    assert!(CertificateStore::read_pkey(&old_pkey_path).is_ok());
+    drop(tmp_dir)
+}
+
+#[test]
+fn private_key_passphrase_serialization() {
+    let passphrase: PrivateKeyPassphrase = serde_yaml::from_str("!env OPCUA_PKEY").unwrap();
+    assert_eq!(
+        passphrase,
+        PrivateKeyPassphrase::Env("OPCUA_PKEY".to_string())
+    );
+    let passphrase: PrivateKeyPassphrase = serde_yaml::from_str("!value secret").unwrap();
+    assert_eq!(
+        passphrase,
+        PrivateKeyPassphrase::Value("secret".to_string())
+    );
+
+    // Passphrase is not written to logs
+    assert!(!format!("{:?}", passphrase).contains("secret"));
+}
+
 #[test]
 fn create_rejected_cert_in_pki() {
     let (tmp_dir, cert_store) = make_certificate_store();
@@ -122,6 +260,37 @@ fn create_rejected_cert_in_pki() {
     drop(tmp_dir);
 }
 
+#[test]
+fn trust_rejected_cert_in_pki() {
+    let (tmp_dir, cert_store) = make_certificate_store();
+
+    let (cert, _) = make_test_cert_2048();
+    let file_name = CertificateStore::cert_file_name(&cert);
+    let _ = cert_store.store_rejected_cert(&cert).unwrap();
+    let rejected_certs = cert_store.rejected_certs();
+    assert_eq!(rejected_certs.len(), 1);
+    assert_eq!(rejected_certs[0].0, file_name);
+
+    // Names that escape the rejected directory are refused
+    assert!(cert_store.trust_rejected_cert("../trusted").is_err());
+    assert!(cert_store.trust_rejected_cert("missing.der").is_err());
+
+    let path = cert_store.trust_rejected_cert(&file_name).unwrap();
+    assert_eq!(path, cert_store.trusted_certs_dir().join(&file_name));
+    assert!(path.exists());
+    assert!(cert_store.rejected_certs().is_empty());
+    assert!(cert_store
+        .validate_or_reject_application_instance_cert(
+            &cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None
+        )
+        .is_good());
+
+    drop(tmp_dir);
+}
+
 #[test]
 fn test_and_reject_application_instance_cert() {
     let (tmp_dir, cert_store) = make_certificate_store();
@@ -144,7 +313,7 @@ fn test_and_trust_application_instance_cert() {
     let (tmp_dir, cert_store) = make_certificate_store();
 
     // Make a cert, write it to the trusted dir
-    let (cert, _) = make_test_cert_1024();
+    let (cert, _) = make_test_cert_2048();
 
     // Simulate user/admin copying cert to the trusted folder
     let der = cert.to_der().unwrap();
@@ -159,7 +328,7 @@ fn test_and_trust_application_instance_cert() {
     // Now validate the cert was stored properly
     let result = cert_store.validate_or_reject_application_instance_cert(
         &cert,
-        SecurityPolicy::Basic128Rsa15,
+        SecurityPolicy::Basic256Sha256,
         None,
         None,
     );
@@ -168,6 +337,183 @@ fn test_and_trust_application_instance_cert() {
     drop(tmp_dir);
 }
 
+#[tokio::test]
+async fn approve_application_instance_cert() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::sync::RwLock;
+
+    let (tmp_dir, cert_store) = make_certificate_store();
+    let cert_store = RwLock::new(cert_store);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let set_approver = |cert_store: &RwLock<CertificateStore>, approval: CertificateApproval| {
+        let calls = calls.clone();
+        cert_store
+            .write()
+            .set_blocking_certificate_approver(move |_cert, status_code| {
+                assert_eq!(status_code, StatusCode::BadCertificateUntrusted);
+                calls.fetch_add(1, Ordering::SeqCst);
+                approval
+            });
+    };
+    let validate = |cert: X509| {
+        let cert_store = &cert_store;
+        async move {
+            CertificateStore::validate_or_approve_application_instance_cert(
+                cert_store,
+                &cert,
+                SecurityPolicy::Basic256Sha256,
+                None,
+                None,
+            )
+            .await
+        }
+    };
+    let is_in_dir = |dir: std::path::PathBuf, cert: &X509| {
+        dir.join(CertificateStore::cert_file_name(cert)).exists()
+    };
+
+    // Approved for this connection only, so the approver is asked again next time
+    let (cert, _) = make_test_cert_2048();
+    set_approver(&cert_store, CertificateApproval::ApproveOnce);
+    assert!(validate(cert.clone()).await.is_good());
+    assert!(validate(cert.clone()).await.is_good());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert!(!is_in_dir(cert_store.read().trusted_certs_dir(), &cert));
+    assert!(!is_in_dir(cert_store.read().rejected_certs_dir(), &cert));
+
+    // Validating without waiting for the approver leaves the cert unknown
+    assert_eq!(
+        cert_store
+            .read()
+            .validate_or_reject_application_instance_cert(
+                &cert,
+                SecurityPolicy::Basic256Sha256,
+                None,
+                None,
+            ),
+        StatusCode::BadCertificateUntrusted
+    );
+    assert!(!is_in_dir(cert_store.read().rejected_certs_dir(), &cert));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Approved permanently, so the cert is trusted from now on
+    set_approver(&cert_store, CertificateApproval::ApprovePermanently);
+    assert!(validate(cert.clone()).await.is_good());
+    assert!(is_in_dir(cert_store.read().trusted_certs_dir(), &cert));
+    assert!(validate(cert.clone()).await.is_good());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // Rejected, so the cert is rejected from now on without asking
+    let (cert, _) = make_test_cert_2048();
+    set_approver(&cert_store, CertificateApproval::Reject);
+    assert_eq!(
+        validate(cert.clone()).await,
+        StatusCode::BadCertificateUntrusted
+    );
+    assert!(is_in_dir(cert_store.read().rejected_certs_dir(), &cert));
+    assert!(validate(cert.clone()).await.is_bad());
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    // Without an approver an unknown cert is rejected
+    let (cert, _) = make_test_cert_2048();
+    cert_store.write().clear_certificate_approver();
+    assert_eq!(
+        validate(cert.clone()).await,
+        StatusCode::BadCertificateUntrusted
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    drop(tmp_dir);
+}
+
+#[tokio::test]
+async fn approve_invalid_application_instance_cert() {
+    use crate::sync::RwLock;
+
+    let (tmp_dir, mut cert_store) = make_certificate_store();
+
+    // The approver is told why the cert is invalid and approval does not make it valid or trusted
+    let (cert, _) = make_test_cert_2048();
+    cert_store.set_certificate_approver(|_cert, status_code| async move {
+        assert_eq!(status_code, StatusCode::BadCertificateHostNameInvalid);
+        CertificateApproval::ApprovePermanently
+    });
+    let cert_store = RwLock::new(cert_store);
+    let result = CertificateStore::validate_or_approve_application_instance_cert(
+        &cert_store,
+        &cert,
+        SecurityPolicy::Basic256Sha256,
+        Some("no.such.host"),
+        None,
+    )
+    .await;
+    assert_eq!(result, StatusCode::BadCertificateHostNameInvalid);
+    let cert_file_name = CertificateStore::cert_file_name(&cert);
+    assert!(!cert_store
+        .read()
+        .trusted_certs_dir()
+        .join(cert_file_name)
+        .exists());
+
+    drop(tmp_dir);
+}
+
+#[tokio::test]
+async fn approve_application_instance_cert_later() {
+    use std::sync::Arc;
+
+    use crate::sync::RwLock;
+
+    let (tmp_dir, mut cert_store) = make_certificate_store();
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let receiver = Arc::new(tokio::sync::Mutex::new(Some(receiver)));
+    cert_store.set_certificate_approver(move |_cert, _status_code| {
+        let receiver = receiver.clone();
+        async move {
+            let receiver = receiver.lock().await.take().unwrap();
+            receiver.await.unwrap()
+        }
+    });
+    let cert_store = Arc::new(RwLock::new(cert_store));
+    let check = |cert: &X509| {
+        cert_store.read().check_or_reject_application_instance_cert(
+            cert,
+            SecurityPolicy::Basic256Sha256,
+            None,
+            None,
+        )
+    };
+
+    // The store is not locked while the approver decides on the cert
+    let (cert, _) = make_test_cert_2048();
+    let CertificateValidation::Unknown(approver, status_code) = check(&cert) else {
+        panic!("Certificate should be left for the approver");
+    };
+    let approval = tokio::spawn(CertificateStore::approve_later(
+        cert_store.clone(),
+        approver,
+        cert.clone(),
+        status_code,
+    ));
+    tokio::task::yield_now().await;
+    let _ = cert_store.write().trusted_certs_dir();
+    sender.send(CertificateApproval::ApproveOnce).unwrap();
+    approval.await.unwrap();
+
+    // The approval applies to the next validation only
+    assert!(matches!(
+        check(&cert),
+        CertificateValidation::Done(StatusCode::Good)
+    ));
+    assert!(matches!(check(&cert), CertificateValidation::Unknown(..)));
+
+    drop(tmp_dir);
+}
+
 #[test]
 fn test_and_reject_thumbprint_mismatch() {
     let (tmp_dir, cert_store) = make_certificate_store();
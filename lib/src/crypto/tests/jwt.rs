@@ -0,0 +1,237 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::{
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    nid::Nid,
+    pkey::{PKey, Private},
+    rsa::{Padding, Rsa},
+    sign::{RsaPssSaltlen, Signer},
+};
+use serde_json::json;
+
+use crate::crypto::jwt::*;
+use crate::types::status_code::StatusCode;
+
+const NOW: i64 = 1_700_000_000;
+
+fn signing_input(alg: &str, claims: &serde_json::Value) -> String {
+    let header = json!({ "alg": alg, "typ": "JWT" });
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    )
+}
+
+fn make_token(signing_input: &str, signature: &[u8]) -> String {
+    format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature))
+}
+
+fn claims() -> serde_json::Value {
+    json!({
+        "iss": "https://issuer.example.com",
+        "aud": ["urn:other", "urn:server"],
+        "sub": "sample1",
+        "exp": NOW + 300,
+        "nbf": NOW - 300,
+    })
+}
+
+fn make_hs256_token(secret: &[u8], claims: &serde_json::Value) -> String {
+    let input = signing_input("HS256", claims);
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(input.as_bytes()).unwrap();
+    make_token(&input, &signer.sign_to_vec().unwrap())
+}
+
+fn make_rsa_token(alg: &str, key: &PKey<Private>, claims: &serde_json::Value) -> String {
+    let input = signing_input(alg, claims);
+    let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+    if alg == "PS256" {
+        signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        signer
+            .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+            .unwrap();
+    }
+    signer.update(input.as_bytes()).unwrap();
+    make_token(&input, &signer.sign_to_vec().unwrap())
+}
+
+fn public_key(key: &PKey<Private>) -> JwtVerificationKey {
+    JwtVerificationKey::from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+}
+
+#[test]
+fn hs256_signature() {
+    let token = make_hs256_token(b"secret", &claims());
+    let jwt = Jwt::decode(&token).unwrap();
+    assert_eq!(jwt.algorithm(), "HS256");
+    assert!(jwt
+        .verify_signature(&JwtVerificationKey::from_secret(b"secret"))
+        .is_ok());
+    assert_eq!(
+        jwt.verify_signature(&JwtVerificationKey::from_secret(b"secreT"))
+            .unwrap_err(),
+        StatusCode::BadIdentityTokenRejected
+    );
+
+    // A secret cannot verify a public key algorithm and vice versa
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    assert!(jwt.verify_signature(&public_key(&rsa)).is_err());
+    let token = make_rsa_token("RS256", &rsa, &claims());
+    let jwt = Jwt::decode(&token).unwrap();
+    assert!(jwt
+        .verify_signature(&JwtVerificationKey::from_secret(b"secret"))
+        .is_err());
+}
+
+#[test]
+fn rsa_signatures() {
+    let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let other = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    ["RS256", "PS256"].iter().for_each(|alg| {
+        let token = make_rsa_token(alg, &rsa, &claims());
+        let jwt = Jwt::decode(&token).unwrap();
+        assert!(jwt.verify_signature(&public_key(&rsa)).is_ok());
+        assert!(jwt.verify_signature(&public_key(&other)).is_err());
+    });
+
+    // The algorithm in the header must match how the token was signed
+    let token = make_rsa_token("RS256", &rsa, &claims());
+    let (_, signature) = token.rsplit_once('.').unwrap();
+    let input = signing_input("PS256", &claims());
+    let token = format!("{}.{}", input, signature);
+    let jwt = Jwt::decode(&token).unwrap();
+    assert!(jwt.verify_signature(&public_key(&rsa)).is_err());
+}
+
+#[test]
+fn es256_signature() {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let ec = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let input = signing_input("ES256", &claims());
+
+    // JWS wants the raw R and S values, each padded to 32 bytes
+    let digest = hash(MessageDigest::sha256(), input.as_bytes()).unwrap();
+    let sig = EcdsaSig::sign(&digest, ec.ec_key().unwrap().as_ref()).unwrap();
+    let mut signature = sig.r().to_vec_padded(32).unwrap();
+    signature.extend(sig.s().to_vec_padded(32).unwrap());
+
+    let jwt = Jwt::decode(&make_token(&input, &signature)).unwrap();
+    assert!(jwt.verify_signature(&public_key(&ec)).is_ok());
+
+    signature[0] ^= 0xff;
+    let jwt = Jwt::decode(&make_token(&input, &signature)).unwrap();
+    assert!(jwt.verify_signature(&public_key(&ec)).is_err());
+}
+
+#[test]
+fn es_signature_needs_matching_curve() {
+    let es512_signature = |curve| {
+        let group = EcGroup::from_curve_name(curve).unwrap();
+        let ec = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let input = signing_input("ES512", &claims());
+        let digest = hash(MessageDigest::sha512(), input.as_bytes()).unwrap();
+        let sig = EcdsaSig::sign(&digest, ec.ec_key().unwrap().as_ref()).unwrap();
+        let mut signature = sig.r().to_vec_padded(66).unwrap();
+        signature.extend(sig.s().to_vec_padded(66).unwrap());
+        let jwt = Jwt::decode(&make_token(&input, &signature)).unwrap();
+        jwt.verify_signature(&public_key(&ec))
+    };
+    assert!(es512_signature(Nid::SECP521R1).is_ok());
+    // A P-384 signature fits in the ES512 components but ES512 requires P-521
+    assert!(es512_signature(Nid::SECP384R1).is_err());
+}
+
+#[test]
+fn unsigned_token_rejected() {
+    let input = signing_input("none", &claims());
+    let jwt = Jwt::decode(&format!("{}.", input)).unwrap();
+    assert!(jwt
+        .verify_signature(&JwtVerificationKey::from_secret(b""))
+        .is_err());
+}
+
+#[test]
+fn malformed_tokens() {
+    let token = make_hs256_token(b"secret", &claims());
+    [
+        "",
+        "abc",
+        "a.b",
+        "a.b.c.d",
+        "!!!.e30.",
+        &format!("{}.extra", token),
+        // Payload is not a JSON object
+        &format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode("{\"alg\":\"HS256\"}"),
+            URL_SAFE_NO_PAD.encode("[]")
+        ),
+        // Header has no algorithm
+        &format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode("{}"),
+            URL_SAFE_NO_PAD.encode("{}")
+        ),
+    ]
+    .iter()
+    .for_each(|token| {
+        assert_eq!(
+            Jwt::decode(token).unwrap_err(),
+            StatusCode::BadIdentityTokenInvalid
+        );
+    });
+}
+
+#[test]
+fn validate_claims() {
+    const ISSUER: &str = "https://issuer.example.com";
+    let validate = |claims: serde_json::Value, now: i64| {
+        let jwt = Jwt::decode(&make_hs256_token(b"secret", &claims)).unwrap();
+        jwt.validate_claims(ISSUER, "urn:server", 60, now)
+    };
+
+    assert!(validate(claims(), NOW).is_ok());
+
+    // Audience may be a single string
+    let mut c = claims();
+    c["aud"] = json!("urn:server");
+    assert!(validate(c, NOW).is_ok());
+
+    // Wrong issuer or audience
+    let mut c = claims();
+    c["iss"] = json!("https://other.example.com");
+    assert!(validate(c, NOW).is_err());
+    let mut c = claims();
+    c["aud"] = json!(["urn:other"]);
+    assert!(validate(c, NOW).is_err());
+
+    // Expiry and not before honour the leeway
+    assert!(validate(claims(), NOW + 300 + 59).is_ok());
+    assert!(validate(claims(), NOW + 300 + 60).is_err());
+    assert!(validate(claims(), NOW - 300 - 60).is_ok());
+    assert!(validate(claims(), NOW - 300 - 61).is_err());
+
+    // Expiry is mandatory
+    let mut c = claims();
+    c.as_object_mut().unwrap().remove("exp");
+    assert_eq!(
+        validate(c, NOW).unwrap_err(),
+        StatusCode::BadIdentityTokenInvalid
+    );
+}
+
+#[test]
+fn claim_matches() {
+    let mut c = claims();
+    c["groups"] = json!(["operators", "engineers"]);
+    let jwt = Jwt::decode(&make_hs256_token(b"secret", &c)).unwrap();
+    assert!(jwt.claim_matches("sub", "sample1"));
+    assert!(!jwt.claim_matches("sub", "sample2"));
+    assert!(jwt.claim_matches("groups", "engineers"));
+    assert!(!jwt.claim_matches("groups", "admins"));
+    assert!(!jwt.claim_matches("missing", "sample1"));
+}
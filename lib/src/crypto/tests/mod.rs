@@ -48,5 +48,8 @@ fn make_test_cert_2048() -> (X509, PrivateKey) {
 }
 
 mod authentication;
+mod ca;
 mod crypto;
+mod jwt;
+mod password;
 mod security_policy;
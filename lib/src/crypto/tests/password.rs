@@ -0,0 +1,62 @@
+use crate::crypto::password::*;
+
+#[test]
+fn hash_and_verify_password() {
+    [
+        PasswordHashAlgorithm::Argon2id,
+        PasswordHashAlgorithm::Scrypt,
+        PasswordHashAlgorithm::Pbkdf2Sha256,
+        PasswordHashAlgorithm::Pbkdf2Sha512,
+    ]
+    .iter()
+    .for_each(|algorithm| {
+        let pass_hash = hash_password(*algorithm, b"sample1pwd").unwrap();
+        assert!(pass_hash.starts_with(&format!("${}$", algorithm)));
+        assert!(is_valid_password_hash(&pass_hash));
+        assert!(verify_password(&pass_hash, b"sample1pwd").unwrap());
+        assert!(!verify_password(&pass_hash, b"sample1pwx").unwrap());
+        assert!(!verify_password(&pass_hash, b"").unwrap());
+
+        // Same password hashes differently because of the salt
+        let pass_hash2 = hash_password(*algorithm, b"sample1pwd").unwrap();
+        assert_ne!(pass_hash, pass_hash2);
+    });
+}
+
+#[test]
+fn verify_known_password_hash() {
+    // PBKDF2-HMAC-SHA256 of "sample1pwd", 1000 iterations, salt "saltsaltsaltsalt" produced by
+    // another implementation
+    let pass_hash =
+        "$pbkdf2-sha256$i=1000$c2FsdHNhbHRzYWx0c2FsdA$95xA8rkNCgomAdxzISqWJHQgHSH9K7Q3xcbKe9gYb2k";
+    assert!(is_valid_password_hash(pass_hash));
+    assert!(verify_password(pass_hash, b"sample1pwd").unwrap());
+    assert!(!verify_password(pass_hash, b"sample2pwd").unwrap());
+}
+
+#[test]
+fn invalid_password_hash() {
+    [
+        "",
+        "sample1pwd",
+        "$md5$abc$def",
+        "$pbkdf2-sha256$i=0$c2FsdA$a2V5",
+        "$pbkdf2-sha256$c2FsdA$a2V5",
+        "$pbkdf2-sha256$i=1000$$a2V5",
+        "$scrypt$ln=15,r=8$c2FsdA$a2V5",
+        "$argon2id$v=19$m=19456,t=2,p=1$!!!$a2V5",
+    ]
+    .iter()
+    .for_each(|pass_hash| {
+        assert!(!is_valid_password_hash(pass_hash));
+        assert!(verify_password(pass_hash, b"sample1pwd").is_err());
+    });
+}
+
+#[test]
+fn constant_time_compare() {
+    assert!(constant_time_eq(b"", b""));
+    assert!(constant_time_eq(b"abc", b"abc"));
+    assert!(!constant_time_eq(b"abc", b"abd"));
+    assert!(!constant_time_eq(b"abc", b"abcd"));
+}
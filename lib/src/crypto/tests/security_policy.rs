@@ -1,6 +1,20 @@
 use std::str::FromStr;
 
-use crate::crypto::SecurityPolicy;
+use openssl::{
+    asn1::Asn1Time,
+    hash::MessageDigest,
+    x509::{X509Builder, X509NameBuilder},
+};
+
+use crate::{
+    crypto::{
+        pkey::PrivateKey,
+        tests::{make_certificate_store, make_test_cert_1024, make_test_cert_2048},
+        x509::{CertificateSignatureHash, X509},
+        CertificateStore, SecurityPolicy, SecurityProfile,
+    },
+    types::status_code::StatusCode,
+};
 
 #[test]
 fn is_deprecated() {
@@ -150,3 +164,186 @@ fn is_valid_keylength() {
     assert!(!SecurityPolicy::Aes256Sha256RsaPss.is_valid_keylength(1024));
     assert!(!SecurityPolicy::Aes256Sha256RsaPss.is_valid_keylength(8192));
 }
+
+#[test]
+fn security_profile_policies() {
+    let profile = SecurityProfile::default();
+    assert!(profile.is_policy_allowed(SecurityPolicy::None));
+    assert!(profile.is_policy_allowed(SecurityPolicy::Basic256Sha256));
+    assert!(profile.is_policy_allowed(SecurityPolicy::Aes128Sha256RsaOaep));
+    assert!(profile.is_policy_allowed(SecurityPolicy::Aes256Sha256RsaPss));
+    assert!(!profile.is_policy_allowed(SecurityPolicy::Basic128Rsa15));
+    assert!(!profile.is_policy_allowed(SecurityPolicy::Basic256));
+    assert!(!profile.is_policy_allowed(SecurityPolicy::Unknown));
+
+    let profile = SecurityProfile {
+        allow_deprecated_policies: true,
+        ..Default::default()
+    };
+    assert!(profile.is_policy_allowed(SecurityPolicy::Basic128Rsa15));
+    assert!(profile.is_policy_allowed(SecurityPolicy::Basic256));
+}
+
+#[test]
+fn security_profile_key_length() {
+    // The default profile leaves the policy's key lengths alone
+    let profile = SecurityProfile::default();
+    assert_eq!(
+        profile.min_max_key_length(SecurityPolicy::Basic128Rsa15),
+        (1024, 2048)
+    );
+    assert_eq!(
+        profile.min_max_key_length(SecurityPolicy::Basic256Sha256),
+        (2048, 4096)
+    );
+
+    // A profile can only narrow the policy's key lengths
+    let profile = SecurityProfile {
+        min_key_length: 2048,
+        max_key_length: 8192,
+        ..Default::default()
+    };
+    assert!(profile.is_valid());
+    assert!(!profile.is_valid_key_length(SecurityPolicy::Basic128Rsa15, 1024));
+    assert!(profile.is_valid_key_length(SecurityPolicy::Basic128Rsa15, 2048));
+    assert!(profile.is_valid_key_length(SecurityPolicy::Basic256Sha256, 4096));
+    assert!(!profile.is_valid_key_length(SecurityPolicy::Basic256Sha256, 8192));
+
+    let profile = SecurityProfile {
+        min_key_length: 4096,
+        max_key_length: 2048,
+        ..Default::default()
+    };
+    assert!(!profile.is_valid());
+}
+
+#[test]
+fn is_valid_certificate_signature_hash() {
+    use CertificateSignatureHash::*;
+    for policy in [SecurityPolicy::Basic128Rsa15, SecurityPolicy::Basic256] {
+        assert!(policy.is_valid_certificate_signature_hash(Sha1));
+        assert!(policy.is_valid_certificate_signature_hash(Sha256));
+        assert!(!policy.is_valid_certificate_signature_hash(Md5));
+    }
+    for policy in [
+        SecurityPolicy::Basic256Sha256,
+        SecurityPolicy::Aes128Sha256RsaOaep,
+        SecurityPolicy::Aes256Sha256RsaPss,
+    ] {
+        assert!(!policy.is_valid_certificate_signature_hash(Sha1));
+        assert!(!policy.is_valid_certificate_signature_hash(Md5));
+        assert!(!policy.is_valid_certificate_signature_hash(Unknown));
+        assert!(policy.is_valid_certificate_signature_hash(Sha256));
+        assert!(policy.is_valid_certificate_signature_hash(Sha384));
+        assert!(policy.is_valid_certificate_signature_hash(Sha512));
+    }
+}
+
+/// Makes a trusted self-signed cert whose signature uses the supplied hash
+fn make_trusted_cert_with_hash(cert_store: &CertificateStore, digest: MessageDigest) -> X509 {
+    let pkey = PrivateKey::new(2048);
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "x").unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&pkey.value).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+        .unwrap();
+    builder.sign(&pkey.value, digest).unwrap();
+    let cert = X509::from(builder.build());
+    std::fs::write(
+        cert_store
+            .trusted_certs_dir()
+            .join(CertificateStore::cert_file_name(&cert)),
+        cert.to_der().unwrap(),
+    )
+    .unwrap();
+    cert
+}
+
+#[test]
+fn validate_cert_against_security_profile() {
+    let (_tmp_dir, mut cert_store) = make_certificate_store();
+    let validate = |cert_store: &CertificateStore, cert: &X509, security_policy| {
+        cert_store.validate_application_instance_cert(cert, security_policy, None, None)
+    };
+
+    // Deprecated policies are rejected unless the profile allows them
+    let cert = make_trusted_cert_with_hash(&cert_store, MessageDigest::sha256());
+    assert_eq!(
+        validate(&cert_store, &cert, SecurityPolicy::Basic128Rsa15),
+        StatusCode::BadSecurityPolicyRejected
+    );
+    assert!(validate(&cert_store, &cert, SecurityPolicy::Basic256Sha256).is_good());
+
+    // SHA-1 signatures are only acceptable to the deprecated policies
+    let sha1_cert = make_trusted_cert_with_hash(&cert_store, MessageDigest::sha1());
+    assert_eq!(sha1_cert.signature_hash(), CertificateSignatureHash::Sha1);
+    assert_eq!(
+        validate(&cert_store, &sha1_cert, SecurityPolicy::Basic256Sha256),
+        StatusCode::BadCertificatePolicyCheckFailed
+    );
+    cert_store.set_security_profile(SecurityProfile {
+        allow_deprecated_policies: true,
+        ..Default::default()
+    });
+    assert!(validate(&cert_store, &sha1_cert, SecurityPolicy::Basic256).is_good());
+    assert!(validate(&cert_store, &cert, SecurityPolicy::Basic128Rsa15).is_good());
+
+    // The profile can raise the minimum key length
+    cert_store.set_security_profile(SecurityProfile {
+        allow_deprecated_policies: true,
+        min_key_length: 4096,
+        ..Default::default()
+    });
+    assert_eq!(
+        validate(&cert_store, &cert, SecurityPolicy::Basic256Sha256),
+        StatusCode::BadSecurityChecksFailed
+    );
+}
+
+#[test]
+fn signature_hash() {
+    let (cert, _) = make_test_cert_1024();
+    assert_eq!(cert.signature_hash(), CertificateSignatureHash::Sha256);
+    let (cert, _) = make_test_cert_2048();
+    assert_eq!(cert.signature_hash(), CertificateSignatureHash::Sha256);
+}
+
+/// A self-signed certificate with an RSASSA-PSS signature using SHA-384
+const RSASSA_PSS_SHA384_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIDZzCCAhugAwIBAgIUeYZ1KbAQlsY2OXMkE0NwaUT6OzwwQQYJKoZIhvcNAQEK
+MDSgDzANBglghkgBZQMEAgIFAKEcMBoGCSqGSIb3DQEBCDANBglghkgBZQMEAgIF
+AKIDAgEgMA4xDDAKBgNVBAMMA3BzczAgFw0yNjEwMTkxMDI1MDNaGA8yMTI2MDky
+NTEwMjUwM1owDjEMMAoGA1UEAwwDcHNzMIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8A
+MIIBCgKCAQEAtSslmYAcjCdPbY/bCKxfhVVNel6xIQ0bnKWyJU9zKHs2+CboyIyX
+II6UnDvsV5mPOO8oNBN9ldbhIk2yp04ZxVLE2FBZlNwzdUWQrxyFCyxrj4NAkUuI
+Tyi+KLF9CnLt33PqLuwhVX7MWjpZOM1bfh/eMQ1nCvdqCQnyI72gPrwcaKov3EIH
+06GO4nx9LWUwf4xjNAvaf3kefpi7Skab0r2otkKIiMjYVe1FZOqPQ/HSHQPM1OxT
+IVBzdZZev4n7Ir34d/0WMZsc6Tzc4XkRD8t1JWWFWquajkEF+FdSOZnFaqD+aylU
+QZleqP99FTJ4CNeVhPPkuZ7GVAuR1FXBfwIDAQABo1MwUTAdBgNVHQ4EFgQUKsNU
+kufP6Zl78gm4w4aUn8LFx7IwHwYDVR0jBBgwFoAUKsNUkufP6Zl78gm4w4aUn8LF
+x7IwDwYDVR0TAQH/BAUwAwEB/zBBBgkqhkiG9w0BAQowNKAPMA0GCWCGSAFlAwQC
+AgUAoRwwGgYJKoZIhvcNAQEIMA0GCWCGSAFlAwQCAgUAogMCASADggEBABfWiFka
+MHPB4FnCRzYlh7nH+jF96Opxq3qHqQrppXBTN+L1p+HdTN9fpusn1zRXYaApLfHl
+EWyVSGQmSsubK9cYw9tQqlTeEPctdFa0DNOaPUVG0mnN3yS/VkAriWw4nA5dykHu
+jzje4jYcVZ2IPu9qZc106B6cxVpnK54aNPtNan+RfQnJb34qVU5ahzUMN6bNwFgb
+Qp5R2lKgvlGaw1fMhvk3VHge/LUIdvbaNS6dSh3R1EK84gr3qfXjDAkwxQ9EEyHT
+mXEYLDDjq3Pmkiz3bfRZADMoohgmhX973ItxZSuo36gMRX8hZSx/BwdZwrGhe8RK
+RNkmFyhiPpOPoqg=
+-----END CERTIFICATE-----";
+
+#[test]
+fn signature_hash_rsassa_pss() {
+    let cert =
+        X509::from(openssl::x509::X509::from_pem(RSASSA_PSS_SHA384_CERT.as_bytes()).unwrap());
+    assert_eq!(cert.signature_hash(), CertificateSignatureHash::Sha384);
+}
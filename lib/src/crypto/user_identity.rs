@@ -2,9 +2,10 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-//! Functions related to encrypting / decrypting passwords in a UserNameIdentityToken.
+//! Functions related to encrypting / decrypting passwords in a UserNameIdentityToken and the
+//! token data in an IssuedIdentityToken.
 //!
-//! The code here determines how or if to encrypt the password depending on the security policy
+//! The code here determines how or if to encrypt the secret depending on the security policy
 //! and user token policy.
 
 use std::io::{Cursor, Write};
@@ -12,7 +13,10 @@ use std::str::FromStr;
 
 use crate::types::{
     encoding::{read_u32, write_u32},
-    service_types::{SignatureData, UserNameIdentityToken, UserTokenPolicy, X509IdentityToken},
+    service_types::{
+        IssuedIdentityToken, SignatureData, UserNameIdentityToken, UserTokenPolicy,
+        X509IdentityToken,
+    },
     status_code::StatusCode,
     ByteString, UAString,
 };
@@ -28,6 +32,53 @@ pub fn make_user_name_identity_token(
     user: &str,
     pass: &str,
 ) -> Result<UserNameIdentityToken, StatusCode> {
+    let (password, encryption_algorithm) = encrypt_user_token_secret(
+        channel_security_policy,
+        user_token_policy,
+        nonce,
+        cert,
+        pass.as_bytes(),
+    )?;
+    Ok(UserNameIdentityToken {
+        policy_id: user_token_policy.policy_id.clone(),
+        user_name: UAString::from(user),
+        password,
+        encryption_algorithm,
+    })
+}
+
+/// Create a filled in IssuedIdentityToken by using the supplied channel security policy, user token policy, nonce, cert
+/// and the token data, e.g. the JWT obtained from an authorization service.
+pub fn make_issued_identity_token(
+    channel_security_policy: SecurityPolicy,
+    user_token_policy: &UserTokenPolicy,
+    nonce: &[u8],
+    cert: &Option<X509>,
+    token_data: &[u8],
+) -> Result<IssuedIdentityToken, StatusCode> {
+    let (token_data, encryption_algorithm) = encrypt_user_token_secret(
+        channel_security_policy,
+        user_token_policy,
+        nonce,
+        cert,
+        token_data,
+    )?;
+    Ok(IssuedIdentityToken {
+        policy_id: user_token_policy.policy_id.clone(),
+        token_data,
+        encryption_algorithm,
+    })
+}
+
+/// Encrypts a user token secret (a password or issued token data) and returns it with the
+/// encryption algorithm that was used, or a null algorithm if the secret is sent as is.
+fn encrypt_user_token_secret(
+    channel_security_policy: SecurityPolicy,
+    user_token_policy: &UserTokenPolicy,
+    nonce: &[u8],
+    cert: &Option<X509>,
+    secret: &[u8],
+) -> Result<(ByteString, UAString), StatusCode> {
     // This is a condensed version of Table 187 Opc Part 4 that details the EncryptionAlgorithm
     // selection.
     //
@@ -51,40 +102,33 @@ pub fn make_user_name_identity_token(
         }
     };
 
-    // Now it should be a matter of using the policy (or lack thereof) to encrypt the password
+    // Now it should be a matter of using the policy (or lack thereof) to encrypt the secret
     // using the secure channel's cert and nonce.
-    let (password, encryption_algorithm) = match security_policy {
+    match security_policy {
         SecurityPolicy::None => {
             // Plain text
             if channel_security_policy == SecurityPolicy::None {
-                warn!("A user identity's password is being sent over the network in plain text. This could be a serious security issue");
+                warn!("A user identity's secret is being sent over the network in plain text. This could be a serious security issue");
             }
-            (ByteString::from(pass.as_bytes()), UAString::null())
+            Ok((ByteString::from(secret), UAString::null()))
         }
         SecurityPolicy::Unknown => {
             // This should only happen if channel_security_policy were Unknown when it shouldn't be
             panic!("Don't know how to make the token for this server");
         }
         security_policy => {
-            // Create a password which is encrypted using the secure channel info and the user token policy for the endpoint
-            let password = legacy_password_encrypt(
-                pass,
+            // Create a secret which is encrypted using the secure channel info and the user token policy for the endpoint
+            let secret = legacy_secret_encrypt(
+                secret,
                 nonce,
                 cert.as_ref().unwrap(),
                 security_policy.asymmetric_encryption_padding(),
             )?;
             let encryption_algorithm =
                 UAString::from(security_policy.asymmetric_encryption_algorithm());
-            (password, encryption_algorithm)
+            Ok((secret, encryption_algorithm))
         }
-    };
-
-    Ok(UserNameIdentityToken {
-        policy_id: user_token_policy.policy_id.clone(),
-        user_name: UAString::from(user),
-        password,
-        encryption_algorithm,
-    })
+    }
 }
 
 /// Decrypt the password inside of a user identity token.
@@ -97,17 +141,7 @@ pub fn decrypt_user_identity_token_password(
         // Assumed to be UTF-8 plain text
         user_identity_token.plaintext_password()
     } else {
-        // Determine the padding from the algorithm.
-        let encryption_algorithm = user_identity_token.encryption_algorithm.as_ref();
-        let padding = match encryption_algorithm {
-            super::algorithms::ENC_RSA_15 => RsaPadding::Pkcs1,
-            super::algorithms::ENC_RSA_OAEP => RsaPadding::OaepSha1,
-            super::algorithms::ENC_RSA_OAEP_SHA256 => RsaPadding::OaepSha256,
-            _ => {
-                error!("decrypt_user_identity_token_password has rejected unsupported user identity encryption algorithm \"{}\"", encryption_algorithm);
-                return Err(StatusCode::BadIdentityTokenInvalid);
-            }
-        };
+        let padding = padding_from_algorithm(user_identity_token.encryption_algorithm.as_ref())?;
         legacy_password_decrypt(
             &user_identity_token.password,
             server_nonce,
@@ -117,6 +151,45 @@ pub fn decrypt_user_identity_token_password(
     }
 }
 
+/// Decrypt the token data inside of an issued identity token.
+pub fn decrypt_issued_identity_token(
+    issued_identity_token: &IssuedIdentityToken,
+    server_nonce: &[u8],
+    server_key: &PrivateKey,
+) -> Result<Vec<u8>, StatusCode> {
+    if issued_identity_token.encryption_algorithm.is_empty() {
+        issued_identity_token
+            .token_data
+            .value
+            .clone()
+            .ok_or(StatusCode::BadIdentityTokenInvalid)
+    } else {
+        let padding = padding_from_algorithm(issued_identity_token.encryption_algorithm.as_ref())?;
+        legacy_secret_decrypt(
+            &issued_identity_token.token_data,
+            server_nonce,
+            server_key,
+            padding,
+        )
+    }
+}
+
+/// Determine the padding from the encryption algorithm of a user identity token.
+fn padding_from_algorithm(encryption_algorithm: &str) -> Result<RsaPadding, StatusCode> {
+    match encryption_algorithm {
+        super::algorithms::ENC_RSA_15 => Ok(RsaPadding::Pkcs1),
+        super::algorithms::ENC_RSA_OAEP => Ok(RsaPadding::OaepSha1),
+        super::algorithms::ENC_RSA_OAEP_SHA256 => Ok(RsaPadding::OaepSha256),
+        _ => {
+            error!(
+                "Rejected unsupported user identity encryption algorithm \"{}\"",
+                encryption_algorithm
+            );
+            Err(StatusCode::BadIdentityTokenInvalid)
+        }
+    }
+}
+
 /// Encrypt a client side user's password using the server nonce and cert. This is described in table 176
 /// OPC UA part 4. This function is prefixed "legacy" because 1.04 describes another way of encrypting passwords.
 pub fn legacy_password_encrypt(
@@ -125,13 +198,24 @@ pub fn legacy_password_encrypt(
     server_cert: &X509,
     padding: RsaPadding,
 ) -> Result<ByteString, StatusCode> {
-    // Message format is size, password, nonce
-    let plaintext_size = 4 + password.len() + server_nonce.len();
+    legacy_secret_encrypt(password.as_bytes(), server_nonce, server_cert, padding)
+}
+
+/// Encrypt a secret using the server nonce and cert in the legacy format, i.e. the length, the secret
+/// and the nonce encrypted with the server's public key.
+pub fn legacy_secret_encrypt(
+    secret: &[u8],
+    server_nonce: &[u8],
+    server_cert: &X509,
+    padding: RsaPadding,
+) -> Result<ByteString, StatusCode> {
+    // Message format is size, secret, nonce
+    let plaintext_size = 4 + secret.len() + server_nonce.len();
     let mut src = Cursor::new(vec![0u8; plaintext_size]);
 
     // Write the length of the data to be encrypted excluding the length itself)
     write_u32(&mut src, (plaintext_size - 4) as u32)?;
-    src.write(password.as_bytes())
+    src.write(secret)
         .map_err(|_| StatusCode::BadEncodingError)?;
     src.write(server_nonce)
         .map_err(|_| StatusCode::BadEncodingError)?;
@@ -158,6 +242,17 @@ pub fn legacy_password_decrypt(
     server_key: &PrivateKey,
     padding: RsaPadding,
 ) -> Result<String, StatusCode> {
+    let password = legacy_secret_decrypt(secret, server_nonce, server_key, padding)?;
+    String::from_utf8(password).map_err(|_| StatusCode::BadEncodingError)
+}
+
+/// Decrypt a secret in the legacy format using the server's nonce and private key.
+pub fn legacy_secret_decrypt(
+    secret: &ByteString,
+    server_nonce: &[u8],
+    server_key: &PrivateKey,
+    padding: RsaPadding,
+) -> Result<Vec<u8>, StatusCode> {
     if secret.is_null() {
         Err(StatusCode::BadDecodingError)
     } else {
@@ -180,10 +275,7 @@ pub fn legacy_password_decrypt(
             if nonce != server_nonce {
                 Err(StatusCode::BadDecodingError)
             } else {
-                let password = &dst[4..nonce_begin];
-                let password = String::from_utf8(password.to_vec())
-                    .map_err(|_| StatusCode::BadEncodingError)?;
-                Ok(password)
+                Ok(dst[4..nonce_begin].to_vec())
             }
         }
     }
@@ -161,6 +161,26 @@ impl X509Data {
         }
     }
 
+    /// Makes the subject name of a certificate from the data
+    pub(crate) fn x509_name(&self) -> Result<x509::X509Name, String> {
+        let err = |e: openssl::error::ErrorStack| format!("Cannot build subject name, {}", e);
+        let mut name = x509::X509NameBuilder::new().map_err(err)?;
+        // Common name
+        name.append_entry_by_text("CN", &self.common_name)
+            .map_err(err)?;
+        // Organization
+        name.append_entry_by_text("O", &self.organization)
+            .map_err(err)?;
+        // Organizational Unit
+        name.append_entry_by_text("OU", &self.organizational_unit)
+            .map_err(err)?;
+        // Country
+        name.append_entry_by_text("C", &self.country).map_err(err)?;
+        // State
+        name.append_entry_by_text("ST", &self.state).map_err(err)?;
+        Ok(name.build())
+    }
+
     /// Creates a sample certificate for testing, sample purposes only
     pub fn sample_cert() -> X509Data {
         let alt_host_names = Self::alt_host_names("urn:OPCUADemo", None, false, true, true);
@@ -177,6 +197,30 @@ impl X509Data {
     }
 }
 
+/// What a certificate built by this module is for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum CertificateUsage {
+    /// A self-signed application instance certificate
+    SelfSigned,
+    /// An application instance certificate issued by a certificate authority
+    Issued,
+    /// A certificate authority's certificate
+    CertificateAuthority,
+}
+
+/// The hash algorithm of a certificate's signature
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CertificateSignatureHash {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    /// Any other hash, or one that cannot be determined from the signature algorithm
+    Unknown,
+}
+
 #[derive(Debug)]
 pub struct X509Error;
 
@@ -191,7 +235,7 @@ impl std::error::Error for X509Error {}
 /// This is a wrapper around the `OpenSSL` `X509` cert
 #[derive(Clone)]
 pub struct X509 {
-    value: x509::X509,
+    pub(crate) value: x509::X509,
 }
 
 impl Debug for X509 {
@@ -245,72 +289,129 @@ impl X509 {
     }
 
     pub fn from_pkey(pkey: &PrivateKey, x509_data: &X509Data) -> Result<Self, String> {
-        let mut builder = x509::X509Builder::new().unwrap();
+        // Issuer and subject shall be the same for self-signed cert
+        let subject_name = x509_data.x509_name()?;
+        Self::build(
+            &pkey.value,
+            &subject_name,
+            &x509_data.alt_host_names,
+            x509_data.certificate_duration_days,
+            CertificateUsage::SelfSigned,
+            None,
+            pkey,
+        )
+    }
+
+    /// Builds a certificate holding the public key and signs it with the signing key. The issuer is
+    /// the certificate of the signing key, or none for a self-signed certificate.
+    pub(crate) fn build<T>(
+        public_key: &pkey::PKeyRef<T>,
+        subject_name: &x509::X509NameRef,
+        alt_host_names: &[String],
+        certificate_duration_days: u32,
+        usage: CertificateUsage,
+        issuer: Option<&X509>,
+        signing_key: &PrivateKey,
+    ) -> Result<Self, String>
+    where
+        T: pkey::HasPublic,
+    {
+        let err = |e: openssl::error::ErrorStack| format!("Cannot build certificate, {}", e);
+        let mut builder = x509::X509Builder::new().map_err(err)?;
         // value 2 == version 3 (go figure)
         let _ = builder.set_version(2);
-        let issuer_name = {
-            let mut name = x509::X509NameBuilder::new().unwrap();
-            // Common name
-            name.append_entry_by_text("CN", &x509_data.common_name)
-                .unwrap();
-            // Organization
-            name.append_entry_by_text("O", &x509_data.organization)
-                .unwrap();
-            // Organizational Unit
-            name.append_entry_by_text("OU", &x509_data.organizational_unit)
-                .unwrap();
-            // Country
-            name.append_entry_by_text("C", &x509_data.country).unwrap();
-            // State
-            name.append_entry_by_text("ST", &x509_data.state).unwrap();
-            name.build()
-        };
-        // Issuer and subject shall be the same for self-signed cert
-        let _ = builder.set_subject_name(&issuer_name);
-        let _ = builder.set_issuer_name(&issuer_name);
+        builder.set_subject_name(subject_name).map_err(err)?;
+        if let Some(issuer) = issuer {
+            builder
+                .set_issuer_name(issuer.value.subject_name())
+                .map_err(err)?;
+        } else {
+            builder.set_issuer_name(subject_name).map_err(err)?;
+        }
 
         // For Application Instance Certificate specifies how cert may be used
-        let key_usage = KeyUsage::new()
-            .digital_signature()
-            .non_repudiation()
-            .key_encipherment()
-            .data_encipherment()
-            .key_cert_sign()
-            .build()
-            .unwrap();
-        let _ = builder.append_extension(key_usage);
-        let extended_key_usage = ExtendedKeyUsage::new()
-            .client_auth()
-            .server_auth()
-            .build()
-            .unwrap();
-        let _ = builder.append_extension(extended_key_usage);
+        match usage {
+            CertificateUsage::SelfSigned | CertificateUsage::Issued => {
+                let mut key_usage = KeyUsage::new();
+                key_usage
+                    .digital_signature()
+                    .non_repudiation()
+                    .key_encipherment()
+                    .data_encipherment();
+                // OPC UA requires keyCertSign on a self-signed cert, which signs itself. It is
+                // not a CA, so it is still never trusted as the issuer of another cert.
+                if usage == CertificateUsage::SelfSigned {
+                    key_usage.key_cert_sign();
+                }
+                builder
+                    .append_extension(key_usage.build().map_err(err)?)
+                    .map_err(err)?;
+                let extended_key_usage = ExtendedKeyUsage::new()
+                    .client_auth()
+                    .server_auth()
+                    .build()
+                    .map_err(err)?;
+                builder.append_extension(extended_key_usage).map_err(err)?;
+            }
+            CertificateUsage::CertificateAuthority => {
+                let basic_constraints = BasicConstraints::new().critical().ca().build();
+                builder
+                    .append_extension(basic_constraints.map_err(err)?)
+                    .map_err(err)?;
+                let key_usage = KeyUsage::new()
+                    .critical()
+                    .digital_signature()
+                    .key_cert_sign()
+                    .crl_sign()
+                    .build()
+                    .map_err(err)?;
+                builder.append_extension(key_usage).map_err(err)?;
+            }
+        }
 
-        builder
-            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
-            .unwrap();
-        builder
-            .set_not_after(&Asn1Time::days_from_now(x509_data.certificate_duration_days).unwrap())
-            .unwrap();
-        builder.set_pubkey(&pkey.value).unwrap();
+        let not_before = Asn1Time::days_from_now(0).map_err(err)?;
+        builder.set_not_before(&not_before).map_err(err)?;
+        let not_after = Asn1Time::days_from_now(certificate_duration_days).map_err(err)?;
+        builder.set_not_after(&not_after).map_err(err)?;
+        builder.set_pubkey(public_key).map_err(err)?;
 
         // Random serial number
         {
             use openssl::bn::BigNum;
             use openssl::bn::MsbOption;
-            let mut serial = BigNum::new().unwrap();
-            serial.rand(128, MsbOption::MAYBE_ZERO, false).unwrap();
-            let serial = serial.to_asn1_integer().unwrap();
+            let mut serial = BigNum::new().map_err(err)?;
+            serial
+                .rand(128, MsbOption::MAYBE_ZERO, false)
+                .map_err(err)?;
+            let serial = serial.to_asn1_integer().map_err(err)?;
             let _ = builder.set_serial_number(&serial);
         }
 
+        // A cert issued by a CA identifies its own key and the key of the CA
+        if usage != CertificateUsage::SelfSigned {
+            let subject_key_identifier = SubjectKeyIdentifier::new()
+                .build(&builder.x509v3_context(None, None))
+                .map_err(err)?;
+            builder
+                .append_extension(subject_key_identifier)
+                .map_err(err)?;
+            if let Some(issuer) = issuer {
+                let authority_key_identifier = AuthorityKeyIdentifier::new()
+                    .keyid(false)
+                    .build(&builder.x509v3_context(Some(&issuer.value), None))
+                    .map_err(err)?;
+                builder
+                    .append_extension(authority_key_identifier)
+                    .map_err(err)?;
+            }
+        }
+
         // Subject alt names - The first is assumed to be the application uri. The remainder
         // are either IP or DNS entries.
-        if !x509_data.alt_host_names.is_empty() {
+        if !alt_host_names.is_empty() {
             let subject_alternative_name = {
                 let mut subject_alternative_name = SubjectAlternativeName::new();
-                x509_data
-                    .alt_host_names
+                alt_host_names
                     .iter()
                     .enumerate()
                     .for_each(|(i, alt_host_name)| {
@@ -330,14 +431,17 @@ impl X509 {
                         }
                     });
                 subject_alternative_name
-                    .build(&builder.x509v3_context(None, None))
-                    .unwrap()
+                    .build(&builder.x509v3_context(issuer.map(|i| i.value.as_ref()), None))
+                    .map_err(err)?
             };
-            builder.append_extension(subject_alternative_name).unwrap();
+            builder
+                .append_extension(subject_alternative_name)
+                .map_err(err)?;
         }
 
-        // Self-sign
-        let _ = builder.sign(&pkey.value, hash::MessageDigest::sha256());
+        builder
+            .sign(&signing_key.value, hash::MessageDigest::sha256())
+            .map_err(err)?;
 
         Ok(X509::from(builder.build()))
     }
@@ -376,6 +480,75 @@ impl X509 {
         Ok(pub_key.size() * 8)
     }
 
+    /// Returns the hash algorithm of the certificate's signature
+    pub fn signature_hash(&self) -> CertificateSignatureHash {
+        let nid = self.value.signature_algorithm().object().nid();
+        if nid == Nid::RSASSAPSS {
+            // The hash of an RSASSA-PSS signature is in the parameters of the algorithm
+            return self
+                .value
+                .to_der()
+                .ok()
+                .and_then(|der| Self::rsassa_pss_hash(&der))
+                .unwrap_or(CertificateSignatureHash::Unknown);
+        }
+        let digest = nid
+            .signature_algorithms()
+            .map(|algorithms| algorithms.digest);
+        match digest {
+            Some(Nid::MD5) => CertificateSignatureHash::Md5,
+            Some(Nid::SHA1) => CertificateSignatureHash::Sha1,
+            Some(Nid::SHA224) => CertificateSignatureHash::Sha224,
+            Some(Nid::SHA256) => CertificateSignatureHash::Sha256,
+            Some(Nid::SHA384) => CertificateSignatureHash::Sha384,
+            Some(Nid::SHA512) => CertificateSignatureHash::Sha512,
+            _ => CertificateSignatureHash::Unknown,
+        }
+    }
+
+    /// Reads the hash algorithm from the RSASSA-PSS parameters of the signature algorithm of a
+    /// DER encoded certificate. A missing hash algorithm means SHA-1 (RFC 4055).
+    fn rsassa_pss_hash(der: &[u8]) -> Option<CertificateSignatureHash> {
+        // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+        let (0x30, certificate, _) = der_element(der)? else {
+            return None;
+        };
+        let (_, _, rest) = der_element(certificate)?;
+        // AlgorithmIdentifier ::= SEQUENCE { algorithm OID, parameters }
+        let (0x30, algorithm, _) = der_element(rest)? else {
+            return None;
+        };
+        let (0x06, _, parameters) = der_element(algorithm)? else {
+            return None;
+        };
+        // RSASSA-PSS-params ::= SEQUENCE { hashAlgorithm [0] AlgorithmIdentifier DEFAULT sha1, .. }
+        let (0x30, parameters, _) = der_element(parameters)? else {
+            return None;
+        };
+        let hash_algorithm = match der_element(parameters) {
+            Some((0xa0, hash_algorithm, _)) => hash_algorithm,
+            _ => return Some(CertificateSignatureHash::Sha1),
+        };
+        let (0x30, hash_algorithm, _) = der_element(hash_algorithm)? else {
+            return None;
+        };
+        let (0x06, oid, _) = der_element(hash_algorithm)? else {
+            return None;
+        };
+        // 2.16.840.1.101.3.4.2.x is the NIST hash algorithm arc
+        const NIST_HASH_ALGORITHMS: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02];
+        const SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+        let hash = match oid {
+            SHA1 => CertificateSignatureHash::Sha1,
+            [arc @ .., 0x01] if arc == NIST_HASH_ALGORITHMS => CertificateSignatureHash::Sha256,
+            [arc @ .., 0x02] if arc == NIST_HASH_ALGORITHMS => CertificateSignatureHash::Sha384,
+            [arc @ .., 0x03] if arc == NIST_HASH_ALGORITHMS => CertificateSignatureHash::Sha512,
+            [arc @ .., 0x04] if arc == NIST_HASH_ALGORITHMS => CertificateSignatureHash::Sha224,
+            _ => CertificateSignatureHash::Unknown,
+        };
+        Some(hash)
+    }
+
     fn get_subject_entry(&self, nid: Nid) -> Result<String, X509Error> {
         let subject_name = self.value.subject_name();
         let mut entries = subject_name.entries_by_nid(nid);
@@ -575,6 +748,72 @@ impl X509 {
         Self::parse_asn1_date(&date)
     }
 
+    /// Tests if the certificate expires within the supplied number of days of now, or has
+    /// already expired.
+    pub fn expires_within(&self, now: &DateTime<Utc>, days: u32) -> bool {
+        if let Ok(not_after) = self.not_after() {
+            not_after <= *now + chrono::Duration::days(days as i64)
+        } else {
+            false
+        }
+    }
+
+    /// Tests if the certificate is self-signed, i.e. its issuer is its subject and it is signed
+    /// by its own key.
+    pub fn is_self_signed(&self) -> bool {
+        // Not `issued`, which also requires the key usage of a CA
+        self.value
+            .issuer_name()
+            .try_cmp(self.value.subject_name())
+            .map(|o| o.is_eq())
+            .unwrap_or(false)
+            && self
+                .value
+                .public_key()
+                .and_then(|pkey| self.value.verify(&pkey))
+                .unwrap_or(false)
+    }
+
+    /// Tests if the certificate is a certificate authority, i.e. its basic constraints say CA:TRUE.
+    pub fn is_ca(&self) -> bool {
+        use foreign_types::ForeignTypeRef;
+        // The openssl crate has no accessor for the basic constraints so this calls the ffi
+        // function directly
+        let flags = unsafe { openssl_sys::X509_get_extension_flags(self.value.as_ptr()) };
+        flags & openssl_sys::EXFLAG_CA != 0
+    }
+
+    /// Produces the data to create a replacement for this certificate, i.e. one with the same
+    /// subject, application uri, alt host names, key size and duration.
+    pub fn to_x509_data(&self) -> Result<X509Data, X509Error> {
+        let application_uri = self
+            .value
+            .subject_alt_names()
+            .and_then(|alt_names| {
+                alt_names
+                    .iter()
+                    .next()
+                    .and_then(|n| n.uri().map(String::from))
+            })
+            .ok_or(X509Error)?;
+        let mut alt_host_names = vec![application_uri];
+        if let Some(subject_alt_names) = self.subject_alt_names() {
+            alt_host_names.extend(subject_alt_names.into_iter().filter(|n| !n.is_empty()));
+        }
+        let certificate_duration_days =
+            (self.not_after()? - self.not_before()?).num_days().max(1) as u32;
+        Ok(X509Data {
+            key_size: self.key_length()? as u32,
+            common_name: self.get_subject_entry(Nid::COMMONNAME)?,
+            organization: self.get_subject_entry(Nid::ORGANIZATIONNAME)?,
+            organizational_unit: self.get_subject_entry(Nid::ORGANIZATIONALUNITNAME)?,
+            country: self.get_subject_entry(Nid::COUNTRYNAME)?,
+            state: self.get_subject_entry(Nid::STATEORPROVINCENAME)?,
+            alt_host_names,
+            certificate_duration_days,
+        })
+    }
+
     pub fn to_der(&self) -> Result<Vec<u8>, X509Error> {
         self.value.to_der().map_err(|e| {
             error!("Cannot turn X509 cert to DER, err = {:?}", e);
@@ -602,6 +841,31 @@ impl X509 {
     }
 }
 
+/// Splits the first DER element of `data` into its tag, its content and the data that follows it.
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&length, mut data) = data.split_first()?;
+    let length = if length & 0x80 == 0 {
+        length as usize
+    } else {
+        // Long form, the low bits are the number of length bytes that follow
+        let count = (length & 0x7f) as usize;
+        if count == 0 || count > std::mem::size_of::<usize>() || data.len() < count {
+            return None;
+        }
+        let (length, rest) = data.split_at(count);
+        data = rest;
+        length
+            .iter()
+            .fold(0usize, |length, &b| (length << 8) | b as usize)
+    };
+    if data.len() < length {
+        return None;
+    }
+    let (content, rest) = data.split_at(length);
+    Some((tag, content, rest))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
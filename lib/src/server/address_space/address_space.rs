@@ -11,7 +11,10 @@ use chrono::Utc;
 use crate::sync::*;
 use crate::types::{
     node_ids::VariableId::*,
-    service_types::{BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass},
+    service_types::{
+        BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass, RedundancySupport,
+        RedundantServerDataType, ServerState as ServerStateType,
+    },
     status_code::StatusCode,
     *,
 };
@@ -21,14 +24,18 @@ use crate::server::{
         node::{HasNodeId, NodeType},
         object::{Object, ObjectBuilder},
         references::{Reference, ReferenceDirection, References},
-        variable::Variable,
+        variable::{Variable, VariableBuilder},
         AttrFnGetter,
     },
-    callbacks, constants,
+    callbacks,
+    config::ServerRedundancy,
+    constants,
     diagnostics::ServerDiagnostics,
     historical::HistoryServerCapabilities,
     session::SessionManager,
+    session_diagnostics,
     state::ServerState,
+    subscriptions::node_changes::{ChangedNodes, NodeChangeListeners},
 };
 
 /// Finds a node in the address space and coerces it into a reference of the expected node type.
@@ -175,6 +182,8 @@ pub struct AddressSpace {
     internal_namespace: u16,
     /// The list of all registered namespaces.
     namespaces: Vec<String>,
+    /// Subscriptions interested in changes to nodes
+    change_listeners: NodeChangeListeners,
 }
 
 impl Default for AddressSpace {
@@ -191,6 +200,7 @@ impl Default for AddressSpace {
             // OPC UA namespace for its standard nodes. The second is the internal namespace used
             // by this implementation.
             namespaces: vec!["http://opcfoundation.org/UA/".to_string()],
+            change_listeners: NodeChangeListeners::default(),
         }
     }
 }
@@ -248,6 +258,60 @@ impl AddressSpace {
             .map(|i| i as u16)
     }
 
+    /// Sets the redundancy support of the server. The `ServerRedundancy` object of a server that
+    /// is part of a non-transparent redundant server set gets the uris of the servers of the set,
+    /// and the array of the servers with their service levels which the server keeps updated.
+    fn set_server_redundancy(&mut self, redundancy: &ServerRedundancy) {
+        let now = DateTime::now();
+        let support = redundancy
+            .redundancy_support()
+            .unwrap_or(RedundancySupport::None);
+        self.set_variable_value(
+            Server_ServerRedundancy_RedundancySupport,
+            support as i32,
+            &now,
+            &now,
+        );
+        if !redundancy.is_enabled() {
+            return;
+        }
+
+        let server_redundancy_id: NodeId = ObjectId::Server_ServerRedundancy.into();
+        self.delete_reference(
+            &server_redundancy_id,
+            &ObjectTypeId::ServerRedundancyType.into(),
+            ReferenceTypeId::HasTypeDefinition,
+        );
+        self.insert_reference(
+            &server_redundancy_id,
+            &ObjectTypeId::NonTransparentRedundancyType.into(),
+            ReferenceTypeId::HasTypeDefinition,
+        );
+
+        let _ = VariableBuilder::new(
+            &Server_ServerRedundancy_ServerUriArray.into(),
+            "ServerUriArray",
+            "ServerUriArray",
+        )
+        .data_type(DataTypeId::String)
+        .value_rank(1)
+        .value(&redundancy.server_uris)
+        .has_type_definition(VariableTypeId::PropertyType)
+        .property_of(server_redundancy_id.clone())
+        .insert(self);
+        let _ = VariableBuilder::new(
+            &Server_ServerRedundancy_RedundantServerArray.into(),
+            "RedundantServerArray",
+            "RedundantServerArray",
+        )
+        .data_type(DataTypeId::RedundantServerDataType)
+        .value_rank(1)
+        .value(Variant::Empty)
+        .has_type_definition(VariableTypeId::PropertyType)
+        .property_of(server_redundancy_id)
+        .insert(self);
+    }
+
     fn set_servers(&mut self, server_state: Arc<RwLock<ServerState>>, now: &DateTime) {
         let server_state = trace_read_lock!(server_state);
         if let Some(ref mut v) = self.find_variable_mut(Server_ServerArray) {
@@ -272,6 +336,76 @@ impl AddressSpace {
         self.set_variable_value(Server_ServiceLevel, service_level, now, now);
     }
 
+    /// Sets the servers of the redundant server set and their service levels in the
+    /// `ServerRedundancy.RedundantServerArray`
+    pub fn set_redundant_servers(&mut self, servers: &[RedundantServerDataType]) {
+        let now = DateTime::now();
+        let servers = servers
+            .iter()
+            .map(|server| {
+                Variant::from(ExtensionObject::from_encodable(
+                    ObjectId::RedundantServerDataType_Encoding_DefaultBinary,
+                    server,
+                ))
+            })
+            .collect::<Vec<Variant>>();
+        self.set_variable_value(
+            Server_ServerRedundancy_RedundantServerArray,
+            (VariantTypeId::ExtensionObject, servers),
+            &now,
+            &now,
+        );
+    }
+
+    /// Sets the state of the server in `ServerStatus`, along with the seconds until it shuts down
+    /// and the reason for the shutdown.
+    pub fn set_server_status(
+        &mut self,
+        state: ServerStateType,
+        seconds_till_shutdown: u32,
+        shutdown_reason: LocalizedText,
+    ) {
+        let now = DateTime::now();
+        self.set_variable_value(Server_ServerStatus_State, state as i32, &now, &now);
+        self.set_variable_value(
+            Server_ServerStatus_SecondsTillShutdown,
+            seconds_till_shutdown,
+            &now,
+            &now,
+        );
+        self.set_variable_value(
+            Server_ServerStatus_ShutdownReason,
+            shutdown_reason,
+            &now,
+            &now,
+        );
+    }
+
+    /// Tests if the server collects session and subscription diagnostics, which is controlled by
+    /// the Server.ServerDiagnostics.EnabledFlag variable.
+    pub fn is_diagnostics_enabled(&self) -> bool {
+        matches!(
+            self.get_variable_value(Server_ServerDiagnostics_EnabledFlag),
+            Ok(DataValue {
+                value: Some(Variant::Boolean(true)),
+                ..
+            })
+        )
+    }
+
+    /// Switches the collection of session and subscription diagnostics on or off. Switching them
+    /// off removes those in the address space, while switching them on exposes those of every
+    /// session with the next request a session handles.
+    pub fn set_diagnostics_enabled(&mut self, enabled: bool) {
+        let now = DateTime::now();
+        self.set_variable_value(Server_ServerDiagnostics_EnabledFlag, enabled, &now, &now);
+        if !enabled {
+            if let Some(server_diagnostics) = self.server_diagnostics.clone() {
+                session_diagnostics::hide_sessions(self, &server_diagnostics);
+            }
+        }
+    }
+
     /// Sets values for nodes representing the server.
     pub fn set_server_state(&mut self, server_state: Arc<RwLock<ServerState>>) {
         // Server state requires the generated address space, otherwise nothing
@@ -510,10 +644,25 @@ impl AddressSpace {
                 );
             }
 
-            // Server_ServerDiagnostics_ServerDiagnosticsSummary
-            // Server_ServerDiagnostics_SamplingIntervalDiagnosticsArray
-            // Server_ServerDiagnostics_SubscriptionDiagnosticsArray
             // Server_ServerDiagnostics_EnabledFlag
+            {
+                let server_state = trace_read_lock!(server_state);
+                let server_config = trace_read_lock!(server_state.config);
+                if let Some(v) = self.find_variable_mut(Server_ServerDiagnostics_EnabledFlag) {
+                    // Clients may switch diagnostics on and off
+                    v.set_writable(true);
+                    v.set_user_access_level(
+                        v.user_access_level() | super::UserAccessLevel::CURRENT_WRITE,
+                    );
+                }
+                self.set_diagnostics_enabled(server_config.diagnostics_enabled);
+            }
+
+            // Server_ServerDiagnostics_ServerDiagnosticsSummary
+            // Server_ServerDiagnostics_SamplingIntervalDiagnosticsArray,
+            // Server_ServerDiagnostics_SubscriptionDiagnosticsArray and the sessions beneath
+            // Server_ServerDiagnostics_SessionsDiagnosticsSummary are updated by the sessions as
+            // they handle requests
             {
                 let server_state = trace_read_lock!(server_state);
                 self.server_diagnostics = Some(server_state.diagnostics.clone());
@@ -586,6 +735,11 @@ impl AddressSpace {
             // ServerDiagnostics
             // VendorServiceInfo
             // ServerRedundancy
+            {
+                let server_state = trace_read_lock!(server_state);
+                let server_config = trace_read_lock!(server_state.config);
+                self.set_server_redundancy(&server_config.redundancy);
+            }
 
             // Server_ServerStatus_StartTime
             self.set_variable_value(Server_ServerStatus_StartTime, now, &now, &now);
@@ -603,16 +757,9 @@ impl AddressSpace {
 
             // State OPC UA Part 5 12.6, Valid states are
             //     State (Server_ServerStatus_State)
-            self.set_variable_getter(
-                Server_ServerStatus_State,
-                move |_, timestamps_to_return, _, _, _, _| {
-                    // let server_state =  trace_read_lock!(server_state);
-                    let now = DateTime::now();
-                    let mut value = DataValue::from(0i32);
-                    value.set_timestamps(timestamps_to_return, now, now);
-                    Ok(Some(value))
-                },
-            );
+            //     SecondsTillShutdown (Server_ServerStatus_SecondsTillShutdown)
+            //     ShutdownReason (Server_ServerStatus_ShutdownReason)
+            self.set_server_status(ServerStateType::Running, 0, LocalizedText::null());
 
             // ServerStatus_BuildInfo
             {
@@ -634,6 +781,10 @@ impl AddressSpace {
                 MethodId::Server_GetMonitoredItems,
                 Box::new(method_impls::ServerGetMonitoredItemsMethod),
             );
+            self.register_method_handler(
+                MethodId::Server_SetSubscriptionDurable,
+                Box::new(method_impls::ServerSetSubscriptionDurableMethod),
+            );
         }
     }
 
@@ -788,6 +939,7 @@ impl AddressSpace {
             false
         } else {
             self.node_map.insert(node_id.clone(), node_type);
+            self.change_listeners.notify(&node_id);
             // If references are supplied, add them now
             if let Some(references) = references {
                 self.references.insert(&node_id, references);
@@ -921,8 +1073,11 @@ impl AddressSpace {
                 let _ = self.delete(&node_id, delete_target_references);
             });
         }
-        // Remove the node
+        // Remove the node, telling its monitored items it is gone
         let removed_node = self.node_map.remove(node_id);
+        if removed_node.is_some() {
+            self.change_listeners.notify(node_id);
+        }
         // Remove references
         let removed_target_references = if delete_target_references {
             self.references.delete_node_references(node_id)
@@ -967,8 +1122,10 @@ impl AddressSpace {
         self.node_map.get(node_id)
     }
 
-    /// Finds a node by its node id and returns a mutable reference to it.
+    /// Finds a node by its node id and returns a mutable reference to it. Monitored items of
+    /// the node are told that it may have changed.
     pub fn find_node_mut(&mut self, node_id: &NodeId) -> Option<&mut NodeType> {
+        self.change_listeners.notify(node_id);
         self.node_map.get_mut(node_id)
     }
 
@@ -1061,6 +1218,12 @@ impl AddressSpace {
             .ok_or_else(|| ())
     }
 
+    /// Registers a sampling schedule's interest in changes to a node. Changes to the node made
+    /// through the address space are pushed into the changed nodes.
+    pub(crate) fn add_change_listener(&self, node_id: &NodeId, changed_nodes: &ChangedNodes) {
+        self.change_listeners.register(node_id, changed_nodes);
+    }
+
     /// Registers a method callback on the specified object id and method id
     pub fn register_method_handler<N>(&mut self, method_id: N, handler: MethodCallback)
     where
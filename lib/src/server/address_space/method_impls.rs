@@ -117,6 +117,74 @@ impl Method for ServerResendDataMethod {
     }
 }
 
+/// This is the handler for the Server.SetSubscriptionDurable method call.
+pub struct ServerSetSubscriptionDurableMethod;
+
+impl Method for ServerSetSubscriptionDurableMethod {
+    fn call(
+        &mut self,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        debug!("Method handler for SetSubscriptionDurable");
+
+        // OPC UA part 5 - SetSubscriptionDurable([in] UInt32 subscriptionId, [in] UInt32 lifetimeInHours, [out] UInt32 revisedLifetimeInHours);
+        //
+        // subscriptionId - Identifier of the subscription
+        // lifetimeInHours - The requested lifetime in hours for the durable subscription
+        // revisedLifetimeInHours - The revised lifetime in hours the server applied
+        //
+        // Return codes
+        //
+        // BadSubscriptionIdInvalid
+        // BadInvalidState - the subscription already has monitored items
+        // BadNotSupported - durable subscriptions are not supported
+        // BadUserAccessDenied
+
+        ensure_input_argument_count(request, 2)?;
+
+        let subscription_id = get_input_argument!(request, 0, UInt32)?;
+        let lifetime_in_hours = get_input_argument!(request, 1, UInt32)?;
+
+        {
+            let session_manager = trace_read_lock!(session_manager);
+            if let Some(session) = session_manager.find_session_by_id(session_id) {
+                let mut session = trace_write_lock!(session);
+                let owner = session.client_user_id();
+                let revised_lifetime_in_hours = {
+                    let durable_subscriptions = trace_lock!(session.durable_subscriptions());
+                    durable_subscriptions.revise_lifetime_in_hours(*lifetime_in_hours)
+                };
+                if let Some(subscription) = session.subscriptions_mut().get_mut(*subscription_id) {
+                    let Some(revised_lifetime_in_hours) = revised_lifetime_in_hours else {
+                        return Err(StatusCode::BadNotSupported);
+                    };
+                    // The subscription must be made durable before it has any monitored items
+                    if subscription.monitored_items_len() > 0 {
+                        return Err(StatusCode::BadInvalidState);
+                    }
+                    subscription.set_durable(owner, revised_lifetime_in_hours);
+                    return Ok(CallMethodResult {
+                        status_code: StatusCode::Good,
+                        input_argument_results: Some(vec![StatusCode::Good, StatusCode::Good]),
+                        input_argument_diagnostic_infos: None,
+                        output_arguments: Some(vec![Variant::from(revised_lifetime_in_hours)]),
+                    });
+                };
+            } else {
+                return Err(StatusCode::BadSessionIdInvalid);
+            }
+        }
+
+        if subscription_exists_on_other_session(session_id, session_manager, *subscription_id) {
+            Err(StatusCode::BadUserAccessDenied)
+        } else {
+            Err(StatusCode::BadSubscriptionIdInvalid)
+        }
+    }
+}
+
 /// This is the handler for the Server.GetMonitoredItems method call.
 pub struct ServerGetMonitoredItemsMethod;
 
@@ -588,6 +588,11 @@ impl Variable {
         self.value_getter = Some(value_getter);
     }
 
+    /// Tests if the value of this variable comes from a getter function
+    pub(crate) fn has_value_getter(&self) -> bool {
+        self.value_getter.is_some()
+    }
+
     /// Sets a setter function that will be called to set the value of this variable.
     pub fn set_value_setter(&mut self, value_setter: Arc<Mutex<dyn AttributeSetter + Send>>) {
         self.value_setter = Some(value_setter);
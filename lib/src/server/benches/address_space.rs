@@ -3,8 +3,8 @@ extern crate criterion;
 
 use criterion::Criterion;
 
-use crate::server::address_space::{address_space::AddressSpace, references::References};
-use crate::types::node_ids::ReferenceTypeId;
+use opcua::server::address_space::{address_space::AddressSpace, references::References};
+use opcua::types::node_ids::ReferenceTypeId;
 
 fn populate_address_space() {
     let _address_space = AddressSpace::new();
@@ -12,7 +12,7 @@ fn populate_address_space() {
 
 fn address_space_benchmark(c: &mut Criterion) {
     // This benchmark tests how long it takes to populate the address space
-    c.bench_function("address_space", |b| b.iter(|| populate_address_space()));
+    c.bench_function("address_space", |b| b.iter(populate_address_space));
 }
 
 fn reference_type_matches(references: &References) {
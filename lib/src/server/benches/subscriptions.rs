@@ -0,0 +1,181 @@
+#[macro_use]
+extern crate criterion;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use criterion::Criterion;
+
+use opcua::server::{
+    diagnostics::ServerDiagnostics,
+    prelude::*,
+    subscriptions::subscription::{Subscription, TickReason},
+};
+use opcua::sync::*;
+
+/// The number of variables, each of which is monitored by one item
+const MONITORED_ITEMS: u32 = 10_000;
+
+/// The number of variables changed between two ticks
+const CHANGED_ITEMS: u32 = 100;
+
+struct Fixture {
+    server: Server,
+    subscription: Subscription,
+    now: DateTimeUtc,
+    sample_time: Instant,
+    ns: u16,
+}
+
+impl Fixture {
+    fn new() -> Fixture {
+        let pki_dir = std::env::temp_dir().join("opcua-bench-pki");
+        let server = ServerBuilder::new_anonymous("Subscriptions Benchmark")
+            .create_sample_keypair(false)
+            .pki_dir(pki_dir)
+            .server()
+            .unwrap();
+
+        let node_ids = {
+            let address_space = server.address_space();
+            let mut address_space = address_space.write();
+            let ns = address_space.register_namespace("urn:bench").unwrap();
+            let node_ids = (0..MONITORED_ITEMS)
+                .map(|i| NodeId::new(ns, i))
+                .collect::<Vec<NodeId>>();
+            let variables = node_ids
+                .iter()
+                .map(|node_id| Variable::new(node_id, "v", "v", 0i32))
+                .collect();
+            address_space.add_variables(variables, &ObjectId::ObjectsFolder.into());
+            node_ids
+        };
+
+        let mut subscription = Subscription::new(
+            Arc::new(RwLock::new(ServerDiagnostics::default())),
+            1,
+            true,
+            1000f64,
+            300,
+            100,
+            0,
+        );
+        let items_to_create = node_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, node_id)| MonitoredItemCreateRequest {
+                item_to_monitor: ReadValueId {
+                    node_id,
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                },
+                monitoring_mode: MonitoringMode::Reporting,
+                requested_parameters: MonitoringParameters {
+                    client_handle: i as u32,
+                    sampling_interval: 0f64,
+                    filter: ExtensionObject::null(),
+                    queue_size: 1,
+                    discard_oldest: true,
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let now = Utc::now();
+        {
+            let server_state = server.server_state();
+            let server_state = server_state.read();
+            let address_space = server.address_space();
+            let address_space = address_space.read();
+            subscription.create_monitored_items(
+                &server_state,
+                &address_space,
+                &now,
+                TimestampsToReturn::Both,
+                &items_to_create,
+            );
+        }
+
+        let mut fixture = Fixture {
+            server,
+            subscription,
+            now,
+            sample_time: Instant::now(),
+            ns: items_to_create[0].item_to_monitor.node_id.namespace,
+        };
+        // The first tick takes the initial value of every item
+        fixture.tick();
+        fixture
+    }
+
+    /// Ticks the subscription with a clock that advances by the publishing interval, so every
+    /// monitored item is due for sampling.
+    fn tick(&mut self) {
+        self.now += chrono::Duration::seconds(1);
+        // No publish requests are made so the subscription must be kept alive
+        self.subscription.reset_lifetime_counter();
+        let address_space = self.server.address_space();
+        let address_space = address_space.read();
+        self.subscription
+            .tick(&self.now, &address_space, TickReason::TickTimerFired, false);
+    }
+
+    /// Samples the monitored items, as the sampling task of the server does when their sampling
+    /// schedule is due, with a clock that advances by their sampling interval.
+    fn sample(&mut self) {
+        self.sample_time += Duration::from_millis(100);
+        let server_state = self.server.server_state();
+        let server_state = server_state.read();
+        let address_space = self.server.address_space();
+        let address_space = address_space.read();
+        let mut sampling_schedules = server_state.sampling_schedules.lock();
+        sampling_schedules.sample(&address_space, self.sample_time);
+    }
+
+    /// Changes the values of some of the monitored variables
+    fn change_values(&mut self, value: i32) {
+        let address_space = self.server.address_space();
+        let mut address_space = address_space.write();
+        let now = DateTime::now();
+        (0..CHANGED_ITEMS).for_each(|i| {
+            let node_id = NodeId::new(self.ns, i * (MONITORED_ITEMS / CHANGED_ITEMS));
+            address_space.set_variable_value(node_id, value, &now, &now);
+        });
+    }
+}
+
+fn tick_unchanged_benchmark(c: &mut Criterion) {
+    // This benchmark tests how long it takes to tick a subscription when no monitored value changed
+    let mut fixture = Fixture::new();
+    c.bench_function("tick_unchanged", |b| b.iter(|| fixture.tick()));
+}
+
+fn tick_changed_benchmark(c: &mut Criterion) {
+    // This benchmark tests how long it takes to change some monitored values, sample them and tick
+    // the subscription
+    let mut fixture = Fixture::new();
+    let mut value = 0;
+    c.bench_function("tick_changed", |b| {
+        b.iter(|| {
+            value += 1;
+            fixture.change_values(value);
+            fixture.sample();
+            fixture.tick();
+        })
+    });
+}
+
+fn sample_unchanged_benchmark(c: &mut Criterion) {
+    // This benchmark tests how long it takes to sample the monitored items when no value changed
+    let mut fixture = Fixture::new();
+    c.bench_function("sample_unchanged", |b| b.iter(|| fixture.sample()));
+}
+
+criterion_group!(
+    benches,
+    tick_unchanged_benchmark,
+    tick_changed_benchmark,
+    sample_unchanged_benchmark
+);
+criterion_main!(benches);
@@ -4,10 +4,16 @@
 
 use std::path::PathBuf;
 
-use crate::core::config::Config;
+use crate::{
+    core::config::Config,
+    crypto::{CertificateRenewal, PrivateKeyPassphrase, SecurityProfile},
+};
 
 use super::{
-    config::{ServerConfig, ServerEndpoint, ServerUserToken, ANONYMOUS_USER_TOKEN_ID},
+    config::{
+        ServerConfig, ServerEndpoint, ServerListener, ServerRedundancy, ServerUserToken,
+        ANONYMOUS_USER_TOKEN_ID,
+    },
     constants,
     server::Server,
 };
@@ -80,7 +86,9 @@ impl ServerBuilder {
                 ServerUserToken {
                     user: "sample1".to_string(),
                     pass: Some("sample1pwd".to_string()),
+                    pass_hash: None,
                     x509: None,
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -89,7 +97,9 @@ impl ServerBuilder {
                 ServerUserToken {
                     user: "sample_x509".to_string(),
                     pass: None,
+                    pass_hash: None,
                     x509: Some("./users/sample-x509.der".to_string()),
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -98,7 +108,9 @@ impl ServerBuilder {
                 ServerUserToken {
                     user: "unused".to_string(),
                     pass: Some("unused1".to_string()),
+                    pass_hash: None,
                     x509: None,
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -229,6 +241,78 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets where the passphrase of an encrypted private key comes from, e.g. an environment
+    /// variable or a callback. If the server creates a sample keypair, its private key is
+    /// encrypted with this passphrase.
+    pub fn private_key_passphrase(mut self, private_key_passphrase: PrivateKeyPassphrase) -> Self {
+        self.config.private_key_passphrase = Some(private_key_passphrase);
+        self
+    }
+
+    /// Sets the server to renew its own self-signed certificate when it expires within the
+    /// specified number of days. The server checks when it starts and every hour thereafter.
+    pub fn renew_certificate_before_days(mut self, renew_before_days: u32) -> Self {
+        self.config.certificate_renewal = CertificateRenewal {
+            enabled: true,
+            renew_before_days,
+        };
+        self
+    }
+
+    /// Sets the file that durable subscriptions are saved to, so clients can transfer them back
+    /// after the server restarts.
+    pub fn durable_subscriptions_path<T>(mut self, durable_subscriptions_path: T) -> Self
+    where
+        T: Into<PathBuf>,
+    {
+        self.config.durable_subscriptions_path = Some(durable_subscriptions_path.into());
+        self
+    }
+
+    /// Sets whether the server starts with session and subscription diagnostics enabled. Clients
+    /// may switch them with the Server.ServerDiagnostics.EnabledFlag variable.
+    pub fn diagnostics_enabled(mut self, diagnostics_enabled: bool) -> Self {
+        self.config.diagnostics_enabled = diagnostics_enabled;
+        self
+    }
+
+    /// Adds a bearer token that grants access to the admin API of the embedded HTTP server.
+    pub fn http_admin_token<T>(mut self, token: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.config.http_admin.tokens.push(token.into());
+        self
+    }
+
+    /// Grants access to the admin API of the embedded HTTP server with basic auth, using the user
+    /// name and password of the user token with the id.
+    pub fn http_admin_user_token_id<T>(mut self, user_token_id: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.config
+            .http_admin
+            .user_token_ids
+            .insert(user_token_id.into());
+        self
+    }
+
+    /// Sets the redundancy of the server with the other servers of a redundant server set, which
+    /// is exposed to clients through the Server.ServerRedundancy object.
+    pub fn redundancy(mut self, redundancy: ServerRedundancy) -> Self {
+        self.config.redundancy = redundancy;
+        self
+    }
+
+    /// Sets the security profile, which restricts the security policies of the endpoints and the
+    /// client certificates the server accepts. By default the deprecated policies Basic128Rsa15
+    /// and Basic256 are not allowed and endpoints using them are disabled.
+    pub fn security_profile(mut self, security_profile: SecurityProfile) -> Self {
+        self.config.security_profile = security_profile;
+        self
+    }
+
     /// Sets the pki directory where server's own key pair is stored and where `/trusted` and
     /// `/rejected` server certificates are stored.
     pub fn pki_dir<T>(mut self, pki_dir: T) -> Self
@@ -286,6 +370,14 @@ impl ServerBuilder {
         self
     }
 
+    /// Adds a listener, i.e. a host and port to listen on and the ids of the endpoints offered
+    /// through it. Once a listener is added, the server no longer listens on the host and port
+    /// set by `host_and_port`, so add one for every socket the server should listen on.
+    pub fn listener(mut self, listener: ServerListener) -> Self {
+        self.config.listeners.push(listener);
+        self
+    }
+
     /// Discovery endpoint urls - the urls of this server used by clients to get endpoints.
     /// If the url is relative, e.g. "/" then the code will make a url for you using the port/host
     /// settings as they are at the time this function is executed.
@@ -7,5 +7,6 @@
 
 mod secure_channel_service;
 
+pub mod socket;
 pub mod tcp_transport;
 pub mod transport;
@@ -2,20 +2,25 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-use std::result::Result;
+use std::{
+    result::Result,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use crate::core::{comms::prelude::*, supported_message::SupportedMessage};
 
 use crate::crypto::SecurityPolicy;
 use crate::types::{status_code::StatusCode, *};
 
+/// The next secure channel id. Ids are unique across the connections of the process so the
+/// sessions of a connection can be told apart from those of other connections.
+static NEXT_SECURE_CHANNEL_ID: AtomicU32 = AtomicU32::new(1);
+
 struct SecureChannelState {
     // Issued flag
     issued: bool,
     // Renew count, debugging
     renew_count: usize,
-    // Last secure channel id
-    last_secure_channel_id: u32,
     /// Last token id number
     last_token_id: u32,
 }
@@ -23,7 +28,6 @@ struct SecureChannelState {
 impl SecureChannelState {
     pub fn new() -> SecureChannelState {
         SecureChannelState {
-            last_secure_channel_id: 0,
             issued: false,
             renew_count: 0,
             last_token_id: 0,
@@ -31,8 +35,7 @@ impl SecureChannelState {
     }
 
     pub fn create_secure_channel_id(&mut self) -> u32 {
-        self.last_secure_channel_id += 1;
-        self.last_secure_channel_id
+        NEXT_SECURE_CHANNEL_ID.fetch_add(1, Ordering::Relaxed)
     }
 
     pub fn create_token_id(&mut self) -> u32 {
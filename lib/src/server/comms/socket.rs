@@ -0,0 +1,99 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Sockets that the server listens on and accepts connections through. A listener is either a
+//! TCP socket or, on Unix platforms, a Unix domain socket serving `opc.uds` urls.
+
+use std::{fmt, io, net::SocketAddr};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// The address that a listener binds to
+#[derive(Debug, Clone)]
+pub(crate) enum ListenAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ListenAddress {
+    /// Binds a listener to the address. A stale socket file left behind by a previous run of the
+    /// server is removed before binding a Unix domain socket.
+    pub async fn bind(&self) -> io::Result<SocketListener> {
+        match self {
+            ListenAddress::Tcp(addr) => TcpListener::bind(addr).await.map(SocketListener::Tcp),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        debug!("Removing stale socket file {}", path.display());
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                UnixListener::bind(path).map(SocketListener::Unix)
+            }
+        }
+    }
+}
+
+/// A bound listener that accepts connections
+pub(crate) enum SocketListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl SocketListener {
+    /// Waits for the next connection
+    pub async fn accept(&self) -> io::Result<ConnectionSocket> {
+        match self {
+            SocketListener::Tcp(listener) => listener
+                .accept()
+                .await
+                .map(|(socket, _)| ConnectionSocket::Tcp(socket)),
+            #[cfg(unix)]
+            SocketListener::Unix(listener) => listener
+                .accept()
+                .await
+                .map(|(socket, _)| ConnectionSocket::Unix(socket)),
+        }
+    }
+}
+
+/// A connected socket that a client talks to the server through
+#[derive(Debug)]
+pub enum ConnectionSocket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl From<TcpStream> for ConnectionSocket {
+    fn from(socket: TcpStream) -> Self {
+        ConnectionSocket::Tcp(socket)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for ConnectionSocket {
+    fn from(socket: UnixStream) -> Self {
+        ConnectionSocket::Unix(socket)
+    }
+}
@@ -14,11 +14,7 @@ use futures::StreamExt;
 use std::{net::SocketAddr, sync::Arc};
 use tokio::{
     self,
-    io::AsyncWriteExt,
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
+    io::{AsyncWriteExt, ReadHalf, WriteHalf},
     sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     time::{interval_at, Duration, Instant},
 };
@@ -30,6 +26,7 @@ use crate::core::{
     comms::{
         message_writer::MessageWriter,
         secure_channel::SecureChannel,
+        stream::BoxedStream,
         tcp_codec::{self, TcpCodec},
     },
     prelude::*,
@@ -40,7 +37,8 @@ use crate::types::status_code::StatusCode;
 
 use crate::server::{
     address_space::types::AddressSpace,
-    comms::{secure_channel_service::SecureChannelService, transport::*},
+    comms::{secure_channel_service::SecureChannelService, socket::ConnectionSocket, transport::*},
+    config::ServerListener,
     services::message_handler::MessageHandler,
     session::SessionManager,
     state::ServerState,
@@ -50,8 +48,9 @@ use crate::server::{
 /// Messages that may be sent to the writer.
 #[derive(Debug)]
 enum Message {
-    // Message for writer to quit right now.
-    Quit,
+    // Message for writer to quit right now. A bad status code is sent to the client in an error
+    // message first.
+    Quit(StatusCode),
     // A supported message with a request id
     Message(u32, SupportedMessage),
 }
@@ -63,7 +62,7 @@ pub struct MessageSender {
 
 impl MessageSender {
     pub fn send_quit(&self) {
-        let _ = self.sender.send(Message::Quit);
+        let _ = self.sender.send(Message::Quit(StatusCode::Good));
     }
 
     pub fn send_message(&self, request_id: u32, message: SupportedMessage) {
@@ -79,7 +78,7 @@ struct ReadState {
     /// Time to wait for a HELLO from the client
     pub hello_timeout: u32,
     /// Reader from which messages will be decoded
-    pub reader: OwnedReadHalf,
+    pub reader: ReadHalf<BoxedStream>,
 }
 
 struct WriteState {
@@ -88,7 +87,7 @@ struct WriteState {
     /// Secure channel state
     pub secure_channel: Arc<RwLock<SecureChannel>>,
     /// Writing portion of socket
-    pub writer: OwnedWriteHalf,
+    pub writer: WriteHalf<BoxedStream>,
     /// Write buffer (protected since it might be accessed by publish response / event activity)
     pub send_buffer: Arc<Mutex<MessageWriter>>,
 }
@@ -96,6 +95,8 @@ struct WriteState {
 /// This is the thing that handles input and output for the open connection associated with the
 /// session.
 pub struct TcpTransport {
+    /// The listener that the client connected through
+    listener: ServerListener,
     /// Server state, address space etc.
     server_state: Arc<RwLock<ServerState>>,
     /// Transport id (for debugging)
@@ -106,8 +107,10 @@ pub struct TcpTransport {
     address_space: Arc<RwLock<AddressSpace>>,
     /// The current transport state
     transport_state: TransportState,
-    /// Client address
+    /// Client address, for TCP connections
     client_address: Option<SocketAddr>,
+    /// Client process credentials, for Unix domain socket connections
+    peer_credentials: Option<PeerCredentials>,
     /// Secure channel handler
     secure_channel_service: SecureChannelService,
     /// Message handler
@@ -135,9 +138,22 @@ impl Transport for TcpTransport {
                 status_code
             );
             self.transport_state = TransportState::Finished(status_code);
-            // Clear sessions
+            if status_code.is_bad() {
+                let server_state = trace_read_lock!(self.server_state);
+                // Connections closed by a shutdown are not errors
+                if !server_state.is_shutdown_due() {
+                    let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+                    diagnostics.on_secure_channel_error(status_code, self.client_address);
+                }
+            }
+            let secure_channel_id = trace_read_lock!(self.secure_channel).secure_channel_id();
+            {
+                let mut server_state = trace_write_lock!(self.server_state);
+                server_state.open_secure_channels.remove(&secure_channel_id);
+            }
+            // Clear the sessions of this connection
             let mut session_manager = trace_write_lock!(self.session_manager);
-            session_manager.clear(self.address_space.clone());
+            session_manager.clear_secure_channel(secure_channel_id, self.address_space.clone());
         } else {
             trace!("Transport is being placed in finished state when it is already finished, ignoring code {}", status_code);
         }
@@ -147,13 +163,26 @@ impl Transport for TcpTransport {
         self.client_address
     }
 
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
     fn session_manager(&self) -> Arc<RwLock<SessionManager>> {
         self.session_manager.clone()
     }
 }
 
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        let server_state = trace_read_lock!(self.server_state);
+        let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+        diagnostics.on_close_connection();
+    }
+}
+
 impl TcpTransport {
     pub fn new(
+        listener: ServerListener,
         certificate_store: Arc<RwLock<CertificateStore>>,
         server_state: Arc<RwLock<ServerState>>,
         address_space: Arc<RwLock<AddressSpace>>,
@@ -171,6 +200,7 @@ impl TcpTransport {
         )));
 
         let message_handler = MessageHandler::new(
+            listener.clone(),
             secure_channel.clone(),
             certificate_store,
             server_state.clone(),
@@ -181,12 +211,20 @@ impl TcpTransport {
         let secure_channel_service = SecureChannelService::new();
         let transport_id = NodeId::next_numeric(0);
 
+        {
+            let server_state = trace_read_lock!(server_state);
+            let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+            diagnostics.on_open_connection();
+        }
+
         TcpTransport {
+            listener,
             server_state,
             transport_id,
             address_space,
             transport_state: TransportState::New,
             client_address: None,
+            peer_credentials: None,
             message_handler,
             secure_channel,
             secure_channel_service,
@@ -199,28 +237,57 @@ impl TcpTransport {
 
     /// This is the entry point for the session. This function is asynchronous - it spawns tokio
     /// tasks to handle the session execution loop so this function will returns immediately.
-    pub fn run(connection: Arc<RwLock<TcpTransport>>, socket: TcpStream, looping_interval_ms: f64) {
-        info!(
-            "Socket info:\n  Linger - {},\n  TTL - {}",
-            if let Ok(v) = socket.linger() {
-                match v {
-                    Some(d) => format!("{}ms", d.as_millis()),
-                    None => "No linger".to_string(),
-                }
-            } else {
-                "No Linger (err)".to_string()
-            },
-            if let Ok(v) = socket.ttl() {
-                format!("{}", v)
-            } else {
-                "No TTL".to_string()
+    pub fn run<S>(connection: Arc<RwLock<TcpTransport>>, socket: S, looping_interval_ms: f64)
+    where
+        S: Into<ConnectionSocket>,
+    {
+        let (socket, client_address, peer_credentials): (BoxedStream, _, _) = match socket.into() {
+            ConnectionSocket::Tcp(socket) => {
+                info!(
+                    "Socket info:\n  Linger - {},\n  TTL - {}",
+                    if let Ok(v) = socket.linger() {
+                        match v {
+                            Some(d) => format!("{}ms", d.as_millis()),
+                            None => "No linger".to_string(),
+                        }
+                    } else {
+                        "No Linger (err)".to_string()
+                    },
+                    if let Ok(v) = socket.ttl() {
+                        format!("{}", v)
+                    } else {
+                        "No TTL".to_string()
+                    }
+                );
+                let client_address = socket.peer_addr().ok();
+                (Box::new(socket), client_address, None)
             }
-        );
+            #[cfg(unix)]
+            ConnectionSocket::Unix(socket) => {
+                let peer_credentials = match socket.peer_cred() {
+                    Ok(cred) => Some(PeerCredentials {
+                        uid: cred.uid(),
+                        gid: cred.gid(),
+                        pid: cred.pid(),
+                    }),
+                    Err(err) => {
+                        warn!("Cannot get the credentials of the socket peer, {:?}", err);
+                        None
+                    }
+                };
+                info!("Socket info:\n  Peer credentials - {:?}", peer_credentials);
+                (Box::new(socket), None, peer_credentials)
+            }
+        };
 
-        // Store the address of the client
+        // Store the address / credentials of the client
         let (send_buffer_size, receive_buffer_size) = {
             let mut connection = trace_write_lock!(connection);
-            connection.client_address = Some(socket.peer_addr().unwrap());
+            connection.client_address = client_address;
+            connection.peer_credentials = peer_credentials;
+            connection
+                .message_handler
+                .set_peer_credentials(peer_credentials);
             connection.transport_state = TransportState::WaitingHello;
             let server_state = trace_read_lock!(connection.server_state);
             (
@@ -255,7 +322,7 @@ impl TcpTransport {
 
     async fn spawn_session_handler_task(
         transport: Arc<RwLock<TcpTransport>>,
-        socket: TcpStream,
+        socket: BoxedStream,
         looping_interval_ms: f64,
         send_buffer_size: usize,
         receive_buffer_size: usize,
@@ -264,7 +331,7 @@ impl TcpTransport {
         let (tx, rx) = unbounded_channel();
         let send_buffer = Arc::new(Mutex::new(MessageWriter::new(send_buffer_size, 0, 0)));
 
-        let (reader, writer) = socket.into_split();
+        let (reader, writer) = tokio::io::split(socket);
         let (hello_timeout, secure_channel) = {
             let transport = trace_read_lock!(transport);
             let server_state = trace_read_lock!(transport.server_state);
@@ -314,7 +381,7 @@ impl TcpTransport {
     /// Spawns the writing loop task. The writing loop takes messages to send off of a queue
     /// and sends them to the stream.
     async fn spawn_writing_loop_task(
-        writer: OwnedWriteHalf,
+        writer: WriteHalf<BoxedStream>,
         mut receiver: UnboundedReceiver<Message>,
         secure_channel: Arc<RwLock<SecureChannel>>,
         transport: Arc<RwLock<TcpTransport>>,
@@ -331,10 +398,22 @@ impl TcpTransport {
         while let Some(message) = receiver.recv().await {
             trace!("Writing loop received message: {:?}", message);
             let (request_id, response) = match message {
-                Message::Quit => {
+                Message::Quit(status_code) if status_code.is_good() => {
                     debug!("Server writer received a quit so it will quit");
                     return Ok(());
                 }
+                Message::Quit(status_code) => {
+                    debug!(
+                        "Server writer is closing the connection with status {}",
+                        status_code
+                    );
+                    {
+                        let mut send_buffer = trace_lock!(write_state.send_buffer);
+                        send_buffer.write_error(&ErrorMessage::from_status_code(status_code))?;
+                    }
+                    let _ = Self::write_bytes_task(write_state).await;
+                    return Err(status_code);
+                }
                 Message::Message(request_id, response) => {
                     if let SupportedMessage::Invalid(_) = response {
                         error!("Writer terminating - received an invalid message");
@@ -362,7 +441,7 @@ impl TcpTransport {
     }
 
     async fn wait_for_hello(
-        reader: &mut FramedRead<OwnedReadHalf, TcpCodec>,
+        reader: &mut FramedRead<ReadHalf<BoxedStream>, TcpCodec>,
         hello_timeout: u32,
     ) -> Result<HelloMessage, StatusCode> {
         let duration = Duration::from_secs(u64::from(hello_timeout));
@@ -454,46 +533,103 @@ impl TcpTransport {
         loop {
             timer.tick().await;
 
-            let transport = trace_read_lock!(transport);
-            let session_manager = trace_read_lock!(transport.session_manager);
-
-            for (_node_id, session) in session_manager.sessions.iter() {
-                let mut session = trace_write_lock!(session);
-                let address_space = trace_read_lock!(transport.address_space);
-                let now = Utc::now();
-
-                // Request queue might contain stale publish requests
-                session.expire_stale_publish_requests(&now);
-
-                // Process subscriptions
-                session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired)?;
-
-                // Check if there are publish responses to send for transmission
-                if let Some(publish_responses) =
-                    session.subscriptions_mut().take_publish_responses()
-                {
-                    for publish_response in publish_responses {
-                        trace!(
-                            "<-- Sending a Publish Response{}, {:?}",
-                            publish_response.request_id,
-                            &publish_response.response
-                        );
-                        // Messages will be sent by the writing task
-                        sender
-                            .send(Message::Message(
+            let shutdown_due = {
+                let transport = trace_read_lock!(transport);
+                let session_manager = trace_read_lock!(transport.session_manager);
+                let shutdown_due = transport.is_server_shutdown_due();
+                let secure_channel_id =
+                    trace_read_lock!(transport.secure_channel).secure_channel_id();
+                // The sessions whose secure channel has closed are ticked by the connection with
+                // the lowest secure channel id, so their subscriptions still run out of lifetime
+                let (open_secure_channels, ticks_closed_channels) = {
+                    let server_state = trace_read_lock!(transport.server_state);
+                    let open_secure_channels = server_state.open_secure_channels.clone();
+                    let ticks_closed_channels =
+                        open_secure_channels.first() == Some(&secure_channel_id);
+                    (open_secure_channels, ticks_closed_channels)
+                };
+
+                for (_node_id, session) in session_manager.sessions.iter() {
+                    let mut session = trace_write_lock!(session);
+                    // Publish responses can only be sent to the sessions of this connection
+                    let is_own_session = session.secure_channel_id() == secure_channel_id;
+                    if !is_own_session
+                        && (!ticks_closed_channels
+                            || open_secure_channels.contains(&session.secure_channel_id()))
+                    {
+                        continue;
+                    }
+                    let address_space = trace_read_lock!(transport.address_space);
+                    let now = Utc::now();
+
+                    // Request queue might contain stale publish requests
+                    session.expire_stale_publish_requests(&now);
+
+                    // Process subscriptions
+                    session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired)?;
+
+                    // The server is shutting down so no more publish responses can be sent
+                    // after these ones
+                    if shutdown_due {
+                        session.cancel_publish_requests(StatusCode::BadShutdown);
+                    }
+
+                    // The publish responses of a closed secure channel cannot be sent
+                    if !is_own_session {
+                        let _ = session.subscriptions_mut().take_publish_responses();
+                        continue;
+                    }
+
+                    // Check if there are publish responses to send for transmission
+                    if let Some(publish_responses) =
+                        session.subscriptions_mut().take_publish_responses()
+                    {
+                        for publish_response in publish_responses {
+                            trace!(
+                                "<-- Sending a Publish Response{}, {:?}",
                                 publish_response.request_id,
-                                publish_response.response,
-                            ))
-                            .map_err(|e| {
-                                error!("Unable to send publish response to writer task: {}", e);
-                                StatusCode::BadUnexpectedError
-                            })?;
+                                &publish_response.response
+                            );
+                            // Messages will be sent by the writing task
+                            sender
+                                .send(Message::Message(
+                                    publish_response.request_id,
+                                    publish_response.response,
+                                ))
+                                .map_err(|e| {
+                                    error!("Unable to send publish response to writer task: {}", e);
+                                    StatusCode::BadUnexpectedError
+                                })?;
+                        }
+                        // Publishing changed the diagnostics of the session's subscriptions
+                        if trace_read_lock!(session.server_diagnostics()).is_exposing_sessions() {
+                            drop(address_space);
+                            let mut address_space = trace_write_lock!(transport.address_space);
+                            session.update_diagnostics(&mut address_space);
+                        }
                     }
                 }
+                shutdown_due
+            };
+
+            if shutdown_due {
+                // The writing task closes the connection after it has written the publish
+                // responses that are queued ahead of the close message.
+                info!("Server is shutting down so the connection will be closed");
+                sender
+                    .send(Message::Quit(StatusCode::BadSecureChannelClosed))
+                    .map_err(|_| StatusCode::BadUnexpectedError)?;
+                futures::future::pending::<()>().await;
             }
         }
     }
 
+    /// Test if the server is shutting down and the connection should be closed
+    fn is_server_shutdown_due(&self) -> bool {
+        let server_state = trace_read_lock!(self.server_state);
+        server_state.is_shutdown_due()
+    }
+
     /// Test if the connection should abort
     pub fn is_server_abort(&self) -> bool {
         let server_state = trace_read_lock!(self.server_state);
@@ -511,7 +647,7 @@ impl TcpTransport {
         let server_protocol_version = 0;
         let endpoints = {
             let server_state = trace_read_lock!(self.server_state);
-            server_state.endpoints(&hello.endpoint_url, &None)
+            server_state.endpoints(&self.listener, &hello.endpoint_url, &None)
         }
         .unwrap();
 
@@ -654,6 +790,12 @@ impl TcpTransport {
             self.client_protocol_version,
             request,
         )?;
+        {
+            let mut server_state = trace_write_lock!(self.server_state);
+            server_state
+                .open_secure_channels
+                .insert(secure_channel.secure_channel_id());
+        }
         let _ = sender.send_message(request_id, response);
         Ok(())
     }
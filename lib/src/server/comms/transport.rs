@@ -11,7 +11,7 @@ use std::{net::SocketAddr, sync::Arc};
 use crate::sync::*;
 use crate::types::status_code::StatusCode;
 
-use crate::server::session::SessionManager;
+use crate::server::{config::ServerListener, session::SessionManager};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TransportState {
@@ -21,6 +21,27 @@ pub enum TransportState {
     Finished(StatusCode),
 }
 
+/// Credentials of the process at the other end of a Unix domain socket connection, as reported
+/// by the operating system. They cannot be forged by the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// Effective user id of the peer process
+    pub uid: u32,
+    /// Effective group id of the peer process
+    pub gid: u32,
+    /// Process id of the peer, if the platform reports it
+    pub pid: Option<i32>,
+}
+
+/// The connection that a client sends its requests through
+#[derive(Clone, Debug)]
+pub struct ClientConnection {
+    /// The listener that the client connected through
+    pub listener: ServerListener,
+    /// Credentials of the client process, for Unix domain socket connections
+    pub peer_credentials: Option<PeerCredentials>,
+}
+
 /// Represents a transport layer, the thing responsible for maintaining an open channel and transferring
 /// data between the server and the client.
 pub trait Transport {
@@ -41,6 +62,9 @@ pub trait Transport {
     }
     /// Returns the address of the client (peer) of this connection
     fn client_address(&self) -> Option<SocketAddr>;
+    /// Returns the credentials of the client (peer) of this connection, if it connected through
+    /// a Unix domain socket
+    fn peer_credentials(&self) -> Option<PeerCredentials>;
     /// Returns the session map for the connection
     fn session_manager(&self) -> Arc<RwLock<SessionManager>>;
 }
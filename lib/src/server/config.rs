@@ -10,12 +10,23 @@ use std::{
 };
 
 use crate::{
-    core::{comms::url::url_matches_except_host, config::Config},
-    crypto::{CertificateStore, SecurityPolicy, Thumbprint},
-    types::{service_types::ApplicationType, DecodingOptions, MessageSecurityMode, UAString},
+    core::{
+        comms::url::{url_matches_except_host, OPC_UDS_SCHEME},
+        config::Config,
+    },
+    crypto::{
+        jwt::{Jwt, JwtVerificationKey},
+        passphrase, password, CertificateRenewal, CertificateStore, PrivateKeyPassphrase,
+        SecurityPolicy, SecurityProfile, Thumbprint,
+    },
+    types::{
+        service_types::{ApplicationType, RedundancySupport},
+        status_code::StatusCode,
+        DecodingOptions, MessageSecurityMode, UAString,
+    },
 };
 
-use super::constants;
+use super::{comms::transport::PeerCredentials, constants};
 
 pub const ANONYMOUS_USER_TOKEN_ID: &str = "ANONYMOUS";
 
@@ -32,20 +43,262 @@ pub struct TcpConfig {
     pub port: u16,
 }
 
+/// A socket that the server listens on, and the endpoints offered to clients that connect
+/// through it.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ServerListener {
+    /// The hostname or IP address to listen on, which is also supplied in the endpoint urls of
+    /// the listener. IPv6 addresses are written without brackets, e.g. `::1`
+    #[serde(default)]
+    pub host: String,
+    /// The port number to listen on
+    #[serde(default)]
+    pub port: u16,
+    /// Path of a Unix domain socket to listen on instead of the host and port. The endpoint urls
+    /// of the listener are `opc.uds` urls, e.g. `opc.uds:///run/opcua/server.sock/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+    /// Ids of the endpoints offered through this listener. All endpoints are offered if empty
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub endpoint_ids: BTreeSet<String>,
+    /// User ids of the client processes that may activate sessions through a Unix domain socket.
+    /// Any process may do so when both this and `peer_gids` are empty
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub peer_uids: BTreeSet<u32>,
+    /// Group ids of the client processes that may activate sessions through a Unix domain socket
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub peer_gids: BTreeSet<u32>,
+}
+
+impl ServerListener {
+    pub fn new<T>(host: T, port: u16, endpoint_ids: &[&str]) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerListener {
+            host: host.into(),
+            port,
+            socket_path: None,
+            endpoint_ids: endpoint_ids.iter().map(|id| id.to_string()).collect(),
+            peer_uids: BTreeSet::new(),
+            peer_gids: BTreeSet::new(),
+        }
+    }
+
+    /// Creates a listener on the Unix domain socket at the path, which must be absolute
+    pub fn new_unix<T>(socket_path: T, endpoint_ids: &[&str]) -> Self
+    where
+        T: Into<PathBuf>,
+    {
+        ServerListener {
+            socket_path: Some(socket_path.into()),
+            ..Self::new("", 0, endpoint_ids)
+        }
+    }
+
+    pub fn is_valid(&self, endpoints: &BTreeMap<String, ServerEndpoint>) -> bool {
+        let mut valid = true;
+        if let Some(ref socket_path) = self.socket_path {
+            if !cfg!(unix) {
+                error!(
+                    "Listener {} is invalid, Unix domain sockets are not supported on this platform.",
+                    socket_path.display()
+                );
+                valid = false;
+            } else if !socket_path.is_absolute() {
+                error!(
+                    "Listener {} has a socket path that is not absolute.",
+                    socket_path.display()
+                );
+                valid = false;
+            }
+        } else {
+            if self.host.is_empty() {
+                error!("Listener on port {} has an empty host.", self.port);
+                valid = false;
+            }
+            if !self.peer_uids.is_empty() || !self.peer_gids.is_empty() {
+                error!(
+                    "Listener {} restricts peer uids / gids but is not a Unix domain socket.",
+                    self.base_endpoint_url()
+                );
+                valid = false;
+            }
+        }
+        for id in &self.endpoint_ids {
+            if !endpoints.contains_key(id) {
+                error!(
+                    "Listener {} refers to endpoint {} which does not exist.",
+                    self.base_endpoint_url(),
+                    id
+                );
+                valid = false;
+            }
+        }
+        valid
+    }
+
+    /// Returns a opc.tcp://server:port url, or an opc.uds:///socket/path url, for the listener
+    /// that paths can be appended onto
+    pub fn base_endpoint_url(&self) -> String {
+        if let Some(ref socket_path) = self.socket_path {
+            format!("{}://{}", OPC_UDS_SCHEME, socket_path.display())
+        } else {
+            base_endpoint_url(&self.host, self.port)
+        }
+    }
+
+    /// Tests if the endpoint with the id is offered through this listener
+    pub fn offers_endpoint(&self, id: &str) -> bool {
+        self.endpoint_ids.is_empty() || self.endpoint_ids.contains(id)
+    }
+
+    /// Tests if a client process with the credentials may activate sessions through this
+    /// listener. Credentials are only known for Unix domain socket connections.
+    pub fn accepts_peer(&self, peer_credentials: Option<&PeerCredentials>) -> bool {
+        if self.peer_uids.is_empty() && self.peer_gids.is_empty() {
+            true
+        } else if let Some(peer_credentials) = peer_credentials {
+            self.peer_uids.contains(&peer_credentials.uid)
+                || self.peer_gids.contains(&peer_credentials.gid)
+        } else {
+            false
+        }
+    }
+}
+
+/// Makes a opc.tcp://server:port url, putting brackets around an IPv6 address
+fn base_endpoint_url(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("opc.tcp://[{}]:{}", host, port)
+    } else {
+        format!("opc.tcp://{}:{}", host, port)
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ServerUserToken {
     /// User name
     pub user: String,
-    /// Password
+    /// Password in plaintext. Deprecated in favour of `pass_hash`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pass: Option<String>,
+    /// Salted password hash as a PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$...`. Argon2id,
+    /// scrypt and PBKDF2 (SHA-256 / SHA-512) hashes are supported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pass_hash: Option<String>,
     // X509 file path (as a string)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub x509: Option<String>,
+    /// Settings for verifying a JSON Web Token presented in an IssuedIdentityToken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<ServerJwtConfig>,
     #[serde(skip)]
     pub thumbprint: Option<Thumbprint>,
 }
 
+/// Describes how a JSON Web Token supplied as an IssuedIdentityToken is verified. The token
+/// belongs to the user token when its `user_claim` matches the token's `user`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ServerJwtConfig {
+    /// Expected issuer, i.e. the `iss` claim
+    pub issuer: String,
+    /// Expected audience, i.e. a value in the `aud` claim
+    pub audience: String,
+    /// Path to a PEM public key or certificate for verifying RS*, PS* and ES* signatures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Shared secret for verifying HS* signatures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Claim that identifies the user, `sub` by default
+    #[serde(default = "ServerJwtConfig::default_user_claim")]
+    pub user_claim: String,
+    /// Clock skew in seconds that is tolerated when checking `exp` and `nbf`
+    #[serde(default = "ServerJwtConfig::default_leeway")]
+    pub leeway: u32,
+    /// Url of the authorization service that issues tokens. It is advertised to clients in the
+    /// endpoint's user token policy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority_url: Option<String>,
+}
+
+impl ServerJwtConfig {
+    /// Create a JWT config for tokens signed with the key held in the PEM file at the path
+    pub fn with_public_key<T>(issuer: T, audience: T, public_key_path: &Path) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerJwtConfig {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            public_key: Some(public_key_path.to_string_lossy().to_string()),
+            secret: None,
+            user_claim: Self::default_user_claim(),
+            leeway: Self::default_leeway(),
+            authority_url: None,
+        }
+    }
+
+    /// Create a JWT config for tokens signed with a shared secret
+    pub fn with_secret<T>(issuer: T, audience: T, secret: T) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerJwtConfig {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            public_key: None,
+            secret: Some(secret.into()),
+            user_claim: Self::default_user_claim(),
+            leeway: Self::default_leeway(),
+            authority_url: None,
+        }
+    }
+
+    fn default_user_claim() -> String {
+        "sub".into()
+    }
+
+    fn default_leeway() -> u32 {
+        60
+    }
+
+    /// Loads the key that token signatures are verified with. The server loads it once, when it
+    /// is created or when `ServerState::reload_jwt_verification_keys()` is called.
+    pub fn verification_key(&self) -> Result<JwtVerificationKey, StatusCode> {
+        if let Some(ref secret) = self.secret {
+            Ok(JwtVerificationKey::from_secret(secret.as_bytes()))
+        } else if let Some(ref public_key) = self.public_key {
+            let pem = std::fs::read(public_key).map_err(|err| {
+                error!("Cannot read JWT public key from {}, {}", public_key, err);
+                StatusCode::BadConfigurationError
+            })?;
+            JwtVerificationKey::from_pem(&pem)
+        } else {
+            Err(StatusCode::BadConfigurationError)
+        }
+    }
+
+    /// Verifies the token's signature with the key from `verification_key()` and its claims, and
+    /// tests if it identifies the user.
+    pub fn verify(
+        &self,
+        key: &JwtVerificationKey,
+        jwt: &Jwt,
+        user: &str,
+        now: i64,
+    ) -> Result<(), StatusCode> {
+        jwt.verify_signature(key)?;
+        jwt.validate_claims(&self.issuer, &self.audience, self.leeway as i64, now)?;
+        if jwt.claim_matches(&self.user_claim, user) {
+            Ok(())
+        } else {
+            Err(StatusCode::BadUserAccessDenied)
+        }
+    }
+}
+
 impl ServerUserToken {
     /// Create a user pass token
     pub fn user_pass<T>(user: T, pass: T) -> Self
@@ -55,7 +308,25 @@ impl ServerUserToken {
         ServerUserToken {
             user: user.into(),
             pass: Some(pass.into()),
+            pass_hash: None,
+            x509: None,
+            jwt: None,
+            thumbprint: None,
+        }
+    }
+
+    /// Create a user pass token from a salted password hash, e.g. one produced by
+    /// `crypto::password::hash_password()`
+    pub fn user_pass_hash<T>(user: T, pass_hash: T) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerUserToken {
+            user: user.into(),
+            pass: None,
+            pass_hash: Some(pass_hash.into()),
             x509: None,
+            jwt: None,
             thumbprint: None,
         }
     }
@@ -68,7 +339,24 @@ impl ServerUserToken {
         ServerUserToken {
             user: user.into(),
             pass: None,
+            pass_hash: None,
             x509: Some(cert_path.to_string_lossy().to_string()),
+            jwt: None,
+            thumbprint: None,
+        }
+    }
+
+    /// Create a token for a user who authenticates with a JSON Web Token
+    pub fn jwt<T>(user: T, jwt: ServerJwtConfig) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerUserToken {
+            user: user.into(),
+            pass: None,
+            pass_hash: None,
+            x509: None,
+            jwt: Some(jwt),
             thumbprint: None,
         }
     }
@@ -102,29 +390,95 @@ impl ServerUserToken {
             error!("User token {} has an empty user name.", id);
             valid = false;
         }
-        if self.pass.is_some() && self.x509.is_some() {
+        if self.pass.is_some() && self.pass_hash.is_some() {
+            error!(
+                "User token {} holds a password and a password hash - it cannot be both.",
+                id
+            );
+            valid = false;
+        } else if (self.pass.is_some() || self.pass_hash.is_some()) && self.x509.is_some() {
             error!(
                 "User token {} holds a password and certificate info - it cannot be both.",
                 id
             );
             valid = false;
-        } else if self.pass.is_none() && self.x509.is_none() {
+        } else if (self.pass.is_some() || self.pass_hash.is_some() || self.x509.is_some())
+            && self.jwt.is_some()
+        {
             error!(
-                "User token {} fails to provide a password or certificate info.",
+                "User token {} holds a password or certificate info and JWT settings - it cannot be both.",
                 id
             );
             valid = false;
+        } else if self.pass.is_none()
+            && self.pass_hash.is_none()
+            && self.x509.is_none()
+            && self.jwt.is_none()
+        {
+            error!(
+                "User token {} fails to provide a password, certificate info or JWT settings.",
+                id
+            );
+            valid = false;
+        }
+        if let Some(ref jwt) = self.jwt {
+            if jwt.issuer.is_empty() || jwt.audience.is_empty() || jwt.user_claim.is_empty() {
+                error!(
+                    "User token {} has JWT settings with an empty issuer, audience or user claim.",
+                    id
+                );
+                valid = false;
+            }
+            if jwt.public_key.is_some() == jwt.secret.is_some() {
+                error!(
+                    "User token {} has JWT settings that must hold either a public key or a secret.",
+                    id
+                );
+                valid = false;
+            }
+        }
+        if let Some(ref pass_hash) = self.pass_hash {
+            if !password::is_valid_password_hash(pass_hash) {
+                error!(
+                    "User token {} has an invalid or unsupported password hash.",
+                    id
+                );
+                valid = false;
+            }
+        } else if self.pass.is_some() {
+            warn!(
+                "User token {} holds a plaintext password. Replace it with a pass_hash, e.g. generated by the opcua-password-hash tool.",
+                id
+            );
         }
         valid
     }
 
+    /// Tests if the supplied password matches this user pass token. Hashed passwords are
+    /// verified with the hash's algorithm and all comparisons are made in constant time.
+    pub fn verify_password(&self, password: &[u8]) -> bool {
+        if let Some(ref pass_hash) = self.pass_hash {
+            password::verify_password(pass_hash, password).unwrap_or(false)
+        } else if let Some(ref pass) = self.pass {
+            // Password compared as UTF-8 bytes
+            password::constant_time_eq(pass.as_bytes(), password)
+        } else {
+            // Empty password for user
+            password.is_empty()
+        }
+    }
+
     pub fn is_user_pass(&self) -> bool {
-        self.x509.is_none()
+        self.x509.is_none() && self.jwt.is_none()
     }
 
     pub fn is_x509(&self) -> bool {
         self.x509.is_some()
     }
+
+    pub fn is_issued_token(&self) -> bool {
+        self.jwt.is_some()
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -157,6 +511,23 @@ pub struct Limits {
     pub send_buffer_size: usize,
     /// Receive buffer size in bytes
     pub receive_buffer_size: usize,
+    /// Maximum lifetime in hours of a durable subscription, 0 if durable subscriptions are not
+    /// supported
+    #[serde(default = "Limits::default_max_durable_subscription_lifetime_hours")]
+    pub max_durable_subscription_lifetime_hours: u32,
+    /// Maximum number of values in a monitored item queue of a durable subscription
+    #[serde(default = "Limits::default_max_durable_monitored_item_queue_size")]
+    pub max_durable_monitored_item_queue_size: usize,
+}
+
+impl Limits {
+    fn default_max_durable_subscription_lifetime_hours() -> u32 {
+        constants::DEFAULT_MAX_DURABLE_SUBSCRIPTION_LIFETIME_HOURS
+    }
+
+    fn default_max_durable_monitored_item_queue_size() -> usize {
+        constants::DEFAULT_MAX_DURABLE_MONITORED_ITEM_QUEUE_SIZE
+    }
 }
 
 impl Default for Limits {
@@ -176,6 +547,10 @@ impl Default for Limits {
             min_publishing_interval: constants::MIN_PUBLISHING_INTERVAL,
             send_buffer_size: SEND_BUFFER_SIZE,
             receive_buffer_size: RECEIVE_BUFFER_SIZE,
+            max_durable_subscription_lifetime_hours:
+                Self::default_max_durable_subscription_lifetime_hours(),
+            max_durable_monitored_item_queue_size:
+                Self::default_max_durable_monitored_item_queue_size(),
         }
     }
 }
@@ -513,6 +888,21 @@ impl ServerEndpoint {
         false
     }
 
+    /// Tests if this endpoint supports issued (JWT) tokens. It does this by looking to see
+    /// if any of the users allowed to access this endpoint are JWT users.
+    pub fn supports_issued_token(&self, server_tokens: &BTreeMap<String, ServerUserToken>) -> bool {
+        for user_token_id in &self.user_token_ids {
+            if user_token_id != ANONYMOUS_USER_TOKEN_ID {
+                if let Some(user_token) = server_tokens.get(user_token_id) {
+                    if user_token.is_issued_token() {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
     pub fn supports_user_token_id(&self, id: &str) -> bool {
         self.user_token_ids.contains(id)
     }
@@ -525,6 +915,145 @@ pub struct Performance {
     pub single_threaded_executor: bool,
 }
 
+/// Access to the admin API of the embedded HTTP server. The API is disabled unless at least one
+/// means of access is configured.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct HttpAdmin {
+    /// Bearer tokens that grant access, sent in an `Authorization: Bearer <token>` header
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<String>,
+    /// Ids of user name / password tokens in `user_tokens` that grant access with basic auth
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub user_token_ids: BTreeSet<String>,
+}
+
+impl HttpAdmin {
+    /// Tests if the admin API is enabled
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.user_token_ids.is_empty()
+    }
+
+    pub fn is_valid(&self, user_tokens: &BTreeMap<String, ServerUserToken>) -> bool {
+        let mut valid = true;
+        if self.tokens.iter().any(|token| token.is_empty()) {
+            error!("Http admin settings are invalid. A bearer token is empty");
+            valid = false;
+        }
+        for id in &self.user_token_ids {
+            match user_tokens.get(id) {
+                Some(user_token) if user_token.is_user_pass() => {}
+                _ => {
+                    error!(
+                        "Http admin settings are invalid. User token {} does not exist or is not a user name / password token",
+                        id
+                    );
+                    valid = false;
+                }
+            }
+        }
+        valid
+    }
+
+    /// Tests if the bearer token grants access. Comparisons are made in constant time.
+    pub fn authenticate_token(&self, token: &str) -> bool {
+        self.tokens
+            .iter()
+            .any(|t| password::constant_time_eq(t.as_bytes(), token.as_bytes()))
+    }
+
+    /// Tests if the user name and password match one of the user tokens that grant access.
+    pub fn authenticate_user(
+        &self,
+        user_tokens: &BTreeMap<String, ServerUserToken>,
+        user: &str,
+        pass: &[u8],
+    ) -> bool {
+        self.user_token_ids.iter().any(|id| {
+            user_tokens.get(id).is_some_and(|user_token| {
+                user_token.is_user_pass()
+                    && user_token.user == user
+                    && user_token.verify_password(pass)
+            })
+        })
+    }
+}
+
+/// Non-transparent redundancy of the server with the other servers of a redundant server set.
+/// Clients fail over between the servers of the set themselves, guided by their service level.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ServerRedundancy {
+    /// The failover mode supported by the set, one of "None", "Cold", "Warm", "Hot" or
+    /// "HotAndMirrored"
+    pub support: String,
+    /// Application uris of the servers of the set, including this server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub server_uris: Vec<String>,
+    /// Interval in milliseconds at which the service level is updated from the health check
+    /// and sent to the other servers of the set
+    pub update_interval_ms: u64,
+    /// Replicates the subscriptions of the server to the other servers of the set, so a client
+    /// that fails over can transfer them with TransferSubscriptions
+    #[serde(default)]
+    pub replicate_subscriptions: bool,
+}
+
+impl Default for ServerRedundancy {
+    fn default() -> Self {
+        Self {
+            support: "None".to_string(),
+            server_uris: Vec::new(),
+            update_interval_ms: 1000,
+            replicate_subscriptions: false,
+        }
+    }
+}
+
+impl ServerRedundancy {
+    /// The redundancy support, or `None` if the setting is not recognised
+    pub fn redundancy_support(&self) -> Option<RedundancySupport> {
+        match self.support.as_str() {
+            "None" => Some(RedundancySupport::None),
+            "Cold" => Some(RedundancySupport::Cold),
+            "Warm" => Some(RedundancySupport::Warm),
+            "Hot" => Some(RedundancySupport::Hot),
+            "HotAndMirrored" => Some(RedundancySupport::HotAndMirrored),
+            _ => None,
+        }
+    }
+
+    /// Tests if the server is part of a redundant server set
+    pub fn is_enabled(&self) -> bool {
+        !matches!(
+            self.redundancy_support(),
+            None | Some(RedundancySupport::None)
+        )
+    }
+
+    pub fn is_valid(&self, application_uri: &str) -> bool {
+        let mut valid = true;
+        if self.redundancy_support().is_none() {
+            error!(
+                "Redundancy settings are invalid. Redundancy support {} is not recognised",
+                self.support
+            );
+            valid = false;
+        } else if self.is_enabled() {
+            if !self.server_uris.iter().any(|uri| uri == application_uri) {
+                error!(
+                    "Redundancy settings are invalid. The server uris do not contain the application uri {}",
+                    application_uri
+                );
+                valid = false;
+            }
+            if self.update_interval_ms == 0 {
+                error!("Redundancy settings are invalid. The update interval is 0");
+                valid = false;
+            }
+        }
+        valid
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ServerConfig {
     /// An id for this server
@@ -540,15 +1069,44 @@ pub struct ServerConfig {
     pub certificate_path: Option<PathBuf>,
     /// Path to a custom private key, to be used instead of the default private key
     pub private_key_path: Option<PathBuf>,
+    /// Passphrase of the private key if it is an encrypted PKCS#8 key. A sample keypair is
+    /// created with an encrypted private key when this is set.
+    #[serde(default, skip_serializing_if = "passphrase::skip_serializing")]
+    pub private_key_passphrase: Option<PrivateKeyPassphrase>,
     /// Checks the certificate's time validity
     pub certificate_validation: CertificateValidation,
+    /// Automatic renewal of the server's own certificate before it expires
+    #[serde(default)]
+    pub certificate_renewal: CertificateRenewal,
+    /// Restricts the security policies of the endpoints and the client certificates they accept.
+    /// Deprecated policies are disabled by default.
+    #[serde(default)]
+    pub security_profile: SecurityProfile,
     /// PKI folder, either absolute or relative to executable
     pub pki_dir: PathBuf,
+    /// File that durable subscriptions are saved to, so they survive a restart of the server.
+    /// Without it, durable subscriptions only survive their session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durable_subscriptions_path: Option<PathBuf>,
+    /// Initial state of the Server.ServerDiagnostics.EnabledFlag, which controls if session and
+    /// subscription diagnostics are exposed in the address space. Clients may switch it at runtime.
+    #[serde(default)]
+    pub diagnostics_enabled: bool,
+    /// Access to the admin API of the embedded HTTP server, when the `http` feature is enabled
+    #[serde(default)]
+    pub http_admin: HttpAdmin,
+    /// Redundancy of the server with the other servers of a redundant server set
+    #[serde(default)]
+    pub redundancy: ServerRedundancy,
     /// Url to a discovery server - adding this string causes the server to assume you wish to
     /// register the server with a discovery server.
     pub discovery_server_url: Option<String>,
     /// tcp configuration information
     pub tcp_config: TcpConfig,
+    /// Sockets that the server listens on, each offering a set of endpoints. When empty, the
+    /// server listens on the host and port of the tcp configuration and offers all endpoints.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ServerListener>,
     /// Server OPA UA limits
     pub limits: Limits,
     /// Server Performance
@@ -584,8 +1142,16 @@ impl Config for ServerConfig {
         for (id, endpoint) in &self.endpoints {
             if !endpoint.is_valid(id, &self.user_tokens) {
                 valid = false;
+            } else if !self.is_endpoint_allowed(endpoint) {
+                warn!(
+                    "Endpoint {} is disabled because the security profile does not allow its security policy {}",
+                    id, endpoint.security_policy
+                );
             }
         }
+        if !self.security_profile.is_valid() {
+            valid = false;
+        }
         if let Some(ref default_endpoint) = self.default_endpoint {
             if !self.endpoints.contains_key(default_endpoint) {
                 valid = false;
@@ -596,6 +1162,25 @@ impl Config for ServerConfig {
                 valid = false;
             }
         }
+        if !self.http_admin.is_valid(&self.user_tokens) {
+            valid = false;
+        }
+        if !self.redundancy.is_valid(&self.application_uri) {
+            valid = false;
+        }
+        let mut listener_addresses = BTreeSet::new();
+        for listener in &self.listeners {
+            if !listener.is_valid(&self.endpoints) {
+                valid = false;
+            }
+            if !listener_addresses.insert(listener.base_endpoint_url()) {
+                error!(
+                    "Server configuration is invalid. Listener {} is defined more than once",
+                    listener.base_endpoint_url()
+                );
+                valid = false;
+            }
+        }
         if self.limits.max_array_length == 0 {
             error!("Server configuration is invalid. Max array length is invalid");
             valid = false;
@@ -650,14 +1235,22 @@ impl Default for ServerConfig {
             create_sample_keypair: false,
             certificate_path: None,
             private_key_path: None,
+            private_key_passphrase: None,
             pki_dir,
+            durable_subscriptions_path: None,
+            diagnostics_enabled: false,
+            http_admin: HttpAdmin::default(),
+            redundancy: ServerRedundancy::default(),
             certificate_validation: CertificateValidation::default(),
+            certificate_renewal: CertificateRenewal::default(),
+            security_profile: SecurityProfile::default(),
             discovery_server_url: None,
             tcp_config: TcpConfig {
                 host: "127.0.0.1".to_string(),
                 port: constants::DEFAULT_RUST_OPC_UA_SERVER_PORT,
                 hello_timeout: constants::DEFAULT_HELLO_TIMEOUT_SECONDS,
             },
+            listeners: Vec::new(),
             limits: Limits::default(),
             user_tokens: BTreeMap::new(),
             locale_ids: vec!["en".to_string()],
@@ -703,17 +1296,25 @@ impl ServerConfig {
             create_sample_keypair: false,
             certificate_path: None,
             private_key_path: None,
+            private_key_passphrase: None,
             certificate_validation: CertificateValidation {
                 trust_client_certs: false,
                 check_time: true,
             },
+            certificate_renewal: CertificateRenewal::default(),
+            security_profile: SecurityProfile::default(),
             pki_dir,
+            durable_subscriptions_path: None,
+            diagnostics_enabled: false,
+            http_admin: HttpAdmin::default(),
+            redundancy: ServerRedundancy::default(),
             discovery_server_url,
             tcp_config: TcpConfig {
                 host,
                 port,
                 hello_timeout: constants::DEFAULT_HELLO_TIMEOUT_SECONDS,
             },
+            listeners: Vec::new(),
             limits: Limits::default(),
             locale_ids,
             user_tokens,
@@ -748,33 +1349,93 @@ impl ServerConfig {
             .for_each(|(_, token)| token.read_thumbprint());
     }
 
+    /// Loads the JWT verification keys of the user tokens, by user token id. User tokens whose
+    /// key cannot be loaded are left out, so no token is accepted for them.
+    pub(crate) fn jwt_verification_keys(&self) -> BTreeMap<String, JwtVerificationKey> {
+        self.user_tokens
+            .iter()
+            .filter_map(|(user_token_id, token)| {
+                let key = token.jwt.as_ref()?.verification_key().ok();
+                if key.is_none() {
+                    error!(
+                        "Cannot load the JWT verification key of user token {}",
+                        user_token_id
+                    );
+                }
+                key.map(|key| (user_token_id.clone(), key))
+            })
+            .collect()
+    }
+
     /// Returns a opc.tcp://server:port url that paths can be appended onto
     pub fn base_endpoint_url(&self) -> String {
-        format!(
-            "opc.tcp://{}:{}",
-            self.tcp_config.host, self.tcp_config.port
-        )
+        base_endpoint_url(&self.tcp_config.host, self.tcp_config.port)
     }
 
-    /// Find the default endpoint
-    pub fn default_endpoint(&self) -> Option<&ServerEndpoint> {
+    /// Returns the listeners of the server. If none are configured, that is a single listener on
+    /// the host and port of the tcp configuration which offers all the endpoints.
+    pub fn listeners(&self) -> Vec<ServerListener> {
+        if self.listeners.is_empty() {
+            vec![ServerListener::new(
+                self.tcp_config.host.clone(),
+                self.tcp_config.port,
+                &[],
+            )]
+        } else {
+            self.listeners.clone()
+        }
+    }
+
+    /// Find the default endpoint, if it is offered through the listener
+    pub fn default_endpoint(&self, listener: &ServerListener) -> Option<&ServerEndpoint> {
         if let Some(ref default_endpoint) = self.default_endpoint {
-            self.endpoints.get(default_endpoint)
+            self.endpoints
+                .get(default_endpoint)
+                .filter(|e| self.is_endpoint_allowed(e))
+                .filter(|_| listener.offers_endpoint(default_endpoint))
         } else {
             None
         }
     }
 
-    /// Find the first endpoint that matches the specified url, security policy and message
-    /// security mode.
+    /// Tests if the security profile allows the endpoint's security policy. An endpoint that it
+    /// does not allow is neither offered to nor usable by clients.
+    pub fn is_endpoint_allowed(&self, endpoint: &ServerEndpoint) -> bool {
+        self.security_profile
+            .is_policy_allowed(endpoint.security_policy())
+    }
+
+    /// Returns the endpoints that the security profile allows
+    pub fn allowed_endpoints(&self) -> impl Iterator<Item = (&String, &ServerEndpoint)> {
+        self.endpoints
+            .iter()
+            .filter(|(_, e)| self.is_endpoint_allowed(e))
+    }
+
+    /// Returns the endpoints that the security profile allows and which are offered through the
+    /// listener
+    pub fn listener_endpoints<'a>(
+        &'a self,
+        listener: &'a ServerListener,
+    ) -> impl Iterator<Item = (&'a String, &'a ServerEndpoint)> {
+        self.allowed_endpoints()
+            .filter(|(id, _)| listener.offers_endpoint(id))
+    }
+
+    /// Find the first endpoint offered through the listener that matches the specified url,
+    /// security policy and message security mode.
     pub fn find_endpoint(
         &self,
+        listener: &ServerListener,
         endpoint_url: &str,
         security_policy: SecurityPolicy,
         security_mode: MessageSecurityMode,
     ) -> Option<&ServerEndpoint> {
-        let base_endpoint_url = self.base_endpoint_url();
-        let endpoint = self.endpoints.iter().find(|&(_, e)| {
+        let base_endpoint_url = listener.base_endpoint_url();
+        let endpoint = self.allowed_endpoints().find(|&(id, e)| {
+            if !listener.offers_endpoint(id) {
+                return false;
+            }
             // Test end point's security_policy_uri and matching url
             if url_matches_except_host(&e.endpoint_url(&base_endpoint_url), endpoint_url) {
                 if e.security_policy() == security_policy
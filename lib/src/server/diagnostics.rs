@@ -5,18 +5,111 @@
 //! Provides diagnostics structures and functions for gathering information about the running
 //! state of a server.
 
-use crate::types::service_types::ServerDiagnosticsSummaryDataType;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use tokio::sync::broadcast;
+
+use crate::types::{
+    service_types::{SamplingIntervalDiagnosticsDataType, ServerDiagnosticsSummaryDataType},
+    status_code::StatusCode,
+    DateTime, NodeId,
+};
 use crate::{deregister_runtime_component, register_runtime_component};
 
 use crate::core::RUNTIME;
 
-use super::{session::Session, subscriptions::subscription::Subscription};
+use super::{
+    session::Session, session_diagnostics::SessionDiagnosticsSnapshot,
+    subscriptions::subscription::Subscription,
+};
+
+/// Upper bounds in seconds of the buckets of the service latency histograms
+pub const SERVICE_LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+/// Request counts and latencies of a service
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct ServiceMetrics {
+    /// Number of requests received
+    pub request_count: u64,
+    /// Number of requests that ended in a service fault
+    pub error_count: u64,
+    /// Number of responses with a latency at or under each of `SERVICE_LATENCY_BUCKETS`
+    pub latency_buckets: [u64; SERVICE_LATENCY_BUCKETS.len()],
+    /// Number of responses whose latency was measured
+    pub latency_count: u64,
+    /// Sum of the measured latencies in seconds
+    pub latency_sum: f64,
+}
+
+impl ServiceMetrics {
+    fn observe_latency(&mut self, latency: Duration) {
+        let latency = latency.as_secs_f64();
+        SERVICE_LATENCY_BUCKETS
+            .iter()
+            .zip(self.latency_buckets.iter_mut())
+            .filter(|(le, _)| latency <= **le)
+            .for_each(|(_, count)| *count += 1);
+        self.latency_count += 1;
+        self.latency_sum += latency;
+    }
+}
+
+/// The most recent events that are kept for listeners that start listening later
+const MAX_RECENT_EVENTS: usize = 100;
+
+/// The kind of a server event
+#[derive(Clone, Copy, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerEventKind {
+    /// An audit event was raised
+    Audit,
+    /// A connection was closed because of an error on its secure channel
+    SecureChannelError,
+}
+
+/// Something that happened in the server that is worth showing to an operator straight away
+#[derive(Clone, Serialize, Debug)]
+pub struct ServerEvent {
+    pub time: DateTime,
+    pub kind: ServerEventKind,
+    pub message: String,
+}
 
 /// Structure that captures di                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                          agnostics information for the server
 #[derive(Clone, Serialize, Debug)]
 pub struct ServerDiagnostics {
     /// This is a live summary of the server diagnostics
     server_diagnostics_summary: ServerDiagnosticsSummaryDataType,
+    /// Number of connections currently open
+    current_connection_count: u32,
+    /// Number of connections opened since the server started
+    cumulated_connection_count: u32,
+    /// Number of monitored items currently in subscriptions
+    current_monitored_item_count: u32,
+    /// Number of publish requests currently queued by sessions
+    current_publish_request_count: u32,
+    /// Number of client certificates rejected, by the status code of the rejection
+    certificate_rejections: BTreeMap<String, u64>,
+    /// Request counts and latencies, by service name
+    services: BTreeMap<String, ServiceMetrics>,
+    /// The most recent events, oldest first
+    recent_events: VecDeque<ServerEvent>,
+    /// Sends events to live listeners as they happen
+    #[serde(skip)]
+    event_sender: broadcast::Sender<ServerEvent>,
+    /// The diagnostics of every session while they are exposed in the address space
+    #[serde(skip)]
+    sessions: Option<HashMap<NodeId, SessionDiagnosticsSnapshot>>,
+    /// The most monitored items there have been at once for each sampling interval while the
+    /// diagnostics of the sessions are exposed
+    #[serde(skip)]
+    max_monitored_item_counts: BTreeMap<u64, u32>,
 }
 
 const SERVER_DIAGNOSTICS: &str = "ServerDiagnostics";
@@ -26,6 +119,16 @@ impl Default for ServerDiagnostics {
         register_runtime_component!(SERVER_DIAGNOSTICS);
         Self {
             server_diagnostics_summary: ServerDiagnosticsSummaryDataType::default(),
+            current_connection_count: 0,
+            cumulated_connection_count: 0,
+            current_monitored_item_count: 0,
+            current_publish_request_count: 0,
+            certificate_rejections: BTreeMap::new(),
+            services: BTreeMap::new(),
+            recent_events: VecDeque::with_capacity(MAX_RECENT_EVENTS),
+            event_sender: broadcast::channel(MAX_RECENT_EVENTS).0,
+            sessions: None,
+            max_monitored_item_counts: BTreeMap::new(),
         }
     }
 }
@@ -43,6 +146,135 @@ impl ServerDiagnostics {
         &self.server_diagnostics_summary
     }
 
+    /// Returns the number of connections currently open
+    pub fn current_connection_count(&self) -> u32 {
+        self.current_connection_count
+    }
+
+    /// Returns the number of connections opened since the server started
+    pub fn cumulated_connection_count(&self) -> u32 {
+        self.cumulated_connection_count
+    }
+
+    /// Returns the number of monitored items currently in subscriptions
+    pub fn current_monitored_item_count(&self) -> u32 {
+        self.current_monitored_item_count
+    }
+
+    /// Returns the number of publish requests currently queued by sessions
+    pub fn current_publish_request_count(&self) -> u32 {
+        self.current_publish_request_count
+    }
+
+    /// Returns the number of client certificates rejected, by the status code of the rejection
+    pub fn certificate_rejections(&self) -> &BTreeMap<String, u64> {
+        &self.certificate_rejections
+    }
+
+    /// Returns the request counts and latencies, by service name
+    pub fn services(&self) -> &BTreeMap<String, ServiceMetrics> {
+        &self.services
+    }
+
+    /// Returns the most recent events, oldest first
+    pub fn recent_events(&self) -> &VecDeque<ServerEvent> {
+        &self.recent_events
+    }
+
+    /// Returns a receiver of the events that happen from now on
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerEvent> {
+        self.event_sender.subscribe()
+    }
+
+    fn push_event(&mut self, kind: ServerEventKind, message: String) {
+        let event = ServerEvent {
+            time: DateTime::now(),
+            kind,
+            message,
+        };
+        if self.recent_events.len() == MAX_RECENT_EVENTS {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back(event.clone());
+        // Sending only fails when nobody is listening
+        let _ = self.event_sender.send(event);
+    }
+
+    /// Record an audit event that was raised.
+    pub(crate) fn on_audit_event(&mut self, message: String) {
+        self.push_event(ServerEventKind::Audit, message);
+    }
+
+    /// Record a connection that was closed because of an error on its secure channel.
+    pub(crate) fn on_secure_channel_error(
+        &mut self,
+        status_code: StatusCode,
+        client_address: Option<SocketAddr>,
+    ) {
+        let message = if let Some(client_address) = client_address {
+            format!(
+                "Connection from {} closed with {}",
+                client_address, status_code
+            )
+        } else {
+            format!("Connection closed with {}", status_code)
+        };
+        self.push_event(ServerEventKind::SecureChannelError, message);
+    }
+
+    /// Increment the number of connections currently open.
+    pub(crate) fn on_open_connection(&mut self) {
+        self.current_connection_count += 1;
+        self.cumulated_connection_count += 1;
+    }
+
+    /// Decrement the number of connections currently open.
+    pub(crate) fn on_close_connection(&mut self) {
+        self.current_connection_count = self.current_connection_count.saturating_sub(1);
+    }
+
+    /// Adjust the number of monitored items when items are added to or removed from a subscription.
+    pub(crate) fn on_monitored_items_changed(&mut self, added: usize, removed: usize) {
+        self.current_monitored_item_count =
+            (self.current_monitored_item_count + added as u32).saturating_sub(removed as u32);
+    }
+
+    /// Adjust the number of queued publish requests when a session queues or takes requests.
+    pub(crate) fn on_publish_requests_changed(&mut self, queued: usize, taken: usize) {
+        self.current_publish_request_count =
+            (self.current_publish_request_count + queued as u32).saturating_sub(taken as u32);
+    }
+
+    /// Increment the number of client certificates rejected with the status code.
+    pub(crate) fn on_rejected_certificate(&mut self, status_code: StatusCode) {
+        *self
+            .certificate_rejections
+            .entry(status_code.name().to_string())
+            .or_default() += 1;
+    }
+
+    /// Count a request to a service. The latency is that of the response, or `None` if the
+    /// response is sent later on, e.g. for a publish request.
+    pub(crate) fn on_service_request(
+        &mut self,
+        service: &str,
+        latency: Option<Duration>,
+        error: bool,
+    ) {
+        let service_metrics = if let Some(service_metrics) = self.services.get_mut(service) {
+            service_metrics
+        } else {
+            self.services.entry(service.to_string()).or_default()
+        };
+        service_metrics.request_count += 1;
+        if error {
+            service_metrics.error_count += 1;
+        }
+        if let Some(latency) = latency {
+            service_metrics.observe_latency(latency);
+        }
+    }
+
     /// Increment the number of requests that were rejected due to security constraints since the server was
     /// started (or restarted). The requests include all Services defined in Part 4, also requests
     /// to create sessions.
@@ -59,33 +291,126 @@ impl ServerDiagnostics {
     }
 
     /// Increment the number of client sessions currently established in the server.
-    pub(crate) fn on_create_session(&mut self, _session: &Session) {
+    pub(crate) fn on_create_session(&mut self, session: &Session) {
         self.server_diagnostics_summary.current_session_count += 1;
         self.server_diagnostics_summary.cumulated_session_count += 1;
         debug!(
-            "Incrementing current session count to {}",
+            "Session {} created, incrementing current session count to {}",
+            session.session_id(),
             self.server_diagnostics_summary.current_session_count
         );
     }
 
     /// Decrement the number of client sessions currently established in the server.
-    pub(crate) fn on_destroy_session(&mut self, _session: &Session) {
+    pub(crate) fn on_destroy_session(&mut self, session: &Session) {
         self.server_diagnostics_summary.current_session_count -= 1;
         debug!(
-            "Decrementing current session count to {}",
+            "Session {} destroyed, decrementing current session count to {}",
+            session.session_id(),
             self.server_diagnostics_summary.current_session_count
         );
     }
 
     /// Increment the number of subscriptions currently established in the server.
-    pub(crate) fn on_create_subscription(&mut self, _subscription: &Subscription) {
+    pub(crate) fn on_create_subscription(&mut self, subscription: &Subscription) {
         self.server_diagnostics_summary.current_subscription_count += 1;
         self.server_diagnostics_summary.cumulated_subscription_count += 1;
+        debug!(
+            "Subscription {} created, incrementing current subscription count to {}",
+            subscription.subscription_id(),
+            self.server_diagnostics_summary.current_subscription_count
+        );
     }
 
     /// Decrement the number of subscriptions currently established in the server.
-    pub(crate) fn on_destroy_subscription(&mut self, _subscription: &Subscription) {
+    pub(crate) fn on_destroy_subscription(&mut self, subscription: &Subscription) {
         self.server_diagnostics_summary.current_subscription_count -= 1;
+        self.on_monitored_items_changed(0, subscription.monitored_items_len());
+        debug!(
+            "Subscription {} destroyed, decrementing current subscription count to {}",
+            subscription.subscription_id(),
+            self.server_diagnostics_summary.current_subscription_count
+        );
+    }
+
+    /// Tests if the diagnostics of the sessions are exposed in the address space, i.e. they are
+    /// enabled and every session has been added since.
+    pub(crate) fn is_exposing_sessions(&self) -> bool {
+        self.sessions.is_some()
+    }
+
+    /// Start exposing the diagnostics of the sessions, beginning with those of every session.
+    pub(crate) fn on_expose_sessions(&mut self, sessions: Vec<SessionDiagnosticsSnapshot>) {
+        self.sessions = Some(
+            sessions
+                .into_iter()
+                .map(|session| (session.session_id.clone(), session))
+                .collect(),
+        );
+        self.max_monitored_item_counts.clear();
+    }
+
+    /// Stop exposing the diagnostics of the sessions. Returns `true` if they were exposed.
+    pub(crate) fn on_hide_sessions(&mut self) -> bool {
+        self.max_monitored_item_counts.clear();
+        self.sessions.take().is_some()
+    }
+
+    /// Replace the diagnostics of a session after it changed, if they are exposed.
+    pub(crate) fn on_session_changed(&mut self, session: SessionDiagnosticsSnapshot) {
+        if let Some(ref mut sessions) = self.sessions {
+            sessions.insert(session.session_id.clone(), session);
+        }
+    }
+
+    /// Remove the diagnostics of a session that has gone.
+    pub(crate) fn on_session_removed(&mut self, session_id: &NodeId) {
+        if let Some(ref mut sessions) = self.sessions {
+            sessions.remove(session_id);
+        }
+    }
+
+    /// Returns the diagnostics of every session, if they are exposed, with the sampling interval
+    /// diagnostics made from their monitored items.
+    pub(crate) fn exposed_sessions(
+        &mut self,
+    ) -> Option<(
+        Vec<&SessionDiagnosticsSnapshot>,
+        Vec<SamplingIntervalDiagnosticsDataType>,
+    )> {
+        let sessions = self.sessions.as_ref()?;
+        let mut sampling_interval_counts = BTreeMap::<u64, (u32, u32)>::new();
+        for session in sessions.values() {
+            for (sampling_interval, (monitored_item_count, disabled_count)) in
+                &session.sampling_interval_counts
+            {
+                let counts = sampling_interval_counts
+                    .entry(*sampling_interval)
+                    .or_default();
+                counts.0 += monitored_item_count;
+                counts.1 += disabled_count;
+            }
+        }
+        let max_monitored_item_counts = &mut self.max_monitored_item_counts;
+        let sampling_interval_diagnostics = sampling_interval_counts
+            .into_iter()
+            .map(
+                |(sampling_interval, (monitored_item_count, disabled_count))| {
+                    let max_monitored_item_count = max_monitored_item_counts
+                        .entry(sampling_interval)
+                        .or_default();
+                    *max_monitored_item_count =
+                        u32::max(*max_monitored_item_count, monitored_item_count);
+                    SamplingIntervalDiagnosticsDataType {
+                        sampling_interval: f64::from_bits(sampling_interval),
+                        monitored_item_count,
+                        max_monitored_item_count: *max_monitored_item_count,
+                        disabled_monitored_item_count: disabled_count,
+                    }
+                },
+            )
+            .collect();
+        Some((sessions.values().collect(), sampling_interval_diagnostics))
     }
 
     /// Increment the number of client sessions that were closed due to timeout since the server was started (or restarted).
@@ -93,6 +418,15 @@ impl ServerDiagnostics {
         self.server_diagnostics_summary.session_timeout_count += 1;
     }
 
+    /// Increment the number of requests that were rejected due to security constraints since the server was
+    /// started (or restarted). The requests include all Services defined in Part 4, also requests
+    /// to create sessions. Rejected requests are counted too.
+    pub(crate) fn on_security_rejected_request(&mut self) {
+        self.server_diagnostics_summary
+            .security_rejected_requests_count += 1;
+        self.server_diagnostics_summary.rejected_requests_count += 1;
+    }
+
     // --- These are not yet called by anything
 
     /*
@@ -114,14 +448,6 @@ impl ServerDiagnostics {
         unimplemented!()
     }
 
-    /// Increment the number of requests that were rejected due to security constraints since the server was
-    /// started (or restarted). The requests include all Services defined in Part 4, also requests
-    /// to create sessions.
-    pub fn on_security_rejected_request(&mut self) {
-        self.server_diagnostics_summary.security_rejected_requests_count += 1;
-        unimplemented!()
-    }
-
     /// Increment the number of requests that were rejected since the server was started (or restarted). The
     /// requests include all Services defined in Part 4, also requests to create sessions. This
     /// number includes the securityRejectedRequestsCount.
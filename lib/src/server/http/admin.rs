@@ -0,0 +1,467 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! The admin API of the embedded HTTP server. It lists and closes sessions, inspects their
+//! subscriptions, lists and trusts rejected certificates, changes the log level and browses, reads
+//! and writes nodes, all as JSON. Every request must be authenticated with a bearer token or with
+//! basic auth as configured by `http_admin` in the server configuration.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use actix_web::{
+    dev::Payload, http::header, http::StatusCode as HttpStatusCode, web, FromRequest, HttpRequest,
+    HttpResponse, ResponseError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::{ready, Ready};
+use log::LevelFilter;
+
+use crate::crypto::CertificateStore;
+use crate::sync::*;
+use crate::types::{
+    AttributeId, BrowseDirection, DataValue, DateTime, NodeId, NumericRange, QualifiedName,
+    TimestampsToReturn, Variant,
+};
+
+use crate::server::{
+    address_space::AddressSpace, server::Server, session::SessionManager, state::ServerState,
+    subscriptions::subscriptions,
+};
+
+/// The state shared by the handlers of the admin API
+#[derive(Clone)]
+pub struct AdminState {
+    pub server_state: Arc<RwLock<ServerState>>,
+    pub session_manager: Arc<RwLock<SessionManager>>,
+    pub address_space: Arc<RwLock<AddressSpace>>,
+    pub certificate_store: Arc<RwLock<CertificateStore>>,
+}
+
+impl AdminState {
+    pub fn new(server: &Server) -> AdminState {
+        AdminState {
+            server_state: server.server_state(),
+            session_manager: server.session_manager(),
+            address_space: server.address_space(),
+            certificate_store: server.certificate_store(),
+        }
+    }
+}
+
+/// Errors returned by the admin API. They are sent as JSON with an `error` field.
+#[derive(Debug)]
+pub enum AdminError {
+    /// The admin API is not enabled by the configuration
+    Disabled,
+    /// The request has no valid credentials
+    Unauthorized,
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::Disabled => write!(f, "The admin API is disabled"),
+            AdminError::Unauthorized => write!(f, "Unauthorized"),
+            AdminError::NotFound(message) | AdminError::BadRequest(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> HttpStatusCode {
+        match self {
+            AdminError::Disabled => HttpStatusCode::FORBIDDEN,
+            AdminError::Unauthorized => HttpStatusCode::UNAUTHORIZED,
+            AdminError::NotFound(_) => HttpStatusCode::NOT_FOUND,
+            AdminError::BadRequest(_) => HttpStatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let AdminError::Unauthorized = self {
+            response.insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"opcua\""));
+        }
+        response.json(serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// An authenticated request. Handlers that take it as an argument are only called when the
+/// request carries credentials that the `http_admin` configuration accepts.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = AdminError;
+    type Future = Ready<Result<Admin, AdminError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let Some(state) = req.app_data::<web::Data<AdminState>>() else {
+            return ready(Err(AdminError::Disabled));
+        };
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        ready(authenticate(&state.server_state, authorization))
+    }
+}
+
+/// Tests the credentials of the authorization header against the `http_admin` configuration
+fn authenticate(
+    server_state: &Arc<RwLock<ServerState>>,
+    authorization: Option<&str>,
+) -> Result<Admin, AdminError> {
+    let config = {
+        let server_state = trace_read_lock!(server_state);
+        server_state.config.clone()
+    };
+    let config = trace_read_lock!(config);
+    if !config.http_admin.is_enabled() {
+        return Err(AdminError::Disabled);
+    }
+    let authenticated = match authorization.and_then(|a| a.split_once(' ')) {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => {
+            config.http_admin.authenticate_token(token.trim())
+        }
+        Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("Basic") => STANDARD
+            .decode(credentials.trim())
+            .ok()
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .is_some_and(|credentials| {
+                if let Some((user, pass)) = credentials.split_once(':') {
+                    config
+                        .http_admin
+                        .authenticate_user(&config.user_tokens, user, pass.as_bytes())
+                } else {
+                    false
+                }
+            }),
+        _ => false,
+    };
+    if authenticated {
+        Ok(Admin)
+    } else {
+        warn!("Admin API request was rejected because its credentials are missing or invalid");
+        Err(AdminError::Unauthorized)
+    }
+}
+
+fn parse_node_id(node_id: &str) -> Result<NodeId, AdminError> {
+    NodeId::from_str(node_id)
+        .map_err(|_| AdminError::BadRequest(format!("Invalid node id {}", node_id)))
+}
+
+#[derive(Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub session_name: String,
+    pub client_application_uri: String,
+    pub client_user_id: String,
+    pub endpoint_url: String,
+    pub security_mode: String,
+    pub activated: bool,
+    pub client_connection_time: String,
+    pub last_service_request_time: String,
+    pub subscription_count: usize,
+}
+
+async fn list_sessions(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+) -> Result<HttpResponse, AdminError> {
+    let sessions = {
+        let session_manager = trace_read_lock!(state.session_manager);
+        session_manager
+            .sessions
+            .values()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let mut sessions = sessions
+        .iter()
+        .map(|session| {
+            let session = trace_read_lock!(session);
+            SessionInfo {
+                session_id: session.session_id().to_string(),
+                session_name: session.session_name().to_string(),
+                client_application_uri: session.client_description().application_uri.to_string(),
+                client_user_id: session.client_user_id().to_string(),
+                endpoint_url: session.endpoint_url().to_string(),
+                security_mode: format!("{:?}", session.security_mode()),
+                activated: session.is_activated(),
+                client_connection_time: session.client_connection_time().to_rfc3339(),
+                last_service_request_time: session.last_service_request_timestamp().to_rfc3339(),
+                subscription_count: session.subscriptions().len(),
+            }
+        })
+        .collect::<Vec<_>>();
+    sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+async fn close_session(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    session_id: web::Path<String>,
+) -> Result<HttpResponse, AdminError> {
+    let session_id = parse_node_id(&session_id)?;
+    let closed = {
+        let mut session_manager = trace_write_lock!(state.session_manager);
+        session_manager.close_session(&session_id, state.address_space.clone())
+    };
+    if closed {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AdminError::NotFound(format!("No session {}", session_id)))
+    }
+}
+
+async fn session_subscriptions(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    session_id: web::Path<String>,
+) -> Result<HttpResponse, AdminError> {
+    let session_id = parse_node_id(&session_id)?;
+    let session = {
+        let session_manager = trace_read_lock!(state.session_manager);
+        session_manager.find_session_by_id(&session_id)
+    };
+    let Some(session) = session else {
+        return Err(AdminError::NotFound(format!("No session {}", session_id)));
+    };
+    let metrics: subscriptions::Metrics = {
+        let session = trace_read_lock!(session);
+        session.subscriptions().metrics()
+    };
+    Ok(HttpResponse::Ok().json(metrics))
+}
+
+#[derive(Serialize)]
+pub struct CertificateInfo {
+    pub file_name: String,
+    pub subject_name: String,
+    pub thumbprint: String,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+}
+
+async fn list_rejected_certificates(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+) -> Result<HttpResponse, AdminError> {
+    let certs = {
+        let certificate_store = trace_read_lock!(state.certificate_store);
+        certificate_store.rejected_certs()
+    };
+    let certs = certs
+        .into_iter()
+        .map(|(file_name, cert)| CertificateInfo {
+            file_name,
+            subject_name: cert.subject_name(),
+            thumbprint: cert.thumbprint().as_hex_string(),
+            not_before: cert.not_before().ok().map(|t| t.to_rfc3339()),
+            not_after: cert.not_after().ok().map(|t| t.to_rfc3339()),
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(certs))
+}
+
+async fn trust_rejected_certificate(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    file_name: web::Path<String>,
+) -> Result<HttpResponse, AdminError> {
+    let certificate_store = trace_read_lock!(state.certificate_store);
+    if file_name.contains(['/', '\\'])
+        || !certificate_store
+            .rejected_certs_dir()
+            .join(&*file_name)
+            .is_file()
+    {
+        return Err(AdminError::NotFound(format!(
+            "No rejected certificate {}",
+            file_name
+        )));
+    }
+    certificate_store
+        .trust_rejected_cert(&file_name)
+        .map(|_| HttpResponse::NoContent().finish())
+        .map_err(AdminError::BadRequest)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogLevel {
+    pub level: String,
+}
+
+async fn get_log_level(_admin: Admin) -> Result<HttpResponse, AdminError> {
+    Ok(HttpResponse::Ok().json(LogLevel {
+        level: log::max_level().to_string().to_lowercase(),
+    }))
+}
+
+async fn set_log_level(
+    _admin: Admin,
+    log_level: web::Json<LogLevel>,
+) -> Result<HttpResponse, AdminError> {
+    let level = LevelFilter::from_str(&log_level.level)
+        .map_err(|_| AdminError::BadRequest(format!("Invalid log level {}", log_level.level)))?;
+    info!("Log level is being changed to {} by the admin API", level);
+    log::set_max_level(level);
+    Ok(HttpResponse::Ok().json(LogLevel {
+        level: level.to_string().to_lowercase(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct NodeQuery {
+    pub node_id: String,
+}
+
+#[derive(Serialize)]
+pub struct ReferenceInfo {
+    pub reference_type: String,
+    pub is_forward: bool,
+    pub node_id: String,
+    pub browse_name: Option<String>,
+    pub display_name: Option<String>,
+    pub node_class: Option<String>,
+}
+
+async fn browse_node(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    query: web::Query<NodeQuery>,
+) -> Result<HttpResponse, AdminError> {
+    let node_id = parse_node_id(&query.node_id)?;
+    let address_space = trace_read_lock!(state.address_space);
+    if !address_space.node_exists(&node_id) {
+        return Err(AdminError::NotFound(format!("No node {}", node_id)));
+    }
+    let (references, inverse_ref_idx) =
+        address_space.find_references_by_direction::<NodeId>(&node_id, BrowseDirection::Both, None);
+    let references = references
+        .into_iter()
+        .enumerate()
+        .map(|(idx, reference)| {
+            let target = address_space.find_node(&reference.target_node);
+            ReferenceInfo {
+                reference_type: reference.reference_type.to_string(),
+                is_forward: idx < inverse_ref_idx,
+                node_id: reference.target_node.to_string(),
+                browse_name: target.map(|n| n.as_node().browse_name().name.to_string()),
+                display_name: target.map(|n| n.as_node().display_name().text.to_string()),
+                node_class: target.map(|n| format!("{:?}", n.node_class())),
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(references))
+}
+
+#[derive(Serialize)]
+pub struct AttributeValue {
+    pub attribute: String,
+    pub value: DataValue,
+}
+
+async fn read_node(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    query: web::Query<NodeQuery>,
+) -> Result<HttpResponse, AdminError> {
+    let node_id = parse_node_id(&query.node_id)?;
+    let address_space = trace_read_lock!(state.address_space);
+    let Some(node) = address_space.find_node(&node_id) else {
+        return Err(AdminError::NotFound(format!("No node {}", node_id)));
+    };
+    let node = node.as_node();
+    let attributes = (AttributeId::NodeId as u32..=AttributeId::AccessLevelEx as u32)
+        .filter_map(|attribute_id| AttributeId::from_u32(attribute_id).ok())
+        .filter_map(|attribute_id| {
+            node.get_attribute(
+                TimestampsToReturn::Both,
+                attribute_id,
+                NumericRange::None,
+                &QualifiedName::null(),
+            )
+            .map(|value| AttributeValue {
+                attribute: format!("{:?}", attribute_id),
+                value,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(attributes))
+}
+
+#[derive(Deserialize)]
+pub struct WriteValue {
+    pub node_id: String,
+    /// The value as an OPC UA JSON variant, e.g. `{"Type": 6, "Body": 30}`
+    pub value: Variant,
+}
+
+async fn write_node(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+    write_value: web::Json<WriteValue>,
+) -> Result<HttpResponse, AdminError> {
+    let write_value = write_value.into_inner();
+    let node_id = parse_node_id(&write_value.node_id)?;
+    let now = DateTime::now();
+    let mut address_space = trace_write_lock!(state.address_space);
+    if address_space.set_variable_value_by_ref(&node_id, write_value.value, &now, &now) {
+        info!("Variable {} was written by the admin API", node_id);
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AdminError::NotFound(format!("No variable {}", node_id)))
+    }
+}
+
+async fn abort_server(
+    _admin: Admin,
+    state: web::Data<AdminState>,
+) -> Result<HttpResponse, AdminError> {
+    if cfg!(debug_assertions) {
+        // Abort the server from the command
+        let mut server_state = trace_write_lock!(state.server_state);
+        server_state.abort();
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        // Abort is only enabled in debug mode
+        Err(AdminError::NotFound(
+            "Abort is only enabled in debug builds".to_string(),
+        ))
+    }
+}
+
+/// Adds the routes of the admin API beneath `/admin`. The app must hold an `AdminState`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/sessions", web::get().to(list_sessions))
+            .route("/sessions/{session_id}", web::delete().to(close_session))
+            .route(
+                "/sessions/{session_id}/subscriptions",
+                web::get().to(session_subscriptions),
+            )
+            .route(
+                "/certificates/rejected",
+                web::get().to(list_rejected_certificates),
+            )
+            .route(
+                "/certificates/rejected/{file_name}/trust",
+                web::post().to(trust_rejected_certificate),
+            )
+            .route("/log-level", web::get().to(get_log_level))
+            .route("/log-level", web::put().to(set_log_level))
+            .route("/nodes/browse", web::get().to(browse_node))
+            .route("/nodes/read", web::get().to(read_node))
+            .route("/nodes/write", web::post().to(write_node))
+            .route("/server/abort", web::post().to(abort_server)),
+    );
+}
@@ -0,0 +1,81 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Server-sent events that keep a live view of the server up to date. A `metrics` event holds the
+//! json server metrics and is sent periodically, and straight after anything worth showing
+//! happens. An `event` message holds a `ServerEvent`, e.g. an audit event or a secure channel
+//! error. A new listener is sent the recent events first.
+
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use actix_web::web::Bytes;
+use futures::{stream, Stream};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{interval_at, Instant},
+};
+
+use crate::sync::*;
+
+use crate::server::{constants, diagnostics::ServerEvent, state::ServerState};
+
+/// Formats a server-sent event. The data must be a single line.
+fn sse_message(event: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+fn event_message(event: &ServerEvent) -> Bytes {
+    sse_message("event", &serde_json::to_string(event).unwrap())
+}
+
+/// Returns an endless stream of server-sent events for the server. The metrics function is
+/// called for the json of each `metrics` event.
+pub fn event_stream<F>(
+    server_state: Arc<RwLock<ServerState>>,
+    metrics: F,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>>
+where
+    F: Fn() -> String + 'static,
+{
+    let (pending, receiver) = {
+        let server_state = trace_read_lock!(server_state);
+        let diagnostics = trace_read_lock!(server_state.diagnostics);
+        let pending = diagnostics
+            .recent_events()
+            .iter()
+            .map(event_message)
+            .collect::<VecDeque<_>>();
+        (pending, diagnostics.subscribe_events())
+    };
+    let period = Duration::from_millis(constants::LIVE_METRICS_INTERVAL_MS);
+    let timer = interval_at(Instant::now() + period, period);
+    let mut pending = pending;
+    pending.push_back(sse_message("metrics", &metrics()));
+
+    stream::unfold(
+        (pending, receiver, timer, metrics),
+        |(mut pending, mut receiver, mut timer, metrics)| async move {
+            loop {
+                if let Some(message) = pending.pop_front() {
+                    return Some((Ok(message), (pending, receiver, timer, metrics)));
+                }
+                tokio::select! {
+                    _ = timer.tick() => {
+                        pending.push_back(sse_message("metrics", &metrics()));
+                    }
+                    event = receiver.recv() => match event {
+                        Ok(event) => {
+                            pending.push_back(event_message(&event));
+                            pending.push_back(sse_message("metrics", &metrics()));
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("Live view skipped {} server events", skipped);
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        },
+    )
+}
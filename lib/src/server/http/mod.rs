@@ -10,11 +10,20 @@ use tokio::runtime::Runtime;
 
 use crate::sync::*;
 
-use crate::server::{metrics::ServerMetrics, server::Connections, state::ServerState};
+use crate::server::{
+    metrics::{self, ServerMetrics},
+    server::{Connections, Server},
+    state::ServerState,
+};
+
+use self::admin::{Admin, AdminState};
+
+pub mod admin;
+pub mod live;
 
 /// This is our metrics service, the thing called to handle requests coming from hyper
 #[derive(Clone)]
-struct AppState {
+pub(crate) struct AppState {
     server_state: Arc<RwLock<ServerState>>,
     connections: Arc<RwLock<Connections>>,
     server_metrics: Arc<RwLock<ServerMetrics>>,
@@ -29,48 +38,87 @@ async fn index(data: web::Data<AppState>) -> Result<fs::NamedFile> {
     Ok(fs::NamedFile::open(index_path)?)
 }
 
-async fn abort(data: web::Data<AppState>) -> impl Responder {
-    if cfg!(debug_assertions) {
-        // Abort the server from the command
-        let mut server_state = data.server_state.write();
-        server_state.abort();
-        HttpResponse::Ok().content_type("text/plain").body("OK")
-    } else {
-        // Abort is only enabled in debug mode
-        HttpResponse::Ok()
-            .content_type("text/plain")
-            .body("NOT IMPLEMENTED")
+impl AppState {
+    pub(crate) fn new(
+        server_state: Arc<RwLock<ServerState>>,
+        connections: Arc<RwLock<Connections>>,
+        server_metrics: Arc<RwLock<ServerMetrics>>,
+        content_path: &str,
+    ) -> AppState {
+        AppState {
+            server_state,
+            connections,
+            server_metrics,
+            base_path: Arc::new(RwLock::new(PathBuf::from(content_path))),
+        }
     }
-}
 
-async fn metrics(data: web::Data<AppState>) -> impl Responder {
-    use std::ops::Deref;
+    /// Updates the server metrics and returns them as json
+    fn metrics_json(&self, pretty: bool) -> String {
+        use std::ops::Deref;
 
-    // Send metrics data as json
-    let json = {
         // Careful with the ordering here to avoid potential deadlock. Metrics are locked
         // several times in scope to avoid deadlocks issues.
         {
-            let server_state = data.server_state.read();
-            let mut server_metrics = data.server_metrics.write();
+            let server_state = self.server_state.read();
+            let mut server_metrics = self.server_metrics.write();
             server_metrics.update_from_server_state(&server_state);
         }
 
         // Take a copy of connections
         let connections = {
-            let connections = data.connections.read();
+            let connections = self.connections.read();
             connections.clone()
         };
-        let mut server_metrics = data.server_metrics.write();
+        let mut server_metrics = self.server_metrics.write();
         server_metrics.update_from_connections(connections);
-        serde_json::to_string_pretty(server_metrics.deref()).unwrap()
-    };
+        if pretty {
+            serde_json::to_string_pretty(server_metrics.deref()).unwrap()
+        } else {
+            serde_json::to_string(server_metrics.deref()).unwrap()
+        }
+    }
+}
 
+async fn metrics(data: web::Data<AppState>) -> impl Responder {
+    // Send metrics data as json
+    let json = data.metrics_json(true);
     HttpResponse::Ok()
         .content_type("application/json")
         .body(json)
 }
 
+async fn events(_admin: Admin, data: web::Data<AppState>) -> impl Responder {
+    // Push metrics and server events to the page as server-sent events. Events reveal who
+    // connects from where, so the feed is only served to admin users.
+    let server_state = data.server_state.clone();
+    let stream = live::event_stream(server_state, move || data.metrics_json(false));
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+async fn open_metrics(data: web::Data<AppState>) -> impl Responder {
+    // Metrics in the OpenMetrics text format for Prometheus
+    let text = {
+        let server_state = data.server_state.read();
+        metrics::open_metrics(&server_state)
+    };
+    HttpResponse::Ok()
+        .content_type(metrics::OPEN_METRICS_CONTENT_TYPE)
+        .body(text)
+}
+
+/// Adds the routes of the metrics and the index page. The app must hold an `AppState`, and an
+/// `AdminState` for the live feed of server events to be served.
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/server/metrics", web::get().to(metrics))
+        .route("/metrics", web::get().to(open_metrics))
+        .route("/server/events", web::get().to(events))
+        .route("/", web::get().to(index));
+}
+
 /// Runs an http server on the specified binding address, serving out the supplied server metrics
 pub fn run_http_server(
     runtime: &Runtime,
@@ -79,10 +127,49 @@ pub fn run_http_server(
     server_state: Arc<RwLock<ServerState>>,
     connections: Arc<RwLock<Connections>>,
     server_metrics: Arc<RwLock<ServerMetrics>>,
+) {
+    run_http_server_inner(
+        runtime,
+        address,
+        content_path,
+        server_state,
+        connections,
+        server_metrics,
+        None,
+    )
+}
+
+/// Runs an http server on the specified binding address, serving out the metrics of the server
+/// and the admin API beneath `/admin`. The admin API is only usable when `http_admin` is set in
+/// the server configuration.
+pub fn run_http_server_with_admin(
+    runtime: &Runtime,
+    address: &str,
+    content_path: &str,
+    server: &Server,
+) {
+    run_http_server_inner(
+        runtime,
+        address,
+        content_path,
+        server.server_state(),
+        server.connections(),
+        server.server_metrics(),
+        Some(AdminState::new(server)),
+    )
+}
+
+fn run_http_server_inner(
+    runtime: &Runtime,
+    address: &str,
+    content_path: &str,
+    server_state: Arc<RwLock<ServerState>>,
+    connections: Arc<RwLock<Connections>>,
+    server_metrics: Arc<RwLock<ServerMetrics>>,
+    admin_state: Option<AdminState>,
 ) {
     let address = String::from(address);
-    let base_path = PathBuf::from(content_path);
-    let server_state_http = server_state.clone();
+    let app_state = AppState::new(server_state, connections, server_metrics, content_path);
 
     // Getting this working was very painful since Actix HttpServer does not implement Send trait, so the
     // code has to run on a single thread, but also async and through Tokio.
@@ -98,16 +185,15 @@ pub fn run_http_server(
         local.spawn_local(async move {
             // Spawns a new HTTP server
             if let Ok(server) = HttpServer::new(move || {
-                App::new()
-                    .app_data(web::Data::new(AppState {
-                        server_state: server_state_http.clone(),
-                        connections: connections.clone(),
-                        server_metrics: server_metrics.clone(),
-                        base_path: Arc::new(RwLock::new(base_path.clone())),
-                    }))
-                    .route("/server/metrics", web::get().to(metrics))
-                    .route("/server/abort", web::get().to(abort))
-                    .route("/", web::get().to(index))
+                let app = App::new()
+                    .app_data(web::Data::new(app_state.clone()))
+                    .configure(configure);
+                if let Some(ref admin_state) = admin_state {
+                    app.app_data(web::Data::new(admin_state.clone()))
+                        .configure(admin::configure)
+                } else {
+                    app
+                }
             })
             .bind(&address)
             {
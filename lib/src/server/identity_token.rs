@@ -9,12 +9,14 @@ pub(crate) const POLICY_ID_USER_PASS_NONE: &str = "userpass_none";
 pub(crate) const POLICY_ID_USER_PASS_RSA_15: &str = "userpass_rsa_15";
 pub(crate) const POLICY_ID_USER_PASS_RSA_OAEP: &str = "userpass_rsa_oaep";
 pub(crate) const POLICY_ID_X509: &str = "x509";
+pub(crate) const POLICY_ID_ISSUED_JWT: &str = "issued_jwt";
 
 pub enum IdentityToken {
     None,
     AnonymousIdentityToken(AnonymousIdentityToken),
     UserNameIdentityToken(UserNameIdentityToken),
     X509IdentityToken(X509IdentityToken),
+    IssuedIdentityToken(IssuedIdentityToken),
     Invalid(ExtensionObject),
 }
 
@@ -50,6 +52,13 @@ impl IdentityToken {
                         IdentityToken::Invalid(o.clone())
                     }
                 }
+                ObjectId::IssuedIdentityToken_Encoding_DefaultBinary => {
+                    if let Ok(token) = o.decode_inner::<IssuedIdentityToken>(decoding_options) {
+                        IdentityToken::IssuedIdentityToken(token)
+                    } else {
+                        IdentityToken::Invalid(o.clone())
+                    }
+                }
                 _ => IdentityToken::Invalid(o.clone()),
             }
         } else {
@@ -5,6 +5,11 @@
 //! Provides debug metric of server state that can be used by anything that wants
 //! to see what is happening in the server. State is updated by the server as sessions are added, removed,
 //! and when subscriptions / monitored items are added, removed.
+//!
+//! The counters of the server diagnostics can also be rendered in the OpenMetrics text format
+//! for scraping by Prometheus with `open_metrics()`.
+
+use std::fmt::Write;
 
 use crate::runtime_components;
 use crate::types::DateTime;
@@ -12,7 +17,7 @@ use crate::types::DateTime;
 use crate::server::{
     comms::transport::{Transport, TransportState},
     config,
-    diagnostics::ServerDiagnostics,
+    diagnostics::{ServerDiagnostics, SERVICE_LATENCY_BUCKETS},
     server,
     state::ServerState,
     subscriptions::subscriptions,
@@ -82,14 +87,18 @@ impl ServerMetrics {
             let config = trace_read_lock!(config);
             config.clone()
         };
-        // For security, blank out user tokens
+        // For security, blank out the private key passphrase, user tokens and admin tokens
+        config.private_key_passphrase = None;
+        config.http_admin.tokens.clear();
         config.user_tokens.clear();
         config.user_tokens.insert(
             String::new(),
             config::ServerUserToken {
                 user: String::from("User identity tokens have been removed"),
                 pass: None,
+                pass_hash: None,
                 x509: None,
+                jwt: None,
                 thumbprint: None,
             },
         );
@@ -179,3 +188,205 @@ impl ServerMetrics {
             .collect();
     }
 }
+
+/// The content type of the OpenMetrics text format
+pub const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Writes the metadata and the single sample of a metric family
+fn write_metric(out: &mut String, name: &str, metric_type: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    if metric_type == "counter" {
+        let _ = writeln!(out, "{}_total {}", name, value);
+    } else {
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+}
+
+/// Renders the counters of the server diagnostics in the OpenMetrics text format. The counters
+/// are updated by the server as things happen, so a scrape always sees the current values.
+pub fn open_metrics(server_state: &ServerState) -> String {
+    let diagnostics = trace_read_lock!(server_state.diagnostics);
+    let summary = diagnostics.server_diagnostics_summary();
+    let uptime = DateTime::now()
+        .as_chrono()
+        .signed_duration_since(server_state.start_time.as_chrono());
+
+    let mut out = String::with_capacity(4096);
+    write_metric(
+        &mut out,
+        "opcua_server_uptime_seconds",
+        "gauge",
+        "Time since the server started.",
+        uptime.num_milliseconds() as f64 / 1000.0,
+    );
+    write_metric(
+        &mut out,
+        "opcua_connections",
+        "gauge",
+        "Connections currently open.",
+        diagnostics.current_connection_count() as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_connections_opened",
+        "counter",
+        "Connections opened since the server started.",
+        diagnostics.cumulated_connection_count() as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_sessions",
+        "gauge",
+        "Sessions currently established.",
+        summary.current_session_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_sessions_created",
+        "counter",
+        "Sessions created since the server started.",
+        summary.cumulated_session_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_sessions_rejected",
+        "counter",
+        "Session requests that were rejected.",
+        summary.rejected_session_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_sessions_security_rejected",
+        "counter",
+        "Session requests that were rejected for security reasons.",
+        summary.security_rejected_session_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_session_timeouts",
+        "counter",
+        "Sessions closed because they timed out.",
+        summary.session_timeout_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_subscriptions",
+        "gauge",
+        "Subscriptions currently established.",
+        summary.current_subscription_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_subscriptions_created",
+        "counter",
+        "Subscriptions created since the server started.",
+        summary.cumulated_subscription_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_monitored_items",
+        "gauge",
+        "Monitored items currently in subscriptions.",
+        diagnostics.current_monitored_item_count() as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_publish_requests_queued",
+        "gauge",
+        "Publish requests queued by sessions waiting for notifications.",
+        diagnostics.current_publish_request_count() as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_requests_rejected",
+        "counter",
+        "Service requests that were rejected.",
+        summary.rejected_requests_count as f64,
+    );
+    write_metric(
+        &mut out,
+        "opcua_requests_security_rejected",
+        "counter",
+        "Service requests rejected because their session is invalid, not activated or timed out.",
+        summary.security_rejected_requests_count as f64,
+    );
+
+    let _ = writeln!(out, "# TYPE opcua_certificates_rejected counter");
+    let _ = writeln!(
+        out,
+        "# HELP opcua_certificates_rejected Client certificates that were rejected, by reason."
+    );
+    for (reason, count) in diagnostics.certificate_rejections() {
+        let _ = writeln!(
+            out,
+            "opcua_certificates_rejected_total{{reason=\"{}\"}} {}",
+            reason, count
+        );
+    }
+
+    let services = diagnostics.services();
+    let _ = writeln!(out, "# TYPE opcua_service_requests counter");
+    let _ = writeln!(
+        out,
+        "# HELP opcua_service_requests Service requests received, by service."
+    );
+    for (service, metrics) in services {
+        let _ = writeln!(
+            out,
+            "opcua_service_requests_total{{service=\"{}\"}} {}",
+            service, metrics.request_count
+        );
+    }
+    let _ = writeln!(out, "# TYPE opcua_service_errors counter");
+    let _ = writeln!(
+        out,
+        "# HELP opcua_service_errors Service requests that ended in a service fault, by service."
+    );
+    for (service, metrics) in services {
+        let _ = writeln!(
+            out,
+            "opcua_service_errors_total{{service=\"{}\"}} {}",
+            service, metrics.error_count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# TYPE opcua_service_request_duration_seconds histogram"
+    );
+    let _ = writeln!(out, "# UNIT opcua_service_request_duration_seconds seconds");
+    let _ = writeln!(
+        out,
+        "# HELP opcua_service_request_duration_seconds Time taken to respond to service requests, by service."
+    );
+    for (service, metrics) in services {
+        for (le, count) in SERVICE_LATENCY_BUCKETS
+            .iter()
+            .zip(metrics.latency_buckets.iter())
+        {
+            let _ = writeln!(
+                out,
+                "opcua_service_request_duration_seconds_bucket{{service=\"{}\",le=\"{}\"}} {}",
+                service, le, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "opcua_service_request_duration_seconds_bucket{{service=\"{}\",le=\"+Inf\"}} {}",
+            service, metrics.latency_count
+        );
+        let _ = writeln!(
+            out,
+            "opcua_service_request_duration_seconds_count{{service=\"{}\"}} {}",
+            service, metrics.latency_count
+        );
+        let _ = writeln!(
+            out,
+            "opcua_service_request_duration_seconds_sum{{service=\"{}\"}} {}",
+            service, metrics.latency_sum
+        );
+    }
+    out.push_str("# EOF\n");
+    out
+}
@@ -58,6 +58,7 @@ pub mod diagnostics;
 pub mod events;
 pub mod historical;
 pub mod metrics;
+pub mod redundancy;
 pub mod server;
 pub mod session;
 pub mod state;
@@ -77,6 +78,7 @@ pub mod prelude {
         config::*,
         events::event::*,
         historical::*,
+        redundancy::*,
         server::*,
         subscriptions::*,
         util::*,
@@ -96,6 +98,10 @@ pub mod constants {
     pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 100;
     /// Default maximum number of monitored items per subscription
     pub const DEFAULT_MAX_MONITORED_ITEMS_PER_SUB: usize = 1000;
+    /// Default maximum lifetime in hours of a durable subscription
+    pub const DEFAULT_MAX_DURABLE_SUBSCRIPTION_LIFETIME_HOURS: u32 = 24;
+    /// Default maximum number of values in a monitored item queue of a durable subscription
+    pub const DEFAULT_MAX_DURABLE_MONITORED_ITEM_QUEUE_SIZE: usize = 10000;
     /// Default, well known address for TCP discovery server
     pub const DEFAULT_DISCOVERY_SERVER_URL: &str = "opc.tcp://localhost:4840/UADiscovery";
 
@@ -116,6 +122,15 @@ pub mod constants {
     /// Interval to check for HELLO timeout in millis. This can be fairly coarse because it's not
     /// something that requires huge accuracy.
     pub const HELLO_TIMEOUT_POLL_MS: u64 = 500;
+    /// Interval in millis between saves of the durable subscriptions that have no session
+    pub const DURABLE_SUBSCRIPTIONS_SAVE_INTERVAL_MS: u64 = 10 * 1000;
+    /// Interval in millis between pushes of the metrics to live views of the http server
+    pub const LIVE_METRICS_INTERVAL_MS: u64 = 1000;
+    /// Time in millis that connections have to close after the delay of a graceful shutdown
+    /// expires, before the server aborts regardless
+    pub const SHUTDOWN_CLOSE_TIMEOUT_MS: u64 = 5000;
+    /// Interval in millis between checks that the server's own certificate needs renewing
+    pub const CERTIFICATE_RENEWAL_CHECK_INTERVAL_MS: u64 = 60 * 60 * 1000;
     /// Maximum time in MS that a session can be inactive before a timeout
     pub const MAX_SESSION_TIMEOUT: f64 = 60000f64;
     /// Maximum size in bytes that a request message is allowed to be
@@ -0,0 +1,254 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Non-transparent server redundancy. The servers of a redundant server set expose the failover
+//! mode they support, the uris of the set and the service level of each server through the
+//! `Server.ServerRedundancy` object, and clients fail over between them by themselves.
+//!
+//! The service level of a server is driven from the health of the application, either pushed with
+//! `Server::set_health` or polled from a health check. The servers exchange their service levels,
+//! and optionally their subscriptions, with [`RedundancyMessage`]s over a [`RedundancyChannel`]
+//! that the application provides. A client that fails over to a server that received the
+//! subscriptions of the previous server can transfer them with TransferSubscriptions.
+
+use std::{collections::BTreeMap, sync::Arc, time::Instant};
+
+use crate::sync::*;
+use crate::types::{
+    service_types::{RedundantServerDataType, ServerState as ServerStateType},
+    UAString,
+};
+
+use crate::server::{
+    config::ServerRedundancy,
+    session::Session,
+    subscriptions::durable::{DurableSubscriptions, SubscriptionRecord},
+};
+
+/// The health of the application, which sets the service level of the server
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ServerHealth {
+    /// The server is in maintenance and clients must not use it, service level 0
+    Maintenance,
+    /// The server has no data to serve, e.g. it lost its connection to the underlying system,
+    /// service level 1
+    NoData,
+    /// The server serves data of degraded quality, service level 2 to 199 where higher is better
+    Degraded(u8),
+    /// The server is fully operational, service level 200 to 255 where higher is better
+    Healthy(u8),
+}
+
+impl ServerHealth {
+    /// The service level of the health, within the range of the health
+    pub fn service_level(&self) -> u8 {
+        match *self {
+            ServerHealth::Maintenance => 0,
+            ServerHealth::NoData => 1,
+            ServerHealth::Degraded(service_level) => service_level.clamp(2, 199),
+            ServerHealth::Healthy(service_level) => service_level.max(200),
+        }
+    }
+}
+
+/// A function that the server polls for the health of the application at the update interval
+/// of its redundancy settings.
+pub type HealthCheck = Arc<dyn Fn() -> ServerHealth + Send + Sync>;
+
+/// A subscription of a server, replicated to the other servers of the redundant server set. Its
+/// content is opaque to the application, which only carries it between the servers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicatedSubscription(SubscriptionRecord);
+
+impl ReplicatedSubscription {
+    pub fn subscription_id(&self) -> u32 {
+        self.0.subscription_id
+    }
+}
+
+/// A message that a server sends to the other servers of the redundant server set. Messages can
+/// be serialized so the application can send them over any transport.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RedundancyMessage {
+    /// The current service level of the server
+    ServiceLevel {
+        server_uri: String,
+        service_level: u8,
+    },
+    /// All the subscriptions of the server, which replace those it sent before
+    Subscriptions {
+        server_uri: String,
+        subscriptions: Vec<ReplicatedSubscription>,
+    },
+}
+
+/// A channel to the other servers of the redundant server set. The application delivers the
+/// messages to the other servers, which receive them with `Server::receive_redundancy_message`.
+///
+/// `send` is called from a server task so it must not block. Messages that cannot be delivered
+/// may be dropped since the servers send their state again at the next update.
+pub trait RedundancyChannel: Send + Sync {
+    fn send(&self, message: RedundancyMessage);
+}
+
+/// The state of the server within its redundant server set
+pub(crate) struct Redundancy {
+    /// Application uri of this server
+    server_uri: String,
+    /// Application uris of the servers of the set, including this one
+    server_uris: Vec<String>,
+    /// Time after which another server that has not sent its service level is considered gone
+    peer_timeout: std::time::Duration,
+    /// Replicate the subscriptions to the other servers
+    replicate_subscriptions: bool,
+    /// Service level of this server
+    service_level: u8,
+    /// Last service level received from each of the other servers, and when
+    peers: BTreeMap<String, (u8, Instant)>,
+    /// Health check that sets the service level
+    pub health_check: Option<HealthCheck>,
+    /// Channel to the other servers
+    pub channel: Option<Arc<dyn RedundancyChannel>>,
+}
+
+impl Redundancy {
+    pub fn new(redundancy: &ServerRedundancy, application_uri: &str) -> Self {
+        Self {
+            server_uri: application_uri.to_string(),
+            server_uris: redundancy.server_uris.clone(),
+            // Allow a couple of updates to go missing
+            peer_timeout: std::time::Duration::from_millis(redundancy.update_interval_ms * 3),
+            replicate_subscriptions: redundancy.replicate_subscriptions,
+            service_level: 255,
+            peers: BTreeMap::new(),
+            health_check: None,
+            channel: None,
+        }
+    }
+
+    pub fn server_uri(&self) -> &str {
+        &self.server_uri
+    }
+
+    pub fn service_level(&self) -> u8 {
+        self.service_level
+    }
+
+    pub fn set_service_level(&mut self, service_level: u8) {
+        self.service_level = service_level;
+    }
+
+    pub fn replicate_subscriptions(&self) -> bool {
+        self.replicate_subscriptions && self.channel.is_some()
+    }
+
+    /// Sets the service level from the health check, if there is one
+    pub fn check_health(&mut self) {
+        if let Some(ref health_check) = self.health_check {
+            self.service_level = health_check().service_level();
+        }
+    }
+
+    /// Tests if the uri is that of another server of the set
+    pub fn is_peer(&self, server_uri: &str) -> bool {
+        server_uri != self.server_uri && self.server_uris.iter().any(|uri| uri == server_uri)
+    }
+
+    /// Keeps the service level received from another server. Returns `false` if the server is
+    /// not part of the set.
+    pub fn receive_service_level(&mut self, server_uri: &str, service_level: u8) -> bool {
+        if !self.is_peer(server_uri) {
+            return false;
+        }
+        self.peers
+            .insert(server_uri.to_string(), (service_level, Instant::now()));
+        true
+    }
+
+    /// The servers of the set with their service levels. Other servers that have not sent their
+    /// service level recently have an unknown state.
+    pub fn redundant_servers(&self) -> Vec<RedundantServerDataType> {
+        let now = Instant::now();
+        self.server_uris
+            .iter()
+            .map(|server_uri| {
+                let (service_level, server_state) = if *server_uri == self.server_uri {
+                    (self.service_level, ServerStateType::Running)
+                } else {
+                    match self.peers.get(server_uri) {
+                        Some((service_level, received))
+                            if now.duration_since(*received) < self.peer_timeout =>
+                        {
+                            (*service_level, ServerStateType::Running)
+                        }
+                        _ => (0, ServerStateType::Unknown),
+                    }
+                };
+                RedundantServerDataType {
+                    server_id: UAString::from(server_uri),
+                    service_level,
+                    server_state,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The id after which a server of a redundant server set numbers its subscriptions. Each server of
+/// the set numbers them from its own range, so replicated subscriptions keep their ids.
+pub(crate) fn last_subscription_id(redundancy: &ServerRedundancy, application_uri: &str) -> u32 {
+    if !redundancy.is_enabled() {
+        return 0;
+    }
+    redundancy
+        .server_uris
+        .iter()
+        .position(|uri| uri == application_uri)
+        .map(|idx| {
+            ((idx as u64 * (u32::MAX as u64 + 1)) / redundancy.server_uris.len() as u64) as u32
+        })
+        .unwrap_or_default()
+}
+
+/// Makes the subscriptions of the sessions and the durable subscriptions without a session into
+/// replicated subscriptions. A subscription of a session belongs to the user of the session, so
+/// only that user can transfer it on another server.
+pub(crate) fn replicated_subscriptions(
+    sessions: &[Arc<RwLock<Session>>],
+    durable_subscriptions: &Mutex<DurableSubscriptions>,
+) -> Vec<ReplicatedSubscription> {
+    let mut replicated_subscriptions = Vec::new();
+    for session in sessions {
+        let session = trace_read_lock!(session);
+        let owner = session.client_user_id();
+        replicated_subscriptions.extend(session.subscriptions().subscriptions().values().map(
+            |subscription| {
+                let mut record = subscription.to_durable_record();
+                record.owner = owner.clone();
+                ReplicatedSubscription(record)
+            },
+        ));
+    }
+    let records = {
+        let mut durable_subscriptions = trace_lock!(durable_subscriptions);
+        durable_subscriptions.receive_detached();
+        durable_subscriptions.to_records()
+    };
+    replicated_subscriptions.extend(records.into_iter().map(ReplicatedSubscription));
+    replicated_subscriptions
+}
+
+/// Keeps the subscriptions replicated from another server of the set, so clients can transfer
+/// them after failing over.
+pub(crate) fn receive_subscriptions(
+    durable_subscriptions: &Mutex<DurableSubscriptions>,
+    server_uri: &str,
+    subscriptions: Vec<ReplicatedSubscription>,
+) {
+    let records = subscriptions
+        .into_iter()
+        .map(|subscription| subscription.0)
+        .collect();
+    trace_lock!(durable_subscriptions).set_replicas(server_uri, records);
+}
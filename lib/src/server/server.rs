@@ -4,13 +4,12 @@
 
 //! Provides the [`Server`] type and functionality related to it.
 
-use std::{marker::Sync, net::SocketAddr, sync::Arc};
+use std::{collections::BTreeSet, marker::Sync, sync::Arc};
 
 use tokio::{
     self,
-    net::{TcpListener, TcpStream, ToSocketAddrs},
     sync::oneshot::{self, Sender},
-    time::{interval_at, Duration, Instant},
+    time::{interval_at, sleep_until, Duration, Instant},
 };
 
 use crate::core::{config::Config, prelude::*};
@@ -20,15 +19,22 @@ use crate::types::service_types::ServerState as ServerStateType;
 
 use crate::server::{
     address_space::types::AddressSpace,
+    comms::socket::{ConnectionSocket, ListenAddress, SocketListener},
     comms::tcp_transport::*,
     comms::transport::Transport,
-    config::ServerConfig,
+    config::{ServerConfig, ServerListener},
     constants,
     diagnostics::ServerDiagnostics,
     events::audit::AuditLog,
     metrics::ServerMetrics,
+    redundancy::{self, Redundancy, RedundancyChannel, RedundancyMessage, ServerHealth},
+    services::audit,
     session::SessionManager,
     state::{OperationalLimits, ServerState},
+    subscriptions::{
+        durable::{self, DurableSubscriptions, SubscriptionRecord},
+        sampling::SamplingSchedules,
+    },
     util::PollingAction,
 };
 
@@ -77,6 +83,8 @@ pub struct Server {
     connections: Arc<RwLock<Connections>>,
     /// Session manager
     session_manager: Arc<RwLock<SessionManager>>,
+    /// State of the server within its redundant server set
+    redundancy: Arc<Mutex<Redundancy>>,
 }
 
 impl From<ServerConfig> for Server {
@@ -101,13 +109,19 @@ impl Server {
         let product_uri = UAString::from(&config.product_uri);
         let start_time = DateTime::now();
         let servers = vec![config.application_uri.clone()];
-        let base_endpoint = format!(
-            "opc.tcp://{}:{}",
-            config.tcp_config.host, config.tcp_config.port
-        );
+        let base_endpoint = config.base_endpoint_url();
         let max_subscriptions = config.limits.max_subscriptions;
         let max_monitored_items_per_sub = config.limits.max_monitored_items_per_sub;
         let max_monitored_item_queue_size = config.limits.max_monitored_item_queue_size;
+        let max_durable_monitored_item_queue_size =
+            config.limits.max_durable_monitored_item_queue_size;
+        let last_subscription_id =
+            redundancy::last_subscription_id(&config.redundancy, &config.application_uri);
+        let redundancy = Redundancy::new(&config.redundancy, &config.application_uri);
+        let durable_subscriptions = DurableSubscriptions::new(
+            config.durable_subscriptions_path.clone(),
+            config.limits.max_durable_subscription_lifetime_hours,
+        );
 
         let diagnostics = Arc::new(RwLock::new(ServerDiagnostics::default()));
         let min_publishing_interval_ms = config.limits.min_publishing_interval * 1000.0;
@@ -127,6 +141,7 @@ impl Server {
                 false,
                 config.certificate_path.as_deref(),
                 config.private_key_path.as_deref(),
+                config.private_key_passphrase.clone(),
                 application_description,
             );
         if server_certificate.is_none() || server_pkey.is_none() {
@@ -135,6 +150,7 @@ impl Server {
 
         // Load thumbprints of every user token
         config.read_x509_thumbprints();
+        let jwt_verification_keys = config.jwt_verification_keys();
 
         // Servers may choose to auto trust clients to save some messing around with rejected certs.
         // This is strongly not advised in production.
@@ -143,6 +159,7 @@ impl Server {
             certificate_store.set_trust_unknown_certs(true);
         }
         certificate_store.set_check_time(config.certificate_validation.check_time);
+        certificate_store.set_security_profile(config.security_profile.clone());
 
         let config = Arc::new(RwLock::new(config));
 
@@ -165,17 +182,23 @@ impl Server {
             config,
             server_certificate,
             server_pkey,
-            last_subscription_id: 0,
+            jwt_verification_keys,
+            last_subscription_id,
             max_subscriptions,
             max_monitored_items_per_sub,
             max_monitored_item_queue_size,
+            max_durable_monitored_item_queue_size,
             min_publishing_interval_ms,
             min_sampling_interval_ms,
+            sampling_schedules: Arc::new(Mutex::new(SamplingSchedules::default())),
+            durable_subscriptions: Arc::new(Mutex::new(durable_subscriptions)),
+            open_secure_channels: BTreeSet::new(),
             default_keep_alive_count: constants::DEFAULT_KEEP_ALIVE_COUNT,
             max_keep_alive_count: constants::MAX_KEEP_ALIVE_COUNT,
             max_lifetime_count: constants::MAX_KEEP_ALIVE_COUNT * 3,
             diagnostics,
             abort: false,
+            shutdown: None,
             audit_log,
             register_nodes_callback: None,
             unregister_nodes_callback: None,
@@ -206,6 +229,7 @@ impl Server {
             certificate_store,
             connections: Arc::new(RwLock::new(Vec::new())),
             session_manager: Arc::new(RwLock::new(SessionManager::default())),
+            redundancy: Arc::new(Mutex::new(redundancy)),
         };
 
         let mut server_metrics = trace_write_lock!(server_metrics);
@@ -267,14 +291,14 @@ impl Server {
 
     /// Returns the main server task - the loop that waits for connections and processes them.
     pub async fn new_server_task(server: Arc<RwLock<Server>>) {
-        // Get the address and discovery url
-        let (sock_addr, discovery_server_url) = {
+        // Get the addresses and discovery url
+        let (sock_addrs, discovery_server_url) = {
             let server = trace_read_lock!(server);
 
             // Debug endpoints
             server.log_endpoint_info();
 
-            let sock_addr = server.get_socket_address();
+            let sock_addrs = server.get_socket_addresses();
             let server_state = trace_read_lock!(server.server_state);
             let config = trace_read_lock!(server_state.config);
 
@@ -290,30 +314,40 @@ impl Server {
                     None
                 };
 
-            (sock_addr, discovery_server_url)
+            (sock_addrs, discovery_server_url)
         };
-        match sock_addr {
+        match sock_addrs {
             None => {
                 error!("Cannot resolve server address, check configuration of server");
             }
-            Some(sock_addr) => Self::server_task(server, sock_addr, discovery_server_url).await,
+            Some(sock_addrs) => Self::server_task(server, sock_addrs, discovery_server_url).await,
         }
     }
 
-    async fn server_task<A: ToSocketAddrs>(
+    async fn server_task(
         server: Arc<RwLock<Server>>,
-        sock_addr: A,
+        sock_addrs: Vec<(ServerListener, ListenAddress)>,
         discovery_server_url: Option<String>,
     ) {
         // This is returned as the main server task
         info!("Waiting for Connection");
-        // Listen for connections (or abort)
-        let listener = match TcpListener::bind(&sock_addr).await {
-            Ok(listener) => listener,
-            Err(err) => {
-                panic!("Could not bind to socket {:?}", err)
+        // Listen for connections (or abort) on every listener
+        let mut listeners = Vec::with_capacity(sock_addrs.len());
+        for (listener, sock_addr) in sock_addrs {
+            match sock_addr.bind().await {
+                Ok(socket_listener) => {
+                    info!(
+                        "Listening on {} for {}",
+                        sock_addr,
+                        listener.base_endpoint_url()
+                    );
+                    listeners.push((listener, socket_listener));
+                }
+                Err(err) => {
+                    panic!("Could not bind to socket {}, {:?}", sock_addr, err)
+                }
             }
-        };
+        }
 
         let (tx_abort, rx_abort) = oneshot::channel();
 
@@ -336,42 +370,32 @@ impl Server {
 
             // Start any pending polling action timers
             server.start_pending_polling_actions();
+
+            // Start a timer that renews the server's own certificate before it expires
+            server.start_certificate_renewal_timer();
+
+            // Start the task that samples monitored items
+            server.start_sampling_timer();
+
+            // Restore durable subscriptions and start the task that keeps them
+            server.start_durable_subscriptions_timer();
+
+            // Start the task that updates the service level and exchanges it with the other
+            // servers of the redundant server set
+            server.start_redundancy_timer();
         }
 
         // Start a server abort task loop
         Self::start_abort_poll(server.clone(), tx_abort);
 
         // This isn't nice syntax, but basically there are two async actions
-        // going on, one of which has to complete - either the listener breaks out of its
-        // loop, or the rx_abort receives an abort message.
+        // going on, one of which has to complete - either the listeners break out of their
+        // loops, or the rx_abort receives an abort message.
+        let accept_loops = listeners.into_iter().map(|(listener, socket_listener)| {
+            Self::accept_connections(server.clone(), listener, socket_listener)
+        });
         tokio::select! {
-            _ = async {
-                loop {
-                    match listener.accept().await {
-                        Ok((socket, _addr)) => {
-                            // Clear out dead sessions
-                            info!("Handling new connection {:?}", socket);
-                            // Check for abort
-                            let mut server = trace_write_lock!(server);
-                            let is_abort = {
-                                let server_state = trace_read_lock!(server.server_state);
-                                server_state.is_abort()
-                            };
-                            if is_abort {
-                                info!("Server is aborting so it will not accept new connections");
-                                break;
-                            } else {
-                                server.handle_connection(socket);
-                            }
-                        }
-                        Err(e) => {
-                            error!("couldn't accept connection to client: {:?}", e);
-                        }
-                    }
-                }
-                // Help the rust type inferencer out
-                Ok::<_, tokio::io::Error>(())
-            } => {}
+            _ = futures::future::join_all(accept_loops) => {}
             _ = rx_abort => {
                 info!("abort received");
             }
@@ -379,6 +403,37 @@ impl Server {
         info!("main server task is finished");
     }
 
+    /// Accepts connections on the listener until the server aborts
+    async fn accept_connections(
+        server: Arc<RwLock<Server>>,
+        listener: ServerListener,
+        socket_listener: SocketListener,
+    ) {
+        loop {
+            match socket_listener.accept().await {
+                Ok(socket) => {
+                    // Clear out dead sessions
+                    info!("Handling new connection {:?}", socket);
+                    // Check for abort
+                    let mut server = trace_write_lock!(server);
+                    let is_abort = {
+                        let server_state = trace_read_lock!(server.server_state);
+                        server_state.is_abort()
+                    };
+                    if is_abort {
+                        info!("Server is aborting so it will not accept new connections");
+                        break;
+                    } else {
+                        server.handle_connection(listener.clone(), socket);
+                    }
+                }
+                Err(e) => {
+                    error!("couldn't accept connection to client: {:?}", e);
+                }
+            }
+        }
+    }
+
     /// Returns the current [`ServerState`] for the server.
     ///
     /// [`ServerState`]: ../state/struct.ServerState.html
@@ -405,6 +460,13 @@ impl Server {
         self.connections.clone()
     }
 
+    /// Returns the [`SessionManager`] that holds the sessions of the server.
+    ///
+    /// [`SessionManager`]: ../session/struct.SessionManager.html
+    pub fn session_manager(&self) -> Arc<RwLock<SessionManager>> {
+        self.session_manager.clone()
+    }
+
     /// Returns the [`ServerMetrics`] for the server.
     ///
     /// [`ServerMetrics`]: ../metrics/struct.ServerMetrics.html
@@ -427,6 +489,111 @@ impl Server {
         server_state.abort();
     }
 
+    /// Shuts the server down gracefully. The server enters the `Shutdown` state and counts down
+    /// `ServerStatus.SecondsTillShutdown` with the `ShutdownReason`, so clients can fail over or
+    /// reconnect. No new sessions are accepted during the delay. When the delay expires, pending
+    /// publish requests are answered, the secure channels are closed and the server aborts.
+    pub fn shutdown<T>(&mut self, delay: Duration, reason: T)
+    where
+        T: Into<LocalizedText>,
+    {
+        info!("Server has been instructed to shut down");
+        let (seconds_till_shutdown, shutdown_reason) = {
+            let mut server_state = trace_write_lock!(self.server_state);
+            server_state.begin_shutdown(delay, reason.into());
+            (
+                server_state.seconds_till_shutdown(),
+                server_state.shutdown_reason(),
+            )
+        };
+        let mut address_space = trace_write_lock!(self.address_space);
+        address_space.set_server_status(
+            ServerStateType::Shutdown,
+            seconds_till_shutdown,
+            shutdown_reason,
+        );
+    }
+
+    /// Sets the health of the application, which sets the service level of the server. Clients
+    /// of a redundant server set fail over to the server with the best service level. The health
+    /// is replaced by that of the health check at its next poll, if there is one.
+    pub fn set_health(&self, health: ServerHealth) {
+        let redundant_servers = {
+            let mut redundancy = trace_lock!(self.redundancy);
+            redundancy.set_service_level(health.service_level());
+            redundancy.redundant_servers()
+        };
+        let mut address_space = trace_write_lock!(self.address_space);
+        address_space.set_service_level(health.service_level(), &DateTime::now());
+        if !redundant_servers.is_empty() {
+            address_space.set_redundant_servers(&redundant_servers);
+        }
+    }
+
+    /// Sets a function that the server polls for the health of the application, at the update
+    /// interval of the redundancy settings, to set its service level.
+    pub fn set_health_check<F>(&mut self, health_check: F)
+    where
+        F: Fn() -> ServerHealth + Send + Sync + 'static,
+    {
+        trace_lock!(self.redundancy).health_check = Some(Arc::new(health_check));
+    }
+
+    /// Sets the channel through which the server sends its service level, and its subscriptions
+    /// if they are replicated, to the other servers of the redundant server set.
+    pub fn set_redundancy_channel<T>(&mut self, channel: T)
+    where
+        T: RedundancyChannel + 'static,
+    {
+        trace_lock!(self.redundancy).channel = Some(Arc::new(channel));
+    }
+
+    /// Receives a message sent by another server of the redundant server set through its
+    /// redundancy channel. Messages from servers outside of the set are ignored.
+    pub fn receive_redundancy_message(&self, message: RedundancyMessage) {
+        match message {
+            RedundancyMessage::ServiceLevel {
+                server_uri,
+                service_level,
+            } => {
+                let redundant_servers = {
+                    let mut redundancy = trace_lock!(self.redundancy);
+                    if !redundancy.receive_service_level(&server_uri, service_level) {
+                        warn!(
+                            "Ignoring the service level of server {}, which is not in the redundant server set",
+                            server_uri
+                        );
+                        return;
+                    }
+                    redundancy.redundant_servers()
+                };
+                let mut address_space = trace_write_lock!(self.address_space);
+                address_space.set_redundant_servers(&redundant_servers);
+            }
+            RedundancyMessage::Subscriptions {
+                server_uri,
+                subscriptions,
+            } => {
+                if !trace_lock!(self.redundancy).is_peer(&server_uri) {
+                    warn!(
+                        "Ignoring the subscriptions of server {}, which is not in the redundant server set",
+                        server_uri
+                    );
+                    return;
+                }
+                let durable_subscriptions = {
+                    let server_state = trace_read_lock!(self.server_state);
+                    server_state.durable_subscriptions.clone()
+                };
+                redundancy::receive_subscriptions(
+                    &durable_subscriptions,
+                    &server_uri,
+                    subscriptions,
+                );
+            }
+        }
+    }
+
     /// Strip out dead connections, i.e those which have disconnected. Returns `true` if there are
     /// still open connections after this function completes.
     fn remove_dead_connections(&self) -> bool {
@@ -453,8 +620,19 @@ impl Server {
         let config = trace_read_lock!(server_state.config);
         info!("OPC UA Server: {}", server_state.application_name);
         info!("Base url: {}", server_state.base_endpoint);
+        for listener in config.listeners() {
+            let endpoint_ids: Vec<&String> = config
+                .listener_endpoints(&listener)
+                .map(|(id, _)| id)
+                .collect();
+            info!(
+                "Listener {} offers endpoints {:?}",
+                listener.base_endpoint_url(),
+                endpoint_ids
+            );
+        }
         info!("Supported endpoints:");
-        for (id, endpoint) in &config.endpoints {
+        for (id, endpoint) in config.allowed_endpoints() {
             let users: Vec<String> = endpoint.user_token_ids.iter().cloned().collect();
             let users = users.join(", ");
             info!("Endpoint \"{}\": {}", id, endpoint.path);
@@ -464,18 +642,37 @@ impl Server {
         }
     }
 
-    /// Returns the server socket address.
-    fn get_socket_address(&self) -> Option<SocketAddr> {
+    /// Returns the socket address of each listener of the server, or `None` if any of them
+    /// cannot be resolved.
+    fn get_socket_addresses(&self) -> Option<Vec<(ServerListener, ListenAddress)>> {
         use std::net::ToSocketAddrs;
         let server_state = trace_read_lock!(self.server_state);
         let config = trace_read_lock!(server_state.config);
-        // Resolve this host / port to an address (or not)
-        let address = format!("{}:{}", config.tcp_config.host, config.tcp_config.port);
-        if let Ok(mut addrs_iter) = address.to_socket_addrs() {
-            addrs_iter.next()
-        } else {
-            None
-        }
+        // Resolve each host / port to an address (or not)
+        config
+            .listeners()
+            .into_iter()
+            .map(|listener| {
+                let address = match listener.socket_path {
+                    #[cfg(unix)]
+                    Some(ref socket_path) => Some(ListenAddress::Unix(socket_path.clone())),
+                    #[cfg(not(unix))]
+                    Some(_) => None,
+                    None => (listener.host.as_str(), listener.port)
+                        .to_socket_addrs()
+                        .ok()
+                        .and_then(|mut addrs_iter| addrs_iter.next())
+                        .map(ListenAddress::Tcp),
+                };
+                if address.is_none() {
+                    error!(
+                        "Cannot resolve the address of listener {}",
+                        listener.base_endpoint_url()
+                    );
+                }
+                address.map(|address| (listener, address))
+            })
+            .collect()
     }
 
     /// This timer will poll the server to see if it has aborted. It also cleans up dead connections.
@@ -490,7 +687,26 @@ impl Server {
                 {
                     let server = trace_read_lock!(server);
                     let has_open_connections = server.remove_dead_connections();
-                    let server_state = trace_read_lock!(server.server_state);
+                    let mut server_state = trace_write_lock!(server.server_state);
+                    if server_state.is_shutting_down() && !server_state.is_abort() {
+                        // Count down to the shutdown. The server aborts when the delay has
+                        // expired and the connections are closed, or a while after that.
+                        let seconds_till_shutdown = server_state.seconds_till_shutdown();
+                        let shutdown_reason = server_state.shutdown_reason();
+                        if server_state.is_shutdown_due()
+                            && (!has_open_connections || server_state.is_shutdown_overdue())
+                        {
+                            server_state.abort();
+                        }
+                        drop(server_state);
+                        let mut address_space = trace_write_lock!(server.address_space);
+                        address_space.set_server_status(
+                            ServerStateType::Shutdown,
+                            seconds_till_shutdown,
+                            shutdown_reason,
+                        );
+                        server_state = trace_write_lock!(server.server_state);
+                    }
                     // Predicate breaks on abort & no open connections
                     if server_state.is_abort() {
                         if has_open_connections {
@@ -582,6 +798,285 @@ impl Server {
         });
     }
 
+    /// Certificate renewal runs a timer that checks every hour, starting now, if the server's own
+    /// certificate is about to expire and renews it if so. Existing secure channels carry on with
+    /// the old certificate and key, new ones use the renewed pair.
+    fn start_certificate_renewal_timer(&self) {
+        let renew_before_days = {
+            let server_state = trace_read_lock!(self.server_state);
+            let config = trace_read_lock!(server_state.config);
+            if !config.certificate_renewal.enabled {
+                return;
+            }
+            config.certificate_renewal.renew_before_days
+        };
+        let server_state = self.server_state.clone();
+        let certificate_store = self.certificate_store.clone();
+        let address_space = self.address_space.clone();
+
+        let check_duration =
+            Duration::from_millis(constants::CERTIFICATE_RENEWAL_CHECK_INTERVAL_MS);
+        let mut last_checked = Instant::now() - check_duration;
+
+        tokio::spawn(async move {
+            // Polls quickly so the task terminates on server abort but only checks the
+            // certificate when the check duration has elapsed.
+            let mut timer = interval_at(Instant::now(), Duration::from_millis(1000));
+            loop {
+                {
+                    let server_state = trace_read_lock!(server_state);
+                    if !server_state.is_serving() {
+                        break;
+                    }
+                }
+
+                timer.tick().await;
+
+                let now = Instant::now();
+                if now.duration_since(last_checked) >= check_duration {
+                    last_checked = now;
+                    Self::renew_certificate(
+                        &server_state,
+                        &certificate_store,
+                        &address_space,
+                        renew_before_days,
+                    );
+                }
+            }
+            info!("Certificate renewal timer task is finished");
+        });
+    }
+
+    /// The sampling timer samples the monitored items of every subscription when the schedule of
+    /// their sampling interval is due. The task sleeps until the next schedule is due, or a new
+    /// schedule is added, but wakes up at least once a second to terminate on server abort.
+    fn start_sampling_timer(&self) {
+        let server_state = self.server_state.clone();
+        let address_space = self.address_space.clone();
+        let (sampling_schedules, schedule_added) = {
+            let server_state = trace_read_lock!(server_state);
+            let schedule_added = trace_lock!(server_state.sampling_schedules).schedule_added();
+            (server_state.sampling_schedules.clone(), schedule_added)
+        };
+
+        tokio::spawn(async move {
+            loop {
+                {
+                    let server_state = trace_read_lock!(server_state);
+                    if !server_state.is_serving() {
+                        break;
+                    }
+                }
+
+                let poll_time = Instant::now() + Duration::from_millis(1000);
+                let next_sample = trace_lock!(sampling_schedules)
+                    .next_sample()
+                    .map(Instant::from_std)
+                    .map_or(poll_time, |next_sample| next_sample.min(poll_time));
+                tokio::select! {
+                    _ = sleep_until(next_sample) => {}
+                    _ = schedule_added.notified() => {}
+                }
+
+                let address_space = trace_read_lock!(address_space);
+                let mut sampling_schedules = trace_lock!(sampling_schedules);
+                sampling_schedules.sample(&address_space, std::time::Instant::now());
+            }
+            info!("Sampling timer task is finished");
+        });
+    }
+
+    /// The durable subscriptions timer restores the durable subscriptions saved when the server
+    /// last ran and then ticks the durable subscriptions whose session has ended, so their
+    /// monitored items keep queueing values until they are transferred or expire. The durable
+    /// subscriptions, including those of live sessions, are saved periodically and when the
+    /// server stops.
+    fn start_durable_subscriptions_timer(&self) {
+        let server_state = self.server_state.clone();
+        let address_space = self.address_space.clone();
+        let session_manager = self.session_manager.clone();
+        let (durable_subscriptions, min_publishing_interval_ms) = {
+            let mut server_state = trace_write_lock!(server_state);
+            let durable_subscriptions = server_state.durable_subscriptions.clone();
+            {
+                let address_space = trace_read_lock!(address_space);
+                let mut durable_subscriptions = trace_lock!(durable_subscriptions);
+                durable_subscriptions.restore(&mut server_state, &address_space);
+            }
+            (
+                durable_subscriptions,
+                server_state.min_publishing_interval_ms,
+            )
+        };
+
+        let save_duration =
+            Duration::from_millis(constants::DURABLE_SUBSCRIPTIONS_SAVE_INTERVAL_MS);
+        let mut last_saved = Instant::now();
+
+        tokio::spawn(async move {
+            let mut timer = interval_at(
+                Instant::now(),
+                Duration::from_millis(min_publishing_interval_ms as u64),
+            );
+            loop {
+                {
+                    let server_state = trace_read_lock!(server_state);
+                    if !server_state.is_serving() {
+                        break;
+                    }
+                }
+
+                timer.tick().await;
+
+                let now = chrono::Utc::now();
+                {
+                    let address_space = trace_read_lock!(address_space);
+                    let mut durable_subscriptions = trace_lock!(durable_subscriptions);
+                    durable_subscriptions.tick(&now, &address_space);
+                }
+                if last_saved.elapsed() >= save_duration {
+                    last_saved = Instant::now();
+                    let session_records = Self::durable_session_records(&session_manager);
+                    trace_lock!(durable_subscriptions).save_with_sessions(session_records);
+                }
+            }
+            let session_records = Self::durable_session_records(&session_manager);
+            trace_lock!(durable_subscriptions).save_with_sessions(session_records);
+            info!("Durable subscriptions timer task is finished");
+        });
+    }
+
+    /// Makes the records of the durable subscriptions of the live sessions
+    fn durable_session_records(
+        session_manager: &Arc<RwLock<SessionManager>>,
+    ) -> Vec<SubscriptionRecord> {
+        let sessions = {
+            let session_manager = trace_read_lock!(session_manager);
+            session_manager
+                .sessions
+                .values()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        durable::session_records(&sessions)
+    }
+
+    /// The redundancy timer runs while the server is part of a redundant server set. It sets the
+    /// service level from the health check and updates the servers of the set in the address
+    /// space, then sends the service level and the subscriptions to replicate through the
+    /// redundancy channel.
+    fn start_redundancy_timer(&self) {
+        let update_interval_ms = {
+            let server_state = trace_read_lock!(self.server_state);
+            let config = trace_read_lock!(server_state.config);
+            if !config.redundancy.is_enabled() {
+                return;
+            }
+            config.redundancy.update_interval_ms
+        };
+        let server_state = self.server_state.clone();
+        let address_space = self.address_space.clone();
+        let session_manager = self.session_manager.clone();
+        let redundancy = self.redundancy.clone();
+
+        tokio::spawn(async move {
+            let mut timer = interval_at(Instant::now(), Duration::from_millis(update_interval_ms));
+            let mut last_update = None;
+            loop {
+                timer.tick().await;
+
+                let (is_running, durable_subscriptions) = {
+                    let server_state = trace_read_lock!(server_state);
+                    if !server_state.is_serving() {
+                        break;
+                    }
+                    (
+                        server_state.is_running(),
+                        server_state.durable_subscriptions.clone(),
+                    )
+                };
+
+                let (server_uri, service_level, redundant_servers, channel, replicate) = {
+                    let mut redundancy = trace_lock!(redundancy);
+                    redundancy.check_health();
+                    (
+                        redundancy.server_uri().to_string(),
+                        redundancy.service_level(),
+                        redundancy.redundant_servers(),
+                        redundancy.channel.clone(),
+                        redundancy.replicate_subscriptions(),
+                    )
+                };
+
+                // Only touch the address space when something changed
+                let update = (service_level, redundant_servers);
+                if last_update.as_ref() != Some(&update) {
+                    let mut address_space = trace_write_lock!(address_space);
+                    address_space.set_service_level(update.0, &DateTime::now());
+                    address_space.set_redundant_servers(&update.1);
+                    last_update = Some(update);
+                }
+
+                let Some(channel) = channel else {
+                    continue;
+                };
+                // A server that is shutting down tells the others it can no longer be used, and
+                // stops replicating so they keep its subscriptions as they were before its
+                // sessions closed
+                channel.send(RedundancyMessage::ServiceLevel {
+                    server_uri: server_uri.clone(),
+                    service_level: if is_running { service_level } else { 0 },
+                });
+                if replicate && is_running {
+                    let sessions = {
+                        let session_manager = trace_read_lock!(session_manager);
+                        session_manager
+                            .sessions
+                            .values()
+                            .cloned()
+                            .collect::<Vec<_>>()
+                    };
+                    let subscriptions =
+                        redundancy::replicated_subscriptions(&sessions, &durable_subscriptions);
+                    channel.send(RedundancyMessage::Subscriptions {
+                        server_uri,
+                        subscriptions,
+                    });
+                }
+            }
+            info!("Redundancy timer task is finished");
+        });
+    }
+
+    /// Renews the server's own certificate if it expires within the specified number of days and
+    /// raises an audit event holding the old certificate.
+    fn renew_certificate(
+        server_state: &Arc<RwLock<ServerState>>,
+        certificate_store: &Arc<RwLock<CertificateStore>>,
+        address_space: &Arc<RwLock<AddressSpace>>,
+        renew_before_days: u32,
+    ) {
+        let result = {
+            let certificate_store = trace_write_lock!(certificate_store);
+            certificate_store.renew_application_instance_cert(renew_before_days)
+        };
+        match result {
+            Ok(Some((cert, pkey))) => {
+                let mut server_state = trace_write_lock!(server_state);
+                let old_cert = server_state.server_certificate.replace(cert);
+                server_state.server_pkey = Some(pkey);
+                if let Some(ref old_cert) = old_cert {
+                    audit::log_certificate_renewed(&server_state, address_space.clone(), old_cert);
+                }
+                info!("Server certificate has been renewed");
+            }
+            Ok(None) => {}
+            Err(err) => {
+                error!("Server certificate could not be renewed, error = {}", err);
+            }
+        }
+    }
+
     /// Creates a polling action that happens continuously on an interval while the server
     /// is running. For example, a server might run a polling action every 100ms to synchronous
     /// address space state between variables and their physical backends.
@@ -627,9 +1122,10 @@ impl Server {
             });
     }
 
-    /// Create a new transport.
-    pub fn new_transport(&self) -> TcpTransport {
+    /// Create a new transport for a connection made through the listener.
+    pub fn new_transport(&self, listener: ServerListener) -> TcpTransport {
         TcpTransport::new(
+            listener,
             self.certificate_store.clone(),
             self.server_state.clone(),
             self.address_space.clone(),
@@ -638,24 +1134,21 @@ impl Server {
     }
 
     /// Handles the incoming request
-    fn handle_connection(&mut self, socket: TcpStream) {
+    fn handle_connection(&mut self, listener: ServerListener, socket: ConnectionSocket) {
         trace!("Connection thread spawning");
 
         // Spawn a task for the connection
-        let connection = Arc::new(RwLock::new(self.new_transport()));
+        let connection = Arc::new(RwLock::new(self.new_transport(listener)));
         {
             let mut connections = trace_write_lock!(self.connections);
             connections.push(connection.clone());
         }
 
-        // Looping interval has to cope with whatever sampling rate server needs
+        // Looping interval only has to cope with the publishing rate, monitored items are sampled
+        // by their own schedules
         let looping_interval_ms = {
             let server_state = trace_read_lock!(self.server_state);
-            // Get the minimum interval in ms
-            f64::min(
-                server_state.min_publishing_interval_ms,
-                server_state.min_sampling_interval_ms,
-            )
+            server_state.min_publishing_interval_ms
         };
 
         // Run adds a session task to the tokio session
@@ -4,6 +4,7 @@
 
 use std::sync::Arc;
 
+use crate::crypto::X509;
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
@@ -148,3 +149,21 @@ pub fn log_certificate_error(
         }
     };
 }
+
+/// Logs that the server's own certificate was about to expire and has been replaced by a new one.
+/// The event holds the old certificate.
+pub fn log_certificate_renewed(
+    server_state: &ServerState,
+    address_space: Arc<RwLock<AddressSpace>>,
+    old_certificate: &X509,
+) {
+    let node_id = next_node_id(address_space);
+    let now = DateTime::now();
+
+    let event = AuditCertificateExpiredEventType::new(node_id, now)
+        .certificate(old_certificate.as_byte_string())
+        .message("Application instance certificate was about to expire and has been renewed")
+        .source_name("Server")
+        .status(true);
+    let _ = server_state.raise_and_log(event);
+}
@@ -8,7 +8,7 @@ use crate::core::{config::Config, supported_message::SupportedMessage};
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
-use crate::server::state::ServerState;
+use crate::server::{config::ServerListener, state::ServerState};
 
 use super::Service;
 
@@ -28,6 +28,7 @@ impl DiscoveryService {
 
     pub fn get_endpoints(
         &self,
+        listener: &ServerListener,
         server_state: Arc<RwLock<ServerState>>,
         request: &GetEndpointsRequest,
     ) -> SupportedMessage {
@@ -38,7 +39,8 @@ impl DiscoveryService {
 
         // TODO audit - generate event for failed service invocation
 
-        let endpoints = server_state.endpoints(&request.endpoint_url, &request.profile_uris);
+        let endpoints =
+            server_state.endpoints(listener, &request.endpoint_url, &request.profile_uris);
         GetEndpointsResponse {
             response_header: ResponseHeader::new_good(&request.request_header),
             endpoints,
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use chrono::Utc;
 
@@ -14,7 +14,11 @@ use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
     address_space::AddressSpace,
-    comms::tcp_transport::MessageSender,
+    comms::{
+        tcp_transport::MessageSender,
+        transport::{ClientConnection, PeerCredentials},
+    },
+    config::ServerListener,
     services::{
         attribute::AttributeService, discovery::DiscoveryService, method::MethodService,
         monitored_item::MonitoredItemService, node_management::NodeManagementService,
@@ -22,12 +26,14 @@ use crate::server::{
         view::ViewService,
     },
     session::{Session, SessionManager},
-    session_diagnostics::*,
+    session_diagnostics::{self, *},
     state::ServerState,
 };
 
 /// Processes and dispatches messages for handling
 pub(crate) struct MessageHandler {
+    /// The connection that messages arrive through
+    connection: ClientConnection,
     /// Secure channel
     secure_channel: Arc<RwLock<SecureChannel>>,
     /// Certificate store for certs
@@ -60,6 +66,7 @@ pub(crate) struct MessageHandler {
 
 impl MessageHandler {
     pub fn new(
+        listener: ServerListener,
         secure_channel: Arc<RwLock<SecureChannel>>,
         certificate_store: Arc<RwLock<CertificateStore>>,
         server_state: Arc<RwLock<ServerState>>,
@@ -67,6 +74,10 @@ impl MessageHandler {
         address_space: Arc<RwLock<AddressSpace>>,
     ) -> MessageHandler {
         MessageHandler {
+            connection: ClientConnection {
+                listener,
+                peer_credentials: None,
+            },
             secure_channel,
             certificate_store,
             server_state,
@@ -84,6 +95,11 @@ impl MessageHandler {
         }
     }
 
+    /// Sets the credentials of the client process once they are known from the socket
+    pub fn set_peer_credentials(&mut self, peer_credentials: Option<PeerCredentials>) {
+        self.connection.peer_credentials = peer_credentials;
+    }
+
     pub fn handle_message(
         &mut self,
         request_id: u32,
@@ -98,11 +114,16 @@ impl MessageHandler {
 
         let server_state = self.server_state.clone();
         let address_space = self.address_space.clone();
+        let started = Instant::now();
 
         let response = match message {
             // Discovery Service Set, OPC UA Part 4, Section 5.4
             SupportedMessage::GetEndpointsRequest(request) => {
-                Some(self.discovery_service.get_endpoints(server_state, request))
+                Some(self.discovery_service.get_endpoints(
+                    &self.connection.listener,
+                    server_state,
+                    request,
+                ))
             }
 
             SupportedMessage::RegisterServerRequest(request) => Some(
@@ -131,6 +152,7 @@ impl MessageHandler {
                         .into()
                 } else {
                     let (session, response) = self.session_service.create_session(
+                        &self.connection,
                         self.secure_channel.clone(),
                         self.certificate_store.clone(),
                         server_state,
@@ -160,6 +182,7 @@ impl MessageHandler {
                 .validate_activate_service_request(message, "", |session| {
                     let secure_channel = self.secure_channel.clone();
                     self.session_service.activate_session(
+                        &self.connection,
                         secure_channel,
                         server_state,
                         session,
@@ -424,10 +447,12 @@ impl MessageHandler {
             }
             SupportedMessage::TransferSubscriptionsRequest(request) => self
                 .validate_service_request(message, TRANSFER_SUBSCRIPTIONS_COUNT, |session, _| {
-                    Some(
-                        self.subscription_service
-                            .transfer_subscriptions(session, request),
-                    )
+                    Some(self.subscription_service.transfer_subscriptions(
+                        server_state,
+                        session,
+                        address_space,
+                        request,
+                    ))
                 }),
             SupportedMessage::PublishRequest(request) => {
                 self.validate_service_request(message, "", |session, _| {
@@ -461,6 +486,8 @@ impl MessageHandler {
             }
         };
 
+        self.diag_service_request(message, started, response.as_ref());
+
         if let Some(response) = response {
             let _ = sender.send_message(request_id, response);
         }
@@ -468,6 +495,43 @@ impl MessageHandler {
         Ok(())
     }
 
+    /// Returns the name of the service a request is for, e.g. "Read" for a ReadRequest
+    fn service_name(request: &SupportedMessage) -> Option<String> {
+        match request {
+            SupportedMessage::Invalid(_) | SupportedMessage::AcknowledgeMessage(_) => None,
+            request => request.node_id().as_object_id().ok().map(|object_id| {
+                format!("{:?}", object_id)
+                    .trim_end_matches("Request_Encoding_DefaultBinary")
+                    .to_string()
+            }),
+        }
+    }
+
+    /// Count the request and the latency of its response in the server diagnostics. Responses
+    /// that are sent later on, i.e. to publish requests, have no latency.
+    fn diag_service_request(
+        &self,
+        request: &SupportedMessage,
+        started: Instant,
+        response: Option<&SupportedMessage>,
+    ) {
+        if let Some(service) = Self::service_name(request) {
+            let latency = response.map(|_| started.elapsed());
+            let error = matches!(response, Some(SupportedMessage::ServiceFault(_)));
+            let server_state = trace_read_lock!(self.server_state);
+            let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+            diagnostics.on_service_request(&service, latency, error);
+        }
+    }
+
+    /// Count a request that was rejected because its session is invalid, not activated or timed
+    /// out in the server diagnostics
+    fn diag_security_rejected_request(&self) {
+        let server_state = trace_read_lock!(self.server_state);
+        let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+        diagnostics.on_security_rejected_request();
+    }
+
     /// Tests if this request should be rejected because of a session timeout
     fn is_session_timed_out(
         session: Arc<RwLock<Session>>,
@@ -544,13 +608,18 @@ impl MessageHandler {
                 session.set_last_service_request_timestamp(now);
                 (response, true)
             };
-            Self::diag_service_response(session, authorized, &response, diagnostic_key);
+            if !authorized {
+                self.diag_security_rejected_request();
+            }
+            Self::diag_service_response(session.clone(), authorized, &response, diagnostic_key);
+            self.diag_update_session(&session);
             Some(response)
         } else {
             warn!(
                 "validate_activate_service_request, session not found for token {}",
                 &request_header.authentication_token
             );
+            self.diag_security_rejected_request();
             Some(ServiceFault::new(request_header, StatusCode::BadSessionIdInvalid).into())
         }
     }
@@ -587,16 +656,42 @@ impl MessageHandler {
                     session.set_last_service_request_timestamp(now);
                     (response, true)
                 };
+            if !authorized {
+                self.diag_security_rejected_request();
+            }
             // Async calls may not return a response here
-            response.map(|response| {
-                Self::diag_service_response(session, authorized, &response, diagnostic_key);
+            let response = response.map(|response| {
+                Self::diag_service_response(session.clone(), authorized, &response, diagnostic_key);
                 response
-            })
+            });
+            self.diag_update_session(&session);
+            response
         } else {
+            self.diag_security_rejected_request();
             Some(ServiceFault::new(request_header, StatusCode::BadSessionIdInvalid).into())
         }
     }
 
+    /// Update the diagnostics of the session in the address space after it handled a request,
+    /// or expose those of every session if diagnostics have just been enabled
+    fn diag_update_session(&self, session: &Arc<RwLock<Session>>) {
+        let session = trace_read_lock!(session);
+        let diagnostics = session.server_diagnostics().clone();
+        let is_exposing_sessions = trace_read_lock!(diagnostics).is_exposing_sessions();
+        if is_exposing_sessions {
+            let mut address_space = trace_write_lock!(self.address_space);
+            session.update_diagnostics(&mut address_space);
+        } else {
+            // The sessions are locked one by one to snapshot them
+            drop(session);
+            session_diagnostics::expose_sessions(
+                &self.session_manager,
+                &self.address_space,
+                &diagnostics,
+            );
+        }
+    }
+
     /// Increment count of request in session diagnostics
     fn diag_authorized_request(session_diagnostics: &mut SessionDiagnostics, authorized: bool) {
         if authorized {
@@ -36,4 +36,4 @@ pub mod session;
 pub mod subscription;
 pub mod view;
 
-mod audit;
+pub(crate) mod audit;
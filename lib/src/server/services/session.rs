@@ -6,12 +6,16 @@ use std::sync::Arc;
 
 use crate::core::comms::secure_channel::SecureChannel;
 use crate::core::supported_message::SupportedMessage;
-use crate::crypto::{self as crypto, random, CertificateStore, SecurityPolicy};
+use crate::crypto::{
+    self as crypto, certificate_store::CertificateValidation, random, CertificateStore,
+    SecurityPolicy, X509,
+};
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
     address_space::address_space::AddressSpace,
+    comms::transport::ClientConnection,
     constants,
     identity_token::IdentityToken,
     services::{audit, Service},
@@ -35,6 +39,7 @@ impl SessionService {
 
     pub fn create_session(
         &self,
+        connection: &ClientConnection,
         secure_channel: Arc<RwLock<SecureChannel>>,
         certificate_store: Arc<RwLock<CertificateStore>>,
         server_state: Arc<RwLock<ServerState>>,
@@ -46,12 +51,16 @@ impl SessionService {
 
         debug!("Create session request {:?}", request);
 
-        let endpoints = server_state.new_endpoint_descriptions(request.endpoint_url.as_ref());
+        let endpoints = server_state
+            .new_endpoint_descriptions(&connection.listener, request.endpoint_url.as_ref());
 
         // Check the args
         let service_result = {
-            // Validate the endpoint url
-            if request.endpoint_url.is_null() {
+            if server_state.is_shutting_down() {
+                info!("Create session rejected because the server is shutting down");
+                StatusCode::BadShutdown
+            } else if request.endpoint_url.is_null() {
+                // Validate the endpoint url
                 error!("Create session was passed an null endpoint url");
                 StatusCode::BadTcpEndpointUrlInvalid
             } else {
@@ -86,14 +95,28 @@ impl SessionService {
                 secure_channel.security_policy()
             };
             let service_result = if security_policy != SecurityPolicy::None {
-                let certificate_store = trace_read_lock!(certificate_store);
                 let result = if let Some(ref client_certificate) = client_certificate {
-                    certificate_store.validate_or_reject_application_instance_cert(
-                        client_certificate,
-                        security_policy,
-                        None,
-                        None,
-                    )
+                    let validation = trace_read_lock!(certificate_store)
+                        .check_or_reject_application_instance_cert(
+                            client_certificate,
+                            security_policy,
+                            None,
+                            None,
+                        );
+                    match validation {
+                        CertificateValidation::Done(result) => result,
+                        CertificateValidation::Unknown(approver, status_code) => {
+                            // The session is not held up while the approver decides. Its decision
+                            // applies when the client tries again.
+                            tokio::spawn(CertificateStore::approve_later(
+                                certificate_store.clone(),
+                                approver,
+                                client_certificate.clone(),
+                                status_code,
+                            ));
+                            StatusCode::BadCertificateUntrusted
+                        }
+                    }
                 } else {
                     warn!("Certificate supplied by client is invalid");
                     StatusCode::BadCertificateInvalid
@@ -110,6 +133,7 @@ impl SessionService {
                     // Rejected for security reasons
                     let mut diagnostics = trace_write_lock!(server_state.diagnostics);
                     diagnostics.on_rejected_security_session();
+                    diagnostics.on_rejected_certificate(result);
                 }
                 result
             } else {
@@ -141,8 +165,14 @@ impl SessionService {
 
                 let max_request_message_size = constants::MAX_REQUEST_MESSAGE_SIZE;
 
+                // The channel's own certificate and key are used in preference to the server's
+                // so a channel opened before the server's certificate was renewed stays consistent
+                let server_pkey = secure_channel
+                    .private_key()
+                    .or(server_state.server_pkey.as_ref());
+
                 // Calculate a signature (assuming there is a pkey)
-                let server_signature = if let Some(ref pkey) = server_state.server_pkey {
+                let server_signature = if let Some(pkey) = server_pkey {
                     crypto::create_signature_data(pkey, security_policy, &request.client_certificate, &request.client_nonce)
                         .unwrap_or_else(|err| {
                             error!("Cannot create signature data from private key, check log and error {:?}", err);
@@ -154,7 +184,11 @@ impl SessionService {
 
                 let authentication_token = NodeId::new(0, random::byte_string(32));
                 let server_nonce = security_policy.random_nonce();
-                let server_certificate = server_state.server_certificate_as_byte_string();
+                let server_certificate = if let Some(cert) = secure_channel.cert() {
+                    cert.as_byte_string()
+                } else {
+                    server_state.server_certificate_as_byte_string()
+                };
                 let server_endpoints = Some(endpoints);
 
                 session.set_authentication_token(authentication_token.clone());
@@ -168,6 +202,10 @@ impl SessionService {
                 session.set_client_certificate(client_certificate);
                 session.set_session_nonce(server_nonce.clone());
                 session.set_session_name(request.session_name.clone());
+                session.set_client_description(request.client_description.clone());
+                session.set_server_uri(request.server_uri.clone());
+                session.set_security_mode(secure_channel.security_mode());
+                session.set_peer_credentials(connection.peer_credentials);
 
                 audit::log_create_session(
                     &server_state,
@@ -203,6 +241,7 @@ impl SessionService {
 
     pub fn activate_session(
         &self,
+        connection: &ClientConnection,
         secure_channel: Arc<RwLock<SecureChannel>>,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
@@ -213,17 +252,23 @@ impl SessionService {
         let mut session = trace_write_lock!(session);
         let endpoint_url = session.endpoint_url().as_ref();
 
-        let (security_policy, security_mode, secure_channel_id) = {
-            let secure_channel = trace_read_lock!(secure_channel);
-            (
-                secure_channel.security_policy(),
-                secure_channel.security_mode(),
-                secure_channel.secure_channel_id(),
-            )
-        };
+        // The channel's own certificate and key are used in preference to the server's, as they
+        // are when the session is created
+        let secure_channel = trace_read_lock!(secure_channel);
+        let security_policy = secure_channel.security_policy();
+        let security_mode = secure_channel.security_mode();
+        let secure_channel_id = secure_channel.secure_channel_id();
+        let channel_certificate = secure_channel.cert();
+        let server_certificate = channel_certificate
+            .as_ref()
+            .or(server_state.server_certificate.as_ref());
+        let server_pkey = secure_channel
+            .private_key()
+            .or(server_state.server_pkey.as_ref());
 
         let server_nonce = security_policy.random_nonce();
         let mut service_result = if !server_state.endpoint_exists(
+            &connection.listener,
             endpoint_url,
             security_policy,
             security_mode,
@@ -239,7 +284,7 @@ impl SessionService {
             // signature supplied by the client during the create.
             Self::verify_client_signature(
                 security_policy,
-                &server_state,
+                server_certificate,
                 &session,
                 &request.client_signature,
             )
@@ -250,15 +295,21 @@ impl SessionService {
 
         if service_result.is_good() {
             if let Err(err) = server_state.authenticate_endpoint(
+                &connection.listener,
                 request,
                 endpoint_url,
                 security_policy,
                 security_mode,
-                &request.user_identity_token,
+                server_certificate,
+                server_pkey,
                 session.session_nonce(),
             ) {
                 error!("activate_session, invalid endpoint");
                 service_result = err;
+            } else if let Err(err) = server_state
+                .authenticate_peer(&connection.listener, connection.peer_credentials.as_ref())
+            {
+                service_result = err;
             }
         }
 
@@ -297,20 +348,18 @@ impl SessionService {
                 &server_state.decoding_options(),
             ));
             session.set_locale_ids(request.locale_ids.clone());
+            session.set_peer_credentials(connection.peer_credentials);
 
             let diagnostic_infos = None;
 
-            {
-                let secure_channel = trace_read_lock!(secure_channel);
-                audit::log_activate_session(
-                    &secure_channel,
-                    &server_state,
-                    &session,
-                    address_space,
-                    true,
-                    request,
-                );
-            }
+            audit::log_activate_session(
+                &secure_channel,
+                &server_state,
+                &session,
+                address_space,
+                true,
+                request,
+            );
 
             ActivateSessionResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
@@ -366,9 +415,16 @@ impl SessionService {
                     );
                 }
 
+                // Durable subscriptions outlive the session unless the client deletes them
+                if request.delete_subscriptions {
+                    let _ = session.subscriptions_mut().remove_durable();
+                }
+
                 session.set_authentication_token(NodeId::null());
                 session.set_user_identity(IdentityToken::None);
                 session.set_activated(false);
+                session.set_terminated();
+                session.deregister_session(address_space.clone());
                 audit::log_close_session(&server_state, &session, address_space, true, request);
             }
 
@@ -408,12 +464,12 @@ impl SessionService {
     /// from the server's certificate and nonce.
     fn verify_client_signature(
         security_policy: SecurityPolicy,
-        server_state: &ServerState,
+        server_certificate: Option<&X509>,
         session: &Session,
         client_signature: &SignatureData,
     ) -> StatusCode {
         if let Some(ref client_certificate) = session.client_certificate() {
-            if let Some(ref server_certificate) = server_state.server_certificate {
+            if let Some(server_certificate) = server_certificate {
                 crypto::verify_signature_data(
                     client_signature,
                     security_policy,
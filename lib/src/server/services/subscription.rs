@@ -113,7 +113,11 @@ impl SubscriptionService {
             subscription.set_publishing_interval(revised_publishing_interval);
             subscription.set_max_keep_alive_count(revised_max_keep_alive_count);
             subscription.set_max_lifetime_count(revised_lifetime_count);
+            // A durable subscription keeps the lifetime it was made durable for
+            subscription.revise_durable_lifetime();
+            let revised_lifetime_count = subscription.max_lifetime_count();
             subscription.set_priority(request.priority);
+            subscription.counters_mut().modify_count += 1;
             subscription.reset_lifetime_counter();
             subscription.reset_keep_alive_counter();
             // ...max_notifications_per_publish??
@@ -164,32 +168,122 @@ impl SubscriptionService {
         }
     }
 
-    /// Handles a TransferSubscriptionsRequest
+    /// Handles a TransferSubscriptionsRequest. Durable subscriptions whose session has ended are
+    /// transferred to the session if it belongs to the same user.
     pub fn transfer_subscriptions(
         &self,
-        _session: Arc<RwLock<Session>>,
+        server_state: Arc<RwLock<ServerState>>,
+        session: Arc<RwLock<Session>>,
+        address_space: Arc<RwLock<AddressSpace>>,
         request: &TransferSubscriptionsRequest,
     ) -> SupportedMessage {
         if is_empty_option_vec!(request.subscription_ids) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
+            let max_subscriptions = {
+                let server_state = trace_read_lock!(server_state);
+                server_state.max_subscriptions
+            };
+            let mut session = trace_write_lock!(session);
+            let owner = session.client_user_id();
+            let durable_subscriptions = session.durable_subscriptions().clone();
             let subscription_ids = request.subscription_ids.as_ref().unwrap();
+            // Subscriptions replicated from another server, which are made once the durable
+            // subscriptions are unlocked
+            let mut replicas = Vec::new();
             let results = {
-                // TODO this is a stub. The real thing should look up subscriptions belonging to
-                //  other sessions and transfer them across to this one.
+                let mut durable_subscriptions = trace_lock!(durable_subscriptions);
+                // Keep the subscriptions of sessions that ended since the durable subscriptions
+                // were last ticked
+                durable_subscriptions.receive_detached();
+                let subscriptions = session.subscriptions_mut();
                 let results = subscription_ids
                     .iter()
-                    .map(|_subscription_id| TransferResult {
-                        status_code: StatusCode::BadSubscriptionIdInvalid,
-                        available_sequence_numbers: None,
+                    .map(|subscription_id| {
+                        let mut available_sequence_numbers = None;
+                        let status_code = if let Some(subscription) =
+                            subscriptions.get_mut(*subscription_id)
+                        {
+                            // Already belongs to the session
+                            let counters = subscription.counters_mut();
+                            counters.transfer_request_count += 1;
+                            counters.transferred_to_same_client_count += 1;
+                            available_sequence_numbers =
+                                subscriptions.available_sequence_numbers(*subscription_id);
+                            StatusCode::Good
+                        } else if !durable_subscriptions.contains(*subscription_id) {
+                            StatusCode::BadSubscriptionIdInvalid
+                        } else if max_subscriptions > 0
+                            && subscriptions.len() + replicas.len() >= max_subscriptions
+                        {
+                            StatusCode::BadTooManySubscriptions
+                        } else if durable_subscriptions.is_replica(*subscription_id) {
+                            match durable_subscriptions.transfer_replica(*subscription_id, &owner) {
+                                Ok(record) => {
+                                    replicas.push(record);
+                                    StatusCode::Good
+                                }
+                                Err(status_code) => status_code,
+                            }
+                        } else {
+                            match durable_subscriptions.transfer(*subscription_id, &owner) {
+                                Ok(mut subscription) => {
+                                    // The notifications it kept can be republished
+                                    available_sequence_numbers =
+                                        subscription.available_sequence_numbers();
+                                    subscription.counters_mut().transfer_request_count += 1;
+                                    subscription.reset_lifetime_counter();
+                                    if request.send_initial_values {
+                                        subscription.set_resend_data();
+                                    }
+                                    subscriptions.insert(*subscription_id, subscription);
+                                    StatusCode::Good
+                                }
+                                Err(status_code) => status_code,
+                            }
+                        };
+                        TransferResult {
+                            status_code,
+                            available_sequence_numbers,
+                        }
                     })
                     .collect::<Vec<TransferResult>>();
-                Some(results)
+                results
             };
+            let mut results = results;
+            if !replicas.is_empty() {
+                let now = chrono::Utc::now();
+                let server_state = trace_read_lock!(server_state);
+                let address_space = trace_read_lock!(address_space);
+                for record in replicas {
+                    let subscription_id = record.subscription_id;
+                    let mut subscription = Subscription::from_durable_record(
+                        record,
+                        &server_state,
+                        &address_space,
+                        &now,
+                    );
+                    subscription.counters_mut().transfer_request_count += 1;
+                    subscription.reset_lifetime_counter();
+                    if request.send_initial_values {
+                        subscription.set_resend_data();
+                    }
+                    if let Some(idx) = subscription_ids
+                        .iter()
+                        .position(|id| *id == subscription_id)
+                    {
+                        results[idx].available_sequence_numbers =
+                            subscription.available_sequence_numbers();
+                    }
+                    session
+                        .subscriptions_mut()
+                        .insert(subscription_id, subscription);
+                }
+            }
             let diagnostic_infos = None;
             TransferSubscriptionsResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
-                results,
+                results: Some(results),
                 diagnostic_infos,
             }
             .into()
@@ -271,6 +365,14 @@ impl SubscriptionService {
         let result = session
             .subscriptions()
             .find_notification_message(request.subscription_id, request.retransmit_sequence_number);
+        if let Some(subscription) = session.subscriptions_mut().get_mut(request.subscription_id) {
+            let counters = subscription.counters_mut();
+            counters.republish_request_count += 1;
+            counters.republish_message_request_count += 1;
+            if result.is_ok() {
+                counters.republish_message_count += 1;
+            }
+        }
         if let Ok(notification_message) = result {
             session.reset_subscription_lifetime_counter(request.subscription_id);
             let response = RepublishResponse {
@@ -5,7 +5,7 @@ use std::{
     collections::{HashMap, HashSet, VecDeque},
     sync::{
         atomic::{AtomicI32, Ordering},
-        Arc,
+        mpsc, Arc,
     },
 };
 
@@ -13,16 +13,26 @@ use chrono::Utc;
 
 use crate::crypto::X509;
 use crate::sync::*;
-use crate::types::{service_types::PublishRequest, status_code::StatusCode, *};
+use crate::types::{
+    profiles,
+    service_types::{
+        ApplicationDescription, MessageSecurityMode, PublishRequest, SessionDiagnosticsDataType,
+        SessionSecurityDiagnosticsDataType,
+    },
+    status_code::StatusCode,
+    *,
+};
 
 use crate::server::{
     address_space::{AddressSpace, UserAccessLevel},
+    comms::transport::PeerCredentials,
     continuation_point::BrowseContinuationPoint,
     diagnostics::ServerDiagnostics,
     identity_token::IdentityToken,
-    session_diagnostics::SessionDiagnostics,
+    session_diagnostics::{self, SessionDiagnostics},
     state::ServerState,
-    subscriptions::subscription::TickReason,
+    subscriptions::durable::DurableSubscriptions,
+    subscriptions::subscription::{Subscription, TickReason},
     subscriptions::subscriptions::Subscriptions,
 };
 
@@ -70,14 +80,43 @@ impl SessionManager {
         self.sessions_terminated
     }
 
-    /// Puts all sessions into a terminated state, deregisters them, and clears the map
-    pub fn clear(&mut self, address_space: Arc<RwLock<AddressSpace>>) {
-        for (_nodeid, session) in self.sessions.drain() {
+    /// Puts the sessions of the secure channel into a terminated state, deregisters them, and
+    /// removes them from the map. The sessions of other connections are left alone.
+    pub fn clear_secure_channel(
+        &mut self,
+        secure_channel_id: u32,
+        address_space: Arc<RwLock<AddressSpace>>,
+    ) {
+        self.sessions.retain(|_nodeid, session| {
             let mut session = trace_write_lock!(session);
+            if session.secure_channel_id() != secure_channel_id {
+                return true;
+            }
             session.set_terminated();
-            let mut space = trace_write_lock!(address_space);
-            let diagnostics = trace_write_lock!(session.session_diagnostics);
-            diagnostics.deregister_session(&session, &mut space);
+            session.deregister_session(address_space.clone());
+            false
+        });
+    }
+
+    /// Terminates the session with the session id and deregisters it, as if its client had closed
+    /// it. Returns false if there is no such session.
+    pub fn close_session(
+        &mut self,
+        session_id: &NodeId,
+        address_space: Arc<RwLock<AddressSpace>>,
+    ) -> bool {
+        if let Some(session) = self.sessions.remove(session_id) {
+            let mut session = trace_write_lock!(session);
+            info!("Session {} is being closed by the server", session_id);
+            session.set_authentication_token(NodeId::null());
+            session.set_user_identity(IdentityToken::None);
+            session.set_activated(false);
+            session.set_terminated();
+            session.deregister_session(address_space);
+            self.sessions_terminated = self.sessions.is_empty();
+            true
+        } else {
+            false
         }
     }
 
@@ -168,6 +207,18 @@ pub struct Session {
     max_response_message_size: u32,
     /// Endpoint url for this session
     endpoint_url: UAString,
+    /// Description of the client application (supplied by client)
+    client_description: ApplicationDescription,
+    /// Server uri requested by the client
+    server_uri: UAString,
+    /// Security mode of the secure channel the session was created on
+    security_mode: MessageSecurityMode,
+    /// Credentials of the client process, when it connected through a Unix domain socket
+    peer_credentials: Option<PeerCredentials>,
+    /// Time the session was created
+    client_connection_time: DateTimeUtc,
+    /// Users that have activated the session, oldest to newest
+    client_user_id_history: Vec<UAString>,
     /// Maximum number of continuation points
     max_browse_continuation_points: usize,
     /// Browse continuation points (oldest to newest)
@@ -191,12 +242,22 @@ pub struct Session {
     last_service_request_timestamp: DateTimeUtc,
     /// Subscriptions associated with the session
     subscriptions: Subscriptions,
+    /// Durable subscriptions of the server, which keep those of the session when it ends
+    durable_subscriptions: Arc<Mutex<DurableSubscriptions>>,
+    /// Sends the durable subscriptions of the session to the durable subscriptions when it ends
+    detach_sender: mpsc::Sender<Subscription>,
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
         info!("Session is being dropped");
+        // The durable subscriptions timer keeps and saves them, so the drop does no I/O and does
+        // not wait for the lock of the durable subscriptions
+        for subscription in self.subscriptions.remove_durable() {
+            let _ = self.detach_sender.send(subscription);
+        }
         let mut diagnostics = trace_write_lock!(self.diagnostics);
+        diagnostics.on_publish_requests_changed(0, self.subscriptions.publish_request_queue_len());
         diagnostics.on_destroy_session(self);
     }
 }
@@ -205,6 +266,12 @@ impl Session {
     #[cfg(test)]
     pub fn new_no_certificate_store() -> Session {
         let max_browse_continuation_points = super::constants::MAX_BROWSE_CONTINUATION_POINTS;
+        let durable_subscriptions = DurableSubscriptions::new(
+            None,
+            super::constants::DEFAULT_MAX_DURABLE_SUBSCRIPTION_LIFETIME_HOURS,
+        );
+        let detach_sender = durable_subscriptions.detach_sender();
+        let durable_subscriptions = Arc::new(Mutex::new(durable_subscriptions));
         let session = Session {
             subscriptions: Subscriptions::new(100, PUBLISH_REQUEST_TIMEOUT),
             session_id: next_session_id(),
@@ -224,12 +291,20 @@ impl Session {
             max_request_message_size: 0,
             max_response_message_size: 0,
             endpoint_url: UAString::null(),
+            client_description: ApplicationDescription::default(),
+            server_uri: UAString::null(),
+            security_mode: MessageSecurityMode::None,
+            peer_credentials: None,
+            client_connection_time: Utc::now(),
+            client_user_id_history: Vec::new(),
             max_browse_continuation_points,
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
             can_modify_address_space: true,
             diagnostics: Arc::new(RwLock::new(ServerDiagnostics::default())),
             session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
             last_service_request_timestamp: Utc::now(),
+            durable_subscriptions,
+            detach_sender,
         };
 
         {
@@ -246,6 +321,8 @@ impl Session {
         let server_state = trace_read_lock!(server_state);
         let max_subscriptions = server_state.max_subscriptions;
         let diagnostics = server_state.diagnostics.clone();
+        let durable_subscriptions = server_state.durable_subscriptions.clone();
+        let detach_sender = trace_lock!(durable_subscriptions).detach_sender();
         let can_modify_address_space = {
             let config = trace_read_lock!(server_state.config);
             config.limits.clients_can_modify_address_space
@@ -270,12 +347,20 @@ impl Session {
             max_request_message_size: 0,
             max_response_message_size: 0,
             endpoint_url: UAString::null(),
+            client_description: ApplicationDescription::default(),
+            server_uri: UAString::null(),
+            security_mode: MessageSecurityMode::None,
+            peer_credentials: None,
+            client_connection_time: Utc::now(),
+            client_user_id_history: Vec::new(),
             max_browse_continuation_points,
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
             can_modify_address_space,
             diagnostics,
             session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
             last_service_request_timestamp: Utc::now(),
+            durable_subscriptions,
+            detach_sender,
         };
         {
             let mut diagnostics = trace_write_lock!(session.diagnostics);
@@ -338,6 +423,10 @@ impl Session {
         self.max_request_message_size = max_request_message_size;
     }
 
+    pub fn max_response_message_size(&self) -> u32 {
+        self.max_response_message_size
+    }
+
     pub fn set_max_response_message_size(&mut self, max_response_message_size: u32) {
         self.max_response_message_size = max_response_message_size;
     }
@@ -356,6 +445,49 @@ impl Session {
 
     pub fn set_user_identity(&mut self, user_identity: IdentityToken) {
         self.user_identity = user_identity;
+        let client_user_id = self.client_user_id();
+        if !client_user_id.is_null() && self.client_user_id_history.last() != Some(&client_user_id)
+        {
+            self.client_user_id_history.push(client_user_id);
+        }
+    }
+
+    pub fn client_description(&self) -> &ApplicationDescription {
+        &self.client_description
+    }
+
+    pub fn set_client_description(&mut self, client_description: ApplicationDescription) {
+        self.client_description = client_description;
+    }
+
+    pub fn server_uri(&self) -> &UAString {
+        &self.server_uri
+    }
+
+    pub fn set_server_uri(&mut self, server_uri: UAString) {
+        self.server_uri = server_uri;
+    }
+
+    pub fn client_connection_time(&self) -> DateTimeUtc {
+        self.client_connection_time
+    }
+
+    pub fn security_mode(&self) -> MessageSecurityMode {
+        self.security_mode
+    }
+
+    pub fn set_security_mode(&mut self, security_mode: MessageSecurityMode) {
+        self.security_mode = security_mode;
+    }
+
+    /// Returns the credentials of the client process, if the session was created or last
+    /// activated through a Unix domain socket
+    pub fn peer_credentials(&self) -> Option<&PeerCredentials> {
+        self.peer_credentials.as_ref()
+    }
+
+    pub fn set_peer_credentials(&mut self, peer_credentials: Option<PeerCredentials>) {
+        self.peer_credentials = peer_credentials;
     }
 
     pub fn last_service_request_timestamp(&self) -> DateTimeUtc {
@@ -413,6 +545,10 @@ impl Session {
         &mut self.subscriptions
     }
 
+    pub(crate) fn durable_subscriptions(&self) -> &Arc<Mutex<DurableSubscriptions>> {
+        &self.durable_subscriptions
+    }
+
     pub(crate) fn enqueue_publish_request(
         &mut self,
         now: &DateTimeUtc,
@@ -420,8 +556,9 @@ impl Session {
         request: PublishRequest,
         address_space: &AddressSpace,
     ) -> Result<(), StatusCode> {
-        self.subscriptions
-            .enqueue_publish_request(now, request_id, request, address_space)
+        self.track_publish_requests(|subscriptions| {
+            subscriptions.enqueue_publish_request(now, request_id, request, address_space)
+        })
     }
 
     pub(crate) fn tick_subscriptions(
@@ -430,7 +567,26 @@ impl Session {
         address_space: &AddressSpace,
         reason: TickReason,
     ) -> Result<(), StatusCode> {
-        self.subscriptions.tick(now, address_space, reason)
+        self.track_publish_requests(|subscriptions| subscriptions.tick(now, address_space, reason))
+    }
+
+    /// Calls the function on the subscriptions, telling the server diagnostics if the number of
+    /// queued publish requests changes
+    fn track_publish_requests<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Subscriptions) -> T,
+    {
+        let previous_len = self.subscriptions.publish_request_queue_len();
+        let result = f(&mut self.subscriptions);
+        let len = self.subscriptions.publish_request_queue_len();
+        if len != previous_len {
+            let mut diagnostics = trace_write_lock!(self.diagnostics);
+            diagnostics.on_publish_requests_changed(
+                len.saturating_sub(previous_len),
+                previous_len.saturating_sub(len),
+            );
+        }
+        result
     }
 
     /// Reset the lifetime counter on the subscription, e.g. because a service references the
@@ -444,7 +600,16 @@ impl Session {
     /// Iterates through the existing queued publish requests and creates a timeout
     /// publish response any that have expired.
     pub(crate) fn expire_stale_publish_requests(&mut self, now: &DateTimeUtc) {
-        self.subscriptions.expire_stale_publish_requests(now);
+        self.track_publish_requests(|subscriptions| {
+            subscriptions.expire_stale_publish_requests(now)
+        });
+    }
+
+    /// Answers every queued publish request with a service fault of the supplied status code.
+    pub(crate) fn cancel_publish_requests(&mut self, status_code: StatusCode) {
+        self.track_publish_requests(|subscriptions| {
+            subscriptions.cancel_publish_requests(status_code)
+        });
     }
 
     pub(crate) fn add_browse_continuation_point(
@@ -535,6 +700,8 @@ impl Session {
                     UAString::from("Invalid certificate")
                 }
             }
+            // The token data may be encrypted so the user is not known from it
+            IdentityToken::IssuedIdentityToken(_) => UAString::null(),
             IdentityToken::Invalid(_) => UAString::from("invalid"),
         }
     }
@@ -547,15 +714,61 @@ impl Session {
         self.terminate_session = true;
     }
 
-    pub(crate) fn register_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
+    /// Makes the SessionDiagnostics of the session
+    pub(crate) fn session_diagnostics_data(&self) -> SessionDiagnosticsDataType {
         let session_diagnostics = trace_read_lock!(self.session_diagnostics);
+        session_diagnostics.session_diagnostics_data(self)
+    }
+
+    /// Makes the SessionSecurityDiagnostics of the session
+    pub(crate) fn session_security_diagnostics_data(&self) -> SessionSecurityDiagnosticsDataType {
+        let authentication_mechanism = match self.user_identity {
+            IdentityToken::None | IdentityToken::AnonymousIdentityToken(_) => "Anonymous",
+            IdentityToken::UserNameIdentityToken(_) => "UserName",
+            IdentityToken::X509IdentityToken(_) => "Certificate",
+            IdentityToken::IssuedIdentityToken(_) => "IssuedToken",
+            IdentityToken::Invalid(_) => "Invalid",
+        };
+        SessionSecurityDiagnosticsDataType {
+            session_id: self.session_id.clone(),
+            client_user_id_of_session: self.client_user_id(),
+            client_user_id_history: Some(self.client_user_id_history.clone()),
+            authentication_mechanism: UAString::from(authentication_mechanism),
+            encoding: UAString::from("UA Binary"),
+            transport_protocol: UAString::from(profiles::TRANSPORT_PROFILE_URI_BINARY),
+            security_mode: self.security_mode,
+            security_policy_uri: UAString::from(&self.security_policy_uri),
+            client_certificate: self
+                .client_certificate
+                .as_ref()
+                .map(|cert| cert.as_byte_string())
+                .unwrap_or_else(ByteString::null),
+        }
+    }
+
+    /// Adds the diagnostics of a new session to the address space, if diagnostics are enabled
+    pub(crate) fn register_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
         let mut address_space = trace_write_lock!(address_space);
-        session_diagnostics.register_session(self, &mut address_space);
+        self.update_diagnostics(&mut address_space);
     }
 
+    /// Removes the diagnostics of the session from the address space
     pub(crate) fn deregister_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
-        let session_diagnostics = trace_read_lock!(self.session_diagnostics);
         let mut address_space = trace_write_lock!(address_space);
-        session_diagnostics.deregister_session(self, &mut address_space);
+        session_diagnostics::remove_session(
+            &self.session_id,
+            &mut address_space,
+            &self.diagnostics,
+        );
+    }
+
+    /// Updates the diagnostics of the session in the address space after it handled a request or
+    /// published, if diagnostics are enabled
+    pub(crate) fn update_diagnostics(&self, address_space: &mut AddressSpace) {
+        session_diagnostics::update_session(self, address_space, &self.diagnostics);
+    }
+
+    pub(crate) fn server_diagnostics(&self) -> &Arc<RwLock<ServerDiagnostics>> {
+        &self.diagnostics
     }
 }
@@ -1,10 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::types::{node_ids::ObjectTypeId, service_types::ServiceCounterDataType};
+use crate::types::{
+    node_ids::{DataTypeId, ObjectId, ObjectTypeId, ReferenceTypeId, VariableId, VariableTypeId},
+    service_types::{
+        SamplingIntervalDiagnosticsDataType, ServiceCounterDataType, SessionDiagnosticsDataType,
+        SessionSecurityDiagnosticsDataType, SubscriptionDiagnosticsDataType,
+    },
+    *,
+};
+
+use crate::sync::*;
 
 use super::{
-    address_space::{address_space::AddressSpace, object::ObjectBuilder},
-    session::Session,
+    address_space::{
+        address_space::AddressSpace, object::ObjectBuilder, variable::VariableBuilder,
+    },
+    diagnostics::ServerDiagnostics,
+    session::{Session, SessionManager},
 };
 
 /// This object tracks session diagnostics for exposure through the address space
@@ -16,103 +28,100 @@ pub(crate) struct SessionDiagnostics {
     service_counters: HashMap<&'static str, ServiceCounterDataType>,
 }
 
-impl SessionDiagnostics {
-    /// Registers a session object
-    pub(crate) fn register_session(&self, session: &Session, address_space: &mut AddressSpace) {
-        // TODO SessionDiagnosticsObjectType
-
-        let session_id = session.session_id();
-        debug!("register_session for session id {}", session_id);
-
-        debug!("Adding an object node for the session id {}", session_id);
-        let _ = ObjectBuilder::new(
-            session_id,
-            format!("{}", session_id),
-            format!("{}", session_id),
-        )
-        .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
-        .insert(address_space);
+/// A snapshot of the diagnostics of a session and its subscriptions, kept by the server
+/// diagnostics so the address space can be updated without holding the lock of every session.
+#[derive(Debug, Clone)]
+pub(crate) struct SessionDiagnosticsSnapshot {
+    pub session_id: NodeId,
+    pub session_name: UAString,
+    pub session_diagnostics: SessionDiagnosticsDataType,
+    pub session_security_diagnostics: SessionSecurityDiagnosticsDataType,
+    pub subscription_diagnostics: Vec<SubscriptionDiagnosticsDataType>,
+    /// The number of monitored items of the session per sampling interval, with the number of
+    /// those that are disabled
+    pub sampling_interval_counts: BTreeMap<u64, (u32, u32)>,
+}
 
-        // Now add variables
-        /*
-             12816 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics),
-           12817 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionId),
-           12818 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionName),
-           12819 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientDescription),
-           12820 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ServerUri),
-           12821 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_EndpointUrl),
-           12822 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_LocaleIds),
-           12823 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ActualSessionTimeout),
-           12824 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_MaxResponseMessageSize),
-           12825 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientConnectionTime),
-           12826 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientLastContactTime),
-           12827 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentSubscriptionsCount),
-           12828 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentMonitoredItemsCount),
-           12829 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentPublishRequestsInQueue),
-
-           12830 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TotalRequestCount),
-           12831 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnauthorizedRequestCount),
-
-           12832 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ReadCount),
-           12833 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryReadCount),
-           12834 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_WriteCount),
-           12835 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryUpdateCount),
-           12836 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CallCount),
-           12837 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateMonitoredItemsCount),
-           12838 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifyMonitoredItemsCount),
-           12839 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetMonitoringModeCount),
-           12840 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetTriggeringCount),
-           12841 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteMonitoredItemsCount),
-           12842 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateSubscriptionCount),
-           12843 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifySubscriptionCount),
-           12844 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetPublishingModeCount),
-           12845 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_PublishCount),
-           12846 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RepublishCount),
-           12847 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TransferSubscriptionsCount),
-           12848 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteSubscriptionsCount),
-           12849 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddNodesCount),
-           12850 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddReferencesCount),
-           12851 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteNodesCount),
-           12852 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteReferencesCount),
-           12853 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseCount),
-           12854 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseNextCount),
-           12855 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TranslateBrowsePathsToNodeIdsCount),
-           12856 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryFirstCount),
-           12857 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryNextCount),
-           12858 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RegisterNodesCount),
-           12859 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnregisterNodesCount),
-        */
-
-        // Browse name shall be session name
-        // session id is the nodeid
-
-        // SessionDiagnostics - SessionDiagnosticsDataType
-        //   SessionId - NodeId
-        //   SessionName - String
-        //   ClientDescription - Application Description
-        //   ServerUri - String
-        //   EndpointUrl - String
-        //   LocaleId - LocaleId[]
-        //   MaxResponseMessageSize - UInt32
-        //   ActualSessionTimeout - Duration
-        //   ClientConnectionTime - UtcTime
-        //   ClientLastContactTime - UtcTime
-        //   CurrentSubscriptionsCount - UInt32
-        //   CurrentMonitoredItemsCount - UInt32
-        //   CurrentPublishRequestsInQueue - UInt32
-        //   TotalRequestCount - ServiceCounterData
-        //   UnauthorizedRequestCount - UInt32
-        //   ReadCount - ServiceCounterData
-        //   HistoryReadCount - ServiceCounterData
-        //   WriteCount - ServiceCounterData
-        //   HistoryUpdateCount
-        // SessionSecurityDiagnostics - SessionSecurityDiagnosticDataType
-        // SeubscriptionDiagnosticsArray - SubscriptionDiagnosticsArray
-    }
-
-    /// Deregisters a session object
-    pub(crate) fn deregister_session(&self, session: &Session, address_space: &mut AddressSpace) {
-        address_space.delete(session.session_id(), true);
+impl SessionDiagnosticsSnapshot {
+    pub fn new(session: &Session) -> Self {
+        let mut sampling_interval_counts = BTreeMap::new();
+        session
+            .subscriptions()
+            .sampling_interval_counts(&mut sampling_interval_counts);
+        SessionDiagnosticsSnapshot {
+            session_id: session.session_id().clone(),
+            session_name: session.session_name().clone(),
+            session_diagnostics: session.session_diagnostics_data(),
+            session_security_diagnostics: session.session_security_diagnostics_data(),
+            subscription_diagnostics: session.subscriptions().diagnostics(session.session_id()),
+            sampling_interval_counts,
+        }
+    }
+}
+
+impl SessionDiagnostics {
+    /// Makes the diagnostics of the session from the counters
+    pub(crate) fn session_diagnostics_data(&self, session: &Session) -> SessionDiagnosticsDataType {
+        let subscriptions = session.subscriptions();
+        let error_count = self
+            .service_counters
+            .values()
+            .fold(self.unauthorized_request_count, |error_count, counter| {
+                error_count + counter.error_count
+            });
+        SessionDiagnosticsDataType {
+            session_id: session.session_id().clone(),
+            session_name: session.session_name().clone(),
+            client_description: session.client_description().clone(),
+            server_uri: session.server_uri().clone(),
+            endpoint_url: session.endpoint_url().clone(),
+            locale_ids: session.locale_ids().clone(),
+            actual_session_timeout: session.session_timeout(),
+            max_response_message_size: session.max_response_message_size(),
+            client_connection_time: DateTime::from(session.client_connection_time()),
+            client_last_contact_time: DateTime::from(session.last_service_request_timestamp()),
+            current_subscriptions_count: subscriptions.len() as u32,
+            current_monitored_items_count: subscriptions
+                .subscriptions()
+                .values()
+                .map(|subscription| subscription.monitored_items_len() as u32)
+                .sum(),
+            current_publish_requests_in_queue: subscriptions.publish_request_queue_len() as u32,
+            total_request_count: ServiceCounterDataType {
+                total_count: self.total_request_count,
+                error_count,
+            },
+            unauthorized_request_count: self.unauthorized_request_count,
+            read_count: self.service_counter(READ_COUNT),
+            history_read_count: self.service_counter(HISTORY_READ_COUNT),
+            write_count: self.service_counter(WRITE_COUNT),
+            history_update_count: self.service_counter(HISTORY_UPDATE_COUNT),
+            call_count: self.service_counter(CALL_COUNT),
+            create_monitored_items_count: self.service_counter(CREATE_MONITORED_ITEMS_COUNT),
+            modify_monitored_items_count: self.service_counter(MODIFY_MONITORED_ITEMS_COUNT),
+            set_monitoring_mode_count: self.service_counter(SET_MONITORING_MODE_COUNT),
+            set_triggering_count: self.service_counter(SET_TRIGGERING_COUNT),
+            delete_monitored_items_count: self.service_counter(DELETE_MONITORED_ITEMS_COUNT),
+            create_subscription_count: self.service_counter(CREATE_SUBSCRIPTION_COUNT),
+            modify_subscription_count: self.service_counter(MODIFY_SUBSCRIPTION_COUNT),
+            set_publishing_mode_count: self.service_counter(SET_PUBLISHING_MODE_COUNT),
+            publish_count: ServiceCounterDataType::default(),
+            republish_count: self.service_counter(REPUBLISH_COUNT),
+            transfer_subscriptions_count: self.service_counter(TRANSFER_SUBSCRIPTIONS_COUNT),
+            delete_subscriptions_count: self.service_counter(DELETE_SUBSCRIPTIONS_COUNT),
+            add_nodes_count: self.service_counter(ADD_NODES_COUNT),
+            add_references_count: self.service_counter(ADD_REFERENCES_COUNT),
+            delete_nodes_count: self.service_counter(DELETE_NODES_COUNT),
+            delete_references_count: self.service_counter(DELETE_REFERENCES_COUNT),
+            browse_count: self.service_counter(BROWSE_COUNT),
+            browse_next_count: self.service_counter(BROWSE_NEXT_COUNT),
+            translate_browse_paths_to_node_ids_count: self
+                .service_counter(TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT),
+            query_first_count: ServiceCounterDataType::default(),
+            query_next_count: ServiceCounterDataType::default(),
+            register_nodes_count: self.service_counter(REGISTER_NODES_COUNT),
+            unregister_nodes_count: self.service_counter(UNREGISTER_NODES_COUNT),
+        }
     }
 
     /// Called on every request
@@ -127,15 +136,11 @@ impl SessionDiagnostics {
     }
 
     /// Fetches a snapshot of the current service counter value
-    pub(crate) fn service_counter(
-        &mut self,
-        diagnostic_key: &'static str,
-    ) -> ServiceCounterDataType {
-        if let Some(counter) = self.service_counters.get_mut(diagnostic_key) {
-            counter.clone()
-        } else {
-            ServiceCounterDataType::default()
-        }
+    pub(crate) fn service_counter(&self, diagnostic_key: &'static str) -> ServiceCounterDataType {
+        self.service_counters
+            .get(diagnostic_key)
+            .cloned()
+            .unwrap_or_default()
     }
 
     /// Increments the service counter for a successful service call
@@ -161,6 +166,360 @@ impl SessionDiagnostics {
     }
 }
 
+/// Makes the node id of a variable of a session object
+fn session_variable_node_id(session_id: &NodeId, name: &str) -> NodeId {
+    let session_id_str = match session_id.identifier {
+        Identifier::String(ref v) => v.to_string(),
+        ref identifier => identifier.to_string(),
+    };
+    NodeId::new(session_id.namespace, format!("{}.{}", session_id_str, name))
+}
+
+/// Makes the node id of the diagnostics variable of a subscription
+fn subscription_node_id(subscription_id: u32) -> NodeId {
+    NodeId::new(1, format!("Subscription-{}.Diagnostics", subscription_id))
+}
+
+fn extension_objects<T>(object_id: ObjectId, values: &[T]) -> Variant
+where
+    T: BinaryEncoder<T>,
+{
+    let values = values
+        .iter()
+        .map(|value| Variant::from(ExtensionObject::from_encodable(object_id, value)))
+        .collect::<Vec<Variant>>();
+    Variant::from((VariantTypeId::ExtensionObject, values))
+}
+
+/// Finds the nodes that are components of the node, ignoring those from the standard namespace
+fn dynamic_components_of(address_space: &AddressSpace, node_id: &NodeId) -> Vec<NodeId> {
+    address_space
+        .find_references(node_id, Some((ReferenceTypeId::HasComponent, false)))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|reference| reference.target_node)
+        .filter(|target_node| target_node.namespace != 0)
+        .collect()
+}
+
+/// Adds the object of a session, with its SessionDiagnostics, SessionSecurityDiagnostics and
+/// SubscriptionDiagnosticsArray variables, unless it already exists.
+fn add_session_nodes(
+    address_space: &mut AddressSpace,
+    session_id: &NodeId,
+    session_name: &UAString,
+) {
+    if address_space.node_exists(session_id) {
+        return;
+    }
+    // Browse name shall be the session name
+    let browse_name = if session_name.is_null() {
+        session_id.to_string()
+    } else {
+        session_name.to_string()
+    };
+    let _ = ObjectBuilder::new(session_id, browse_name.as_str(), browse_name.as_str())
+        .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
+        .component_of(ObjectId::Server_ServerDiagnostics_SessionsDiagnosticsSummary)
+        .insert(address_space);
+    let variables = [
+        (
+            "SessionDiagnostics",
+            DataTypeId::SessionDiagnosticsDataType,
+            VariableTypeId::SessionDiagnosticsVariableType,
+            -1,
+        ),
+        (
+            "SessionSecurityDiagnostics",
+            DataTypeId::SessionSecurityDiagnosticsDataType,
+            VariableTypeId::SessionSecurityDiagnosticsType,
+            -1,
+        ),
+        (
+            "SubscriptionDiagnosticsArray",
+            DataTypeId::SubscriptionDiagnosticsDataType,
+            VariableTypeId::SubscriptionDiagnosticsArrayType,
+            1,
+        ),
+    ];
+    for (name, data_type, type_definition, value_rank) in variables {
+        let _ = VariableBuilder::new(&session_variable_node_id(session_id, name), name, name)
+            .data_type(data_type)
+            .value_rank(value_rank)
+            .value(Variant::Empty)
+            .has_type_definition(type_definition)
+            .component_of(session_id.clone())
+            .insert(address_space);
+    }
+}
+
+/// Removes the object of a session, its variables and those of its subscriptions
+fn remove_session_nodes(address_space: &mut AddressSpace, session_id: &NodeId) {
+    address_space.delete(session_id, true);
+}
+
+/// Exposes the diagnostics of the session in the address space after it changed, e.g. because it
+/// handled a request or published. The diagnostics of every session are removed if diagnostics
+/// have been disabled since they were exposed.
+pub(crate) fn update_session(
+    session: &Session,
+    address_space: &mut AddressSpace,
+    diagnostics: &RwLock<ServerDiagnostics>,
+) {
+    if session.is_terminated() {
+        return;
+    }
+    expose(address_space, diagnostics, |diagnostics| {
+        if diagnostics.is_exposing_sessions() {
+            diagnostics.on_session_changed(SessionDiagnosticsSnapshot::new(session));
+        }
+    });
+}
+
+/// Removes the diagnostics of a session that has gone from the address space.
+pub(crate) fn remove_session(
+    session_id: &NodeId,
+    address_space: &mut AddressSpace,
+    diagnostics: &RwLock<ServerDiagnostics>,
+) {
+    expose(address_space, diagnostics, |diagnostics| {
+        diagnostics.on_session_removed(session_id)
+    });
+}
+
+/// Exposes the diagnostics of every session in the address space, if diagnostics have been
+/// enabled since they were last exposed. From then on, each session updates its own.
+pub(crate) fn expose_sessions(
+    session_manager: &RwLock<SessionManager>,
+    address_space: &RwLock<AddressSpace>,
+    diagnostics: &RwLock<ServerDiagnostics>,
+) {
+    if trace_read_lock!(diagnostics).is_exposing_sessions()
+        || !trace_read_lock!(address_space).is_diagnostics_enabled()
+    {
+        return;
+    }
+    // Snapshot the sessions without holding the address space lock, because session handlers
+    // lock the address space while holding the session
+    let sessions = {
+        let session_manager = trace_read_lock!(session_manager);
+        session_manager
+            .sessions
+            .values()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let sessions = sessions
+        .iter()
+        .filter_map(|session| {
+            let session = trace_read_lock!(session);
+            (!session.is_terminated()).then(|| SessionDiagnosticsSnapshot::new(&session))
+        })
+        .collect::<Vec<_>>();
+    let mut address_space = trace_write_lock!(address_space);
+    expose(&mut address_space, diagnostics, move |diagnostics| {
+        if !diagnostics.is_exposing_sessions() {
+            diagnostics.on_expose_sessions(sessions);
+        }
+    });
+}
+
+/// Removes the diagnostics of every session from the address space, if they are exposed. Called
+/// when diagnostics are disabled.
+pub(crate) fn hide_sessions(
+    address_space: &mut AddressSpace,
+    diagnostics: &RwLock<ServerDiagnostics>,
+) {
+    if trace_write_lock!(diagnostics).on_hide_sessions() {
+        clear_diagnostics(address_space);
+    }
+}
+
+/// Applies a change to the diagnostics of the sessions and writes them to the address space, or
+/// removes them if diagnostics have been disabled.
+fn expose<F>(address_space: &mut AddressSpace, diagnostics: &RwLock<ServerDiagnostics>, f: F)
+where
+    F: FnOnce(&mut ServerDiagnostics),
+{
+    if !address_space.is_diagnostics_enabled() {
+        hide_sessions(address_space, diagnostics);
+        return;
+    }
+    let mut diagnostics = trace_write_lock!(diagnostics);
+    f(&mut diagnostics);
+    if let Some((sessions, sampling_interval_diagnostics)) = diagnostics.exposed_sessions() {
+        update_diagnostics(address_space, &sessions, &sampling_interval_diagnostics);
+    }
+}
+
+/// Updates the session and subscription diagnostics in the address space from snapshots of the
+/// current sessions. Objects and variables are added for new sessions and subscriptions, and
+/// removed for those that have gone.
+fn update_diagnostics(
+    address_space: &mut AddressSpace,
+    sessions: &[&SessionDiagnosticsSnapshot],
+    sampling_interval_diagnostics: &[SamplingIntervalDiagnosticsDataType],
+) {
+    let now = DateTime::now();
+    let sessions_summary_id: NodeId =
+        ObjectId::Server_ServerDiagnostics_SessionsDiagnosticsSummary.into();
+    let server_subscriptions_id: NodeId =
+        VariableId::Server_ServerDiagnostics_SubscriptionDiagnosticsArray.into();
+
+    // Remove the objects of sessions that have gone
+    let session_ids = sessions
+        .iter()
+        .map(|session| &session.session_id)
+        .collect::<HashSet<_>>();
+    for node_id in dynamic_components_of(address_space, &sessions_summary_id) {
+        if !session_ids.contains(&node_id) {
+            remove_session_nodes(address_space, &node_id);
+        }
+    }
+
+    let mut subscription_ids = HashSet::new();
+    for session in sessions.iter() {
+        add_session_nodes(address_space, &session.session_id, &session.session_name);
+        let session_subscriptions_id =
+            session_variable_node_id(&session.session_id, "SubscriptionDiagnosticsArray");
+
+        for subscription in &session.subscription_diagnostics {
+            let node_id = subscription_node_id(subscription.subscription_id);
+            if !address_space.node_exists(&node_id) {
+                let name = format!("{}", subscription.subscription_id);
+                let _ = VariableBuilder::new(&node_id, name.as_str(), name.as_str())
+                    .data_type(DataTypeId::SubscriptionDiagnosticsDataType)
+                    .value(Variant::Empty)
+                    .has_type_definition(VariableTypeId::SubscriptionDiagnosticsType)
+                    .insert(address_space);
+                address_space.insert_reference(
+                    &server_subscriptions_id,
+                    &node_id,
+                    ReferenceTypeId::HasComponent,
+                );
+            }
+            // The subscription may have been transferred from another session
+            address_space.insert_reference(
+                &session_subscriptions_id,
+                &node_id,
+                ReferenceTypeId::HasComponent,
+            );
+            let value = ExtensionObject::from_encodable(
+                ObjectId::SubscriptionDiagnosticsDataType_Encoding_DefaultBinary,
+                subscription,
+            );
+            address_space.set_variable_value(node_id.clone(), value, &now, &now);
+            subscription_ids.insert(node_id);
+        }
+
+        // Subscriptions that left the session
+        let session_subscription_ids = session
+            .subscription_diagnostics
+            .iter()
+            .map(|subscription| subscription_node_id(subscription.subscription_id))
+            .collect::<HashSet<_>>();
+        for node_id in dynamic_components_of(address_space, &session_subscriptions_id) {
+            if !session_subscription_ids.contains(&node_id) {
+                address_space.delete_reference(
+                    &session_subscriptions_id,
+                    &node_id,
+                    ReferenceTypeId::HasComponent,
+                );
+            }
+        }
+
+        address_space.set_variable_value(
+            session_variable_node_id(&session.session_id, "SessionDiagnostics"),
+            ExtensionObject::from_encodable(
+                ObjectId::SessionDiagnosticsDataType_Encoding_DefaultBinary,
+                &session.session_diagnostics,
+            ),
+            &now,
+            &now,
+        );
+        address_space.set_variable_value(
+            session_variable_node_id(&session.session_id, "SessionSecurityDiagnostics"),
+            ExtensionObject::from_encodable(
+                ObjectId::SessionSecurityDiagnosticsDataType_Encoding_DefaultBinary,
+                &session.session_security_diagnostics,
+            ),
+            &now,
+            &now,
+        );
+        address_space.set_variable_value(
+            session_subscriptions_id,
+            extension_objects(
+                ObjectId::SubscriptionDiagnosticsDataType_Encoding_DefaultBinary,
+                &session.subscription_diagnostics,
+            ),
+            &now,
+            &now,
+        );
+    }
+
+    // Remove the variables of subscriptions that have gone
+    for node_id in dynamic_components_of(address_space, &server_subscriptions_id) {
+        if !subscription_ids.contains(&node_id) {
+            address_space.delete(&node_id, true);
+        }
+    }
+
+    let session_diagnostics = sessions
+        .iter()
+        .map(|session| session.session_diagnostics.clone())
+        .collect::<Vec<_>>();
+    let session_security_diagnostics = sessions
+        .iter()
+        .map(|session| session.session_security_diagnostics.clone())
+        .collect::<Vec<_>>();
+    let subscription_diagnostics = sessions
+        .iter()
+        .flat_map(|session| session.subscription_diagnostics.iter().cloned())
+        .collect::<Vec<_>>();
+    address_space.set_variable_value(
+        VariableId::Server_ServerDiagnostics_SessionsDiagnosticsSummary_SessionDiagnosticsArray,
+        extension_objects(
+            ObjectId::SessionDiagnosticsDataType_Encoding_DefaultBinary,
+            &session_diagnostics,
+        ),
+        &now,
+        &now,
+    );
+    address_space.set_variable_value(
+        VariableId::Server_ServerDiagnostics_SessionsDiagnosticsSummary_SessionSecurityDiagnosticsArray,
+        extension_objects(
+            ObjectId::SessionSecurityDiagnosticsDataType_Encoding_DefaultBinary,
+            &session_security_diagnostics,
+        ),
+        &now,
+        &now,
+    );
+    address_space.set_variable_value(
+        server_subscriptions_id,
+        extension_objects(
+            ObjectId::SubscriptionDiagnosticsDataType_Encoding_DefaultBinary,
+            &subscription_diagnostics,
+        ),
+        &now,
+        &now,
+    );
+    address_space.set_variable_value(
+        VariableId::Server_ServerDiagnostics_SamplingIntervalDiagnosticsArray,
+        extension_objects(
+            ObjectId::SamplingIntervalDiagnosticsDataType_Encoding_DefaultBinary,
+            sampling_interval_diagnostics,
+        ),
+        &now,
+        &now,
+    );
+}
+
+/// Removes the objects of the sessions and subscriptions and empties the diagnostics arrays, when
+/// diagnostics are disabled.
+fn clear_diagnostics(address_space: &mut AddressSpace) {
+    update_diagnostics(address_space, &[], &[]);
+}
+
 pub(crate) const READ_COUNT: &str = "ReadCount";
 pub(crate) const HISTORY_READ_COUNT: &str = "HistoryReadCount";
 pub(crate) const WRITE_COUNT: &str = "WriteCount";
@@ -4,24 +4,32 @@
 
 //! Provides server state information, such as status, configuration, running servers and so on.
 
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Instant,
+};
 
 use crate::core::prelude::*;
-use crate::crypto::{user_identity, PrivateKey, SecurityPolicy, X509};
+use crate::crypto::{
+    jwt::{Jwt, JwtVerificationKey},
+    user_identity, PrivateKey, SecurityPolicy, X509,
+};
 use crate::sync::*;
 use crate::types::{
     profiles,
     service_types::{
         ActivateSessionRequest, AnonymousIdentityToken, ApplicationDescription, ApplicationType,
-        EndpointDescription, RegisteredServer, ServerState as ServerStateType, SignatureData,
-        UserNameIdentityToken, UserTokenPolicy, UserTokenType, X509IdentityToken,
+        EndpointDescription, IssuedIdentityToken, RegisteredServer, ServerState as ServerStateType,
+        SignatureData, UserNameIdentityToken, UserTokenPolicy, UserTokenType, X509IdentityToken,
     },
     status_code::StatusCode,
 };
 
 use crate::server::{
     callbacks::{RegisterNodes, UnregisterNodes},
-    config::{ServerConfig, ServerEndpoint},
+    comms::transport::PeerCredentials,
+    config::{ServerConfig, ServerEndpoint, ServerListener},
     constants,
     diagnostics::ServerDiagnostics,
     events::{
@@ -30,11 +38,21 @@ use crate::server::{
     },
     historical::{HistoricalDataProvider, HistoricalEventProvider},
     identity_token::{
-        IdentityToken, POLICY_ID_ANONYMOUS, POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15,
-        POLICY_ID_USER_PASS_RSA_OAEP, POLICY_ID_X509,
+        IdentityToken, POLICY_ID_ANONYMOUS, POLICY_ID_ISSUED_JWT, POLICY_ID_USER_PASS_NONE,
+        POLICY_ID_USER_PASS_RSA_15, POLICY_ID_USER_PASS_RSA_OAEP, POLICY_ID_X509,
     },
+    subscriptions::{durable::DurableSubscriptions, sampling::SamplingSchedules},
 };
 
+/// A graceful shutdown of the server, requested through `Server::shutdown`.
+#[derive(Debug, Clone)]
+pub(crate) struct ServerShutdown {
+    /// The time at which the server closes its connections and exits
+    pub deadline: Instant,
+    /// The reason the server is shutting down, exposed through `ServerStatus.ShutdownReason`
+    pub reason: LocalizedText,
+}
+
 pub(crate) struct OperationalLimits {
     pub max_nodes_per_translate_browse_paths_to_node_ids: usize,
     pub max_nodes_per_read: usize,
@@ -91,6 +109,8 @@ pub struct ServerState {
     pub server_certificate: Option<X509>,
     /// Server private key
     pub server_pkey: Option<PrivateKey>,
+    /// Keys that the JWTs of the user tokens are verified with, by user token id
+    pub(crate) jwt_verification_keys: BTreeMap<String, JwtVerificationKey>,
     /// The next subscription id - subscriptions are shared across the whole server. Initial value
     /// is a random u32.
     pub last_subscription_id: u32,
@@ -100,10 +120,18 @@ pub struct ServerState {
     pub max_monitored_items_per_sub: usize,
     /// Maximum number of queued values in a monitored item, 0 means no limit (danger)
     pub max_monitored_item_queue_size: usize,
+    /// Maximum number of queued values in a monitored item of a durable subscription
+    pub max_durable_monitored_item_queue_size: usize,
     /// Minimum publishing interval (in millis)
     pub min_publishing_interval_ms: Duration,
     /// Minimum sampling interval (in millis)
     pub min_sampling_interval_ms: Duration,
+    /// Sampling schedules of the monitored items of all subscriptions
+    pub sampling_schedules: Arc<Mutex<SamplingSchedules>>,
+    /// Durable subscriptions whose session has ended
+    pub(crate) durable_subscriptions: Arc<Mutex<DurableSubscriptions>>,
+    /// Ids of the secure channels of the open connections
+    pub(crate) open_secure_channels: BTreeSet<u32>,
     /// Default keep alive count
     pub default_keep_alive_count: u32,
     /// Maxmimum keep alive count
@@ -116,6 +144,8 @@ pub struct ServerState {
     pub state: ServerStateType,
     /// Sets the abort flag that terminates the associated server
     pub abort: bool,
+    /// A graceful shutdown that is counting down, if any
+    pub(crate) shutdown: Option<ServerShutdown>,
     /// Audit log
     pub(crate) audit_log: Arc<RwLock<AuditLog>>,
     /// Diagnostic information
@@ -135,8 +165,10 @@ pub struct ServerState {
 }
 
 impl ServerState {
+    /// Returns the endpoint descriptions of the endpoints offered through the listener
     pub fn endpoints(
         &self,
+        listener: &ServerListener,
         endpoint_url: &UAString,
         transport_profile_uris: &Option<Vec<UAString>>,
     ) -> Option<Vec<EndpointDescription>> {
@@ -164,13 +196,12 @@ impl ServerState {
 
         let config = trace_read_lock!(self.config);
         if let Ok(hostname) = hostname_from_url(endpoint_url.as_ref()) {
-            if !hostname.eq_ignore_ascii_case(&config.tcp_config.host) {
-                debug!("Endpoint url \"{}\" hostname supplied by caller does not match server's hostname \"{}\"", endpoint_url, &config.tcp_config.host);
+            if !hostname.eq_ignore_ascii_case(&listener.host) {
+                debug!("Endpoint url \"{}\" hostname supplied by caller does not match server's hostname \"{}\"", endpoint_url, &listener.host);
             }
             let endpoints = config
-                .endpoints
-                .iter()
-                .map(|(_, e)| self.new_endpoint_description(&config, e, true))
+                .listener_endpoints(listener)
+                .map(|(_, e)| self.new_endpoint_description(&config, listener, e, true))
                 .collect();
             Some(endpoints)
         } else {
@@ -178,8 +209,10 @@ impl ServerState {
                 "Endpoint url \"{}\" is unrecognized, using default",
                 endpoint_url
             );
-            if let Some(e) = config.default_endpoint() {
-                Some(vec![self.new_endpoint_description(&config, e, true)])
+            if let Some(e) = config.default_endpoint(listener) {
+                Some(vec![
+                    self.new_endpoint_description(&config, listener, e, true)
+                ])
             } else {
                 Some(vec![])
             }
@@ -188,34 +221,35 @@ impl ServerState {
 
     pub fn endpoint_exists(
         &self,
+        listener: &ServerListener,
         endpoint_url: &str,
         security_policy: SecurityPolicy,
         security_mode: MessageSecurityMode,
     ) -> bool {
         let config = trace_read_lock!(self.config);
         config
-            .find_endpoint(endpoint_url, security_policy, security_mode)
+            .find_endpoint(listener, endpoint_url, security_policy, security_mode)
             .is_some()
     }
 
-    /// Make matching endpoint descriptions for the specified url.
-    /// If none match then None will be passed, therefore if Some is returned it will be guaranteed
-    /// to contain at least one result.
+    /// Make matching endpoint descriptions for the specified url from the endpoints offered
+    /// through the listener. If none match then None will be passed, therefore if Some is
+    /// returned it will be guaranteed to contain at least one result.
     pub fn new_endpoint_descriptions(
         &self,
+        listener: &ServerListener,
         endpoint_url: &str,
     ) -> Option<Vec<EndpointDescription>> {
         debug!("find_endpoint, url = {}", endpoint_url);
         let config = trace_read_lock!(self.config);
-        let base_endpoint_url = config.base_endpoint_url();
+        let base_endpoint_url = listener.base_endpoint_url();
         let endpoints: Vec<EndpointDescription> = config
-            .endpoints
-            .iter()
+            .listener_endpoints(listener)
             .filter(|&(_, e)| {
                 // Test end point's security_policy_uri and matching url
                 url_matches_except_host(&e.endpoint_url(&base_endpoint_url), endpoint_url)
             })
-            .map(|(_, e)| self.new_endpoint_description(&config, e, false))
+            .map(|(_, e)| self.new_endpoint_description(&config, listener, e, false))
             .collect();
         if endpoints.is_empty() {
             None
@@ -249,12 +283,32 @@ impl ServerState {
         UAString::null()
     }
 
+    /// The issuer endpoint url of a JWT user token policy is a JSON object that tells the client
+    /// which authorization service to obtain a token from (OPC UA Part 6, 6.5.1).
+    fn issuer_endpoint_url(config: &ServerConfig, endpoint: &ServerEndpoint) -> UAString {
+        endpoint
+            .user_token_ids
+            .iter()
+            .filter_map(|id| config.user_tokens.get(id))
+            .filter_map(|user_token| user_token.jwt.as_ref())
+            .find_map(|jwt| jwt.authority_url.as_ref().map(|url| (jwt, url)))
+            .map(|(jwt, authority_url)| {
+                let issuer = serde_json::json!({
+                    "ua:resourceId": jwt.audience,
+                    "ua:authorityUrl": authority_url,
+                    "ua:authorityProfileUri": "http://opcfoundation.org/UA/Authorization#OAuth2",
+                });
+                UAString::from(issuer.to_string())
+            })
+            .unwrap_or_else(UAString::null)
+    }
+
     fn user_identity_tokens(
         &self,
         config: &ServerConfig,
         endpoint: &ServerEndpoint,
     ) -> Vec<UserTokenPolicy> {
-        let mut user_identity_tokens = Vec::with_capacity(3);
+        let mut user_identity_tokens = Vec::with_capacity(4);
 
         // Anonymous policy
         if endpoint.supports_anonymous() {
@@ -287,6 +341,16 @@ impl ServerState {
                 security_policy_uri: UAString::from(SecurityPolicy::Basic128Rsa15.to_uri()),
             });
         }
+        // Issued token (JWT) policy
+        if endpoint.supports_issued_token(&config.user_tokens) {
+            user_identity_tokens.push(UserTokenPolicy {
+                policy_id: UAString::from(POLICY_ID_ISSUED_JWT),
+                token_type: UserTokenType::IssuedToken,
+                issued_token_type: UAString::from(profiles::ISSUED_TOKEN_TYPE_JWT),
+                issuer_endpoint_url: Self::issuer_endpoint_url(config, endpoint),
+                security_policy_uri: UAString::null(),
+            });
+        }
 
         if user_identity_tokens.is_empty() {
             debug!(
@@ -302,10 +366,11 @@ impl ServerState {
     fn new_endpoint_description(
         &self,
         config: &ServerConfig,
+        listener: &ServerListener,
         endpoint: &ServerEndpoint,
         all_fields: bool,
     ) -> EndpointDescription {
-        let base_endpoint_url = config.base_endpoint_url();
+        let base_endpoint_url = listener.base_endpoint_url();
 
         let user_identity_tokens = self.user_identity_tokens(config, endpoint);
 
@@ -391,6 +456,72 @@ impl ServerState {
         self.state == ServerStateType::Running
     }
 
+    /// Puts the server into the `Shutdown` state, counting down until the delay expires, after
+    /// which the connections are closed and the server aborts.
+    pub fn begin_shutdown(&mut self, delay: std::time::Duration, reason: LocalizedText) {
+        info!(
+            "Server will shut down in {}ms, reason = {}",
+            delay.as_millis(),
+            reason
+        );
+        self.state = ServerStateType::Shutdown;
+        self.shutdown = Some(ServerShutdown {
+            deadline: Instant::now() + delay,
+            reason,
+        });
+    }
+
+    /// Tests if the server is counting down to a graceful shutdown
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_some()
+    }
+
+    /// Tests if the delay of a graceful shutdown has expired, or if the server is aborting.
+    /// Connections are closed when this is true.
+    pub fn is_shutdown_due(&self) -> bool {
+        self.abort
+            || self
+                .shutdown
+                .as_ref()
+                .is_some_and(|shutdown| shutdown.deadline <= Instant::now())
+    }
+
+    /// Tests if the connections have had long enough to close after the delay of a graceful
+    /// shutdown expired, so the server should abort regardless.
+    pub(crate) fn is_shutdown_overdue(&self) -> bool {
+        self.shutdown.as_ref().is_some_and(|shutdown| {
+            let close_timeout =
+                std::time::Duration::from_millis(constants::SHUTDOWN_CLOSE_TIMEOUT_MS);
+            shutdown.deadline + close_timeout <= Instant::now()
+        })
+    }
+
+    /// Returns the number of seconds until the server shuts down, or 0 if it isn't shutting down
+    pub fn seconds_till_shutdown(&self) -> u32 {
+        self.shutdown.as_ref().map_or(0, |shutdown| {
+            let remaining = shutdown
+                .deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis();
+            // Round up so the count only reaches 0 when the delay has expired
+            remaining.div_ceil(1000) as u32
+        })
+    }
+
+    /// Returns the reason for the shutdown, or null text if the server isn't shutting down
+    pub fn shutdown_reason(&self) -> LocalizedText {
+        self.shutdown
+            .as_ref()
+            .map(|shutdown| shutdown.reason.clone())
+            .unwrap_or_else(LocalizedText::null)
+    }
+
+    /// Tests if the server tasks should carry on working, i.e. the server is running or is
+    /// counting down to a graceful shutdown and has not aborted yet.
+    pub fn is_serving(&self) -> bool {
+        !self.abort && (self.is_running() || self.is_shutting_down())
+    }
+
     pub fn server_certificate_as_byte_string(&self) -> ByteString {
         if let Some(ref server_certificate) = self.server_certificate {
             server_certificate.as_byte_string()
@@ -425,25 +556,35 @@ impl ServerState {
         self.last_subscription_id
     }
 
-    /// Authenticates access to an endpoint. The endpoint is described by its path, policy, mode and
-    /// the token is supplied in an extension object that must be extracted and authenticated.
+    /// Authenticates access to an endpoint offered through the listener. The endpoint is described
+    /// by its path, policy, mode and the token is supplied in an extension object of the request
+    /// that must be extracted and authenticated.
     ///
     /// It is possible that the endpoint does not exist, or that the token is invalid / unsupported
     /// or that the token cannot be used with the end point. The return codes reflect the responses
     /// that ActivateSession would expect from a service call.
+    ///
+    /// The certificate and private key are those of the secure channel the session was activated
+    /// through, which may predate a renewal of the server's own.
+    #[allow(clippy::too_many_arguments)]
     pub fn authenticate_endpoint(
         &self,
+        listener: &ServerListener,
         request: &ActivateSessionRequest,
         endpoint_url: &str,
         security_policy: SecurityPolicy,
         security_mode: MessageSecurityMode,
-        user_identity_token: &ExtensionObject,
+        server_certificate: Option<&X509>,
+        server_key: Option<&PrivateKey>,
         server_nonce: &ByteString,
     ) -> Result<String, StatusCode> {
+        let user_identity_token = &request.user_identity_token;
         // Get security from endpoint url
         let config = trace_read_lock!(self.config);
 
-        if let Some(endpoint) = config.find_endpoint(endpoint_url, security_policy, security_mode) {
+        if let Some(endpoint) =
+            config.find_endpoint(listener, endpoint_url, security_policy, security_mode)
+        {
             // Now validate the user identity token
             match IdentityToken::new(user_identity_token, &self.decoding_options()) {
                 IdentityToken::None => {
@@ -458,7 +599,7 @@ impl ServerState {
                         &config,
                         endpoint,
                         &token,
-                        &self.server_pkey,
+                        server_key,
                         server_nonce,
                     ),
                 IdentityToken::X509IdentityToken(token) => self.authenticate_x509_identity_token(
@@ -466,9 +607,17 @@ impl ServerState {
                     endpoint,
                     &token,
                     &request.user_token_signature,
-                    &self.server_certificate,
+                    server_certificate,
                     server_nonce,
                 ),
+                IdentityToken::IssuedIdentityToken(token) => self
+                    .authenticate_issued_identity_token(
+                        &config,
+                        endpoint,
+                        &token,
+                        server_key,
+                        server_nonce,
+                    ),
                 IdentityToken::Invalid(o) => {
                     error!("User identity token type {:?} is unsupported", o.node_id);
                     Err(StatusCode::BadIdentityTokenInvalid)
@@ -480,6 +629,25 @@ impl ServerState {
         }
     }
 
+    /// Authenticates the client process at the other end of the connection against the peer
+    /// uids / gids that the listener allows. Only Unix domain socket connections have credentials.
+    pub fn authenticate_peer(
+        &self,
+        listener: &ServerListener,
+        peer_credentials: Option<&PeerCredentials>,
+    ) -> Result<(), StatusCode> {
+        if listener.accepts_peer(peer_credentials) {
+            Ok(())
+        } else {
+            error!(
+                "Listener {} rejected the client process, credentials {:?}",
+                listener.base_endpoint_url(),
+                peer_credentials
+            );
+            Err(StatusCode::BadUserAccessDenied)
+        }
+    }
+
     pub fn set_register_nodes_callbacks(
         &mut self,
         register_nodes_callback: Box<dyn RegisterNodes + Send + Sync>,
@@ -522,7 +690,7 @@ impl ServerState {
         config: &ServerConfig,
         endpoint: &ServerEndpoint,
         token: &UserNameIdentityToken,
-        server_key: &Option<PrivateKey>,
+        server_key: Option<&PrivateKey>,
         server_nonce: &ByteString,
     ) -> Result<String, StatusCode> {
         if !endpoint.supports_user_pass(&config.user_tokens) {
@@ -541,7 +709,7 @@ impl ServerState {
                 token.encryption_algorithm.as_ref()
             );
             let token_password = if !token.encryption_algorithm.is_null() {
-                if let Some(ref server_key) = server_key {
+                if let Some(server_key) = server_key {
                     user_identity::decrypt_user_identity_token_password(
                         token,
                         server_nonce.as_ref(),
@@ -561,16 +729,7 @@ impl ServerState {
                     if server_user_token.is_user_pass()
                         && server_user_token.user == token.user_name.as_ref()
                     {
-                        // test for empty password
-                        let valid = if server_user_token.pass.is_none() {
-                            // Empty password for user
-                            token_password.is_empty()
-                        } else {
-                            // Password compared as UTF-8 bytes
-                            let server_password =
-                                server_user_token.pass.as_ref().unwrap().as_bytes();
-                            server_password == token_password.as_bytes()
-                        };
+                        let valid = server_user_token.verify_password(token_password.as_bytes());
                         if !valid {
                             error!(
                                 "Cannot authenticate \"{}\", password is invalid",
@@ -599,7 +758,7 @@ impl ServerState {
         endpoint: &ServerEndpoint,
         token: &X509IdentityToken,
         user_token_signature: &SignatureData,
-        server_certificate: &Option<X509>,
+        server_certificate: Option<&X509>,
         server_nonce: &ByteString,
     ) -> Result<String, StatusCode> {
         if !endpoint.supports_x509(&config.user_tokens) {
@@ -610,7 +769,7 @@ impl ServerState {
             Err(StatusCode::BadIdentityTokenRejected)
         } else {
             let result = match server_certificate {
-                Some(ref server_certificate) => {
+                Some(server_certificate) => {
                     // Find the security policy used for verifying tokens
                     let user_identity_tokens = self.user_identity_tokens(config, endpoint);
                     let security_policy = user_identity_tokens
@@ -657,6 +816,78 @@ impl ServerState {
         }
     }
 
+    /// Authenticates the issued identity token, a JSON Web Token, against the endpoint. The function returns the user
+    /// token identifier that matches the identity token.
+    fn authenticate_issued_identity_token(
+        &self,
+        config: &ServerConfig,
+        endpoint: &ServerEndpoint,
+        token: &IssuedIdentityToken,
+        server_key: Option<&PrivateKey>,
+        server_nonce: &ByteString,
+    ) -> Result<String, StatusCode> {
+        if !endpoint.supports_issued_token(&config.user_tokens) {
+            error!("Endpoint doesn't support issued tokens");
+            Err(StatusCode::BadIdentityTokenRejected)
+        } else if token.policy_id.as_ref() != POLICY_ID_ISSUED_JWT {
+            error!("Token doesn't possess the correct policy id");
+            Err(StatusCode::BadIdentityTokenInvalid)
+        } else {
+            let token_data = if !token.encryption_algorithm.is_null() {
+                if let Some(server_key) = server_key {
+                    user_identity::decrypt_issued_identity_token(
+                        token,
+                        server_nonce.as_ref(),
+                        server_key,
+                    )?
+                } else {
+                    error!("Issued token is encrypted but no server private key was supplied");
+                    return Err(StatusCode::BadIdentityTokenInvalid);
+                }
+            } else {
+                token
+                    .token_data
+                    .value
+                    .clone()
+                    .ok_or(StatusCode::BadIdentityTokenInvalid)?
+            };
+            let token_data =
+                String::from_utf8(token_data).map_err(|_| StatusCode::BadIdentityTokenInvalid)?;
+            let jwt = Jwt::decode(&token_data)?;
+            let now = chrono::Utc::now().timestamp();
+
+            // The token must be valid for one of the endpoint's JWT users
+            let mut result = Err(StatusCode::BadIdentityTokenRejected);
+            for user_token_id in &endpoint.user_token_ids {
+                if let Some(server_user_token) = config.user_tokens.get(user_token_id) {
+                    if let Some(ref jwt_config) = server_user_token.jwt {
+                        let Some(key) = self.jwt_verification_keys.get(user_token_id) else {
+                            continue;
+                        };
+                        match jwt_config.verify(key, &jwt, &server_user_token.user, now) {
+                            Ok(_) => return Ok(user_token_id.clone()),
+                            Err(StatusCode::BadUserAccessDenied) => {
+                                // Token is genuine but for someone else
+                                result = Err(StatusCode::BadUserAccessDenied);
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                }
+            }
+            error!("Cannot authenticate issued token, no user on the endpoint accepts it");
+            result
+        }
+    }
+
+    /// Loads the keys that the JWTs of the user tokens are verified with. They are loaded when
+    /// the server is created, so call this after changing the JWT settings of the configuration,
+    /// or to pick up a key file that changed.
+    pub fn reload_jwt_verification_keys(&mut self) {
+        let config = trace_read_lock!(self.config);
+        self.jwt_verification_keys = config.jwt_verification_keys();
+    }
+
     pub fn set_historical_data_provider(
         &mut self,
         historical_data_provider: Box<dyn HistoricalDataProvider + Send + Sync>,
@@ -675,7 +906,12 @@ impl ServerState {
     where
         T: AuditEvent + Event,
     {
-        let audit_log = trace_write_lock!(self.audit_log);
-        audit_log.raise_and_log(event)
+        let message = event.log_message();
+        let result = {
+            let audit_log = trace_write_lock!(self.audit_log);
+            audit_log.raise_and_log(event)
+        };
+        trace_write_lock!(self.diagnostics).on_audit_event(message);
+        result
     }
 }
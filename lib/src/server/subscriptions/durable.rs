@@ -0,0 +1,430 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Durable subscriptions. A subscription made durable with the SetSubscriptionDurable method
+//! outlives its session. When the session ends, the server keeps the subscription and its
+//! monitored items carry on queueing values, until a client of the same user transfers it to
+//! another session with TransferSubscriptions or its lifetime expires. If the server has a
+//! durable subscriptions file, the subscriptions are saved to it and restored when the server
+//! restarts.
+//!
+//! The subscriptions replicated from the other servers of a redundant server set are kept here
+//! as well, so a client that fails over to this server can transfer them.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+
+use crate::types::{
+    service_types::{MonitoringMode, NotificationMessage, ReadValueId, TimestampsToReturn},
+    status_code::StatusCode,
+    *,
+};
+
+use crate::sync::*;
+
+use crate::server::{
+    address_space::AddressSpace,
+    session::Session,
+    state::ServerState,
+    subscriptions::{monitored_item::Notification, subscription::Subscription},
+};
+
+/// A saved durable subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SubscriptionRecord {
+    pub subscription_id: u32,
+    /// The user the subscription belongs to
+    pub owner: UAString,
+    pub lifetime_in_hours: u32,
+    /// The maximum lifetime count, used when the subscription is not durable
+    #[serde(default)]
+    pub max_lifetime_count: u32,
+    /// The remaining lifetime count
+    pub lifetime_counter: u32,
+    pub publishing_interval: f64,
+    pub publishing_enabled: bool,
+    pub max_keep_alive_count: u32,
+    pub priority: u8,
+    pub last_sequence_number: u32,
+    pub next_monitored_item_id: u32,
+    pub monitored_items: Vec<MonitoredItemRecord>,
+    /// Notification messages that were waiting for a publish request
+    pub notifications: Vec<NotificationMessageRecord>,
+}
+
+/// A saved monitored item of a durable subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct MonitoredItemRecord {
+    pub monitored_item_id: u32,
+    pub item_to_monitor: ReadValueId,
+    pub monitoring_mode: MonitoringMode,
+    pub client_handle: u32,
+    pub sampling_interval: f64,
+    pub filter: ExtensionObject,
+    pub queue_size: usize,
+    pub discard_oldest: bool,
+    pub timestamps_to_return: TimestampsToReturn,
+    pub triggered_items: Vec<u32>,
+    /// The last value of the item, which later values are compared to
+    pub last_data_value: Option<DataValue>,
+    /// Notifications that were queued in the monitored item
+    pub notifications: Vec<Notification>,
+}
+
+/// A saved notification message of a durable subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NotificationMessageRecord {
+    pub sequence_number: u32,
+    pub publish_time: DateTime,
+    pub notification_data: Option<Vec<ExtensionObject>>,
+}
+
+impl From<&NotificationMessage> for NotificationMessageRecord {
+    fn from(notification: &NotificationMessage) -> Self {
+        NotificationMessageRecord {
+            sequence_number: notification.sequence_number,
+            publish_time: notification.publish_time,
+            notification_data: notification.notification_data.clone(),
+        }
+    }
+}
+
+impl From<NotificationMessageRecord> for NotificationMessage {
+    fn from(record: NotificationMessageRecord) -> Self {
+        NotificationMessage {
+            sequence_number: record.sequence_number,
+            publish_time: record.publish_time,
+            notification_data: record.notification_data,
+        }
+    }
+}
+
+/// The content of the durable subscriptions file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DurableSubscriptionsFile {
+    subscriptions: Vec<SubscriptionRecord>,
+}
+
+/// The durable subscriptions of the server whose session has ended
+pub struct DurableSubscriptions {
+    /// File the subscriptions are saved to
+    path: Option<PathBuf>,
+    /// Maximum lifetime in hours of a durable subscription, 0 if they are not supported
+    max_lifetime_in_hours: u32,
+    /// Subscriptions without a session
+    subscriptions: BTreeMap<u32, Subscription>,
+    /// Subscriptions loaded from the file that have not been restored yet
+    records: Vec<SubscriptionRecord>,
+    /// Durable subscriptions of the live sessions as of the last periodic save, so they are
+    /// restored if the server stops without closing its sessions
+    session_records: Vec<SubscriptionRecord>,
+    /// Subscriptions replicated from the other servers of a redundant server set, by the uri of
+    /// the server they come from
+    replicas: BTreeMap<String, Vec<SubscriptionRecord>>,
+    /// Ids of the replicated subscriptions that were transferred to a session of this server, and
+    /// the uri of the server they come from
+    transferred_replicas: BTreeMap<u32, String>,
+    /// Sends the durable subscriptions of a session that ends, see `detach_sender()`
+    detach_sender: mpsc::Sender<Subscription>,
+    /// Durable subscriptions of the sessions that ended which have not been kept yet
+    detached: mpsc::Receiver<Subscription>,
+    /// Set when durable subscriptions of sessions that ended were kept but not saved yet
+    detached_unsaved: bool,
+}
+
+impl DurableSubscriptions {
+    /// Creates the durable subscriptions, loading the subscriptions saved to the file if there
+    /// is one. They are restored by calling `restore()`.
+    pub fn new(path: Option<PathBuf>, max_lifetime_in_hours: u32) -> Self {
+        let records = path
+            .as_ref()
+            .filter(|path| path.exists())
+            .map(|path| match Self::load(path) {
+                Ok(file) => {
+                    info!(
+                        "Loaded {} durable subscriptions from {}",
+                        file.subscriptions.len(),
+                        path.display()
+                    );
+                    file.subscriptions
+                }
+                Err(err) => {
+                    error!(
+                        "Cannot load durable subscriptions from {}, {}",
+                        path.display(),
+                        err
+                    );
+                    Vec::new()
+                }
+            })
+            .unwrap_or_default();
+        let (detach_sender, detached) = mpsc::channel();
+        DurableSubscriptions {
+            path,
+            max_lifetime_in_hours,
+            subscriptions: BTreeMap::new(),
+            records,
+            session_records: Vec::new(),
+            replicas: BTreeMap::new(),
+            transferred_replicas: BTreeMap::new(),
+            detach_sender,
+            detached,
+            detached_unsaved: false,
+        }
+    }
+
+    fn load(path: &PathBuf) -> Result<DurableSubscriptionsFile, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())
+    }
+
+    /// Revises the requested lifetime in hours of a durable subscription. Returns `None` if
+    /// durable subscriptions are not supported.
+    pub fn revise_lifetime_in_hours(&self, lifetime_in_hours: u32) -> Option<u32> {
+        if self.max_lifetime_in_hours == 0 {
+            None
+        } else if lifetime_in_hours == 0 || lifetime_in_hours > self.max_lifetime_in_hours {
+            Some(self.max_lifetime_in_hours)
+        } else {
+            Some(lifetime_in_hours)
+        }
+    }
+
+    /// Tests if there is a durable or replicated subscription with the id waiting to be
+    /// transferred
+    pub fn contains(&self, subscription_id: u32) -> bool {
+        self.subscriptions.contains_key(&subscription_id) || self.is_replica(subscription_id)
+    }
+
+    /// Tests if there is a subscription with the id replicated from another server
+    pub fn is_replica(&self, subscription_id: u32) -> bool {
+        self.replicas.values().any(|records| {
+            records
+                .iter()
+                .any(|record| record.subscription_id == subscription_id)
+        })
+    }
+
+    /// Replaces the subscriptions replicated from another server of the redundant server set.
+    /// Subscriptions that were already transferred to this server are ignored.
+    pub(crate) fn set_replicas(&mut self, server_uri: &str, mut records: Vec<SubscriptionRecord>) {
+        // Forget the transferred subscriptions the other server no longer has
+        self.transferred_replicas.retain(|subscription_id, uri| {
+            uri != server_uri
+                || records
+                    .iter()
+                    .any(|record| record.subscription_id == *subscription_id)
+        });
+        records.retain(|record| {
+            !self
+                .transferred_replicas
+                .contains_key(&record.subscription_id)
+        });
+        debug!(
+            "Server {} replicated {} subscriptions",
+            server_uri,
+            records.len()
+        );
+        self.replicas.insert(server_uri.to_string(), records);
+    }
+
+    /// Takes a replicated subscription to transfer it to a session of the user it belongs to.
+    /// The subscription is made from the record by the caller.
+    pub(crate) fn transfer_replica(
+        &mut self,
+        subscription_id: u32,
+        owner: &UAString,
+    ) -> Result<SubscriptionRecord, StatusCode> {
+        for (server_uri, records) in self.replicas.iter_mut() {
+            let Some(idx) = records
+                .iter()
+                .position(|record| record.subscription_id == subscription_id)
+            else {
+                continue;
+            };
+            if records[idx].owner != *owner {
+                return Err(StatusCode::BadUserAccessDenied);
+            }
+            info!(
+                "Replicated subscription {} of server {} is transferred",
+                subscription_id, server_uri
+            );
+            self.transferred_replicas
+                .insert(subscription_id, server_uri.clone());
+            return Ok(records.remove(idx));
+        }
+        Err(StatusCode::BadSubscriptionIdInvalid)
+    }
+
+    /// Makes the records of the durable subscriptions whose session has ended, to replicate them
+    pub(crate) fn to_records(&self) -> Vec<SubscriptionRecord> {
+        self.subscriptions
+            .values()
+            .map(|subscription| subscription.to_durable_record())
+            .collect()
+    }
+
+    /// Keeps the durable subscriptions of a session that has ended
+    #[cfg(test)]
+    pub(crate) fn detach(&mut self, subscriptions: Vec<Subscription>) {
+        if !subscriptions.is_empty() {
+            self.keep(subscriptions);
+            self.save();
+        }
+    }
+
+    /// Returns the sender a session sends its durable subscriptions to when it is dropped, so
+    /// dropping it does not lock the durable subscriptions or write the file. They are kept the
+    /// next time the durable subscriptions are ticked or transferred.
+    pub(crate) fn detach_sender(&self) -> mpsc::Sender<Subscription> {
+        self.detach_sender.clone()
+    }
+
+    /// Keeps the durable subscriptions sent by the sessions that were dropped. They are saved
+    /// on the next tick.
+    pub(crate) fn receive_detached(&mut self) {
+        let subscriptions = self.detached.try_iter().collect::<Vec<_>>();
+        if !subscriptions.is_empty() {
+            self.keep(subscriptions);
+            self.detached_unsaved = true;
+        }
+    }
+
+    fn keep(&mut self, subscriptions: Vec<Subscription>) {
+        for subscription in subscriptions {
+            info!(
+                "Durable subscription {} is kept after its session ended",
+                subscription.subscription_id()
+            );
+            self.subscriptions
+                .insert(subscription.subscription_id(), subscription);
+        }
+    }
+
+    /// Takes a durable subscription to transfer it to a session of the user it belongs to
+    pub(crate) fn transfer(
+        &mut self,
+        subscription_id: u32,
+        owner: &UAString,
+    ) -> Result<Subscription, StatusCode> {
+        match self.subscriptions.get(&subscription_id) {
+            None => Err(StatusCode::BadSubscriptionIdInvalid),
+            Some(subscription) if !subscription.is_owned_by(owner) => {
+                Err(StatusCode::BadUserAccessDenied)
+            }
+            Some(_) => {
+                let subscription = self.subscriptions.remove(&subscription_id).unwrap();
+                // It belongs to a live session now
+                self.session_records.push(subscription.to_durable_record());
+                self.save();
+                Ok(subscription)
+            }
+        }
+    }
+
+    /// Restores the subscriptions loaded from the file. The subscription ids of the server
+    /// continue after the restored ids.
+    pub(crate) fn restore(&mut self, server_state: &mut ServerState, address_space: &AddressSpace) {
+        let now = chrono::Utc::now();
+        for record in std::mem::take(&mut self.records) {
+            let subscription_id = record.subscription_id;
+            if server_state.last_subscription_id < subscription_id {
+                server_state.last_subscription_id = subscription_id;
+            }
+            let subscription =
+                Subscription::from_durable_record(record, server_state, address_space, &now);
+            info!(
+                "Restored durable subscription {} with {} monitored items",
+                subscription_id,
+                subscription.monitored_items_len()
+            );
+            self.subscriptions.insert(subscription_id, subscription);
+        }
+    }
+
+    /// Ticks the durable subscriptions so their monitored items queue values, and drops those
+    /// whose lifetime expired.
+    pub(crate) fn tick(&mut self, now: &DateTimeUtc, address_space: &AddressSpace) {
+        self.receive_detached();
+        let len = self.subscriptions.len();
+        self.subscriptions.retain(|subscription_id, subscription| {
+            let alive = subscription.tick_detached(now, address_space);
+            if !alive {
+                info!("Durable subscription {} has expired", subscription_id);
+            }
+            alive
+        });
+        if self.detached_unsaved || self.subscriptions.len() != len {
+            self.detached_unsaved = false;
+            self.save();
+        }
+    }
+
+    /// Saves the subscriptions to the file along with the durable subscriptions of the live
+    /// sessions, which are made by `session_records()`.
+    pub(crate) fn save_with_sessions(&mut self, session_records: Vec<SubscriptionRecord>) {
+        self.session_records = session_records;
+        self.save();
+    }
+
+    /// Saves the subscriptions to the file, if there is one. The durable subscriptions of the
+    /// live sessions are those of the last `save_with_sessions()`.
+    pub fn save(&self) {
+        let Some(ref path) = self.path else {
+            return;
+        };
+        // A subscription whose session ended since the last periodic save is only saved once
+        let session_records = self
+            .session_records
+            .iter()
+            .filter(|record| !self.subscriptions.contains_key(&record.subscription_id))
+            .cloned();
+        let file = DurableSubscriptionsFile {
+            subscriptions: self
+                .subscriptions
+                .values()
+                .map(|subscription| subscription.to_durable_record())
+                .chain(self.records.iter().cloned())
+                .chain(session_records)
+                .collect(),
+        };
+        // Write to a temporary file first so a failed save does not lose the previous one
+        let tmp_path = path.with_extension("tmp");
+        let result = File::create(&tmp_path)
+            .map_err(|err| err.to_string())
+            .and_then(|f| {
+                serde_json::to_writer(BufWriter::new(f), &file).map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&tmp_path, path).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            error!(
+                "Cannot save durable subscriptions to {}, {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Makes the records of the durable subscriptions of the sessions, so they are saved with the
+/// durable subscriptions whose session has ended. The sessions are locked one at a time, so this
+/// must be called before the durable subscriptions are locked.
+pub(crate) fn session_records(sessions: &[Arc<RwLock<Session>>]) -> Vec<SubscriptionRecord> {
+    sessions
+        .iter()
+        .flat_map(|session| {
+            let session = trace_read_lock!(session);
+            session
+                .subscriptions()
+                .subscriptions()
+                .values()
+                .filter(|subscription| subscription.is_durable())
+                .map(|subscription| subscription.to_durable_record())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
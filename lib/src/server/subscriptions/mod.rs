@@ -34,6 +34,9 @@ fn duration_from_ms(d: f64) -> Duration {
     Duration::from_micros((d * 1000f64) as u64)
 }
 
+pub mod durable;
 pub mod monitored_item;
+pub(crate) mod node_changes;
+pub mod sampling;
 pub mod subscription;
 pub mod subscriptions;
@@ -4,7 +4,9 @@
 
 use std::collections::{BTreeSet, VecDeque};
 use std::result::Result;
+use std::sync::Arc;
 
+use crate::sync::*;
 use crate::types::{
     node_ids::ObjectId,
     service_types::{
@@ -19,9 +21,15 @@ use crate::server::{
     address_space::{node::Node, AddressSpace, EventNotifier},
     events::event_filter,
     state::ServerState,
+    subscriptions::{
+        durable::MonitoredItemRecord,
+        sampling::{
+            SampleListener, SampledItems, SampledValueQueue, SampledValues, SamplingSchedules,
+        },
+    },
 };
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Notification {
     MonitoredItemNotification(MonitoredItemNotification),
     Event(EventFieldList),
@@ -82,9 +90,24 @@ impl FilterType {
             Err(StatusCode::BadFilterNotAllowed)
         }
     }
+
+    /// Encodes the filter back into the extension object it was decoded from
+    pub fn to_filter(&self) -> ExtensionObject {
+        match self {
+            FilterType::None => ExtensionObject::null(),
+            FilterType::DataChangeFilter(filter) => ExtensionObject::from_encodable(
+                ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+                filter,
+            ),
+            FilterType::EventFilter(filter) => ExtensionObject::from_encodable(
+                ObjectId::EventFilter_Encoding_DefaultBinary,
+                filter,
+            ),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub(crate) struct MonitoredItem {
     monitored_item_id: u32,
     item_to_monitor: ReadValueId,
@@ -101,9 +124,16 @@ pub(crate) struct MonitoredItem {
     /// message, pop back gets the most recent.
     notification_queue: VecDeque<Notification>,
     queue_overflow: bool,
+    /// Number of times the queue overflowed, reported by the subscription diagnostics
+    queue_overflow_count: u32,
     timestamps_to_return: TimestampsToReturn,
     last_sample_time: DateTimeUtc,
     last_data_value: Option<DataValue>,
+    /// The item belongs to a durable subscription
+    durable: bool,
+    /// Values pushed by the sampling schedule of the item's sampling interval
+    #[serde(skip)]
+    sampled_values: SampledValues,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -135,6 +165,7 @@ impl MonitoredItem {
         let queue_size = Self::sanitize_queue_size(
             server_state,
             request.requested_parameters.queue_size as usize,
+            false,
         );
         Ok(MonitoredItem {
             monitored_item_id,
@@ -148,12 +179,83 @@ impl MonitoredItem {
             timestamps_to_return,
             last_sample_time: *now,
             last_data_value: None,
+            durable: false,
+            sampled_values: SampledValues::default(),
             queue_size,
             notification_queue: VecDeque::with_capacity(queue_size),
             queue_overflow: false,
+            queue_overflow_count: 0,
         })
     }
 
+    /// Restores the monitored item of a durable subscription from its record
+    pub(crate) fn from_durable_record(
+        now: &DateTimeUtc,
+        server_state: &ServerState,
+        record: MonitoredItemRecord,
+    ) -> Result<MonitoredItem, StatusCode> {
+        let filter = FilterType::from_filter(&record.filter, &server_state.decoding_options())?;
+        let queue_size = Self::sanitize_queue_size(server_state, record.queue_size, true);
+        let mut notification_queue = VecDeque::with_capacity(record.notifications.len());
+        notification_queue.extend(record.notifications);
+        // The durable queue limit may have been lowered since the item was saved
+        while notification_queue.len() > queue_size {
+            if record.discard_oldest {
+                notification_queue.pop_front();
+            } else {
+                notification_queue.pop_back();
+            }
+        }
+        Ok(MonitoredItem {
+            monitored_item_id: record.monitored_item_id,
+            item_to_monitor: record.item_to_monitor,
+            monitoring_mode: record.monitoring_mode,
+            triggered_items: record.triggered_items.into_iter().collect(),
+            client_handle: record.client_handle,
+            sampling_interval: record.sampling_interval,
+            filter,
+            discard_oldest: record.discard_oldest,
+            timestamps_to_return: record.timestamps_to_return,
+            last_sample_time: *now,
+            last_data_value: record.last_data_value,
+            durable: true,
+            sampled_values: SampledValues::default(),
+            queue_size,
+            notification_queue,
+            queue_overflow: false,
+            queue_overflow_count: 0,
+        })
+    }
+
+    /// Makes the record the monitored item is saved as by a durable subscription
+    pub(crate) fn to_durable_record(&self) -> MonitoredItemRecord {
+        MonitoredItemRecord {
+            monitored_item_id: self.monitored_item_id,
+            item_to_monitor: self.item_to_monitor.clone(),
+            monitoring_mode: self.monitoring_mode,
+            client_handle: self.client_handle,
+            sampling_interval: self.sampling_interval,
+            filter: self.filter.to_filter(),
+            queue_size: self.queue_size,
+            discard_oldest: self.discard_oldest,
+            timestamps_to_return: self.timestamps_to_return,
+            triggered_items: self.triggered_items.iter().copied().collect(),
+            last_data_value: self.last_data_value.clone(),
+            notifications: self.notification_queue.iter().cloned().collect(),
+        }
+    }
+
+    /// Marks the item as belonging to a durable subscription and revises its queue size. Durable
+    /// items may queue more values than others while no session takes them, up to the durable
+    /// limit. The queue grows as it fills rather than being allocated up front.
+    pub fn set_durable(&mut self, server_state: &ServerState, requested_queue_size: usize) {
+        self.durable = true;
+        self.queue_size = Self::sanitize_queue_size(server_state, requested_queue_size, true);
+        self.sampled_values
+            .lock()
+            .set_limits(self.queue_size, self.discard_oldest);
+    }
+
     /// Modifies the existing item with the values of the modify request. On success, the result
     /// holds the filter result.
     pub fn modify(
@@ -175,19 +277,23 @@ impl MonitoredItem {
         self.queue_size = Self::sanitize_queue_size(
             server_state,
             request.requested_parameters.queue_size as usize,
+            self.durable,
         );
         self.client_handle = request.requested_parameters.client_handle;
         self.discard_oldest = request.requested_parameters.discard_oldest;
+        self.sampled_values
+            .lock()
+            .set_limits(self.queue_size, self.discard_oldest);
 
         // Shrink / grow the notification queue to the new threshold
         if self.notification_queue.len() > self.queue_size {
             // Discard old notifications
-            let discard = self.queue_size - self.notification_queue.len();
+            let discard = self.notification_queue.len() - self.queue_size;
             let _ = self.notification_queue.drain(0..discard);
             // TODO potential edge case with discard oldest behaviour
             // Shrink the queue
             self.notification_queue.shrink_to_fit();
-        } else if self.notification_queue.capacity() < self.queue_size {
+        } else if !self.durable && self.notification_queue.capacity() < self.queue_size {
             // Reserve space for more elements
             let extra_capacity = self.queue_size - self.notification_queue.capacity();
             self.notification_queue.reserve(extra_capacity);
@@ -229,9 +335,13 @@ impl MonitoredItem {
 
     /// Called repeatedly on the monitored item.
     ///
-    /// If the monitored item has a negative interval and subscription interval has elapsed,
-    /// the value is tested immediately. Otherwise, the monitored items sampling interval is enforced
-    /// the subscriptions and controls the rate.
+    /// The values of a data change item are sampled by the schedule of its sampling interval, so
+    /// the tick compares the values sampled since the last tick. The value is read directly on
+    /// the first tick, or if the data must be resent.
+    ///
+    /// If an event item has a negative interval and subscription interval has elapsed, events are
+    /// tested immediately. Otherwise, the monitored items sampling interval is enforced the
+    /// subscriptions and controls the rate.
     ///
     /// Function returns a `TickResult` denoting if the value changed or not, and whether it should
     /// be reported.
@@ -243,35 +353,55 @@ impl MonitoredItem {
         resend_data: bool,
     ) -> TickResult {
         if self.monitoring_mode == MonitoringMode::Disabled {
+            // Values sampled while disabled are not reported
+            self.sampled_values.lock().clear();
             TickResult::NoChange
         } else {
-            let check_value = if resend_data {
-                // Always check for resend_data flag
-                true
-            } else if self.sampling_interval < 0f64 {
-                // -1 means use the subscription publishing interval so if the publishing interval elapsed,
-                // then this monitored item is evaluated otherwise it won't be.
-                publishing_interval_elapsed
-            } else if self.sampling_interval == 0f64 {
-                // 0 means fastest practical rate, i.e. the tick quantum itself
-                // 0 is also used for clients subscribing for events.
-                true
-            } else {
-                // Compare sample interval to the time elapsed
-                let sampling_interval = super::duration_from_ms(self.sampling_interval);
-                let elapsed = now
-                    .signed_duration_since(self.last_sample_time)
-                    .to_std()
-                    .unwrap();
-                elapsed >= sampling_interval
-            };
-
-            // Test the value (or don't)
-            let value_changed = check_value && {
-                // Indicate a change if reporting is enabled
-                let first_tick = !self.is_event_filter() && self.last_data_value.is_none();
+            let value_changed = if self.is_event_filter() {
+                let check_value = if resend_data {
+                    // Always check for resend_data flag
+                    true
+                } else if self.sampling_interval < 0f64 {
+                    // -1 means use the subscription publishing interval so if the publishing interval elapsed,
+                    // then this monitored item is evaluated otherwise it won't be.
+                    publishing_interval_elapsed
+                } else if self.sampling_interval == 0f64 {
+                    // 0 means fastest practical rate, i.e. the tick quantum itself
+                    // 0 is also used for clients subscribing for events.
+                    true
+                } else {
+                    // Compare sample interval to the time elapsed
+                    let sampling_interval = super::duration_from_ms(self.sampling_interval);
+                    let elapsed = now
+                        .signed_duration_since(self.last_sample_time)
+                        .to_std()
+                        .unwrap();
+                    elapsed >= sampling_interval
+                };
+                check_value && {
+                    let value_changed = self.check_value(address_space, now, resend_data);
+                    value_changed || !self.notification_queue.is_empty()
+                }
+            } else if resend_data || self.last_data_value.is_none() {
+                // Indicate a change on the first tick
+                let first_tick = self.last_data_value.is_none();
+                self.sampled_values.lock().clear();
                 let value_changed = self.check_value(address_space, now, resend_data);
                 first_tick || value_changed || !self.notification_queue.is_empty()
+            } else {
+                let (sampled_values, discarded) = self.sampled_values.lock().take();
+                // Every sampled value is checked so each change is enqueued
+                let mut value_changed = false;
+                for value in sampled_values {
+                    if self.check_data_value(value, false) {
+                        value_changed = true;
+                    }
+                }
+                if discarded > 0 {
+                    self.sampled_values_discarded(discarded);
+                }
+                value_changed
+                    || (publishing_interval_elapsed && !self.notification_queue.is_empty())
             };
 
             if value_changed {
@@ -351,7 +481,17 @@ impl MonitoredItem {
             NumericRange::None,
             &QualifiedName::null(),
         );
-        if let Some(mut data_value) = data_value {
+        if let Some(data_value) = data_value {
+            self.check_data_value(data_value, resend_data)
+        } else {
+            false
+        }
+    }
+
+    /// Compares a value of the monitored item to the last value and enqueues a notification if
+    /// it has changed according to a filter / equality check.
+    fn check_data_value(&mut self, mut data_value: DataValue, resend_data: bool) -> bool {
+        {
             // Test for data change
             let data_change = if resend_data {
                 true
@@ -423,12 +563,10 @@ impl MonitoredItem {
                 );
             }
             data_change
-        } else {
-            false
         }
     }
 
-    fn is_event_filter(&self) -> bool {
+    pub fn is_event_filter(&self) -> bool {
         matches!(self.filter, FilterType::EventFilter(_))
     }
 
@@ -499,6 +637,7 @@ impl MonitoredItem {
                 "Data change overflow, node {:?}",
                 self.item_to_monitor.node_id
             );
+            self.queue_overflow_count = self.queue_overflow_count.wrapping_add(1);
             // Overflow behaviour
             if self.discard_oldest {
                 // Throw away oldest item (the one at the start) to make space at the end
@@ -524,6 +663,25 @@ impl MonitoredItem {
         self.notification_queue.push_back(notification);
     }
 
+    /// Counts sampled values that were discarded before the item was ticked as overflows, and
+    /// sets the overflow bit on the newest notification like an overflow of the queue does
+    fn sampled_values_discarded(&mut self, discarded: u32) {
+        trace!(
+            "Sampled value overflow, node {:?}",
+            self.item_to_monitor.node_id
+        );
+        self.queue_overflow_count = self.queue_overflow_count.wrapping_add(discarded);
+        if self.queue_size > 1 {
+            if let Some(Notification::MonitoredItemNotification(notification)) =
+                self.notification_queue.back_mut()
+            {
+                notification.value.status =
+                    Some(notification.value.status() | StatusCode::OVERFLOW);
+                self.queue_overflow = true;
+            }
+        }
+    }
+
     /// Gets the oldest notification message from the notification queue
     #[cfg(test)]
     pub fn oldest_notification_message(&mut self) -> Option<Notification> {
@@ -566,14 +724,23 @@ impl MonitoredItem {
     }
 
     /// Takes the requested queue size and ensures it is within the range supported by the server
-    fn sanitize_queue_size(server_state: &ServerState, requested_queue_size: usize) -> usize {
+    fn sanitize_queue_size(
+        server_state: &ServerState,
+        requested_queue_size: usize,
+        durable: bool,
+    ) -> usize {
+        let max_queue_size = if durable {
+            server_state.max_durable_monitored_item_queue_size
+        } else {
+            server_state.max_monitored_item_queue_size
+        };
         if requested_queue_size == 0 || requested_queue_size == 1 {
             // For data monitored items 0 -> 1
             // Future - for event monitored items, queue size should be the default queue size for event notifications
             1
         // Future - for event monitored items, the minimum queue size the server requires for event notifications
-        } else if requested_queue_size > server_state.max_monitored_item_queue_size {
-            server_state.max_monitored_item_queue_size
+        } else if requested_queue_size > max_queue_size {
+            max_queue_size
         // Future - for event monitored items MaxUInt32 returns the maximum queue size the server support
         // for event notifications
         } else {
@@ -593,6 +760,58 @@ impl MonitoredItem {
         self.sampling_interval
     }
 
+    pub fn attribute_id(&self) -> u32 {
+        self.item_to_monitor.attribute_id
+    }
+
+    pub fn node_id(&self) -> &NodeId {
+        &self.item_to_monitor.node_id
+    }
+
+    /// Sets the sampling interval, e.g. to the interval the item is really sampled at
+    pub fn set_sampling_interval(&mut self, sampling_interval: Duration) {
+        self.sampling_interval = sampling_interval;
+    }
+
+    /// Adds a data change item to the sampling schedule of its sampling interval. The id of the
+    /// item is pushed to the sampled items when there are sampled values. The item leaves the
+    /// schedule it was previously added to.
+    pub fn add_to_sampling_schedule(
+        &mut self,
+        sampling_schedules: &mut SamplingSchedules,
+        address_space: &AddressSpace,
+        sampled_items: &SampledItems,
+    ) {
+        self.sampled_values = Arc::new(Mutex::new(SampledValueQueue::new(
+            self.queue_size,
+            self.discard_oldest,
+        )));
+        if !self.is_event_filter() {
+            if let Ok(attribute_id) = AttributeId::from_u32(self.item_to_monitor.attribute_id) {
+                sampling_schedules.add_item(
+                    address_space,
+                    self.sampling_interval,
+                    &self.item_to_monitor.node_id,
+                    attribute_id,
+                    SampleListener::new(
+                        self.monitored_item_id,
+                        &self.sampled_values,
+                        sampled_items,
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Tests if the monitored item has to be ticked without being given sampled values, i.e.
+    /// it monitors events, has not been checked yet or holds notifications.
+    pub fn is_pending(&self) -> bool {
+        self.monitoring_mode != MonitoringMode::Disabled
+            && (self.is_event_filter()
+                || self.last_data_value.is_none()
+                || !self.notification_queue.is_empty())
+    }
+
     pub fn triggered_items(&self) -> &BTreeSet<u32> {
         &self.triggered_items
     }
@@ -609,6 +828,10 @@ impl MonitoredItem {
         self.queue_size
     }
 
+    pub fn queue_overflow_count(&self) -> u32 {
+        self.queue_overflow_count
+    }
+
     #[cfg(test)]
     pub fn queue_overflow(&self) -> bool {
         self.queue_overflow
@@ -622,5 +845,8 @@ impl MonitoredItem {
     #[cfg(test)]
     pub(crate) fn set_discard_oldest(&mut self, discard_oldest: bool) {
         self.discard_oldest = discard_oldest;
+        self.sampled_values
+            .lock()
+            .set_limits(self.queue_size, self.discard_oldest);
     }
 }
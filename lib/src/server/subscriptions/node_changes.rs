@@ -0,0 +1,57 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Change notification from the address space to sampling schedules. A schedule registers
+//! interest in the nodes its monitored items monitor and the address space pushes the id of
+//! each of those nodes that is changed to it. Nodes then only have to be read when they
+//! changed, or when their value comes from an `AttributeGetter`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+use crate::sync::*;
+use crate::types::NodeId;
+
+/// The ids of the nodes that changed since a schedule last looked at them
+pub(crate) type ChangedNodes = Arc<Mutex<HashSet<NodeId>>>;
+
+/// A reference to a schedule's changed nodes that does not keep the schedule's interest alive
+type ChangedNodesListener = Weak<Mutex<HashSet<NodeId>>>;
+
+/// The schedules interested in changes to nodes. A schedule's interest ends when the schedule
+/// is dropped.
+#[derive(Default)]
+pub(crate) struct NodeChangeListeners {
+    listeners: Mutex<HashMap<NodeId, Vec<ChangedNodesListener>>>,
+}
+
+impl NodeChangeListeners {
+    /// Registers interest in changes to the node. Changes are pushed into the set of changed nodes.
+    pub fn register(&self, node_id: &NodeId, changed_nodes: &ChangedNodes) {
+        let changed_nodes = Arc::downgrade(changed_nodes);
+        let mut listeners = self.listeners.lock();
+        let listeners = listeners.entry(node_id.clone()).or_default();
+        if !listeners.iter().any(|l| l.ptr_eq(&changed_nodes)) {
+            listeners.push(changed_nodes);
+        }
+    }
+
+    /// Pushes a change to the node to every interested schedule
+    pub fn notify(&self, node_id: &NodeId) {
+        let mut listeners = self.listeners.lock();
+        if let Some(node_listeners) = listeners.get_mut(node_id) {
+            node_listeners.retain(|l| {
+                if let Some(changed_nodes) = l.upgrade() {
+                    changed_nodes.lock().insert(node_id.clone());
+                    true
+                } else {
+                    false
+                }
+            });
+            if node_listeners.is_empty() {
+                listeners.remove(node_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Sampling of monitored values. Every distinct sampling interval has its own schedule which is
+//! shared by the monitored items of every subscription sampling at that interval, independent of
+//! publishing intervals. When a schedule is due, it reads the values of its nodes that changed
+//! since it was last due, or that come from a getter, and pushes them to the monitored items.
+//! Other nodes are not visited, so sampling costs nothing while values stay the same.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::sync::*;
+use crate::types::{
+    service_types::TimestampsToReturn, status_code::StatusCode, AttributeId, DataValue, NodeId,
+    NumericRange, QualifiedName,
+};
+
+use crate::server::{
+    address_space::{node::NodeType, AddressSpace},
+    subscriptions::node_changes::ChangedNodes,
+};
+
+/// How often a schedule drops the monitored items that no longer exist. A schedule without
+/// monitored items is removed at that point.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The values sampled for a monitored item since its subscription last ticked it
+pub(crate) type SampledValues = Arc<Mutex<SampledValueQueue>>;
+
+/// The values sampled for a monitored item, oldest first. Like the notification queue of the
+/// item, it holds at most the queue size of the item and discards the oldest or the newest value
+/// when it is full.
+#[derive(Debug, Default)]
+pub(crate) struct SampledValueQueue {
+    values: VecDeque<DataValue>,
+    queue_size: usize,
+    discard_oldest: bool,
+    /// Number of values discarded since the queue was last taken
+    discarded: u32,
+}
+
+impl SampledValueQueue {
+    pub fn new(queue_size: usize, discard_oldest: bool) -> Self {
+        SampledValueQueue {
+            values: VecDeque::with_capacity(queue_size),
+            queue_size,
+            discard_oldest,
+            discarded: 0,
+        }
+    }
+
+    /// Sets the queue size and overflow behaviour after the monitored item was modified
+    pub fn set_limits(&mut self, queue_size: usize, discard_oldest: bool) {
+        self.queue_size = queue_size;
+        self.discard_oldest = discard_oldest;
+        while self.values.len() > self.queue_size.max(1) {
+            self.discard();
+        }
+    }
+
+    fn discard(&mut self) {
+        if self.discard_oldest {
+            self.values.pop_front();
+        } else {
+            self.values.pop_back();
+        }
+        self.discarded = self.discarded.wrapping_add(1);
+    }
+
+    fn push(&mut self, value: DataValue) {
+        if self.values.len() >= self.queue_size.max(1) {
+            self.discard();
+        }
+        self.values.push_back(value);
+    }
+
+    /// Takes the values, oldest first, and the number of values discarded since the last take
+    pub fn take(&mut self) -> (VecDeque<DataValue>, u32) {
+        (
+            std::mem::take(&mut self.values),
+            std::mem::take(&mut self.discarded),
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.discarded = 0;
+    }
+}
+
+/// The ids of the monitored items of a subscription that were given sampled values since the
+/// subscription last ticked
+pub(crate) type SampledItems = Arc<Mutex<BTreeSet<u32>>>;
+
+/// A monitored item sampled by a schedule. It does not keep the item or its subscription alive.
+pub(crate) struct SampleListener {
+    monitored_item_id: u32,
+    values: Weak<Mutex<SampledValueQueue>>,
+    items: Weak<Mutex<BTreeSet<u32>>>,
+}
+
+impl SampleListener {
+    /// Creates a listener that pushes sampled values to the values of the monitored item and its
+    /// id to the sampled items of its subscription
+    pub fn new(monitored_item_id: u32, values: &SampledValues, items: &SampledItems) -> Self {
+        SampleListener {
+            monitored_item_id,
+            values: Arc::downgrade(values),
+            items: Arc::downgrade(items),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.values.strong_count() > 0 && self.items.strong_count() > 0
+    }
+
+    /// Pushes the value to the monitored item. Returns `false` if the monitored item or its
+    /// subscription no longer exist.
+    fn push(&self, value: &DataValue) -> bool {
+        match (self.values.upgrade(), self.items.upgrade()) {
+            (Some(values), Some(items)) => {
+                values.lock().push(value.clone());
+                items.lock().insert(self.monitored_item_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An attribute of a node sampled by a schedule
+struct SampledAttribute {
+    attribute_id: AttributeId,
+    listeners: Vec<SampleListener>,
+    /// The value comes from a getter so it is read every time the schedule is due
+    has_getter: bool,
+}
+
+impl SampledAttribute {
+    fn is_getter_backed(&self, node: Option<&NodeType>) -> bool {
+        match node {
+            Some(NodeType::Variable(variable)) => {
+                self.attribute_id == AttributeId::Value && variable.has_value_getter()
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads the value and pushes it to the monitored items that still exist. A node that was
+    /// deleted gives them a `BadNodeIdUnknown` value.
+    fn sample(&mut self, node: Option<&NodeType>) {
+        self.has_getter = self.is_getter_backed(node);
+        let value = match node {
+            Some(node) => node.as_node().get_attribute(
+                TimestampsToReturn::Neither,
+                self.attribute_id,
+                NumericRange::None,
+                &QualifiedName::null(),
+            ),
+            None => Some(DataValue {
+                status: Some(StatusCode::BadNodeIdUnknown),
+                ..DataValue::null()
+            }),
+        };
+        if let Some(value) = value {
+            self.listeners.retain(|listener| listener.push(&value));
+        }
+    }
+}
+
+/// The schedule of one sampling interval
+struct SamplingSchedule {
+    interval: Duration,
+    next_sample: Instant,
+    /// The sampled attributes of each node
+    nodes: HashMap<NodeId, Vec<SampledAttribute>>,
+    /// The nodes with an attribute that comes from a getter
+    getter_nodes: HashSet<NodeId>,
+    /// The nodes changed since the schedule was last due, pushed by the address space
+    changed_nodes: ChangedNodes,
+    /// The time the schedule next drops the monitored items that no longer exist
+    next_prune: Instant,
+}
+
+impl SamplingSchedule {
+    /// Samples the nodes that changed or have an attribute that comes from a getter. Other nodes
+    /// are only visited to drop the monitored items that no longer exist when `prune` is set.
+    fn sample(&mut self, address_space: &AddressSpace, prune: bool) {
+        let changed_nodes = std::mem::take(&mut *self.changed_nodes.lock());
+        let getter_nodes = self
+            .getter_nodes
+            .iter()
+            .filter(|node_id| !changed_nodes.contains(*node_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for node_id in changed_nodes.iter().chain(getter_nodes.iter()) {
+            let Some(attributes) = self.nodes.get_mut(node_id) else {
+                continue;
+            };
+            let changed = changed_nodes.contains(node_id);
+            let node = address_space.find_node(node_id);
+            attributes
+                .iter_mut()
+                .filter(|attribute| changed || attribute.has_getter)
+                .for_each(|attribute| attribute.sample(node));
+            if attributes.iter().any(|attribute| attribute.has_getter) {
+                self.getter_nodes.insert(node_id.clone());
+            } else {
+                self.getter_nodes.remove(node_id);
+            }
+        }
+        if prune {
+            self.nodes.retain(|_, attributes| {
+                attributes.retain_mut(|attribute| {
+                    attribute.listeners.retain(SampleListener::is_alive);
+                    !attribute.listeners.is_empty()
+                });
+                !attributes.is_empty()
+            });
+            let nodes = &self.nodes;
+            self.getter_nodes
+                .retain(|node_id| nodes.contains_key(node_id));
+        }
+    }
+}
+
+/// The sampling schedules of the server, one for each distinct sampling interval. A schedule is
+/// removed when no monitored item samples at its interval.
+pub struct SamplingSchedules {
+    schedules: BTreeMap<u64, SamplingSchedule>,
+    /// Wakes the sampling task when a schedule is added
+    schedule_added: Arc<Notify>,
+}
+
+impl Default for SamplingSchedules {
+    fn default() -> Self {
+        SamplingSchedules {
+            schedules: BTreeMap::new(),
+            schedule_added: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl SamplingSchedules {
+    /// Adds a monitored item to the schedule of its sampling interval. The item is removed from
+    /// the schedule when it or its subscription is dropped. Intervals shorter than a
+    /// millisecond are sampled every millisecond.
+    pub(crate) fn add_item(
+        &mut self,
+        address_space: &AddressSpace,
+        sampling_interval: f64,
+        node_id: &NodeId,
+        attribute_id: AttributeId,
+        listener: SampleListener,
+    ) {
+        let interval = super::duration_from_ms(sampling_interval).max(Duration::from_millis(1));
+        let schedule = self
+            .schedules
+            .entry(interval.as_micros() as u64)
+            .or_insert_with(|| {
+                self.schedule_added.notify_one();
+                let now = Instant::now();
+                SamplingSchedule {
+                    interval,
+                    next_sample: now + interval,
+                    nodes: HashMap::new(),
+                    getter_nodes: HashSet::new(),
+                    changed_nodes: ChangedNodes::default(),
+                    next_prune: now + PRUNE_INTERVAL,
+                }
+            });
+        address_space.add_change_listener(node_id, &schedule.changed_nodes);
+
+        let attributes = schedule.nodes.entry(node_id.clone()).or_default();
+        let index = match attributes
+            .iter()
+            .position(|a| a.attribute_id == attribute_id)
+        {
+            Some(index) => index,
+            None => {
+                attributes.push(SampledAttribute {
+                    attribute_id,
+                    listeners: Vec::new(),
+                    has_getter: false,
+                });
+                attributes.len() - 1
+            }
+        };
+        let attribute = &mut attributes[index];
+        attribute.has_getter = attribute.is_getter_backed(address_space.find_node(node_id));
+        if attribute.has_getter {
+            schedule.getter_nodes.insert(node_id.clone());
+        }
+        attribute.listeners.push(listener);
+    }
+
+    /// Samples the schedules that are due. A schedule that has fallen behind skips the samples
+    /// it missed rather than catching up.
+    pub fn sample(&mut self, address_space: &AddressSpace, now: Instant) {
+        self.schedules.retain(|_, schedule| {
+            if schedule.next_sample <= now {
+                let prune = schedule.next_prune <= now;
+                if prune {
+                    schedule.next_prune = now + PRUNE_INTERVAL;
+                }
+                schedule.sample(address_space, prune);
+                schedule.next_sample += schedule.interval;
+                if schedule.next_sample <= now {
+                    schedule.next_sample = now + schedule.interval;
+                }
+            }
+            !schedule.nodes.is_empty()
+        });
+    }
+
+    /// Samples every schedule now, whether it is due or not, and drops the monitored items that
+    /// no longer exist
+    pub fn sample_all(&mut self, address_space: &AddressSpace) {
+        let now = Instant::now();
+        self.schedules.retain(|_, schedule| {
+            schedule.sample(address_space, true);
+            schedule.next_sample = now + schedule.interval;
+            schedule.next_prune = now + PRUNE_INTERVAL;
+            !schedule.nodes.is_empty()
+        });
+    }
+
+    /// The time the next schedule is due, if there are any schedules
+    pub fn next_sample(&self) -> Option<Instant> {
+        self.schedules.values().map(|s| s.next_sample).min()
+    }
+
+    /// The sampling intervals of the schedules in milliseconds
+    pub fn sampling_intervals(&self) -> Vec<f64> {
+        self.schedules
+            .values()
+            .map(|s| s.interval.as_secs_f64() * 1000.0)
+            .collect()
+    }
+
+    /// Notified when a schedule is added, so a sampling task waiting for the next sample can wake
+    /// up for the new schedule.
+    pub(crate) fn schedule_added(&self) -> Arc<Notify> {
+        self.schedule_added.clone()
+    }
+}
@@ -2,14 +2,15 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::sync::Arc;
 
 use crate::sync::*;
 use crate::types::{
     service_types::{
         MonitoredItemCreateRequest, MonitoredItemCreateResult, MonitoredItemModifyRequest,
-        MonitoredItemModifyResult, NotificationMessage, TimestampsToReturn,
+        MonitoredItemModifyResult, NotificationMessage, SubscriptionDiagnosticsDataType,
+        TimestampsToReturn,
     },
     status_code::StatusCode,
     *,
@@ -18,11 +19,15 @@ use crate::types::{
 use crate::core::handle::Handle;
 
 use crate::server::{
-    address_space::AddressSpace,
+    address_space::{node::NodeType, AddressSpace},
     constants,
     diagnostics::ServerDiagnostics,
     state::ServerState,
-    subscriptions::monitored_item::{MonitoredItem, Notification, TickResult},
+    subscriptions::{
+        durable::{NotificationMessageRecord, SubscriptionRecord},
+        monitored_item::{MonitoredItem, Notification, TickResult},
+        sampling::SampledItems,
+    },
 };
 
 /// The state of the subscription
@@ -100,8 +105,37 @@ impl UpdateStateResult {
     }
 }
 
+/// Durability of a subscription made durable with the SetSubscriptionDurable method
+#[derive(Debug, Clone, Serialize)]
+struct Durability {
+    /// The lifetime of the subscription without a session
+    lifetime_in_hours: u32,
+    /// The user whose sessions the subscription may be transferred to
+    owner: UAString,
+}
+
+/// Counters of the subscription that are reported by its diagnostics
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct SubscriptionCounters {
+    pub modify_count: u32,
+    pub enable_count: u32,
+    pub disable_count: u32,
+    pub republish_request_count: u32,
+    pub republish_message_request_count: u32,
+    pub republish_message_count: u32,
+    pub transfer_request_count: u32,
+    pub transferred_to_same_client_count: u32,
+    pub publish_request_count: u32,
+    pub data_change_notifications_count: u32,
+    pub event_notifications_count: u32,
+    pub notifications_count: u32,
+    pub late_publish_request_count: u32,
+    pub discarded_message_count: u32,
+}
+
+/// The reason a subscription is ticked
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub(crate) enum TickReason {
+pub enum TickReason {
     ReceivePublishRequest,
     TickTimerFired,
 }
@@ -122,6 +156,13 @@ pub struct Subscription {
     priority: u8,
     /// Map of monitored items
     monitored_items: HashMap<u32, MonitoredItem>,
+    /// The monitored items given values by their sampling schedules since the last tick
+    #[serde(skip)]
+    sampled_items: SampledItems,
+    /// The monitored items that have to be ticked on the next tick. Other monitored items wait
+    /// for sampled values.
+    #[serde(skip)]
+    pending_items: BTreeSet<u32>,
     /// State of the subscription
     state: SubscriptionState,
     /// A value that contains the number of consecutive publishing timer expirations without Client
@@ -159,6 +200,10 @@ pub struct Subscription {
     /// Stops the subscription calling diagnostics on drop
     #[serde(skip)]
     diagnostics_on_drop: bool,
+    /// Set if the subscription outlives its session
+    durability: Option<Durability>,
+    /// Counters reported by the diagnostics of the subscription
+    counters: SubscriptionCounters,
 }
 
 impl Drop for Subscription {
@@ -185,6 +230,8 @@ impl Subscription {
             publishing_interval,
             priority,
             monitored_items: HashMap::with_capacity(constants::DEFAULT_MONITORED_ITEM_CAPACITY),
+            sampled_items: SampledItems::default(),
+            pending_items: BTreeSet::new(),
             max_lifetime_counter: lifetime_counter,
             max_keep_alive_counter: keep_alive_counter,
             // State variables
@@ -202,6 +249,8 @@ impl Subscription {
             notifications: VecDeque::with_capacity(100),
             diagnostics,
             diagnostics_on_drop: true,
+            durability: None,
+            counters: SubscriptionCounters::default(),
         };
         {
             let mut diagnostics = trace_write_lock!(subscription.diagnostics);
@@ -239,9 +288,10 @@ impl Subscription {
         items_to_create: &[MonitoredItemCreateRequest],
     ) -> Vec<MonitoredItemCreateResult> {
         self.reset_lifetime_counter();
+        let previous_len = self.monitored_items.len();
 
         // Add items to the subscription if they're not already in its
-        items_to_create
+        let results = items_to_create
             .iter()
             .map(|item_to_create| {
                 if !address_space.node_exists(&item_to_create.item_to_monitor.node_id) {
@@ -259,19 +309,33 @@ impl Subscription {
                         server_state,
                         item_to_create,
                     ) {
-                        Ok(monitored_item) => {
+                        Ok(mut monitored_item) => {
+                            if self.durability.is_some() {
+                                monitored_item.set_durable(
+                                    server_state,
+                                    item_to_create.requested_parameters.queue_size as usize,
+                                );
+                            }
                             if server_state.max_monitored_items_per_sub == 0
                                 || self.monitored_items.len()
                                     <= server_state.max_monitored_items_per_sub
                             {
-                                let revised_sampling_interval = monitored_item.sampling_interval();
                                 let revised_queue_size = monitored_item.queue_size() as u32;
                                 // Validate the filter before registering the item
                                 match monitored_item.validate_filter(address_space) {
                                     Ok(filter_result) => {
-                                        // Register the item with the subscription
+                                        // Register the item with the sampling schedule of its
+                                        // revised interval
+                                        self.schedule_monitored_item(
+                                            server_state,
+                                            address_space,
+                                            &mut monitored_item,
+                                        );
+                                        let revised_sampling_interval =
+                                            monitored_item.sampling_interval();
                                         self.monitored_items
                                             .insert(monitored_item_id, monitored_item);
+                                        self.pending_items.insert(monitored_item_id);
                                         self.next_monitored_item_id += 1;
                                         MonitoredItemCreateResult {
                                             status_code: StatusCode::Good,
@@ -296,7 +360,22 @@ impl Subscription {
                     }
                 }
             })
-            .collect()
+            .collect();
+        self.monitored_items_changed(previous_len);
+        results
+    }
+
+    /// Tells the server diagnostics the number of monitored items changed from the previous
+    /// number
+    fn monitored_items_changed(&self, previous_len: usize) {
+        let len = self.monitored_items.len();
+        if len != previous_len {
+            let mut diagnostics = trace_write_lock!(self.diagnostics);
+            diagnostics.on_monitored_items_changed(
+                len.saturating_sub(previous_len),
+                previous_len.saturating_sub(len),
+            );
+        }
     }
 
     /// Modify the specified monitored items, returning a result for each
@@ -323,6 +402,14 @@ impl Subscription {
                             timestamps_to_return,
                             item_to_modify,
                         );
+                        Self::schedule_item(
+                            server_state,
+                            address_space,
+                            self.publishing_interval,
+                            &self.sampled_items,
+                            monitored_item,
+                        );
+                        self.pending_items.insert(item_to_modify.monitored_item_id);
                         match modify_result {
                             Ok(filter_result) => MonitoredItemModifyResult {
                                 status_code: StatusCode::Good,
@@ -358,6 +445,7 @@ impl Subscription {
     ) -> StatusCode {
         if let Some(monitored_item) = self.monitored_items.get_mut(&monitored_item_id) {
             monitored_item.set_monitoring_mode(monitoring_mode);
+            self.pending_items.insert(monitored_item_id);
             StatusCode::Good
         } else {
             StatusCode::BadMonitoredItemIdInvalid
@@ -367,15 +455,75 @@ impl Subscription {
     /// Delete the specified monitored items (by item id), returning a status code for each
     pub fn delete_monitored_items(&mut self, items_to_delete: &[u32]) -> Vec<StatusCode> {
         self.reset_lifetime_counter();
-        items_to_delete
+        let previous_len = self.monitored_items.len();
+        let results = items_to_delete
             .iter()
             .map(
                 |item_to_delete| match self.monitored_items.remove(item_to_delete) {
-                    Some(_) => StatusCode::Good,
+                    Some(_) => {
+                        // Dropping the item takes it off its sampling schedule
+                        self.pending_items.remove(item_to_delete);
+                        StatusCode::Good
+                    }
                     None => StatusCode::BadMonitoredItemIdInvalid,
                 },
             )
-            .collect()
+            .collect();
+        self.monitored_items_changed(previous_len);
+        results
+    }
+
+    /// Revises the sampling interval of a monitored item to the interval it is really sampled
+    /// at and adds it to the sampling schedule of that interval.
+    fn schedule_monitored_item(
+        &self,
+        server_state: &ServerState,
+        address_space: &AddressSpace,
+        monitored_item: &mut MonitoredItem,
+    ) {
+        Self::schedule_item(
+            server_state,
+            address_space,
+            self.publishing_interval,
+            &self.sampled_items,
+            monitored_item,
+        );
+    }
+
+    fn schedule_item(
+        server_state: &ServerState,
+        address_space: &AddressSpace,
+        publishing_interval: Duration,
+        sampled_items: &SampledItems,
+        monitored_item: &mut MonitoredItem,
+    ) {
+        if monitored_item.is_event_filter() {
+            // Events are not sampled
+            return;
+        }
+        // -1 means the item is sampled at the publishing interval
+        let mut sampling_interval = monitored_item.sampling_interval();
+        if sampling_interval < 0f64 {
+            sampling_interval = publishing_interval;
+        }
+        // A variable may not be sampled faster than its minimum sampling interval
+        if monitored_item.attribute_id() == AttributeId::Value as u32 {
+            if let Some(NodeType::Variable(variable)) =
+                address_space.find_node(monitored_item.node_id())
+            {
+                if let Some(minimum_sampling_interval) = variable.minimum_sampling_interval() {
+                    sampling_interval = f64::max(sampling_interval, minimum_sampling_interval);
+                }
+            }
+        }
+        monitored_item.set_sampling_interval(sampling_interval);
+
+        let mut sampling_schedules = trace_lock!(server_state.sampling_schedules);
+        monitored_item.add_to_sampling_schedule(
+            &mut sampling_schedules,
+            address_space,
+            sampled_items,
+        );
     }
 
     // Returns two vecs representing the server and client handles for each monitored item.
@@ -421,7 +569,7 @@ impl Subscription {
 
     /// Checks the subscription and monitored items for state change, messages. Returns `true`
     /// if there are zero or more notifications waiting to be processed.
-    pub(crate) fn tick(
+    pub fn tick(
         &mut self,
         now: &DateTimeUtc,
         address_space: &AddressSpace,
@@ -554,7 +702,10 @@ impl Subscription {
                 }
                 // Delete the monitored items, issue a status change for the subscription
                 debug!("Subscription status change to closed / timeout");
+                let previous_len = self.monitored_items.len();
                 self.monitored_items.clear();
+                self.monitored_items_changed(previous_len);
+                self.pending_items.clear();
                 let notification = NotificationMessage::status_change(
                     self.sequence_number.next(),
                     DateTime::from(*now),
@@ -716,6 +867,7 @@ impl Subscription {
                     // State #8
                     self.start_publishing_timer();
                     self.state = SubscriptionState::Late;
+                    self.counters.late_publish_request_count += 1;
                     return UpdateStateResult::new(
                         HandledState::IntervalElapsed8,
                         UpdateStateAction::None,
@@ -851,9 +1003,26 @@ impl Subscription {
         resend_data: bool,
     ) -> Option<NotificationMessage> {
         let mut triggered_items: BTreeSet<u32> = BTreeSet::new();
-        let mut monitored_item_notifications = Vec::with_capacity(self.monitored_items.len() * 2);
 
-        for monitored_item in self.monitored_items.values_mut() {
+        // Values pushed by the sampling schedules make the items pending. Only pending items
+        // are ticked, unless every item must resend its data.
+        self.pending_items
+            .extend(std::mem::take(&mut *self.sampled_items.lock()));
+        let items_to_tick = if resend_data {
+            self.pending_items.clear();
+            self.monitored_items
+                .keys()
+                .copied()
+                .collect::<BTreeSet<u32>>()
+        } else {
+            std::mem::take(&mut self.pending_items)
+        };
+        let mut monitored_item_notifications = Vec::with_capacity(items_to_tick.len() * 2);
+
+        for monitored_item_id in items_to_tick {
+            let Some(monitored_item) = self.monitored_items.get_mut(&monitored_item_id) else {
+                continue;
+            };
             // If this returns true then the monitored item wants to report its notification
             let monitoring_mode = monitored_item.monitoring_mode();
             match monitored_item.tick(now, address_space, publishing_interval_elapsed, resend_data)
@@ -906,6 +1075,9 @@ impl Subscription {
                     // Ignore
                 }
             }
+            if monitored_item.is_pending() {
+                self.pending_items.insert(monitored_item_id);
+            }
         }
 
         // Are there any triggered items to force a change on?
@@ -961,7 +1133,7 @@ impl Subscription {
                         panic!()
                     }
                 })
-                .collect();
+                .collect::<Vec<_>>();
 
             // Collect event notifications
             let event_notifications = monitored_item_notifications
@@ -974,7 +1146,11 @@ impl Subscription {
                         panic!()
                     }
                 })
-                .collect();
+                .collect::<Vec<_>>();
+
+            self.counters.data_change_notifications_count += data_change_notifications.len() as u32;
+            self.counters.event_notifications_count += event_notifications.len() as u32;
+            self.counters.notifications_count += monitored_item_notifications.len() as u32;
 
             // Make a notification
             let notification = NotificationMessage::data_change(
@@ -989,6 +1165,177 @@ impl Subscription {
         }
     }
 
+    /// Makes the subscription durable so it outlives its session, for a lifetime in hours. The
+    /// lifetime count is revised to match the lifetime.
+    pub(crate) fn set_durable(&mut self, owner: UAString, lifetime_in_hours: u32) {
+        let lifetime_ms = lifetime_in_hours as f64 * 3_600_000f64;
+        self.max_lifetime_counter = if self.publishing_interval > 0f64 {
+            (lifetime_ms / self.publishing_interval).min(u32::MAX as f64) as u32
+        } else {
+            u32::MAX
+        };
+        self.reset_lifetime_counter();
+        self.durability = Some(Durability {
+            lifetime_in_hours,
+            owner,
+        });
+    }
+
+    pub fn is_durable(&self) -> bool {
+        self.durability.is_some()
+    }
+
+    /// Tests if the subscription is durable and belongs to the user
+    pub(crate) fn is_owned_by(&self, owner: &UAString) -> bool {
+        matches!(self.durability, Some(ref durability) if durability.owner == *owner)
+    }
+
+    /// Re-applies the durability after the publishing interval of a durable subscription was
+    /// modified, so it keeps its lifetime in hours.
+    pub(crate) fn revise_durable_lifetime(&mut self) {
+        if let Some(durability) = self.durability.take() {
+            self.set_durable(durability.owner, durability.lifetime_in_hours);
+        }
+    }
+
+    /// Ticks a durable subscription whose session has ended. Its monitored items keep queueing
+    /// notifications but nothing is published, and the lifetime counter counts down each
+    /// publishing interval. Returns `false` when the lifetime has expired.
+    pub(crate) fn tick_detached(
+        &mut self,
+        now: &DateTimeUtc,
+        address_space: &AddressSpace,
+    ) -> bool {
+        // The publishing interval never elapses for the monitored items so notifications stay
+        // queued in them
+        let notification = self.tick_monitored_items(now, address_space, false, false);
+        debug_assert!(notification.is_none());
+        if self.test_and_set_publishing_interval_elapsed(now) {
+            self.lifetime_counter = self.lifetime_counter.saturating_sub(1);
+        }
+        self.lifetime_counter > 0
+    }
+
+    /// Returns the sequence numbers of the notifications waiting to be published, e.g. those kept
+    /// by a durable subscription after its session ended
+    pub(crate) fn available_sequence_numbers(&self) -> Option<Vec<u32>> {
+        if self.notifications.is_empty() {
+            None
+        } else {
+            Some(
+                self.notifications
+                    .iter()
+                    .map(|notification| notification.sequence_number)
+                    .collect(),
+            )
+        }
+    }
+
+    /// Puts notifications that were taken from the subscription but not acknowledged back at
+    /// the front of its queue.
+    pub(crate) fn requeue_notifications(&mut self, mut notifications: Vec<NotificationMessage>) {
+        notifications.sort_by_key(|notification| notification.sequence_number);
+        for notification in notifications.into_iter().rev() {
+            self.notifications.push_front(notification);
+        }
+    }
+
+    /// Makes the record the durable subscription is saved as
+    pub(crate) fn to_durable_record(&self) -> SubscriptionRecord {
+        let (owner, lifetime_in_hours) = match self.durability {
+            Some(ref durability) => (durability.owner.clone(), durability.lifetime_in_hours),
+            None => (UAString::null(), 0),
+        };
+        SubscriptionRecord {
+            subscription_id: self.subscription_id,
+            owner,
+            lifetime_in_hours,
+            max_lifetime_count: self.max_lifetime_counter,
+            lifetime_counter: self.lifetime_counter,
+            publishing_interval: self.publishing_interval,
+            publishing_enabled: self.publishing_enabled,
+            max_keep_alive_count: self.max_keep_alive_counter,
+            priority: self.priority,
+            last_sequence_number: self.last_sequence_number,
+            next_monitored_item_id: self.next_monitored_item_id,
+            monitored_items: self
+                .monitored_items
+                .values()
+                .map(|monitored_item| monitored_item.to_durable_record())
+                .collect(),
+            notifications: self
+                .notifications
+                .iter()
+                .map(NotificationMessageRecord::from)
+                .collect(),
+        }
+    }
+
+    /// Restores a durable or replicated subscription from its record. Its monitored items are
+    /// added to the sampling schedules again.
+    pub(crate) fn from_durable_record(
+        record: SubscriptionRecord,
+        server_state: &ServerState,
+        address_space: &AddressSpace,
+        now: &DateTimeUtc,
+    ) -> Subscription {
+        let mut subscription = Subscription::new(
+            server_state.diagnostics.clone(),
+            record.subscription_id,
+            record.publishing_enabled,
+            record.publishing_interval,
+            record.max_lifetime_count,
+            record.max_keep_alive_count,
+            record.priority,
+        );
+        // A record without a lifetime in hours is not of a durable subscription
+        if record.lifetime_in_hours > 0 {
+            subscription.set_durable(record.owner, record.lifetime_in_hours);
+        }
+        // The lifetime carries on from where it was when the subscription was saved
+        subscription.lifetime_counter = record
+            .lifetime_counter
+            .min(subscription.max_lifetime_counter);
+        subscription.last_sequence_number = record.last_sequence_number;
+        subscription
+            .sequence_number
+            .set_next(if record.last_sequence_number == u32::MAX {
+                1
+            } else {
+                record.last_sequence_number + 1
+            });
+        subscription.next_monitored_item_id = record.next_monitored_item_id;
+        subscription.notifications = record
+            .notifications
+            .into_iter()
+            .map(NotificationMessage::from)
+            .collect();
+        for monitored_item in record.monitored_items {
+            let monitored_item_id = monitored_item.monitored_item_id;
+            match MonitoredItem::from_durable_record(now, server_state, monitored_item) {
+                Ok(mut monitored_item) => {
+                    subscription.schedule_monitored_item(
+                        server_state,
+                        address_space,
+                        &mut monitored_item,
+                    );
+                    subscription
+                        .monitored_items
+                        .insert(monitored_item_id, monitored_item);
+                    subscription.pending_items.insert(monitored_item_id);
+                }
+                Err(status_code) => {
+                    error!(
+                        "Cannot restore monitored item {} of durable subscription {}, {}",
+                        monitored_item_id, record.subscription_id, status_code
+                    );
+                }
+            }
+        }
+        subscription.monitored_items_changed(0);
+        subscription
+    }
+
     /// Reset the keep-alive counter to the maximum keep-alive count of the Subscription.
     /// The maximum keep-alive count is set by the Client when the Subscription is created
     /// and may be modified using the ModifySubscription Service
@@ -1083,10 +1430,92 @@ impl Subscription {
     }
 
     pub(crate) fn set_publishing_enabled(&mut self, publishing_enabled: bool) {
+        if publishing_enabled {
+            self.counters.enable_count += 1;
+        } else {
+            self.counters.disable_count += 1;
+        }
         self.publishing_enabled = publishing_enabled;
         self.reset_lifetime_counter();
     }
 
+    pub(crate) fn counters_mut(&mut self) -> &mut SubscriptionCounters {
+        &mut self.counters
+    }
+
+    /// Makes the diagnostics of the subscription, which belongs to the session
+    pub(crate) fn diagnostics(
+        &self,
+        session_id: &NodeId,
+        unacknowledged_message_count: u32,
+    ) -> SubscriptionDiagnosticsDataType {
+        let counters = &self.counters;
+        let (disabled_monitored_item_count, monitoring_queue_overflow_count) = self
+            .monitored_items
+            .values()
+            .fold((0, 0), |(disabled, overflow), monitored_item| {
+                (
+                    disabled
+                        + (monitored_item.monitoring_mode() == MonitoringMode::Disabled) as u32,
+                    overflow + monitored_item.queue_overflow_count(),
+                )
+            });
+        SubscriptionDiagnosticsDataType {
+            session_id: session_id.clone(),
+            subscription_id: self.subscription_id,
+            priority: self.priority,
+            publishing_interval: self.publishing_interval,
+            max_keep_alive_count: self.max_keep_alive_counter,
+            max_lifetime_count: self.max_lifetime_counter,
+            max_notifications_per_publish: 0,
+            publishing_enabled: self.publishing_enabled,
+            modify_count: counters.modify_count,
+            enable_count: counters.enable_count,
+            disable_count: counters.disable_count,
+            republish_request_count: counters.republish_request_count,
+            republish_message_request_count: counters.republish_message_request_count,
+            republish_message_count: counters.republish_message_count,
+            transfer_request_count: counters.transfer_request_count,
+            transferred_to_alt_client_count: 0,
+            transferred_to_same_client_count: counters.transferred_to_same_client_count,
+            publish_request_count: counters.publish_request_count,
+            data_change_notifications_count: counters.data_change_notifications_count,
+            event_notifications_count: counters.event_notifications_count,
+            notifications_count: counters.notifications_count,
+            late_publish_request_count: counters.late_publish_request_count,
+            current_keep_alive_count: self.keep_alive_counter,
+            current_lifetime_count: self.lifetime_counter,
+            unacknowledged_message_count,
+            discarded_message_count: counters.discarded_message_count,
+            monitored_item_count: self.monitored_items.len() as u32,
+            disabled_monitored_item_count,
+            monitoring_queue_overflow_count,
+            next_sequence_number: if self.last_sequence_number == u32::MAX {
+                1
+            } else {
+                self.last_sequence_number + 1
+            },
+            event_queue_over_flow_count: 0,
+        }
+    }
+
+    /// Counts the monitored items of the subscription per sampling interval, with the number of
+    /// those that are disabled.
+    pub(crate) fn sampling_interval_counts(&self, counts: &mut BTreeMap<u64, (u32, u32)>) {
+        for monitored_item in self.monitored_items.values() {
+            if monitored_item.is_event_filter() {
+                continue;
+            }
+            let count = counts
+                .entry(monitored_item.sampling_interval().to_bits())
+                .or_default();
+            count.0 += 1;
+            if monitored_item.monitoring_mode() == MonitoringMode::Disabled {
+                count.1 += 1;
+            }
+        }
+    }
+
     pub(crate) fn set_diagnostics_on_drop(&mut self, diagnostics_on_drop: bool) {
         self.diagnostics_on_drop = diagnostics_on_drop;
     }
@@ -8,7 +8,10 @@ use std::{
 };
 
 use crate::types::{
-    service_types::{NotificationMessage, PublishRequest, PublishResponse, ServiceFault},
+    service_types::{
+        NotificationMessage, PublishRequest, PublishResponse, ServiceFault,
+        SubscriptionDiagnosticsDataType,
+    },
     status_code::StatusCode,
     *,
 };
@@ -97,6 +100,10 @@ impl Subscriptions {
         &mut self.publish_request_queue
     }
 
+    pub(crate) fn publish_request_queue_len(&self) -> usize {
+        self.publish_request_queue.len()
+    }
+
     #[cfg(test)]
     pub(crate) fn publish_response_queue(&mut self) -> &mut VecDeque<PublishResponseEntry> {
         &mut self.publish_response_queue
@@ -199,6 +206,41 @@ impl Subscriptions {
         self.subscriptions.get_mut(&subscription_id)
     }
 
+    /// Removes the durable subscriptions so they can outlive the session. Notifications that
+    /// were queued for sending or not acknowledged yet go back to their subscription, so the
+    /// client they are transferred to can have them.
+    pub(crate) fn remove_durable(&mut self) -> Vec<Subscription> {
+        let durable_ids = self
+            .subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.is_durable())
+            .map(|(subscription_id, _)| *subscription_id)
+            .collect::<Vec<_>>();
+        durable_ids
+            .into_iter()
+            .filter_map(|subscription_id| {
+                let mut subscription = self.subscriptions.remove(&subscription_id)?;
+                let mut notifications = self
+                    .retransmission_queue
+                    .range((subscription_id, 0)..=(subscription_id, u32::MAX))
+                    .map(|(_, notification)| notification.clone())
+                    .collect::<Vec<_>>();
+                self.retransmission_queue
+                    .retain(|(id, _), _| *id != subscription_id);
+                self.transmission_queue.retain(|(id, _, notification)| {
+                    if *id == subscription_id {
+                        notifications.push(notification.clone());
+                        false
+                    } else {
+                        true
+                    }
+                });
+                subscription.requeue_notifications(notifications);
+                Some(subscription)
+            })
+            .collect()
+    }
+
     /// The tick causes the subscription manager to iterate through individual subscriptions calling tick
     /// on each in order of priority. In each case this could generate data change notifications. Data change
     /// notifications will be attached to the next available publish response and queued for sending
@@ -241,6 +283,7 @@ impl Subscriptions {
                 if !self.publish_request_queue.is_empty() {
                     if let Some(notification_message) = subscription.take_notification() {
                         let publish_request = self.publish_request_queue.pop_back().unwrap();
+                        subscription.counters_mut().publish_request_count += 1;
                         // Consume the publish request and queue the notification onto the transmission queue
                         self.transmission_queue.push_front((
                             subscription_id,
@@ -341,6 +384,28 @@ impl Subscriptions {
             .append(&mut expired_publish_responses);
     }
 
+    /// Answers every queued publish request with a service fault of the supplied status code,
+    /// e.g. because the server is shutting down.
+    pub(crate) fn cancel_publish_requests(&mut self, status_code: StatusCode) {
+        let now = DateTime::now();
+        let cancelled =
+            self.publish_request_queue
+                .drain(..)
+                .rev()
+                .map(|request| PublishResponseEntry {
+                    request_id: request.request_id,
+                    response: ServiceFault {
+                        response_header: ResponseHeader::new_timestamped_service_result(
+                            now,
+                            &request.request.request_header,
+                            status_code,
+                        ),
+                    }
+                    .into(),
+                });
+        self.publish_response_queue.extend(cancelled);
+    }
+
     /// Deletes the acknowledged notifications, returning a list of status code for each according
     /// to whether it was found or not.
     ///
@@ -390,7 +455,7 @@ impl Subscriptions {
     }
 
     /// Returns the array of available sequence numbers in the retransmission queue for the specified subscription
-    fn available_sequence_numbers(&self, subscription_id: u32) -> Option<Vec<u32>> {
+    pub(crate) fn available_sequence_numbers(&self, subscription_id: u32) -> Option<Vec<u32>> {
         if self.retransmission_queue.is_empty() {
             None
         } else {
@@ -462,6 +527,29 @@ impl Subscriptions {
         }
     }
 
+    /// Makes the diagnostics of the subscriptions, which belong to the session
+    pub(crate) fn diagnostics(&self, session_id: &NodeId) -> Vec<SubscriptionDiagnosticsDataType> {
+        self.subscriptions
+            .values()
+            .map(|subscription| {
+                let subscription_id = subscription.subscription_id();
+                let unacknowledged_message_count = self
+                    .retransmission_queue
+                    .range((subscription_id, 0)..=(subscription_id, u32::MAX))
+                    .count() as u32;
+                subscription.diagnostics(session_id, unacknowledged_message_count)
+            })
+            .collect()
+    }
+
+    /// Counts the monitored items of the subscriptions per sampling interval, with the number of
+    /// those that are disabled.
+    pub(crate) fn sampling_interval_counts(&self, counts: &mut BTreeMap<u64, (u32, u32)>) {
+        self.subscriptions
+            .values()
+            .for_each(|subscription| subscription.sampling_interval_counts(counts));
+    }
+
     fn remove_notifications(&mut self, sequence_nrs_to_remove: &[(u32, u32)]) {
         sequence_nrs_to_remove.iter().for_each(|n| {
             trace!(
@@ -496,6 +584,11 @@ impl Subscriptions {
                 .take(remove_count)
                 .map(|(k, _)| *k)
                 .collect::<Vec<_>>();
+            for (subscription_id, _) in &sequence_nrs_to_remove {
+                if let Some(subscription) = self.subscriptions.get_mut(subscription_id) {
+                    subscription.counters_mut().discarded_message_count += 1;
+                }
+            }
             self.remove_notifications(&sequence_nrs_to_remove);
         }
     }
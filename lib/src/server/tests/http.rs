@@ -0,0 +1,379 @@
+use actix_web::{
+    http::{header, StatusCode as HttpStatusCode},
+    test as actix_test, web, App,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::server::{
+    http::{
+        self,
+        admin::{self, AdminState},
+        live, AppState,
+    },
+    server::Server,
+};
+
+use super::*;
+
+fn admin_server() -> Server {
+    ServerBuilder::new_sample()
+        .http_admin_token("secret")
+        .http_admin_user_token_id("sample_password_user")
+        .server()
+        .unwrap()
+}
+
+fn bearer(token: &str) -> (header::HeaderName, String) {
+    (header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+fn basic(user: &str, pass: &str) -> (header::HeaderName, String) {
+    let credentials = STANDARD.encode(format!("{}:{}", user, pass));
+    (header::AUTHORIZATION, format!("Basic {}", credentials))
+}
+
+#[test]
+fn http_admin_config() {
+    let config = ServerBuilder::new_sample().config();
+    assert!(!config.http_admin.is_enabled());
+    assert!(config.is_valid());
+
+    let config = admin_server().server_state().read().config.read().clone();
+    assert!(config.http_admin.is_enabled());
+    assert!(config.is_valid());
+    assert!(config.http_admin.authenticate_token("secret"));
+    assert!(!config.http_admin.authenticate_token("secret2"));
+    assert!(config
+        .http_admin
+        .authenticate_user(&config.user_tokens, "sample1", b"sample1pwd"));
+    assert!(!config
+        .http_admin
+        .authenticate_user(&config.user_tokens, "sample1", b"wrong"));
+    // Users without a token id in the admin settings have no access
+    assert!(!config
+        .http_admin
+        .authenticate_user(&config.user_tokens, "unused", b"unused1"));
+
+    // Only user name / password tokens can grant access
+    let config = ServerBuilder::new_sample()
+        .http_admin_user_token_id("sample_x509_user")
+        .config();
+    assert!(!config.is_valid());
+}
+
+#[actix_web::test]
+async fn http_admin_disabled() {
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(AdminState::new(&server)))
+            .configure(admin::configure),
+    )
+    .await;
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/sessions")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn http_admin_api() {
+    let server = admin_server();
+    add_sample_vars_to_address_space(server.address_space());
+    let ns = server
+        .address_space()
+        .read()
+        .namespace_index("urn:test")
+        .unwrap();
+
+    let session = Arc::new(RwLock::new(Session::new(server.server_state())));
+    let session_id = session.read().session_id().to_string();
+    server.session_manager().write().register_session(session);
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(AdminState::new(&server)))
+            .configure(admin::configure),
+    )
+    .await;
+
+    // Requests without valid credentials are refused
+    for authorization in [None, Some(bearer("wrong")), Some(basic("sample1", "wrong"))] {
+        let mut req = actix_test::TestRequest::get().uri("/admin/sessions");
+        if let Some(authorization) = authorization {
+            req = req.insert_header(authorization);
+        }
+        let resp = actix_test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), HttpStatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+    }
+
+    // Sessions can be listed with a bearer token or basic auth
+    for authorization in [bearer("secret"), basic("sample1", "sample1pwd")] {
+        let req = actix_test::TestRequest::get()
+            .uri("/admin/sessions")
+            .insert_header(authorization)
+            .to_request();
+        let sessions: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["session_id"], session_id.as_str());
+        assert_eq!(sessions[0]["subscription_count"], 0);
+    }
+
+    let req = actix_test::TestRequest::get()
+        .uri(&format!("/admin/sessions/{}/subscriptions", session_id))
+        .insert_header(bearer("secret"))
+        .to_request();
+    let subscriptions: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+    assert!(subscriptions["subscriptions"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+
+    // Browse the objects folder
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/nodes/browse?node_id=i%3D85")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let references: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+    let references = references.as_array().unwrap();
+    assert!(references
+        .iter()
+        .any(|r| r["browse_name"] == "Sample" && r["is_forward"] == true));
+    assert!(references
+        .iter()
+        .any(|r| r["node_id"] == "i=84" && r["is_forward"] == false));
+
+    // Write a variable and read it back
+    let req = actix_test::TestRequest::post()
+        .uri("/admin/nodes/write")
+        .insert_header(bearer("secret"))
+        .set_json(serde_json::json!({
+            "node_id": format!("ns={};s=v1", ns),
+            "value": { "Type": 6, "Body": 42 }
+        }))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::NO_CONTENT);
+    let value = server
+        .address_space()
+        .read()
+        .get_variable_value(NodeId::new(ns, "v1"))
+        .unwrap();
+    assert_eq!(value.value, Some(Variant::Int32(42)));
+
+    let req = actix_test::TestRequest::get()
+        .uri(&format!("/admin/nodes/read?node_id=ns%3D{}%3Bs%3Dv1", ns))
+        .insert_header(bearer("secret"))
+        .to_request();
+    let attributes: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+    let value = attributes
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|a| a["attribute"] == "Value")
+        .unwrap();
+    assert_eq!(value["value"]["Value"]["Body"], 42);
+
+    // Unknown nodes and bad node ids
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/nodes/read?node_id=ns%3D9%3Bs%3Dnothing")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::NOT_FOUND);
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/nodes/read?node_id=nonsense")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::BAD_REQUEST);
+
+    // Log level, setting it to what it already is
+    let req = actix_test::TestRequest::get()
+        .uri("/admin/log-level")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let log_level: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+    let req = actix_test::TestRequest::put()
+        .uri("/admin/log-level")
+        .insert_header(bearer("secret"))
+        .set_json(&log_level)
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::OK);
+    let req = actix_test::TestRequest::put()
+        .uri("/admin/log-level")
+        .insert_header(bearer("secret"))
+        .set_json(serde_json::json!({ "level": "loud" }))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::BAD_REQUEST);
+
+    // Close the session
+    let req = actix_test::TestRequest::delete()
+        .uri(&format!("/admin/sessions/{}", session_id))
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::NO_CONTENT);
+    assert_eq!(server.session_manager().read().len(), 0);
+    let req = actix_test::TestRequest::delete()
+        .uri(&format!("/admin/sessions/{}", session_id))
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::NOT_FOUND);
+}
+
+async fn next_message<S>(stream: &mut S) -> String
+where
+    S: futures::Stream<Item = Result<actix_web::web::Bytes, actix_web::Error>> + Unpin,
+{
+    use futures::StreamExt;
+    let message = stream.next().await.unwrap().unwrap();
+    String::from_utf8(message.to_vec()).unwrap()
+}
+
+#[actix_web::test]
+async fn http_live_events() {
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let server_state = server.server_state();
+    let diagnostics = server_state.read().diagnostics.clone();
+
+    // An event from before the listener started is sent first, then the metrics
+    diagnostics
+        .write()
+        .on_audit_event("Session closed".to_string());
+    let mut stream = Box::pin(live::event_stream(server_state.clone(), || {
+        "{\"sessions\":0}".to_string()
+    }));
+    let message = next_message(&mut stream).await;
+    assert!(message.starts_with("event: event\ndata: {"));
+    assert!(message.contains("\"kind\":\"audit\""));
+    assert!(message.contains("Session closed"));
+    assert!(message.ends_with("}\n\n"));
+    assert_eq!(
+        next_message(&mut stream).await,
+        "event: metrics\ndata: {\"sessions\":0}\n\n"
+    );
+
+    // Events are pushed as they happen, followed by fresh metrics
+    diagnostics
+        .write()
+        .on_secure_channel_error(StatusCode::BadSecurityChecksFailed, None);
+    let message = next_message(&mut stream).await;
+    assert!(message.contains("\"kind\":\"secure_channel_error\""));
+    assert!(message.contains("BadSecurityChecksFailed"));
+    assert_eq!(
+        next_message(&mut stream).await,
+        "event: metrics\ndata: {\"sessions\":0}\n\n"
+    );
+}
+
+#[actix_web::test]
+async fn http_live_events_need_admin() {
+    fn app_state(server: &Server) -> AppState {
+        AppState::new(
+            server.server_state(),
+            server.connections(),
+            server.server_metrics(),
+            ".",
+        )
+    }
+
+    // The feed is not served without the admin API
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(app_state(&server)))
+            .configure(http::configure),
+    )
+    .await;
+    let req = actix_test::TestRequest::get()
+        .uri("/server/events")
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::FORBIDDEN);
+    let req = actix_test::TestRequest::get()
+        .uri("/server/metrics")
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::OK);
+
+    // With the admin API it is only served to admin users
+    let server = admin_server();
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(app_state(&server)))
+            .app_data(web::Data::new(AdminState::new(&server)))
+            .configure(http::configure)
+            .configure(admin::configure),
+    )
+    .await;
+    let req = actix_test::TestRequest::get()
+        .uri("/server/events")
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::UNAUTHORIZED);
+    let req = actix_test::TestRequest::get()
+        .uri("/server/events")
+        .insert_header(basic("sample1", "sample1pwd"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/event-stream"
+    );
+}
+
+#[actix_web::test]
+async fn http_admin_abort() {
+    let server = admin_server();
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(AppState::new(
+                server.server_state(),
+                server.connections(),
+                server.server_metrics(),
+                ".",
+            )))
+            .app_data(web::Data::new(AdminState::new(&server)))
+            .configure(http::configure)
+            .configure(admin::configure),
+    )
+    .await;
+
+    // Abort is no longer an unauthenticated GET
+    for req in [
+        actix_test::TestRequest::get().uri("/server/abort"),
+        actix_test::TestRequest::get()
+            .uri("/admin/server/abort")
+            .insert_header(bearer("secret")),
+    ] {
+        let resp = actix_test::call_service(&app, req.to_request()).await;
+        assert!(resp.status().is_client_error());
+    }
+    let req = actix_test::TestRequest::post()
+        .uri("/admin/server/abort")
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert_eq!(resp.status(), HttpStatusCode::UNAUTHORIZED);
+    assert!(!server.server_state().read().is_abort());
+
+    let req = actix_test::TestRequest::post()
+        .uri("/admin/server/abort")
+        .insert_header(bearer("secret"))
+        .to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    if cfg!(debug_assertions) {
+        assert_eq!(resp.status(), HttpStatusCode::NO_CONTENT);
+        assert!(server.server_state().read().is_abort());
+    } else {
+        assert_eq!(resp.status(), HttpStatusCode::NOT_FOUND);
+    }
+}
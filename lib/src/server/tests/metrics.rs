@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use crate::server::{
+    diagnostics::{ServerEventKind, SERVICE_LATENCY_BUCKETS},
+    metrics::open_metrics,
+};
+
+use super::*;
+
+#[test]
+fn open_metrics_text() {
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let server_state = server.server_state();
+
+    {
+        let server_state = trace_read_lock!(server_state);
+        let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+        diagnostics.on_open_connection();
+        diagnostics.on_open_connection();
+        diagnostics.on_close_connection();
+        diagnostics.on_monitored_items_changed(5, 2);
+        diagnostics.on_publish_requests_changed(3, 1);
+        diagnostics.on_rejected_certificate(StatusCode::BadCertificateUntrusted);
+        diagnostics.on_security_rejected_request();
+        diagnostics.on_service_request("Read", Some(Duration::from_micros(300)), false);
+        diagnostics.on_service_request("Read", Some(Duration::from_millis(20)), true);
+        diagnostics.on_service_request("Publish", None, false);
+
+        let read = &diagnostics.services()["Read"];
+        assert_eq!(read.request_count, 2);
+        assert_eq!(read.error_count, 1);
+        assert_eq!(read.latency_count, 2);
+        // Buckets are cumulative
+        assert_eq!(read.latency_buckets[0], 1);
+        assert_eq!(read.latency_buckets[SERVICE_LATENCY_BUCKETS.len() - 1], 2);
+    }
+
+    let text = {
+        let server_state = trace_read_lock!(server_state);
+        open_metrics(&server_state)
+    };
+    let lines = text.lines().collect::<Vec<_>>();
+    let expected = [
+        "# TYPE opcua_connections gauge",
+        "opcua_connections 1",
+        "opcua_connections_opened_total 2",
+        "opcua_monitored_items 3",
+        "opcua_publish_requests_queued 2",
+        "opcua_requests_security_rejected_total 1",
+        "opcua_certificates_rejected_total{reason=\"BadCertificateUntrusted\"} 1",
+        "opcua_service_requests_total{service=\"Publish\"} 1",
+        "opcua_service_requests_total{service=\"Read\"} 2",
+        "opcua_service_errors_total{service=\"Read\"} 1",
+        "opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"0.0005\"} 1",
+        "opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"+Inf\"} 2",
+        "opcua_service_request_duration_seconds_count{service=\"Publish\"} 0",
+    ];
+    expected
+        .iter()
+        .for_each(|line| assert!(lines.contains(line), "Missing line {}", line));
+    assert_eq!(lines.last(), Some(&"# EOF"));
+}
+
+#[test]
+fn recent_server_events() {
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let server_state = server.server_state();
+    let server_state = trace_read_lock!(server_state);
+    let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+    let mut receiver = diagnostics.subscribe_events();
+
+    // Only the most recent events are kept
+    (0..105).for_each(|i| diagnostics.on_audit_event(format!("Event {}", i)));
+    let recent_events = diagnostics.recent_events();
+    assert_eq!(recent_events.len(), 100);
+    assert_eq!(recent_events.front().unwrap().message, "Event 5");
+    assert_eq!(recent_events.back().unwrap().message, "Event 104");
+
+    // Listeners receive every event that happened since they started listening
+    let event = receiver.try_recv().unwrap();
+    assert_eq!(event.kind, ServerEventKind::Audit);
+    assert_eq!(event.message, "Event 0");
+}
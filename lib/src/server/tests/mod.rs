@@ -17,6 +17,10 @@ use crate::{
 
 mod address_space;
 mod events;
+#[cfg(feature = "http")]
+mod http;
+mod metrics;
+mod redundancy;
 mod services;
 mod subscriptions;
 
@@ -174,3 +178,107 @@ pub fn expired_publish_requests() {
         }
     }
 }
+
+#[test]
+pub fn graceful_shutdown() {
+    use crate::types::service_types::ServerState as ServerStateType;
+
+    let mut server = ServerBuilder::new_sample().server().unwrap();
+    let server_state = server.server_state();
+    let address_space = server.address_space();
+
+    let read_value = |node_id: VariableId| {
+        address_space
+            .read()
+            .get_variable_value(node_id)
+            .unwrap()
+            .value
+            .unwrap()
+    };
+    assert_eq!(
+        read_value(VariableId::Server_ServerStatus_State),
+        Variant::Int32(ServerStateType::Running as i32)
+    );
+
+    server.shutdown(std::time::Duration::from_secs(10), "Maintenance");
+    {
+        let server_state = server_state.read();
+        assert_eq!(server_state.state(), ServerStateType::Shutdown);
+        assert!(server_state.is_shutting_down());
+        assert!(server_state.is_serving());
+        assert!(!server_state.is_shutdown_due());
+        assert!(!server_state.is_abort());
+        assert_eq!(server_state.seconds_till_shutdown(), 10);
+    }
+    assert_eq!(
+        read_value(VariableId::Server_ServerStatus_State),
+        Variant::Int32(ServerStateType::Shutdown as i32)
+    );
+    assert_eq!(
+        read_value(VariableId::Server_ServerStatus_SecondsTillShutdown),
+        Variant::UInt32(10)
+    );
+    assert_eq!(
+        read_value(VariableId::Server_ServerStatus_ShutdownReason),
+        Variant::from(LocalizedText::from("Maintenance"))
+    );
+
+    // When the delay expires, the connections are closed
+    server.shutdown(std::time::Duration::ZERO, "Maintenance");
+    {
+        let server_state = server_state.read();
+        assert!(server_state.is_shutdown_due());
+        assert_eq!(server_state.seconds_till_shutdown(), 0);
+    }
+    server.abort();
+    assert!(!server_state.read().is_serving());
+}
+
+#[test]
+pub fn cancel_publish_requests() {
+    let now = DateTime::now();
+    let mut session = Session::new_no_certificate_store();
+    {
+        let publish_request_queue = session.subscriptions_mut().publish_request_queue();
+        publish_request_queue.clear();
+        for (request_id, request_handle) in [(1, 1000), (2, 2000)] {
+            publish_request_queue.push_front(PublishRequestEntry {
+                request_id,
+                request: PublishRequest {
+                    request_header: RequestHeader::new(&NodeId::null(), &now, request_handle),
+                    subscription_acknowledgements: None,
+                },
+                results: None,
+            });
+        }
+    }
+
+    // Every queued request is answered, oldest first
+    session.cancel_publish_requests(StatusCode::BadShutdown);
+    assert!(session
+        .subscriptions_mut()
+        .publish_request_queue()
+        .is_empty());
+    let publish_responses = session
+        .subscriptions_mut()
+        .take_publish_responses()
+        .unwrap();
+    let responses = publish_responses
+        .iter()
+        .map(|r| match r.response {
+            SupportedMessage::ServiceFault(ref fault) => (
+                r.request_id,
+                fault.response_header.request_handle,
+                fault.response_header.service_result,
+            ),
+            _ => panic!("Expected service faults for cancelled publish requests"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        responses,
+        vec![
+            (1, 1000, StatusCode::BadShutdown),
+            (2, 2000, StatusCode::BadShutdown)
+        ]
+    );
+}
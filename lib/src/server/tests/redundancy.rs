@@ -0,0 +1,145 @@
+use crate::server::{
+    config::ServerRedundancy,
+    redundancy::{self, RedundancyMessage, ServerHealth},
+    server::Server,
+};
+use crate::types::service_types::{
+    RedundancySupport, RedundantServerDataType, ServerState as ServerStateType,
+};
+
+use super::*;
+
+const PEER_URI: &str = "urn:peer";
+
+fn redundant_server() -> (Server, String) {
+    let config = ServerBuilder::new_sample().config();
+    let application_uri = config.application_uri.clone();
+    let server = ServerBuilder::from_config(config)
+        .redundancy(ServerRedundancy {
+            support: "Hot".to_string(),
+            server_uris: vec![application_uri.clone(), PEER_URI.to_string()],
+            ..Default::default()
+        })
+        .server()
+        .unwrap();
+    (server, application_uri)
+}
+
+fn variable_value(server: &Server, node_id: VariableId) -> Variant {
+    let address_space = server.address_space();
+    let address_space = trace_read_lock!(address_space);
+    address_space
+        .get_variable_value(node_id)
+        .unwrap()
+        .value
+        .unwrap()
+}
+
+fn redundant_servers(server: &Server) -> Vec<RedundantServerDataType> {
+    let Variant::Array(array) = variable_value(
+        server,
+        VariableId::Server_ServerRedundancy_RedundantServerArray,
+    ) else {
+        panic!("RedundantServerArray is not an array");
+    };
+    array
+        .values
+        .iter()
+        .map(|value| match value {
+            Variant::ExtensionObject(obj) => obj.decode_inner(&DecodingOptions::test()).unwrap(),
+            _ => panic!("RedundantServerArray holds {:?}", value),
+        })
+        .collect()
+}
+
+#[test]
+fn server_health_service_level() {
+    assert_eq!(ServerHealth::Maintenance.service_level(), 0);
+    assert_eq!(ServerHealth::NoData.service_level(), 1);
+    assert_eq!(ServerHealth::Degraded(0).service_level(), 2);
+    assert_eq!(ServerHealth::Degraded(150).service_level(), 150);
+    assert_eq!(ServerHealth::Degraded(250).service_level(), 199);
+    assert_eq!(ServerHealth::Healthy(10).service_level(), 200);
+    assert_eq!(ServerHealth::Healthy(255).service_level(), 255);
+}
+
+#[test]
+fn server_redundancy_config() {
+    let mut redundancy = ServerRedundancy::default();
+    assert!(!redundancy.is_enabled());
+    assert!(redundancy.is_valid("urn:server"));
+
+    redundancy.support = "Warm".to_string();
+    assert!(redundancy.is_enabled());
+    // The set must contain the server
+    assert!(!redundancy.is_valid("urn:server"));
+    redundancy.server_uris = vec!["urn:server".to_string(), PEER_URI.to_string()];
+    assert!(redundancy.is_valid("urn:server"));
+
+    redundancy.support = "Transparent".to_string();
+    assert!(!redundancy.is_valid("urn:server"));
+}
+
+#[test]
+fn server_redundancy_subscription_ids() {
+    let redundancy = ServerRedundancy {
+        support: "Hot".to_string(),
+        server_uris: vec!["urn:a".to_string(), "urn:b".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(redundancy::last_subscription_id(&redundancy, "urn:a"), 0);
+    assert_eq!(
+        redundancy::last_subscription_id(&redundancy, "urn:b"),
+        0x8000_0000
+    );
+    assert_eq!(
+        redundancy::last_subscription_id(&ServerRedundancy::default(), "urn:b"),
+        0
+    );
+}
+
+#[test]
+fn server_redundancy_address_space() {
+    let (server, application_uri) = redundant_server();
+
+    assert_eq!(
+        variable_value(
+            &server,
+            VariableId::Server_ServerRedundancy_RedundancySupport
+        ),
+        Variant::Int32(RedundancySupport::Hot as i32)
+    );
+    assert_eq!(
+        variable_value(&server, VariableId::Server_ServerRedundancy_ServerUriArray),
+        Variant::from(&vec![application_uri.clone(), PEER_URI.to_string()])
+    );
+
+    server.set_health(ServerHealth::Degraded(150));
+    assert_eq!(
+        variable_value(&server, VariableId::Server_ServiceLevel),
+        Variant::Byte(150)
+    );
+    let servers = redundant_servers(&server);
+    assert_eq!(servers.len(), 2);
+    assert_eq!(servers[0].server_id.as_ref(), application_uri);
+    assert_eq!(servers[0].service_level, 150);
+    assert_eq!(servers[0].server_state, ServerStateType::Running);
+    // Nothing has been heard from the other server
+    assert_eq!(servers[1].server_id.as_ref(), PEER_URI);
+    assert_eq!(servers[1].service_level, 0);
+    assert_eq!(servers[1].server_state, ServerStateType::Unknown);
+
+    server.receive_redundancy_message(RedundancyMessage::ServiceLevel {
+        server_uri: PEER_URI.to_string(),
+        service_level: 220,
+    });
+    // Servers outside of the set are ignored
+    server.receive_redundancy_message(RedundancyMessage::ServiceLevel {
+        server_uri: "urn:other".to_string(),
+        service_level: 255,
+    });
+    let servers = redundant_servers(&server);
+    assert_eq!(servers.len(), 2);
+    assert_eq!(servers[1].service_level, 220);
+    assert_eq!(servers[1].server_state, ServerStateType::Running);
+}
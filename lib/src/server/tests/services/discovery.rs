@@ -1,4 +1,4 @@
-use crate::server::services::discovery::DiscoveryService;
+use crate::server::{comms::transport::PeerCredentials, services::discovery::DiscoveryService};
 use crate::supported_message_as;
 use crate::sync::*;
 use crate::types::UAString;
@@ -20,6 +20,7 @@ where
 #[test]
 fn get_endpoints() {
     do_discovery_service_test(|server_state, _session, ds| {
+        let listener = default_listener(&server_state.read());
         let request = GetEndpointsRequest {
             request_header: make_request_header(),
             endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
@@ -27,7 +28,7 @@ fn get_endpoints() {
             profile_uris: None,
         };
 
-        let result = ds.get_endpoints(server_state, &request);
+        let result = ds.get_endpoints(&listener, server_state, &request);
         let result = supported_message_as!(result, GetEndpointsResponse);
 
         // Verify endpoints
@@ -71,6 +72,7 @@ fn find_servers() {
 #[test]
 fn discovery_test() {
     do_discovery_service_test(|server_state, _session, ds| {
+        let listener = default_listener(&server_state.read());
         let endpoint_url = UAString::from("opc.tcp://localhost:4855/");
         {
             let request = GetEndpointsRequest {
@@ -80,13 +82,17 @@ fn discovery_test() {
                 profile_uris: None,
             };
 
-            let result = ds.get_endpoints(server_state.clone(), &request);
+            let result = ds.get_endpoints(&listener, server_state.clone(), &request);
             let result = supported_message_as!(result, GetEndpointsResponse);
 
-            // Verify endpoints
+            // Verify endpoints. The sample endpoints with deprecated policies are disabled by
+            // the default security profile.
             let endpoints = result.endpoints.unwrap();
             assert!(!endpoints.is_empty());
-            assert_eq!(endpoints.len(), 12);
+            assert_eq!(endpoints.len(), 8);
+            assert!(endpoints.iter().all(|e| {
+                !SecurityPolicy::from_uri(e.security_policy_uri.as_ref()).is_deprecated()
+            }));
         }
 
         // specify profile ids in request
@@ -99,7 +105,7 @@ fn discovery_test() {
                 locale_ids: None,
                 profile_uris: Some(profile_uris),
             };
-            let result = ds.get_endpoints(server_state.clone(), &request);
+            let result = ds.get_endpoints(&listener, server_state.clone(), &request);
             let result = supported_message_as!(result, GetEndpointsResponse);
             assert!(result.endpoints.is_none());
 
@@ -113,10 +119,165 @@ fn discovery_test() {
                 locale_ids: None,
                 profile_uris: Some(profile_uris),
             };
-            let result = ds.get_endpoints(server_state.clone(), &request);
+            let result = ds.get_endpoints(&listener, server_state.clone(), &request);
             let result = supported_message_as!(result, GetEndpointsResponse);
             let endpoints = result.endpoints.unwrap();
             assert!(!endpoints.is_empty())
         }
     });
 }
+
+#[test]
+fn get_endpoints_per_listener() {
+    let plant = ServerListener::new("10.0.0.5", 4855, &["basic256sha256_sign_encrypt"]);
+    let local = ServerListener::new("127.0.0.1", 4855, &["none"]);
+    let ipv6 = ServerListener::new("::1", 4856, &[]);
+    let st = ServiceTest::new_with_server(
+        ServerBuilder::new_sample()
+            .listener(plant.clone())
+            .listener(local.clone())
+            .listener(ipv6.clone()),
+    );
+    let server_state = st.server_state;
+    assert_eq!(
+        server_state.read().config.read().listeners(),
+        vec![plant.clone(), local.clone(), ipv6.clone()]
+    );
+
+    let ds = DiscoveryService::new();
+    let get_endpoints = |listener: &ServerListener| {
+        let request = GetEndpointsRequest {
+            request_header: make_request_header(),
+            endpoint_url: UAString::from(format!("{}/", listener.base_endpoint_url())),
+            locale_ids: None,
+            profile_uris: None,
+        };
+        let result = ds.get_endpoints(listener, server_state.clone(), &request);
+        let result = supported_message_as!(result, GetEndpointsResponse);
+        result
+            .endpoints
+            .unwrap()
+            .into_iter()
+            .map(|e| {
+                (
+                    e.endpoint_url.to_string(),
+                    SecurityPolicy::from_uri(e.security_policy_uri.as_ref()),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Each listener only offers its own endpoints, with urls of the listener
+    assert_eq!(
+        get_endpoints(&plant),
+        vec![(
+            "opc.tcp://10.0.0.5:4855/".to_string(),
+            SecurityPolicy::Basic256Sha256
+        )]
+    );
+    assert_eq!(
+        get_endpoints(&local),
+        vec![(
+            "opc.tcp://127.0.0.1:4855/".to_string(),
+            SecurityPolicy::None
+        )]
+    );
+    let endpoints = get_endpoints(&ipv6);
+    assert_eq!(endpoints.len(), 8);
+    assert!(endpoints
+        .iter()
+        .all(|(url, _)| url.starts_with("opc.tcp://[::1]:4856/")));
+
+    // Sessions can only use an endpoint offered through the listener they connected through
+    let server_state = server_state.read();
+    assert!(server_state.endpoint_exists(
+        &local,
+        "opc.tcp://127.0.0.1:4855/",
+        SecurityPolicy::None,
+        MessageSecurityMode::None
+    ));
+    assert!(!server_state.endpoint_exists(
+        &plant,
+        "opc.tcp://10.0.0.5:4855/",
+        SecurityPolicy::None,
+        MessageSecurityMode::None
+    ));
+    assert!(server_state
+        .new_endpoint_descriptions(&plant, "opc.tcp://localhost:4855/")
+        .is_some());
+    assert!(server_state
+        .new_endpoint_descriptions(&local, "opc.tcp://localhost:4855/noaccess")
+        .is_none());
+}
+
+#[test]
+fn listener_config() {
+    let config = ServerBuilder::new_sample()
+        .listener(ServerListener::new("127.0.0.1", 4855, &["none"]))
+        .config();
+    assert!(config.is_valid());
+
+    // Endpoints must exist
+    let config = ServerBuilder::new_sample()
+        .listener(ServerListener::new("127.0.0.1", 4855, &["nothing"]))
+        .config();
+    assert!(!config.is_valid());
+
+    // Listeners must be distinct
+    let config = ServerBuilder::new_sample()
+        .listener(ServerListener::new("127.0.0.1", 4855, &["none"]))
+        .listener(ServerListener::new("127.0.0.1", 4855, &["no_access"]))
+        .config();
+    assert!(!config.is_valid());
+}
+
+#[test]
+#[cfg(unix)]
+fn unix_listener_config() {
+    let listener = ServerListener::new_unix("/tmp/opcua.sock", &["none"]);
+    assert_eq!(listener.base_endpoint_url(), "opc.uds:///tmp/opcua.sock");
+    let config = ServerBuilder::new_sample()
+        .listener(listener.clone())
+        .listener(ServerListener::new("127.0.0.1", 4855, &[]))
+        .config();
+    assert!(config.is_valid());
+    let endpoints: Vec<_> = config.listener_endpoints(&listener).collect();
+    assert_eq!(endpoints.len(), 1);
+    assert_eq!(
+        endpoints[0].1.endpoint_url(&listener.base_endpoint_url()),
+        "opc.uds:///tmp/opcua.sock/"
+    );
+
+    // Socket paths must be absolute
+    let config = ServerBuilder::new_sample()
+        .listener(ServerListener::new_unix("opcua.sock", &[]))
+        .config();
+    assert!(!config.is_valid());
+
+    // Peer credentials are only known for Unix domain sockets
+    let mut tcp_listener = ServerListener::new("127.0.0.1", 4855, &[]);
+    tcp_listener.peer_uids.insert(1000);
+    let config = ServerBuilder::new_sample().listener(tcp_listener).config();
+    assert!(!config.is_valid());
+}
+
+#[test]
+fn listener_accepts_peer() {
+    let peer = PeerCredentials {
+        uid: 1000,
+        gid: 100,
+        pid: Some(1),
+    };
+    let mut listener = ServerListener::new_unix("/tmp/opcua.sock", &[]);
+    assert!(listener.accepts_peer(Some(&peer)));
+    assert!(listener.accepts_peer(None));
+
+    listener.peer_uids.insert(1001);
+    assert!(!listener.accepts_peer(Some(&peer)));
+    assert!(!listener.accepts_peer(None));
+    listener.peer_gids.insert(100);
+    assert!(listener.accepts_peer(Some(&peer)));
+    listener.peer_gids.clear();
+    listener.peer_uids.insert(1000);
+    assert!(listener.accepts_peer(Some(&peer)));
+}
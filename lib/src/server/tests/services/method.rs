@@ -6,6 +6,7 @@ use crate::types::{
     status_code::StatusCode,
 };
 
+use crate::server::constants;
 use crate::server::services::{
     method::MethodService, monitored_item::MonitoredItemService, subscription::SubscriptionService,
 };
@@ -390,3 +391,91 @@ fn call_resend_data() {
         }
     });
 }
+
+#[test]
+fn call_set_subscription_durable() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let call_set_subscription_durable = |subscription_id: u32, lifetime_in_hours: u32| {
+            let args: Vec<Variant> = vec![subscription_id.into(), lifetime_in_hours.into()];
+            let request = new_call_method_request(
+                ObjectId::Server,
+                MethodId::Server_SetSubscriptionDurable,
+                Some(args),
+            );
+            call_single(
+                s,
+                server_state.clone(),
+                session_manager.clone(),
+                session.clone(),
+                address_space.clone(),
+                request,
+            )
+            .unwrap()
+        };
+
+        // Call with invalid subscription id
+        let response = call_set_subscription_durable(100, 1);
+        assert_eq!(response.status_code, StatusCode::BadSubscriptionIdInvalid);
+
+        let ss = SubscriptionService::new();
+        let mis = MonitoredItemService::new();
+        let subscription_id = {
+            let request = create_subscription_request();
+            let response: CreateSubscriptionResponse = supported_message_as!(
+                ss.create_subscription(server_state.clone(), session.clone(), &request),
+                CreateSubscriptionResponse
+            );
+            response.subscription_id
+        };
+
+        // The lifetime is revised to the maximum lifetime
+        let response = call_set_subscription_durable(subscription_id, 1000);
+        assert_eq!(response.status_code, StatusCode::Good);
+        assert_eq!(
+            response.output_arguments.unwrap(),
+            vec![Variant::from(
+                constants::DEFAULT_MAX_DURABLE_SUBSCRIPTION_LIFETIME_HOURS
+            )]
+        );
+        {
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            assert!(subscription.is_durable());
+            // 24 hours at a publishing interval of 100ms
+            assert_eq!(subscription.max_lifetime_count(), 24 * 36000);
+        }
+
+        // Monitored items of a durable subscription may queue more values
+        {
+            let mut request = create_monitored_items_request(
+                subscription_id,
+                999,
+                VariableId::Server_ServerStatus_CurrentTime,
+            );
+            request.items_to_create.as_mut().unwrap()[0]
+                .requested_parameters
+                .queue_size = 20000;
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            assert_eq!(
+                response.results.unwrap()[0].revised_queue_size as usize,
+                constants::DEFAULT_MAX_DURABLE_MONITORED_ITEM_QUEUE_SIZE
+            );
+        }
+
+        // A subscription with monitored items cannot be made durable
+        let response = call_set_subscription_durable(subscription_id, 1);
+        assert_eq!(response.status_code, StatusCode::BadInvalidState);
+    });
+}
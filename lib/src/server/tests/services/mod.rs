@@ -45,6 +45,12 @@ impl ServiceTest {
     }
 }
 
+/// Returns the listener that the sample server listens on
+fn default_listener(server_state: &ServerState) -> ServerListener {
+    let config = server_state.config.read();
+    config.listeners().remove(0)
+}
+
 fn make_request_header() -> RequestHeader {
     RequestHeader {
         authentication_token: NodeId::new(0, 99),
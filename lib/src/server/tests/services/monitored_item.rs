@@ -9,6 +9,7 @@ use crate::{
         services::{monitored_item::MonitoredItemService, subscription::SubscriptionService},
         subscriptions::{
             monitored_item::*,
+            sampling::{SampledItems, SamplingSchedules},
             subscription::{SubscriptionState, TickReason},
         },
     },
@@ -267,6 +268,14 @@ where
     now
 }
 
+/// Samples the monitored items of all subscriptions as the server's sampling task would when
+/// every sampling schedule is due
+fn sample_values(server_state: Arc<RwLock<ServerState>>, address_space: Arc<RwLock<AddressSpace>>) {
+    let server_state = trace_read_lock!(server_state);
+    let address_space = trace_read_lock!(address_space);
+    trace_lock!(server_state.sampling_schedules).sample_all(&address_space);
+}
+
 fn populate_monitored_item(server_state: &ServerState, discard_oldest: bool) -> MonitoredItem {
     let client_handle = 999;
     let mut monitored_item = MonitoredItem::new(
@@ -459,16 +468,24 @@ fn monitored_item_data_change_filter() {
             let server_state = trace_read_lock!(server_state);
 
             // Create request should monitor attribute of variable, e.g. value
-            // Sample interval is negative so it will always test on repeated calls
             let mut monitored_item = MonitoredItem::new(
                 &chrono::Utc::now(),
                 1,
                 TimestampsToReturn::Both,
                 &server_state,
-                &make_create_request_data_change_filter(-1f64, 5),
+                &make_create_request_data_change_filter(100f64, 5),
             )
             .unwrap();
 
+            // The item is given the values sampled by the schedule of its interval
+            let mut sampling_schedules = SamplingSchedules::default();
+            let sampled_items = SampledItems::default();
+            monitored_item.add_to_sampling_schedule(
+                &mut sampling_schedules,
+                &address_space,
+                &sampled_items,
+            );
+
             let now = Utc::now();
 
             assert_eq!(monitored_item.notification_queue().len(), 0);
@@ -507,11 +524,26 @@ fn monitored_item_data_change_filter() {
                 panic!("Expected a variable, didn't get one!!");
             }
 
-            // Expect change but only when subscription timer elapsed
+            // Expect no change until the schedule samples the value
             assert_eq!(
                 monitored_item.tick(&now, &address_space, false, false),
                 TickResult::NoChange
             );
+            sampling_schedules.sample(
+                &address_space,
+                std::time::Instant::now() + std::time::Duration::from_secs(1),
+            );
+            assert_eq!(
+                sampled_items.lock().iter().copied().collect::<Vec<_>>(),
+                vec![1]
+            );
+
+            // Expect the sampled change, which is reported again when the subscription timer
+            // elapsed while it is still queued
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, false, false),
+                TickResult::ReportValueChanged
+            );
             assert_eq!(
                 monitored_item.tick(&now, &address_space, true, false),
                 TickResult::ReportValueChanged
@@ -521,6 +553,144 @@ fn monitored_item_data_change_filter() {
     )
 }
 
+#[test]
+fn monitored_item_node_deleted() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_data_change_filter(100f64, 5),
+            )
+            .unwrap();
+            let mut sampling_schedules = SamplingSchedules::default();
+            let sampled_items = SampledItems::default();
+            monitored_item.add_to_sampling_schedule(
+                &mut sampling_schedules,
+                &address_space,
+                &sampled_items,
+            );
+
+            let now = Utc::now();
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+
+            // Deleting the node tells the monitored item that it is gone
+            assert!(address_space.delete(&test_var_node_id(), true));
+            sampling_schedules.sample(
+                &address_space,
+                std::time::Instant::now() + std::time::Duration::from_secs(1),
+            );
+            assert_eq!(
+                sampled_items.lock().iter().copied().collect::<Vec<_>>(),
+                vec![1]
+            );
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, false, false),
+                TickResult::ReportValueChanged
+            );
+            assert_eq!(monitored_item.notification_queue().len(), 2);
+            let _ = monitored_item.oldest_notification_message();
+            match monitored_item.oldest_notification_message().unwrap() {
+                Notification::MonitoredItemNotification(notification) => {
+                    assert_eq!(notification.value.status(), StatusCode::BadNodeIdUnknown);
+                    assert!(notification.value.value.is_none());
+                }
+                _ => panic!("Expected a data change"),
+            }
+        },
+    )
+}
+
+#[test]
+fn monitored_item_sampled_values_capped() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_data_change_filter(100f64, 3),
+            )
+            .unwrap();
+            monitored_item.set_discard_oldest(true);
+            let mut sampling_schedules = SamplingSchedules::default();
+            let sampled_items = SampledItems::default();
+            monitored_item.add_to_sampling_schedule(
+                &mut sampling_schedules,
+                &address_space,
+                &sampled_items,
+            );
+
+            let now = Utc::now();
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            let _ = monitored_item.oldest_notification_message();
+
+            // Sample more values than the queue holds before the item is ticked
+            let mut sample_time = std::time::Instant::now();
+            for i in 1..=5 {
+                if let &mut NodeType::Variable(ref mut node) =
+                    address_space.find_node_mut(&test_var_node_id()).unwrap()
+                {
+                    node.set_value(NumericRange::None, Variant::UInt32(i))
+                        .unwrap();
+                }
+                sample_time += std::time::Duration::from_secs(1);
+                sampling_schedules.sample(&address_space, sample_time);
+            }
+
+            // Only the newest values are kept and the newest has the overflow bit set
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, false, false),
+                TickResult::ReportValueChanged
+            );
+            assert_eq!(monitored_item.queue_overflow_count(), 2);
+            assert!(monitored_item.queue_overflow());
+            let values = monitored_item
+                .notification_queue()
+                .iter()
+                .map(|notification| match notification {
+                    Notification::MonitoredItemNotification(notification) => (
+                        notification.value.value.clone().unwrap(),
+                        notification.value.status().contains(StatusCode::OVERFLOW),
+                    ),
+                    _ => panic!("Expected a data change"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                values,
+                vec![
+                    (Variant::UInt32(3), false),
+                    (Variant::UInt32(4), false),
+                    (Variant::UInt32(5), true)
+                ]
+            );
+        },
+    )
+}
+
 #[test]
 fn monitored_item_event_filter() {
     // create an address space
@@ -865,6 +1035,7 @@ fn monitored_item_triggers() {
                     &DateTime::from(now.clone()),
                 );
             }
+            sample_values(server_state.clone(), address_space.clone());
 
             // In this case, the triggering item changes, but triggered items are all reporting so are ignored unless they themselves
             // need to report. Only 3 will fire because it was disabled previously
@@ -932,6 +1103,7 @@ fn monitored_item_triggers() {
                     &DateTime::from(now.clone()),
                 );
             }
+            sample_values(server_state.clone(), address_space.clone());
 
             // do a publish on the monitored item,
             let now = publish_tick_response(
@@ -975,6 +1147,7 @@ fn monitored_item_triggers() {
                     &DateTime::from(now.clone()),
                 );
             }
+            sample_values(server_state.clone(), address_space.clone());
 
             // do a publish on the monitored item, expect 0 data changes
             let _ = publish_tick_no_response(
@@ -988,6 +1161,123 @@ fn monitored_item_triggers() {
     );
 }
 
+#[test]
+fn monitored_item_value_changes() {
+    // Sampling schedules only read values stored in the address space when the address space
+    // says their node changed, values from a getter are read every time
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            // The value of the second variable comes from a getter that changes on every call
+            {
+                let mut address_space = trace_write_lock!(address_space);
+                let mut value = 0;
+                let getter = AttrFnGetter::new(move |_, _, _, _, _, _| {
+                    value += 1;
+                    Ok(Some(DataValue::new_now(value)))
+                });
+                address_space
+                    .find_variable_mut(var_node_id(1))
+                    .unwrap()
+                    .set_value_getter(Arc::new(Mutex::new(getter)));
+            }
+
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+            {
+                let mut session = trace_write_lock!(session);
+                session
+                    .subscriptions_mut()
+                    .get_mut(subscription_id)
+                    .unwrap()
+                    .set_state(SubscriptionState::Normal);
+            }
+
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![var_node_id(0), var_node_id(1)],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            response
+                .results
+                .unwrap()
+                .iter()
+                .for_each(|r| assert_eq!(r.status_code, StatusCode::Good));
+
+            let client_handles = |response: PublishResponse| {
+                let (notifications, _) = response
+                    .notification_message
+                    .notifications(&DecodingOptions::test())
+                    .unwrap();
+                assert_eq!(notifications.len(), 1);
+                notifications[0]
+                    .monitored_items
+                    .as_ref()
+                    .unwrap()
+                    .iter()
+                    .map(|min| min.client_handle)
+                    .collect::<HashSet<u32>>()
+            };
+
+            // Both items report their initial value
+            let now = publish_tick_response(
+                session.clone(),
+                &ss,
+                address_space.clone(),
+                Utc::now(),
+                chrono::Duration::seconds(2),
+                |response| assert_eq!(client_handles(response), HashSet::from([0, 1])),
+            );
+
+            // Only the getter changes
+            sample_values(server_state.clone(), address_space.clone());
+            let now = publish_tick_response(
+                session.clone(),
+                &ss,
+                address_space.clone(),
+                now,
+                chrono::Duration::seconds(2),
+                |response| assert_eq!(client_handles(response), HashSet::from([1])),
+            );
+
+            // Changing the variable through the address space is pushed to its item
+            {
+                let mut address_space = trace_write_lock!(address_space);
+                let _ = address_space
+                    .find_variable_mut(var_node_id(0))
+                    .unwrap()
+                    .set_value(NumericRange::None, 100);
+            }
+            sample_values(server_state.clone(), address_space.clone());
+            let _ = publish_tick_response(
+                session.clone(),
+                &ss,
+                address_space.clone(),
+                now,
+                chrono::Duration::seconds(2),
+                |response| assert_eq!(client_handles(response), HashSet::from([0, 1])),
+            );
+        },
+    );
+}
+
 #[test]
 fn monitored_item_queue_discard_oldest() {
     // The purpose of this test is to monitor the discard oldest behaviour. Depending on true/false
@@ -1023,3 +1313,104 @@ fn monitored_item_queue_discard_oldest() {
         },
     );
 }
+
+#[test]
+fn monitored_item_sampling_schedules() {
+    // Items sampling at the same interval share a schedule across subscriptions and the revised
+    // sampling interval is the interval of the schedule the item is on
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            {
+                let mut address_space = trace_write_lock!(address_space);
+                address_space
+                    .find_variable_mut(var_node_id(2))
+                    .unwrap()
+                    .set_minimum_sampling_interval(500f64);
+            }
+
+            let create_monitored_items = |items: &[(usize, f64)]| {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                let subscription_id = response.subscription_id;
+                let items_to_create = items
+                    .iter()
+                    .map(|(idx, sampling_interval)| {
+                        let mut item = make_create_request(
+                            *sampling_interval,
+                            1,
+                            var_node_id(*idx),
+                            AttributeId::Value,
+                            ExtensionObject::null(),
+                        );
+                        item.requested_parameters.client_handle = *idx as u32;
+                        item
+                    })
+                    .collect::<Vec<_>>();
+                let request = CreateMonitoredItemsRequest {
+                    request_header: RequestHeader::dummy(),
+                    subscription_id,
+                    timestamps_to_return: TimestampsToReturn::Both,
+                    items_to_create: Some(items_to_create),
+                };
+                let response: CreateMonitoredItemsResponse = supported_message_as!(
+                    mis.create_monitored_items(
+                        server_state.clone(),
+                        session.clone(),
+                        address_space.clone(),
+                        &request
+                    ),
+                    CreateMonitoredItemsResponse
+                );
+                let results = response.results.unwrap();
+                results
+                    .iter()
+                    .for_each(|r| assert_eq!(r.status_code, StatusCode::Good));
+                (subscription_id, results)
+            };
+
+            // -1 is the publishing interval, 0.1 is the minimum sampling interval of the server
+            // but the variable cannot be sampled faster than its own minimum sampling interval
+            let (subscription_id, results) =
+                create_monitored_items(&[(0, 250f64), (1, -1f64), (2, 0.1f64)]);
+            let revised_sampling_intervals = results
+                .iter()
+                .map(|r| r.revised_sampling_interval)
+                .collect::<Vec<_>>();
+            assert_eq!(revised_sampling_intervals, vec![250f64, 100f64, 500f64]);
+            let monitored_item_ids = results
+                .iter()
+                .map(|r| r.monitored_item_id)
+                .collect::<Vec<_>>();
+
+            let (_, results) = create_monitored_items(&[(0, 250f64)]);
+            assert_eq!(results[0].revised_sampling_interval, 250f64);
+
+            let sampling_intervals = || {
+                let server_state = trace_read_lock!(server_state);
+                let sampling_schedules = trace_lock!(server_state.sampling_schedules);
+                sampling_schedules.sampling_intervals()
+            };
+            assert_eq!(sampling_intervals(), vec![100f64, 250f64, 500f64]);
+
+            // Schedules without items are dropped when they are next due
+            let request = DeleteMonitoredItemsRequest {
+                request_header: RequestHeader::dummy(),
+                subscription_id,
+                monitored_item_ids: Some(monitored_item_ids),
+            };
+            let _: DeleteMonitoredItemsResponse = supported_message_as!(
+                mis.delete_monitored_items(session.clone(), &request),
+                DeleteMonitoredItemsResponse
+            );
+            sample_values(server_state.clone(), address_space.clone());
+            assert_eq!(sampling_intervals(), vec![250f64]);
+        },
+    );
+}
@@ -1,15 +1,28 @@
-use crate::crypto::{random, user_identity::make_user_name_identity_token, SecurityPolicy};
-use crate::types::{ActivateSessionRequest, RequestHeader, SignatureData};
+use crate::crypto::{
+    password::{hash_password, PasswordHashAlgorithm},
+    random,
+    user_identity::make_user_name_identity_token,
+    PrivateKey, SecurityPolicy, SecurityProfile, X509Data,
+};
+use crate::types::{
+    profiles, ActivateSessionRequest, IssuedIdentityToken, RequestHeader, SignatureData,
+    UserTokenType,
+};
 
 use crate::server::{
     builder::ServerBuilder,
+    comms::transport::ClientConnection,
+    config::{ServerJwtConfig, ServerUserToken},
     identity_token::{
-        POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15, POLICY_ID_USER_PASS_RSA_OAEP,
+        POLICY_ID_ISSUED_JWT, POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15,
+        POLICY_ID_USER_PASS_RSA_OAEP,
     },
     services::session::SessionService,
+    session::Session,
     state::ServerState,
     tests::*,
 };
+use crate::supported_message_as;
 
 use super::*;
 
@@ -30,6 +43,31 @@ fn dummy_activate_session_request() -> ActivateSessionRequest {
     }
 }
 
+/// Authenticates the token on an endpoint offered through the listener of the sample server
+fn authenticate_endpoint(
+    server_state: &ServerState,
+    endpoint_url: &str,
+    security_policy: SecurityPolicy,
+    security_mode: MessageSecurityMode,
+    user_identity_token: &ExtensionObject,
+    server_nonce: &ByteString,
+) -> Result<String, StatusCode> {
+    let request = ActivateSessionRequest {
+        user_identity_token: user_identity_token.clone(),
+        ..dummy_activate_session_request()
+    };
+    server_state.authenticate_endpoint(
+        &default_listener(server_state),
+        &request,
+        endpoint_url,
+        security_policy,
+        security_mode,
+        server_state.server_certificate.as_ref(),
+        server_state.server_pkey.as_ref(),
+        server_nonce,
+    )
+}
+
 /// A helper that sets up a subscription service test
 fn do_session_service_test<T>(pki_dir: Option<&str>, f: T)
 where
@@ -37,7 +75,11 @@ where
 {
     crate::console_logging::init();
 
-    let mut server_builder = ServerBuilder::new_sample();
+    // The tests cover user tokens encrypted by the deprecated policies too
+    let mut server_builder = ServerBuilder::new_sample().security_profile(SecurityProfile {
+        allow_deprecated_policies: true,
+        ..Default::default()
+    });
     if let Some(pki_dir) = pki_dir {
         server_builder = server_builder.pki_dir(pki_dir);
     };
@@ -62,10 +104,8 @@ fn anonymous_user_token() {
 
         let server_nonce = random::byte_string(20);
 
-        let request = dummy_activate_session_request();
-
-        let result = server_state.authenticate_endpoint(
-            &request,
+        let result = authenticate_endpoint(
+            &server_state,
             "opc.tcp://localhost:4855/",
             SecurityPolicy::None,
             MessageSecurityMode::None,
@@ -75,8 +115,8 @@ fn anonymous_user_token() {
         trace!("result = {:?}", result);
         assert!(result.is_ok());
 
-        let result = server_state.authenticate_endpoint(
-            &request,
+        let result = authenticate_endpoint(
+            &server_state,
             "opc.tcp://localhost:4855/x",
             SecurityPolicy::None,
             MessageSecurityMode::None,
@@ -86,8 +126,8 @@ fn anonymous_user_token() {
         trace!("result = {:?}", result);
         assert_eq!(result.unwrap_err(), StatusCode::BadTcpEndpointUrlInvalid);
 
-        let result = server_state.authenticate_endpoint(
-            &request,
+        let result = authenticate_endpoint(
+            &server_state,
             "opc.tcp://localhost:4855/noaccess",
             SecurityPolicy::None,
             MessageSecurityMode::None,
@@ -155,12 +195,10 @@ fn user_name_pass_token() {
 
             const ENDPOINT_URL: &str = "opc.tcp://localhost:4855/";
 
-            let request = dummy_activate_session_request();
-
             // Test that a good user authenticates in unencrypt and encrypted policies
             let token = make_unencrypted_user_name_identity_token("sample1", "sample1pwd");
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
                 MessageSecurityMode::None,
@@ -177,8 +215,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "sample1pwd",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic128Rsa15,
                 MessageSecurityMode::SignAndEncrypt,
@@ -195,8 +233,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "sample1pwd",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256,
                 MessageSecurityMode::SignAndEncrypt,
@@ -213,8 +251,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "sample1pwd",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
                 MessageSecurityMode::SignAndEncrypt,
@@ -234,8 +272,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "sample1pwd",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
                 MessageSecurityMode::SignAndEncrypt,
@@ -253,8 +291,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "sample1pwd",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
                 MessageSecurityMode::SignAndEncrypt,
@@ -265,8 +303,8 @@ fn user_name_pass_token() {
 
             // Invalid user
             let token = make_unencrypted_user_name_identity_token("samplex", "sample1pwd");
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
                 MessageSecurityMode::None,
@@ -277,8 +315,8 @@ fn user_name_pass_token() {
 
             // Invalid password
             let token = make_unencrypted_user_name_identity_token("sample1", "sample");
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
                 MessageSecurityMode::None,
@@ -289,8 +327,8 @@ fn user_name_pass_token() {
 
             // Empty user
             let token = make_unencrypted_user_name_identity_token("", "sample1pwd");
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
                 MessageSecurityMode::None,
@@ -308,8 +346,8 @@ fn user_name_pass_token() {
                 "sample1",
                 "samplexx1",
             );
-            let result = server_state.authenticate_endpoint(
-                &request,
+            let result = authenticate_endpoint(
+                &server_state,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
                 MessageSecurityMode::SignAndEncrypt,
@@ -320,3 +358,325 @@ fn user_name_pass_token() {
         },
     );
 }
+
+#[test]
+fn user_name_pass_hash_token() {
+    do_session_service_test(None, |server_state, _session_service| {
+        let server_nonce = random::byte_string(20);
+
+        // Add a user whose password is only held as a hash
+        {
+            let server_state = server_state.read();
+            let mut config = server_state.config.write();
+            let pass_hash =
+                hash_password(PasswordHashAlgorithm::Pbkdf2Sha256, b"hashed1pwd").unwrap();
+            let user_token = ServerUserToken::user_pass_hash("hashed1", &pass_hash);
+            assert!(user_token.is_valid("hashed_password_user"));
+            config
+                .user_tokens
+                .insert("hashed_password_user".to_string(), user_token);
+            config
+                .endpoints
+                .get_mut("none")
+                .unwrap()
+                .user_token_ids
+                .insert("hashed_password_user".to_string());
+        }
+
+        let server_state = server_state.read();
+
+        const ENDPOINT_URL: &str = "opc.tcp://localhost:4855/";
+
+        let token = make_unencrypted_user_name_identity_token("hashed1", "hashed1pwd");
+        let result = authenticate_endpoint(
+            &server_state,
+            ENDPOINT_URL,
+            SecurityPolicy::None,
+            MessageSecurityMode::None,
+            &token,
+            &server_nonce,
+        );
+        assert_eq!(result.unwrap(), "hashed_password_user");
+
+        // Invalid password
+        let token = make_unencrypted_user_name_identity_token("hashed1", "hashed1pwx");
+        let result = authenticate_endpoint(
+            &server_state,
+            ENDPOINT_URL,
+            SecurityPolicy::None,
+            MessageSecurityMode::None,
+            &token,
+            &server_nonce,
+        );
+        assert_eq!(result.unwrap_err(), StatusCode::BadUserAccessDenied);
+
+        // The hash itself is not accepted as the password
+        let pass_hash = server_state.config.read().user_tokens["hashed_password_user"]
+            .pass_hash
+            .clone()
+            .unwrap();
+        let token = make_unencrypted_user_name_identity_token("hashed1", &pass_hash);
+        let result = authenticate_endpoint(
+            &server_state,
+            ENDPOINT_URL,
+            SecurityPolicy::None,
+            MessageSecurityMode::None,
+            &token,
+            &server_nonce,
+        );
+        assert_eq!(result.unwrap_err(), StatusCode::BadUserAccessDenied);
+    });
+}
+
+fn make_unencrypted_issued_identity_token(jwt: &str) -> ExtensionObject {
+    let token = IssuedIdentityToken {
+        policy_id: UAString::from(POLICY_ID_ISSUED_JWT),
+        token_data: ByteString::from(jwt.as_bytes()),
+        encryption_algorithm: UAString::null(),
+    };
+    ExtensionObject::from_encodable(ObjectId::IssuedIdentityToken_Encoding_DefaultBinary, &token)
+}
+
+/// Makes an HS256 signed JSON Web Token
+fn make_jwt(secret: &[u8], claims: serde_json::Value) -> String {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+    let input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(input.as_bytes()).unwrap();
+    let signature = signer.sign_to_vec().unwrap();
+    format!("{}.{}", input, URL_SAFE_NO_PAD.encode(signature))
+}
+
+const JWT_ENDPOINT_URL: &str = "opc.tcp://localhost:4855/";
+
+/// Adds a user who authenticates with a JWT signed with the secret to the none endpoint
+fn add_jwt_user(server_state: &RwLock<ServerState>, secret: &str) {
+    {
+        let server_state = server_state.read();
+        let mut config = server_state.config.write();
+        let mut jwt = ServerJwtConfig::with_secret("https://issuer", "urn:server", secret);
+        jwt.authority_url = Some("https://issuer/oauth2".to_string());
+        let user_token = ServerUserToken::jwt("jwt1", jwt);
+        assert!(user_token.is_valid("jwt_user"));
+        assert!(user_token.is_issued_token());
+        assert!(!user_token.is_user_pass());
+        config
+            .user_tokens
+            .insert("jwt_user".to_string(), user_token);
+        config
+            .endpoints
+            .get_mut("none")
+            .unwrap()
+            .user_token_ids
+            .insert("jwt_user".to_string());
+    }
+    server_state.write().reload_jwt_verification_keys();
+}
+
+fn jwt_claims() -> serde_json::Value {
+    serde_json::json!({
+        "iss": "https://issuer",
+        "aud": "urn:server",
+        "sub": "jwt1",
+        "exp": chrono::Utc::now().timestamp() + 300,
+    })
+}
+
+#[test]
+fn issued_jwt_token() {
+    do_session_service_test(None, |server_state, _session_service| {
+        let server_nonce = random::byte_string(20);
+
+        add_jwt_user(&server_state, "secret");
+
+        let server_state = server_state.read();
+
+        // The endpoint advertises the JWT policy and where to get a token from
+        let endpoints = server_state
+            .new_endpoint_descriptions(&default_listener(&server_state), JWT_ENDPOINT_URL)
+            .unwrap();
+        let endpoint = endpoints
+            .iter()
+            .find(|e| e.security_mode == MessageSecurityMode::None)
+            .unwrap();
+        let policy = endpoint.find_policy(UserTokenType::IssuedToken).unwrap();
+        assert_eq!(policy.policy_id.as_ref(), POLICY_ID_ISSUED_JWT);
+        assert_eq!(
+            policy.issued_token_type.as_ref(),
+            profiles::ISSUED_TOKEN_TYPE_JWT
+        );
+        let issuer: serde_json::Value =
+            serde_json::from_str(policy.issuer_endpoint_url.as_ref()).unwrap();
+        assert_eq!(issuer["ua:authorityUrl"], "https://issuer/oauth2");
+        assert_eq!(issuer["ua:resourceId"], "urn:server");
+        let now = chrono::Utc::now().timestamp();
+        let authenticate = |jwt: &str| {
+            authenticate_endpoint(
+                &server_state,
+                JWT_ENDPOINT_URL,
+                SecurityPolicy::None,
+                MessageSecurityMode::None,
+                &make_unencrypted_issued_identity_token(jwt),
+                &server_nonce,
+            )
+        };
+
+        let claims = jwt_claims();
+        assert_eq!(
+            authenticate(&make_jwt(b"secret", claims.clone())).unwrap(),
+            "jwt_user"
+        );
+
+        // Wrong signature
+        assert_eq!(
+            authenticate(&make_jwt(b"secreT", claims.clone())).unwrap_err(),
+            StatusCode::BadIdentityTokenRejected
+        );
+
+        // Genuine token for an unknown user
+        let mut other = claims.clone();
+        other["sub"] = serde_json::json!("jwt2");
+        assert_eq!(
+            authenticate(&make_jwt(b"secret", other)).unwrap_err(),
+            StatusCode::BadUserAccessDenied
+        );
+
+        // Expired token
+        let mut expired = claims.clone();
+        expired["exp"] = serde_json::json!(now - 3600);
+        assert_eq!(
+            authenticate(&make_jwt(b"secret", expired)).unwrap_err(),
+            StatusCode::BadIdentityTokenRejected
+        );
+
+        // Garbage
+        assert_eq!(
+            authenticate("not a jwt").unwrap_err(),
+            StatusCode::BadIdentityTokenInvalid
+        );
+    });
+}
+
+#[test]
+fn jwt_verification_key_is_cached() {
+    do_session_service_test(None, |server_state, _session_service| {
+        let server_nonce = random::byte_string(20);
+        add_jwt_user(&server_state, "secret");
+        let authenticate = |secret: &[u8]| {
+            authenticate_endpoint(
+                &server_state.read(),
+                JWT_ENDPOINT_URL,
+                SecurityPolicy::None,
+                MessageSecurityMode::None,
+                &make_unencrypted_issued_identity_token(&make_jwt(secret, jwt_claims())),
+                &server_nonce,
+            )
+        };
+        assert!(authenticate(b"secret").is_ok());
+
+        // The key is not loaded again for each token, so a config change has no effect until
+        // the keys are reloaded
+        {
+            let server_state = server_state.read();
+            let mut config = server_state.config.write();
+            let user_token = config.user_tokens.get_mut("jwt_user").unwrap();
+            user_token.jwt.as_mut().unwrap().secret = Some("secret2".to_string());
+        }
+        assert!(authenticate(b"secret").is_ok());
+        assert!(authenticate(b"secret2").is_err());
+
+        server_state.write().reload_jwt_verification_keys();
+        assert!(authenticate(b"secret").is_err());
+        assert!(authenticate(b"secret2").is_ok());
+    });
+}
+
+fn make_cert(common_name: &str) -> (X509, PrivateKey) {
+    X509::cert_and_pkey(&X509Data {
+        key_size: 2048,
+        common_name: common_name.to_string(),
+        organization: "x.org".to_string(),
+        organizational_unit: "x.org ops".to_string(),
+        country: "EN".to_string(),
+        state: "London".to_string(),
+        alt_host_names: vec![format!("urn:{}", common_name), "localhost".to_string()],
+        certificate_duration_days: 60,
+    })
+    .unwrap()
+}
+
+#[test]
+fn activate_session_after_certificate_renewal() {
+    do_session_service_test(
+        Some("./pki_activate_session_after_renewal"),
+        |server_state, session_service| {
+            const ENDPOINT_URL: &str = "opc.tcp://localhost:4855/";
+            let security_policy = SecurityPolicy::Basic256Sha256;
+
+            // The secure channel was opened with the certificate and key the server had before
+            // they were renewed
+            let (renewed_cert, renewed_pkey) = make_cert("renewed");
+            let mut secure_channel = SecureChannel::new_no_certificate_store();
+            secure_channel.set_security_policy(security_policy);
+            secure_channel.set_security_mode(MessageSecurityMode::SignAndEncrypt);
+            {
+                let mut server_state = server_state.write();
+                secure_channel.set_cert(server_state.server_certificate.replace(renewed_cert));
+                secure_channel.set_private_key(server_state.server_pkey.replace(renewed_pkey));
+            }
+            let channel_cert = secure_channel.cert();
+
+            let (client_cert, client_pkey) = make_cert("client");
+            let session_nonce = security_policy.random_nonce();
+            let session = Arc::new(RwLock::new(Session::new(server_state.clone())));
+            {
+                let mut session = session.write();
+                session.set_endpoint_url(UAString::from(ENDPOINT_URL));
+                session.set_client_certificate(Some(client_cert));
+                session.set_session_nonce(session_nonce.clone());
+            }
+
+            // The client signs and encrypts for the certificate of the channel
+            let request = ActivateSessionRequest {
+                client_signature: crate::crypto::create_signature_data(
+                    &client_pkey,
+                    security_policy,
+                    &channel_cert.as_ref().unwrap().as_byte_string(),
+                    &session_nonce,
+                )
+                .unwrap(),
+                user_identity_token: make_encrypted_user_name_identity_token(
+                    POLICY_ID_USER_PASS_RSA_OAEP,
+                    security_policy,
+                    &session_nonce,
+                    &channel_cert,
+                    "sample1",
+                    "sample1pwd",
+                ),
+                ..dummy_activate_session_request()
+            };
+            let connection = ClientConnection {
+                listener: default_listener(&server_state.read()),
+                peer_credentials: None,
+            };
+            let response = session_service.activate_session(
+                &connection,
+                Arc::new(RwLock::new(secure_channel)),
+                server_state.clone(),
+                session.clone(),
+                Arc::new(RwLock::new(AddressSpace::new())),
+                &request,
+            );
+            let response: ActivateSessionResponse =
+                supported_message_as!(response, ActivateSessionResponse);
+            assert!(response.response_header.service_result.is_good());
+            assert!(session.read().is_activated());
+        },
+    );
+}
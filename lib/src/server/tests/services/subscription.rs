@@ -4,9 +4,14 @@ use chrono::Utc;
 
 use crate::server::{
     prelude::*,
+    redundancy,
     services::{monitored_item::MonitoredItemService, subscription::SubscriptionService},
+    session_diagnostics,
     state::ServerState,
-    subscriptions::subscription::*,
+    subscriptions::{
+        durable::{self, DurableSubscriptions},
+        subscription::*,
+    },
 };
 use crate::supported_message_as;
 use crate::sync::*;
@@ -459,3 +464,477 @@ fn republish() {
         );
     })
 }
+
+/// Makes the subscription durable and ends its session, leaving the subscription with the
+/// durable subscriptions of the server.
+fn detach_durable_subscription(
+    session: &Arc<RwLock<Session>>,
+    subscription_id: u32,
+    owner: UAString,
+) {
+    let mut session = trace_write_lock!(session);
+    let subscriptions = session.subscriptions_mut();
+    subscriptions
+        .get_mut(subscription_id)
+        .unwrap()
+        .set_durable(owner, 1);
+    let subscriptions = subscriptions.remove_durable();
+    trace_lock!(session.durable_subscriptions()).detach(subscriptions);
+}
+
+fn transfer_subscriptions_request(subscription_ids: Vec<u32>) -> TransferSubscriptionsRequest {
+    TransferSubscriptionsRequest {
+        request_header: RequestHeader::dummy(),
+        subscription_ids: Some(subscription_ids),
+        send_initial_values: true,
+    }
+}
+
+#[test]
+fn transfer_durable_subscription() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        let other_subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            VariableId::Server_ServerStatus_StartTime,
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+
+        // The session is anonymous so its user id is null
+        detach_durable_subscription(&session, subscription_id, UAString::null());
+        detach_durable_subscription(
+            &session,
+            other_subscription_id,
+            UAString::from("other user"),
+        );
+        {
+            let session = trace_read_lock!(session);
+            assert!(session.subscriptions().is_empty());
+        }
+
+        let request = transfer_subscriptions_request(vec![
+            subscription_id,
+            other_subscription_id,
+            other_subscription_id + 1,
+        ]);
+        let response: TransferSubscriptionsResponse = supported_message_as!(
+            ss.transfer_subscriptions(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &request
+            ),
+            TransferSubscriptionsResponse
+        );
+        let results = response
+            .results
+            .unwrap()
+            .into_iter()
+            .map(|result| result.status_code)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                StatusCode::Good,
+                StatusCode::BadUserAccessDenied,
+                StatusCode::BadSubscriptionIdInvalid
+            ]
+        );
+
+        // The transferred subscription keeps its monitored items
+        let session = trace_read_lock!(session);
+        let subscription = session
+            .subscriptions()
+            .subscriptions()
+            .get(&subscription_id)
+            .unwrap();
+        assert!(subscription.is_durable());
+        assert_eq!(subscription.monitored_items_len(), 1);
+        let durable_subscriptions = trace_lock!(session.durable_subscriptions());
+        assert!(!durable_subscriptions.contains(subscription_id));
+        assert!(durable_subscriptions.contains(other_subscription_id));
+    })
+}
+
+#[test]
+fn transfer_subscription_of_dropped_session() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _mis| {
+        // A session of the same anonymous user ends with a durable subscription whose
+        // notification was not acknowledged
+        let other_session = Arc::new(RwLock::new(Session::new(server_state.clone())));
+        let subscription_id = create_subscription(server_state.clone(), other_session.clone(), &ss);
+        let sequence_number = {
+            let notification = NotificationMessage::data_change(
+                1,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle: 1,
+                    value: Variant::Empty.into(),
+                }],
+                vec![],
+            );
+            let sequence_number = notification.sequence_number;
+            let mut other_session = trace_write_lock!(other_session);
+            let subscriptions = other_session.subscriptions_mut();
+            subscriptions
+                .get_mut(subscription_id)
+                .unwrap()
+                .set_durable(UAString::null(), 1);
+            subscriptions
+                .retransmission_queue()
+                .insert((subscription_id, sequence_number), notification);
+            sequence_number
+        };
+        let durable_subscriptions = trace_read_lock!(session).durable_subscriptions().clone();
+        drop(other_session);
+        assert!(!trace_lock!(durable_subscriptions).contains(subscription_id));
+
+        // The subscription is kept when it is transferred, along with the notification
+        let request = transfer_subscriptions_request(vec![subscription_id]);
+        let response: TransferSubscriptionsResponse = supported_message_as!(
+            ss.transfer_subscriptions(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &request
+            ),
+            TransferSubscriptionsResponse
+        );
+        let result = &response.results.unwrap()[0];
+        assert_eq!(result.status_code, StatusCode::Good);
+        assert_eq!(
+            result.available_sequence_numbers,
+            Some(vec![sequence_number])
+        );
+        assert!(trace_read_lock!(session)
+            .subscriptions()
+            .contains(subscription_id));
+    })
+}
+
+#[test]
+fn transfer_replicated_subscription() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            VariableId::Server_ServerStatus_StartTime,
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+
+        // Replicate the subscription as if it came from another server, where it belongs to the
+        // anonymous session
+        let durable_subscriptions = Mutex::new(DurableSubscriptions::new(None, 24));
+        let replicas = redundancy::replicated_subscriptions(
+            std::slice::from_ref(&session),
+            &durable_subscriptions,
+        );
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas[0].subscription_id(), subscription_id);
+        let durable_subscriptions = {
+            let mut session = trace_write_lock!(session);
+            session.subscriptions_mut().remove(subscription_id);
+            session.durable_subscriptions().clone()
+        };
+        redundancy::receive_subscriptions(&durable_subscriptions, "urn:peer", replicas.clone());
+        assert!(trace_lock!(durable_subscriptions).contains(subscription_id));
+
+        let request = transfer_subscriptions_request(vec![subscription_id]);
+        let response: TransferSubscriptionsResponse = supported_message_as!(
+            ss.transfer_subscriptions(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &request
+            ),
+            TransferSubscriptionsResponse
+        );
+        assert_eq!(response.results.unwrap()[0].status_code, StatusCode::Good);
+
+        // The transferred subscription keeps its monitored items
+        {
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            assert!(!subscription.is_durable());
+            assert_eq!(subscription.monitored_items_len(), 1);
+        }
+
+        // Replicating it again does not bring it back while the other server still has it
+        redundancy::receive_subscriptions(&durable_subscriptions, "urn:peer", replicas.clone());
+        assert!(!trace_lock!(durable_subscriptions).contains(subscription_id));
+        redundancy::receive_subscriptions(&durable_subscriptions, "urn:peer", Vec::new());
+        redundancy::receive_subscriptions(&durable_subscriptions, "urn:peer", replicas);
+        assert!(trace_lock!(durable_subscriptions).contains(subscription_id));
+    })
+}
+
+#[test]
+fn durable_subscriptions_save_restore() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let path = make_test_file("durable_subscriptions.json");
+        let _ = std::fs::remove_file(&path);
+
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            VariableId::Server_ServerStatus_StartTime,
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+        // Tick so the monitored item queues its first value
+        {
+            let address_space = trace_read_lock!(address_space);
+            let mut session = trace_write_lock!(session);
+            let subscription = session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap();
+            subscription.set_durable(UAString::null(), 1);
+            assert!(subscription.tick_detached(&Utc::now(), &address_space));
+        }
+
+        // Save the subscription as the server stops
+        {
+            let mut session = trace_write_lock!(session);
+            let subscriptions = session.subscriptions_mut().remove_durable();
+            let mut durable_subscriptions = DurableSubscriptions::new(Some(path.clone()), 24);
+            durable_subscriptions.detach(subscriptions);
+        }
+        assert!(path.exists());
+
+        // Restore it as the server starts
+        let mut durable_subscriptions = DurableSubscriptions::new(Some(path.clone()), 24);
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.last_subscription_id = 0;
+            let address_space = trace_read_lock!(address_space);
+            durable_subscriptions.restore(&mut server_state, &address_space);
+            assert_eq!(server_state.last_subscription_id, subscription_id);
+        }
+        assert!(durable_subscriptions.contains(subscription_id));
+
+        let mut subscription = durable_subscriptions
+            .transfer(subscription_id, &UAString::null())
+            .unwrap();
+        assert!(subscription.is_durable());
+        assert_eq!(subscription.monitored_items_len(), 1);
+
+        // The value queued before the server stopped is published after the transfer
+        {
+            let address_space = trace_read_lock!(address_space);
+            for seconds in 1..=2 {
+                subscription.tick(
+                    &Utc::now().add(chrono::Duration::seconds(seconds)),
+                    &address_space,
+                    TickReason::TickTimerFired,
+                    true,
+                );
+            }
+        }
+        let notification = subscription.take_notification().unwrap();
+        let (data_changes, _) = notification
+            .notifications(&DecodingOptions::test())
+            .unwrap();
+        assert_eq!(data_changes[0].monitored_items.as_ref().unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    })
+}
+
+#[test]
+fn durable_subscriptions_of_live_sessions_are_saved() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _mis| {
+        let path = make_test_file("durable_subscriptions_live.json");
+        let _ = std::fs::remove_file(&path);
+
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        let other_subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        {
+            let mut session = trace_write_lock!(session);
+            session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap()
+                .set_durable(UAString::null(), 1);
+        }
+
+        // The periodic save includes the durable subscription of the live session only
+        let session_records = durable::session_records(std::slice::from_ref(&session));
+        assert_eq!(session_records.len(), 1);
+        let mut durable_subscriptions = DurableSubscriptions::new(Some(path.clone()), 24);
+        durable_subscriptions.save_with_sessions(session_records);
+
+        // It is restored if the server stops without closing the session
+        let mut durable_subscriptions = DurableSubscriptions::new(Some(path.clone()), 24);
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            let address_space = trace_read_lock!(address_space);
+            durable_subscriptions.restore(&mut server_state, &address_space);
+        }
+        assert!(durable_subscriptions.contains(subscription_id));
+        assert!(!durable_subscriptions.contains(other_subscription_id));
+
+        let _ = std::fs::remove_file(&path);
+    })
+}
+
+#[test]
+fn subscription_record_keeps_lifetime_count() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        let (record, max_lifetime_count) = {
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            (
+                subscription.to_durable_record(),
+                subscription.max_lifetime_count(),
+            )
+        };
+        assert_eq!(record.lifetime_in_hours, 0);
+
+        // A subscription that is not durable is restored with its own lifetime count
+        let server_state = trace_read_lock!(server_state);
+        let address_space = trace_read_lock!(address_space);
+        let subscription =
+            Subscription::from_durable_record(record, &server_state, &address_space, &Utc::now());
+        assert!(!subscription.is_durable());
+        assert_eq!(subscription.max_lifetime_count(), max_lifetime_count);
+    })
+}
+
+/// Decodes the subscription diagnostics held by an array variable
+fn subscription_diagnostics_array(
+    address_space: &AddressSpace,
+    node_id: NodeId,
+) -> Vec<SubscriptionDiagnosticsDataType> {
+    let value = address_space.get_variable_value(node_id).unwrap().value;
+    match value {
+        Some(Variant::Array(array)) => array
+            .values
+            .iter()
+            .map(|value| match value {
+                Variant::ExtensionObject(value) => value
+                    .decode_inner::<SubscriptionDiagnosticsDataType>(&DecodingOptions::test())
+                    .unwrap(),
+                _ => panic!("Expected an extension object"),
+            })
+            .collect(),
+        _ => panic!("Expected an array, got {:?}", value),
+    }
+}
+
+#[test]
+fn session_and_subscription_diagnostics() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let sessions_summary_id: NodeId =
+            ObjectId::Server_ServerDiagnostics_SessionsDiagnosticsSummary.into();
+        let server_subscriptions_id: NodeId =
+            VariableId::Server_ServerDiagnostics_SubscriptionDiagnosticsArray.into();
+
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            var_node_id(1),
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+
+        let (session_id, diagnostics) = {
+            let session = trace_read_lock!(session);
+            // The transport protocol is the profile URI of the transport the session runs on
+            assert_eq!(
+                session
+                    .session_security_diagnostics_data()
+                    .transport_protocol
+                    .as_ref(),
+                profiles::TRANSPORT_PROFILE_URI_BINARY
+            );
+            (
+                session.session_id().clone(),
+                session.server_diagnostics().clone(),
+            )
+        };
+        let session_manager = Arc::new(RwLock::new(SessionManager::default()));
+        trace_write_lock!(session_manager).register_session(session.clone());
+
+        // Diagnostics are disabled by default
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            assert!(!address_space.is_diagnostics_enabled());
+            trace_read_lock!(session).update_diagnostics(&mut address_space);
+            assert!(!address_space.node_exists(&session_id));
+            address_space.set_diagnostics_enabled(true);
+        }
+
+        // Enabling them exposes every session
+        session_diagnostics::expose_sessions(&session_manager, &address_space, &diagnostics);
+        {
+            let address_space = trace_read_lock!(address_space);
+            // The session has an object beneath the summary and the subscription has a variable
+            assert!(address_space.node_exists(&session_id));
+            let session_references = address_space
+                .find_references(
+                    &sessions_summary_id,
+                    Some((ReferenceTypeId::HasComponent, false)),
+                )
+                .unwrap();
+            assert!(session_references
+                .iter()
+                .any(|reference| reference.target_node == session_id));
+
+            let subscriptions =
+                subscription_diagnostics_array(&address_space, server_subscriptions_id.clone());
+            assert_eq!(subscriptions.len(), 1);
+            let subscription = &subscriptions[0];
+            assert_eq!(subscription.session_id, session_id);
+            assert_eq!(subscription.subscription_id, subscription_id);
+            assert_eq!(subscription.monitored_item_count, 1);
+            assert_eq!(subscription.republish_request_count, 0);
+            assert!(subscription.publishing_enabled);
+        }
+
+        // The session updates its diagnostics as it changes
+        let request = republish_request(subscription_id, 1);
+        let _ = ss.republish(session.clone(), &request);
+        let mut address_space = trace_write_lock!(address_space);
+        trace_read_lock!(session).update_diagnostics(&mut address_space);
+        let subscriptions =
+            subscription_diagnostics_array(&address_space, server_subscriptions_id.clone());
+        assert_eq!(subscriptions[0].republish_request_count, 1);
+        assert_eq!(subscriptions[0].republish_message_count, 0);
+
+        // The session's own array holds the same subscription
+        let Identifier::String(ref session_name) = session_id.identifier else {
+            panic!("Expected a string session id");
+        };
+        let session_subscriptions_id =
+            NodeId::new(1, format!("{}.SubscriptionDiagnosticsArray", session_name));
+        let session_subscriptions =
+            subscription_diagnostics_array(&address_space, session_subscriptions_id);
+        assert_eq!(session_subscriptions, subscriptions);
+
+        // Disabling diagnostics removes the session and its subscriptions
+        address_space.set_diagnostics_enabled(false);
+        assert!(!address_space.node_exists(&session_id));
+        assert!(subscription_diagnostics_array(&address_space, server_subscriptions_id).is_empty());
+    })
+}
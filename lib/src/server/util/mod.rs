@@ -32,13 +32,13 @@ impl PollingAction {
                 {
                     // trace!("polling action.take_while");
                     let server_state = trace_read_lock!(server_state);
-                    // If the server aborts or is in a failed state, this polling timer will stop
+                    // If the server aborts or is in a failed state, this polling timer will stop.
+                    // It carries on while the server counts down to a graceful shutdown.
                     let abort = match server_state.state() {
-                        ServerStateType::Failed
-                        | ServerStateType::NoConfiguration
-                        | ServerStateType::Shutdown => true,
-                        _ => server_state.is_abort(),
-                    };
+                        ServerStateType::Failed | ServerStateType::NoConfiguration => true,
+                        ServerStateType::Shutdown => !server_state.is_shutting_down(),
+                        _ => false,
+                    } || server_state.is_abort();
                     if abort {
                         debug!("Polling action is stopping due to server state / abort");
                         break;
@@ -51,7 +51,7 @@ impl PollingAction {
                 // Polling timer will only call the action if the server is in a running state
                 let process_action = {
                     let server_state = trace_read_lock!(server_state);
-                    server_state.is_running()
+                    server_state.is_serving()
                 };
                 if process_action {
                     action();
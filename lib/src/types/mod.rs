@@ -21,6 +21,8 @@ pub mod profiles {
         "http://opcfoundation.org/UA-Profile/Security/UserToken/Anonymous";
     pub const SECURITY_USER_TOKEN_POLICY_USERPASS: &str =
         "http://opcfoundation.org/UA-Profile/ Security/UserToken-Server/UserNamePassword";
+    /// Issued token type of a JSON Web Token held in an IssuedIdentityToken
+    pub const ISSUED_TOKEN_TYPE_JWT: &str = "http://opcfoundation.org/UA/UserToken#JWT";
 }
 
 pub mod constants {
@@ -159,16 +159,7 @@ fn main() {
 }
 
 fn start_http_server(runtime: &tokio::runtime::Runtime, server: &Server, content_path: &str) {
-    let server_state = server.server_state();
-    let connections = server.connections();
-    let metrics = server.server_metrics();
-    // The index.html is in a path relative to the working dir.
-    let _ = http::run_http_server(
-        runtime,
-        "127.0.0.1:8585",
-        content_path,
-        server_state,
-        connections,
-        metrics,
-    );
+    // The index.html is in a path relative to the working dir. The admin API beneath /admin is
+    // only usable when http_admin is set in the server configuration.
+    let _ = http::run_http_server_with_admin(runtime, "127.0.0.1:8585", content_path, server);
 }
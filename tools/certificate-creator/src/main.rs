@@ -1,64 +1,227 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use opcua::crypto::*;
 
 fn main() {
-    if let Ok((x509_data, overwrite, pki_path, cert_path, pkey_path)) = parse_x509_args() {
-        println!("Creating certificate...");
-        println!("  Key size = {}", x509_data.key_size);
-        println!("  CN (common name) = \"{}\"", x509_data.common_name);
-        println!("  O (organization) = \"{}\"", x509_data.organization);
+    if let Ok((mode, x509_args, ca_args)) = parse_x509_args() {
+        match mode {
+            Mode::SelfSigned => create_self_signed(x509_args),
+            Mode::CertificateAuthority => create_ca(x509_args, ca_args),
+            Mode::Issue => issue(x509_args, ca_args),
+            Mode::SignRequest(request_path) => sign_request(x509_args, ca_args, request_path),
+            Mode::Revoke(cert_path) => revoke(ca_args, Some(cert_path)),
+            Mode::Crl => revoke(ca_args, None),
+        }
+    }
+}
+
+fn print_x509_data(x509_data: &X509Data) {
+    println!("  Key size = {}", x509_data.key_size);
+    println!("  CN (common name) = \"{}\"", x509_data.common_name);
+    println!("  O (organization) = \"{}\"", x509_data.organization);
+    println!(
+        "  OU (organizational unit) = \"{}\"",
+        x509_data.organizational_unit
+    );
+    println!("  C (country) = \"{}\"", x509_data.country);
+    println!("  ST (state) = \"{}\"", x509_data.state);
+    println!("  Duration = {} days", x509_data.certificate_duration_days);
+    print_alt_host_names(&x509_data.alt_host_names);
+}
+
+fn print_alt_host_names(alt_host_names: &[String]) {
+    alt_host_names.iter().enumerate().for_each(|(idx, addr)| {
+        if idx == 0 {
+            println!("  Application URI = \"{}\"", addr);
+        } else {
+            println!("  DNS = \"{}\"", addr);
+        }
+    });
+}
+
+fn create_self_signed(x509_args: X509Args) {
+    let (x509_data, overwrite, pki_path, cert_path, pkey_path, pkey_passphrase) = x509_args;
+
+    println!("Creating certificate...");
+    print_x509_data(&x509_data);
+
+    // Make paths relative
+    let cert_path = pki_path.join(cert_path);
+    let pkey_path = pki_path.join(pkey_path);
+
+    if pkey_passphrase.is_some() {
+        println!("  Private key is encrypted with a passphrase");
+    }
+
+    let _ = CertificateStore::create_certificate_and_key_with_passphrase(
+        &x509_data,
+        overwrite,
+        &cert_path,
+        &pkey_path,
+        pkey_passphrase.as_ref(),
+    )
+    .map_err(|err| {
+        eprintln!(
+            "Certificate creation failed, check above and reason \"{}\" for errors",
+            err
+        );
+    })
+    .map(|_| {
         println!(
-            "  OU (organizational unit) = \"{}\"",
-            x509_data.organizational_unit
+            "Certificate and private key have been written to {} and {}",
+            cert_path.display(),
+            pkey_path.display()
         );
-        println!("  C (country) = \"{}\"", x509_data.country);
-        println!("  ST (state) = \"{}\"", x509_data.state);
-        println!("  Duration = {} days", x509_data.certificate_duration_days);
-
-        x509_data
-            .alt_host_names
-            .iter()
-            .enumerate()
-            .for_each(|(idx, addr)| {
-                if idx == 0 {
-                    println!("  Application URI = \"{}\"", addr);
-                } else {
-                    println!("  DNS = \"{}\"", addr);
-                }
-            });
-
-        // Make paths relative
-        let cert_path = {
-            let mut path = pki_path.clone();
-            path.push(cert_path);
-            path
-        };
-        let pkey_path = {
-            let mut path = pki_path;
-            path.push(pkey_path);
-            path
-        };
+    });
+}
 
-        let _ = CertificateStore::create_certificate_and_key(
-            &x509_data, overwrite, &cert_path, &pkey_path,
-        )
-        .map_err(|err| {
-            eprintln!(
-                "Certificate creation failed, check above and reason \"{}\" for errors",
-                err
-            );
+fn create_ca(x509_args: X509Args, ca_args: CaArgs) {
+    let (x509_data, overwrite, pki_path, _, _, _) = x509_args;
+    let ca_cert_path = pki_path.join(&ca_args.cert_path);
+    let ca_pkey_path = pki_path.join(&ca_args.pkey_path);
+    let crl_path = pki_path.join(&ca_args.crl_path);
+
+    println!("Creating certificate authority...");
+    let mut x509_data = x509_data;
+    x509_data.alt_host_names.clear();
+    print_x509_data(&x509_data);
+    if ca_args.pkey_passphrase.is_some() {
+        println!("  Private key is encrypted with a passphrase");
+    }
+
+    let result = CertificateAuthority::create(&x509_data).and_then(|ca| {
+        CertificateStore::write_certificate_and_key(
+            ca.cert(),
+            ca.pkey(),
+            overwrite,
+            &ca_cert_path,
+            &ca_pkey_path,
+            ca_args.pkey_passphrase.as_ref(),
+        )?;
+        let crl = ca.crl(None, ca_args.crl_days)?;
+        CertificateStore::write_crl(&crl, &crl_path)
+    });
+    match result {
+        Ok(_) => println!(
+            "CA certificate, private key and CRL have been written to {}, {} and {}",
+            ca_cert_path.display(),
+            ca_pkey_path.display(),
+            crl_path.display()
+        ),
+        Err(err) => eprintln!("Certificate authority creation failed, reason \"{}\"", err),
+    }
+}
+
+fn issue(x509_args: X509Args, ca_args: CaArgs) {
+    let (x509_data, overwrite, pki_path, cert_path, pkey_path, pkey_passphrase) = x509_args;
+    let cert_path = pki_path.join(cert_path);
+    let pkey_path = pki_path.join(pkey_path);
+
+    println!("Issuing certificate...");
+    print_x509_data(&x509_data);
+    if pkey_passphrase.is_some() {
+        println!("  Private key is encrypted with a passphrase");
+    }
+
+    let result = read_ca(&pki_path, &ca_args)
+        .and_then(|ca| ca.issue(&x509_data))
+        .and_then(|(cert, pkey)| {
+            CertificateStore::write_certificate_and_key(
+                &cert,
+                &pkey,
+                overwrite,
+                &cert_path,
+                &pkey_path,
+                pkey_passphrase.as_ref(),
+            )
+        });
+    match result {
+        Ok(_) => println!(
+            "Certificate and private key have been written to {} and {}",
+            cert_path.display(),
+            pkey_path.display()
+        ),
+        Err(err) => eprintln!("Certificate issue failed, reason \"{}\"", err),
+    }
+}
+
+fn sign_request(x509_args: X509Args, ca_args: CaArgs, request_path: PathBuf) {
+    let (x509_data, overwrite, pki_path, cert_path, _, _) = x509_args;
+    let cert_path = pki_path.join(cert_path);
+
+    println!("Signing certificate request {}...", request_path.display());
+    println!("  Duration = {} days", x509_data.certificate_duration_days);
+    print_alt_host_names(&x509_data.alt_host_names);
+
+    let result = std::fs::read(&request_path)
+        .map_err(|_| format!("Cannot read {}", request_path.display()))
+        .and_then(|request| {
+            read_ca(&pki_path, &ca_args)?.sign_request(
+                &request,
+                &x509_data.alt_host_names,
+                x509_data.certificate_duration_days,
+            )
         })
-        .map(|_| {
+        .and_then(|cert| {
+            if !overwrite && cert_path.exists() {
+                return Err(format!(
+                    "File {} already exists and will not be overwritten",
+                    cert_path.display()
+                ));
+            }
+            let der = cert.to_der().map_err(|_| "Cannot encode certificate")?;
+            std::fs::write(&cert_path, der)
+                .map_err(|_| format!("Cannot write {}", cert_path.display()))
+        });
+    match result {
+        Ok(_) => println!("Certificate has been written to {}", cert_path.display()),
+        Err(err) => eprintln!("Certificate request signing failed, reason \"{}\"", err),
+    }
+}
+
+/// Revokes the certificate, if any, and writes a new CRL holding all the revocations so far
+fn revoke(ca_args: CaArgs, cert_path: Option<PathBuf>) {
+    let pki_path = PathBuf::from(&ca_args.pki_path);
+    let crl_path = pki_path.join(&ca_args.crl_path);
+    let result = read_ca(&pki_path, &ca_args).and_then(|ca| {
+        let previous = if crl_path.exists() {
+            Some(CertificateStore::read_crl(&crl_path)?)
+        } else {
+            None
+        };
+        let crl = if let Some(cert_path) = cert_path {
+            let cert = CertificateStore::read_cert(&cert_path)?;
             println!(
-                "Certificate and private key have been written to {} and {}",
+                "Revoking certificate {}, thumbprint {}",
                 cert_path.display(),
-                pkey_path.display()
+                cert.thumbprint().as_hex_string()
             );
-        });
+            ca.revoke(previous.as_ref(), &cert, ca_args.crl_days)?
+        } else {
+            ca.crl(previous.as_ref(), ca_args.crl_days)?
+        };
+        CertificateStore::write_crl(&crl, &crl_path)?;
+        Ok(crl)
+    });
+    match result {
+        Ok(crl) => println!(
+            "CRL with {} revoked certificate(s) has been written to {}",
+            crl.revoked_serial_numbers().len(),
+            crl_path.display()
+        ),
+        Err(err) => eprintln!("CRL creation failed, reason \"{}\"", err),
     }
 }
 
+fn read_ca(pki_path: &Path, ca_args: &CaArgs) -> Result<CertificateAuthority, String> {
+    let cert = CertificateStore::read_cert(&pki_path.join(&ca_args.cert_path))?;
+    let pkey = CertificateStore::read_pkey_with_passphrase(
+        &pki_path.join(&ca_args.pkey_path),
+        ca_args.pkey_passphrase.as_ref(),
+    )?;
+    CertificateAuthority::new(cert, pkey)
+}
+
 struct Args {
     help: bool,
     overwrite: bool,
@@ -66,6 +229,18 @@ struct Args {
     pki_path: String,
     cert_path: String,
     pkey_path: String,
+    pkey_passphrase_env: Option<String>,
+    pkey_passphrase_stdin: bool,
+    ca: bool,
+    issue: bool,
+    sign_csr: Option<String>,
+    revoke: Option<String>,
+    crl: bool,
+    ca_cert_path: String,
+    ca_pkey_path: String,
+    ca_pkey_passphrase_env: Option<String>,
+    crl_path: String,
+    crl_days: u32,
     duration: u32,
     application_uri: String,
     hostnames: String,
@@ -97,6 +272,26 @@ impl Args {
             pkey_path: args
                 .opt_value_from_str("--pkey-name")?
                 .unwrap_or_else(|| String::from(DEFAULT_PKEY_PATH)),
+            pkey_passphrase_env: args.opt_value_from_str("--pkey-passphrase-env")?,
+            pkey_passphrase_stdin: args.contains("--pkey-passphrase-stdin"),
+            ca: args.contains("--ca"),
+            issue: args.contains("--issue"),
+            sign_csr: args.opt_value_from_str("--sign-csr")?,
+            revoke: args.opt_value_from_str("--revoke")?,
+            crl: args.contains("--crl"),
+            ca_cert_path: args
+                .opt_value_from_str("--ca-cert-name")?
+                .unwrap_or_else(|| String::from(DEFAULT_CA_CERT_PATH)),
+            ca_pkey_path: args
+                .opt_value_from_str("--ca-pkey-name")?
+                .unwrap_or_else(|| String::from(DEFAULT_CA_PKEY_PATH)),
+            ca_pkey_passphrase_env: args.opt_value_from_str("--ca-pkey-passphrase-env")?,
+            crl_path: args
+                .opt_value_from_str("--crl-name")?
+                .unwrap_or_else(|| String::from(DEFAULT_CRL_PATH)),
+            crl_days: args
+                .opt_value_from_str("--crl-days")?
+                .unwrap_or(DEFAULT_CRL_DAYS),
             duration: args
                 .opt_value_from_str("--duration")?
                 .unwrap_or(DEFAULT_DURATION),
@@ -135,6 +330,12 @@ This creates a self-signed key and X509 certificate for use with OPC UA clients
 Use the flags to control what the certificate contains. For convenience some values will be
 prefilled from defaults, but for production purposes all defaults should be overridden.
 
+It can also act as a certificate authority for a private PKI. Create the CA with --ca, then
+issue certificates with --issue or --sign-csr and revoke them with --revoke. A client or server
+trusts every certificate issued by the CA once the CA certificate is in its pki/trusted folder,
+and rejects revoked ones once the CRL is in its pki/crl folder. The CRL must be reissued with
+--crl before it expires.
+
 Usage:
   -h, --help            Show help.
   -o, --overwrite       Overwrites existing files.
@@ -142,6 +343,22 @@ Usage:
   --pki-path path       Path to write the certificate and key. (default: {})
   --cert-name           Name of certificate file relative to pki-path. (default: {})
   --pkey-name           Name of private key file relative to pki-path. (default: {})
+  --pkey-passphrase-env name
+                        Encrypts the private key with the passphrase held in the named environment variable.
+  --pkey-passphrase-stdin
+                        Encrypts the private key with the passphrase read from the first line of standard input.
+  --ca                  Creates a CA certificate, private key and an empty CRL instead of a self-signed certificate.
+  --issue               Creates a certificate and private key issued by the CA instead of a self-signed certificate.
+  --sign-csr file       Signs the certificate signing request in the file with the CA, writing the certificate to cert-name.
+                        The subject comes from the request and the alt host names from the arguments.
+  --revoke file         Revokes the certificate in the file and writes the updated CRL.
+  --crl                 Reissues the CRL with the same revocations so it does not expire.
+  --ca-cert-name        Name of CA certificate file relative to pki-path. (default: {})
+  --ca-pkey-name        Name of CA private key file relative to pki-path. (default: {})
+  --ca-pkey-passphrase-env name
+                        The CA private key is encrypted with the passphrase held in the named environment variable.
+  --crl-name            Name of CRL file relative to pki-path. (default: {})
+  --crl-days days       The number of days until the CRL must be reissued. (default: {})
   --duration days       The duration in days of this certificate before it expires. (default: {})
   --application-uri     The application's uri used by OPC UA for authentication purposes. (default: {})
   --add-computer-name   Add this computer's name (inferred from COMPUTERNAME / NAME environment variables) to the alt host names.
@@ -157,6 +374,10 @@ Usage:
             DEFAULT_PKI_PATH,
             DEFAULT_CERT_PATH,
             DEFAULT_PKEY_PATH,
+            DEFAULT_CA_CERT_PATH,
+            DEFAULT_CA_PKEY_PATH,
+            DEFAULT_CRL_PATH,
+            DEFAULT_CRL_DAYS,
             DEFAULT_DURATION,
             DEFAULT_APPLICATION_URI,
             DEFAULT_CN,
@@ -179,14 +400,95 @@ const DEFAULT_C: &str = "IE";
 const DEFAULT_ST: &str = "Dublin";
 const DEFAULT_CERT_PATH: &str = "cert.der";
 const DEFAULT_PKEY_PATH: &str = "private.pem";
+const DEFAULT_CA_CERT_PATH: &str = "trusted/ca.der";
+const DEFAULT_CA_PKEY_PATH: &str = "private/ca.pem";
+const DEFAULT_CRL_PATH: &str = "crl/ca.crl";
+const DEFAULT_CRL_DAYS: u32 = 30;
 
-fn parse_x509_args() -> Result<(X509Data, bool, PathBuf, PathBuf, PathBuf), ()> {
+/// What the tool has been asked to do
+enum Mode {
+    SelfSigned,
+    CertificateAuthority,
+    Issue,
+    SignRequest(PathBuf),
+    Revoke(PathBuf),
+    Crl,
+}
+
+/// Where the CA keeps its certificate, private key and CRL
+struct CaArgs {
+    pki_path: String,
+    cert_path: String,
+    pkey_path: String,
+    pkey_passphrase: Option<PrivateKeyPassphrase>,
+    crl_path: String,
+    crl_days: u32,
+}
+
+type X509Args = (
+    X509Data,
+    bool,
+    PathBuf,
+    PathBuf,
+    PathBuf,
+    Option<PrivateKeyPassphrase>,
+);
+
+fn parse_x509_args() -> Result<(Mode, X509Args, CaArgs), ()> {
     // Read command line arguments
     let args = Args::parse_args().map_err(|_| Args::usage())?;
-    if args.help || ![2048u16, 4096u16].contains(&args.key_size) || args.duration == 0 {
+    let modes = [
+        args.ca,
+        args.issue,
+        args.sign_csr.is_some(),
+        args.revoke.is_some(),
+        args.crl,
+    ];
+    if args.help
+        || ![2048u16, 4096u16].contains(&args.key_size)
+        || args.duration == 0
+        || args.crl_days == 0
+        || (args.pkey_passphrase_env.is_some() && args.pkey_passphrase_stdin)
+        || modes.iter().filter(|m| **m).count() > 1
+    {
         Args::usage();
         Err(())
     } else {
+        let pkey_passphrase = if let Some(name) = args.pkey_passphrase_env {
+            Some(PrivateKeyPassphrase::Env(name))
+        } else if args.pkey_passphrase_stdin {
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|_| eprintln!("Cannot read the passphrase from standard input"))?;
+            let passphrase = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+            Some(PrivateKeyPassphrase::Value(passphrase))
+        } else {
+            None
+        };
+
+        let mode = if args.ca {
+            Mode::CertificateAuthority
+        } else if args.issue {
+            Mode::Issue
+        } else if let Some(request_path) = args.sign_csr {
+            Mode::SignRequest(PathBuf::from(request_path))
+        } else if let Some(cert_path) = args.revoke {
+            Mode::Revoke(PathBuf::from(cert_path))
+        } else if args.crl {
+            Mode::Crl
+        } else {
+            Mode::SelfSigned
+        };
+        let ca_args = CaArgs {
+            pki_path: args.pki_path.clone(),
+            cert_path: args.ca_cert_path,
+            pkey_path: args.ca_pkey_path,
+            pkey_passphrase: args.ca_pkey_passphrase_env.map(PrivateKeyPassphrase::Env),
+            crl_path: args.crl_path,
+            crl_days: args.crl_days,
+        };
+
         let pki_path = args.pki_path;
         let cert_path = args.cert_path;
         let pkey_path = args.pkey_path;
@@ -214,27 +516,34 @@ fn parse_x509_args() -> Result<(X509Data, bool, PathBuf, PathBuf, PathBuf), ()>
             add_ip_addresses,
         );
 
-        // Add the host names that were supplied by argument
-        if alt_host_names.len() == 1 {
+        // Add the host names that were supplied by argument. A CA has none and revocation needs none.
+        let needs_alt_host_names =
+            matches!(mode, Mode::SelfSigned | Mode::Issue | Mode::SignRequest(_));
+        if needs_alt_host_names && alt_host_names.len() == 1 {
             eprintln!("No alt host names were supplied or could be inferred. Certificate is useless without at least one DNS entry.");
             return Err(());
         }
 
         Ok((
-            X509Data {
-                key_size,
-                common_name,
-                organization,
-                organizational_unit,
-                country,
-                state,
-                alt_host_names,
-                certificate_duration_days,
-            },
-            overwrite,
-            PathBuf::from(&pki_path),
-            PathBuf::from(&cert_path),
-            PathBuf::from(&pkey_path),
+            mode,
+            (
+                X509Data {
+                    key_size,
+                    common_name,
+                    organization,
+                    organizational_unit,
+                    country,
+                    state,
+                    alt_host_names,
+                    certificate_duration_days,
+                },
+                overwrite,
+                PathBuf::from(&pki_path),
+                PathBuf::from(&cert_path),
+                PathBuf::from(&pkey_path),
+                pkey_passphrase,
+            ),
+            ca_args,
         ))
     }
 }
@@ -0,0 +1,87 @@
+use std::{io::BufRead, str::FromStr};
+
+use opcua::crypto::password::{hash_password, PasswordHashAlgorithm};
+
+fn main() {
+    if let Ok((algorithm, password)) = parse_args() {
+        match hash_password(algorithm, password.as_bytes()) {
+            Ok(pass_hash) => {
+                // Only the hash goes to stdout so the output can be piped into a config file
+                println!("{}", pass_hash);
+            }
+            Err(err) => {
+                eprintln!("Password hashing failed, reason \"{}\"", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        std::process::exit(1);
+    }
+}
+
+struct Args {
+    help: bool,
+    algorithm: String,
+    password: Option<String>,
+}
+
+impl Args {
+    pub fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
+        let mut args = pico_args::Arguments::from_env();
+        Ok(Args {
+            help: args.contains(["-h", "--help"]),
+            algorithm: args
+                .opt_value_from_str("--algorithm")?
+                .unwrap_or_else(|| String::from(DEFAULT_ALGORITHM)),
+            password: args.opt_value_from_str("--password")?,
+        })
+    }
+
+    pub fn usage() {
+        println!(
+            r#"OPC UA Password Hash
+
+This creates a salted password hash suitable for the pass_hash field of a user token in a
+server configuration. The password is read from the first line of standard input unless it is
+supplied as an argument. Note that passing a password as an argument may leave it in your shell
+history.
+
+Usage:
+  -h, --help            Show help.
+  --algorithm name      Hash algorithm - [argon2id, scrypt, pbkdf2-sha256, pbkdf2-sha512] (default: {})
+  --password password   The password to hash."#,
+            DEFAULT_ALGORITHM
+        );
+    }
+}
+
+const DEFAULT_ALGORITHM: &str = "argon2id";
+
+fn parse_args() -> Result<(PasswordHashAlgorithm, String), ()> {
+    let args = Args::parse_args().map_err(|_| Args::usage())?;
+    if args.help {
+        Args::usage();
+        return Err(());
+    }
+    let algorithm = PasswordHashAlgorithm::from_str(&args.algorithm).map_err(|_| {
+        eprintln!("Unsupported algorithm \"{}\"", args.algorithm);
+        Args::usage();
+    })?;
+    let password = if let Some(password) = args.password {
+        password
+    } else {
+        let mut line = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut line)
+            .map_err(|err| {
+                eprintln!("Cannot read password from stdin, reason \"{}\"", err);
+            })?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        eprintln!("Password is empty");
+        return Err(());
+    }
+    Ok((algorithm, password))
+}
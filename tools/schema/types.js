@@ -70,7 +70,7 @@ const JSON_SERIALIZED_TYPES = [
     "ConfigurationVersionDataType", "DataSetMetaDataType", "StructureDescription",
     "EnumDescription", "SimpleTypeDescription", "StructureDefinition", "EnumDefinition",
     "FieldMetaData", "KeyValuePair", "DataSetFieldFlags", "StructureType", "StructureField",
-    "EnumField"
+    "EnumField", "WriteValue", "CallMethodRequest"
 ];
 
 // The map from OPC UA types to their corresponding Rust types.